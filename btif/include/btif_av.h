@@ -187,6 +187,24 @@ void btif_av_set_audio_delay(const RawAddress& peer_address, uint16_t delay);
  */
 uint16_t btif_av_get_audio_delay(void);
 
+/**
+ * Callback for the audio delays of the sinks.
+ *
+ * @param peer_address the address of the sink
+ * @param delay the delay in units of 1/10ms
+ */
+typedef void (*btif_av_audio_delay_callback_t)(const RawAddress& peer_address,
+                                               uint16_t delay);
+
+/**
+ * Register a callback called on the JNI thread whenever the audio delay of a
+ * sink is set, e.g. because the sink sent a delay report.
+ *
+ * @param callback the callback, or nullptr to unregister it
+ */
+void btif_av_register_audio_delay_callback(
+    btif_av_audio_delay_callback_t callback);
+
 /**
  * Reset the audio delay and count of audio bytes sent to zero.
  */
@@ -40,6 +40,12 @@ void bte_dm_evt(tBTA_DM_SEC_EVT event, tBTA_DM_SEC* p_data);
  */
 void btif_dm_on_disable(void);
 
+/**
+ * Sets whether incoming temporary JustWorks pairings are accepted without
+ * asking for consent. When disabled they are reported as a consent request.
+ */
+void btif_dm_set_auto_accept_temporary_pairing(bool auto_accept);
+
 /**
  * Callout for handling io_capabilities request
  */
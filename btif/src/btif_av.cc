@@ -3435,6 +3435,13 @@ void btif_debug_av_dump(int fd) {
   btif_debug_av_sink_dump(fd);
 }
 
+static btif_av_audio_delay_callback_t audio_delay_callback = nullptr;
+
+void btif_av_register_audio_delay_callback(
+    btif_av_audio_delay_callback_t callback) {
+  audio_delay_callback = callback;
+}
+
 void btif_av_set_audio_delay(const RawAddress& peer_address, uint16_t delay) {
   btif_a2dp_control_set_audio_delay(delay);
   BtifAvPeer* peer = btif_av_find_peer(peer_address);
@@ -3443,6 +3450,10 @@ void btif_av_set_audio_delay(const RawAddress& peer_address, uint16_t delay) {
     if (peer->IsActivePeer()) {
       bluetooth::audio::a2dp::set_remote_delay(peer->GetDelayReport());
     }
+    if (audio_delay_callback != nullptr) {
+      do_in_jni_thread(FROM_HERE, base::Bind(audio_delay_callback,
+                                             peer_address, delay));
+    }
   }
 }
 
@@ -39,6 +39,7 @@
 #include <time.h>
 #include <unistd.h>
 
+#include <atomic>
 #include <mutex>
 
 #include <bluetooth/uuid.h>
@@ -181,6 +182,10 @@ static skip_sdp_entry_t sdp_rejectlist[] = {{76}};  // Apple Mouse and Keyboard
 /* This flag will be true if HCI_Inquiry is in progress */
 static bool btif_dm_inquiry_in_progress = false;
 
+/* Whether incoming temporary JustWorks pairings are accepted without consent.
+ * Written from the stack's client, read on the stack thread. */
+static std::atomic<bool> auto_accept_temporary_pairing{true};
+
 /*******************************************************************************
  *  Static variables
  ******************************************************************************/
@@ -832,7 +837,7 @@ static void btif_dm_ssp_cfm_req_evt(tBTA_DM_SP_CFM_REQ* p_ssp_cfm_req) {
     /* Pairing consent for JustWorks NOT needed if:
      * 1. Incoming temporary pairing is detected
      */
-    if (is_incoming &&
+    if (is_incoming && auto_accept_temporary_pairing &&
         pairing_cb.bond_type == tBTM_SEC_DEV_REC::BOND_TYPE_TEMPORARY) {
       BTIF_TRACE_EVENT(
           "%s: Auto-accept JustWorks pairing for temporary incoming", __func__);
@@ -2379,6 +2384,10 @@ static void stop_oob_advertiser() {
   oob_advertiser_id = 0;
 }
 
+void btif_dm_set_auto_accept_temporary_pairing(bool auto_accept) {
+  auto_accept_temporary_pairing = auto_accept;
+}
+
 /*******************************************************************************
  *
  * Function         btif_dm_generate_local_oob_data
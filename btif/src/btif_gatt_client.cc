@@ -192,10 +192,14 @@ static void btif_gattc_upstreams_evt(uint16_t event, char* p_param) {
     case BTA_GATTC_DEREG_EVT:
     case BTA_GATTC_SEARCH_RES_EVT:
     case BTA_GATTC_CANCEL_OPEN_EVT:
-    case BTA_GATTC_SRVC_DISC_DONE_EVT:
       LOG_DEBUG("Ignoring event (%d)", event);
       break;
 
+    case BTA_GATTC_SRVC_DISC_DONE_EVT:
+      HAL_CBACK(bt_gatt_callbacks, client->services_rediscovered_cb,
+                p_data->remote_bda);
+      break;
+
     case BTA_GATTC_CFG_MTU_EVT: {
       HAL_CBACK(bt_gatt_callbacks, client->configure_mtu_cb,
                 p_data->cfg_mtu.conn_id, p_data->cfg_mtu.status,
@@ -75,6 +75,18 @@ fn make_context(intf: BluetoothInterface) -> Context {
         acl_state_changed: Box::new(move |_status, _address, _state, _hci_reason| {
             println!("Acl state changed");
         }),
+        link_quality_report: Box::new(move |_timestamp, _id, _rssi, _snr, _retx, _no_rx, _nak| {
+            println!("Link quality report");
+        }),
+        local_oob_data_generated: Box::new(move |_transport, _oob_data| {
+            println!("Local OOB data generated");
+        }),
+        dut_mode_recv: Box::new(move |_opcode, _buf| {
+            println!("DUT mode event received");
+        }),
+        le_test_mode: Box::new(move |_status, _num_packets| {
+            println!("LE test mode done");
+        }),
     });
 
     return Context { tx, rx, callbacks: cb, intf };
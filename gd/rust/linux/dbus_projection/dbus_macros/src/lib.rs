@@ -9,10 +9,28 @@ use std::path::Path;
 use syn::parse::Parser;
 use syn::punctuated::Punctuated;
 use syn::token::Comma;
-use syn::{Expr, FnArg, ImplItem, ItemImpl, ItemStruct, Meta, Pat, ReturnType, Type};
+use syn::{
+    Expr, FnArg, ImplItem, ItemImpl, ItemStruct, Lit, Meta, NestedMeta, Pat, ReturnType, Type,
+};
 
 use crate::proc_macro::TokenStream;
 
+/// Parses the `log` option of `dbus_method`, e.g. `redact(device, pin)`, into the names of the
+/// arguments to redact.
+fn parse_log_redactions(log: &str) -> Vec<String> {
+    let log = log.trim();
+    if log.is_empty() {
+        return vec![];
+    }
+
+    let names = log
+        .strip_prefix("redact(")
+        .and_then(|names| names.strip_suffix(')'))
+        .unwrap_or_else(|| panic!("unknown log option `{}`, only `redact(...)` is supported", log));
+
+    names.split(',').map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).collect()
+}
+
 fn debug_output_to_file(gen: &proc_macro2::TokenStream, filename: String) {
     let path = Path::new(filename.as_str());
     let mut file = File::create(&path).unwrap();
@@ -20,6 +38,20 @@ fn debug_output_to_file(gen: &proc_macro2::TokenStream, filename: String) {
 }
 
 /// Marks a method to be projected to a D-Bus method and specifies the D-Bus method name.
+///
+/// An optional second argument names the output of the method in the introspection data, e.g.
+/// `#[dbus_method("GetName", "name")]`. By default the output of a `get_foo` method is named
+/// `foo`, and the output of any other method `result`.
+///
+/// A method returning a tuple has one output per element of the tuple, named by the arguments
+/// after the method name, e.g. `#[dbus_method("GetIdentity", "name", "address")]` for a method
+/// returning `(String, String)`. By default they are named after the output of the method
+/// followed by their index, e.g. `identity0` and `identity1`.
+///
+/// A `log` option makes the exporter pass the arguments of each call to the method tracer as a
+/// `MethodTraceEvent::Arguments`, whether or not the exporter has the `trace` option. Arguments
+/// that must not end up in the logs, e.g. addresses and keys, are redacted by naming them, e.g.
+/// `#[dbus_method("CreateBond", log = "redact(device)")]`.
 #[proc_macro_attribute]
 pub fn dbus_method(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let ori_item: proc_macro2::TokenStream = item.clone().into();
@@ -31,6 +63,20 @@ pub fn dbus_method(_attr: TokenStream, item: TokenStream) -> TokenStream {
 }
 
 /// Generates a function to export a Rust object to D-Bus.
+///
+/// Method arguments and return values are converted with `DBusArg`, so methods may return
+/// anything that implements it, e.g. `Vec<T>` and `#[dbus_propmap]` structs. Each element of a
+/// returned tuple is converted and returned as an output of its own, see `dbus_method`.
+///
+/// The arguments after the interface name are:
+/// * `version = N`, the version of the interface, starting at 1. It is generated as the
+///   `INTERFACE_VERSION` constant and exported as the read-only `Version` D-Bus property, so
+///   clients can detect an incompatible daemon. It must be bumped whenever the signature of a
+///   method or callback changes, which is why it has no default.
+/// * optionally `trace`, to make every projected method emit entry/exit events through
+///   `dbus_projection::trace_method_call`.
+///
+/// e.g. `#[generate_dbus_exporter(export_foo_dbus_obj, "org.example.Foo", trace, version = 2)]`.
 #[proc_macro_attribute]
 pub fn generate_dbus_exporter(attr: TokenStream, item: TokenStream) -> TokenStream {
     let ori_item: proc_macro2::TokenStream = item.clone().into();
@@ -49,6 +95,22 @@ pub fn generate_dbus_exporter(attr: TokenStream, item: TokenStream) -> TokenStre
         panic!("D-Bus interface name must be specified");
     };
 
+    let mut trace = false;
+    let mut version = None;
+    for option in args.iter().skip(2) {
+        match option {
+            Expr::Path(p) if p.path.is_ident("trace") => trace = true,
+            Expr::Assign(assign) => match (&*assign.left, &*assign.right) {
+                (Expr::Path(p), Expr::Lit(lit)) if p.path.is_ident("version") => {
+                    version = Some(lit.to_token_stream());
+                }
+                _ => panic!("unknown exporter option, only `version = N` is supported"),
+            },
+            _ => panic!("unknown exporter option, only `trace` and `version` are supported"),
+        }
+    }
+    let version = version.expect("the `version` of the D-Bus interface must be specified");
+
     let ast: ItemImpl = syn::parse(item.clone()).unwrap();
     let api_iface_ident = ast.trait_.unwrap().1.to_token_stream();
 
@@ -66,24 +128,50 @@ pub fn generate_dbus_exporter(attr: TokenStream, item: TokenStream) -> TokenStre
             }
 
             let attr_args = attr.parse_meta().unwrap();
-            let dbus_method_name = if let Meta::List(meta_list) = attr_args {
-                Some(meta_list.nested[0].clone())
+            let mut log = None;
+            let (dbus_method_name, dbus_output_names) = if let Meta::List(meta_list) = attr_args {
+                let mut names = vec![];
+                for nested in meta_list.nested.iter().skip(1) {
+                    match nested {
+                        NestedMeta::Meta(Meta::Path(p)) if p.is_ident("log") => {
+                            log = Some(String::new());
+                        }
+                        NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("log") => {
+                            match &nv.lit {
+                                Lit::Str(s) => log = Some(s.value()),
+                                _ => panic!(
+                                    "`log` must be a string, e.g. `log = \"redact(device)\"`"
+                                ),
+                            }
+                        }
+                        name => names.push(name.clone()),
+                    }
+                }
+                (Some(meta_list.nested[0].clone()), names)
             } else {
-                None
+                (None, vec![])
             };
+            let redacted = log.as_deref().map(parse_log_redactions).unwrap_or_default();
 
             if dbus_method_name.is_none() {
                 continue;
             }
 
             let method_name = method.sig.ident;
+            let default_output_name = {
+                let name = method_name.to_string();
+                String::from(name.strip_prefix("get_").unwrap_or("result"))
+            };
 
             let mut arg_names = quote! {};
             let mut method_args = quote! {};
             let mut make_args = quote! {};
             let mut dbus_input_vars = quote! {};
             let mut dbus_input_types = quote! {};
+            let mut log_args = quote! {};
+            let mut input_names = vec![];
 
+            let mut arg_index = 0usize;
             for input in method.sig.inputs {
                 if let FnArg::Typed(ref typed) = input {
                     let arg_type = &typed.ty;
@@ -93,6 +181,7 @@ pub fn generate_dbus_exporter(attr: TokenStream, item: TokenStream) -> TokenStre
                         dbus_input_ident.push_str("_");
                         let dbus_input_arg = format_ident!("{}", dbus_input_ident);
                         let ident_string = ident.to_string();
+                        input_names.push(ident_string.clone());
 
                         arg_names = quote! {
                             #arg_names #ident_string,
@@ -111,6 +200,17 @@ pub fn generate_dbus_exporter(attr: TokenStream, item: TokenStream) -> TokenStre
                             <#arg_type as DBusArg>::DBusType,
                         };
 
+                        // The arguments are logged as received, before they are converted.
+                        let logged_value = if redacted.contains(&ident_string) {
+                            quote! { String::from("<redacted>") }
+                        } else {
+                            quote! { format!("{:?}", #dbus_input_arg) }
+                        };
+                        log_args = quote! {
+                            #log_args
+                            format!("{} = {}", #ident_string, #logged_value),
+                        };
+
                         make_args = quote! {
                             #make_args
                             let #ident = <#arg_type as DBusArg>::from_dbus(
@@ -122,12 +222,21 @@ pub fn generate_dbus_exporter(attr: TokenStream, item: TokenStream) -> TokenStre
 
                             if let Result::Err(e) = #ident {
                                 return Err(dbus_crossroads::MethodErr::invalid_arg(
-                                    e.to_string().as_str()
+                                    format!(
+                                        "{}: argument {} ({}): {}",
+                                        #dbus_method_name,
+                                        #arg_index,
+                                        #ident_string,
+                                        e
+                                    )
+                                    .as_str()
                                 ));
                             }
 
                             let #ident = #ident.unwrap();
                         };
+
+                        arg_index += 1;
                     }
                 }
             }
@@ -136,15 +245,133 @@ pub fn generate_dbus_exporter(attr: TokenStream, item: TokenStream) -> TokenStre
                 (#dbus_input_vars): (#dbus_input_types)
             };
 
+            for name in &redacted {
+                if !input_names.contains(name) {
+                    panic!("{}: cannot redact `{}`, there is no such argument", method_name, name);
+                }
+            }
+
+            let log_call = if log.is_some() {
+                quote! {
+                    {
+                        let sender = match ctx.message().sender() {
+                            Some(sender) => sender.to_string(),
+                            None => String::from(""),
+                        };
+                        let args: Vec<String> = vec![#log_args];
+                        dbus_projection::trace_method_call(
+                            dbus_projection::MethodTraceEvent::Arguments {
+                                iface: #dbus_iface_name,
+                                method: #dbus_method_name,
+                                sender: sender.as_str(),
+                                args: args.join(", ").as_str(),
+                            },
+                        );
+                    }
+                }
+            } else {
+                quote! {}
+            };
+
+            // A tuple is returned as one output per element, anything else as a single output.
+            let (output_types, is_tuple): (Vec<Type>, bool) = match method.sig.output {
+                ReturnType::Type(_, t) => match *t {
+                    Type::Tuple(tuple) => (tuple.elems.into_iter().collect(), true),
+                    t => (vec![t], false),
+                },
+                ReturnType::Default => (vec![], false),
+            };
+
+            if !dbus_output_names.is_empty() && dbus_output_names.len() != output_types.len() {
+                panic!("{}: one output name must be given per returned value", method_name);
+            }
+
             let mut output_names = quote! {};
             let mut output_type = quote! {};
-            let mut ret = quote! {Ok(())};
-            if let ReturnType::Type(_, t) = method.sig.output {
-                output_type = quote! {#t,};
-                ret = quote! {Ok((ret,))};
-                output_names = quote! { "out", };
+            let mut convert_outputs = quote! {};
+            let mut outputs = quote! {};
+            for (index, t) in output_types.iter().enumerate() {
+                let output_name = match dbus_output_names.get(index) {
+                    Some(name) => name.to_token_stream(),
+                    None if is_tuple => {
+                        let name = format!("{}{}", default_output_name, index);
+                        quote! { #name }
+                    }
+                    None => quote! { #default_output_name },
+                };
+                let value = if is_tuple {
+                    let index = syn::Index::from(index);
+                    quote! { ret.#index }
+                } else {
+                    quote! { ret }
+                };
+                let output = format_ident!("output{}", index);
+
+                convert_outputs = quote! {
+                    #convert_outputs
+                    let #output = match <#t as DBusArg>::to_dbus(#value) {
+                        Ok(output) => output,
+                        Err(e) => {
+                            return Err(dbus_crossroads::MethodErr::failed(
+                                format!("{}: output ({}): {}", #dbus_method_name, #output_name, e)
+                                    .as_str()
+                            ));
+                        }
+                    };
+                };
+                output_type = quote! { #output_type <#t as DBusArg>::DBusType, };
+                outputs = quote! { #outputs #output, };
+                output_names = quote! { #output_names #output_name, };
             }
 
+            let ret = quote! {
+                #convert_outputs
+                Ok((#outputs))
+            };
+
+            let method_body = if trace {
+                quote! {
+                    #log_call
+                    let sender = match ctx.message().sender() {
+                        Some(sender) => sender.to_string(),
+                        None => String::from(""),
+                    };
+                    dbus_projection::trace_method_call(dbus_projection::MethodTraceEvent::Enter {
+                        iface: #dbus_iface_name,
+                        method: #dbus_method_name,
+                        sender: sender.as_str(),
+                    });
+                    let start = std::time::Instant::now();
+
+                    let result = (|| -> Result<(#output_type), dbus_crossroads::MethodErr> {
+                        #make_args
+                        let ret = obj.lock().unwrap().#method_name(#method_args);
+                        #ret
+                    })();
+
+                    let error = match &result {
+                        Ok(_) => None,
+                        Err(e) => Some(format!("{}: {}", e.errorname(), e.description())),
+                    };
+                    dbus_projection::trace_method_call(dbus_projection::MethodTraceEvent::Exit {
+                        iface: #dbus_iface_name,
+                        method: #dbus_method_name,
+                        sender: sender.as_str(),
+                        duration: start.elapsed(),
+                        error: error.as_deref(),
+                    });
+
+                    result
+                }
+            } else {
+                quote! {
+                    #log_call
+                    #make_args
+                    let ret = obj.lock().unwrap().#method_name(#method_args);
+                    #ret
+                }
+            };
+
             register_methods = quote! {
                 #register_methods
 
@@ -154,9 +381,7 @@ pub fn generate_dbus_exporter(attr: TokenStream, item: TokenStream) -> TokenStre
                                           obj: &mut ObjType,
                                           #dbus_input_args |
                       -> Result<(#output_type), dbus_crossroads::MethodErr> {
-                    #make_args
-                    let ret = obj.lock().unwrap().#method_name(#method_args);
-                    #ret
+                    #method_body
                 };
                 ibuilder.method(
                     #dbus_method_name,
@@ -173,6 +398,9 @@ pub fn generate_dbus_exporter(attr: TokenStream, item: TokenStream) -> TokenStre
 
         type ObjType = std::sync::Arc<std::sync::Mutex<dyn #api_iface_ident + Send>>;
 
+        /// Version of the D-Bus interface, exported as its `Version` property.
+        pub const INTERFACE_VERSION: u32 = #version;
+
         pub fn #fn_ident(
             path: &'static str,
             conn: std::sync::Arc<SyncConnection>,
@@ -187,11 +415,16 @@ pub fn generate_dbus_exporter(attr: TokenStream, item: TokenStream) -> TokenStre
             ) -> dbus_crossroads::IfaceToken<ObjType> {
                 cr.register(#dbus_iface_name, |ibuilder| {
                     #register_methods
+
+                    ibuilder
+                        .property::<u32, _>("Version")
+                        .get(|_, _| Ok(INTERFACE_VERSION));
                 })
             }
 
             let iface_token = get_iface_token(conn, cr, disconnect_watcher);
             cr.insert(path, &[iface_token], obj);
+            dbus_projection::notify_interface_added(path, #dbus_iface_name, INTERFACE_VERSION);
         }
     };
 
@@ -215,6 +448,11 @@ fn copy_without_attributes(item: &TokenStream) -> TokenStream {
 }
 
 /// Generates a DBusArg implementation to transform Rust plain structs to a D-Bus data structure.
+///
+/// Parsing is strict by default: every field is required. The optional `lenient` argument lets
+/// fields be missing, in which case they keep their `Default` value, e.g.
+/// `#[dbus_propmap(Foo, lenient)]`. Fields of the wrong type are always rejected. All the missing
+/// and invalid fields are reported together in one error.
 // TODO: Support more data types of struct fields (currently only supports integers and enums).
 #[proc_macro_attribute]
 pub fn dbus_propmap(attr: TokenStream, item: TokenStream) -> TokenStream {
@@ -228,8 +466,15 @@ pub fn dbus_propmap(attr: TokenStream, item: TokenStream) -> TokenStream {
 
     let struct_str = struct_ident.to_string();
 
+    let lenient = match args.iter().nth(1) {
+        Some(Expr::Path(p)) if p.path.is_ident("lenient") => true,
+        Some(Expr::Path(p)) if p.path.is_ident("strict") => false,
+        Some(_) => panic!("unknown propmap option, only `strict` and `lenient` are supported"),
+        None => false,
+    };
+
     let mut make_fields = quote! {};
-    let mut field_idents = quote! {};
+    let mut set_fields = quote! {};
 
     let mut insert_map_fields = quote! {};
     for field in ast.fields {
@@ -259,8 +504,11 @@ pub fn dbus_propmap(attr: TokenStream, item: TokenStream) -> TokenStream {
 
         let field_type_ident = format_ident!("{}", field_type_str);
 
-        field_idents = quote! {
-            #field_idents #field_ident,
+        set_fields = quote! {
+            #set_fields
+            if let Some(#field_ident) = #field_ident {
+                result.#field_ident = #field_ident;
+            }
         };
 
         let make_field = if !propmap_attr.is_none() {
@@ -319,19 +567,36 @@ pub fn dbus_propmap(attr: TokenStream, item: TokenStream) -> TokenStream {
             }
         };
 
+        let missing_field = if lenient {
+            quote! {}
+        } else {
+            quote! {
+                errors.push(format!("{}.{} is required", #struct_str, #field_str));
+            }
+        };
+
         make_fields = quote! {
             #make_fields
 
             let #field_ident = match data.get(#field_str) {
-                Some(data) => data,
+                Some(#field_ident) => {
+                    let parsed = (|| -> Result<#field_type_ident, Box<dyn Error>> {
+                        #make_field
+                        Ok(#field_ident)
+                    })();
+                    match parsed {
+                        Ok(#field_ident) => Some(#field_ident),
+                        Err(e) => {
+                            errors.push(e.to_string());
+                            None
+                        }
+                    }
+                }
                 None => {
-                    return Err(Box::new(DBusArgError::new(String::from(format!(
-                        "{}.{} is required",
-                        #struct_str, #field_str
-                    )))));
+                    #missing_field
+                    None
                 }
             };
-            #make_field
         };
 
         insert_map_fields = quote! {
@@ -354,12 +619,16 @@ pub fn dbus_propmap(attr: TokenStream, item: TokenStream) -> TokenStream {
                 remote: BusName<'static>,
                 disconnect_watcher: Arc<Mutex<dbus_projection::DisconnectWatcher>>,
             ) -> Result<#struct_ident, Box<dyn Error>> {
+                let mut errors: Vec<String> = vec![];
                 #make_fields
 
-                return Ok(#struct_ident {
-                    #field_idents
-                    ..Default::default()
-                });
+                if !errors.is_empty() {
+                    return Err(Box::new(DBusArgError::new(errors.join("; "))));
+                }
+
+                let mut result = #struct_ident::default();
+                #set_fields
+                return Ok(result);
             }
 
             fn to_dbus(data: #struct_ident) -> Result<dbus::arg::PropMap, Box<dyn Error>> {
@@ -446,11 +715,12 @@ pub fn dbus_proxy_obj(attr: TokenStream, item: TokenStream) -> TokenStream {
                     let remote = self.remote.clone();
                     let objpath = self.objpath.clone();
                     let conn = self.conn.clone();
+                    let disconnect_watcher = self.disconnect_watcher.clone();
                     bt_topshim::topstack::get_runtime().spawn(async move {
                         let proxy = dbus::nonblock::Proxy::new(
-                            remote,
+                            remote.clone(),
                             objpath,
-                            std::time::Duration::from_secs(2),
+                            dbus_projection::method_timeout(#dbus_iface_name, #dbus_method_name),
                             conn,
                         );
                         let future: dbus::nonblock::MethodReply<()> = proxy.method_call(
@@ -458,7 +728,22 @@ pub fn dbus_proxy_obj(attr: TokenStream, item: TokenStream) -> TokenStream {
                             #dbus_method_name,
                             (#method_args),
                         );
-                        let _result = future.await;
+                        let result = future.await;
+
+                        let timed_out = match &result {
+                            Ok(_) => false,
+                            Err(e) => e.name() == Some("org.freedesktop.DBus.Error.NoReply"),
+                        };
+                        if dbus_projection::record_callback_reply(&remote, timed_out) {
+                            eprintln!(
+                                "Client {} keeps timing out on {} callbacks, unregistering it",
+                                remote, #dbus_iface_name
+                            );
+                            dbus_projection::DisconnectWatcher::force_disconnect(
+                                &disconnect_watcher,
+                                &remote,
+                            );
+                        }
                     });
                 }
             };
@@ -469,7 +754,13 @@ pub fn dbus_proxy_obj(attr: TokenStream, item: TokenStream) -> TokenStream {
         #ori_item
 
         impl RPCProxy for #self_ty {
-            fn register_disconnect(&mut self, _disconnect_callback: Box<dyn Fn() + Send>) {}
+            fn register_disconnect(&mut self, _disconnect_callback: Box<dyn Fn() + Send>) -> u32 {
+                0
+            }
+
+            fn unregister(&mut self, _id: u32) -> bool {
+                false
+            }
         }
 
         struct #struct_ident {
@@ -477,6 +768,8 @@ pub fn dbus_proxy_obj(attr: TokenStream, item: TokenStream) -> TokenStream {
             remote: BusName<'static>,
             objpath: Path<'static>,
             disconnect_watcher: Arc<Mutex<DisconnectWatcher>>,
+            /// Ids of the disconnect callbacks registered in `disconnect_watcher`.
+            disconnect_ids: Vec<u32>,
         }
 
         impl #trait_ for #struct_ident {
@@ -484,8 +777,33 @@ pub fn dbus_proxy_obj(attr: TokenStream, item: TokenStream) -> TokenStream {
         }
 
         impl RPCProxy for #struct_ident {
-            fn register_disconnect(&mut self, disconnect_callback: Box<dyn Fn() + Send>) {
-                self.disconnect_watcher.lock().unwrap().add(self.remote.clone(), disconnect_callback);
+            fn register_disconnect(&mut self, disconnect_callback: Box<dyn Fn() + Send>) -> u32 {
+                let id = self
+                    .disconnect_watcher
+                    .lock()
+                    .unwrap()
+                    .add(self.remote.clone(), disconnect_callback);
+                self.disconnect_ids.push(id);
+                id
+            }
+
+            fn unregister(&mut self, id: u32) -> bool {
+                if !self.disconnect_ids.contains(&id) {
+                    return false;
+                }
+
+                self.disconnect_ids.retain(|disconnect_id| *disconnect_id != id);
+                self.disconnect_watcher.lock().unwrap().remove(&self.remote, id)
+            }
+        }
+
+        impl Drop for #struct_ident {
+            fn drop(&mut self) {
+                // Don't leave the disconnect callbacks behind for as long as the client lives.
+                for id in std::mem::take(&mut self.disconnect_ids) {
+                    self.disconnect_watcher.lock().unwrap().remove(&self.remote, id);
+                }
+                dbus_projection::remove_client_callback(&self.remote);
             }
         }
 
@@ -498,7 +816,20 @@ pub fn dbus_proxy_obj(attr: TokenStream, item: TokenStream) -> TokenStream {
                 remote: BusName<'static>,
                 disconnect_watcher: Arc<Mutex<DisconnectWatcher>>,
             ) -> Result<Box<dyn #trait_ + Send>, Box<dyn Error>> {
-                Ok(Box::new(#struct_ident { conn, remote, objpath, disconnect_watcher }))
+                if !dbus_projection::add_client_callback(&remote) {
+                    return Err(Box::new(DBusArgError::new(String::from(format!(
+                        "{} has too many callbacks registered",
+                        remote
+                    )))));
+                }
+
+                Ok(Box::new(#struct_ident {
+                    conn,
+                    remote,
+                    objpath,
+                    disconnect_watcher,
+                    disconnect_ids: vec![],
+                }))
             }
 
             fn to_dbus(_data: Box<dyn #trait_ + Send>) -> Result<Path<'static>, Box<dyn Error>> {
@@ -566,10 +897,14 @@ pub fn generate_dbus_arg(_item: TokenStream) -> TokenStream {
             fn to_dbus(x: Self) -> Result<Self::DBusType, Box<dyn Error>>;
         }
 
-        // Types that implement dbus::arg::Append do not need any conversion.
+        // Types that implement dbus::arg::Append do not need any conversion. u8 is left out so that
+        // Vec<u8> can be projected by its own impl below.
         pub(crate) trait DirectDBus {}
+        impl DirectDBus for bool {}
         impl DirectDBus for i32 {}
         impl DirectDBus for u32 {}
+        impl DirectDBus for i64 {}
+        impl DirectDBus for u64 {}
         impl DirectDBus for String {}
         impl<T: DirectDBus> DBusArg for T {
             type DBusType = T;
@@ -619,6 +954,85 @@ pub fn generate_dbus_arg(_item: TokenStream) -> TokenStream {
                 Ok(list)
             }
         }
+
+        // Binary payloads, e.g. GATT values, are passed as a D-Bus byte array ("ay") as they are,
+        // rather than converted byte by byte like the other vectors.
+        impl DBusArg for Vec<u8> {
+            type DBusType = Vec<u8>;
+
+            fn from_dbus(
+                data: Vec<u8>,
+                _conn: Arc<SyncConnection>,
+                _remote: BusName<'static>,
+                _disconnect_watcher: Arc<Mutex<DisconnectWatcher>>,
+            ) -> Result<Vec<u8>, Box<dyn Error>> {
+                Ok(data)
+            }
+
+            fn to_dbus(data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+                Ok(data)
+            }
+        }
+
+        // Dictionaries of variants ("a{sv}") are passed as they are, for APIs that take loosely
+        // typed properties.
+        impl DBusArg for PropMap {
+            type DBusType = PropMap;
+
+            fn from_dbus(
+                data: PropMap,
+                _conn: Arc<SyncConnection>,
+                _remote: BusName<'static>,
+                _disconnect_watcher: Arc<Mutex<DisconnectWatcher>>,
+            ) -> Result<PropMap, Box<dyn Error>> {
+                Ok(data)
+            }
+
+            fn to_dbus(data: PropMap) -> Result<PropMap, Box<dyn Error>> {
+                Ok(data)
+            }
+        }
+
+        impl<A: DBusArg, B: DBusArg> DBusArg for (A, B) {
+            type DBusType = (A::DBusType, B::DBusType);
+
+            fn from_dbus(
+                data: (A::DBusType, B::DBusType),
+                conn: Arc<SyncConnection>,
+                remote: BusName<'static>,
+                disconnect_watcher: Arc<Mutex<DisconnectWatcher>>,
+            ) -> Result<(A, B), Box<dyn Error>> {
+                let a = A::from_dbus(data.0, conn.clone(), remote.clone(), disconnect_watcher.clone())?;
+                let b = B::from_dbus(data.1, conn, remote, disconnect_watcher)?;
+                Ok((a, b))
+            }
+
+            fn to_dbus(data: (A, B)) -> Result<(A::DBusType, B::DBusType), Box<dyn Error>> {
+                Ok((A::to_dbus(data.0)?, B::to_dbus(data.1)?))
+            }
+        }
+
+        impl<A: DBusArg, B: DBusArg, C: DBusArg> DBusArg for (A, B, C) {
+            type DBusType = (A::DBusType, B::DBusType, C::DBusType);
+
+            fn from_dbus(
+                data: (A::DBusType, B::DBusType, C::DBusType),
+                conn: Arc<SyncConnection>,
+                remote: BusName<'static>,
+                disconnect_watcher: Arc<Mutex<DisconnectWatcher>>,
+            ) -> Result<(A, B, C), Box<dyn Error>> {
+                let a = A::from_dbus(data.0, conn.clone(), remote.clone(), disconnect_watcher.clone())?;
+                let b = B::from_dbus(data.1, conn.clone(), remote.clone(), disconnect_watcher.clone())?;
+                let c = C::from_dbus(data.2, conn, remote, disconnect_watcher)?;
+                Ok((a, b, c))
+            }
+
+            fn to_dbus(
+                data: (A, B, C),
+            ) -> Result<(A::DBusType, B::DBusType, C::DBusType), Box<dyn Error>> {
+                Ok((A::to_dbus(data.0)?, B::to_dbus(data.1)?, C::to_dbus(data.2)?))
+            }
+        }
     };
 
     // TODO: Have a switch to turn this debug off/on.
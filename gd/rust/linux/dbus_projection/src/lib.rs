@@ -2,34 +2,81 @@
 //!
 //! For D-Bus projection to work automatically, the API needs to follow certain restrictions.
 
-use dbus::channel::MatchingReceiver;
+#[macro_use]
+extern crate lazy_static;
+
+use dbus::arg::{PropMap, Variant};
+use dbus::channel::{MatchingReceiver, Sender};
 use dbus::message::MatchRule;
 use dbus::nonblock::SyncConnection;
-use dbus::strings::BusName;
+use dbus::strings::{BusName, Path};
+use dbus::Message;
+
+use dbus_crossroads::{Crossroads, IfaceToken};
 
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 /// A D-Bus "NameOwnerChanged" handler that continuously monitors client disconnects.
 pub struct DisconnectWatcher {
-    callbacks: Arc<Mutex<HashMap<BusName<'static>, Vec<Box<dyn Fn() + Send>>>>>,
+    /// Callbacks of each client address, in the order they were added, with their ids.
+    callbacks: Arc<Mutex<HashMap<BusName<'static>, Vec<(u32, Box<dyn Fn() + Send>)>>>>,
+    last_id: u32,
 }
 
 impl DisconnectWatcher {
     /// Creates a new DisconnectWatcher with empty callbacks.
     pub fn new() -> DisconnectWatcher {
-        DisconnectWatcher { callbacks: Arc::new(Mutex::new(HashMap::new())) }
+        DisconnectWatcher { callbacks: Arc::new(Mutex::new(HashMap::new())), last_id: 0 }
     }
 }
 
 impl DisconnectWatcher {
     /// Adds a client address to be monitored for disconnect events.
-    pub fn add(&mut self, address: BusName<'static>, callback: Box<dyn Fn() + Send>) {
-        if !self.callbacks.lock().unwrap().contains_key(&address) {
-            self.callbacks.lock().unwrap().insert(address.clone(), vec![]);
+    ///
+    /// Returns the id of the callback, which `remove` takes once the callback is not needed
+    /// anymore. Callbacks are otherwise only dropped when the client disconnects.
+    pub fn add(&mut self, address: BusName<'static>, callback: Box<dyn Fn() + Send>) -> u32 {
+        self.last_id = self.last_id.wrapping_add(1);
+        let id = self.last_id;
+
+        self.callbacks.lock().unwrap().entry(address).or_default().push((id, callback));
+        id
+    }
+
+    /// Removes a callback added with `add` for a client address, before the client disconnects.
+    ///
+    /// Returns false if there is no such callback, e.g. because it was already called.
+    pub fn remove(&mut self, address: &BusName<'static>, id: u32) -> bool {
+        let mut callbacks_map = self.callbacks.lock().unwrap();
+
+        let callbacks = match callbacks_map.get_mut(address) {
+            Some(callbacks) => callbacks,
+            None => return false,
+        };
+
+        let len = callbacks.len();
+        callbacks.retain(|(callback_id, _)| *callback_id != id);
+        let removed = callbacks.len() != len;
+
+        if callbacks.is_empty() {
+            callbacks_map.remove(address);
         }
 
-        (*self.callbacks.lock().unwrap().get_mut(&address).unwrap()).push(callback);
+        removed
+    }
+
+    /// Handles a client as if it disconnected, e.g. because it stopped replying.
+    ///
+    /// The callbacks are called with `watcher` unlocked, since they may drop the objects that
+    /// registered them, which then `remove` themselves from it.
+    pub fn force_disconnect(watcher: &Mutex<DisconnectWatcher>, address: &BusName<'static>) {
+        let callbacks = watcher.lock().unwrap().callbacks.lock().unwrap().remove(address);
+
+        for (_, callback) in callbacks.unwrap_or_default() {
+            callback();
+        }
     }
 
     /// Sets up the D-Bus handler that monitors client disconnects.
@@ -57,24 +104,301 @@ impl DisconnectWatcher {
                 // disconnected. So call the registered callbacks to be notified of this client
                 // disconnect.
                 let addr = BusName::new(addr.unwrap()).unwrap().into_static();
-                if !callbacks_map.lock().unwrap().contains_key(&addr) {
-                    return true;
-                }
+                let callbacks = callbacks_map.lock().unwrap().remove(&addr);
 
-                for callback in &callbacks_map.lock().unwrap()[&addr] {
+                for (_, callback) in callbacks.unwrap_or_default() {
                     callback();
                 }
 
-                callbacks_map.lock().unwrap().remove(&addr);
-
                 true
             }),
         );
     }
 }
 
+/// A tracing event of a projected D-Bus method call.
+///
+/// Generated by exporters created with the `trace` option of `generate_dbus_exporter`, and for
+/// the methods with the `log` option of `dbus_method`.
+#[derive(Debug)]
+pub enum MethodTraceEvent<'a> {
+    /// The method is about to be dispatched to the API implementation.
+    Enter { iface: &'a str, method: &'a str, sender: &'a str },
+
+    /// The method call is done. `error` is set if the call is replied with an error.
+    Exit {
+        iface: &'a str,
+        method: &'a str,
+        sender: &'a str,
+        duration: Duration,
+        error: Option<&'a str>,
+    },
+
+    /// The arguments of a call to a method with the `log` option, as `name = value` pairs in
+    /// which the redacted values are replaced by `<redacted>`.
+    Arguments { iface: &'a str, method: &'a str, sender: &'a str, args: &'a str },
+}
+
+lazy_static! {
+    static ref METHOD_TRACER: Mutex<Option<Box<dyn Fn(&MethodTraceEvent) + Send>>> =
+        Mutex::new(None);
+}
+
+/// Sets the function that receives tracing events of projected method calls.
+///
+/// There is no tracer by default, in which case the events are dropped.
+pub fn set_method_tracer(tracer: Box<dyn Fn(&MethodTraceEvent) + Send>) {
+    *METHOD_TRACER.lock().unwrap() = Some(tracer);
+}
+
+/// Passes a tracing event to the method tracer. Called by the generated exporters.
+pub fn trace_method_call(event: MethodTraceEvent) {
+    if let Some(tracer) = &*METHOD_TRACER.lock().unwrap() {
+        tracer(&event);
+    }
+}
+
+/// Limits applied to the callback objects registered by each D-Bus client.
+#[derive(Clone, Copy, Debug)]
+pub struct CallbackLimits {
+    /// Maximum number of callback objects a client can have registered at the same time.
+    pub max_callbacks_per_client: usize,
+
+    /// Number of consecutive callback method calls to a client that time out before the client is
+    /// handled as disconnected.
+    pub max_consecutive_timeouts: u32,
+}
+
+impl Default for CallbackLimits {
+    fn default() -> Self {
+        CallbackLimits { max_callbacks_per_client: 32, max_consecutive_timeouts: 5 }
+    }
+}
+
+#[derive(Default)]
+struct ClientCallbacks {
+    count: usize,
+    consecutive_timeouts: u32,
+}
+
+lazy_static! {
+    static ref CALLBACK_LIMITS: Mutex<CallbackLimits> = Mutex::new(CallbackLimits::default());
+    static ref CLIENT_CALLBACKS: Mutex<HashMap<BusName<'static>, ClientCallbacks>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Sets the limits applied to the callback objects of every client.
+pub fn set_callback_limits(limits: CallbackLimits) {
+    *CALLBACK_LIMITS.lock().unwrap() = limits;
+}
+
+/// Accounts for a new callback object of a client. Called by the proxies generated by
+/// `dbus_proxy_obj`.
+///
+/// Returns false if the client already has as many callback objects as allowed.
+pub fn add_client_callback(client: &BusName<'static>) -> bool {
+    let max = CALLBACK_LIMITS.lock().unwrap().max_callbacks_per_client;
+    let mut clients = CLIENT_CALLBACKS.lock().unwrap();
+    let entry = clients.entry(client.clone()).or_default();
+
+    if entry.count >= max {
+        return false;
+    }
+
+    entry.count += 1;
+    true
+}
+
+/// Accounts for a callback object of a client being dropped.
+pub fn remove_client_callback(client: &BusName<'static>) {
+    let mut clients = CLIENT_CALLBACKS.lock().unwrap();
+
+    if let Some(entry) = clients.get_mut(client) {
+        entry.count = entry.count.saturating_sub(1);
+        if entry.count == 0 {
+            clients.remove(client);
+        }
+    }
+}
+
+/// Records whether a callback method call to a client timed out.
+///
+/// Returns true when the client reaches the maximum number of consecutive timeouts, in which case
+/// it should be handled as disconnected. The count then starts over.
+pub fn record_callback_reply(client: &BusName<'static>, timed_out: bool) -> bool {
+    let max = CALLBACK_LIMITS.lock().unwrap().max_consecutive_timeouts;
+    let mut clients = CLIENT_CALLBACKS.lock().unwrap();
+
+    let entry = match clients.get_mut(client) {
+        Some(entry) => entry,
+        None => return false,
+    };
+
+    if !timed_out {
+        entry.consecutive_timeouts = 0;
+        return false;
+    }
+
+    entry.consecutive_timeouts += 1;
+    if entry.consecutive_timeouts < max {
+        return false;
+    }
+
+    entry.consecutive_timeouts = 0;
+    true
+}
+
+/// Timeout of outgoing D-Bus method calls that have no specific timeout set.
+pub const DEFAULT_METHOD_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Timeouts of outgoing D-Bus method calls, looked up by method, then interface.
+struct MethodTimeouts {
+    default: Duration,
+    ifaces: HashMap<String, Duration>,
+    methods: HashMap<(String, String), Duration>,
+}
+
+lazy_static! {
+    static ref METHOD_TIMEOUTS: Mutex<MethodTimeouts> = Mutex::new(MethodTimeouts {
+        default: DEFAULT_METHOD_TIMEOUT,
+        ifaces: HashMap::new(),
+        methods: HashMap::new(),
+    });
+}
+
+/// Sets the timeout of outgoing method calls that have no interface or method timeout set.
+pub fn set_default_method_timeout(timeout: Duration) {
+    METHOD_TIMEOUTS.lock().unwrap().default = timeout;
+}
+
+/// Sets the timeout of outgoing calls to every method of a D-Bus interface.
+pub fn set_iface_method_timeout(iface: &str, timeout: Duration) {
+    METHOD_TIMEOUTS.lock().unwrap().ifaces.insert(String::from(iface), timeout);
+}
+
+/// Sets the timeout of outgoing calls to a single D-Bus method, overriding the interface timeout.
+pub fn set_method_timeout(iface: &str, method: &str, timeout: Duration) {
+    METHOD_TIMEOUTS
+        .lock()
+        .unwrap()
+        .methods
+        .insert((String::from(iface), String::from(method)), timeout);
+}
+
+/// Returns the timeout to use for an outgoing call to a D-Bus method.
+///
+/// Used by the proxy objects generated by `dbus_proxy_obj`, and meant to be used by clients for
+/// their own outgoing calls so that all timeouts are configured in one place.
+pub fn method_timeout(iface: &str, method: &str) -> Duration {
+    let timeouts = METHOD_TIMEOUTS.lock().unwrap();
+
+    if let Some(timeout) = timeouts.methods.get(&(String::from(iface), String::from(method))) {
+        return *timeout;
+    }
+
+    match timeouts.ifaces.get(iface) {
+        Some(timeout) => *timeout,
+        None => timeouts.default,
+    }
+}
+
+const OBJECT_MANAGER_IFACE: &str = "org.freedesktop.DBus.ObjectManager";
+
+/// The object manager exported with `export_object_manager`.
+struct ObjectManager {
+    conn: Arc<SyncConnection>,
+    path: String,
+}
+
+impl ObjectManager {
+    /// Returns whether `path` is in the object tree managed by the object manager.
+    fn manages(&self, path: &str) -> bool {
+        self.path == "/" || path == self.path || path.starts_with(&format!("{}/", self.path))
+    }
+
+    fn send_signal<A: dbus::arg::Append>(&self, member: &'static str, path: &str, args: A) {
+        let msg = Message::signal(
+            &Path::from(self.path.clone()),
+            &OBJECT_MANAGER_IFACE.into(),
+            &member.into(),
+        )
+        .append2(Path::from(String::from(path)), args);
+
+        if self.conn.send(msg).is_err() {
+            eprintln!("Failed to send {} for {}", member, path);
+        }
+    }
+}
+
+lazy_static! {
+    static ref OBJECT_MANAGER: Mutex<Option<ObjectManager>> = Mutex::new(None);
+}
+
+/// Exports org.freedesktop.DBus.ObjectManager at `path`.
+///
+/// Clients can then discover the objects exported below `path` with GetManagedObjects, which is
+/// answered by crossroads, instead of hardcoding their paths. The objects exported afterwards
+/// are announced with the InterfacesAdded signal. The daemon objects live as long as the daemon,
+/// so none is ever removed.
+pub fn export_object_manager(path: &'static str, conn: Arc<SyncConnection>, cr: &mut Crossroads) {
+    let token: IfaceToken<()> = cr.object_manager();
+    cr.insert(path, &[token], ());
+
+    *OBJECT_MANAGER.lock().unwrap() = Some(ObjectManager { conn, path: String::from(path) });
+}
+
+/// Emits InterfacesAdded for an interface exported at `path`, with its `version`. Called by the
+/// generated exporters.
+pub fn notify_interface_added(path: &str, iface: &str, version: u32) {
+    if let Some(manager) = &*OBJECT_MANAGER.lock().unwrap() {
+        if !manager.manages(path) {
+            return;
+        }
+
+        // `Version` is the only D-Bus property of the projected interfaces.
+        let mut props = PropMap::new();
+        props.insert(String::from("Version"), Variant(Box::new(version)));
+        let mut ifaces: HashMap<String, PropMap> = HashMap::new();
+        ifaces.insert(String::from(iface), props);
+        manager.send_signal("InterfacesAdded", path, ifaces);
+    }
+}
+
+/// Implements `DBusArg` for an enum of the stack.
+///
+/// The enum is projected as its `i32` value, or with a list of names, e.g.
+/// `impl_dbus_arg_enum!(Transport, { Bredr => "classic", Le => "le" })`, as the name of its
+/// variant. Values that aren't a variant of the enum are refused with a `DBusArgError` naming the
+/// enum and the value, which the exporters return to the client as an invalid argument.
 #[macro_export]
 macro_rules! impl_dbus_arg_enum {
+    ($enum_type:ident, { $($variant:ident => $name:literal),* $(,)? }) => {
+        impl DBusArg for $enum_type {
+            type DBusType = String;
+            fn from_dbus(
+                data: String,
+                _conn: Arc<SyncConnection>,
+                _remote: BusName<'static>,
+                _disconnect_watcher: Arc<Mutex<dbus_projection::DisconnectWatcher>>,
+            ) -> Result<$enum_type, Box<dyn Error>> {
+                match data.as_str() {
+                    $($name => Ok($enum_type::$variant),)*
+                    _ => Err(Box::new(DBusArgError::new(format!(
+                        "invalid {} \"{}\", expected one of: {}",
+                        stringify!($enum_type),
+                        data,
+                        [$($name),*].join(", ")
+                    )))),
+                }
+            }
+
+            fn to_dbus(data: $enum_type) -> Result<String, Box<dyn Error>> {
+                match data {
+                    $($enum_type::$variant => Ok(String::from($name)),)*
+                }
+            }
+        }
+    };
     ($enum_type:ty) => {
         impl DBusArg for $enum_type {
             type DBusType = i32;
@@ -86,16 +410,22 @@ macro_rules! impl_dbus_arg_enum {
             ) -> Result<$enum_type, Box<dyn Error>> {
                 match <$enum_type>::from_i32(data) {
                     Some(x) => Ok(x),
-                    None => Err(Box::new(DBusArgError::new(String::from(format!(
-                        "error converting {} to {}",
-                        data,
-                        stringify!($enum_type)
-                    ))))),
+                    None => Err(Box::new(DBusArgError::new(format!(
+                        "invalid {} {}",
+                        stringify!($enum_type),
+                        data
+                    )))),
                 }
             }
 
             fn to_dbus(data: $enum_type) -> Result<i32, Box<dyn Error>> {
-                return Ok(data.to_i32().unwrap());
+                match data.to_i32() {
+                    Some(x) => Ok(x),
+                    None => Err(Box::new(DBusArgError::new(format!(
+                        "{} value out of the i32 range",
+                        stringify!($enum_type)
+                    )))),
+                }
             }
         }
     };
@@ -0,0 +1,117 @@
+//! Tests of the projections of enums generated by `impl_dbus_arg_enum`.
+
+use dbus::channel::Channel;
+use dbus::nonblock::SyncConnection;
+use dbus::strings::BusName;
+
+use dbus_projection::{impl_dbus_arg_enum, DisconnectWatcher};
+
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::cast::{FromPrimitive, ToPrimitive};
+
+use std::error::Error;
+use std::os::unix::net::UnixListener;
+use std::sync::{Arc, Mutex};
+
+use crate::dbus_arg::{DBusArg, DBusArgError};
+
+mod dbus_arg {
+    use dbus_macros::generate_dbus_arg;
+
+    generate_dbus_arg!();
+}
+
+/// An enum projected as its `i32` value.
+#[derive(Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(i32)]
+enum Profile {
+    A2dpSink = 1,
+    Hfp = 2,
+}
+
+impl_dbus_arg_enum!(Profile);
+
+/// An enum with a value out of the `i32` range.
+#[derive(Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(u32)]
+enum Flag {
+    Low = 1,
+    High = 0x8000_0000,
+}
+
+impl_dbus_arg_enum!(Flag);
+
+/// An enum projected as the names of its variants.
+#[derive(Debug, PartialEq)]
+enum Transport {
+    Bredr,
+    Le,
+}
+
+impl_dbus_arg_enum!(Transport, { Bredr => "classic", Le => "le" });
+
+/// Returns a connection for the conversions, which don't use it. It only has to be open, so it
+/// is made to a socket that nothing answers on, instead of to a bus.
+fn connection() -> Arc<SyncConnection> {
+    let name = format!("dbus_arg_enum_{:?}", std::thread::current().id());
+    let path = std::env::temp_dir().join(name.replace(|c: char| !c.is_alphanumeric(), "_"));
+    let _ = std::fs::remove_file(&path);
+    let _listener = UnixListener::bind(&path).unwrap();
+
+    let channel = Channel::open_private(&format!("unix:path={}", path.display())).unwrap();
+    let _ = std::fs::remove_file(&path);
+    Arc::new(SyncConnection::from(channel))
+}
+
+fn from_dbus<T: DBusArg>(data: T::DBusType) -> Result<T, Box<dyn Error>> {
+    T::from_dbus(
+        data,
+        connection(),
+        BusName::new(":1.1").unwrap(),
+        Arc::new(Mutex::new(DisconnectWatcher::new())),
+    )
+}
+
+#[test]
+fn value_projection() {
+    assert_eq!(Profile::to_dbus(Profile::Hfp).unwrap(), 2);
+    assert_eq!(from_dbus::<Profile>(1).unwrap(), Profile::A2dpSink);
+    assert_eq!(Flag::to_dbus(Flag::Low).unwrap(), 1);
+}
+
+#[test]
+fn unknown_value_refused() {
+    let error = from_dbus::<Profile>(3).unwrap_err();
+    assert!(error.is::<DBusArgError>());
+    assert_eq!(error.to_string(), "invalid Profile 3");
+
+    let error = from_dbus::<Flag>(i32::MIN).unwrap_err();
+    assert_eq!(error.to_string(), format!("invalid Flag {}", i32::MIN));
+}
+
+#[test]
+fn out_of_range_value_refused() {
+    let error = Flag::to_dbus(Flag::High).unwrap_err();
+    assert!(error.is::<DBusArgError>());
+    assert_eq!(error.to_string(), "Flag value out of the i32 range");
+}
+
+#[test]
+fn name_projection() {
+    assert_eq!(Transport::to_dbus(Transport::Bredr).unwrap(), "classic");
+    assert_eq!(Transport::to_dbus(Transport::Le).unwrap(), "le");
+    assert_eq!(from_dbus::<Transport>(String::from("classic")).unwrap(), Transport::Bredr);
+    assert_eq!(from_dbus::<Transport>(String::from("le")).unwrap(), Transport::Le);
+}
+
+#[test]
+fn unknown_name_refused() {
+    for name in ["", "LE", "bredr", "le "] {
+        let error = from_dbus::<Transport>(String::from(name)).unwrap_err();
+        assert!(error.is::<DBusArgError>());
+        assert_eq!(
+            error.to_string(),
+            format!("invalid Transport \"{}\", expected one of: classic, le", name)
+        );
+    }
+}
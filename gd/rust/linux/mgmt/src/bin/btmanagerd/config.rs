@@ -0,0 +1,236 @@
+//! Persistent configuration of the adapters, so that they come back as they were after a reboot.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+/// Where the configuration is stored.
+pub const CONFIG_PATH: &str = "/var/lib/bluetooth/btmanagerd.conf";
+
+/// Version of the configuration format. Files of older versions are migrated when loaded.
+const CONFIG_VERSION: u32 = 1;
+
+/// Configuration of an adapter, identified by its HCI interface.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AdapterConfig {
+    /// Whether the adapter was last started rather than stopped, in which case it is started
+    /// again when the manager starts.
+    pub enabled: bool,
+    /// Name the adapter is given by the UI, if it set one.
+    pub name: Option<String>,
+    pub discoverable: bool,
+}
+
+/// Configuration of all the adapters the manager has seen.
+///
+/// The file is a flat TOML document with a `version` key and a table per adapter, e.g.
+///
+/// ```text
+/// version = 1
+///
+/// [hci0]
+/// enabled = true
+/// name = "Chromebook"
+/// discoverable = false
+/// ```
+#[derive(Debug, Default, PartialEq)]
+pub struct ManagerConfig {
+    adapters: BTreeMap<i32, AdapterConfig>,
+}
+
+impl ManagerConfig {
+    /// Reads the configuration from the file at `path`.
+    ///
+    /// On first boot the file doesn't exist yet, so it is written with no adapter enabled. Files
+    /// of an older version are rewritten in the current format. A malformed file is replaced
+    /// too, as the manager couldn't restore the adapters from it anyway.
+    pub fn load(path: &str) -> ManagerConfig {
+        let (config, migrate) = match fs::read_to_string(path) {
+            Ok(contents) => match ManagerConfig::parse(&contents) {
+                Ok((config, version)) => (config, version < CONFIG_VERSION),
+                Err(e) => {
+                    println!("Resetting the adapter config, {} is malformed: {}", path, e);
+                    (ManagerConfig::default(), true)
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                println!("Creating the adapter config at {}", path);
+                (ManagerConfig::default(), true)
+            }
+            Err(e) => {
+                println!("Using the default adapter config, {} is not readable: {}", path, e);
+                return ManagerConfig::default();
+            }
+        };
+
+        if migrate {
+            if let Err(e) = config.save(path) {
+                println!("Failed to write the adapter config to {}: {}", path, e);
+            }
+        }
+
+        config
+    }
+
+    /// Writes the configuration to the file at `path`, replacing it at once so that a crash
+    /// doesn't leave it half written.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let tmp_path = format!("{}.new", path);
+        fs::write(&tmp_path, self.serialize())?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Returns the configuration of an adapter, the default one if the manager hasn't seen it.
+    pub fn adapter(&self, hci_interface: i32) -> AdapterConfig {
+        self.adapters.get(&hci_interface).cloned().unwrap_or_default()
+    }
+
+    pub fn adapter_mut(&mut self, hci_interface: i32) -> &mut AdapterConfig {
+        self.adapters.entry(hci_interface).or_default()
+    }
+
+    /// Returns the adapters to start when the manager starts.
+    pub fn enabled_adapters(&self) -> Vec<i32> {
+        self.adapters.iter().filter(|(_, adapter)| adapter.enabled).map(|(hci, _)| *hci).collect()
+    }
+
+    /// Parses a configuration, returning it with the version of its format.
+    fn parse(contents: &str) -> Result<(ManagerConfig, u32), String> {
+        let mut config = ManagerConfig::default();
+        // Files written before the format was versioned have no version key.
+        let mut version = 0;
+        let mut adapter: Option<i32> = None;
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                let hci = line[1..line.len() - 1].trim();
+                adapter = match hci.strip_prefix("hci").and_then(|n| n.parse::<i32>().ok()) {
+                    Some(hci) if hci >= 0 => Some(hci),
+                    _ => return Err(format!("line {}: {} is not an adapter", number + 1, hci)),
+                };
+                continue;
+            }
+
+            let mut pair = line.splitn(2, '=');
+            let (key, value) = match (pair.next(), pair.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => return Err(format!("line {} is not a key = value pair", number + 1)),
+            };
+
+            let hci = match adapter {
+                Some(hci) => hci,
+                None if key == "version" => {
+                    version = value
+                        .parse::<u32>()
+                        .map_err(|_| format!("line {}: {} is not a version", number + 1, value))?;
+                    continue;
+                }
+                None => return Err(format!("line {}: {} is not in a table", number + 1, key)),
+            };
+
+            let adapter = config.adapter_mut(hci);
+            match key {
+                "enabled" => adapter.enabled = parse_bool(value, number)?,
+                "discoverable" => adapter.discoverable = parse_bool(value, number)?,
+                "name" => adapter.name = Some(parse_string(value, number)?),
+                // Keys of newer versions of the manager are tolerated.
+                _ => println!("Ignoring unknown adapter config key {}", key),
+            }
+        }
+
+        Ok((config, version))
+    }
+
+    fn serialize(&self) -> String {
+        let mut contents = format!("version = {}\n", CONFIG_VERSION);
+
+        for (hci, adapter) in &self.adapters {
+            contents += &format!("\n[hci{}]\nenabled = {}\n", hci, adapter.enabled);
+            if let Some(name) = &adapter.name {
+                contents +=
+                    &format!("name = \"{}\"\n", name.replace('\\', "\\\\").replace('"', "\\\""));
+            }
+            contents += &format!("discoverable = {}\n", adapter.discoverable);
+        }
+
+        contents
+    }
+}
+
+fn parse_bool(value: &str, number: usize) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("line {}: {} is not a boolean", number + 1, value)),
+    }
+}
+
+/// Parses a basic TOML string, of which only the `\"` and `\\` escapes are supported.
+fn parse_string(value: &str, number: usize) -> Result<String, String> {
+    let malformed = || format!("line {}: {} is not a string", number + 1, value);
+
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return Err(malformed());
+    }
+
+    let mut string = String::new();
+    let mut chars = value[1..value.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ ('"' | '\\')) => string.push(escaped),
+                _ => return Err(malformed()),
+            },
+            '"' => return Err(malformed()),
+            c => string.push(c),
+        }
+    }
+
+    Ok(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialized_config_parses_back() {
+        let mut config = ManagerConfig::default();
+        config.adapter_mut(0).enabled = true;
+        *config.adapter_mut(1) = AdapterConfig {
+            enabled: false,
+            name: Some("Living \"room\" \\ 1".to_string()),
+            discoverable: true,
+        };
+
+        let (parsed, version) = ManagerConfig::parse(&config.serialize()).unwrap();
+        assert_eq!(parsed, config);
+        assert_eq!(version, CONFIG_VERSION);
+        assert_eq!(parsed.enabled_adapters(), vec![0]);
+    }
+
+    #[test]
+    fn unversioned_config_is_version_0() {
+        let (config, version) = ManagerConfig::parse("[hci2]\nenabled = true\n").unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(config.enabled_adapters(), vec![2]);
+    }
+
+    #[test]
+    fn malformed_config_is_rejected() {
+        assert!(ManagerConfig::parse("enabled = true\n").is_err());
+        assert!(ManagerConfig::parse("[hciX]\nenabled = true\n").is_err());
+        assert!(ManagerConfig::parse("[hci0]\nenabled = yes\n").is_err());
+        assert!(ManagerConfig::parse("[hci0]\nname = \"a\"b\"\n").is_err());
+    }
+
+    #[test]
+    fn unknown_adapter_has_default_config() {
+        assert_eq!(ManagerConfig::default().adapter(3), AdapterConfig::default());
+    }
+}
@@ -1,3 +1,4 @@
+mod config;
 mod state_machine;
 
 use dbus::channel::MatchingReceiver;
@@ -9,6 +10,7 @@ use dbus_tokio::connection;
 pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let context = state_machine::start_new_state_machine_context();
     let proxy = context.get_proxy();
+    let restore_proxy = context.get_proxy();
 
     // Connect to the D-Bus system bus (this is blocking, unfortunately).
     let (resource, c) = connection::new_system_sync()?;
@@ -84,6 +86,35 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 ctx.reply(Ok((result,)))
             }
         });
+        b.method_with_cr_async(
+            "GetAdapterConfig",
+            ("hci_interface",),
+            ("enabled", "name", "discoverable"),
+            |mut ctx, cr, (hci_interface,): (i32,)| {
+                let proxy =
+                    cr.data_mut::<state_machine::StateMachineProxy>(ctx.path()).unwrap().clone();
+                async move {
+                    let config = proxy.get_adapter_config(hci_interface).await;
+                    // An empty name means that the UI didn't set one.
+                    let name = config.name.unwrap_or_default();
+                    ctx.reply(Ok((config.enabled, name, config.discoverable)))
+                }
+            },
+        );
+        b.method_with_cr_async(
+            "SetAdapterConfig",
+            ("hci_interface", "name", "discoverable"),
+            (),
+            |mut ctx, cr, (hci_interface, name, discoverable): (i32, String, bool)| {
+                let proxy =
+                    cr.data_mut::<state_machine::StateMachineProxy>(ctx.path()).unwrap().clone();
+                async move {
+                    let name = if name.is_empty() { None } else { Some(name) };
+                    proxy.set_adapter_config(hci_interface, name, discoverable).await;
+                    ctx.reply(Ok(()))
+                }
+            },
+        );
         b.method_with_cr_async(
             "RegisterStateChangeObserver",
             ("object_path",),
@@ -141,6 +172,13 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
         state_machine::mainloop(context).await;
     });
 
+    // The adapters come back as they were before the manager restarted, e.g. after a reboot.
+    tokio::spawn(async move {
+        if restore_proxy.restore_adapters().await.is_err() {
+            println!("Failed to restore the adapters");
+        }
+    });
+
     loop {}
 
     // Run forever.
@@ -1,3 +1,4 @@
+use crate::config::{AdapterConfig, ManagerConfig, CONFIG_PATH};
 use bt_common::time::Alarm;
 use std::collections::VecDeque;
 use std::process::{Child, Command, Stdio};
@@ -27,6 +28,7 @@ pub struct StateMachineContext<PM> {
     tx: mpsc::Sender<StateMachineActions>,
     rx: mpsc::Receiver<StateMachineActions>,
     state_machine: ManagerStateMachine<PM>,
+    config: Arc<Mutex<ManagerConfig>>,
 }
 
 impl<PM> StateMachineContext<PM> {
@@ -35,7 +37,8 @@ impl<PM> StateMachineContext<PM> {
         PM: ProcessManager + Send,
     {
         let (tx, rx) = mpsc::channel::<StateMachineActions>(1);
-        StateMachineContext { tx: tx, rx: rx, state_machine: state_machine }
+        let config = Arc::new(Mutex::new(ManagerConfig::load(CONFIG_PATH)));
+        StateMachineContext { tx: tx, rx: rx, state_machine: state_machine, config: config }
     }
 
     pub fn get_proxy(&self) -> StateMachineProxy {
@@ -43,6 +46,7 @@ impl<PM> StateMachineContext<PM> {
             tx: self.tx.clone(),
             state: self.state_machine.state.clone(),
             state_change_observers: self.state_machine.state_change_observers.clone(),
+            config: self.config.clone(),
         }
     }
 }
@@ -56,18 +60,56 @@ pub struct StateMachineProxy {
     tx: mpsc::Sender<StateMachineActions>,
     state: Arc<Mutex<State>>,
     state_change_observers: Arc<Mutex<Vec<String>>>,
+    config: Arc<Mutex<ManagerConfig>>,
 }
 
 impl StateMachineProxy {
+    /// Starts an adapter, and records it to be started again when the manager restarts.
     pub async fn start_bluetooth(
         &self,
         hci_interface: i32,
     ) -> Result<(), SendError<StateMachineActions>> {
-        self.tx.send(StateMachineActions::StartBluetooth(hci_interface)).await
+        self.tx.send(StateMachineActions::StartBluetooth(hci_interface)).await?;
+        self.set_enabled(hci_interface, true).await;
+        Ok(())
     }
 
+    /// Stops an adapter, and records it to stay stopped when the manager restarts.
     pub async fn stop_bluetooth(&self, hci_interface: i32,) -> Result<(), SendError<StateMachineActions>> {
-        self.tx.send(StateMachineActions::StopBluetooth(hci_interface)).await
+        self.tx.send(StateMachineActions::StopBluetooth(hci_interface)).await?;
+        self.set_enabled(hci_interface, false).await;
+        Ok(())
+    }
+
+    /// Starts the adapters that were enabled when the manager last stopped.
+    pub async fn restore_adapters(&self) -> Result<(), SendError<StateMachineActions>> {
+        let enabled = self.config.lock().await.enabled_adapters();
+        for hci_interface in enabled {
+            println!("Restoring hci{}", hci_interface);
+            self.tx.send(StateMachineActions::StartBluetooth(hci_interface)).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_adapter_config(&self, hci_interface: i32) -> AdapterConfig {
+        self.config.lock().await.adapter(hci_interface)
+    }
+
+    /// Records the name and discoverability of an adapter, for the UI to restore them.
+    pub async fn set_adapter_config(&self, hci_interface: i32, name: Option<String>, discoverable: bool) {
+        let mut config = self.config.lock().await;
+        let adapter = config.adapter_mut(hci_interface);
+        adapter.name = name;
+        adapter.discoverable = discoverable;
+        save_config(&config);
+    }
+
+    async fn set_enabled(&self, hci_interface: i32, enabled: bool) {
+        let mut config = self.config.lock().await;
+        if config.adapter(hci_interface).enabled != enabled {
+            config.adapter_mut(hci_interface).enabled = enabled;
+            save_config(&config);
+        }
     }
 
     pub async fn get_state(&self) -> State {
@@ -93,6 +135,12 @@ impl StateMachineProxy {
     }
 }
 
+fn save_config(config: &ManagerConfig) {
+    if let Err(e) = config.save(CONFIG_PATH) {
+        println!("Failed to save the adapter config: {}", e);
+    }
+}
+
 pub async fn mainloop<PM>(mut context: StateMachineContext<PM>)
 where
     PM: ProcessManager + Send,
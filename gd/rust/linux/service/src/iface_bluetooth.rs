@@ -1,20 +1,36 @@
 extern crate bt_shim;
 
-use btstack::bluetooth::{IBluetooth, IBluetoothCallback};
+use bt_topshim::btif::ffi::BtOutOfBandData;
+use bt_topshim::btif::{BtSspVariant, BtStatus, SupportedProfiles};
+
+use btstack::bluetooth::{
+    BondStatus, ControllerInfo, DiscoveryFilter, IBluetooth, IBluetoothCallback,
+    IBluetoothPairingAgent, IoCapability, LeConnectionPreset, LeOwnAddress, LeOwnAddressType,
+    LinkRole, SecurityLevel,
+};
+use btstack::bluetooth_gatt::RSSISettings;
+use btstack::device_class::DeviceCategory;
+use btstack::uuid::Uuid128;
 use btstack::RPCProxy;
 
+use dbus::arg::{PropMap, RefArg, Variant};
 use dbus::nonblock::SyncConnection;
 use dbus::strings::{BusName, Path};
 
-use dbus_macros::{dbus_method, dbus_proxy_obj, generate_dbus_exporter};
+use dbus_macros::{dbus_method, dbus_propmap, dbus_proxy_obj, generate_dbus_exporter};
 
+use dbus_projection::impl_dbus_arg_enum;
 use dbus_projection::DisconnectWatcher;
 
+use num_traits::cast::{FromPrimitive, ToPrimitive};
+
+use std::collections::HashMap;
+use std::convert::TryInto;
 use std::error::Error;
 use std::sync::Arc;
 use std::sync::Mutex;
 
-use crate::dbus_arg::DBusArg;
+use crate::dbus_arg::{DBusArg, DBusArgError};
 
 #[allow(dead_code)]
 struct BluetoothCallbackDBus {}
@@ -25,27 +41,436 @@ impl IBluetoothCallback for BluetoothCallbackDBus {
     fn on_bluetooth_state_changed(&self, prev_state: u32, new_state: u32) {}
     #[dbus_method("OnBluetoothAddressChanged")]
     fn on_bluetooth_address_changed(&self, addr: String) {}
+    #[dbus_method("OnLeOwnAddressChanged")]
+    fn on_le_own_address_changed(&self, address: LeOwnAddress) {}
+    #[dbus_method("OnBondStateChanged")]
+    fn on_bond_state_changed(&self, status: BondStatus, addr: String, state: u32) {}
+    #[dbus_method("OnDeviceConnected")]
+    fn on_device_connected(&self, addr: String) {}
+    #[dbus_method("OnDeviceDisconnected")]
+    fn on_device_disconnected(&self, addr: String, status: BtStatus, hci_reason: i32) {}
+    #[dbus_method("OnDiscoverableChanged")]
+    fn on_discoverable_changed(&self, discoverable: bool) {}
+    #[dbus_method("OnPairableChanged")]
+    fn on_pairable_changed(&self, pairable: bool) {}
+    #[dbus_method("OnLocalOobDataGenerated")]
+    fn on_local_oob_data_generated(&self, transport: i32, data: BtOutOfBandData) {}
+    #[dbus_method("OnRssiChanged")]
+    fn on_rssi_changed(&self, addr: String, rssi: i32, near: bool) {}
+    #[dbus_method("OnLePhyChanged")]
+    fn on_le_phy_changed(&self, addr: String, tx_phy: u32, rx_phy: u32) {}
+    #[dbus_method("OnRemoteUuidsChanged")]
+    fn on_remote_uuids_changed(&self, addr: String, uuids: Vec<Uuid128>) {}
+    #[dbus_method("OnIdentityAddressResolved")]
+    fn on_identity_address_resolved(&self, addr: String, identity_address: String) {}
+    #[dbus_method("OnDeviceFound")]
+    fn on_device_found(&self, addr: String, name: String, class_of_device: u32, rssi: i32) {}
+    #[dbus_method("OnDiscoveringChanged")]
+    fn on_discovering_changed(&self, discovering: bool) {}
+    #[dbus_method("OnLinkRoleChanged")]
+    fn on_link_role_changed(&self, addr: String, transport: i32, role: LinkRole) {}
+    #[dbus_method("OnRoleSwitchCompleted")]
+    fn on_role_switch_completed(&self, addr: String, status: BtStatus, role: LinkRole) {}
+}
+
+#[allow(dead_code)]
+struct BluetoothPairingAgentDBus {}
+
+#[dbus_proxy_obj(BluetoothPairingAgent, "org.chromium.bluetooth.BluetoothPairingAgent")]
+impl IBluetoothPairingAgent for BluetoothPairingAgentDBus {
+    #[dbus_method("OnSspRequest")]
+    fn on_ssp_request(&self, addr: String, name: String, variant: BtSspVariant, passkey: u32) {}
+    #[dbus_method("OnPinRequest")]
+    fn on_pin_request(&self, addr: String, name: String, min_16_digit: bool) {}
+    #[dbus_method("OnRepairRequest")]
+    fn on_repair_request(&self, addr: String, name: String) {}
+}
+
+/// Returns the value of `key` in the D-Bus representation of `BtOutOfBandData`.
+fn get_oob_field<'a>(data: &'a PropMap, key: &str) -> Result<&'a dyn RefArg, Box<dyn Error>> {
+    match data.get(key) {
+        Some(variant) => Ok(&*variant.0),
+        None => Err(Box::new(DBusArgError::new(String::from(format!(
+            "BtOutOfBandData.{} is required",
+            key
+        ))))),
+    }
+}
+
+/// Returns the byte array value of `key` in the D-Bus representation of `BtOutOfBandData`.
+fn get_oob_bytes(data: &PropMap, key: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    match get_oob_field(data, key)?.as_any().downcast_ref::<Vec<u8>>() {
+        Some(bytes) => Ok(bytes.clone()),
+        None => Err(Box::new(DBusArgError::new(String::from(format!(
+            "BtOutOfBandData.{} must be a byte array",
+            key
+        ))))),
+    }
+}
+
+fn oob_length_error(key: &str, expected: usize, found: usize) -> Box<dyn Error> {
+    Box::new(DBusArgError::new(String::from(format!(
+        "BtOutOfBandData.{} must be {} bytes, found {} bytes",
+        key, expected, found
+    ))))
+}
+
+impl DBusArg for BtOutOfBandData {
+    type DBusType = PropMap;
+
+    fn from_dbus(
+        data: PropMap,
+        _conn: Arc<SyncConnection>,
+        _remote: BusName<'static>,
+        _disconnect_watcher: Arc<Mutex<DisconnectWatcher>>,
+    ) -> Result<BtOutOfBandData, Box<dyn Error>> {
+        let is_valid = match get_oob_field(&data, "is_valid")?.as_any().downcast_ref::<bool>() {
+            Some(is_valid) => *is_valid,
+            None => {
+                return Err(Box::new(DBusArgError::new(String::from(
+                    "BtOutOfBandData.is_valid must be a boolean",
+                ))));
+            }
+        };
+        let address = get_oob_bytes(&data, "address")?
+            .try_into()
+            .map_err(|v: Vec<u8>| oob_length_error("address", 7, v.len()))?;
+        let c = get_oob_bytes(&data, "c")?
+            .try_into()
+            .map_err(|v: Vec<u8>| oob_length_error("c", 16, v.len()))?;
+        let r = get_oob_bytes(&data, "r")?
+            .try_into()
+            .map_err(|v: Vec<u8>| oob_length_error("r", 16, v.len()))?;
+
+        Ok(BtOutOfBandData { is_valid, address, c, r })
+    }
+
+    fn to_dbus(data: BtOutOfBandData) -> Result<PropMap, Box<dyn Error>> {
+        let mut map: PropMap = HashMap::new();
+        map.insert(String::from("is_valid"), Variant(Box::new(data.is_valid)));
+        map.insert(String::from("address"), Variant(Box::new(data.address.to_vec())));
+        map.insert(String::from("c"), Variant(Box::new(data.c.to_vec())));
+        map.insert(String::from("r"), Variant(Box::new(data.r.to_vec())));
+        Ok(map)
+    }
+}
+
+impl_dbus_arg_enum!(BondStatus);
+impl_dbus_arg_enum!(BtStatus);
+impl_dbus_arg_enum!(SupportedProfiles);
+impl_dbus_arg_enum!(LeConnectionPreset);
+impl_dbus_arg_enum!(DeviceCategory);
+impl_dbus_arg_enum!(IoCapability);
+impl_dbus_arg_enum!(BtSspVariant);
+impl_dbus_arg_enum!(LinkRole);
+impl_dbus_arg_enum!(LeOwnAddressType);
+
+#[dbus_propmap(SecurityLevel)]
+pub struct SecurityLevelDBus {
+    connected: bool,
+    le: bool,
+    encrypted: bool,
+    authenticated: bool,
+    secure_connections: bool,
+}
+
+#[dbus_propmap(LeOwnAddress)]
+pub struct LeOwnAddressDBus {
+    address: String,
+    address_type: LeOwnAddressType,
+}
+
+#[dbus_propmap(DiscoveryFilter)]
+pub struct DiscoveryFilterDBus {
+    major_classes: u32,
+    transport: i32,
+    name_substring: String,
+}
+
+#[dbus_propmap(ControllerInfo)]
+pub struct ControllerInfoDBus {
+    is_ready: bool,
+    hci_version: u32,
+    hci_revision: u32,
+    lmp_version: u32,
+    manufacturer: u32,
+    lmp_subversion: u32,
+    le_supported: bool,
+    le_2m_phy: bool,
+    le_coded_phy: bool,
+    le_extended_advertising: bool,
+    le_periodic_advertising: bool,
+    le_max_advertising_data_length: u32,
+    le_number_of_advertising_sets: u32,
 }
 
 #[allow(dead_code)]
 struct IBluetoothDBus {}
 
-#[generate_dbus_exporter(export_bluetooth_dbus_obj, "org.chromium.bluetooth.Bluetooth")]
+#[generate_dbus_exporter(
+    export_bluetooth_dbus_obj,
+    "org.chromium.bluetooth.Bluetooth",
+    trace,
+    version = 3
+)]
 impl IBluetooth for IBluetoothDBus {
     #[dbus_method("RegisterCallback")]
-    fn register_callback(&mut self, callback: Box<dyn IBluetoothCallback + Send>) {}
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothCallback + Send>) -> u32 {
+        0
+    }
 
     #[dbus_method("Enable")]
-    fn enable(&mut self) -> bool {
-        false
+    fn enable(&mut self) -> BtStatus {
+        BtStatus::Success
     }
     #[dbus_method("Disable")]
-    fn disable(&mut self) -> bool {
-        false
+    fn disable(&mut self) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("FactoryReset")]
+    fn factory_reset(&mut self) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("GetState")]
+    fn get_state(&self) -> u32 {
+        0
+    }
+
+    #[dbus_method("GetStackVersion")]
+    fn get_stack_version(&self) -> String {
+        String::from("")
     }
 
     #[dbus_method("GetAddress")]
     fn get_address(&self) -> String {
         String::from("")
     }
+
+    #[dbus_method("GetConnectionState")]
+    fn get_connection_state(&self, device: String) -> u32 {
+        0
+    }
+
+    #[dbus_method("GetRemoteName")]
+    fn get_remote_name(&self, device: String) -> String {
+        String::from("")
+    }
+
+    #[dbus_method("GetUuids")]
+    fn get_uuids(&self) -> Vec<Uuid128> {
+        vec![]
+    }
+
+    #[dbus_method("GetRemoteUuids")]
+    fn get_remote_uuids(&self, device: String) -> Vec<Uuid128> {
+        vec![]
+    }
+
+    #[dbus_method("GetRemoteCategory")]
+    fn get_remote_category(&self, device: String) -> DeviceCategory {
+        DeviceCategory::Unknown
+    }
+
+    #[dbus_method("GetSecurityLevel")]
+    fn get_security_level(&self, device: String) -> SecurityLevel {
+        SecurityLevel::default()
+    }
+
+    #[dbus_method("GetIdentityAddress")]
+    fn get_identity_address(&mut self, device: String) -> String {
+        String::new()
+    }
+
+    #[dbus_method("FetchRemoteUuids")]
+    fn fetch_remote_uuids(&mut self, device: String) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("GenerateLocalOobData")]
+    fn generate_local_oob_data(&mut self, transport: i32) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("CreateBond", log = "redact(device)")]
+    fn create_bond(&mut self, device: String, transport: i32) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("CreateBondOutOfBand")]
+    fn create_bond_out_of_band(
+        &mut self,
+        device: String,
+        transport: i32,
+        p192_data: BtOutOfBandData,
+        p256_data: BtOutOfBandData,
+    ) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("CancelBondProcess", log = "redact(device)")]
+    fn cancel_bond_process(&mut self, device: String) -> bool {
+        false
+    }
+
+    #[dbus_method("RegisterPairingAgent")]
+    fn register_pairing_agent(
+        &mut self,
+        agent: Box<dyn IBluetoothPairingAgent + Send>,
+        capability: IoCapability,
+    ) -> u32 {
+        0
+    }
+
+    #[dbus_method("UnregisterPairingAgent")]
+    fn unregister_pairing_agent(&mut self, id: u32) -> bool {
+        false
+    }
+
+    #[dbus_method("SetPairingConfirmation", log = "redact(device)")]
+    fn set_pairing_confirmation(&mut self, device: String, accept: bool) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("SetRepairConfirmation", log = "redact(device)")]
+    fn set_repair_confirmation(&mut self, device: String, accept: bool) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("SetPasskey", log = "redact(device, passkey)")]
+    fn set_passkey(&mut self, device: String, accept: bool, passkey: u32) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("SetPin", log = "redact(device, pin)")]
+    fn set_pin(&mut self, device: String, accept: bool, pin: Vec<u8>) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("SetDiscoverable")]
+    fn set_discoverable(&mut self, mode: bool, duration: u32) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("GetDiscoverable")]
+    fn get_discoverable(&self) -> bool {
+        false
+    }
+
+    #[dbus_method("SetPairable")]
+    fn set_pairable(&mut self, mode: bool, timeout: u32) {}
+
+    #[dbus_method("GetPairable")]
+    fn get_pairable(&self) -> bool {
+        false
+    }
+
+    #[dbus_method("SetPairingBlockedClasses")]
+    fn set_pairing_blocked_classes(&mut self, major_classes: u32) {}
+
+    #[dbus_method("BlockDevice")]
+    fn block_device(&mut self, device: String) -> bool {
+        false
+    }
+
+    #[dbus_method("UnblockDevice")]
+    fn unblock_device(&mut self, device: String) -> bool {
+        false
+    }
+
+    #[dbus_method("GetBlockedDevices")]
+    fn get_blocked_devices(&self) -> Vec<String> {
+        vec![]
+    }
+
+    #[dbus_method("StartDiscovery")]
+    fn start_discovery(&mut self, callback_id: u32, filter: DiscoveryFilter) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("CancelDiscovery")]
+    fn cancel_discovery(&mut self) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("IsDiscovering")]
+    fn is_discovering(&self) -> bool {
+        false
+    }
+
+    #[dbus_method("GetControllerInfo")]
+    fn get_controller_info(&self) -> ControllerInfo {
+        ControllerInfo::default()
+    }
+
+    #[dbus_method("StartRssiMonitoring")]
+    fn start_rssi_monitoring(
+        &mut self,
+        device: String,
+        period_ms: u32,
+        settings: RSSISettings,
+    ) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("StopRssiMonitoring")]
+    fn stop_rssi_monitoring(&mut self, device: String) -> bool {
+        false
+    }
+
+    #[dbus_method("SetDefaultLePhy")]
+    fn set_default_le_phy(&mut self, tx_phys: u32, rx_phys: u32) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("SetLePhy")]
+    fn set_le_phy(&mut self, device: String, tx_phys: u32, rx_phys: u32) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("SetLeConnectionPreset")]
+    fn set_le_connection_preset(&mut self, device: String, preset: LeConnectionPreset) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("GetLinkRole")]
+    fn get_link_role(&self, device: String, transport: i32) -> LinkRole {
+        LinkRole::Unknown
+    }
+
+    #[dbus_method("RequestRoleSwitch")]
+    fn request_role_switch(&mut self, device: String, role: LinkRole) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("SetLePrivacy")]
+    fn set_le_privacy(&mut self, enabled: bool) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("GetLePrivacy")]
+    fn get_le_privacy(&self) -> bool {
+        true
+    }
+
+    #[dbus_method("SetRpaTimeout")]
+    fn set_rpa_timeout(&mut self, timeout_s: u32) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("SetLeStaticAddress")]
+    fn set_le_static_address(&mut self, address: String) -> BtStatus {
+        BtStatus::Success
+    }
+
+    #[dbus_method("GetLeOwnAddress")]
+    fn get_le_own_address(&self) -> LeOwnAddress {
+        LeOwnAddress::default()
+    }
+
+    #[dbus_method("GetSupportedProfiles")]
+    fn get_supported_profiles(&self) -> Vec<SupportedProfiles> {
+        vec![]
+    }
 }
@@ -0,0 +1,140 @@
+use btstack::bluetooth::ConnectionEvent;
+use btstack::bluetooth_debug::{
+    IBluetoothDebug, IBluetoothDebugCallback, LinkQuality, ThroughputTestMode, ThroughputTestResult,
+};
+use btstack::RPCProxy;
+
+use dbus::arg::RefArg;
+
+use dbus::nonblock::SyncConnection;
+use dbus::strings::{BusName, Path};
+
+use dbus_macros::{dbus_method, dbus_propmap, dbus_proxy_obj, generate_dbus_exporter};
+
+use dbus_projection::impl_dbus_arg_enum;
+use dbus_projection::DisconnectWatcher;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::dbus_arg::{DBusArg, DBusArgError};
+
+#[dbus_propmap(LinkQuality)]
+pub struct LinkQualityDBus {
+    report_count: u32,
+    last_report_id: i32,
+    rssi: i32,
+    average_rssi: i32,
+    snr: i32,
+    retransmission_count: u32,
+    packets_not_received: u32,
+    nak_count: u32,
+}
+
+#[dbus_propmap(ConnectionEvent)]
+pub struct ConnectionEventDBus {
+    timestamp_ms: u64,
+    event_type: u32,
+    status: i32,
+    hci_reason: i32,
+}
+
+#[dbus_propmap(ThroughputTestResult)]
+pub struct ThroughputTestResultDBus {
+    status: i32,
+    duration_ms: u32,
+    payload_len: u32,
+    packet_count: u32,
+    byte_count: u64,
+    failure_count: u32,
+    kbps: u32,
+    latency_p50_us: u32,
+    latency_p90_us: u32,
+    latency_p99_us: u32,
+}
+
+impl_dbus_arg_enum!(ThroughputTestMode, {
+    WriteWithoutResponse => "write_without_response",
+    Notification => "notification",
+});
+
+#[allow(dead_code)]
+struct BluetoothDebugCallbackDBus {}
+
+#[dbus_proxy_obj(BluetoothDebugCallback, "org.chromium.bluetooth.BluetoothDebugCallback")]
+impl IBluetoothDebugCallback for BluetoothDebugCallbackDBus {
+    #[dbus_method("OnLinkQualityMetrics")]
+    fn on_link_quality_metrics(&self, addr: String, quality: LinkQuality) {}
+
+    #[dbus_method("OnThroughputTestDone")]
+    fn on_throughput_test_done(&self, addr: String, result: ThroughputTestResult) {}
+
+    #[dbus_method("OnVendorCommandComplete")]
+    fn on_vendor_command_complete(&self, opcode: u32, params: Vec<u8>) {}
+}
+
+#[allow(dead_code)]
+struct IBluetoothDebugDBus {}
+
+#[generate_dbus_exporter(
+    export_bluetooth_debug_dbus_obj,
+    "org.chromium.bluetooth.BluetoothDebug",
+    trace,
+    version = 2
+)]
+impl IBluetoothDebug for IBluetoothDebugDBus {
+    #[dbus_method("RegisterCallback")]
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothDebugCallback + Send>) {}
+
+    #[dbus_method("GetLinkQuality")]
+    fn get_link_quality(&self, device: String) -> LinkQuality {
+        LinkQuality::default()
+    }
+
+    #[dbus_method("GetConnectionHistory")]
+    fn get_connection_history(&self, device: String, limit: u32) -> Vec<ConnectionEvent> {
+        vec![]
+    }
+
+    #[dbus_method("GetFeatureFlag")]
+    fn get_feature_flag(&self, name: String) -> bool {
+        false
+    }
+
+    #[dbus_method("SetFeatureFlag")]
+    fn set_feature_flag(&mut self, name: String, enabled: bool) -> bool {
+        false
+    }
+
+    #[dbus_method("ClearFeatureFlag")]
+    fn clear_feature_flag(&mut self, name: String) -> bool {
+        false
+    }
+
+    #[dbus_method("StartThroughputTest")]
+    fn start_throughput_test(
+        &mut self,
+        device: String,
+        mode: ThroughputTestMode,
+        duration_secs: u32,
+    ) -> bool {
+        false
+    }
+
+    #[dbus_method("GetThroughputTestResult")]
+    fn get_throughput_test_result(&self, device: String) -> ThroughputTestResult {
+        ThroughputTestResult::default()
+    }
+
+    #[dbus_method("DumpMetrics")]
+    fn dump_metrics(&self) -> String {
+        String::new()
+    }
+
+    #[dbus_method("SendVendorCommand")]
+    fn send_vendor_command(&mut self, opcode: u32, params: Vec<u8>) -> bool {
+        false
+    }
+}
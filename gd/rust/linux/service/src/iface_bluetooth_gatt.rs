@@ -1,6 +1,13 @@
+use bt_topshim::profiles::gatt::GattStatus;
+
 use btstack::bluetooth_gatt::{
-    IBluetoothGatt, IScannerCallback, RSSISettings, ScanFilter, ScanSettings, ScanType,
+    AdvertisementMonitor, AdvertisementPattern, AdvertisingSetParameters, ConnectionPriority,
+    GattAttribute, GattService, IAdvertisementMonitorCallback, IAdvertisingSetCallback,
+    IBluetoothGatt, IBluetoothGattCallback, IBluetoothGattServerCallback, IScannerCallback,
+    OwnAddressType, RSSISettings, ScanFilter, ScanReportMode, ScanResult, ScanSettings, ScanType,
 };
+use btstack::device_class::DeviceCategory;
+use btstack::uuid::Uuid128;
 use btstack::RPCProxy;
 
 use dbus::arg::RefArg;
@@ -21,6 +28,143 @@ use std::sync::{Arc, Mutex};
 
 use crate::dbus_arg::{DBusArg, DBusArgError};
 
+#[allow(dead_code)]
+struct BluetoothGattCallbackDBus {}
+
+#[dbus_proxy_obj(BluetoothGattCallback, "org.chromium.bluetooth.BluetoothGattCallback")]
+impl IBluetoothGattCallback for BluetoothGattCallbackDBus {
+    #[dbus_method("OnClientRegistered")]
+    fn on_client_registered(&self, status: GattStatus, client_id: i32) {}
+
+    #[dbus_method("OnClientConnectionState")]
+    fn on_client_connection_state(
+        &self,
+        status: GattStatus,
+        connected: bool,
+        addr: String,
+        conn_id: i32,
+    ) {
+    }
+
+    #[dbus_method("OnReadRemoteRssi")]
+    fn on_read_remote_rssi(&self, addr: String, rssi: i32, status: i32) {}
+
+    #[dbus_method("OnSearchComplete")]
+    fn on_search_complete(&self, conn_id: i32, status: GattStatus) {}
+
+    #[dbus_method("OnCharacteristicRead")]
+    fn on_characteristic_read(&self, conn_id: i32, status: i32, handle: i32, value: Vec<u8>) {}
+
+    #[dbus_method("OnCharacteristicWrite")]
+    fn on_characteristic_write(&self, conn_id: i32, status: i32, handle: i32) {}
+
+    #[dbus_method("OnExecuteWrite")]
+    fn on_execute_write(&self, conn_id: i32, status: i32) {}
+
+    #[dbus_method("OnNotificationRegistered")]
+    fn on_notification_registered(&self, conn_id: i32, status: i32, handle: i32, registered: bool) {
+    }
+
+    #[dbus_method("OnCharacteristicChanged")]
+    fn on_characteristic_changed(&self, conn_id: i32, handle: i32, value: Vec<u8>) {}
+
+    #[dbus_method("OnConfigureMtu")]
+    fn on_configure_mtu(&self, conn_id: i32, status: GattStatus, mtu: i32) {}
+
+    #[dbus_method("OnPhyUpdate")]
+    fn on_phy_update(&self, addr: String, tx_phy: u32, rx_phy: u32, status: i32) {}
+
+    #[dbus_method("OnPhyRead")]
+    fn on_phy_read(&self, addr: String, tx_phy: u32, rx_phy: u32, status: i32) {}
+
+    #[dbus_method("OnConnectionUpdated")]
+    fn on_connection_updated(
+        &self,
+        addr: String,
+        interval: i32,
+        latency: i32,
+        timeout: i32,
+        status: i32,
+    ) {
+    }
+
+    #[dbus_method("OnServiceChanged")]
+    fn on_service_changed(&self, addr: String) {}
+}
+
+#[allow(dead_code)]
+struct BluetoothGattServerCallbackDBus {}
+
+#[dbus_proxy_obj(BluetoothGattServerCallback, "org.chromium.bluetooth.BluetoothGattServerCallback")]
+impl IBluetoothGattServerCallback for BluetoothGattServerCallbackDBus {
+    #[dbus_method("OnServerRegistered")]
+    fn on_server_registered(&self, status: GattStatus, server_id: i32) {}
+
+    #[dbus_method("OnServerConnectionState")]
+    fn on_server_connection_state(&self, server_id: i32, connected: bool, addr: String) {}
+
+    #[dbus_method("OnNotificationSent")]
+    fn on_notification_sent(&self, addr: String, status: GattStatus) {}
+
+    #[dbus_method("OnNotificationQueueAvailable")]
+    fn on_notification_queue_available(&self, addr: String) {}
+
+    #[dbus_method("OnAttributeWriteRequest")]
+    fn on_attribute_write_request(
+        &self,
+        addr: String,
+        trans_id: i32,
+        handle: i32,
+        offset: i32,
+        need_response: bool,
+        value: Vec<u8>,
+    ) {
+    }
+
+    #[dbus_method("OnExecuteWriteRequest")]
+    fn on_execute_write_request(&self, addr: String, trans_id: i32) {}
+
+    #[dbus_method("OnServiceAdded")]
+    fn on_service_added(&self, status: GattStatus, service: GattService) {}
+
+    #[dbus_method("OnAttributeReadRequest")]
+    fn on_attribute_read_request(
+        &self,
+        addr: String,
+        trans_id: i32,
+        handle: i32,
+        offset: i32,
+        is_long: bool,
+    ) {
+    }
+
+    #[dbus_method("OnPhyRead")]
+    fn on_phy_read(&self, addr: String, tx_phy: u32, rx_phy: u32, status: i32) {}
+}
+
+/// UUIDs are projected as strings, e.g. "0000180d-0000-1000-8000-00805f9b34fb" or "180d".
+impl DBusArg for Uuid128 {
+    type DBusType = String;
+
+    fn from_dbus(
+        data: String,
+        _conn: Arc<SyncConnection>,
+        _remote: BusName<'static>,
+        _disconnect_watcher: Arc<Mutex<DisconnectWatcher>>,
+    ) -> Result<Uuid128, Box<dyn Error>> {
+        match Uuid128::from_string(&data) {
+            Some(uuid) => Ok(uuid),
+            None => {
+                Err(Box::new(DBusArgError::new(String::from(format!("Invalid UUID: {}", data)))))
+            }
+        }
+    }
+
+    fn to_dbus(data: Uuid128) -> Result<String, Box<dyn Error>> {
+        Ok(data.to_string())
+    }
+}
+
 #[allow(dead_code)]
 struct ScannerCallbackDBus {}
 
@@ -28,6 +172,25 @@ struct ScannerCallbackDBus {}
 impl IScannerCallback for ScannerCallbackDBus {
     #[dbus_method("OnScannerRegistered")]
     fn on_scanner_registered(&self, _status: i32, _scanner_id: i32) {}
+
+    #[dbus_method("OnScanResult")]
+    fn on_scan_result(&self, result: ScanResult) {}
+}
+
+#[dbus_propmap(ScanResult)]
+struct ScanResultDBus {
+    address: String,
+    addr_type: i32,
+    event_type: i32,
+    primary_phy: i32,
+    secondary_phy: i32,
+    advertising_sid: i32,
+    tx_power: i32,
+    rssi: i32,
+    periodic_adv_int: i32,
+    adv_data: Vec<u8>,
+    #[dbus_propmap_field_enum]
+    category: DeviceCategory,
 }
 
 #[dbus_propmap(RSSISettings)]
@@ -36,38 +199,456 @@ pub struct RSSISettingsDBus {
     high_threshold: i32,
 }
 
-#[dbus_propmap(ScanSettings)]
+#[dbus_propmap(ScanSettings, lenient)]
 struct ScanSettingsDBus {
     interval: i32,
 
     window: i32,
 
+    background: bool,
+
     #[dbus_propmap_field_enum]
     scan_type: ScanType,
 
     #[dbus_propmap_field_propmap]
     rssi_settings: RSSISettings,
+
+    #[dbus_propmap_field_enum]
+    report_mode: ScanReportMode,
+
+    report_period_ms: i32,
 }
 
+impl_dbus_arg_enum!(GattStatus);
 impl_dbus_arg_enum!(ScanType);
+impl_dbus_arg_enum!(ScanReportMode);
+impl_dbus_arg_enum!(ConnectionPriority);
 
 #[dbus_propmap(ScanFilter)]
 struct ScanFilterDBus {}
 
+#[allow(dead_code)]
+struct AdvertisingSetCallbackDBus {}
+
+#[dbus_proxy_obj(AdvertisingSetCallback, "org.chromium.bluetooth.AdvertisingSetCallback")]
+impl IAdvertisingSetCallback for AdvertisingSetCallbackDBus {
+    #[dbus_method("OnAdvertisingSetStarted")]
+    fn on_advertising_set_started(&self, advertiser_id: i32, tx_power: i32, status: i32) {}
+
+    #[dbus_method("OnOwnAddressRead")]
+    fn on_own_address_read(&self, advertiser_id: i32, address_type: i32, address: String) {}
+
+    #[dbus_method("OnAdvertisingSetStopped")]
+    fn on_advertising_set_stopped(&self, advertiser_id: i32) {}
+
+    #[dbus_method("OnAdvertisingEnabled")]
+    fn on_advertising_enabled(&self, advertiser_id: i32, enable: bool, status: i32) {}
+
+    #[dbus_method("OnAdvertisingDataSet")]
+    fn on_advertising_data_set(&self, advertiser_id: i32, status: i32) {}
+
+    #[dbus_method("OnScanResponseDataSet")]
+    fn on_scan_response_data_set(&self, advertiser_id: i32, status: i32) {}
+
+    #[dbus_method("OnAdvertisingParametersUpdated")]
+    fn on_advertising_parameters_updated(&self, advertiser_id: i32, tx_power: i32, status: i32) {}
+}
+
+#[dbus_propmap(AdvertisingSetParameters)]
+struct AdvertisingSetParametersDBus {
+    connectable: bool,
+    scannable: bool,
+    is_legacy: bool,
+    interval: i32,
+    tx_power_level: i32,
+    own_address_type: OwnAddressType,
+}
+
+impl_dbus_arg_enum!(OwnAddressType);
+
+#[allow(dead_code)]
+struct AdvertisementMonitorCallbackDBus {}
+
+#[dbus_proxy_obj(
+    AdvertisementMonitorCallback,
+    "org.chromium.bluetooth.AdvertisementMonitorCallback"
+)]
+impl IAdvertisementMonitorCallback for AdvertisementMonitorCallbackDBus {
+    #[dbus_method("OnDeviceFoundInMonitor")]
+    fn on_device_found_in_monitor(
+        &self,
+        monitor_id: i32,
+        addr: String,
+        rssi: i32,
+        adv_data: Vec<u8>,
+    ) {
+    }
+}
+
+/// Monitors are projected as (patterns, rssi_high_threshold, rssi_low_threshold) structs, with
+/// each pattern an (ad_type, start_position, content) struct.
+impl DBusArg for AdvertisementMonitor {
+    type DBusType = (Vec<(u8, u8, Vec<u8>)>, i32, i32);
+
+    fn from_dbus(
+        data: (Vec<(u8, u8, Vec<u8>)>, i32, i32),
+        _conn: Arc<SyncConnection>,
+        _remote: BusName<'static>,
+        _disconnect_watcher: Arc<Mutex<DisconnectWatcher>>,
+    ) -> Result<AdvertisementMonitor, Box<dyn Error>> {
+        let (patterns, rssi_high_threshold, rssi_low_threshold) = data;
+        let patterns = patterns
+            .into_iter()
+            .map(|(ad_type, start_position, content)| AdvertisementPattern {
+                ad_type,
+                start_position,
+                content,
+            })
+            .collect();
+
+        Ok(AdvertisementMonitor { patterns, rssi_high_threshold, rssi_low_threshold })
+    }
+
+    fn to_dbus(
+        data: AdvertisementMonitor,
+    ) -> Result<(Vec<(u8, u8, Vec<u8>)>, i32, i32), Box<dyn Error>> {
+        let patterns = data
+            .patterns
+            .into_iter()
+            .map(|pattern| (pattern.ad_type, pattern.start_position, pattern.content))
+            .collect();
+
+        Ok((patterns, data.rssi_high_threshold, data.rssi_low_threshold))
+    }
+}
+
+/// Services are projected as (uuid, handle, primary, attributes) structs, with each attribute an
+/// (attribute_type, uuid, handle, properties, permissions) struct.
+impl DBusArg for GattService {
+    type DBusType = (String, i32, bool, Vec<(i32, String, i32, i32, i32)>);
+
+    fn from_dbus(
+        data: (String, i32, bool, Vec<(i32, String, i32, i32, i32)>),
+        conn: Arc<SyncConnection>,
+        remote: BusName<'static>,
+        disconnect_watcher: Arc<Mutex<DisconnectWatcher>>,
+    ) -> Result<GattService, Box<dyn Error>> {
+        let (uuid, handle, primary, attributes) = data;
+        let uuid =
+            Uuid128::from_dbus(uuid, conn.clone(), remote.clone(), disconnect_watcher.clone())?;
+        let attributes = attributes
+            .into_iter()
+            .map(|(attribute_type, uuid, handle, properties, permissions)| {
+                Ok(GattAttribute {
+                    attribute_type,
+                    uuid: Uuid128::from_dbus(
+                        uuid,
+                        conn.clone(),
+                        remote.clone(),
+                        disconnect_watcher.clone(),
+                    )?,
+                    handle,
+                    properties,
+                    permissions,
+                })
+            })
+            .collect::<Result<Vec<GattAttribute>, Box<dyn Error>>>()?;
+
+        Ok(GattService { uuid, handle, primary, attributes })
+    }
+
+    fn to_dbus(
+        data: GattService,
+    ) -> Result<(String, i32, bool, Vec<(i32, String, i32, i32, i32)>), Box<dyn Error>> {
+        let attributes = data
+            .attributes
+            .into_iter()
+            .map(|attribute| {
+                (
+                    attribute.attribute_type,
+                    attribute.uuid.to_string(),
+                    attribute.handle,
+                    attribute.properties,
+                    attribute.permissions,
+                )
+            })
+            .collect();
+
+        Ok((data.uuid.to_string(), data.handle, data.primary, attributes))
+    }
+}
+
 #[allow(dead_code)]
 struct IBluetoothGattDBus {}
 
-#[generate_dbus_exporter(export_bluetooth_gatt_dbus_obj, "org.chromium.bluetooth.BluetoothGatt")]
+#[generate_dbus_exporter(
+    export_bluetooth_gatt_dbus_obj,
+    "org.chromium.bluetooth.BluetoothGatt",
+    trace,
+    version = 4
+)]
 impl IBluetoothGatt for IBluetoothGattDBus {
     #[dbus_method("RegisterScanner")]
-    fn register_scanner(&self, callback: Box<dyn IScannerCallback + Send>) {}
+    fn register_scanner(&mut self, callback: Box<dyn IScannerCallback + Send>) {}
 
     #[dbus_method("UnregisterScanner")]
-    fn unregister_scanner(&self, scanner_id: i32) {}
+    fn unregister_scanner(&mut self, scanner_id: i32) {}
 
     #[dbus_method("StartScan")]
-    fn start_scan(&self, scanner_id: i32, settings: ScanSettings, filters: Vec<ScanFilter>) {}
+    fn start_scan(
+        &mut self,
+        scanner_id: i32,
+        settings: ScanSettings,
+        filters: Vec<ScanFilter>,
+    ) -> bool {
+        false
+    }
 
     #[dbus_method("StopScan")]
-    fn stop_scan(&self, scanner_id: i32) {}
+    fn stop_scan(&mut self, scanner_id: i32) -> bool {
+        false
+    }
+
+    #[dbus_method("RegisterClient")]
+    fn register_client(
+        &mut self,
+        app_uuid: Uuid128,
+        callback: Box<dyn IBluetoothGattCallback + Send>,
+        eatt_support: bool,
+    ) {
+    }
+
+    #[dbus_method("UnregisterClient")]
+    fn unregister_client(&mut self, client_id: i32) {}
+
+    #[dbus_method("ClientConnect")]
+    fn client_connect(&mut self, client_id: i32, addr: String, is_direct: bool) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("ClientDisconnect")]
+    fn client_disconnect(&mut self, client_id: i32, addr: String) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("ReadRemoteRssi")]
+    fn read_remote_rssi(&mut self, client_id: i32, addr: String) {}
+
+    #[dbus_method("ReadPhy")]
+    fn read_phy(&mut self, client_id: i32, addr: String) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("WriteCharacteristic")]
+    fn write_characteristic(
+        &mut self,
+        client_id: i32,
+        conn_id: i32,
+        handle: i32,
+        write_type: i32,
+        auth_req: i32,
+        value: Vec<u8>,
+    ) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("DiscoverServices")]
+    fn discover_services(&mut self, client_id: i32, conn_id: i32) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("ReadUsingCharacteristicUuid")]
+    fn read_using_characteristic_uuid(
+        &mut self,
+        client_id: i32,
+        conn_id: i32,
+        uuid: Uuid128,
+        auth_req: i32,
+    ) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("WriteCharacteristicByUuid")]
+    fn write_characteristic_by_uuid(
+        &mut self,
+        client_id: i32,
+        conn_id: i32,
+        uuid: Uuid128,
+        write_type: i32,
+        auth_req: i32,
+        value: Vec<u8>,
+    ) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("BeginReliableWrite")]
+    fn begin_reliable_write(&mut self, client_id: i32, conn_id: i32) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("ExecuteWrite")]
+    fn execute_write(&mut self, client_id: i32, conn_id: i32) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("AbortReliableWrite")]
+    fn abort_reliable_write(&mut self, client_id: i32, conn_id: i32) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("ConfigureMtu")]
+    fn configure_mtu(&mut self, client_id: i32, conn_id: i32, mtu: i32) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("GetMtu")]
+    fn get_mtu(&self, conn_id: i32) -> i32 {
+        0
+    }
+
+    #[dbus_method("RequestConnectionPriority")]
+    fn request_connection_priority(
+        &mut self,
+        client_id: i32,
+        addr: String,
+        priority: ConnectionPriority,
+    ) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("GetDatabaseHash")]
+    fn get_database_hash(&self, conn_id: i32) -> Vec<u8> {
+        vec![]
+    }
+
+    #[dbus_method("RegisterForNotification")]
+    fn register_for_notification(
+        &mut self,
+        client_id: i32,
+        conn_id: i32,
+        handle: i32,
+        enable: bool,
+    ) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("RegisterServer")]
+    fn register_server(
+        &mut self,
+        app_uuid: Uuid128,
+        callback: Box<dyn IBluetoothGattServerCallback + Send>,
+        eatt_support: bool,
+    ) {
+    }
+
+    #[dbus_method("UnregisterServer")]
+    fn unregister_server(&mut self, server_id: i32) {}
+
+    #[dbus_method("SendNotification")]
+    fn send_notification(
+        &mut self,
+        server_id: i32,
+        device: String,
+        handle: i32,
+        value: Vec<u8>,
+        confirm: bool,
+    ) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("SendResponse")]
+    fn send_response(
+        &mut self,
+        server_id: i32,
+        device: String,
+        trans_id: i32,
+        status: i32,
+        handle: i32,
+        offset: i32,
+        value: Vec<u8>,
+    ) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("ServerReadPhy")]
+    fn server_read_phy(&mut self, server_id: i32, addr: String) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("AddService")]
+    fn add_service(&mut self, server_id: i32, service: GattService) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("RemoveService")]
+    fn remove_service(&mut self, server_id: i32, handle: i32) -> GattStatus {
+        GattStatus::Success
+    }
+
+    #[dbus_method("StartAdvertisingSet")]
+    fn start_advertising_set(
+        &mut self,
+        parameters: AdvertisingSetParameters,
+        advertise_data: Vec<u8>,
+        scan_response: Vec<u8>,
+        duration: i32,
+        max_ext_adv_events: i32,
+        callback: Box<dyn IAdvertisingSetCallback + Send>,
+    ) -> bool {
+        false
+    }
+
+    #[dbus_method("StopAdvertisingSet")]
+    fn stop_advertising_set(&mut self, advertiser_id: i32) -> bool {
+        false
+    }
+
+    #[dbus_method("EnableAdvertisingSet")]
+    fn enable_advertising_set(
+        &mut self,
+        advertiser_id: i32,
+        enable: bool,
+        duration: i32,
+        max_ext_adv_events: i32,
+    ) -> bool {
+        false
+    }
+
+    #[dbus_method("SetAdvertisingData")]
+    fn set_advertising_data(&mut self, advertiser_id: i32, data: Vec<u8>) -> bool {
+        false
+    }
+
+    #[dbus_method("SetScanResponseData")]
+    fn set_scan_response_data(&mut self, advertiser_id: i32, data: Vec<u8>) -> bool {
+        false
+    }
+
+    #[dbus_method("SetAdvertisingParameters")]
+    fn set_advertising_parameters(
+        &mut self,
+        advertiser_id: i32,
+        parameters: AdvertisingSetParameters,
+    ) -> bool {
+        false
+    }
+
+    #[dbus_method("GetOwnAddress")]
+    fn get_own_address(&mut self, advertiser_id: i32) -> bool {
+        false
+    }
+
+    #[dbus_method("RegisterAdvertisementMonitor")]
+    fn register_advertisement_monitor(
+        &mut self,
+        monitor: AdvertisementMonitor,
+        callback: Box<dyn IAdvertisementMonitorCallback + Send>,
+    ) -> i32 {
+        0
+    }
+
+    #[dbus_method("UnregisterAdvertisementMonitor")]
+    fn unregister_advertisement_monitor(&mut self, monitor_id: i32) -> bool {
+        false
+    }
 }
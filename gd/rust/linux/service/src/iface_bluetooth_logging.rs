@@ -0,0 +1,34 @@
+use btstack::bluetooth_logging::IBluetoothLogging;
+
+use dbus::nonblock::SyncConnection;
+
+use dbus_macros::{dbus_method, generate_dbus_exporter};
+
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::dbus_arg::DBusArg;
+
+#[allow(dead_code)]
+struct IBluetoothLoggingDBus {}
+
+#[generate_dbus_exporter(
+    export_bluetooth_logging_dbus_obj,
+    "org.chromium.bluetooth.BluetoothLogging",
+    trace,
+    version = 1
+)]
+impl IBluetoothLogging for IBluetoothLoggingDBus {
+    #[dbus_method("StartSnoopLog")]
+    fn start_snoop_log(&mut self, file_name: String, max_packets: u32, filtered: bool) -> bool {
+        false
+    }
+
+    #[dbus_method("StopSnoopLog")]
+    fn stop_snoop_log(&mut self) {}
+
+    #[dbus_method("IsSnoopLogEnabled")]
+    fn is_snoop_log_enabled(&self) -> bool {
+        false
+    }
+}
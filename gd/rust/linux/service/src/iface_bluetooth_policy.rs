@@ -0,0 +1,31 @@
+use btstack::policy::IBluetoothPolicy;
+
+use dbus::nonblock::SyncConnection;
+
+use dbus_macros::{dbus_method, generate_dbus_exporter};
+
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::dbus_arg::DBusArg;
+
+#[allow(dead_code)]
+struct IBluetoothPolicyDBus {}
+
+#[generate_dbus_exporter(
+    export_bluetooth_policy_dbus_obj,
+    "org.chromium.bluetooth.BluetoothPolicy",
+    trace,
+    version = 1
+)]
+impl IBluetoothPolicy for IBluetoothPolicyDBus {
+    #[dbus_method("SetAutoConnect")]
+    fn set_auto_connect(&mut self, device: String, enable: bool) -> bool {
+        false
+    }
+
+    #[dbus_method("GetAutoConnect")]
+    fn get_auto_connect(&self, device: String) -> bool {
+        false
+    }
+}
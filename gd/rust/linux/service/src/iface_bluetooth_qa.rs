@@ -0,0 +1,79 @@
+use btstack::bluetooth_qa::{IBluetoothQA, IBluetoothQACallback};
+use btstack::RPCProxy;
+
+use dbus::nonblock::SyncConnection;
+use dbus::strings::{BusName, Path};
+
+use dbus_macros::{dbus_method, dbus_proxy_obj, generate_dbus_exporter};
+
+use dbus_projection::DisconnectWatcher;
+
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::dbus_arg::{DBusArg, DBusArgError};
+
+#[allow(dead_code)]
+struct BluetoothQACallbackDBus {}
+
+#[dbus_proxy_obj(BluetoothQACallback, "org.chromium.bluetooth.BluetoothQACallback")]
+impl IBluetoothQACallback for BluetoothQACallbackDBus {
+    #[dbus_method("OnDutCommandComplete")]
+    fn on_dut_command_complete(&self, opcode: u32, params: Vec<u8>) {}
+
+    #[dbus_method("OnLeTestDone")]
+    fn on_le_test_done(&self, status: i32, num_packets: u32) {}
+
+    #[dbus_method("OnMaxTxPowerRead")]
+    fn on_max_tx_power_read(&self, addr: String, status: i32, tx_power: i32) {}
+}
+
+#[allow(dead_code)]
+struct IBluetoothQADBus {}
+
+#[generate_dbus_exporter(
+    export_bluetooth_qa_dbus_obj,
+    "org.chromium.bluetooth.BluetoothQA",
+    trace,
+    version = 2
+)]
+impl IBluetoothQA for IBluetoothQADBus {
+    #[dbus_method("RegisterCallback")]
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothQACallback + Send>) {}
+
+    #[dbus_method("SetDutMode")]
+    fn set_dut_mode(&mut self, enable: bool) -> bool {
+        true
+    }
+
+    #[dbus_method("GetDutMode")]
+    fn get_dut_mode(&self) -> bool {
+        true
+    }
+
+    #[dbus_method("SendDutCommand")]
+    fn send_dut_command(&mut self, opcode: u32, params: Vec<u8>) -> bool {
+        true
+    }
+
+    #[dbus_method("LeTransmitterTest")]
+    fn le_transmitter_test(&mut self, channel: u32, data_length: u32, payload: u32) -> bool {
+        true
+    }
+
+    #[dbus_method("LeReceiverTest")]
+    fn le_receiver_test(&mut self, channel: u32) -> bool {
+        true
+    }
+
+    #[dbus_method("LeTestEnd")]
+    fn le_test_end(&mut self) -> bool {
+        true
+    }
+
+    #[dbus_method("ReadMaxTxPower")]
+    fn read_max_tx_power(&mut self, device: String, transport: i32) -> bool {
+        true
+    }
+}
@@ -1,4 +1,4 @@
-use bt_topshim::btif::BluetoothInterface;
+use bt_topshim::btif::{BluetoothCallbacks, BluetoothInterface};
 use bt_topshim::topstack;
 
 use dbus::channel::MatchingReceiver;
@@ -6,35 +6,118 @@ use dbus::message::MatchRule;
 
 use dbus_crossroads::Crossroads;
 
-use dbus_projection::DisconnectWatcher;
+use dbus_projection::{DisconnectWatcher, MethodTraceEvent};
 
 use dbus_tokio::connection;
 
-use futures::future;
+use tokio::signal::unix::{signal, SignalKind};
 
 use btstack::bluetooth::btif_bluetooth_callbacks;
 use btstack::bluetooth::Bluetooth;
+use btstack::bluetooth_debug::{btif_debug_callbacks, BluetoothDebug};
 use btstack::bluetooth_gatt::BluetoothGatt;
-use btstack::Stack;
+use btstack::bluetooth_logging::BluetoothLogging;
+#[cfg(feature = "le_audio")]
+use btstack::bluetooth_media::le_audio::{BluetoothLeAudio, BluetoothLeAudioExtension};
+use btstack::bluetooth_media::BluetoothMedia;
+#[cfg(feature = "qa")]
+use btstack::bluetooth_qa::{btif_qa_callbacks, BluetoothQA, BluetoothQAExtension};
+use btstack::bluez_import::{self, BLUEZ_STORAGE_DIR, NATIVE_CONFIG_PATH};
+use btstack::config::{Feature, FeatureFlags};
+use btstack::extension::StackExtensions;
+use btstack::message_trace::{MessageRecorder, MESSAGE_TRACE_PATH};
+use btstack::metrics::{self, LogExporter, Metrics};
+use btstack::policy::{BluetoothPolicy, PolicyConfig};
+use btstack::watchdog::Watchdog;
+use btstack::{Message, Stack};
 
 use std::error::Error;
 use std::sync::{Arc, Mutex};
 
 mod dbus_arg;
 mod iface_bluetooth;
+mod iface_bluetooth_debug;
 mod iface_bluetooth_gatt;
+#[cfg(feature = "le_audio")]
+mod iface_bluetooth_le_audio;
+mod iface_bluetooth_logging;
+mod iface_bluetooth_media;
+mod iface_bluetooth_policy;
+#[cfg(feature = "qa")]
+mod iface_bluetooth_qa;
 
 const DBUS_SERVICE_NAME: &str = "org.chromium.bluetooth";
+const OBJECT_MANAGER: &str = "/org/chromium/bluetooth";
 const OBJECT_BLUETOOTH: &str = "/org/chromium/bluetooth/adapter";
 const OBJECT_BLUETOOTH_GATT: &str = "/org/chromium/bluetooth/gatt";
+const OBJECT_BLUETOOTH_MEDIA: &str = "/org/chromium/bluetooth/media";
+#[cfg(feature = "le_audio")]
+const OBJECT_BLUETOOTH_LE_AUDIO: &str = "/org/chromium/bluetooth/le_audio";
+const OBJECT_BLUETOOTH_POLICY: &str = "/org/chromium/bluetooth/policy";
+const OBJECT_BLUETOOTH_DEBUG: &str = "/org/chromium/bluetooth/debug";
+const OBJECT_BLUETOOTH_LOGGING: &str = "/org/chromium/bluetooth/logging";
+#[cfg(feature = "qa")]
+const OBJECT_BLUETOOTH_QA: &str = "/org/chromium/bluetooth/qa";
+
+/// Feature flags read at startup, see `FeatureFlags::load`.
+const FEATURE_FLAGS_PATH: &str = "/etc/bluetooth/btserv_flags.toml";
+
+/// Devices blocked with `IBluetooth::block_device`, see `Bluetooth::load_blocked_devices`. It is
+/// state written at runtime, so it lives with the rest of the adapter storage rather than in the
+/// read-only configuration directory.
+const BLOCKED_DEVICES_PATH: &str = "/var/lib/bluetooth/blocked_devices.conf";
 
 /// Runs the Bluetooth daemon serving D-Bus IPC.
 fn main() -> Result<(), Box<dyn Error>> {
     let (tx, rx) = Stack::create_channel();
 
+    let feature_flags = Arc::new(Mutex::new(FeatureFlags::load(FEATURE_FLAGS_PATH)));
+    // Exporters for the platform telemetry can be registered here.
+    let metrics = Arc::new(Mutex::new(Metrics::new()));
+    metrics.lock().unwrap().register_exporter(Box::new(LogExporter));
     let intf = Arc::new(Mutex::new(BluetoothInterface::new()));
-    let bluetooth = Arc::new(Mutex::new(Bluetooth::new(tx.clone(), intf.clone())));
-    let bluetooth_gatt = Arc::new(Mutex::new(BluetoothGatt::new(intf.clone())));
+    let bluetooth = Arc::new(Mutex::new(Bluetooth::new(tx.clone(), intf.clone(), metrics.clone())));
+    bluetooth.lock().unwrap().load_blocked_devices(BLOCKED_DEVICES_PATH);
+    let bluetooth_gatt = Arc::new(Mutex::new(BluetoothGatt::new(
+        tx.clone(),
+        intf.clone(),
+        feature_flags.clone(),
+        bluetooth.clone(),
+        metrics.clone(),
+    )));
+    let bluetooth_media =
+        Arc::new(Mutex::new(BluetoothMedia::new(tx.clone(), intf.clone(), metrics.clone())));
+    let bluetooth_policy = Arc::new(Mutex::new(BluetoothPolicy::new(
+        PolicyConfig::default(),
+        bluetooth_media.clone(),
+        bluetooth_gatt.clone(),
+    )));
+    let bluetooth_debug = Arc::new(Mutex::new(BluetoothDebug::new(
+        tx.clone(),
+        bluetooth.clone(),
+        bluetooth_gatt.clone(),
+        feature_flags.clone(),
+        metrics.clone(),
+    )));
+    let bluetooth_logging = Arc::new(Mutex::new(BluetoothLogging::new()));
+    metrics::start_export(metrics.clone());
+    // Modules that are not part of btstack can be registered here.
+    let extensions = Arc::new(Mutex::new(StackExtensions::new(tx.clone())));
+    #[cfg(feature = "qa")]
+    let bluetooth_qa = Arc::new(Mutex::new(BluetoothQA::new(tx.clone(), intf.clone())));
+    #[cfg(feature = "qa")]
+    extensions.lock().unwrap().register(Box::new(BluetoothQAExtension::new(bluetooth_qa.clone())));
+    #[cfg(feature = "le_audio")]
+    let bluetooth_le_audio = if feature_flags.lock().unwrap().is_enabled(Feature::LeAudio) {
+        let le_audio = Arc::new(Mutex::new(BluetoothLeAudio::new(tx.clone(), intf.clone())));
+        extensions
+            .lock()
+            .unwrap()
+            .register(Box::new(BluetoothLeAudioExtension::new(le_audio.clone())));
+        Some(le_audio)
+    } else {
+        None
+    };
 
     topstack::get_runtime().block_on(async {
         // Connect to D-Bus system bus.
@@ -59,15 +142,103 @@ fn main() -> Result<(), Box<dyn Error>> {
             }),
         )));
 
-        intf.lock().unwrap().initialize(Arc::new(btif_bluetooth_callbacks(tx)), vec![]);
+        // Link quality reports are handled by the debug API rather than the adapter API.
+        let callbacks = BluetoothCallbacks {
+            link_quality_report: btif_debug_callbacks(tx.clone()).link_quality_report,
+            ..btif_bluetooth_callbacks(tx.clone())
+        };
+        // Test mode events are handled by the QA API.
+        #[cfg(feature = "qa")]
+        let callbacks = {
+            let qa_callbacks = btif_qa_callbacks(tx.clone());
+            BluetoothCallbacks {
+                dut_mode_recv: qa_callbacks.dut_mode_recv,
+                le_test_mode: qa_callbacks.le_test_mode,
+                ..callbacks
+            }
+        };
+        // Users migrating from BlueZ keep their bonds, which must be in the config of the native
+        // stack before it is initialized.
+        bluez_import::import_on_first_run(BLUEZ_STORAGE_DIR, NATIVE_CONFIG_PATH);
+
+        let init_flags = feature_flags.lock().unwrap().init_flags();
+        intf.lock().unwrap().initialize(Arc::new(callbacks), init_flags);
+
+        // Profiles can only be loaded once the Bluetooth interface is initialized.
+        bluetooth_gatt.lock().unwrap().init_profiles();
 
         // Run the stack main dispatch loop.
-        topstack::get_runtime().spawn(Stack::dispatch(rx, bluetooth.clone()));
+        let watchdog =
+            Watchdog::start(feature_flags.lock().unwrap().is_enabled(Feature::WatchdogAbort));
+        let recorder = if feature_flags.lock().unwrap().is_enabled(Feature::MessageTrace) {
+            MessageRecorder::create(MESSAGE_TRACE_PATH)
+                .map_err(|e| eprintln!("Failed to record the messages: {}", e))
+                .ok()
+        } else {
+            None
+        };
+        let dispatch = topstack::get_runtime().spawn(Stack::dispatch(
+            rx,
+            bluetooth.clone(),
+            bluetooth_gatt.clone(),
+            bluetooth_media.clone(),
+            bluetooth_policy.clone(),
+            bluetooth_debug.clone(),
+            extensions.clone(),
+            watchdog,
+            recorder,
+        ));
+
+        // Shut the stack down gracefully when the daemon is stopped, e.g. by btmanagerd.
+        let mut sigterm = signal(SignalKind::terminate())?;
+        let shutdown_tx = tx.clone();
+        topstack::get_runtime().spawn(async move {
+            tokio::select! {
+                _ = sigterm.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+            let _result = shutdown_tx.send(Message::Shutdown).await;
+        });
+
+        // Log the method calls of the interfaces exported with tracing enabled, and record how
+        // long they take and whether they fail in the metrics.
+        if feature_flags.lock().unwrap().is_enabled(Feature::MethodTrace) {
+            let metrics = metrics.clone();
+            dbus_projection::set_method_tracer(Box::new(move |event| match event {
+                MethodTraceEvent::Enter { iface, method, sender } => {
+                    println!("D-Bus call {}.{} from {}", iface, method, sender);
+                }
+                MethodTraceEvent::Exit { iface, method, sender, duration, error } => {
+                    match error {
+                        None => println!(
+                            "D-Bus call {}.{} from {} done in {:?}",
+                            iface, method, sender, duration
+                        ),
+                        Some(e) => println!(
+                            "D-Bus call {}.{} from {} failed in {:?}: {}",
+                            iface, method, sender, duration, e
+                        ),
+                    }
+                    metrics.lock().unwrap().record_method_call(
+                        iface,
+                        method,
+                        *duration,
+                        error.is_some(),
+                    );
+                }
+                MethodTraceEvent::Arguments { iface, method, sender, args } => {
+                    println!("D-Bus call {}.{} from {} with ({})", iface, method, sender, args);
+                }
+            }));
+        }
 
         // Set up the disconnect watcher to monitor client disconnects.
         let disconnect_watcher = Arc::new(Mutex::new(DisconnectWatcher::new()));
         disconnect_watcher.lock().unwrap().setup_watch(conn.clone()).await;
 
+        // Let clients discover the objects below instead of hardcoding their paths.
+        dbus_projection::export_object_manager(OBJECT_MANAGER, conn.clone(), &mut cr);
+
         // Register D-Bus method handlers of IBluetooth.
         iface_bluetooth::export_bluetooth_dbus_obj(
             OBJECT_BLUETOOTH,
@@ -84,6 +255,58 @@ fn main() -> Result<(), Box<dyn Error>> {
             bluetooth_gatt,
             disconnect_watcher.clone(),
         );
+        // Register D-Bus method handlers of IBluetoothMedia.
+        iface_bluetooth_media::export_bluetooth_media_dbus_obj(
+            OBJECT_BLUETOOTH_MEDIA,
+            conn.clone(),
+            &mut cr,
+            bluetooth_media,
+            disconnect_watcher.clone(),
+        );
+        // Register D-Bus method handlers of IBluetoothLeAudio.
+        #[cfg(feature = "le_audio")]
+        if let Some(bluetooth_le_audio) = bluetooth_le_audio {
+            iface_bluetooth_le_audio::export_bluetooth_le_audio_dbus_obj(
+                OBJECT_BLUETOOTH_LE_AUDIO,
+                conn.clone(),
+                &mut cr,
+                bluetooth_le_audio,
+                disconnect_watcher.clone(),
+            );
+        }
+        // Register D-Bus method handlers of IBluetoothPolicy.
+        iface_bluetooth_policy::export_bluetooth_policy_dbus_obj(
+            OBJECT_BLUETOOTH_POLICY,
+            conn.clone(),
+            &mut cr,
+            bluetooth_policy,
+            disconnect_watcher.clone(),
+        );
+        // Register D-Bus method handlers of IBluetoothDebug.
+        iface_bluetooth_debug::export_bluetooth_debug_dbus_obj(
+            OBJECT_BLUETOOTH_DEBUG,
+            conn.clone(),
+            &mut cr,
+            bluetooth_debug,
+            disconnect_watcher.clone(),
+        );
+        // Register D-Bus method handlers of IBluetoothLogging.
+        iface_bluetooth_logging::export_bluetooth_logging_dbus_obj(
+            OBJECT_BLUETOOTH_LOGGING,
+            conn.clone(),
+            &mut cr,
+            bluetooth_logging,
+            disconnect_watcher.clone(),
+        );
+        // Register D-Bus method handlers of IBluetoothQA.
+        #[cfg(feature = "qa")]
+        iface_bluetooth_qa::export_bluetooth_qa_dbus_obj(
+            OBJECT_BLUETOOTH_QA,
+            conn.clone(),
+            &mut cr,
+            bluetooth_qa,
+            disconnect_watcher.clone(),
+        );
 
         conn.start_receive(
             MatchRule::new_method_call(),
@@ -93,8 +316,8 @@ fn main() -> Result<(), Box<dyn Error>> {
             }),
         );
 
-        // Serve clients forever.
-        future::pending::<()>().await;
-        unreachable!()
+        // Serve clients until the stack is shut down.
+        dispatch.await?;
+        Ok(())
     })
 }
@@ -32,7 +32,9 @@ pub fn stack_message(_attr: TokenStream, item: TokenStream) -> TokenStream {
 
 /// Generates a topshim callback object that contains closures.
 ///
-/// The closures are generated to be calls to the corresponding `Stack::Message`.
+/// The closures are generated to be calls to the corresponding `Stack::Message`. The shims run
+/// them through `topstack::catch_callback_panic`, since unwinding back into the C++ caller would
+/// abort.
 #[proc_macro_attribute]
 pub fn btif_callbacks_generator(attr: TokenStream, item: TokenStream) -> TokenStream {
     let args = Punctuated::<Expr, Comma>::parse_separated_nonempty.parse(attr.clone()).unwrap();
@@ -113,18 +115,12 @@ pub fn btif_callbacks_generator(attr: TokenStream, item: TokenStream) -> TokenSt
         #ori_item
 
         /// Returns a callback object to be passed to topshim.
-        pub fn #fn_ident(tx: tokio::sync::mpsc::Sender<Message>) -> #callbacks_struct_ident {
+        pub fn #fn_ident(tx: MessageSender) -> #callbacks_struct_ident {
             #closure_defs
             #callbacks_struct_ident {
                 #fn_names
-                // TODO: Handle these in main loop.
-                acl_state_changed: Box::new(|_, _, _, _| {}),
-                bond_state_changed: Box::new(|_, _, _| {}),
-                device_found: Box::new(|_, _| {}),
-                discovery_state_changed: Box::new(|_| {}),
-                pin_request: Box::new(|_, _, _, _| {}),
-                remote_device_properties_changed: Box::new(|_, _, _, _| {}),
-                ssp_request: Box::new(|_, _, _, _, _| {}),
+                // Callbacks without a `stack_message` are not handled in the main loop yet.
+                ..Default::default()
             }
         }
     };
@@ -1,7 +1,13 @@
 //! Anything related to the adapter API (IBluetooth).
 
+use bt_topshim::acl::ffi::LeLinkSecurity;
+use bt_topshim::acl::{Acl, AclCallbacks};
 use bt_topshim::btif::ffi;
-use bt_topshim::btif::{BluetoothCallbacks, BluetoothInterface, BtState};
+use bt_topshim::btif::{
+    BluetoothCallbacks, BluetoothInterface, BtAclState, BtBondState, BtDiscoveryState, BtScanMode,
+    BtSspVariant, BtState, BtStatus, SupportedProfiles,
+};
+use bt_topshim::controller::Controller;
 use bt_topshim::topstack;
 
 use btif_macros::btif_callbacks_generator;
@@ -10,183 +16,3396 @@ use btif_macros::stack_message;
 use num_traits::cast::ToPrimitive;
 use num_traits::FromPrimitive;
 
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
 use std::fmt::Debug;
+use std::fs;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::task::JoinHandle;
+
+use crate::bluetooth_gatt::RSSISettings;
+use crate::completion::{Completers, Completion};
+use crate::device_class::DeviceCategory;
+use crate::metrics::{self, Metrics};
+use crate::uuid::Uuid128;
+use crate::{BDAddr, Callbacks, Message, MessageSender, RPCProxy};
+
+/// How long to wait for the name of a newly bonded device before reporting the bond anyway.
+const BONDED_NAME_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a bond may go without progress, e.g. waiting for the controller or the remote
+/// device, before it is cancelled.
+const BOND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many times a bond that failed because the remote device could not be reached is retried.
+const BOND_RETRY_MAX: u32 = 3;
+
+/// Delay before the first retry of a bond, doubled at each of the next ones.
+const BOND_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How long to wait for the local out of band data requested with
+/// `IBluetooth::generate_local_oob_data`.
+const LOCAL_OOB_DATA_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the RSSI of a device read with `Bluetooth::read_remote_rssi`.
+///
+/// Longer than the timeout of the read in the native stack, which reports it as failed.
+const RSSI_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Shortest period accepted by `IBluetooth::start_rssi_monitoring`, in milliseconds.
+const RSSI_MONITORING_MIN_PERIOD_MS: u32 = 100;
+
+/// Number of connection events kept for each device, see `ConnectionEvent`.
+const CONNECTION_HISTORY_LENGTH: usize = 32;
+
+/// Number of resolvable private addresses remembered not to belong to any bonded device, beyond
+/// which they are all forgotten.
+const UNRESOLVED_ADDRESSES_MAX_LEN: usize = 256;
+
+/// PHY options of the HCI LE Set PHY command, 0 for no preferred coding on the LE Coded PHY.
+const LE_PHY_OPTIONS: u16 = 0;
+
+/// Range of the timeout of the resolvable private addresses generated by the controller, in
+/// seconds.
+const RPA_TIMEOUT_MIN: u32 = 1;
+const RPA_TIMEOUT_MAX: u32 = 3600;
+
+/// Largest passkey of a Secure Simple Pairing, which has 6 digits.
+const SSP_PASSKEY_MAX: u32 = 999999;
+
+/// Length of the PIN of a legacy pairing, in bytes.
+const PIN_LEN_MIN: usize = 1;
+const PIN_LEN_MAX: usize = 16;
 
-use tokio::sync::mpsc::Sender;
+/// Types of remote devices, see `bt_device_type_t`.
+const BT_DEVICE_TYPE_BREDR: u32 = 1;
+const BT_DEVICE_TYPE_BLE: u32 = 2;
+const BT_DEVICE_TYPE_DUMO: u32 = 3;
 
-use crate::{BDAddr, Message, RPCProxy};
+/// Transports of the links to a device, see `tBT_TRANSPORT`.
+const BT_TRANSPORT_AUTO: i32 = 0;
+const BT_TRANSPORT_BR_EDR: i32 = 1;
+const BT_TRANSPORT_LE: i32 = 2;
+
+/// Statuses of the BTM requests, see `tBTM_STATUS`.
+const BTM_SUCCESS: i32 = 0;
+const BTM_CMD_STARTED: i32 = 1;
+const BTM_BUSY: i32 = 2;
+const BTM_MODE_UNSUPPORTED: i32 = 4;
+const BTM_UNKNOWN_ADDR: i32 = 7;
+
+/// How often the role of a BR/EDR connection is read while it is switched, as the native stack
+/// doesn't report the completion of the switch.
+const ROLE_SWITCH_CHECK_PERIOD: Duration = Duration::from_millis(500);
+
+/// How many times the role is read before a role switch is deemed failed.
+const ROLE_SWITCH_CHECKS: u32 = 10;
+
+/// Reported by `IBluetooth::get_stack_version`.
+const STACK_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// Defines the adapter API.
+///
+/// Requests that return a `BtStatus` return `BtStatus::Success` if they are accepted,
+/// `BtStatus::InvalidParam` for malformed arguments such as addresses, `BtStatus::NotReady` if
+/// the adapter is not on, or else the status the native stack rejected them with.
 pub trait IBluetooth {
-    /// Adds a callback from a client who wishes to observe adapter events.
-    fn register_callback(&mut self, callback: Box<dyn IBluetoothCallback + Send>);
+    /// Adds a callback from a client who wishes to observe adapter events. Returns the id of the
+    /// callback, which identifies the client in `start_discovery`.
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothCallback + Send>) -> u32;
 
     /// Enables the adapter.
     ///
-    /// Returns true if the request is accepted.
-    fn enable(&mut self) -> bool;
+    /// Returns `BtStatus::Success` if the request is accepted, or if the adapter is already on or
+    /// turning on, and `BtStatus::Busy` while the adapter is turning off.
+    fn enable(&mut self) -> BtStatus;
 
     /// Disables the adapter.
     ///
-    /// Returns true if the request is accepted.
-    fn disable(&mut self) -> bool;
+    /// Returns `BtStatus::Success` if the request is accepted, or if the adapter is already off,
+    /// and `BtStatus::Busy` while the adapter is turning on or off. The adapter stays off after a
+    /// `factory_reset` in progress.
+    fn disable(&mut self) -> BtStatus;
+
+    /// Resets the adapter to its factory state, e.g. for a "reset network settings" flow.
+    ///
+    /// Every bond is removed, along with what is stored of the remote devices and the adapter
+    /// configuration such as its name. The adapter is turned off for the reset and then on again,
+    /// and the removed bonds are reported through `IBluetoothCallback::on_bond_state_changed`.
+    /// Returns `BtStatus::NotReady` if the adapter is not on.
+    fn factory_reset(&mut self) -> BtStatus;
+
+    /// Returns the `AdapterState` of the adapter.
+    fn get_state(&self) -> u32;
+
+    /// Returns the version of the stack, e.g. "0.1.0".
+    ///
+    /// The version of each D-Bus interface is also exported as its `Version` property.
+    fn get_stack_version(&self) -> String;
 
-    /// Returns the Bluetooth address of the local adapter.
+    /// Returns the public Bluetooth address of the local adapter.
+    ///
+    /// The adapter may use another address over LE, see `get_le_own_address`.
     fn get_address(&self) -> String;
-}
 
-/// The interface for adapter callbacks registered through `IBluetooth::register_callback`.
-pub trait IBluetoothCallback: RPCProxy {
-    /// When any of the adapter states is changed.
-    fn on_bluetooth_state_changed(&self, prev_state: u32, new_state: u32);
+    /// Returns the ACL connection state of a remote device.
+    ///
+    /// Returns 0 if not connected, 1 if connected and greater than 1 if the connection is
+    /// encrypted (bit 1 for BR/EDR and bit 2 for LE encryption).
+    fn get_connection_state(&self, device: String) -> u32;
 
-    /// When any of the adapter local address is changed.
-    fn on_bluetooth_address_changed(&self, addr: String);
+    /// Returns the friendly name of a remote device, or an empty string if it is not known.
+    fn get_remote_name(&self, device: String) -> String;
+
+    /// Returns the UUIDs of the services the local adapter supports.
+    ///
+    /// The list is empty until the adapter is enabled.
+    fn get_uuids(&self) -> Vec<Uuid128>;
+
+    /// Returns the UUIDs of the services of a remote device, as last discovered.
+    ///
+    /// The UUIDs of bonded devices are read from storage when the adapter is enabled. The list is
+    /// empty if no service discovery was done on the device yet.
+    fn get_remote_uuids(&self, device: String) -> Vec<Uuid128>;
+
+    /// Returns the category of a remote device, from its Class of Device or, for LE devices, the
+    /// Appearance it advertises. Returns `DeviceCategory::Unknown` until either is known.
+    fn get_remote_category(&self, device: String) -> DeviceCategory;
+
+    /// Returns the security of the current link to a remote device, over BR/EDR if it is
+    /// connected over both transports.
+    ///
+    /// The security is updated by the native stack as the link is encrypted, so that apps can
+    /// check it before sensitive operations. Everything is false if the device is not connected.
+    fn get_security_level(&self, device: String) -> SecurityLevel;
+
+    /// Returns the identity address of the bonded device a resolvable private address belongs to,
+    /// e.g. the address of a connected or discovered device, or an empty string if the address is
+    /// not resolvable or belongs to no bonded device.
+    fn get_identity_address(&mut self, device: String) -> String;
+
+    /// Runs a service discovery on a remote device to refresh its UUIDs.
+    ///
+    /// The UUIDs are reported through `IBluetoothCallback::on_remote_uuids_changed` if they differ
+    /// from the known ones. Returns `BtStatus::NotReady` if the adapter is not on.
+    fn fetch_remote_uuids(&mut self, device: String) -> BtStatus;
+
+    /// Reads the local out of band pairing data for the given transport, to be handed to a remote
+    /// device e.g. through a QR code or NFC.
+    ///
+    /// The data is returned through `IBluetoothCallback::on_local_oob_data_generated`, with
+    /// `is_valid` unset if it is not read in time. While the adapter is turning on, the read is
+    /// made once it is on, and its failure is reported the same way.
+    fn generate_local_oob_data(&mut self, transport: i32) -> BtStatus;
+
+    /// Bonds with a remote device over the given transport, or over both for 0.
+    ///
+    /// A bond that fails because the device could not be reached is retried up to
+    /// `BOND_RETRY_MAX` times, waiting longer before each retry, and only reported as failed once
+    /// the retries are used up. A bond that makes no progress for `BOND_TIMEOUT` is cancelled and
+    /// reported as failed with a `BondStatus::Timeout` status.
+    ///
+    /// While the adapter is turning on, the bond is started once it is on. If it can't be
+    /// started then, or the adapter turns off instead, this is reported through
+    /// `IBluetoothCallback::on_bond_state_changed` with the `BtBondState::NotBonded` state.
+    fn create_bond(&mut self, device: String, transport: i32) -> BtStatus;
+
+    /// Bonds with a remote device using out of band data received from it.
+    ///
+    /// Pass data with `is_valid` unset for whichever of the P-192 and P-256 values is not
+    /// available. Failures are retried and timed out as with `create_bond`.
+    fn create_bond_out_of_band(
+        &mut self,
+        device: String,
+        transport: i32,
+        p192_data: ffi::BtOutOfBandData,
+        p256_data: ffi::BtOutOfBandData,
+    ) -> BtStatus;
+
+    /// Cancels the bonding with a remote device, including the retries still to come.
+    ///
+    /// The bond is reported as failed with a `BondStatus::Cancelled` status right away. Returns
+    /// false if the device is not bonding.
+    fn cancel_bond_process(&mut self, device: String) -> bool;
+
+    /// Registers an agent that involves the user in the pairings, with the input and output it
+    /// has. Returns the id of the agent.
+    ///
+    /// Each pairing request goes to the most recently registered agent able to handle it, and the
+    /// adapter advertises the `IoCapability` that the agents have together, so that the remote
+    /// devices only ask for what the agents can do. Without an agent able to handle a request,
+    /// pairings that need no user input ("just works") are accepted and the others rejected.
+    /// Agents are unregistered when their client disconnects.
+    ///
+    /// The native stack reads the BR/EDR IO capability only when the adapter starts, and the
+    /// capability can only be stored while the adapter is on. A change of the agents is used by the
+    /// next LE pairing, but BR/EDR pairings only see it once the adapter is turned off and on
+    /// again.
+    fn register_pairing_agent(
+        &mut self,
+        agent: Box<dyn IBluetoothPairingAgent + Send>,
+        capability: IoCapability,
+    ) -> u32;
+
+    /// Unregisters a pairing agent, rejecting the requests it has not answered yet. Returns false
+    /// if there is no agent with this id.
+    fn unregister_pairing_agent(&mut self, id: u32) -> bool;
+
+    /// Answers a `BtSspVariant::PasskeyConfirmation` or `BtSspVariant::Consent` request of a
+    /// device. Returns `BtStatus::Fail` if the device has no such request waiting for an answer.
+    fn set_pairing_confirmation(&mut self, device: String, accept: bool) -> BtStatus;
+
+    /// Answers the `IBluetoothPairingAgent::on_repair_request` of a device, bonding with it again
+    /// if accepted. Returns `BtStatus::Fail` if the device has no such request waiting for an
+    /// answer.
+    fn set_repair_confirmation(&mut self, device: String, accept: bool) -> BtStatus;
+
+    /// Answers a `BtSspVariant::PasskeyEntry` request of a device with the passkey the user
+    /// entered. Returns `BtStatus::InvalidParam` if an accepted passkey has more than 6 digits,
+    /// and `BtStatus::Fail` if the device has no such request waiting for an answer.
+    fn set_passkey(&mut self, device: String, accept: bool, passkey: u32) -> BtStatus;
+
+    /// Answers the PIN request of a device doing legacy pairing. Returns `BtStatus::InvalidParam`
+    /// if an accepted PIN is not 1 to 16 bytes long, and `BtStatus::Fail` if the device has no PIN
+    /// request waiting for an answer.
+    fn set_pin(&mut self, device: String, accept: bool, pin: Vec<u8>) -> BtStatus;
+
+    /// Makes the adapter discoverable or not.
+    ///
+    /// If `duration` is not 0, the adapter reverts to not discoverable after `duration` seconds.
+    /// Changing the mode again before then cancels the timeout. Changes are reported through
+    /// `IBluetoothCallback::on_discoverable_changed`. While the adapter is turning on, the mode is
+    /// changed once it is on, and a failure reports the unchanged mode.
+    fn set_discoverable(&mut self, mode: bool, duration: u32) -> BtStatus;
+
+    /// Returns whether the adapter is discoverable.
+    fn get_discoverable(&self) -> bool;
+
+    /// Makes the adapter accept the pairings that remote devices start or not, whether it is
+    /// discoverable or not.
+    ///
+    /// While not pairable, incoming pairings are rejected without reaching the pairing agents,
+    /// and only the bonds made with `create_bond` go on. If `timeout` is not 0, the adapter
+    /// reverts to not pairable after `timeout` seconds. Changing the mode again before then
+    /// cancels the timeout. Changes are reported through `IBluetoothCallback::on_pairable_changed`.
+    /// The adapter is pairable until this is called.
+    fn set_pairable(&mut self, mode: bool, timeout: u32);
+
+    /// Returns whether the adapter accepts incoming pairings.
+    fn get_pairable(&self) -> bool;
+
+    /// Rejects the incoming pairings of the devices of some major device classes, even while the
+    /// adapter is pairable.
+    ///
+    /// `major_classes` is a bit mask with bit n set for major class n, as in
+    /// `DiscoveryFilter::major_classes`, or 0 to block no class. Devices whose Class of Device is
+    /// not known, e.g. LE only devices which are reported as unclassified, are never blocked.
+    fn set_pairing_blocked_classes(&mut self, major_classes: u32);
+
+    /// Blocks a remote device, e.g. a peripheral that keeps reconnecting, until it is unblocked.
+    ///
+    /// The connections to a blocked device are dropped as soon as they come up, whichever side
+    /// starts them, its incoming pairings are rejected and its profiles are no longer connected
+    /// automatically. The device is disconnected if it is connected. The blocked devices are
+    /// kept across restarts and cleared by `factory_reset`. Returns false if `device` is not an
+    /// address.
+    fn block_device(&mut self, device: String) -> bool;
+
+    /// Unblocks a device blocked with `block_device`. If it is bonded, its profiles are connected
+    /// automatically again as they are when the adapter is enabled. Returns false if it is not
+    /// blocked.
+    fn unblock_device(&mut self, device: String) -> bool;
+
+    /// Returns the addresses of the devices blocked with `block_device`.
+    fn get_blocked_devices(&self) -> Vec<String>;
+
+    /// Starts discovering the nearby devices, over both BR/EDR and LE, on behalf of the client of
+    /// the callback `callback_id` returned by `register_callback`.
+    ///
+    /// Only the devices that pass `filter` are reported to that callback through
+    /// `IBluetoothCallback::on_device_found`, with a default filter letting every device through.
+    /// Each client has its own filter, and the callbacks of the clients that did not start the
+    /// discovery are told about every device. Starting again while discovering only replaces the
+    /// filter of the client. Discovery stops by itself after a while, which is reported through
+    /// `IBluetoothCallback::on_discovering_changed`, and the filters are dropped then. Returns
+    /// `BtStatus::InvalidParam` if there is no such callback or the filter has an unknown
+    /// transport.
+    fn start_discovery(&mut self, callback_id: u32, filter: DiscoveryFilter) -> BtStatus;
+
+    /// Stops discovering the nearby devices, for every client.
+    fn cancel_discovery(&mut self) -> BtStatus;
+
+    /// Returns whether the adapter is discovering the nearby devices.
+    fn is_discovering(&self) -> bool;
+
+    /// Returns the version and LE feature support of the local controller.
+    ///
+    /// `is_ready` is unset, along with every other field, while the adapter is disabled.
+    fn get_controller_info(&self) -> ControllerInfo;
+
+    /// Starts reading the RSSI of a connected device every `period_ms` milliseconds.
+    ///
+    /// `IBluetoothCallback::on_rssi_changed` is called when the RSSI rises to
+    /// `settings.high_threshold` or above, or falls to `settings.low_threshold` or below, so that
+    /// fluctuations between the thresholds are not reported. Monitoring stops when the device
+    /// disconnects. Returns `BtStatus::RemoteDeviceDown` if the device is not connected, and
+    /// `BtStatus::InvalidParam` if `period_ms` is below 100 or `settings.low_threshold` is not
+    /// below `settings.high_threshold`.
+    fn start_rssi_monitoring(
+        &mut self,
+        device: String,
+        period_ms: u32,
+        settings: RSSISettings,
+    ) -> BtStatus;
+
+    /// Stops the monitoring started with `start_rssi_monitoring`.
+    ///
+    /// Returns false if the device is not monitored.
+    fn stop_rssi_monitoring(&mut self, device: String) -> bool;
+
+    /// Sets the PHYs preferred for LE connections, as bitmasks of `LePhy::mask` values.
+    ///
+    /// The preferences are requested on each LE connection as it is established, and are
+    /// negotiated with the remote device. Returns `BtStatus::Unsupported` if a bitmask is empty or
+    /// has a PHY that the controller doesn't support.
+    fn set_default_le_phy(&mut self, tx_phys: u32, rx_phys: u32) -> BtStatus;
+
+    /// Overrides the PHYs preferred for the LE connection to a device, until it disconnects.
+    ///
+    /// The negotiated PHYs are reported to the GATT clients of the connection through
+    /// `IBluetoothGattCallback::on_phy_update`. Returns `BtStatus::RemoteDeviceDown` if the device
+    /// is not connected, or fails as `set_default_le_phy` does.
+    fn set_le_phy(&mut self, device: String, tx_phys: u32, rx_phys: u32) -> BtStatus;
+
+    /// Applies the connection parameters and data length of a preset to the LE connection to a
+    /// device.
+    ///
+    /// The parameters the remote device agrees to are reported to the GATT clients of the
+    /// connection through `IBluetoothGattCallback::on_connection_updated`. Returns
+    /// `BtStatus::RemoteDeviceDown` if the device is not connected.
+    fn set_le_connection_preset(&mut self, device: String, preset: LeConnectionPreset) -> BtStatus;
+
+    /// Returns the role of the adapter on the connection to a device over a transport, 1 for
+    /// BR/EDR or 2 for LE, `LinkRole::Unknown` if there is no such connection.
+    fn get_link_role(&self, device: String, transport: i32) -> LinkRole;
+
+    /// Requests the role of the adapter on the BR/EDR connection to a device to be switched, e.g.
+    /// to be the central of every connection of a headset connected to several hosts.
+    ///
+    /// The outcome is reported through `IBluetoothCallback::on_role_switch_completed`. Only the
+    /// switch to `LinkRole::Central` is supported, the other one returns `BtStatus::Unsupported`.
+    /// The role of an LE connection can't be switched, it is set by which side initiated it.
+    /// Returns `BtStatus::RemoteDeviceDown` if the device has no BR/EDR connection, or
+    /// `BtStatus::Busy` while a switch requested on the connection is ongoing.
+    fn request_role_switch(&mut self, device: String, role: LinkRole) -> BtStatus;
+
+    /// Enables or disables LE privacy, i.e. whether the adapter uses resolvable private addresses
+    /// rather than its public address over LE. Privacy is enabled by default.
+    ///
+    /// The setting is kept across restarts of the adapter until the daemon stops. Advertising sets
+    /// keep the address type they started with, see `AdvertisingSetParameters::own_address_type`.
+    /// Returns `BtStatus::Unsupported` if the controller doesn't support LE.
+    fn set_le_privacy(&mut self, enabled: bool) -> BtStatus;
+
+    /// Returns whether LE privacy is enabled, or will be once the adapter is enabled.
+    fn get_le_privacy(&self) -> bool;
+
+    /// Sets how often, in seconds, the controller rotates the resolvable private addresses it
+    /// generates while LE privacy is enabled, from 1 to 3600.
+    ///
+    /// The setting is kept across restarts of the adapter until the daemon stops. Addresses that
+    /// the stack generates itself, e.g. for the advertising sets, keep rotating every 7 to 15
+    /// minutes. Returns `BtStatus::InvalidParam` if `timeout_s` is out of range.
+    fn set_rpa_timeout(&mut self, timeout_s: u32) -> BtStatus;
+
+    /// Makes the adapter use a static random address over LE, e.g. to not expose its public
+    /// address while remaining reachable at a fixed address. An empty `address` removes the static
+    /// address, and the adapter goes back to the address that LE privacy gives.
+    ///
+    /// The static address overrides LE privacy, which `get_le_privacy` then reports as disabled.
+    /// It is kept across restarts of the adapter until the daemon stops. Returns
+    /// `BtStatus::InvalidParam` if `address` is not a static random address, i.e. its two most
+    /// significant bits are not set or its other bits are all 0 or all 1, and
+    /// `BtStatus::Unsupported` if the controller doesn't support LE or the GD stack is in use.
+    /// The setting is left unchanged when the request fails.
+    fn set_le_static_address(&mut self, address: String) -> BtStatus;
+
+    /// Returns the address the adapter uses over LE, or will use once the adapter is enabled.
+    ///
+    /// The address of a `LeOwnAddressType::ResolvablePrivate` address is empty, as the controller
+    /// rotates it.
+    fn get_le_own_address(&self) -> LeOwnAddress;
+
+    /// Returns the profiles that the native stack provides.
+    ///
+    /// APIs of unsupported profiles fail, e.g. `IBluetoothMedia::initialize` without
+    /// `SupportedProfiles::A2dp`.
+    fn get_supported_profiles(&self) -> Vec<SupportedProfiles>;
 }
 
-/// Implementation of the adapter API.
-pub struct Bluetooth {
-    intf: Arc<Mutex<BluetoothInterface>>,
-    state: BtState,
-    callbacks: Vec<(u32, Box<dyn IBluetoothCallback + Send>)>,
-    callbacks_last_id: u32,
-    tx: Sender<Message>,
-    local_address: Option<BDAddr>,
+/// State of the adapter, as reported by `IBluetoothCallback::on_bluetooth_state_changed`.
+///
+/// The native stack only reports the adapter as on or off, the transitions in between are tracked
+/// by the API. Off and On keep the values of `BtState` that clients received before the
+/// transitions were reported.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(u32)]
+pub enum AdapterState {
+    Off = 0,
+    On = 1,
+    TurningOn = 2,
+    TurningOff = 3,
 }
 
-impl Bluetooth {
-    /// Constructs the IBluetooth implementation.
-    pub fn new(tx: Sender<Message>, intf: Arc<Mutex<BluetoothInterface>>) -> Bluetooth {
-        Bluetooth {
-            tx,
-            intf,
-            state: BtState::Off,
-            callbacks: vec![],
-            callbacks_last_id: 0,
-            local_address: None,
+impl AdapterState {
+    /// Returns whether the adapter is expected to go from this state to `next`.
+    fn can_transition_to(&self, next: AdapterState) -> bool {
+        match (self, next) {
+            (AdapterState::Off, AdapterState::TurningOn)
+            | (AdapterState::TurningOn, AdapterState::On)
+            | (AdapterState::On, AdapterState::TurningOff)
+            | (AdapterState::TurningOff, AdapterState::Off) => true,
+            // Enabling failed, or the stack went down on its own.
+            (AdapterState::TurningOn, AdapterState::Off)
+            | (AdapterState::On, AdapterState::Off) => true,
+            _ => false,
         }
     }
+}
 
-    fn update_local_address(&mut self, raw: &Vec<u8>) {
-        self.local_address = Some(BDAddr::from_byte_vec(raw));
+/// Outcome of a bonding attempt, as reported by `IBluetoothCallback::on_bond_state_changed`.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(i32)]
+pub enum BondStatus {
+    Success = 0,
+    /// Any failure without a more specific status.
+    Failed,
+    AuthFailure,
+    AuthRejected,
+    RemoteDeviceDown,
+    /// The bond made no progress in time and was cancelled.
+    Timeout,
+    /// The bond was cancelled with `IBluetooth::cancel_bond_process`.
+    Cancelled,
+    /// The remote device of an existing bond no longer has its keys, e.g. because it was reset,
+    /// so the bond was removed. The device may also be an impostor, so the pairing agent is asked
+    /// with `IBluetoothPairingAgent::on_repair_request` before bonding with it again.
+    KeyMissing,
+}
 
-        for callback in &self.callbacks {
-            callback.1.on_bluetooth_address_changed(self.local_address.unwrap().to_string());
+impl From<BtStatus> for BondStatus {
+    fn from(status: BtStatus) -> Self {
+        match status {
+            BtStatus::Success => BondStatus::Success,
+            BtStatus::AuthFailure => BondStatus::AuthFailure,
+            BtStatus::AuthRejected => BondStatus::AuthRejected,
+            BtStatus::RemoteDeviceDown => BondStatus::RemoteDeviceDown,
+            BtStatus::Timeout => BondStatus::Timeout,
+            _ => BondStatus::Failed,
         }
     }
+}
 
-    pub(crate) fn callback_disconnected(&mut self, id: u32) {
-        self.callbacks.retain(|x| x.0 != id);
+/// PHY of an LE connection, as reported by `IBluetoothCallback::on_le_phy_changed`.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(u32)]
+pub enum LePhy {
+    Le1m = 1,
+    Le2m,
+    LeCoded,
+}
+
+impl LePhy {
+    /// Returns the bit of the PHY in the preferences passed to `IBluetooth::set_default_le_phy`.
+    pub fn mask(&self) -> u32 {
+        1 << (*self as u32 - 1)
     }
 }
 
-#[btif_callbacks_generator(btif_bluetooth_callbacks, BluetoothCallbacks)]
-pub(crate) trait BtifBluetoothCallbacks {
-    #[stack_message(BluetoothAdapterStateChanged)]
-    fn adapter_state_changed(&mut self, state: BtState);
+/// Role of the adapter on a connection, see `tHCI_ROLE`.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(i32)]
+pub enum LinkRole {
+    Central = 0,
+    Peripheral = 1,
+    Unknown = 0xFF,
+}
 
-    #[stack_message(BluetoothAdapterPropertiesChanged)]
-    fn adapter_properties_changed(
-        &mut self,
-        status: i32,
-        num_properties: i32,
-        properties: Vec<ffi::BtProperty>,
-    );
+impl From<i32> for LinkRole {
+    fn from(role: i32) -> Self {
+        LinkRole::from_i32(role).unwrap_or(LinkRole::Unknown)
+    }
 }
 
-#[derive(FromPrimitive, ToPrimitive, PartialEq, PartialOrd)]
+/// Tuning of an LE connection for a kind of device, applied with
+/// `IBluetooth::set_le_connection_preset`.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
 #[repr(i32)]
-#[derive(Debug)]
-enum PropertyType {
-    BDName = 0x01,
-    BDAddr,
-    Uuids,
-    ClassOfDevice,
-    TypeOfDevice,
-    ServiceRecord,
-    AdapterScanMode,
-    AdapterBondedDevices,
-    AdapterDiscoverableTimeout,
-    RemoteFriendlyName,
-    RemoteRssi,
-    RemoteVersionInfo,
-    RemoteLocalLeFeatures,
-    RemoteDynamicAudioBuffer = 0x10,
-    Unknown = 0x100,
+pub enum LeConnectionPreset {
+    /// Shortest connection interval, for input devices like mice and keyboards.
+    LowLatencyHid = 0,
+    /// Trade-off between latency and power, as used by default.
+    Balanced,
+    /// Long connection interval with peripheral latency, for sensors that send little data.
+    LowPowerSensor,
 }
 
-impl BtifBluetoothCallbacks for Bluetooth {
-    fn adapter_state_changed(&mut self, state: BtState) {
-        for callback in &self.callbacks {
-            callback
-                .1
-                .on_bluetooth_state_changed(self.state.to_u32().unwrap(), state.to_u32().unwrap());
+/// Connection parameters and data length requested for an `LeConnectionPreset`.
+struct LeConnectionParameters {
+    /// Connection interval range, in units of 1.25 ms.
+    min_interval: u16,
+    max_interval: u16,
+    /// Number of connection events the peripheral may skip.
+    latency: u16,
+    /// Supervision timeout, in units of 10 ms.
+    timeout: u16,
+    /// Maximum number of payload octets sent in a packet.
+    tx_pdu_length: u16,
+}
+
+impl LeConnectionPreset {
+    fn parameters(&self) -> LeConnectionParameters {
+        match self {
+            LeConnectionPreset::LowLatencyHid => LeConnectionParameters {
+                min_interval: 6,
+                max_interval: 9,
+                latency: 0,
+                timeout: 200,
+                tx_pdu_length: 251,
+            },
+            LeConnectionPreset::Balanced => LeConnectionParameters {
+                min_interval: 24,
+                max_interval: 40,
+                latency: 0,
+                timeout: 500,
+                tx_pdu_length: 251,
+            },
+            // Short packets keep the radio on for less time, sensors don't need longer ones.
+            LeConnectionPreset::LowPowerSensor => LeConnectionParameters {
+                min_interval: 80,
+                max_interval: 100,
+                latency: 4,
+                timeout: 600,
+                tx_pdu_length: 27,
+            },
         }
+    }
+}
 
-        self.state = state;
+/// Version and LE feature support of the local controller, as returned by
+/// `IBluetooth::get_controller_info`.
+#[derive(Clone, Debug, Default)]
+pub struct ControllerInfo {
+    pub is_ready: bool,
+    pub hci_version: u32,
+    pub hci_revision: u32,
+    pub lmp_version: u32,
+    /// Company identifier of the controller manufacturer, as assigned by the Bluetooth SIG.
+    pub manufacturer: u32,
+    pub lmp_subversion: u32,
+    pub le_supported: bool,
+    pub le_2m_phy: bool,
+    pub le_coded_phy: bool,
+    pub le_extended_advertising: bool,
+    pub le_periodic_advertising: bool,
+    pub le_max_advertising_data_length: u32,
+    pub le_number_of_advertising_sets: u32,
+}
+
+/// Security of the link to a remote device, as returned by `IBluetooth::get_security_level`.
+#[derive(Clone, Debug, Default)]
+pub struct SecurityLevel {
+    pub connected: bool,
+    /// Whether the link is over LE rather than BR/EDR.
+    pub le: bool,
+    pub encrypted: bool,
+    /// Whether the link is encrypted with a key exchanged with MITM protection.
+    pub authenticated: bool,
+    /// Whether the key was exchanged with Secure Connections pairing.
+    pub secure_connections: bool,
+}
+
+/// Type of the address the adapter uses over LE, see `IBluetooth::get_le_own_address`.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(u32)]
+pub enum LeOwnAddressType {
+    Public = 0,
+    /// Addresses rotated by the controller, while LE privacy is enabled.
+    ResolvablePrivate,
+    /// The address set with `IBluetooth::set_le_static_address`.
+    StaticRandom,
+}
+
+impl Default for LeOwnAddressType {
+    fn default() -> Self {
+        LeOwnAddressType::Public
     }
+}
 
-    #[allow(unused_variables)]
-    fn adapter_properties_changed(
-        &mut self,
-        status: i32,
-        num_properties: i32,
-        properties: Vec<ffi::BtProperty>,
-    ) {
-        if status != 0 {
-            return;
-        }
+/// Address the adapter uses over LE, as returned by `IBluetooth::get_le_own_address`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LeOwnAddress {
+    pub address: String,
+    pub address_type: LeOwnAddressType,
+}
 
-        for prop in properties {
-            let prop_type = PropertyType::from_i32(prop.prop_type);
+/// Type of a `ConnectionEvent`.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(u32)]
+pub enum ConnectionEventType {
+    Connected = 0,
+    Disconnected,
+    BondingStarted,
+    /// Bonding failed, e.g. because the device could not be connected.
+    BondingFailed,
+}
 
-            if prop_type.is_none() {
-                continue;
-            }
+/// Connection lifecycle event of a device, as returned by
+/// `IBluetoothDebug::get_connection_history`.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionEvent {
+    /// Time of the event, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// A `ConnectionEventType` value.
+    pub event_type: u32,
+    /// btif status of a failure, 0 otherwise.
+    pub status: i32,
+    /// HCI reason code of a disconnection, 0 otherwise.
+    pub hci_reason: i32,
+}
 
-            match prop_type.unwrap() {
-                PropertyType::BDAddr => {
-                    self.update_local_address(&prop.val);
-                }
-                _ => {}
-            }
+/// The interface for adapter callbacks registered through `IBluetooth::register_callback`.
+pub trait IBluetoothCallback: RPCProxy {
+    /// When the adapter goes from an `AdapterState` to another.
+    fn on_bluetooth_state_changed(&self, prev_state: u32, new_state: u32);
+
+    /// When any of the adapter local address is changed.
+    fn on_bluetooth_address_changed(&self, addr: String);
+
+    /// When the address the adapter uses over LE changes, after `IBluetooth::set_le_privacy` or
+    /// `IBluetooth::set_le_static_address`. See `IBluetooth::get_le_own_address`.
+    ///
+    /// The rotations of the resolvable private addresses are not reported.
+    fn on_le_own_address_changed(&self, address: LeOwnAddress);
+
+    /// When the bond state of a remote device is changed.
+    ///
+    /// `state` is a `BtBondState` value. The bonded state is only reported once the name of the
+    /// device is known (or could not be read), so that it can be read with `get_remote_name`.
+    fn on_bond_state_changed(&self, status: BondStatus, addr: String, state: u32);
+
+    /// When an ACL connection to a remote device is established.
+    fn on_device_connected(&self, addr: String);
+
+    /// When an ACL connection to a remote device is closed or fails to be established.
+    ///
+    /// `status` is the btif status of the operation and `hci_reason` is the HCI disconnect
+    /// reason code.
+    fn on_device_disconnected(&self, addr: String, status: BtStatus, hci_reason: i32);
+
+    /// When the adapter becomes discoverable or stops being discoverable.
+    fn on_discoverable_changed(&self, discoverable: bool);
+
+    /// When the adapter starts or stops accepting incoming pairings, see
+    /// `IBluetooth::set_pairable`.
+    fn on_pairable_changed(&self, pairable: bool);
+
+    /// When the local out of band data requested through `generate_local_oob_data` is read.
+    fn on_local_oob_data_generated(&self, transport: i32, data: ffi::BtOutOfBandData);
+
+    /// When the RSSI of a device monitored with `start_rssi_monitoring` crosses the thresholds.
+    ///
+    /// `near` is set if the RSSI is at or above the high threshold, and unset if it is at or below
+    /// the low threshold.
+    fn on_rssi_changed(&self, addr: String, rssi: i32, near: bool);
+
+    /// When an LE connection is established, with the `LePhy` values it uses before the PHY
+    /// preferences are negotiated.
+    fn on_le_phy_changed(&self, addr: String, tx_phy: u32, rx_phy: u32);
+
+    /// When the UUIDs of a remote device change, e.g. after a service discovery.
+    fn on_remote_uuids_changed(&self, addr: String, uuids: Vec<Uuid128>);
+
+    /// When a connected device using the resolvable private address `addr` turns out to be the
+    /// bonded device `identity_address`, so that both can be listed as one device. The scan
+    /// results of bonded devices already carry their identity address.
+    ///
+    /// Reported once for each address, until the device unbonds.
+    fn on_identity_address_resolved(&self, addr: String, identity_address: String);
+
+    /// When a device that passes the filter of `IBluetooth::start_discovery` is found, with the
+    /// name, Class of Device and RSSI it was found with. The name is empty if it is not known yet.
+    fn on_device_found(&self, addr: String, name: String, class_of_device: u32, rssi: i32);
+
+    /// When the adapter starts or stops discovering the nearby devices.
+    fn on_discovering_changed(&self, discovering: bool);
+
+    /// When the role of the adapter on the connection to a device over a transport, 1 for BR/EDR
+    /// or 2 for LE, is known, i.e. once connected and after role switches.
+    ///
+    /// Role switches that the remote device makes are only reported once the role is read again,
+    /// e.g. on the next `IBluetooth::request_role_switch`.
+    fn on_link_role_changed(&self, addr: String, transport: i32, role: LinkRole);
+
+    /// When a switch requested with `IBluetooth::request_role_switch` completes, with the role of
+    /// the adapter on the BR/EDR connection after it.
+    fn on_role_switch_completed(&self, addr: String, status: BtStatus, role: LinkRole);
+}
+
+/// Which devices found by `IBluetooth::start_discovery` are reported. Every criterion is optional.
+#[derive(Clone, Debug, Default)]
+pub struct DiscoveryFilter {
+    /// Major device classes of the Class of Device to report, as a bit mask with bit n set for
+    /// major class n, e.g. 1 << 4 for audio/video devices. 0 for any class. LE only devices have
+    /// no Class of Device and only pass an empty mask.
+    pub major_classes: u32,
+    /// Transport the devices must support, 1 for BR/EDR or 2 for LE. 0 for any transport.
+    pub transport: i32,
+    /// Text the name of the devices must contain, ignoring case. Empty for any name, otherwise
+    /// the devices whose name is not known when found are left out.
+    pub name_substring: String,
+}
+
+impl DiscoveryFilter {
+    fn matches(&self, class_of_device: u32, device_type: Option<u32>, name: &str) -> bool {
+        let major_class = (class_of_device >> 8) & 0x1F;
+        if self.major_classes != 0
+            && (class_of_device == 0 || self.major_classes & (1 << major_class) == 0)
+        {
+            return false;
+        }
+
+        let supported = match (self.transport, device_type) {
+            (0, _) => true,
+            (BT_TRANSPORT_BR_EDR, Some(BT_DEVICE_TYPE_BREDR))
+            | (BT_TRANSPORT_BR_EDR, Some(BT_DEVICE_TYPE_DUMO))
+            | (BT_TRANSPORT_LE, Some(BT_DEVICE_TYPE_BLE))
+            | (BT_TRANSPORT_LE, Some(BT_DEVICE_TYPE_DUMO)) => true,
+            _ => false,
+        };
+        if !supported {
+            return false;
         }
+
+        self.name_substring.is_empty()
+            || name.to_lowercase().contains(&self.name_substring.to_lowercase())
     }
 }
 
-// TODO: Add unit tests for this implementation
-impl IBluetooth for Bluetooth {
-    fn register_callback(&mut self, mut callback: Box<dyn IBluetoothCallback + Send>) {
-        let tx = self.tx.clone();
-
-        // TODO: Refactor into a separate wrap-around id generator.
-        self.callbacks_last_id += 1;
-        let id = self.callbacks_last_id;
+/// Input and output of a pairing agent, as in `bt_io_cap_t`.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(i32)]
+pub enum IoCapability {
+    DisplayOnly = 0,
+    DisplayYesNo,
+    KeyboardOnly,
+    NoInputNoOutput,
+    KeyboardDisplay,
+}
 
-        callback.register_disconnect(Box::new(move || {
-            let tx = tx.clone();
-            topstack::get_runtime().spawn(async move {
-                let _result = tx.send(Message::BluetoothCallbackDisconnected(id)).await;
-            });
-        }));
+impl IoCapability {
+    fn has_display(&self) -> bool {
+        match self {
+            IoCapability::DisplayOnly
+            | IoCapability::DisplayYesNo
+            | IoCapability::KeyboardDisplay => true,
+            _ => false,
+        }
+    }
 
-        self.callbacks.push((id, callback))
+    /// Returns whether the user can accept or reject a pairing.
+    fn has_yes_no(&self) -> bool {
+        match self {
+            IoCapability::DisplayYesNo
+            | IoCapability::KeyboardOnly
+            | IoCapability::KeyboardDisplay => true,
+            _ => false,
+        }
     }
 
-    fn enable(&mut self) -> bool {
-        self.intf.lock().unwrap().enable() == 0
+    fn has_keyboard(&self) -> bool {
+        match self {
+            IoCapability::KeyboardOnly | IoCapability::KeyboardDisplay => true,
+            _ => false,
+        }
     }
 
-    fn disable(&mut self) -> bool {
-        self.intf.lock().unwrap().disable() == 0
+    /// Returns whether an agent can handle a request of the `BtSspVariant`, or a PIN request for
+    /// None.
+    fn can_handle(&self, variant: Option<BtSspVariant>) -> bool {
+        match variant {
+            Some(BtSspVariant::PasskeyConfirmation) => self.has_display() && self.has_yes_no(),
+            Some(BtSspVariant::PasskeyEntry) | None => self.has_keyboard(),
+            Some(BtSspVariant::Consent) => self.has_yes_no(),
+            Some(BtSspVariant::PasskeyNotification) => self.has_display(),
+        }
     }
 
-    fn get_address(&self) -> String {
-        match self.local_address {
-            None => String::from(""),
-            Some(addr) => addr.to_string(),
+    /// Returns the capability that agents with the given capabilities have together, i.e. the
+    /// one whose requests they can all handle between them.
+    fn combine(capabilities: &[IoCapability]) -> IoCapability {
+        let can_handle = |variant| capabilities.iter().any(|c| c.can_handle(Some(variant)));
+
+        match (
+            can_handle(BtSspVariant::PasskeyEntry),
+            can_handle(BtSspVariant::PasskeyConfirmation),
+            can_handle(BtSspVariant::PasskeyNotification),
+        ) {
+            (true, true, _) => IoCapability::KeyboardDisplay,
+            (true, false, _) => IoCapability::KeyboardOnly,
+            (false, true, _) => IoCapability::DisplayYesNo,
+            (false, false, true) => IoCapability::DisplayOnly,
+            (false, false, false) => IoCapability::NoInputNoOutput,
         }
     }
 }
+
+/// A pairing agent, registered through `IBluetooth::register_pairing_agent`.
+pub trait IBluetoothPairingAgent: RPCProxy {
+    /// When a device doing Secure Simple Pairing needs the user.
+    ///
+    /// The passkey is shown to the user to compare for `BtSspVariant::PasskeyConfirmation`, and to
+    /// enter on the remote device for `BtSspVariant::PasskeyNotification`, which expects no
+    /// answer. The other requests are answered with `IBluetooth::set_pairing_confirmation` or
+    /// `IBluetooth::set_passkey`.
+    fn on_ssp_request(&self, addr: String, name: String, variant: BtSspVariant, passkey: u32);
+
+    /// When a device doing legacy pairing asks for the PIN, answered with `IBluetooth::set_pin`.
+    ///
+    /// `min_16_digit` is set if the PIN must have 16 digits.
+    fn on_pin_request(&self, addr: String, name: String, min_16_digit: bool);
+
+    /// When the bond of a device was removed because the device lost its keys, to ask the user
+    /// whether to bond with it again. Answered with `IBluetooth::set_repair_confirmation`.
+    fn on_repair_request(&self, addr: String, name: String);
+}
+
+/// A pairing request waiting for the answer of an agent.
+struct PairingRequest {
+    /// None for a PIN request.
+    variant: Option<BtSspVariant>,
+    agent: u32,
+}
+
+/// A request to the native stack that is answered by a callback.
+///
+/// Each request is timed out in case the callback never comes, e.g. if the controller
+/// misbehaves, so that clients don't wait forever.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PendingOperation {
+    /// Bonding with a device, until it is bonded or fails.
+    Bond(BDAddr),
+    /// Reading the local out of band data of a transport.
+    LocalOobData(i32),
+    /// Reading the RSSI of a device.
+    RssiRead(BDAddr),
+}
+
+/// A bond started with `IBluetooth::create_bond` or `IBluetooth::create_bond_out_of_band`, kept
+/// until it is done to retry it.
+struct BondAttempt {
+    transport: i32,
+    /// The P-192 and P-256 out of band data, if the bond uses it.
+    oob_data: Option<(ffi::BtOutOfBandData, ffi::BtOutOfBandData)>,
+    retries: u32,
+    /// Set while waiting to retry.
+    retry: Option<JoinHandle<()>>,
+}
+
+/// Timer of a `PendingOperation`.
+struct OperationTimer {
+    /// Tells the timer apart from the ones cancelled after they had sent their message.
+    id: u32,
+    timer: JoinHandle<()>,
+}
+
+/// RSSI monitoring of a device, started with `IBluetooth::start_rssi_monitoring`.
+struct RssiMonitor {
+    settings: RSSISettings,
+    /// Which threshold the RSSI crossed last, if it crossed any yet.
+    near: Option<bool>,
+    // Posts a `BluetoothRssiMonitorTick` every period.
+    ticker: JoinHandle<()>,
+}
+
+/// An API call made while the adapter is turning on, run once it is on.
+struct PendingCall {
+    run: Box<dyn FnOnce(&mut Bluetooth) -> BtStatus + Send>,
+    /// Reports the failure of the call to its caller, with the status of the call or
+    /// `BtStatus::NotReady` if the adapter doesn't turn on.
+    fail: Box<dyn FnOnce(&mut Bluetooth, BtStatus) + Send>,
+}
+
+/// Implementation of the adapter API.
+pub struct Bluetooth {
+    intf: Arc<Mutex<BluetoothInterface>>,
+    state: AdapterState,
+    // API calls made while the adapter is turning on, run once it is on.
+    pending_calls: Vec<PendingCall>,
+    callbacks: Callbacks<dyn IBluetoothCallback + Send>,
+    pairing_agents: Callbacks<dyn IBluetoothPairingAgent + Send>,
+    // Capabilities of the pairing agents, in the order they registered.
+    pairing_agent_capabilities: Vec<(u32, IoCapability)>,
+    pairing_requests: HashMap<BDAddr, PairingRequest>,
+    // Devices that lost their keys, waiting for an agent to confirm bonding with them again.
+    repair_requests: HashMap<BDAddr, u32>,
+    tx: MessageSender,
+    metrics: Arc<Mutex<Metrics>>,
+    local_address: Option<BDAddr>,
+    bonded_devices: Vec<BDAddr>,
+    remote_names: HashMap<BDAddr, String>,
+    local_uuids: Vec<Uuid128>,
+    remote_uuids: HashMap<BDAddr, Vec<Uuid128>>,
+    // Categories of the remote devices that told what they are.
+    remote_categories: HashMap<BDAddr, DeviceCategory>,
+    // Devices that are bonded but whose bonded state is not reported until their name is known.
+    pending_bonded: HashSet<BDAddr>,
+    discoverable: bool,
+    // Reverts the adapter to not discoverable when it fires.
+    discoverable_timeout: Option<JoinHandle<()>>,
+    // Tells the timeout that fired apart from the ones cancelled after they had sent their
+    // message.
+    discoverable_timeout_id: u32,
+    pairable: bool,
+    // Reverts the adapter to not pairable when it fires, as `discoverable_timeout` does.
+    pairable_timeout: Option<JoinHandle<()>>,
+    pairable_timeout_id: u32,
+    // Major device classes whose incoming pairings are rejected, as set with
+    // `set_pairing_blocked_classes`.
+    pairing_blocked_classes: u32,
+    // Devices blocked with `block_device`, and the file that keeps them, see
+    // `load_blocked_devices`.
+    blocked_devices: HashSet<BDAddr>,
+    blocked_devices_path: Option<String>,
+    acl: Acl,
+    rssi_monitors: HashMap<BDAddr, RssiMonitor>,
+    // The native stack reads the RSSI of one device at a time, so the reads wait here for the one
+    // in flight to be done.
+    rssi_reads: VecDeque<BDAddr>,
+    rssi_read_in_flight: Option<BDAddr>,
+    // PHYs requested on each LE connection, set with `set_default_le_phy`.
+    default_le_phy: Option<(u8, u8)>,
+    // PHYs requested on the connection to a device with `set_le_phy`, dropped when it
+    // disconnects.
+    le_phy_overrides: HashMap<BDAddr, (u8, u8)>,
+    // Roles on the connections to each device by transport, as last read.
+    link_roles: HashMap<(BDAddr, i32), LinkRole>,
+    // Role switches requested with `request_role_switch`, with how many times the role is still
+    // read before they are deemed failed.
+    role_switches: HashMap<BDAddr, u32>,
+    // Completions of the bonds, resolved once they are made or fail.
+    bond_completions: Completers<BDAddr, (BondStatus, BtBondState)>,
+    // Completions of the UUID fetches, resolved once the UUIDs are read.
+    uuid_completions: Completers<BDAddr, Vec<Uuid128>>,
+    // The last `CONNECTION_HISTORY_LENGTH` connection events of each device, oldest first.
+    connection_history: HashMap<BDAddr, VecDeque<ConnectionEvent>>,
+    pending_operations: HashMap<PendingOperation, OperationTimer>,
+    pending_operation_id: u32,
+    bond_attempts: HashMap<BDAddr, BondAttempt>,
+    // Devices whose bonding was cancelled and already reported as failed, so that the failure
+    // the native stack reports next is not reported again.
+    cancelled_bonds: HashSet<BDAddr>,
+    // Bonded devices whose bonding failed, with the failure, until the bonded devices are read
+    // back to know if the native stack removed the bond.
+    bond_checks: HashMap<BDAddr, BtStatus>,
+    // Identity addresses of the bonded devices that resolvable private addresses belong to.
+    identity_addresses: HashMap<BDAddr, BDAddr>,
+    // Resolvable private addresses known to belong to no bonded device, forgotten when a device
+    // bonds.
+    unresolved_addresses: HashSet<BDAddr>,
+    // LE privacy settings made with the API, applied again whenever the adapter is enabled as the
+    // native stack resets them.
+    le_privacy: Option<bool>,
+    rpa_timeout: Option<u16>,
+    le_static_address: Option<BDAddr>,
+    // Set from `factory_reset` until the removed bonds are reported, to whether to turn the
+    // adapter on again after.
+    factory_reset: Option<bool>,
+    discovering: bool,
+    // Filters of the clients that started the discovery, keyed by the ids of their callbacks.
+    discovery_filters: HashMap<u32, DiscoveryFilter>,
+}
+
+/// Returns whether `class_of_device` falls in one of the major classes set in `blocked_classes`.
+/// A Class of Device that is not known, 0 or the unclassified major class the native stack reports
+/// for LE devices, is never blocked.
+fn is_class_blocked(blocked_classes: u32, class_of_device: u32) -> bool {
+    let major_class = (class_of_device >> 8) & 0x1F;
+    if class_of_device == 0 || major_class == 0x1F {
+        return false;
+    }
+
+    blocked_classes & (1 << major_class) != 0
+}
+
+impl Bluetooth {
+    /// Constructs the IBluetooth implementation.
+    pub fn new(
+        tx: MessageSender,
+        intf: Arc<Mutex<BluetoothInterface>>,
+        metrics: Arc<Mutex<Metrics>>,
+    ) -> Bluetooth {
+        Bluetooth {
+            acl: Acl::new(Arc::new(btif_acl_callbacks(tx.clone()))),
+            rssi_monitors: HashMap::new(),
+            rssi_reads: VecDeque::new(),
+            rssi_read_in_flight: None,
+            default_le_phy: None,
+            le_phy_overrides: HashMap::new(),
+            link_roles: HashMap::new(),
+            role_switches: HashMap::new(),
+            bond_completions: Completers::new(),
+            uuid_completions: Completers::new(),
+            connection_history: HashMap::new(),
+            pending_operations: HashMap::new(),
+            pending_operation_id: 0,
+            bond_attempts: HashMap::new(),
+            cancelled_bonds: HashSet::new(),
+            bond_checks: HashMap::new(),
+            identity_addresses: HashMap::new(),
+            unresolved_addresses: HashSet::new(),
+            le_privacy: None,
+            rpa_timeout: None,
+            le_static_address: None,
+            factory_reset: None,
+            discovering: false,
+            discovery_filters: HashMap::new(),
+            callbacks: Callbacks::new(tx.clone(), Message::BluetoothCallbackDisconnected),
+            pairing_agents: Callbacks::new(tx.clone(), Message::BluetoothPairingAgentDisconnected),
+            pairing_agent_capabilities: vec![],
+            pairing_requests: HashMap::new(),
+            repair_requests: HashMap::new(),
+            tx,
+            metrics,
+            intf,
+            state: AdapterState::Off,
+            pending_calls: vec![],
+            local_address: None,
+            bonded_devices: vec![],
+            remote_names: HashMap::new(),
+            local_uuids: vec![],
+            remote_uuids: HashMap::new(),
+            remote_categories: HashMap::new(),
+            pending_bonded: HashSet::new(),
+            discoverable: false,
+            discoverable_timeout: None,
+            discoverable_timeout_id: 0,
+            pairable: true,
+            pairable_timeout: None,
+            pairable_timeout_id: 0,
+            pairing_blocked_classes: 0,
+            blocked_devices: HashSet::new(),
+            blocked_devices_path: None,
+        }
+    }
+
+    fn update_local_address(&mut self, raw: &Vec<u8>) {
+        self.local_address = Some(BDAddr::from_byte_vec(raw));
+
+        for callback in self.callbacks.iter() {
+            callback.on_bluetooth_address_changed(self.local_address.unwrap().to_string());
+        }
+    }
+
+    fn update_bonded_devices(&mut self, raw: &Vec<u8>) {
+        self.bonded_devices =
+            raw.chunks_exact(6).map(|x| BDAddr::from_byte_vec(&x.to_vec())).collect();
+
+        // The UUIDs found when bonding are stored, so bonded devices need no service discovery.
+        for addr in &self.bonded_devices {
+            if !self.remote_uuids.contains_key(addr) {
+                self.intf.lock().unwrap().get_remote_device_property(
+                    &addr.to_raw_address(),
+                    PropertyType::Uuids.to_i32().unwrap(),
+                );
+            }
+        }
+    }
+
+    /// Returns the devices the adapter is bonded with.
+    pub(crate) fn get_bonded_devices(&self) -> Vec<BDAddr> {
+        self.bonded_devices.clone()
+    }
+
+    /// Returns whether a remote device is bonded, or being bonded with `create_bond`.
+    pub(crate) fn is_bonded_or_bonding(&self, addr: &BDAddr) -> bool {
+        self.bonded_devices.contains(addr) || self.bond_attempts.contains_key(addr)
+    }
+
+    /// Returns the UUIDs of a remote device, or None if they are not known yet.
+    pub(crate) fn get_cached_uuids(&self, addr: &BDAddr) -> Option<Vec<Uuid128>> {
+        self.remote_uuids.get(addr).cloned()
+    }
+
+    /// Returns whether the BR/EDR ACL of a remote device is up.
+    pub(crate) fn is_br_edr_connected(&self, addr: &BDAddr) -> bool {
+        self.acl.get_link_security(&addr.to_raw_address(), BT_TRANSPORT_BR_EDR).connected
+    }
+
+    /// Returns the security of the LE link to a remote device.
+    pub(crate) fn get_le_link_security(&self, addr: &BDAddr) -> LeLinkSecurity {
+        self.acl.get_le_link_security(&addr.to_raw_address())
+    }
+
+    /// Encrypts the LE link to a remote device, pairing first if needed.
+    pub(crate) fn request_le_encryption(&mut self, addr: &BDAddr, mitm: bool) {
+        self.acl.request_le_encryption(&addr.to_raw_address(), mitm);
+    }
+
+    /// Returns the identity address of the bonded device a resolvable private address belongs
+    /// to, if any. The first resolution of each address is reported through
+    /// `IBluetoothCallback::on_identity_address_resolved`.
+    ///
+    /// Resolving costs an AES computation per bonded device, so it is only done for connections.
+    /// The addresses of the advertisements are already resolved by the native stack.
+    pub(crate) fn resolve_identity_address(&mut self, addr: BDAddr) -> Option<BDAddr> {
+        // Only the two most significant bits set to 0b01 make an address resolvable.
+        if addr.val[0] & 0xc0 != 0x40 || self.unresolved_addresses.contains(&addr) {
+            return None;
+        }
+
+        if let Some(identity) = self.identity_addresses.get(&addr) {
+            return Some(*identity);
+        }
+
+        let identity = match self.acl.resolve_identity_address(&addr.to_raw_address()) {
+            Some(identity) => BDAddr::from_raw_address(&identity),
+            None => {
+                // Devices rotate their addresses, so stale ones are dropped once too many pile up.
+                if self.unresolved_addresses.len() >= UNRESOLVED_ADDRESSES_MAX_LEN {
+                    self.unresolved_addresses.clear();
+                }
+                self.unresolved_addresses.insert(addr);
+                return None;
+            }
+        };
+
+        self.identity_addresses.insert(addr, identity);
+        for callback in self.callbacks.iter() {
+            callback.on_identity_address_resolved(addr.to_string(), identity.to_string());
+        }
+
+        Some(identity)
+    }
+
+    fn update_remote_uuids(&mut self, addr: BDAddr, raw: &[u8]) {
+        let uuids = parse_uuids(raw);
+        self.uuid_completions.complete(&addr, uuids.clone());
+        if self.remote_uuids.get(&addr) == Some(&uuids) {
+            return;
+        }
+
+        self.remote_uuids.insert(addr, uuids.clone());
+
+        for callback in self.callbacks.iter() {
+            callback.on_remote_uuids_changed(addr.to_string(), uuids.clone());
+        }
+    }
+
+    fn update_remote_category(&mut self, addr: BDAddr, category: DeviceCategory) {
+        if category != DeviceCategory::Unknown {
+            self.remote_categories.insert(addr, category);
+        }
+    }
+
+    /// Records the Appearance an LE device advertises.
+    pub(crate) fn update_remote_appearance(&mut self, addr: BDAddr, appearance: u16) {
+        self.update_remote_category(addr, DeviceCategory::from_appearance(appearance));
+    }
+
+    fn notify_bond_state(&mut self, status: BondStatus, addr: BDAddr, state: BtBondState) {
+        if state != BtBondState::Bonding {
+            self.bond_completions.complete(&addr, (status, state));
+        }
+
+        if status != BondStatus::Success {
+            let mut metrics = self.metrics.lock().unwrap();
+            metrics.increment(metrics::PAIRING_FAILED);
+            metrics.increment(&format!("{}.{:?}", metrics::PAIRING_FAILED, status));
+        } else if state == BtBondState::Bonded {
+            self.metrics.lock().unwrap().increment(metrics::PAIRING_SUCCEEDED);
+        }
+
+        for callback in self.callbacks.iter() {
+            callback.on_bond_state_changed(status, addr.to_string(), state.to_u32().unwrap());
+        }
+    }
+
+    /// Reports a bond queued while the adapter was turning on that could not be started.
+    fn notify_bond_not_started(&mut self, addr: BDAddr, status: BtStatus) {
+        self.notify_bond_state(BondStatus::from(status), addr, BtBondState::NotBonded);
+    }
+
+    /// Reports the bonding failures of bonded devices once the bonded devices are read back.
+    ///
+    /// A device still bonded keeps its bond. A device that is not is reported as
+    /// `BondStatus::KeyMissing` if it rejected the keys, and the pairing agent is asked whether to
+    /// bond with it again.
+    fn finish_bond_checks(&mut self) {
+        let checks: Vec<(BDAddr, BtStatus)> = self.bond_checks.drain().collect();
+        for (addr, status) in checks {
+            if self.bonded_devices.contains(&addr) {
+                self.notify_bond_state(BondStatus::from(status), addr, BtBondState::Bonded);
+                continue;
+            }
+
+            self.pending_bonded.remove(&addr);
+            self.identity_addresses.retain(|_, identity| *identity != addr);
+
+            if status != BtStatus::AuthFailure {
+                self.notify_bond_state(BondStatus::from(status), addr, BtBondState::NotBonded);
+                continue;
+            }
+            self.notify_bond_state(BondStatus::KeyMissing, addr, BtBondState::NotBonded);
+
+            if let Some(id) = self.pairing_agent_capabilities.last().map(|(id, _)| *id) {
+                self.repair_requests.insert(addr, id);
+                let name = self.remote_names.get(&addr).cloned().unwrap_or_default();
+                if let Some(agent) = self.pairing_agents.get_by_id(id) {
+                    agent.on_repair_request(addr.to_string(), name);
+                }
+            }
+        }
+    }
+
+    /// Reports the bonded state of a device if it was still waiting for the device name.
+    fn flush_pending_bonded(&mut self, addr: BDAddr) {
+        if self.pending_bonded.remove(&addr) {
+            self.notify_bond_state(BondStatus::Success, addr, BtBondState::Bonded);
+        }
+    }
+
+    /// Starts a bond, or retries it, as it was made with the API.
+    fn start_bond(&mut self, addr: BDAddr) -> BtStatus {
+        let attempt = match self.bond_attempts.get(&addr) {
+            Some(attempt) => attempt,
+            None => return BtStatus::Fail,
+        };
+
+        let status = BtStatus::from(match &attempt.oob_data {
+            Some((p192_data, p256_data)) => self.intf.lock().unwrap().create_bond_out_of_band(
+                &addr.to_raw_address(),
+                attempt.transport,
+                p192_data,
+                p256_data,
+            ),
+            None => {
+                self.intf.lock().unwrap().create_bond(&addr.to_raw_address(), attempt.transport)
+            }
+        });
+        if status != BtStatus::Success {
+            self.bond_attempts.remove(&addr);
+            return status;
+        }
+
+        self.cancelled_bonds.remove(&addr);
+        self.start_operation(PendingOperation::Bond(addr), BOND_TIMEOUT);
+        BtStatus::Success
+    }
+
+    /// Schedules the retry of a bond that failed transiently. Returns false if the bond was not
+    /// made with the API or its retries are used up.
+    fn schedule_bond_retry(&mut self, addr: BDAddr) -> bool {
+        let attempt = match self.bond_attempts.get_mut(&addr) {
+            Some(attempt) if attempt.retries < BOND_RETRY_MAX => attempt,
+            _ => return false,
+        };
+
+        let delay = BOND_RETRY_DELAY * 2u32.pow(attempt.retries);
+        attempt.retries += 1;
+
+        let tx = self.tx.clone();
+        attempt.retry = Some(topstack::get_runtime().spawn(async move {
+            tokio::time::sleep(delay).await;
+            let _result = tx.send(Message::BluetoothBondRetry(addr)).await;
+        }));
+        true
+    }
+
+    /// Retries a bond once the delay set by `schedule_bond_retry` is over.
+    pub(crate) fn bond_retry(&mut self, addr: BDAddr) {
+        // The bond may have been cancelled while waiting.
+        if self.bond_attempts.get_mut(&addr).and_then(|attempt| attempt.retry.take()).is_none() {
+            return;
+        }
+
+        let status = self.start_bond(addr);
+        if status != BtStatus::Success {
+            let code = status.to_i32().unwrap();
+            self.record_connection_event(addr, ConnectionEventType::BondingFailed, code, 0);
+            self.notify_bond_state(BondStatus::from(status), addr, BtBondState::NotBonded);
+        }
+    }
+
+    /// Gives up waiting for the name of a bonded device.
+    pub(crate) fn bonded_name_timeout(&mut self, addr: BDAddr) {
+        self.flush_pending_bonded(addr);
+    }
+
+    fn set_scan_mode(&mut self, mode: BtScanMode) -> BtStatus {
+        let val = mode.to_i32().unwrap().to_ne_bytes().to_vec();
+        let prop = ffi::BtProperty {
+            prop_type: PropertyType::AdapterScanMode.to_i32().unwrap(),
+            len: val.len() as i32,
+            val,
+        };
+
+        BtStatus::from(self.intf.lock().unwrap().set_adapter_property(&prop))
+    }
+
+    fn apply_discoverable(&mut self, mode: bool, duration: u32) -> BtStatus {
+        let scan_mode =
+            if mode { BtScanMode::ConnectableDiscoverable } else { BtScanMode::Connectable };
+
+        let status = self.set_scan_mode(scan_mode);
+        if status != BtStatus::Success {
+            return status;
+        }
+
+        self.cancel_discoverable_timeout();
+
+        if mode && duration > 0 {
+            let tx = self.tx.clone();
+            self.discoverable_timeout_id = self.discoverable_timeout_id.wrapping_add(1);
+            let id = self.discoverable_timeout_id;
+            self.discoverable_timeout = Some(topstack::get_runtime().spawn(async move {
+                tokio::time::sleep(Duration::from_secs(duration.into())).await;
+                let _result = tx.send(Message::BluetoothDiscoverableTimeout(id)).await;
+            }));
+        }
+
+        BtStatus::Success
+    }
+
+    fn cancel_discoverable_timeout(&mut self) {
+        if let Some(timeout) = self.discoverable_timeout.take() {
+            timeout.abort();
+        }
+    }
+
+    fn update_scan_mode(&mut self, raw: &[u8]) {
+        let mode = match raw.try_into() {
+            Ok(bytes) => BtScanMode::from_i32(i32::from_ne_bytes(bytes)),
+            Err(_) => None,
+        };
+
+        let discoverable = mode == Some(BtScanMode::ConnectableDiscoverable);
+        if discoverable == self.discoverable {
+            return;
+        }
+
+        self.discoverable = discoverable;
+        if !discoverable {
+            self.cancel_discoverable_timeout();
+        }
+
+        self.notify_discoverable();
+    }
+
+    fn notify_discoverable(&self) {
+        for callback in self.callbacks.iter() {
+            callback.on_discoverable_changed(self.discoverable);
+        }
+    }
+
+    /// Reverts the adapter to not discoverable once the duration set with `set_discoverable` is
+    /// over.
+    pub(crate) fn discoverable_timeout(&mut self, id: u32) {
+        if id != self.discoverable_timeout_id || self.discoverable_timeout.take().is_none() {
+            return;
+        }
+
+        self.set_scan_mode(BtScanMode::Connectable);
+    }
+
+    fn apply_pairable(&mut self, mode: bool) {
+        if mode == self.pairable {
+            return;
+        }
+
+        self.pairable = mode;
+        // The native stack accepts incoming temporary Just Works pairings on its own unless told
+        // otherwise, which would bypass `rejects_incoming_pairing`.
+        self.intf.lock().unwrap().set_auto_accept_temporary_pairing(mode);
+        for callback in self.callbacks.iter() {
+            callback.on_pairable_changed(mode);
+        }
+    }
+
+    fn cancel_pairable_timeout(&mut self) {
+        if let Some(timeout) = self.pairable_timeout.take() {
+            timeout.abort();
+        }
+    }
+
+    /// Reverts the adapter to not pairable once the timeout set with `set_pairable` is over.
+    pub(crate) fn pairable_timeout(&mut self, id: u32) {
+        if id != self.pairable_timeout_id || self.pairable_timeout.take().is_none() {
+            return;
+        }
+
+        self.apply_pairable(false);
+    }
+
+    /// Loads the devices blocked with `block_device` from the file at `path`, which keeps them
+    /// from then on. The file holds an address per line.
+    pub fn load_blocked_devices(&mut self, path: &str) {
+        if let Ok(contents) = fs::read_to_string(path) {
+            self.blocked_devices =
+                contents.lines().filter_map(|line| BDAddr::from_string(line.trim())).collect();
+        }
+
+        self.blocked_devices_path = Some(String::from(path));
+    }
+
+    fn save_blocked_devices(&self) {
+        let path = match &self.blocked_devices_path {
+            Some(path) => path,
+            None => return,
+        };
+
+        let mut addrs: Vec<String> =
+            self.blocked_devices.iter().map(|addr| addr.to_string()).collect();
+        addrs.sort();
+        let contents: String = addrs.iter().map(|addr| format!("{}\n", addr)).collect();
+
+        if let Err(e) = fs::write(path, contents) {
+            eprintln!("Failed to save the blocked devices to {}: {}", path, e);
+        }
+    }
+
+    /// Returns whether a device is blocked, by its address or by the identity address its
+    /// resolvable private address was resolved to.
+    pub(crate) fn is_device_blocked(&self, addr: &BDAddr) -> bool {
+        self.blocked_devices.contains(addr)
+            || self
+                .identity_addresses
+                .get(addr)
+                .map_or(false, |identity| self.blocked_devices.contains(identity))
+    }
+
+    /// Returns whether a pairing is rejected because the device is blocked, or because the remote
+    /// device started it while the adapter is not pairable or the class of the device is blocked.
+    /// Only the pairings of blocked devices are rejected for the bonds made with `create_bond`.
+    fn rejects_incoming_pairing(&self, addr: &BDAddr, class_of_device: u32) -> bool {
+        if self.is_device_blocked(addr) {
+            return true;
+        }
+
+        if self.bond_attempts.contains_key(addr) {
+            return false;
+        }
+
+        !self.pairable || is_class_blocked(self.pairing_blocked_classes, class_of_device)
+    }
+
+    /// Starts timing out an operation, or restarts its timer if it is already pending.
+    fn start_operation(&mut self, operation: PendingOperation, timeout: Duration) {
+        self.finish_operation(operation);
+
+        let tx = self.tx.clone();
+        self.pending_operation_id = self.pending_operation_id.wrapping_add(1);
+        let id = self.pending_operation_id;
+        let timer = topstack::get_runtime().spawn(async move {
+            tokio::time::sleep(timeout).await;
+            let _result = tx.send(Message::BluetoothOperationTimeout(operation, id)).await;
+        });
+
+        self.pending_operations.insert(operation, OperationTimer { id, timer });
+    }
+
+    /// Stops timing out an operation once it is answered. Returns whether it was pending.
+    fn finish_operation(&mut self, operation: PendingOperation) -> bool {
+        match self.pending_operations.remove(&operation) {
+            Some(pending) => {
+                pending.timer.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fails an operation that got no answer in time, with a `BtStatus::Timeout` status.
+    pub(crate) fn operation_timeout(&mut self, operation: PendingOperation, id: u32) {
+        match self.pending_operations.get(&operation) {
+            Some(pending) if pending.id == id => {}
+            _ => return,
+        }
+        self.pending_operations.remove(&operation);
+
+        eprintln!("{:?} timed out", operation);
+        let status = BtStatus::Timeout.to_i32().unwrap();
+
+        match operation {
+            PendingOperation::Bond(addr) => {
+                self.intf.lock().unwrap().cancel_bond(&addr.to_raw_address());
+                self.bond_attempts.remove(&addr);
+                self.cancelled_bonds.insert(addr);
+                self.record_connection_event(addr, ConnectionEventType::BondingFailed, status, 0);
+                self.notify_bond_state(BondStatus::Timeout, addr, BtBondState::NotBonded);
+            }
+            PendingOperation::LocalOobData(transport) => {
+                self.notify_local_oob_data_failed(transport)
+            }
+            PendingOperation::RssiRead(_) => self.finish_rssi_read(status, 0),
+        }
+    }
+
+    /// Reports local out of band data that could not be read, with `is_valid` unset.
+    fn notify_local_oob_data_failed(&self, transport: i32) {
+        let data =
+            ffi::BtOutOfBandData { is_valid: false, address: [0; 7], c: [0; 16], r: [0; 16] };
+        for callback in self.callbacks.iter() {
+            callback.on_local_oob_data_generated(transport, data.clone());
+        }
+    }
+
+    fn record_connection_event(
+        &mut self,
+        addr: BDAddr,
+        event_type: ConnectionEventType,
+        status: i32,
+        hci_reason: i32,
+    ) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+
+        let history = self.connection_history.entry(addr).or_default();
+        if history.len() == CONNECTION_HISTORY_LENGTH {
+            history.pop_front();
+        }
+        history.push_back(ConnectionEvent {
+            timestamp_ms,
+            event_type: event_type.to_u32().unwrap(),
+            status,
+            hci_reason,
+        });
+    }
+
+    /// Returns the last `limit` connection events of a device, oldest first, or all the events
+    /// kept if `limit` is 0.
+    pub(crate) fn get_connection_history(
+        &self,
+        addr: BDAddr,
+        limit: usize,
+    ) -> Vec<ConnectionEvent> {
+        let history = match self.connection_history.get(&addr) {
+            Some(history) => history,
+            None => return vec![],
+        };
+
+        let skip = if limit == 0 { 0 } else { history.len().saturating_sub(limit) };
+        history.iter().skip(skip).cloned().collect()
+    }
+
+    /// Cleans up the Bluetooth interface, once the adapter is off and the profiles are cleaned up.
+    pub(crate) fn cleanup(&mut self) {
+        self.intf.lock().unwrap().cleanup();
+    }
+
+    pub(crate) fn callback_disconnected(&mut self, id: u32) {
+        self.callbacks.remove_callback(id);
+        self.discovery_filters.remove(&id);
+    }
+
+    pub(crate) fn pairing_agent_disconnected(&mut self, id: u32) {
+        self.unregister_pairing_agent(id);
+    }
+
+    fn set_discovering(&mut self, discovering: bool) {
+        if self.discovering == discovering {
+            return;
+        }
+
+        self.discovering = discovering;
+        if !discovering {
+            self.discovery_filters.clear();
+        }
+
+        for callback in self.callbacks.iter() {
+            callback.on_discovering_changed(discovering);
+        }
+    }
+
+    /// Returns the most recently registered pairing agent able to handle a request of the
+    /// `BtSspVariant`, or a PIN request for None.
+    fn find_pairing_agent(&self, variant: Option<BtSspVariant>) -> Option<u32> {
+        self.pairing_agent_capabilities
+            .iter()
+            .rev()
+            .find(|(_, capability)| capability.can_handle(variant))
+            .map(|(id, _)| *id)
+    }
+
+    /// Advertises the IO capability that the pairing agents have together, or no input and no
+    /// output without agents.
+    ///
+    /// The native stack only stores the BR/EDR capability, which it reads when it starts up, so it
+    /// takes effect the next time the adapter is turned on. The LE capability is read for each
+    /// pairing. Storing is refused while the adapter is off, which is why this also runs once the
+    /// adapter is on.
+    fn apply_io_capability(&mut self) {
+        let capabilities: Vec<IoCapability> =
+            self.pairing_agent_capabilities.iter().map(|(_, capability)| *capability).collect();
+        let val = IoCapability::combine(&capabilities).to_i32().unwrap().to_ne_bytes().to_vec();
+
+        for prop_type in [PropertyType::LocalIoCaps, PropertyType::LocalIoCapsBle] {
+            let prop = ffi::BtProperty {
+                prop_type: prop_type.to_i32().unwrap(),
+                len: val.len() as i32,
+                val: val.clone(),
+            };
+            self.intf.lock().unwrap().set_adapter_property(&prop);
+        }
+    }
+
+    /// Answers a pairing request on behalf of its agent, returning the status of the answer.
+    fn reply_to_pairing_request(
+        &mut self,
+        addr: BDAddr,
+        variant: Option<BtSspVariant>,
+        accept: bool,
+        passkey: u32,
+        pin: &[u8],
+    ) -> BtStatus {
+        let addr = addr.to_raw_address();
+        let status = match variant {
+            Some(variant) => self.intf.lock().unwrap().ssp_reply(&addr, variant, accept, passkey),
+            None => self.intf.lock().unwrap().pin_reply(&addr, accept, pin),
+        };
+        BtStatus::from(status)
+    }
+
+    /// Removes the request of a device waiting for an answer, if it is one of those `accepts`
+    /// answers, and returns its variant.
+    fn take_pairing_request(
+        &mut self,
+        addr: BDAddr,
+        accepts: fn(Option<BtSspVariant>) -> bool,
+    ) -> Option<Option<BtSspVariant>> {
+        match self.pairing_requests.get(&addr) {
+            Some(request) if accepts(request.variant) => {
+                self.pairing_requests.remove(&addr).map(|request| request.variant)
+            }
+            _ => None,
+        }
+    }
+
+    /// Moves the adapter to a new state and notifies the callbacks.
+    ///
+    /// Unexpected transitions are still applied, since they come from the native stack, but are
+    /// logged.
+    fn set_state(&mut self, state: AdapterState) {
+        if state == self.state {
+            return;
+        }
+
+        if !self.state.can_transition_to(state) {
+            eprintln!("Unexpected adapter transition from {:?} to {:?}", self.state, state);
+        }
+
+        let prev_state = self.state;
+        self.state = state;
+
+        for callback in self.callbacks.iter() {
+            callback
+                .on_bluetooth_state_changed(prev_state.to_u32().unwrap(), state.to_u32().unwrap());
+        }
+
+        match state {
+            AdapterState::On => {
+                self.apply_le_privacy_settings();
+                self.apply_io_capability();
+
+                for call in std::mem::take(&mut self.pending_calls) {
+                    let status = (call.run)(self);
+                    if status != BtStatus::Success {
+                        (call.fail)(self, status);
+                    }
+                }
+            }
+            AdapterState::Off => {
+                for (_, monitor) in self.rssi_monitors.drain() {
+                    monitor.ticker.abort();
+                }
+
+                for call in std::mem::take(&mut self.pending_calls) {
+                    (call.fail)(self, BtStatus::NotReady);
+                }
+                self.cancel_discoverable_timeout();
+                self.set_discovering(false);
+                self.link_roles.clear();
+                self.role_switches.clear();
+                self.bond_completions.clear();
+                self.uuid_completions.clear();
+
+                // Callbacks don't come anymore, but the clients know from the state change.
+                for (_, pending) in self.pending_operations.drain() {
+                    pending.timer.abort();
+                }
+                let status = BtStatus::NotReady.to_i32().unwrap();
+                let rssi_reads: Vec<BDAddr> = self
+                    .rssi_read_in_flight
+                    .take()
+                    .into_iter()
+                    .chain(self.rssi_reads.drain(..))
+                    .collect();
+                for addr in rssi_reads {
+                    self.post_rssi_read(addr, status, 0);
+                }
+                for (_, attempt) in self.bond_attempts.drain() {
+                    if let Some(retry) = attempt.retry {
+                        retry.abort();
+                    }
+                }
+                self.cancelled_bonds.clear();
+                self.bond_checks.clear();
+                self.repair_requests.clear();
+
+                if self.factory_reset.is_some() {
+                    self.forget_config();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Forgets what is known of the remote devices and the settings made with the API, once the
+    /// adapter is off for `factory_reset`.
+    fn forget_config(&mut self) {
+        self.remote_names.clear();
+        self.remote_uuids.clear();
+        self.remote_categories.clear();
+        self.pending_bonded.clear();
+        self.connection_history.clear();
+        self.identity_addresses.clear();
+        self.unresolved_addresses.clear();
+        self.default_le_phy = None;
+        self.le_privacy = None;
+        self.rpa_timeout = None;
+        self.le_static_address = None;
+        self.blocked_devices.clear();
+        self.save_blocked_devices();
+
+        // The bonds are removed as the native stack would report it, so that every module forgets
+        // the devices. The adapter is only turned on again once they are, see
+        // `factory_reset_done`.
+        let bonded_devices = self.bonded_devices.clone();
+        let tx = self.tx.clone();
+        topstack::get_runtime().spawn(async move {
+            for addr in bonded_devices {
+                let message = Message::BluetoothBondStateChanged(
+                    0,
+                    addr.to_raw_address(),
+                    BtBondState::NotBonded,
+                );
+                let _result = tx.send(message).await;
+            }
+            let _result = tx.send(Message::BluetoothFactoryResetDone).await;
+        });
+    }
+
+    /// Ends a `factory_reset` once the removed bonds are reported, turning the adapter on again
+    /// unless it was disabled meanwhile.
+    pub(crate) fn factory_reset_done(&mut self) {
+        if let Some(true) = self.factory_reset.take() {
+            self.enable();
+        }
+    }
+
+    /// Reads the RSSI of a monitored device, once per monitoring period.
+    pub(crate) fn rssi_monitor_tick(&mut self, addr: BDAddr) {
+        if !self.rssi_monitors.contains_key(&addr) {
+            return;
+        }
+
+        // Waits for the reads of the GATT clients, a read still queued from the last period is not
+        // queued again.
+        self.read_remote_rssi(addr);
+    }
+
+    /// Reads the RSSI of a connected device for the other modules, the result is posted as
+    /// `Message::BluetoothRssiReadDone`.
+    ///
+    /// The native stack fails a read while another one is in flight, for any device, so the reads
+    /// are queued and made one at a time. A read of a device that is already queued is not
+    /// queued again.
+    pub(crate) fn read_remote_rssi(&mut self, addr: BDAddr) {
+        if self.rssi_read_in_flight != Some(addr) && !self.rssi_reads.contains(&addr) {
+            self.rssi_reads.push_back(addr);
+        }
+        self.read_next_rssi();
+    }
+
+    fn read_next_rssi(&mut self) {
+        while self.rssi_read_in_flight.is_none() {
+            let addr = match self.rssi_reads.pop_front() {
+                Some(addr) => addr,
+                None => return,
+            };
+
+            match self.acl.read_rssi(&addr.to_raw_address()) {
+                0 => {
+                    self.rssi_read_in_flight = Some(addr);
+                    self.start_operation(PendingOperation::RssiRead(addr), RSSI_READ_TIMEOUT);
+                }
+                status => self.post_rssi_read(addr, status, 0),
+            }
+        }
+    }
+
+    /// Reports the read in flight as done and starts the next one.
+    fn finish_rssi_read(&mut self, status: i32, rssi: i32) {
+        let addr = match self.rssi_read_in_flight.take() {
+            Some(addr) => addr,
+            None => return,
+        };
+
+        self.finish_operation(PendingOperation::RssiRead(addr));
+        self.post_rssi_read(addr, status, rssi);
+        self.read_next_rssi();
+    }
+
+    fn post_rssi_read(&self, addr: BDAddr, status: i32, rssi: i32) {
+        let tx = self.tx.clone();
+        topstack::get_runtime().spawn(async move {
+            let _result = tx.send(Message::BluetoothRssiReadDone(addr, status, rssi)).await;
+        });
+    }
+
+    fn stop_rssi_monitor(&mut self, addr: &BDAddr) -> bool {
+        match self.rssi_monitors.remove(addr) {
+            Some(monitor) => {
+                monitor.ticker.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether a bitmask of `LePhy::mask` values is a valid PHY preference.
+    ///
+    /// PHYs the controller doesn't support are only rejected once its features are known.
+    fn is_valid_le_phy_mask(&self, phys: u32) -> bool {
+        let all = LePhy::Le1m.mask() | LePhy::Le2m.mask() | LePhy::LeCoded.mask();
+        if phys == 0 || phys & !all != 0 {
+            return false;
+        }
+
+        let info = Controller::new().get_info();
+        if !info.is_ready {
+            return true;
+        }
+
+        (info.le_2m_phy || phys & LePhy::Le2m.mask() == 0)
+            && (info.le_coded_phy || phys & LePhy::LeCoded.mask() == 0)
+    }
+
+    /// Requests the preferred PHYs on the LE connection to a device, if there are any.
+    fn apply_le_phy(&mut self, addr: BDAddr) {
+        let phys = self.le_phy_overrides.get(&addr).or(self.default_le_phy.as_ref());
+        if let Some((tx_phys, rx_phys)) = phys.copied() {
+            self.acl.set_preferred_phy(&addr.to_raw_address(), tx_phys, rx_phys, LE_PHY_OPTIONS);
+        }
+    }
+
+    /// Reads the roles on the connections to a device, and reports those that changed.
+    fn refresh_link_roles(&mut self, addr: BDAddr) {
+        for transport in [BT_TRANSPORT_BR_EDR, BT_TRANSPORT_LE] {
+            let role = LinkRole::from(self.acl.get_link_role(&addr.to_raw_address(), transport));
+            if role == LinkRole::Unknown {
+                self.link_roles.remove(&(addr, transport));
+                continue;
+            }
+
+            if self.link_roles.insert((addr, transport), role) == Some(role) {
+                continue;
+            }
+
+            for callback in self.callbacks.iter() {
+                callback.on_link_role_changed(addr.to_string(), transport, role);
+            }
+        }
+    }
+
+    fn schedule_role_switch_check(&mut self, addr: BDAddr) {
+        let tx = self.tx.clone();
+        topstack::get_runtime().spawn(async move {
+            tokio::time::sleep(ROLE_SWITCH_CHECK_PERIOD).await;
+            let _result = tx.send(Message::BluetoothRoleSwitchCheck(addr)).await;
+        });
+    }
+
+    /// Reads the role of a BR/EDR connection being switched, until it is central or the checks
+    /// are used up.
+    pub(crate) fn role_switch_check(&mut self, addr: BDAddr) {
+        if !self.role_switches.contains_key(&addr) {
+            return;
+        }
+
+        self.refresh_link_roles(addr);
+        match self.link_roles.get(&(addr, BT_TRANSPORT_BR_EDR)).copied() {
+            Some(LinkRole::Central) => self.finish_role_switch(addr, BtStatus::Success),
+            None => self.finish_role_switch(addr, BtStatus::RemoteDeviceDown),
+            Some(_) => {
+                let checks = self.role_switches.get_mut(&addr).unwrap();
+                *checks -= 1;
+                if *checks == 0 {
+                    self.finish_role_switch(addr, BtStatus::Fail);
+                } else {
+                    self.schedule_role_switch_check(addr);
+                }
+            }
+        }
+    }
+
+    fn finish_role_switch(&mut self, addr: BDAddr, status: BtStatus) {
+        if self.role_switches.remove(&addr).is_none() {
+            return;
+        }
+
+        let role = self.link_roles.get(&(addr, BT_TRANSPORT_BR_EDR)).copied();
+        let role = role.unwrap_or(LinkRole::Unknown);
+        for callback in self.callbacks.iter() {
+            callback.on_role_switch_completed(addr.to_string(), status, role);
+        }
+    }
+
+    /// Applies the LE privacy settings made with the API.
+    fn apply_le_privacy_settings(&mut self) {
+        if let Some(enabled) = self.le_privacy {
+            self.acl.configure_le_privacy(enabled);
+        }
+        if let Some(timeout) = self.rpa_timeout {
+            self.acl.set_rpa_timeout(timeout);
+        }
+        if let Some(addr) = self.le_static_address {
+            self.acl.set_le_static_address(&addr.to_raw_address());
+        }
+    }
+
+    /// Returns the address the adapter uses over LE with LE privacy enabled or not.
+    fn le_own_address(&self, le_privacy: bool) -> LeOwnAddress {
+        if let Some(addr) = self.le_static_address {
+            return LeOwnAddress {
+                address: addr.to_string(),
+                address_type: LeOwnAddressType::StaticRandom,
+            };
+        }
+
+        if le_privacy {
+            return LeOwnAddress {
+                address: String::new(),
+                address_type: LeOwnAddressType::ResolvablePrivate,
+            };
+        }
+
+        LeOwnAddress { address: self.get_address(), address_type: LeOwnAddressType::Public }
+    }
+
+    /// Reports the address the adapter uses over LE if it changed from `previous`.
+    ///
+    /// The native stack applies the LE settings asynchronously, so the new address is derived
+    /// from the settings rather than read back.
+    fn notify_le_own_address(&self, previous: LeOwnAddress, address: LeOwnAddress) {
+        if address == previous {
+            return;
+        }
+
+        for callback in self.callbacks.iter() {
+            callback.on_le_own_address_changed(address.clone());
+        }
+    }
+
+    /// Runs an API call that needs the adapter to be on.
+    ///
+    /// The call is queued if the adapter is turning on. Returns the status of the call,
+    /// `BtStatus::Success` if it is queued or `BtStatus::NotReady` if the adapter is neither on nor
+    /// turning on. The caller no longer gets the status of a queued call, so `fail` reports it
+    /// through the callbacks if the call fails once the adapter is on or is dropped because the
+    /// adapter turned off.
+    fn run_when_on<F, E>(&mut self, call: F, fail: E) -> BtStatus
+    where
+        F: FnOnce(&mut Bluetooth) -> BtStatus + Send + 'static,
+        E: FnOnce(&mut Bluetooth, BtStatus) + Send + 'static,
+    {
+        match self.state {
+            AdapterState::On => call(self),
+            AdapterState::TurningOn => {
+                self.pending_calls.push(PendingCall { run: Box::new(call), fail: Box::new(fail) });
+                BtStatus::Success
+            }
+            _ => BtStatus::NotReady,
+        }
+    }
+
+    /// Bonds with a remote device as `IBluetooth::create_bond` does, and returns a completion
+    /// resolved with the status and bond state that end the bonding.
+    pub fn create_bond_with_completion(
+        &mut self,
+        device: String,
+        transport: i32,
+    ) -> Result<Completion<(BondStatus, BtBondState)>, BtStatus> {
+        let addr = BDAddr::from_string(&device).ok_or(BtStatus::InvalidParam)?;
+        match self.create_bond(device, transport) {
+            BtStatus::Success => Ok(self.bond_completions.add(addr)),
+            status => Err(status),
+        }
+    }
+
+    /// Reads the UUIDs of a remote device as `IBluetooth::fetch_remote_uuids` does, and returns a
+    /// completion resolved with the UUIDs read, even if they didn't change.
+    pub fn fetch_remote_uuids_with_completion(
+        &mut self,
+        device: String,
+    ) -> Result<Completion<Vec<Uuid128>>, BtStatus> {
+        let addr = BDAddr::from_string(&device).ok_or(BtStatus::InvalidParam)?;
+        match self.fetch_remote_uuids(device) {
+            BtStatus::Success => Ok(self.uuid_completions.add(addr)),
+            status => Err(status),
+        }
+    }
+}
+
+#[btif_callbacks_generator(btif_bluetooth_callbacks, BluetoothCallbacks)]
+pub(crate) trait BtifBluetoothCallbacks {
+    #[stack_message(BluetoothAdapterStateChanged)]
+    fn adapter_state_changed(&mut self, state: BtState);
+
+    #[stack_message(BluetoothAdapterPropertiesChanged)]
+    fn adapter_properties_changed(
+        &mut self,
+        status: i32,
+        num_properties: i32,
+        properties: Vec<ffi::BtProperty>,
+    );
+
+    #[stack_message(BluetoothRemoteDevicePropertiesChanged)]
+    fn remote_device_properties_changed(
+        &mut self,
+        status: i32,
+        addr: ffi::RustRawAddress,
+        num_properties: i32,
+        properties: Vec<ffi::BtProperty>,
+    );
+
+    #[stack_message(BluetoothDeviceFound)]
+    fn device_found(&mut self, num_properties: i32, properties: Vec<ffi::BtProperty>);
+
+    #[stack_message(BluetoothDiscoveryStateChanged)]
+    fn discovery_state_changed(&mut self, state: BtDiscoveryState);
+
+    #[stack_message(BluetoothPinRequest)]
+    fn pin_request(
+        &mut self,
+        addr: ffi::RustRawAddress,
+        name: String,
+        cod: u32,
+        min_16_digit: bool,
+    );
+
+    #[stack_message(BluetoothSspRequest)]
+    fn ssp_request(
+        &mut self,
+        addr: ffi::RustRawAddress,
+        name: String,
+        cod: u32,
+        variant: BtSspVariant,
+        passkey: u32,
+    );
+
+    #[stack_message(BluetoothBondStateChanged)]
+    fn bond_state_changed(&mut self, status: i32, addr: ffi::RustRawAddress, state: BtBondState);
+
+    #[stack_message(BluetoothAclStateChanged)]
+    fn acl_state_changed(
+        &mut self,
+        status: i32,
+        addr: ffi::RustRawAddress,
+        state: BtAclState,
+        hci_reason: i32,
+    );
+
+    #[stack_message(BluetoothLocalOobDataGenerated)]
+    fn local_oob_data_generated(&mut self, transport: i32, oob_data: ffi::BtOutOfBandData);
+}
+
+#[btif_callbacks_generator(btif_acl_callbacks, AclCallbacks)]
+pub(crate) trait BtifAclCallbacks {
+    #[stack_message(BluetoothRemoteRssiRead)]
+    fn read_rssi(&mut self, addr: ffi::RustRawAddress, status: i32, rssi: i32);
+
+    #[stack_message(BluetoothLePhyRead)]
+    fn read_phy(&mut self, addr: ffi::RustRawAddress, status: i32, tx_phy: u8, rx_phy: u8);
+
+    #[stack_message(BluetoothRoleSwitchRequested)]
+    fn switch_role(&mut self, addr: ffi::RustRawAddress, status: i32);
+}
+
+impl BtifAclCallbacks for Bluetooth {
+    fn read_rssi(&mut self, addr: ffi::RustRawAddress, status: i32, rssi: i32) {
+        // A read that timed out in the native stack is reported without the device address.
+        let timed_out = addr.address == [0; 6];
+        let addr = BDAddr::from_raw_address(&addr);
+        if timed_out || self.rssi_read_in_flight == Some(addr) {
+            self.finish_rssi_read(status, rssi);
+        }
+
+        if status != 0 {
+            return;
+        }
+
+        let monitor = match self.rssi_monitors.get_mut(&addr) {
+            Some(monitor) => monitor,
+            None => return,
+        };
+
+        let near = if rssi >= monitor.settings.high_threshold {
+            true
+        } else if rssi <= monitor.settings.low_threshold {
+            false
+        } else {
+            return;
+        };
+
+        if monitor.near == Some(near) {
+            return;
+        }
+
+        monitor.near = Some(near);
+        for callback in self.callbacks.iter() {
+            callback.on_rssi_changed(addr.to_string(), rssi, near);
+        }
+    }
+
+    fn read_phy(&mut self, addr: ffi::RustRawAddress, status: i32, tx_phy: u8, rx_phy: u8) {
+        // The read fails for BR/EDR connections.
+        if status != 0 {
+            return;
+        }
+
+        let addr = BDAddr::from_raw_address(&addr);
+        for callback in self.callbacks.iter() {
+            callback.on_le_phy_changed(addr.to_string(), tx_phy.into(), rx_phy.into());
+        }
+
+        self.apply_le_phy(addr);
+    }
+
+    fn switch_role(&mut self, addr: ffi::RustRawAddress, status: i32) {
+        let addr = BDAddr::from_raw_address(&addr);
+        match status {
+            BTM_SUCCESS => {
+                self.refresh_link_roles(addr);
+                self.finish_role_switch(addr, BtStatus::Success);
+            }
+            BTM_CMD_STARTED => self.schedule_role_switch_check(addr),
+            BTM_BUSY => self.finish_role_switch(addr, BtStatus::Busy),
+            BTM_MODE_UNSUPPORTED => self.finish_role_switch(addr, BtStatus::Unsupported),
+            BTM_UNKNOWN_ADDR => self.finish_role_switch(addr, BtStatus::RemoteDeviceDown),
+            _ => self.finish_role_switch(addr, BtStatus::Fail),
+        }
+    }
+}
+
+#[derive(FromPrimitive, ToPrimitive, PartialEq, PartialOrd)]
+#[repr(i32)]
+#[derive(Debug)]
+enum PropertyType {
+    BDName = 0x01,
+    BDAddr,
+    Uuids,
+    ClassOfDevice,
+    TypeOfDevice,
+    ServiceRecord,
+    AdapterScanMode,
+    AdapterBondedDevices,
+    AdapterDiscoverableTimeout,
+    RemoteFriendlyName,
+    RemoteRssi,
+    RemoteVersionInfo,
+    RemoteLocalLeFeatures,
+    LocalIoCaps,
+    LocalIoCapsBle,
+    RemoteDynamicAudioBuffer = 0x10,
+    Unknown = 0x100,
+}
+
+/// Returns whether `addr` is a static random address: its two most significant bits are set, and
+/// its other bits are neither all 0 nor all 1.
+fn is_static_random_address(addr: &BDAddr) -> bool {
+    const RANDOM_BITS: u64 = (1 << 46) - 1;
+
+    let value = addr.val.iter().fold(0u64, |value, byte| value << 8 | *byte as u64);
+    let random = value & RANDOM_BITS;
+    value >> 46 == 0b11 && random != 0 && random != RANDOM_BITS
+}
+
+/// Parses the value of a `PropertyType::Uuids` property.
+fn parse_uuids(raw: &[u8]) -> Vec<Uuid128> {
+    raw.chunks_exact(16).map(|uu| Uuid128::from_bytes(uu.try_into().unwrap())).collect()
+}
+
+impl BtifBluetoothCallbacks for Bluetooth {
+    fn adapter_state_changed(&mut self, state: BtState) {
+        match state {
+            BtState::On => self.set_state(AdapterState::On),
+            BtState::Off => self.set_state(AdapterState::Off),
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn adapter_properties_changed(
+        &mut self,
+        status: i32,
+        num_properties: i32,
+        properties: Vec<ffi::BtProperty>,
+    ) {
+        if status != 0 {
+            return;
+        }
+
+        for prop in properties {
+            let prop_type = PropertyType::from_i32(prop.prop_type);
+
+            if prop_type.is_none() {
+                continue;
+            }
+
+            match prop_type.unwrap() {
+                PropertyType::BDAddr => {
+                    self.update_local_address(&prop.val);
+                }
+                PropertyType::AdapterBondedDevices => {
+                    self.update_bonded_devices(&prop.val);
+                    self.finish_bond_checks();
+                }
+                PropertyType::AdapterScanMode => {
+                    self.update_scan_mode(&prop.val);
+                }
+                PropertyType::Uuids => {
+                    self.local_uuids = parse_uuids(&prop.val);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn remote_device_properties_changed(
+        &mut self,
+        status: i32,
+        addr: ffi::RustRawAddress,
+        num_properties: i32,
+        properties: Vec<ffi::BtProperty>,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+
+        if status == 0 {
+            for prop in properties {
+                match PropertyType::from_i32(prop.prop_type) {
+                    Some(PropertyType::BDName) | Some(PropertyType::RemoteFriendlyName) => {
+                        let name = prop.val.split(|x| *x == 0).next().unwrap_or(&[]);
+                        if !name.is_empty() {
+                            self.remote_names
+                                .insert(addr, String::from_utf8_lossy(name).into_owned());
+                        }
+                    }
+                    Some(PropertyType::Uuids) => {
+                        self.update_remote_uuids(addr, &prop.val);
+                    }
+                    Some(PropertyType::ClassOfDevice) => {
+                        if let Ok(cod) = prop.val[..].try_into() {
+                            let cod = u32::from_ne_bytes(cod);
+                            self.update_remote_category(
+                                addr,
+                                DeviceCategory::from_class_of_device(cod),
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // A failed read won't be retried, so there is no point in waiting any longer.
+        if status != 0 || self.remote_names.contains_key(&addr) {
+            self.flush_pending_bonded(addr);
+        }
+    }
+
+    fn device_found(&mut self, _num_properties: i32, properties: Vec<ffi::BtProperty>) {
+        let mut addr = None;
+        let mut name = String::new();
+        let mut class_of_device = 0;
+        let mut device_type = None;
+        let mut rssi = 0;
+
+        for prop in properties {
+            match PropertyType::from_i32(prop.prop_type) {
+                Some(PropertyType::BDAddr) if prop.val.len() == 6 => {
+                    addr = Some(BDAddr::from_byte_vec(&prop.val));
+                }
+                Some(PropertyType::BDName) => {
+                    let raw = prop.val.split(|x| *x == 0).next().unwrap_or(&[]);
+                    name = String::from_utf8_lossy(raw).into_owned();
+                }
+                Some(PropertyType::ClassOfDevice) => {
+                    if let Ok(cod) = prop.val[..].try_into() {
+                        class_of_device = u32::from_ne_bytes(cod);
+                    }
+                }
+                Some(PropertyType::TypeOfDevice) => {
+                    if let Ok(raw) = prop.val[..].try_into() {
+                        device_type = Some(u32::from_ne_bytes(raw));
+                    }
+                }
+                Some(PropertyType::RemoteRssi) => {
+                    rssi = prop.val.first().map_or(0, |rssi| *rssi as i8 as i32);
+                }
+                _ => {}
+            }
+        }
+
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return,
+        };
+
+        if !name.is_empty() {
+            self.remote_names.insert(addr, name.clone());
+        }
+        if class_of_device != 0 {
+            self.update_remote_category(
+                addr,
+                DeviceCategory::from_class_of_device(class_of_device),
+            );
+        }
+
+        for (id, callback) in self.callbacks.iter_with_ids() {
+            let passes = self
+                .discovery_filters
+                .get(&id)
+                .map_or(true, |filter| filter.matches(class_of_device, device_type, &name));
+            if passes {
+                callback.on_device_found(addr.to_string(), name.clone(), class_of_device, rssi);
+            }
+        }
+    }
+
+    fn discovery_state_changed(&mut self, state: BtDiscoveryState) {
+        self.set_discovering(state == BtDiscoveryState::Started);
+    }
+
+    fn pin_request(
+        &mut self,
+        addr: ffi::RustRawAddress,
+        name: String,
+        cod: u32,
+        min_16_digit: bool,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+
+        if self.rejects_incoming_pairing(&addr, cod) {
+            eprintln!("Rejecting the incoming pairing of {}", addr.to_string());
+            self.reply_to_pairing_request(addr, None, false, 0, &[]);
+            return;
+        }
+
+        match self.find_pairing_agent(None) {
+            Some(id) => {
+                self.pairing_requests.insert(addr, PairingRequest { variant: None, agent: id });
+                if let Some(agent) = self.pairing_agents.get_by_id(id) {
+                    agent.on_pin_request(addr.to_string(), name, min_16_digit);
+                }
+            }
+            None => {
+                self.reply_to_pairing_request(addr, None, false, 0, &[]);
+            }
+        }
+    }
+
+    fn ssp_request(
+        &mut self,
+        addr: ffi::RustRawAddress,
+        name: String,
+        cod: u32,
+        variant: BtSspVariant,
+        passkey: u32,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+
+        if self.rejects_incoming_pairing(&addr, cod) {
+            eprintln!("Rejecting the incoming pairing of {}", addr.to_string());
+            // Notifications expect no answer, so the bond is cancelled instead.
+            if variant == BtSspVariant::PasskeyNotification {
+                self.intf.lock().unwrap().cancel_bond(&addr.to_raw_address());
+            } else {
+                self.reply_to_pairing_request(addr, Some(variant), false, 0, &[]);
+            }
+            return;
+        }
+
+        match self.find_pairing_agent(Some(variant)) {
+            Some(id) => {
+                // Notifications are only shown, the remote device answers them.
+                if variant != BtSspVariant::PasskeyNotification {
+                    let request = PairingRequest { variant: Some(variant), agent: id };
+                    self.pairing_requests.insert(addr, request);
+                }
+                if let Some(agent) = self.pairing_agents.get_by_id(id) {
+                    agent.on_ssp_request(addr.to_string(), name, variant, passkey);
+                }
+            }
+            // Without an agent, only the pairings that need no user input are accepted.
+            None if variant != BtSspVariant::PasskeyNotification => {
+                let accept = variant == BtSspVariant::Consent;
+                self.reply_to_pairing_request(addr, Some(variant), accept, 0, &[]);
+            }
+            None => {}
+        }
+    }
+
+    fn bond_state_changed(&mut self, status: i32, addr: ffi::RustRawAddress, state: BtBondState) {
+        let addr = BDAddr::from_raw_address(&addr);
+
+        // A request the agent has not answered yet is void once the pairing is over.
+        if state != BtBondState::Bonding {
+            self.pairing_requests.remove(&addr);
+        }
+
+        // Progress restarts the timeout, as pairing may wait for the user at each step. Bonds
+        // started by the remote device are timed out too, so that they can be cancelled.
+        if status == 0 && state == BtBondState::Bonding {
+            self.cancelled_bonds.remove(&addr);
+            self.start_operation(PendingOperation::Bond(addr), BOND_TIMEOUT);
+        } else {
+            self.finish_operation(PendingOperation::Bond(addr));
+        }
+
+        if status != 0 {
+            if self.cancelled_bonds.remove(&addr) {
+                return;
+            }
+
+            self.record_connection_event(addr, ConnectionEventType::BondingFailed, status, 0);
+
+            let status = BtStatus::from(status);
+            if status == BtStatus::RemoteDeviceDown && self.schedule_bond_retry(addr) {
+                return;
+            }
+            self.bond_attempts.remove(&addr);
+
+            // The native stack only removes the bond for some of the failures it reports, e.g.
+            // not when the user of the remote device rejects the pairing, so the bonded devices
+            // are read back before reporting the failure of a bonded device.
+            if state == BtBondState::NotBonded && self.bonded_devices.contains(&addr) {
+                self.bond_checks.insert(addr, status);
+                self.intf
+                    .lock()
+                    .unwrap()
+                    .get_adapter_property(PropertyType::AdapterBondedDevices.to_i32().unwrap());
+                return;
+            }
+
+            self.notify_bond_state(BondStatus::from(status), addr, state);
+            return;
+        }
+
+        if state != BtBondState::Bonding {
+            self.bond_attempts.remove(&addr);
+        }
+
+        match state {
+            BtBondState::Bonded => {
+                if !self.bonded_devices.contains(&addr) {
+                    self.bonded_devices.push(addr);
+                }
+
+                // The addresses the device used before may resolve with its keys now.
+                self.unresolved_addresses.clear();
+
+                // The name is usually reported while pairing. Otherwise hold the bonded state back
+                // until it is read from storage so UIs don't list the device by its address.
+                if !self.remote_names.contains_key(&addr) {
+                    self.pending_bonded.insert(addr);
+                    self.intf.lock().unwrap().get_remote_device_property(
+                        &addr.to_raw_address(),
+                        PropertyType::BDName.to_i32().unwrap(),
+                    );
+
+                    let tx = self.tx.clone();
+                    topstack::get_runtime().spawn(async move {
+                        tokio::time::sleep(BONDED_NAME_TIMEOUT).await;
+                        let _result = tx.send(Message::BluetoothBondedNameTimeout(addr)).await;
+                    });
+                    return;
+                }
+            }
+            BtBondState::NotBonded => {
+                self.bonded_devices.retain(|x| *x != addr);
+                self.pending_bonded.remove(&addr);
+                self.identity_addresses.retain(|_, identity| *identity != addr);
+            }
+            BtBondState::Bonding => {
+                self.record_connection_event(addr, ConnectionEventType::BondingStarted, 0, 0);
+            }
+        }
+
+        self.notify_bond_state(BondStatus::Success, addr, state);
+    }
+
+    fn acl_state_changed(
+        &mut self,
+        status: i32,
+        addr: ffi::RustRawAddress,
+        state: BtAclState,
+        hci_reason: i32,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+
+        match state {
+            BtAclState::Connected => {
+                self.record_connection_event(addr, ConnectionEventType::Connected, status, 0);
+
+                for callback in self.callbacks.iter() {
+                    callback.on_device_connected(addr.to_string());
+                }
+                self.resolve_identity_address(addr);
+                self.refresh_link_roles(addr);
+
+                // Tells LE connections apart, the preferred PHYs are requested once it is read.
+                self.acl.read_phy(&addr.to_raw_address());
+
+                // Dropped once reported, so that clients see the connection go down as usual.
+                if self.is_device_blocked(&addr) {
+                    println!("Disconnecting blocked device {}", addr.to_string());
+                    self.acl.disconnect(&addr.to_raw_address());
+                }
+            }
+            BtAclState::Disconnected => {
+                self.record_connection_event(
+                    addr,
+                    ConnectionEventType::Disconnected,
+                    status,
+                    hci_reason,
+                );
+                self.stop_rssi_monitor(&addr);
+                self.le_phy_overrides.remove(&addr);
+                self.refresh_link_roles(addr);
+
+                for callback in self.callbacks.iter() {
+                    callback.on_device_disconnected(
+                        addr.to_string(),
+                        BtStatus::from(status),
+                        hci_reason,
+                    );
+                }
+            }
+        }
+    }
+
+    fn local_oob_data_generated(&mut self, transport: i32, oob_data: ffi::BtOutOfBandData) {
+        self.finish_operation(PendingOperation::LocalOobData(transport));
+
+        for callback in self.callbacks.iter() {
+            callback.on_local_oob_data_generated(transport, oob_data.clone());
+        }
+    }
+}
+
+// TODO: Add unit tests for this implementation
+impl IBluetooth for Bluetooth {
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothCallback + Send>) -> u32 {
+        self.callbacks.add_callback(callback)
+    }
+
+    fn enable(&mut self) -> BtStatus {
+        match self.state {
+            AdapterState::Off => (),
+            AdapterState::TurningOn | AdapterState::On => return BtStatus::Success,
+            AdapterState::TurningOff => return BtStatus::Busy,
+        }
+
+        let status = BtStatus::from(self.intf.lock().unwrap().enable());
+        if status != BtStatus::Success {
+            return status;
+        }
+
+        self.set_state(AdapterState::TurningOn);
+        BtStatus::Success
+    }
+
+    fn disable(&mut self) -> BtStatus {
+        if let Some(restart) = &mut self.factory_reset {
+            *restart = false;
+        }
+
+        match self.state {
+            AdapterState::On => (),
+            AdapterState::Off => return BtStatus::Success,
+            AdapterState::TurningOn | AdapterState::TurningOff => return BtStatus::Busy,
+        }
+
+        let status = BtStatus::from(self.intf.lock().unwrap().disable());
+        if status != BtStatus::Success {
+            return status;
+        }
+
+        self.set_state(AdapterState::TurningOff);
+        BtStatus::Success
+    }
+
+    fn factory_reset(&mut self) -> BtStatus {
+        if self.state != AdapterState::On {
+            return BtStatus::NotReady;
+        }
+
+        // The native config can only be cleared while the stack is up, with the GD stack.
+        let status = BtStatus::from(self.intf.lock().unwrap().config_clear());
+        if status != BtStatus::Success {
+            return status;
+        }
+
+        let status = self.disable();
+        if status != BtStatus::Success {
+            return status;
+        }
+
+        self.factory_reset = Some(true);
+        BtStatus::Success
+    }
+
+    fn get_state(&self) -> u32 {
+        self.state.to_u32().unwrap()
+    }
+
+    fn get_stack_version(&self) -> String {
+        String::from(STACK_VERSION)
+    }
+
+    fn get_address(&self) -> String {
+        match self.local_address {
+            None => String::from(""),
+            Some(addr) => addr.to_string(),
+        }
+    }
+
+    fn get_connection_state(&self, device: String) -> u32 {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return 0,
+        };
+
+        self.intf.lock().unwrap().get_connection_state(&addr.to_raw_address()) as u32
+    }
+
+    fn get_remote_name(&self, device: String) -> String {
+        BDAddr::from_string(&device)
+            .and_then(|addr| self.remote_names.get(&addr).cloned())
+            .unwrap_or_default()
+    }
+
+    fn get_uuids(&self) -> Vec<Uuid128> {
+        self.local_uuids.clone()
+    }
+
+    fn get_remote_uuids(&self, device: String) -> Vec<Uuid128> {
+        BDAddr::from_string(&device)
+            .and_then(|addr| self.remote_uuids.get(&addr).cloned())
+            .unwrap_or_default()
+    }
+
+    fn get_remote_category(&self, device: String) -> DeviceCategory {
+        BDAddr::from_string(&device)
+            .and_then(|addr| self.remote_categories.get(&addr).copied())
+            .unwrap_or(DeviceCategory::Unknown)
+    }
+
+    fn get_security_level(&self, device: String) -> SecurityLevel {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr.to_raw_address(),
+            None => return SecurityLevel::default(),
+        };
+
+        let (le, security) = match self.acl.get_link_security(&addr, BT_TRANSPORT_BR_EDR) {
+            security if security.connected => (false, security),
+            _ => (true, self.acl.get_link_security(&addr, BT_TRANSPORT_LE)),
+        };
+
+        if !security.connected {
+            return SecurityLevel::default();
+        }
+
+        SecurityLevel {
+            connected: true,
+            le,
+            encrypted: security.encrypted,
+            authenticated: security.authenticated,
+            secure_connections: security.secure_connections,
+        }
+    }
+
+    fn get_identity_address(&mut self, device: String) -> String {
+        BDAddr::from_string(&device)
+            .and_then(|addr| self.resolve_identity_address(addr))
+            .map(|identity| identity.to_string())
+            .unwrap_or_default()
+    }
+
+    fn fetch_remote_uuids(&mut self, device: String) -> BtStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return BtStatus::InvalidParam,
+        };
+
+        if self.state != AdapterState::On {
+            return BtStatus::NotReady;
+        }
+
+        BtStatus::from(self.intf.lock().unwrap().get_remote_services(&addr.to_raw_address()))
+    }
+
+    fn generate_local_oob_data(&mut self, transport: i32) -> BtStatus {
+        self.run_when_on(
+            move |bluetooth| {
+                let status = BtStatus::from(
+                    bluetooth.intf.lock().unwrap().generate_local_oob_data(transport),
+                );
+                if status != BtStatus::Success {
+                    return status;
+                }
+
+                bluetooth.start_operation(
+                    PendingOperation::LocalOobData(transport),
+                    LOCAL_OOB_DATA_TIMEOUT,
+                );
+                BtStatus::Success
+            },
+            move |bluetooth, _| bluetooth.notify_local_oob_data_failed(transport),
+        )
+    }
+
+    fn create_bond(&mut self, device: String, transport: i32) -> BtStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return BtStatus::InvalidParam,
+        };
+
+        self.run_when_on(
+            move |bluetooth| {
+                let attempt = BondAttempt { transport, oob_data: None, retries: 0, retry: None };
+                bluetooth.bond_attempts.insert(addr, attempt);
+                bluetooth.start_bond(addr)
+            },
+            move |bluetooth, status| bluetooth.notify_bond_not_started(addr, status),
+        )
+    }
+
+    fn create_bond_out_of_band(
+        &mut self,
+        device: String,
+        transport: i32,
+        p192_data: ffi::BtOutOfBandData,
+        p256_data: ffi::BtOutOfBandData,
+    ) -> BtStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return BtStatus::InvalidParam,
+        };
+
+        self.run_when_on(
+            move |bluetooth| {
+                let oob_data = Some((p192_data, p256_data));
+                let attempt = BondAttempt { transport, oob_data, retries: 0, retry: None };
+                bluetooth.bond_attempts.insert(addr, attempt);
+                bluetooth.start_bond(addr)
+            },
+            move |bluetooth, status| bluetooth.notify_bond_not_started(addr, status),
+        )
+    }
+
+    fn cancel_bond_process(&mut self, device: String) -> bool {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+        let retry = self.bond_attempts.remove(&addr).and_then(|attempt| attempt.retry);
+        let bonding = self.finish_operation(PendingOperation::Bond(addr));
+        match retry {
+            Some(retry) => retry.abort(),
+            None if !bonding => return false,
+            None => {}
+        }
+
+        if bonding {
+            self.intf.lock().unwrap().cancel_bond(&addr.to_raw_address());
+            self.cancelled_bonds.insert(addr);
+        }
+
+        let status = BtStatus::Fail.to_i32().unwrap();
+        self.record_connection_event(addr, ConnectionEventType::BondingFailed, status, 0);
+        self.notify_bond_state(BondStatus::Cancelled, addr, BtBondState::NotBonded);
+        true
+    }
+
+    fn register_pairing_agent(
+        &mut self,
+        agent: Box<dyn IBluetoothPairingAgent + Send>,
+        capability: IoCapability,
+    ) -> u32 {
+        let id = self.pairing_agents.add_callback(agent);
+        self.pairing_agent_capabilities.push((id, capability));
+
+        if self.state == AdapterState::On {
+            self.apply_io_capability();
+        }
+        id
+    }
+
+    fn unregister_pairing_agent(&mut self, id: u32) -> bool {
+        if !self.pairing_agents.remove_callback(id) {
+            return false;
+        }
+        self.pairing_agent_capabilities.retain(|(agent, _)| *agent != id);
+
+        let unanswered: Vec<BDAddr> = self
+            .pairing_requests
+            .iter()
+            .filter(|(_, request)| request.agent == id)
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in unanswered {
+            if let Some(request) = self.pairing_requests.remove(&addr) {
+                self.reply_to_pairing_request(addr, request.variant, false, 0, &[]);
+            }
+        }
+        self.repair_requests.retain(|_, agent| *agent != id);
+
+        if self.state == AdapterState::On {
+            self.apply_io_capability();
+        }
+        true
+    }
+
+    fn set_pairing_confirmation(&mut self, device: String, accept: bool) -> BtStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return BtStatus::InvalidParam,
+        };
+
+        let variant = self.take_pairing_request(addr, |variant| {
+            variant == Some(BtSspVariant::PasskeyConfirmation)
+                || variant == Some(BtSspVariant::Consent)
+        });
+        match variant {
+            Some(variant) => self.reply_to_pairing_request(addr, variant, accept, 0, &[]),
+            None => BtStatus::Fail,
+        }
+    }
+
+    fn set_repair_confirmation(&mut self, device: String, accept: bool) -> BtStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return BtStatus::InvalidParam,
+        };
+
+        if self.repair_requests.remove(&addr).is_none() {
+            return BtStatus::Fail;
+        }
+        if !accept {
+            return BtStatus::Success;
+        }
+        self.create_bond(device, BT_TRANSPORT_AUTO)
+    }
+
+    fn set_passkey(&mut self, device: String, accept: bool, passkey: u32) -> BtStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return BtStatus::InvalidParam,
+        };
+        if accept && passkey > SSP_PASSKEY_MAX {
+            return BtStatus::InvalidParam;
+        }
+
+        let variant =
+            self.take_pairing_request(addr, |variant| variant == Some(BtSspVariant::PasskeyEntry));
+        match variant {
+            Some(variant) => self.reply_to_pairing_request(addr, variant, accept, passkey, &[]),
+            None => BtStatus::Fail,
+        }
+    }
+
+    fn set_pin(&mut self, device: String, accept: bool, pin: Vec<u8>) -> BtStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return BtStatus::InvalidParam,
+        };
+        if accept && (pin.len() < PIN_LEN_MIN || pin.len() > PIN_LEN_MAX) {
+            return BtStatus::InvalidParam;
+        }
+
+        match self.take_pairing_request(addr, |variant| variant.is_none()) {
+            Some(variant) => self.reply_to_pairing_request(addr, variant, accept, 0, &pin),
+            None => BtStatus::Fail,
+        }
+    }
+
+    fn set_discoverable(&mut self, mode: bool, duration: u32) -> BtStatus {
+        self.run_when_on(
+            move |bluetooth| bluetooth.apply_discoverable(mode, duration),
+            |bluetooth, _| bluetooth.notify_discoverable(),
+        )
+    }
+
+    fn get_discoverable(&self) -> bool {
+        self.discoverable
+    }
+
+    fn set_pairable(&mut self, mode: bool, timeout: u32) {
+        self.cancel_pairable_timeout();
+        self.apply_pairable(mode);
+
+        if mode && timeout > 0 {
+            let tx = self.tx.clone();
+            self.pairable_timeout_id = self.pairable_timeout_id.wrapping_add(1);
+            let id = self.pairable_timeout_id;
+            self.pairable_timeout = Some(topstack::get_runtime().spawn(async move {
+                tokio::time::sleep(Duration::from_secs(timeout.into())).await;
+                let _result = tx.send(Message::BluetoothPairableTimeout(id)).await;
+            }));
+        }
+    }
+
+    fn get_pairable(&self) -> bool {
+        self.pairable
+    }
+
+    fn set_pairing_blocked_classes(&mut self, major_classes: u32) {
+        self.pairing_blocked_classes = major_classes;
+    }
+
+    fn block_device(&mut self, device: String) -> bool {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+        if self.blocked_devices.insert(addr) {
+            self.save_blocked_devices();
+        }
+
+        // The device may be connected over LE with one of its resolvable private addresses.
+        let mut addrs: Vec<BDAddr> = self
+            .identity_addresses
+            .iter()
+            .filter(|(_, identity)| **identity == addr)
+            .map(|(rpa, _)| *rpa)
+            .collect();
+        addrs.push(addr);
+        for connected in addrs {
+            self.acl.disconnect(&connected.to_raw_address());
+        }
+
+        // Stops the connections the other modules make to the device in the background.
+        let tx = self.tx.clone();
+        topstack::get_runtime().spawn(async move {
+            let _result = tx.send(Message::BluetoothDeviceBlocked(addr)).await;
+        });
+
+        true
+    }
+
+    fn unblock_device(&mut self, device: String) -> bool {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+        if !self.blocked_devices.remove(&addr) {
+            return false;
+        }
+        self.save_blocked_devices();
+
+        // The HOGP host forgot the device when it was blocked.
+        let tx = self.tx.clone();
+        topstack::get_runtime().spawn(async move {
+            let _result = tx.send(Message::BluetoothDeviceUnblocked(addr)).await;
+        });
+
+        true
+    }
+
+    fn get_blocked_devices(&self) -> Vec<String> {
+        let mut addrs: Vec<String> =
+            self.blocked_devices.iter().map(|addr| addr.to_string()).collect();
+        addrs.sort();
+        addrs
+    }
+
+    fn start_discovery(&mut self, callback_id: u32, filter: DiscoveryFilter) -> BtStatus {
+        match filter.transport {
+            0 | BT_TRANSPORT_BR_EDR | BT_TRANSPORT_LE => {}
+            _ => return BtStatus::InvalidParam,
+        }
+
+        if self.callbacks.get_by_id(callback_id).is_none() {
+            return BtStatus::InvalidParam;
+        }
+
+        if self.state != AdapterState::On {
+            return BtStatus::NotReady;
+        }
+
+        // The filter of the previous call is replaced before the first device is found.
+        let discovering = self.discovering;
+        self.discovery_filters.insert(callback_id, filter);
+        if discovering {
+            return BtStatus::Success;
+        }
+
+        BtStatus::from(self.intf.lock().unwrap().start_discovery())
+    }
+
+    fn cancel_discovery(&mut self) -> BtStatus {
+        if self.state != AdapterState::On {
+            return BtStatus::NotReady;
+        }
+
+        BtStatus::from(self.intf.lock().unwrap().cancel_discovery())
+    }
+
+    fn is_discovering(&self) -> bool {
+        self.discovering
+    }
+
+    fn get_controller_info(&self) -> ControllerInfo {
+        let info = Controller::new().get_info();
+
+        ControllerInfo {
+            is_ready: info.is_ready,
+            hci_version: info.hci_version.into(),
+            hci_revision: info.hci_revision.into(),
+            lmp_version: info.lmp_version.into(),
+            manufacturer: info.manufacturer.into(),
+            lmp_subversion: info.lmp_subversion.into(),
+            le_supported: info.le_supported,
+            le_2m_phy: info.le_2m_phy,
+            le_coded_phy: info.le_coded_phy,
+            le_extended_advertising: info.le_extended_advertising,
+            le_periodic_advertising: info.le_periodic_advertising,
+            le_max_advertising_data_length: info.le_max_advertising_data_length.into(),
+            le_number_of_advertising_sets: info.le_number_of_advertising_sets.into(),
+        }
+    }
+
+    fn start_rssi_monitoring(
+        &mut self,
+        device: String,
+        period_ms: u32,
+        settings: RSSISettings,
+    ) -> BtStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return BtStatus::InvalidParam,
+        };
+
+        if period_ms < RSSI_MONITORING_MIN_PERIOD_MS
+            || settings.low_threshold >= settings.high_threshold
+        {
+            return BtStatus::InvalidParam;
+        }
+
+        if self.intf.lock().unwrap().get_connection_state(&addr.to_raw_address()) == 0 {
+            return BtStatus::RemoteDeviceDown;
+        }
+
+        self.stop_rssi_monitor(&addr);
+
+        let tx = self.tx.clone();
+        let ticker = topstack::get_runtime().spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(period_ms.into()));
+            loop {
+                interval.tick().await;
+                if tx.send(Message::BluetoothRssiMonitorTick(addr)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.rssi_monitors.insert(addr, RssiMonitor { settings, near: None, ticker });
+        BtStatus::Success
+    }
+
+    fn stop_rssi_monitoring(&mut self, device: String) -> bool {
+        match BDAddr::from_string(&device) {
+            Some(addr) => self.stop_rssi_monitor(&addr),
+            None => false,
+        }
+    }
+
+    fn set_default_le_phy(&mut self, tx_phys: u32, rx_phys: u32) -> BtStatus {
+        if !self.is_valid_le_phy_mask(tx_phys) || !self.is_valid_le_phy_mask(rx_phys) {
+            return BtStatus::Unsupported;
+        }
+
+        self.default_le_phy = Some((tx_phys as u8, rx_phys as u8));
+        BtStatus::Success
+    }
+
+    fn set_le_phy(&mut self, device: String, tx_phys: u32, rx_phys: u32) -> BtStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return BtStatus::InvalidParam,
+        };
+
+        if !self.is_valid_le_phy_mask(tx_phys) || !self.is_valid_le_phy_mask(rx_phys) {
+            return BtStatus::Unsupported;
+        }
+
+        if self.intf.lock().unwrap().get_connection_state(&addr.to_raw_address()) == 0 {
+            return BtStatus::RemoteDeviceDown;
+        }
+
+        self.le_phy_overrides.insert(addr, (tx_phys as u8, rx_phys as u8));
+        self.apply_le_phy(addr);
+        BtStatus::Success
+    }
+
+    fn set_le_connection_preset(&mut self, device: String, preset: LeConnectionPreset) -> BtStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr.to_raw_address(),
+            None => return BtStatus::InvalidParam,
+        };
+
+        if self.intf.lock().unwrap().get_connection_state(&addr) == 0 {
+            return BtStatus::RemoteDeviceDown;
+        }
+
+        let params = preset.parameters();
+        let status = BtStatus::from(self.acl.update_connection_parameters(
+            &addr,
+            params.min_interval,
+            params.max_interval,
+            params.latency,
+            params.timeout,
+        ));
+        if status != BtStatus::Success {
+            return status;
+        }
+
+        BtStatus::from(self.acl.set_data_length(&addr, params.tx_pdu_length))
+    }
+
+    fn get_link_role(&self, device: String, transport: i32) -> LinkRole {
+        match BDAddr::from_string(&device) {
+            Some(addr) => LinkRole::from(self.acl.get_link_role(&addr.to_raw_address(), transport)),
+            None => LinkRole::Unknown,
+        }
+    }
+
+    fn request_role_switch(&mut self, device: String, role: LinkRole) -> BtStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return BtStatus::InvalidParam,
+        };
+
+        match role {
+            LinkRole::Central => {}
+            LinkRole::Peripheral => return BtStatus::Unsupported,
+            LinkRole::Unknown => return BtStatus::InvalidParam,
+        }
+
+        if self.state != AdapterState::On {
+            return BtStatus::NotReady;
+        }
+
+        if self.role_switches.contains_key(&addr) {
+            return BtStatus::Busy;
+        }
+
+        let raw = addr.to_raw_address();
+        if LinkRole::from(self.acl.get_link_role(&raw, BT_TRANSPORT_BR_EDR)) == LinkRole::Unknown {
+            return BtStatus::RemoteDeviceDown;
+        }
+
+        let status = BtStatus::from(self.acl.switch_role_to_central(&raw));
+        if status == BtStatus::Success {
+            self.role_switches.insert(addr, ROLE_SWITCH_CHECKS);
+        }
+        status
+    }
+
+    fn set_le_privacy(&mut self, enabled: bool) -> BtStatus {
+        let info = Controller::new().get_info();
+        if info.is_ready && !info.le_supported {
+            return BtStatus::Unsupported;
+        }
+
+        let previous = self.get_le_own_address();
+        self.le_privacy = Some(enabled);
+        // The setting applies once the static address is removed.
+        let status = match (self.state, self.le_static_address) {
+            (AdapterState::On, None) => BtStatus::from(self.acl.configure_le_privacy(enabled)),
+            _ => BtStatus::Success,
+        };
+
+        self.notify_le_own_address(previous, self.le_own_address(enabled));
+        status
+    }
+
+    fn get_le_privacy(&self) -> bool {
+        if self.le_static_address.is_some() {
+            return false;
+        }
+
+        // The setting applies on the main thread of the stack, right after it is made.
+        match (self.le_privacy, self.state) {
+            (Some(enabled), _) => enabled,
+            (None, AdapterState::On) => self.acl.is_le_privacy_enabled(),
+            (None, _) => true,
+        }
+    }
+
+    fn set_rpa_timeout(&mut self, timeout_s: u32) -> BtStatus {
+        if timeout_s < RPA_TIMEOUT_MIN || timeout_s > RPA_TIMEOUT_MAX {
+            return BtStatus::InvalidParam;
+        }
+
+        self.rpa_timeout = Some(timeout_s as u16);
+        match self.state {
+            AdapterState::On => BtStatus::from(self.acl.set_rpa_timeout(timeout_s as u16)),
+            _ => BtStatus::Success,
+        }
+    }
+
+    fn set_le_static_address(&mut self, address: String) -> BtStatus {
+        let info = Controller::new().get_info();
+        if info.is_ready && !info.le_supported {
+            return BtStatus::Unsupported;
+        }
+
+        let addr = match address.as_str() {
+            "" => None,
+            _ => match BDAddr::from_string(&address) {
+                Some(addr) if is_static_random_address(&addr) => Some(addr),
+                _ => return BtStatus::InvalidParam,
+            },
+        };
+
+        let previous = self.get_le_own_address();
+        let status = match (self.state, addr) {
+            (AdapterState::On, Some(addr)) => {
+                BtStatus::from(self.acl.set_le_static_address(&addr.to_raw_address()))
+            }
+            (AdapterState::On, None) => {
+                BtStatus::from(self.acl.configure_le_privacy(self.le_privacy.unwrap_or(true)))
+            }
+            _ => BtStatus::Success,
+        };
+        if status != BtStatus::Success {
+            return status;
+        }
+
+        self.le_static_address = addr;
+        self.notify_le_own_address(previous, self.le_own_address(self.le_privacy.unwrap_or(true)));
+        status
+    }
+
+    fn get_le_own_address(&self) -> LeOwnAddress {
+        self.le_own_address(self.get_le_privacy())
+    }
+
+    fn get_supported_profiles(&self) -> Vec<SupportedProfiles> {
+        self.intf.lock().unwrap().supported_profiles()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adapter_state_keeps_bt_state_values() {
+        assert_eq!(AdapterState::Off.to_u32(), BtState::Off.to_u32());
+        assert_eq!(AdapterState::On.to_u32(), BtState::On.to_u32());
+    }
+
+    #[test]
+    fn class_blocked_by_major_class() {
+        // Peripheral (major class 5) with a keyboard minor class.
+        let keyboard = 0x0540;
+        assert!(is_class_blocked(1 << 5, keyboard));
+        assert!(!is_class_blocked(1 << 4, keyboard));
+        assert!(!is_class_blocked(0, keyboard));
+    }
+
+    #[test]
+    fn unknown_class_never_blocked() {
+        assert!(!is_class_blocked(u32::MAX, 0));
+        assert!(!is_class_blocked(u32::MAX, 0x1F << 8));
+    }
+
+    #[test]
+    fn static_random_address() {
+        let is_static = |addr: &str| is_static_random_address(&BDAddr::from_string(addr).unwrap());
+        assert!(is_static("C0:11:22:33:44:55"));
+        assert!(is_static("FF:FF:FF:FF:FF:FE"));
+        // The random part must not be all 0 or all 1.
+        assert!(!is_static("C0:00:00:00:00:00"));
+        assert!(!is_static("FF:FF:FF:FF:FF:FF"));
+        // Resolvable private, non-resolvable private and public addresses.
+        assert!(!is_static("40:11:22:33:44:55"));
+        assert!(!is_static("00:11:22:33:44:55"));
+        assert!(!is_static("80:11:22:33:44:55"));
+    }
+
+    #[test]
+    fn discovery_filter_default_passes_everything() {
+        let filter = DiscoveryFilter::default();
+        assert!(filter.matches(0, None, ""));
+        assert!(filter.matches(0x0540, Some(BT_DEVICE_TYPE_BREDR), "Keyboard"));
+    }
+
+    #[test]
+    fn discovery_filter_major_classes() {
+        let filter = DiscoveryFilter { major_classes: 1 << 4, ..Default::default() };
+        // Audio/video headset.
+        assert!(filter.matches(0x0404, None, ""));
+        // Peripheral keyboard.
+        assert!(!filter.matches(0x0540, None, ""));
+        // LE only devices have no Class of Device.
+        assert!(!filter.matches(0, Some(BT_DEVICE_TYPE_BLE), ""));
+    }
+
+    #[test]
+    fn discovery_filter_transport() {
+        let bredr = DiscoveryFilter { transport: BT_TRANSPORT_BR_EDR, ..Default::default() };
+        assert!(bredr.matches(0, Some(BT_DEVICE_TYPE_BREDR), ""));
+        assert!(bredr.matches(0, Some(BT_DEVICE_TYPE_DUMO), ""));
+        assert!(!bredr.matches(0, Some(BT_DEVICE_TYPE_BLE), ""));
+        assert!(!bredr.matches(0, None, ""));
+
+        let le = DiscoveryFilter { transport: BT_TRANSPORT_LE, ..Default::default() };
+        assert!(le.matches(0, Some(BT_DEVICE_TYPE_BLE), ""));
+        assert!(le.matches(0, Some(BT_DEVICE_TYPE_DUMO), ""));
+        assert!(!le.matches(0, Some(BT_DEVICE_TYPE_BREDR), ""));
+    }
+
+    #[test]
+    fn discovery_filter_name_substring() {
+        let filter = DiscoveryFilter { name_substring: "buds".to_string(), ..Default::default() };
+        assert!(filter.matches(0, None, "Galaxy Buds Pro"));
+        assert!(!filter.matches(0, None, "Headphones"));
+        // Devices found before their name is known are left out.
+        assert!(!filter.matches(0, None, ""));
+    }
+}
@@ -0,0 +1,451 @@
+//! Anything related to the debug API (IBluetoothDebug).
+
+use bt_topshim::btif::BluetoothCallbacks;
+use bt_topshim::topstack;
+#[cfg(feature = "vendor_commands")]
+use bt_topshim::vendor::{Vendor, VendorCallbacks};
+
+use btif_macros::btif_callbacks_generator;
+use btif_macros::stack_message;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::bluetooth::{Bluetooth, ConnectionEvent};
+use crate::bluetooth_gatt::BluetoothGatt;
+use crate::config::{Feature, FeatureFlags};
+use crate::metrics::Metrics;
+use crate::{BDAddr, Callbacks, Message, MessageSender, RPCProxy};
+
+/// Interval between two link quality metrics reports to the debug callbacks.
+const LINK_QUALITY_METRICS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Opcode group of the vendor specific HCI commands, the only ones `send_vendor_command` sends.
+#[cfg(feature = "vendor_commands")]
+const HCI_OGF_VENDOR_SPECIFIC: u32 = 0x3F;
+
+/// Largest parameters of an HCI command, in bytes.
+#[cfg(feature = "vendor_commands")]
+const HCI_COMMAND_MAX_PARAMS: usize = 255;
+
+/// Defines the debug API.
+pub trait IBluetoothDebug {
+    /// Adds a callback from a client who wishes to collect link quality metrics.
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothDebugCallback + Send>);
+
+    /// Returns the link quality aggregated from the controller quality reports of a device.
+    ///
+    /// Returns a report with `report_count` 0 if there is no report for the device.
+    fn get_link_quality(&self, device: String) -> LinkQuality;
+
+    /// Returns the last `limit` connection events of a device, oldest first, or all the events
+    /// kept by the stack if `limit` is 0.
+    ///
+    /// Only the last 32 events of each device are kept.
+    fn get_connection_history(&self, device: String, limit: u32) -> Vec<ConnectionEvent>;
+
+    /// Returns whether the feature `name` is on, false if there is no such feature.
+    fn get_feature_flag(&self, name: String) -> bool;
+
+    /// Turns the feature `name` on or off until the daemon stops, overriding the flag file.
+    ///
+    /// Features of the native stack are only applied on the next start of the daemon. Returns
+    /// false if there is no such feature or it can only be set in the flag file.
+    fn set_feature_flag(&mut self, name: String, enabled: bool) -> bool;
+
+    /// Reverts the feature `name` to its value in the flag file. Returns false if there is no such
+    /// feature or it can only be set in the flag file.
+    fn clear_feature_flag(&mut self, name: String) -> bool;
+
+    /// Measures the throughput of the LE connection to a device by sending it GATT packets of
+    /// the largest size the ATT MTU allows, several at a time, for `duration_secs` seconds.
+    ///
+    /// The result is reported through `IBluetoothDebugCallback::on_throughput_test_done`.
+    /// Returns false if the `throughput_test` flag was off when the daemon started, a test is
+    /// already running, `duration_secs` is not within 1 and 60, or the device is not connected
+    /// over LE.
+    fn start_throughput_test(
+        &mut self,
+        device: String,
+        mode: ThroughputTestMode,
+        duration_secs: u32,
+    ) -> bool;
+
+    /// Returns the result of the last throughput test with a device, with `status` -1 if there
+    /// was none.
+    fn get_throughput_test_result(&self, device: String) -> ThroughputTestResult;
+
+    /// Returns the stack metrics recorded since they were last exported, as text with a metric
+    /// per line, e.g. `pairing.succeeded 3`.
+    fn dump_metrics(&self) -> String;
+
+    /// Sends a vendor specific HCI command (OGF 0x3F) with up to 255 bytes of parameters, for
+    /// controllers that need a setup the stack doesn't do.
+    ///
+    /// Only allowed in builds with the `vendor_commands` feature and when the `vendor_commands`
+    /// flag is on. The command complete event is reported with `on_vendor_command_complete`.
+    fn send_vendor_command(&mut self, opcode: u32, params: Vec<u8>) -> bool;
+}
+
+/// The interface for debug callbacks registered through `IBluetoothDebug::register_callback`.
+pub trait IBluetoothDebugCallback: RPCProxy {
+    /// Reports the link quality of a device periodically, if it received quality reports since the
+    /// last period.
+    fn on_link_quality_metrics(&self, addr: String, quality: LinkQuality);
+
+    /// When a throughput test started with `start_throughput_test` ends.
+    fn on_throughput_test_done(&self, addr: String, result: ThroughputTestResult);
+
+    /// Reports the return parameters of the command complete event of a command sent with
+    /// `send_vendor_command`. The first byte is usually the HCI status of the command.
+    fn on_vendor_command_complete(&self, opcode: u32, params: Vec<u8>);
+}
+
+/// Link quality of a connection, aggregated from Bluetooth Quality Report events.
+#[derive(Clone, Debug, Default)]
+pub struct LinkQuality {
+    /// Number of quality reports received for the connection.
+    pub report_count: u32,
+    /// Quality report id of the last report, e.g. 1 for monitor mode or 3 for A2DP choppy.
+    pub last_report_id: i32,
+    /// RSSI of the last report, in dBm.
+    pub rssi: i32,
+    /// Average RSSI of all the reports, in dBm.
+    pub average_rssi: i32,
+    /// SNR of the last report, in dB.
+    pub snr: i32,
+    /// Number of retransmitted packets over all the reports.
+    pub retransmission_count: u32,
+    /// Number of packets that were not received over all the reports.
+    pub packets_not_received: u32,
+    /// Number of packets that were negatively acknowledged over all the reports.
+    pub nak_count: u32,
+}
+
+/// Traffic of a throughput test, see `IBluetoothDebug::start_throughput_test`.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(i32)]
+pub enum ThroughputTestMode {
+    /// Writes without response to the test characteristic of the device, which must run this
+    /// stack too so that it serves the test service.
+    WriteWithoutResponse = 0,
+    /// Notifications of the test characteristic to the device, whatever stack it runs.
+    Notification,
+}
+
+/// Result of a throughput test.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ThroughputTestResult {
+    /// `GattStatus` value, e.g. `GattStatus::NotFound` if the device has no test service or
+    /// `GattStatus::Error` if the connection was lost during the test.
+    pub status: i32,
+    /// Time the packets flowed for, in milliseconds.
+    pub duration_ms: u32,
+    /// Size of the payload of each packet, the ATT MTU less the ATT header.
+    pub payload_len: u32,
+    /// Number of packets sent and their total payload in bytes.
+    pub packet_count: u32,
+    pub byte_count: u64,
+    /// Number of packets the stack failed to send.
+    pub failure_count: u32,
+    /// Payload throughput, in kbit/s.
+    pub kbps: u32,
+    /// Percentiles of the time from handing a packet to the stack to it being sent, in
+    /// microseconds.
+    pub latency_p50_us: u32,
+    pub latency_p90_us: u32,
+    pub latency_p99_us: u32,
+}
+
+impl Default for ThroughputTestResult {
+    /// Constructs the result of a test that never ran.
+    fn default() -> Self {
+        ThroughputTestResult {
+            status: -1,
+            duration_ms: 0,
+            payload_len: 0,
+            packet_count: 0,
+            byte_count: 0,
+            failure_count: 0,
+            kbps: 0,
+            latency_p50_us: 0,
+            latency_p90_us: 0,
+            latency_p99_us: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct LinkQualityStats {
+    quality: LinkQuality,
+    rssi_sum: i64,
+    /// Whether reports were received since the last metrics report.
+    updated: bool,
+}
+
+/// Implementation of the debug API.
+pub struct BluetoothDebug {
+    bluetooth: Arc<Mutex<Bluetooth>>,
+    bluetooth_gatt: Arc<Mutex<BluetoothGatt>>,
+    feature_flags: Arc<Mutex<FeatureFlags>>,
+    metrics: Arc<Mutex<Metrics>>,
+    callbacks: Callbacks<dyn IBluetoothDebugCallback + Send>,
+    connected_devices: HashSet<BDAddr>,
+    link_qualities: HashMap<BDAddr, LinkQualityStats>,
+    unattributed_reports: u32,
+    throughput_results: HashMap<BDAddr, ThroughputTestResult>,
+    #[cfg(feature = "vendor_commands")]
+    vendor: Vendor,
+}
+
+impl BluetoothDebug {
+    /// Constructs the IBluetoothDebug implementation and starts reporting metrics periodically.
+    pub fn new(
+        tx: MessageSender,
+        bluetooth: Arc<Mutex<Bluetooth>>,
+        bluetooth_gatt: Arc<Mutex<BluetoothGatt>>,
+        feature_flags: Arc<Mutex<FeatureFlags>>,
+        metrics: Arc<Mutex<Metrics>>,
+    ) -> BluetoothDebug {
+        let callbacks = Callbacks::new(tx.clone(), Message::DebugCallbackDisconnected);
+
+        #[cfg(feature = "vendor_commands")]
+        let vendor = {
+            let tx = tx.clone();
+            Vendor::new(Arc::new(VendorCallbacks {
+                command_complete: Box::new(move |opcode, params| {
+                    let tx = tx.clone();
+                    topstack::get_runtime().spawn(async move {
+                        let _ = tx.send(Message::DebugVendorCommandComplete(opcode, params)).await;
+                    });
+                }),
+            }))
+        };
+
+        topstack::get_runtime().spawn(async move {
+            loop {
+                tokio::time::sleep(LINK_QUALITY_METRICS_INTERVAL).await;
+                if tx.send(Message::DebugLinkQualityMetricsTick).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        BluetoothDebug {
+            bluetooth,
+            bluetooth_gatt,
+            feature_flags,
+            metrics,
+            callbacks,
+            connected_devices: HashSet::new(),
+            link_qualities: HashMap::new(),
+            unattributed_reports: 0,
+            throughput_results: HashMap::new(),
+            #[cfg(feature = "vendor_commands")]
+            vendor,
+        }
+    }
+
+    pub(crate) fn callback_disconnected(&mut self, id: u32) {
+        self.callbacks.remove_callback(id);
+    }
+
+    /// Tracks the connected devices, which quality reports are attributed to.
+    pub(crate) fn acl_state_changed(&mut self, addr: BDAddr, connected: bool) {
+        if connected {
+            self.connected_devices.insert(addr);
+        } else {
+            self.connected_devices.remove(&addr);
+        }
+    }
+
+    /// Reports the link quality of the devices that received quality reports since the last call.
+    pub(crate) fn report_link_quality_metrics(&mut self) {
+        for (addr, stats) in &mut self.link_qualities {
+            if !stats.updated {
+                continue;
+            }
+
+            stats.updated = false;
+            for callback in self.callbacks.iter() {
+                callback.on_link_quality_metrics(addr.to_string(), stats.quality.clone());
+            }
+        }
+    }
+
+    /// Reports the result of a throughput test, posted by `BluetoothGatt` when the test ends.
+    pub(crate) fn throughput_test_done(&mut self, addr: BDAddr, result: ThroughputTestResult) {
+        for callback in self.callbacks.iter() {
+            callback.on_throughput_test_done(addr.to_string(), result.clone());
+        }
+
+        self.throughput_results.insert(addr, result);
+    }
+
+    /// Reports the command complete event of a vendor specific command.
+    pub(crate) fn vendor_command_complete(&mut self, opcode: u16, params: Vec<u8>) {
+        for callback in self.callbacks.iter() {
+            callback.on_vendor_command_complete(opcode as u32, params.clone());
+        }
+    }
+}
+
+#[btif_callbacks_generator(btif_debug_callbacks, BluetoothCallbacks)]
+pub(crate) trait BtifDebugCallbacks {
+    #[stack_message(DebugLinkQualityReport)]
+    fn link_quality_report(
+        &mut self,
+        timestamp: u64,
+        report_id: i32,
+        rssi: i32,
+        snr: i32,
+        retransmission_count: i32,
+        packets_not_receive_count: i32,
+        negative_acknowledgement_count: i32,
+    );
+}
+
+impl BtifDebugCallbacks for BluetoothDebug {
+    #[allow(unused_variables)]
+    fn link_quality_report(
+        &mut self,
+        timestamp: u64,
+        report_id: i32,
+        rssi: i32,
+        snr: i32,
+        retransmission_count: i32,
+        packets_not_receive_count: i32,
+        negative_acknowledgement_count: i32,
+    ) {
+        // Quality reports don't identify the connection they are about, so they can only be
+        // attributed when a single device is connected.
+        if self.connected_devices.len() != 1 {
+            self.unattributed_reports = self.unattributed_reports.saturating_add(1);
+            eprintln!(
+                "Dropping link quality report with {} connected devices ({} dropped so far)",
+                self.connected_devices.len(),
+                self.unattributed_reports
+            );
+            return;
+        }
+
+        let addr = *self.connected_devices.iter().next().unwrap();
+        let stats = self.link_qualities.entry(addr).or_default();
+
+        stats.rssi_sum = stats.rssi_sum.saturating_add(rssi.into());
+        stats.updated = true;
+
+        // The counters come from the controller, a negative one is taken as no event and the
+        // totals stop at their maximum rather than overflow.
+        let quality = &mut stats.quality;
+        quality.report_count = quality.report_count.saturating_add(1);
+        quality.last_report_id = report_id;
+        quality.rssi = rssi;
+        quality.average_rssi = (stats.rssi_sum / i64::from(quality.report_count)) as i32;
+        quality.snr = snr;
+        quality.retransmission_count =
+            quality.retransmission_count.saturating_add(retransmission_count.max(0) as u32);
+        quality.packets_not_received =
+            quality.packets_not_received.saturating_add(packets_not_receive_count.max(0) as u32);
+        quality.nak_count =
+            quality.nak_count.saturating_add(negative_acknowledgement_count.max(0) as u32);
+    }
+}
+
+impl IBluetoothDebug for BluetoothDebug {
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothDebugCallback + Send>) {
+        self.callbacks.add_callback(callback);
+    }
+
+    fn get_link_quality(&self, device: String) -> LinkQuality {
+        BDAddr::from_string(&device)
+            .and_then(|addr| self.link_qualities.get(&addr))
+            .map(|stats| stats.quality.clone())
+            .unwrap_or_default()
+    }
+
+    fn get_connection_history(&self, device: String, limit: u32) -> Vec<ConnectionEvent> {
+        match BDAddr::from_string(&device) {
+            Some(addr) => {
+                self.bluetooth.lock().unwrap().get_connection_history(addr, limit as usize)
+            }
+            None => vec![],
+        }
+    }
+
+    fn get_feature_flag(&self, name: String) -> bool {
+        match Feature::from_name(&name) {
+            Some(feature) => self.feature_flags.lock().unwrap().is_enabled(feature),
+            None => false,
+        }
+    }
+
+    fn set_feature_flag(&mut self, name: String, enabled: bool) -> bool {
+        match Feature::from_name(&name) {
+            Some(feature) if feature.is_overridable() => {
+                self.feature_flags.lock().unwrap().set_override(feature, Some(enabled));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn clear_feature_flag(&mut self, name: String) -> bool {
+        match Feature::from_name(&name) {
+            Some(feature) if feature.is_overridable() => {
+                self.feature_flags.lock().unwrap().set_override(feature, None);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn start_throughput_test(
+        &mut self,
+        device: String,
+        mode: ThroughputTestMode,
+        duration_secs: u32,
+    ) -> bool {
+        match BDAddr::from_string(&device) {
+            Some(addr) => {
+                self.bluetooth_gatt.lock().unwrap().start_throughput_test(addr, mode, duration_secs)
+            }
+            None => false,
+        }
+    }
+
+    fn get_throughput_test_result(&self, device: String) -> ThroughputTestResult {
+        BDAddr::from_string(&device)
+            .and_then(|addr| self.throughput_results.get(&addr))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn dump_metrics(&self) -> String {
+        self.metrics.lock().unwrap().snapshot().to_text()
+    }
+
+    #[cfg(feature = "vendor_commands")]
+    fn send_vendor_command(&mut self, opcode: u32, params: Vec<u8>) -> bool {
+        if !self.feature_flags.lock().unwrap().is_enabled(Feature::VendorCommands) {
+            return false;
+        }
+
+        if opcode > 0xFFFF
+            || opcode >> 10 != HCI_OGF_VENDOR_SPECIFIC
+            || params.len() > HCI_COMMAND_MAX_PARAMS
+        {
+            return false;
+        }
+
+        self.vendor.send_command(opcode as u16, &params) == 0
+    }
+
+    #[cfg(not(feature = "vendor_commands"))]
+    fn send_vendor_command(&mut self, _opcode: u32, _params: Vec<u8>) -> bool {
+        false
+    }
+}
@@ -1,23 +1,835 @@
 //! Anything related to the GATT API (IBluetoothGatt).
 
-use bt_topshim::btif::BluetoothInterface;
+use bt_topshim::btif::ffi::{BtUuid, RustRawAddress};
+use bt_topshim::btif::{BluetoothInterface, BtStatus, SupportedProfiles};
+use bt_topshim::controller::Controller;
+use bt_topshim::profiles::gatt::ffi::{BtAdvertiseParameters, BtApcfCondition, BtGattDbElement};
+use bt_topshim::profiles::gatt::{Gatt, GattCallbacks, GattDbElementType, GattStatus};
+use bt_topshim::profiles::ProfileShim;
+use bt_topshim::topstack;
 
+use btif_macros::btif_callbacks_generator;
+use btif_macros::stack_message;
+
+use num_traits::FromPrimitive;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::task::JoinHandle;
+
+use crate::bluetooth::{Bluetooth, IBluetooth, LeConnectionPreset, LePhy};
+use crate::bluetooth_debug::{ThroughputTestMode, ThroughputTestResult};
+use crate::completion::{Completers, Completion};
+use crate::config::{Feature, FeatureFlags};
+use crate::device_class::DeviceCategory;
+use crate::hogp::{HogpDevice, HogpOperation, UhidRequest, CCCD_ENABLE_NOTIFICATIONS};
+use crate::metrics::{self, Metrics};
+use crate::uuid::Uuid128;
+use crate::{BDAddr, Callbacks, Message, MessageSender, RPCProxy};
+
+/// Minimum interval between two RSSI reads of the same device sent to the controller. Requests
+/// within the interval are answered with the last read value, whichever client asks.
+const RSSI_READ_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of notifications queued for a connection. Further notifications are refused
+/// until the queue drains to half of it.
+const NOTIFICATION_QUEUE_MAX_LEN: usize = 16;
+
+/// Write type of the writes that are part of a reliable write transaction.
+const GATT_WRITE_TYPE_PREPARE: i32 = 3;
+
+/// Maximum number of bytes of prepared writes buffered for a connection to a server.
+const PREPARED_WRITES_MAX_LEN: usize = 4096;
+
+/// ATT MTU of a connection until an MTU exchange completes.
+const ATT_DEFAULT_MTU: i32 = 23;
+
+/// Largest ATT MTU that can be requested, as the longest attribute value is 512 bytes.
+const ATT_MAX_MTU: i32 = 517;
+
+/// Shortest report period of a scan in the `ScanReportMode::Periodic` mode.
+const SCAN_REPORT_PERIOD_MIN_MS: i32 = 100;
+
+/// Most anonymous advertisements kept for a report period of the `ScanReportMode::Periodic` mode,
+/// the later ones being dropped.
+const SCAN_BATCH_ANONYMOUS_MAX: usize = 256;
+
+/// Range of the scan interval and window, in units of 0.625 ms.
+const SCAN_INTERVAL_MIN: i32 = 0x0004;
+const SCAN_INTERVAL_MAX: i32 = 0x4000;
+
+/// Interval and window of the scans that leave them unset, 1024 ms every 4096 ms.
+const SCAN_DEFAULT_PARAMETERS: (i32, i32) = (6554, 1638);
+
+/// Interval and window applied while no foreground scan is running, 512 ms every 5120 ms.
+const SCAN_LOW_DUTY_PARAMETERS: (i32, i32) = (8192, 819);
+
+/// Range of the advertising interval, in units of 0.625 ms. Legacy advertising is limited to
+/// 10.24 s.
+const ADVERTISING_INTERVAL_MIN: i32 = 0x20;
+const ADVERTISING_INTERVAL_MAX: i32 = 0xFF_FFFF;
+const LEGACY_ADVERTISING_INTERVAL_MAX: i32 = 0x4000;
+
+/// Range of the TX power of an advertising set, in dBm.
+const ADVERTISING_TX_POWER_MIN: i32 = -127;
+const ADVERTISING_TX_POWER_MAX: i32 = 20;
+
+/// Longest advertising or scan response data of a legacy advertising set.
+const LEGACY_ADVERTISING_DATA_MAX_LEN: usize = 31;
+
+/// Advertising event properties, see the LE Set Extended Advertising Parameters command.
+const ADVERTISING_PROPERTY_CONNECTABLE: u16 = 0x01;
+const ADVERTISING_PROPERTY_SCANNABLE: u16 = 0x02;
+const ADVERTISING_PROPERTY_LEGACY: u16 = 0x10;
+
+/// Advertises on the three primary advertising channels.
+const ADVERTISING_CHANNEL_ALL: u8 = 0x07;
+
+/// LE 1M PHY, used as primary and secondary advertising PHY.
+const ADVERTISING_PHY_LE_1M: u8 = 1;
+
+/// Range of the RSSI thresholds of an advertisement monitor, in dBm.
+const MONITOR_RSSI_MIN: i32 = -127;
+const MONITOR_RSSI_MAX: i32 = 20;
+
+/// Longest data of an advertising data structure, whose length is given by a single byte that
+/// also counts its type.
+const AD_STRUCTURE_DATA_MAX_LEN: usize = 254;
+
+/// Advertising data types whose patterns can be offloaded to the controller.
+const AD_TYPE_SERVICE_DATA_16: u8 = 0x16;
+const AD_TYPE_MANUFACTURER_DATA: u8 = 0xFF;
+
+/// Advertising data type of the Appearance of a device.
+const AD_TYPE_APPEARANCE: u8 = 0x19;
+
+/// Advertising data types of the service data with 32 and 128-bit UUIDs, besides
+/// `AD_TYPE_SERVICE_DATA_16`.
+const AD_TYPE_SERVICE_DATA_32: u8 = 0x20;
+const AD_TYPE_SERVICE_DATA_128: u8 = 0x21;
+
+/// Advertising packet content filter types, see `BTM_BLE_PF_MANU_DATA` and
+/// `BTM_BLE_PF_SRVC_DATA_PATTERN`.
+const APCF_MANUFACTURER_DATA: u8 = 5;
+const APCF_SERVICE_DATA_PATTERN: u8 = 6;
+
+/// Longest data an advertising packet content filter condition matches.
+const APCF_DATA_MAX_LEN: usize = 29;
+
+/// Identifies the client and server applications that run the throughput tests, see
+/// `IBluetoothDebug::start_throughput_test`.
+const THROUGHPUT_APP_UUID: [u8; 16] = [
+    0x8a, 0x3e, 0x51, 0x07, 0x4c, 0x2d, 0x4f, 0x0e, 0x9b, 0x61, 0x1d, 0x5a, 0x70, 0xc2, 0x3f, 0x90,
+];
+
+/// Test service served to the devices running throughput tests, and its characteristic that the
+/// test packets are written to or notified from.
+const THROUGHPUT_SERVICE_UUID: [u8; 16] = [
+    0x8a, 0x3e, 0x51, 0x08, 0x4c, 0x2d, 0x4f, 0x0e, 0x9b, 0x61, 0x1d, 0x5a, 0x70, 0xc2, 0x3f, 0x90,
+];
+const THROUGHPUT_CHARACTERISTIC_UUID: [u8; 16] = [
+    0x8a, 0x3e, 0x51, 0x09, 0x4c, 0x2d, 0x4f, 0x0e, 0x9b, 0x61, 0x1d, 0x5a, 0x70, 0xc2, 0x3f, 0x90,
+];
+
+/// Longest throughput test, in seconds.
+const THROUGHPUT_TEST_MAX_DURATION_SECS: u32 = 60;
+
+/// Packets of a throughput test handed to the stack at once, so that the link doesn't idle while
+/// the callback of each packet comes back.
+const THROUGHPUT_TEST_WINDOW: usize = 8;
+
+/// Identifies the client application of the HOGP host, see `hogp`.
+const HOGP_APP_UUID: [u8; 16] = [
+    0x8a, 0x3e, 0x51, 0x0a, 0x4c, 0x2d, 0x4f, 0x0e, 0x9b, 0x61, 0x1d, 0x5a, 0x70, 0xc2, 0x3f, 0x90,
+];
+
+/// How many times, and how long after, a setup operation of a HOGP device is sent again when
+/// the device refuses it until the link is encrypted.
+const HOGP_OPERATION_MAX_RETRIES: u32 = 3;
+const HOGP_OPERATION_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Transport and PHY of the LE connections of the clients, see `BT_TRANSPORT_LE` and
+/// `PHY_LE_1M`.
+const TRANSPORT_LE: i32 = 2;
+const PHY_LE_1M: i32 = 1;
+
+/// Client Characteristic Configuration descriptor, which devices subscribe to notifications with.
+pub(crate) const CCCD_UUID: u16 = 0x2902;
+
+/// Values of the Client Characteristic Configuration descriptor, besides
+/// `CCCD_ENABLE_NOTIFICATIONS`.
+const CCCD_ENABLE_INDICATIONS: [u8; 2] = [0x02, 0x00];
+const CCCD_DISABLE: [u8; 2] = [0x00, 0x00];
+
+/// Characteristic properties, see `GATT_CHAR_PROP_BIT_WRITE_NR` and the following.
+const GATT_CHAR_PROP_WRITE_NR: i32 = 0x04;
+const GATT_CHAR_PROP_NOTIFY: i32 = 0x10;
+const GATT_CHAR_PROP_INDICATE: i32 = 0x20;
+
+/// Database Hash characteristic, which changes whenever the database of the device does.
+const DATABASE_HASH_UUID: u16 = 0x2B2A;
+const DATABASE_HASH_LEN: usize = 16;
+
+/// Write type of the writes without response.
+const GATT_WRITE_TYPE_NO_RSP: i32 = 1;
+
+/// Write type of the writes with response.
+const GATT_WRITE_TYPE_DEFAULT: i32 = 2;
+
+/// Size of the header of ATT write commands and notifications, which the MTU includes.
+const ATT_HEADER_LEN: i32 = 3;
+
+/// Size of the header of ATT Prepare Write requests, which also carry the value offset.
+const ATT_PREPARE_WRITE_HEADER_LEN: i32 = 5;
+
+/// Size of the header of ATT Read By Type responses, with the length and handle of the value.
+const ATT_READ_BY_TYPE_HEADER_LEN: i32 = 4;
+
+/// Longest attribute value, as set by the ATT protocol.
+const GATT_MAX_ATTR_LEN: usize = 512;
+
+/// Address type of the devices advertising with a random address, see `BLE_ADDR_RANDOM`.
+const BLE_ADDR_RANDOM: i32 = 1;
+
+/// Address type of the anonymous advertisements, which carry no address.
+const BLE_ADDR_ANONYMOUS: i32 = 0xFF;
+
+/// Bit of the event type of the scan results set for legacy advertising PDUs.
+const SCAN_EVENT_TYPE_LEGACY: i32 = 0x10;
+
+/// Secondary PHY of the advertisements that have no auxiliary packets, e.g. legacy ones.
+pub const SECONDARY_PHY_NONE: i32 = 0;
+
+/// Advertising SID of the advertisements that are not part of an advertising set.
+pub const ADVERTISING_SID_NONE: i32 = 0xFF;
+
+/// Permissions of the attributes of a local service, see `GATT_PERM_READ` and the following.
+const GATT_PERM_READ: i32 = 1 << 0;
+const GATT_PERM_READ_ENCRYPTED: i32 = 1 << 1;
+const GATT_PERM_READ_ENC_MITM: i32 = 1 << 2;
+const GATT_PERM_WRITE: i32 = 1 << 4;
+const GATT_PERM_WRITE_ENCRYPTED: i32 = 1 << 5;
+const GATT_PERM_WRITE_ENC_MITM: i32 = 1 << 6;
 
 /// Defines the GATT API.
+///
+/// Requests that return a `GattStatus` return `GattStatus::Success` if they are accepted,
+/// `GattStatus::IllegalParameter` if the client, server or connection is not known or the
+/// arguments are malformed, or `GattStatus::Error` if the native stack rejects them.
 pub trait IBluetoothGatt {
-    fn register_scanner(&self, callback: Box<dyn IScannerCallback + Send>);
+    /// Registers an LE scanner.
+    ///
+    /// The assigned scanner id is reported through `IScannerCallback::on_scanner_registered`.
+    fn register_scanner(&mut self, callback: Box<dyn IScannerCallback + Send>);
+
+    /// Unregisters an LE scanner, stopping its scan.
+    fn unregister_scanner(&mut self, scanner_id: i32);
+
+    /// Starts scanning for a scanner, or restarts it with new settings.
+    ///
+    /// Results are reported through `IScannerCallback::on_scan_result` as selected by
+    /// `ScanSettings::report_mode`. The scanners share the controller, which scans with the
+    /// highest duty cycle of the foreground scans, or a low one while there are only background
+    /// scans. Returns false if the interval or window is out of range.
+    fn start_scan(
+        &mut self,
+        scanner_id: i32,
+        settings: ScanSettings,
+        filters: Vec<ScanFilter>,
+    ) -> bool;
+
+    /// Stops scanning for a scanner. Results batched by the `Periodic` mode are reported first.
+    fn stop_scan(&mut self, scanner_id: i32) -> bool;
+
+    /// Registers a GATT client application identified by `app_uuid`.
+    ///
+    /// The assigned client id is reported through `IBluetoothGattCallback::on_client_registered`.
+    fn register_client(
+        &mut self,
+        app_uuid: Uuid128,
+        callback: Box<dyn IBluetoothGattCallback + Send>,
+        eatt_support: bool,
+    );
+
+    /// Unregisters a GATT client application.
+    fn unregister_client(&mut self, client_id: i32);
+
+    /// Connects a client to a remote device over LE.
+    ///
+    /// The connection is reported through `IBluetoothGattCallback::on_client_connection_state`,
+    /// with a connection id of its own for the client. Clients connected to the same device
+    /// share the link: a client connecting to a device another client is connected to gets its
+    /// connection right away, and the link stays up until the last of them disconnects. Unless
+    /// `is_direct` is set, the connection is made in the background whenever the device is in
+    /// range, until `client_disconnect`. Returns `GattStatus::AlreadyOpen` if the client is
+    /// connected to the device already.
+    fn client_connect(&mut self, client_id: i32, addr: String, is_direct: bool) -> GattStatus;
+
+    /// Disconnects a client from a remote device, or stops connecting it in the background.
+    ///
+    /// Only the connection of the client is closed, the link stays up while other clients are
+    /// connected to the device.
+    fn client_disconnect(&mut self, client_id: i32, addr: String) -> GattStatus;
+
+    /// Reads the RSSI of a connected remote device.
+    ///
+    /// The result is reported through `IBluetoothGattCallback::on_read_remote_rssi`. Reads of the
+    /// same device are throttled across clients, so the reported value may have been read
+    /// recently on behalf of another client.
+    fn read_remote_rssi(&mut self, client_id: i32, addr: String);
+
+    /// Reads the PHYs of the connection of a client to a remote device.
+    ///
+    /// The result is reported through `IBluetoothGattCallback::on_phy_read`. Returns
+    /// `GattStatus::IllegalParameter` if the client is not connected to the device.
+    fn read_phy(&mut self, client_id: i32, addr: String) -> GattStatus;
+
+    /// Writes a characteristic of a connected device.
+    ///
+    /// The result is reported through `IBluetoothGattCallback::on_characteristic_write`. Within a
+    /// reliable write transaction the value is only queued by the device until `execute_write`,
+    /// whatever `write_type` is. Returns `GattStatus::Busy` if another client has a reliable write
+    /// transaction in progress on the connection.
+    ///
+    /// Values that don't fit in a single write request are written in chunks with a Prepare Write
+    /// sequence. Writes without response and the writes of reliable write transactions can't be
+    /// split, so their values must fit in a single PDU. Returns `GattStatus::InvalidAttrLen` if
+    /// the value doesn't, or is longer than 512 bytes.
+    fn write_characteristic(
+        &mut self,
+        client_id: i32,
+        conn_id: i32,
+        handle: i32,
+        write_type: i32,
+        auth_req: i32,
+        value: Vec<u8>,
+    ) -> GattStatus;
+
+    /// Discovers the services of the device of a connection of the client.
+    ///
+    /// The result is reported through `IBluetoothGattCallback::on_search_complete`. The
+    /// discovered database is kept until the connection closes, for the requests that look
+    /// characteristics up by UUID.
+    fn discover_services(&mut self, client_id: i32, conn_id: i32) -> GattStatus;
+
+    /// Reads the first characteristic of type `uuid` of a connected device.
+    ///
+    /// The handle range of the read is resolved from the database discovered with
+    /// `discover_services`. The result is reported through
+    /// `IBluetoothGattCallback::on_characteristic_read`. Values longer than the MTU allows are
+    /// read in full, in chunks. Returns `GattStatus::NotFound` if the database of the connection
+    /// is not discovered or has no such characteristic.
+    fn read_using_characteristic_uuid(
+        &mut self,
+        client_id: i32,
+        conn_id: i32,
+        uuid: Uuid128,
+        auth_req: i32,
+    ) -> GattStatus;
+
+    /// Writes the first characteristic of type `uuid` of a connected device.
+    ///
+    /// Same as `write_characteristic`, with the handle resolved from the database discovered with
+    /// `discover_services`, and fails as both `read_using_characteristic_uuid` and
+    /// `write_characteristic` do.
+    fn write_characteristic_by_uuid(
+        &mut self,
+        client_id: i32,
+        conn_id: i32,
+        uuid: Uuid128,
+        write_type: i32,
+        auth_req: i32,
+        value: Vec<u8>,
+    ) -> GattStatus;
 
-    fn unregister_scanner(&self, scanner_id: i32);
+    /// Starts a reliable write transaction on a connection.
+    ///
+    /// Returns `GattStatus::Busy` if a transaction is already in progress on the connection.
+    fn begin_reliable_write(&mut self, client_id: i32, conn_id: i32) -> GattStatus;
 
-    fn start_scan(&self, scanner_id: i32, settings: ScanSettings, filters: Vec<ScanFilter>);
-    fn stop_scan(&self, scanner_id: i32);
+    /// Commits the writes of the reliable write transaction of a connection.
+    ///
+    /// The result is reported through `IBluetoothGattCallback::on_execute_write`. Returns
+    /// `GattStatus::WrongState` if the client has no transaction in progress on the connection.
+    fn execute_write(&mut self, client_id: i32, conn_id: i32) -> GattStatus;
+
+    /// Cancels the reliable write transaction of a connection, discarding its writes.
+    ///
+    /// The result is reported through `IBluetoothGattCallback::on_execute_write`. Returns
+    /// `GattStatus::WrongState` if the client has no transaction in progress on the connection.
+    fn abort_reliable_write(&mut self, client_id: i32, conn_id: i32) -> GattStatus;
+
+    /// Requests an ATT MTU exchange on a connection of the client.
+    ///
+    /// The negotiated MTU is reported through `IBluetoothGattCallback::on_configure_mtu`. The MTU
+    /// is remembered as preferred for the device, and requested again each time the client
+    /// reconnects to it. Returns `GattStatus::IllegalParameter` if `mtu` is not within 23 and
+    /// 517.
+    fn configure_mtu(&mut self, client_id: i32, conn_id: i32, mtu: i32) -> GattStatus;
+
+    /// Returns the ATT MTU of a client or server connection, or 0 if the connection is not known.
+    fn get_mtu(&self, conn_id: i32) -> i32;
+
+    /// Requests the LE connection of a client to a device to favor latency or power.
+    ///
+    /// The connection parameters of the `LeConnectionPreset` that the priority maps to are
+    /// requested. The clients of a device share a single link, so the highest priority requested
+    /// by its clients applies, and the link goes back to `ConnectionPriority::Balanced` once none
+    /// of them requests one. A request lasts until the client disconnects from the device. The
+    /// parameters are reported through `IBluetoothGattCallback::on_connection_updated`. Returns
+    /// `GattStatus::IllegalParameter` if the client is not connected to the device.
+    fn request_connection_priority(
+        &mut self,
+        client_id: i32,
+        addr: String,
+        priority: ConnectionPriority,
+    ) -> GattStatus;
+
+    /// Returns the value of the Database Hash characteristic of the device of a client
+    /// connection, as read when its services were last discovered.
+    ///
+    /// Clients caching the services of a device can compare it to the hash the cache was made
+    /// with to tell whether the cache is still valid. Returns an empty value if the services are
+    /// not discovered, or the device has no readable Database Hash characteristic.
+    fn get_database_hash(&self, conn_id: i32) -> Vec<u8>;
+
+    /// Subscribes a client to the notifications and indications of a characteristic of a
+    /// connected device, or unsubscribes it.
+    ///
+    /// `handle` is the handle of the characteristic value, looked up in the database discovered
+    /// with `discover_services`. The Client Characteristic Configuration descriptor of the
+    /// characteristic, shared by the clients of the device, is written when the first client
+    /// subscribes and when the last one unsubscribes. It enables notifications if the
+    /// characteristic supports them, and indications otherwise. The result is reported through
+    /// `IBluetoothGattCallback::on_notification_registered`, after which the values the device
+    /// sends are reported through `IBluetoothGattCallback::on_characteristic_changed` to the
+    /// subscribed clients only. Subscriptions end when the connection closes. Returns
+    /// `GattStatus::NotFound` if the characteristic is not in the database, or supports neither
+    /// notifications nor indications.
+    fn register_for_notification(
+        &mut self,
+        client_id: i32,
+        conn_id: i32,
+        handle: i32,
+        enable: bool,
+    ) -> GattStatus;
+
+    /// Registers a GATT server application identified by `app_uuid`.
+    ///
+    /// The assigned server id is reported through
+    /// `IBluetoothGattServerCallback::on_server_registered`.
+    fn register_server(
+        &mut self,
+        app_uuid: Uuid128,
+        callback: Box<dyn IBluetoothGattServerCallback + Send>,
+        eatt_support: bool,
+    );
+
+    /// Unregisters a GATT server application.
+    fn unregister_server(&mut self, server_id: i32);
+
+    /// Queues a notification, or an indication if `confirm` is set, of the value of the attribute
+    /// `handle` to a connected device.
+    ///
+    /// Notifications are sent one at a time and held back while the connection is congested. A
+    /// queued notification of the same attribute that is not sent yet is replaced by the new
+    /// value. Returns `GattStatus::Congested` if too many notifications are queued, in which case
+    /// `IBluetoothGattServerCallback::on_notification_queue_available` is called once there is
+    /// room again. Returns `GattStatus::IllegalParameter` if the attribute is not one of the
+    /// services of the server.
+    fn send_notification(
+        &mut self,
+        server_id: i32,
+        device: String,
+        handle: i32,
+        value: Vec<u8>,
+        confirm: bool,
+    ) -> GattStatus;
+
+    /// Responds to a request reported through `IBluetoothGattServerCallback`.
+    ///
+    /// `status` is 0 or an ATT error code.
+    fn send_response(
+        &mut self,
+        server_id: i32,
+        device: String,
+        trans_id: i32,
+        status: i32,
+        handle: i32,
+        offset: i32,
+        value: Vec<u8>,
+    ) -> GattStatus;
+
+    /// Reads the PHYs of the connection of a remote device to the server.
+    ///
+    /// The result is reported through `IBluetoothGattServerCallback::on_phy_read`. Returns
+    /// `GattStatus::IllegalParameter` if the device is not connected to the server.
+    fn server_read_phy(&mut self, server_id: i32, addr: String) -> GattStatus;
+
+    /// Adds a service to the database of the server.
+    ///
+    /// The service and the handles assigned to its attributes are reported through
+    /// `IBluetoothGattServerCallback::on_service_added`. Reads and writes of attributes that
+    /// require an encrypted link are rejected by the stack with an ATT insufficient encryption or
+    /// authentication error while the link doesn't meet the requirement, and the link is
+    /// encrypted, pairing first if needed, so that the device can retry. Returns
+    /// `GattStatus::IllegalParameter` if the attributes or their permissions are malformed.
+    ///
+    /// The attributes of the service only ever show up in the requests and callbacks of the
+    /// server, although the handles of every server are assigned from the same database. Returns
+    /// `GattStatus::DupReg` if the service is primary and another server has a primary service
+    /// with the same UUID.
+    fn add_service(&mut self, server_id: i32, service: GattService) -> GattStatus;
+
+    /// Removes a service added by the server, identified by the handle of its declaration.
+    fn remove_service(&mut self, server_id: i32, handle: i32) -> GattStatus;
+
+    /// Starts an advertising set with its advertising and scan response data.
+    ///
+    /// The set advertises for `duration`, in units of 10 ms, or `max_ext_adv_events` advertising
+    /// events, 0 for no limit. The result is reported through
+    /// `IAdvertisingSetCallback::on_advertising_set_started`, with an `AdvertisingStatus` telling
+    /// why the set couldn't be started, e.g. because the controller supports no more sets or the
+    /// data is too long. Returns false if the parameters are out of range, without calling
+    /// `callback`.
+    fn start_advertising_set(
+        &mut self,
+        parameters: AdvertisingSetParameters,
+        advertise_data: Vec<u8>,
+        scan_response: Vec<u8>,
+        duration: i32,
+        max_ext_adv_events: i32,
+        callback: Box<dyn IAdvertisingSetCallback + Send>,
+    ) -> bool;
+
+    /// Stops and releases an advertising set.
+    fn stop_advertising_set(&mut self, advertiser_id: i32) -> bool;
+
+    /// Enables or disables an advertising set, with the same limits as `start_advertising_set`.
+    ///
+    /// The result is reported through `IAdvertisingSetCallback::on_advertising_enabled`.
+    fn enable_advertising_set(
+        &mut self,
+        advertiser_id: i32,
+        enable: bool,
+        duration: i32,
+        max_ext_adv_events: i32,
+    ) -> bool;
+
+    /// Replaces the advertising data of an advertising set.
+    ///
+    /// The result is reported through `IAdvertisingSetCallback::on_advertising_data_set`.
+    /// Returns false if the data is longer than the set can advertise.
+    fn set_advertising_data(&mut self, advertiser_id: i32, data: Vec<u8>) -> bool;
+
+    /// Replaces the scan response data of an advertising set.
+    ///
+    /// The result is reported through `IAdvertisingSetCallback::on_scan_response_data_set`.
+    /// Returns false if the data is longer than the set can advertise.
+    fn set_scan_response_data(&mut self, advertiser_id: i32, data: Vec<u8>) -> bool;
+
+    /// Changes the parameters of an advertising set without stopping it.
+    ///
+    /// An enabled set is disabled while its parameters change, then enabled again. The result is
+    /// reported through `IAdvertisingSetCallback::on_advertising_parameters_updated`. Returns
+    /// false if the parameters are out of range or change the own address type of the set, or
+    /// an update of the set is already in progress.
+    fn set_advertising_parameters(
+        &mut self,
+        advertiser_id: i32,
+        parameters: AdvertisingSetParameters,
+    ) -> bool;
+
+    /// Reads the address an advertising set advertises with.
+    ///
+    /// The address is public, or random if LE privacy was enabled when the set started, see
+    /// `AdvertisingSetParameters::own_address_type`. The result is reported through
+    /// `IAdvertisingSetCallback::on_own_address_read`.
+    fn get_own_address(&mut self, advertiser_id: i32) -> bool;
+
+    /// Registers an advertisement monitor, which keeps LE scanning for matching advertisements
+    /// until it is unregistered.
+    ///
+    /// The monitor is offloaded to the controller when it supports advertising packet content
+    /// filters for all of its patterns, so that the host is only woken up by matching
+    /// advertisements. Devices are reported through
+    /// `IAdvertisementMonitorCallback::on_device_found_in_monitor`. Returns the id of the monitor,
+    /// or 0 if the monitor is invalid or the adapter is not enabled.
+    fn register_advertisement_monitor(
+        &mut self,
+        monitor: AdvertisementMonitor,
+        callback: Box<dyn IAdvertisementMonitorCallback + Send>,
+    ) -> i32;
+
+    /// Unregisters an advertisement monitor, stopping LE scanning if nothing else scans.
+    fn unregister_advertisement_monitor(&mut self, monitor_id: i32) -> bool;
+}
+
+/// Callback for GATT Client API, passed to `IBluetoothGatt::register_client`.
+pub trait IBluetoothGattCallback: RPCProxy {
+    /// When the `register_client` request is done.
+    fn on_client_registered(&self, status: GattStatus, client_id: i32);
+
+    /// When the client connects to or disconnects from a remote device.
+    ///
+    /// `conn_id` identifies the connection in the other requests of the client.
+    fn on_client_connection_state(
+        &self,
+        status: GattStatus,
+        connected: bool,
+        addr: String,
+        conn_id: i32,
+    );
+
+    /// When the `read_remote_rssi` request is done.
+    fn on_read_remote_rssi(&self, addr: String, rssi: i32, status: i32);
+
+    /// When the `discover_services` request is done.
+    fn on_search_complete(&self, conn_id: i32, status: GattStatus);
+
+    /// When the `read_using_characteristic_uuid` request is done.
+    ///
+    /// `handle` is the handle of the value that was read. As for the other requests answered by
+    /// the remote device, `status` is the raw `tGATT_STATUS`, so that the application errors of
+    /// the remote device (0x80 to 0x9F), which have no `GattStatus` value of their own, reach the
+    /// client as they are.
+    fn on_characteristic_read(&self, conn_id: i32, status: i32, handle: i32, value: Vec<u8>);
+
+    /// When the `write_characteristic` request is done, with the raw `tGATT_STATUS`.
+    fn on_characteristic_write(&self, conn_id: i32, status: i32, handle: i32);
+
+    /// When the `execute_write` or `abort_reliable_write` request is done, with the raw
+    /// `tGATT_STATUS`.
+    fn on_execute_write(&self, conn_id: i32, status: i32);
+
+    /// When the `register_for_notification` request is done, with the raw `tGATT_STATUS` of the
+    /// descriptor write if any. The client stays unsubscribed if it failed.
+    fn on_notification_registered(&self, conn_id: i32, status: i32, handle: i32, registered: bool);
+
+    /// When a device notifies or indicates the value of a characteristic the client subscribed
+    /// to with `register_for_notification`.
+    fn on_characteristic_changed(&self, conn_id: i32, handle: i32, value: Vec<u8>);
+
+    /// When an MTU exchange requested with `configure_mtu` is done, including the ones requested
+    /// again on reconnection.
+    fn on_configure_mtu(&self, conn_id: i32, status: GattStatus, mtu: i32);
+
+    /// When the PHYs of a connection of the client change, e.g. after the preferences set with
+    /// `IBluetooth::set_le_phy` were negotiated with the remote device.
+    ///
+    /// `tx_phy` and `rx_phy` are `LePhy` values.
+    fn on_phy_update(&self, addr: String, tx_phy: u32, rx_phy: u32, status: i32);
+
+    /// When the `read_phy` request is done. `tx_phy` and `rx_phy` are `LePhy` values.
+    fn on_phy_read(&self, addr: String, tx_phy: u32, rx_phy: u32, status: i32);
+
+    /// When the parameters of a connection of the client change, e.g. after a preset was applied
+    /// with `IBluetooth::set_le_connection_preset`.
+    ///
+    /// `interval` is in units of 1.25 ms, `latency` in connection events and `timeout` in units of
+    /// 10 ms.
+    fn on_connection_updated(
+        &self,
+        addr: String,
+        interval: i32,
+        latency: i32,
+        timeout: i32,
+        status: i32,
+    );
+
+    /// When a device the client is connected to indicates that its services changed.
+    ///
+    /// The database discovered on the connection and the subscriptions of the client to the
+    /// device are dropped, as their handles may now point to other attributes. The native stack
+    /// discovers the services again, and the new database is reported through
+    /// `on_search_complete` once it is done.
+    fn on_service_changed(&self, addr: String);
+}
+
+/// Callback for GATT Server API, passed to `IBluetoothGatt::register_server`.
+pub trait IBluetoothGattServerCallback: RPCProxy {
+    /// When the `register_server` request is done.
+    fn on_server_registered(&self, status: GattStatus, server_id: i32);
+
+    /// When a remote device connects to or disconnects from the server.
+    fn on_server_connection_state(&self, server_id: i32, connected: bool, addr: String);
+
+    /// When a notification queued with `send_notification` is sent, or confirmed by the remote
+    /// device for indications.
+    fn on_notification_sent(&self, addr: String, status: GattStatus);
+
+    /// When notifications to the device are accepted again after `send_notification` refused one.
+    fn on_notification_queue_available(&self, addr: String);
+
+    /// When a remote device writes a characteristic or descriptor of the server.
+    ///
+    /// Must be answered with `send_response` if `need_response` is set. Prepared writes are
+    /// buffered by the stack and only reported once the device executes them, without
+    /// `need_response`, followed by `on_execute_write_request`.
+    fn on_attribute_write_request(
+        &self,
+        addr: String,
+        trans_id: i32,
+        handle: i32,
+        offset: i32,
+        need_response: bool,
+        value: Vec<u8>,
+    );
+
+    /// When a remote device executes its prepared writes, which were all reported through
+    /// `on_attribute_write_request` beforehand.
+    ///
+    /// Must be answered with `send_response`. Cancelled prepared writes are discarded by the stack
+    /// without being reported.
+    fn on_execute_write_request(&self, addr: String, trans_id: i32);
+
+    /// When the `add_service` request is done. `service` holds the assigned handles if `status`
+    /// is `GattStatus::Success`.
+    fn on_service_added(&self, status: GattStatus, service: GattService);
+
+    /// When a remote device reads a characteristic or descriptor of the server.
+    ///
+    /// Must be answered with `send_response`. `is_long` is set when the device reads the rest of
+    /// a long value from `offset`.
+    fn on_attribute_read_request(
+        &self,
+        addr: String,
+        trans_id: i32,
+        handle: i32,
+        offset: i32,
+        is_long: bool,
+    );
+
+    /// When the `server_read_phy` request is done. `tx_phy` and `rx_phy` are `LePhy` values.
+    fn on_phy_read(&self, addr: String, tx_phy: u32, rx_phy: u32, status: i32);
+}
+
+/// Callback of an advertising set, passed to `IBluetoothGatt::start_advertising_set`.
+///
+/// `status` is an `AdvertisingStatus` value in all the methods.
+pub trait IAdvertisingSetCallback: RPCProxy {
+    /// When the `start_advertising_set` request is done. `advertiser_id` identifies the set in
+    /// the other requests, and `tx_power` is the power selected by the controller, in dBm.
+    fn on_advertising_set_started(&self, advertiser_id: i32, tx_power: i32, status: i32);
+
+    /// When the `get_own_address` request is done. `address_type` is 0 for a public address and
+    /// 1 for a random one.
+    fn on_own_address_read(&self, advertiser_id: i32, address_type: i32, address: String);
+
+    /// When the set is stopped with `stop_advertising_set`.
+    fn on_advertising_set_stopped(&self, advertiser_id: i32);
+
+    /// When the `enable_advertising_set` request is done, or the set stops advertising because
+    /// its duration or maximum number of events is reached.
+    fn on_advertising_enabled(&self, advertiser_id: i32, enable: bool, status: i32);
+
+    /// When the `set_advertising_data` request is done.
+    fn on_advertising_data_set(&self, advertiser_id: i32, status: i32);
+
+    /// When the `set_scan_response_data` request is done.
+    fn on_scan_response_data_set(&self, advertiser_id: i32, status: i32);
+
+    /// When the `set_advertising_parameters` request is done.
+    fn on_advertising_parameters_updated(&self, advertiser_id: i32, tx_power: i32, status: i32);
+}
+
+/// Callback of an advertisement monitor, passed to
+/// `IBluetoothGatt::register_advertisement_monitor`.
+pub trait IAdvertisementMonitorCallback: RPCProxy {
+    /// When a device whose advertisement matches the monitor is found.
+    ///
+    /// A device is found again only after the RSSI of its advertisements falls to the low
+    /// threshold of the monitor.
+    fn on_device_found_in_monitor(
+        &self,
+        monitor_id: i32,
+        addr: String,
+        rssi: i32,
+        adv_data: Vec<u8>,
+    );
 }
 
 /// Interface for scanner callbacks to clients, passed to `IBluetoothGatt::register_scanner`.
-pub trait IScannerCallback {
+pub trait IScannerCallback: RPCProxy {
     /// When the `register_scanner` request is done.
     fn on_scanner_registered(&self, status: i32, scanner_id: i32);
+
+    /// When a device is reported by a scan started with `start_scan`.
+    fn on_scan_result(&self, result: ScanResult);
+}
+
+/// An advertisement reported by a scan, see `IScannerCallback::on_scan_result`.
+///
+/// The fields of extended advertising hold their defaults for legacy advertisements, which
+/// controllers without extended advertising only receive: the LE 1M primary PHY,
+/// `SECONDARY_PHY_NONE`, `ADVERTISING_SID_NONE` and no periodic advertising.
+#[derive(Clone, Debug)]
+pub struct ScanResult {
+    /// Address of the advertiser, all zeros for anonymous advertisements.
+    pub address: String,
+    pub addr_type: i32,
+    /// Event type, as in the LE Extended Advertising Report event.
+    pub event_type: i32,
+    /// PHY of the advertisement, as a `LePhy`.
+    pub primary_phy: i32,
+    /// PHY of the auxiliary packets of the advertisement, as a `LePhy` or `SECONDARY_PHY_NONE`.
+    pub secondary_phy: i32,
+    pub advertising_sid: i32,
+    pub tx_power: i32,
+    pub rssi: i32,
+    /// Interval of the periodic advertising of the set, in units of 1.25 ms, 0 if there is none.
+    pub periodic_adv_int: i32,
+    pub adv_data: Vec<u8>,
+    /// Classified from the Appearance in `adv_data`, if any.
+    pub category: DeviceCategory,
+}
+
+impl ScanResult {
+    /// Returns whether the advertisement uses legacy advertising PDUs.
+    pub fn is_legacy(&self) -> bool {
+        self.event_type & SCAN_EVENT_TYPE_LEGACY != 0
+    }
+
+    /// Returns whether the advertisement is anonymous, in which case the advertiser can't be told
+    /// apart from others.
+    pub fn is_anonymous(&self) -> bool {
+        self.addr_type == BLE_ADDR_ANONYMOUS
+    }
+}
+
+/// What an `AdvertisementInterceptor` does with an advertisement it is offered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InterceptAction {
+    /// Leaves the advertisement to the other interceptors and the scanners.
+    Pass,
+    /// Claims the advertisement, which is not reported to the scanners and monitors.
+    Claim,
+    /// Claims the advertisement and bonds with the advertiser over LE, e.g. for a device in
+    /// pairing mode that the user already agreed to pair with. Anonymous advertisements are only
+    /// claimed.
+    ClaimAndBond,
+}
+
+/// A module that claims some advertisements before they reach the scanners, e.g. a Fast Pair
+/// provider, registered with `BluetoothGatt::register_advertisement_interceptor`.
+///
+/// Interceptors don't start scanning themselves. They are offered the advertisements received
+/// while scanning for the scanners or monitors that carry the service data they are interested
+/// in, in the order they registered. They are called with the
+/// GATT module locked, so they must not lock it themselves. Pairing flows that need the user,
+/// e.g. to confirm a pairing, are started later with `Bluetooth::create_bond_with_completion`.
+pub trait AdvertisementInterceptor: Send {
+    /// Returns the name of the interceptor, used for logging.
+    fn name(&self) -> String;
+
+    /// Returns the UUIDs of the service data the interceptor is offered the advertisements of.
+    fn service_data_uuids(&self) -> Vec<Uuid128>;
+
+    /// Handles an advertisement carrying service data of `uuid`.
+    fn intercept(&mut self, result: &ScanResult, uuid: Uuid128, data: &[u8]) -> InterceptAction;
 }
 
 #[derive(Debug, FromPrimitive, ToPrimitive)]
@@ -34,6 +846,48 @@ impl Default for ScanType {
     }
 }
 
+/// Selects which advertisements received by a scan are reported to the scanner.
+#[derive(Debug, FromPrimitive, ToPrimitive, Clone, Copy, PartialEq)]
+#[repr(i32)]
+pub enum ScanReportMode {
+    /// Every advertisement is reported.
+    All = 0,
+    /// Only the first advertisement of each device is reported.
+    FirstMatch = 1,
+    /// Each device seen during a report period is reported once at the end of the period, with
+    /// its latest advertisement and RSSI. Anonymous advertisements are each reported, as their
+    /// advertisers can't be told apart.
+    Periodic = 2,
+}
+
+impl Default for ScanReportMode {
+    fn default() -> Self {
+        ScanReportMode::All
+    }
+}
+
+/// Priority of an LE connection, see `IBluetoothGatt::request_connection_priority`. Ordered from
+/// the highest priority.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(i32)]
+pub enum ConnectionPriority {
+    /// Lowest latency, e.g. to transfer data quickly.
+    High = 0,
+    Balanced = 1,
+    /// Lowest power consumption, at the cost of latency.
+    LowPower = 2,
+}
+
+impl ConnectionPriority {
+    fn preset(&self) -> LeConnectionPreset {
+        match self {
+            ConnectionPriority::High => LeConnectionPreset::LowLatencyHid,
+            ConnectionPriority::Balanced => LeConnectionPreset::Balanced,
+            ConnectionPriority::LowPower => LeConnectionPreset::LowPowerSensor,
+        }
+    }
+}
+
 /// Represents RSSI configurations for hardware offloaded scanning.
 // TODO: This is still a placeholder struct, not yet complete.
 #[derive(Debug, Default)]
@@ -45,42 +899,4227 @@ pub struct RSSISettings {
 /// Represents scanning configurations to be passed to `IBluetoothGatt::start_scan`.
 #[derive(Debug, Default)]
 pub struct ScanSettings {
+    /// Scan interval and window, in units of 0.625 ms, between 0x0004 and 0x4000. The window
+    /// can't be longer than the interval. A default duty cycle applies if both are 0.
     pub interval: i32,
     pub window: i32,
+    /// Whether the scan runs in the background rather than for an app on screen. Background
+    /// scans don't raise the duty cycle of the controller.
+    pub background: bool,
     pub scan_type: ScanType,
     pub rssi_settings: RSSISettings,
+    pub report_mode: ScanReportMode,
+    /// Report period of the `Periodic` mode, in milliseconds.
+    pub report_period_ms: i32,
 }
 
 /// Represents a scan filter to be passed to `IBluetoothGatt::start_scan`.
 #[derive(Debug, Default)]
 pub struct ScanFilter {}
 
-/// Implementation of the GATT API (IBluetoothGatt).
-pub struct BluetoothGatt {
-    _intf: Arc<Mutex<BluetoothInterface>>,
+/// A pattern of advertising data, as part of an `AdvertisementMonitor`.
+#[derive(Debug, Default, Clone)]
+pub struct AdvertisementPattern {
+    /// Type of the advertising data structure the pattern is in, e.g. 0xFF for manufacturer
+    /// specific data.
+    pub ad_type: u8,
+    /// Offset of the pattern in the data of the structure, which follows its type.
+    pub start_position: u8,
+    pub content: Vec<u8>,
 }
 
-impl BluetoothGatt {
-    /// Constructs a new IBluetoothGatt implementation.
-    pub fn new(intf: Arc<Mutex<BluetoothInterface>>) -> BluetoothGatt {
-        BluetoothGatt { _intf: intf }
+impl AdvertisementPattern {
+    /// Returns whether advertising data has a structure that contains the pattern.
+    fn matches(&self, adv_data: &[u8]) -> bool {
+        let start = self.start_position as usize;
+        let end = start + self.content.len();
+        ad_structures(adv_data).any(|(ad_type, data)| {
+            ad_type == self.ad_type && data.get(start..end) == Some(&self.content[..])
+        })
+    }
+
+    /// Returns the equivalent condition of a controller filter, if there is one.
+    fn to_apcf_condition(&self) -> Option<BtApcfCondition> {
+        if self.start_position != 0 {
+            return None;
+        }
+
+        let (filter_type, company, company_mask, data) = match self.ad_type {
+            // The company identifier is matched on its own.
+            AD_TYPE_MANUFACTURER_DATA if self.content.len() >= 2 => (
+                APCF_MANUFACTURER_DATA,
+                u16::from_le_bytes([self.content[0], self.content[1]]),
+                0xFFFF,
+                &self.content[2..],
+            ),
+            AD_TYPE_SERVICE_DATA_16 => (APCF_SERVICE_DATA_PATTERN, 0, 0, &self.content[..]),
+            _ => return None,
+        };
+
+        if data.len() > APCF_DATA_MAX_LEN {
+            return None;
+        }
+
+        Some(BtApcfCondition {
+            filter_type,
+            company,
+            company_mask,
+            data: data.to_vec(),
+            data_mask: vec![0xFF; data.len()],
+        })
     }
 }
 
-impl IBluetoothGatt for BluetoothGatt {
-    fn register_scanner(&self, _callback: Box<dyn IScannerCallback + Send>) {
-        // TODO: implement
+/// Conditions of an advertisement monitor, passed to
+/// `IBluetoothGatt::register_advertisement_monitor`.
+#[derive(Debug, Default, Clone)]
+pub struct AdvertisementMonitor {
+    /// An advertisement matches if it contains any of the patterns.
+    pub patterns: Vec<AdvertisementPattern>,
+    /// A device is found once the RSSI of a matching advertisement is at or above this, in dBm.
+    pub rssi_high_threshold: i32,
+    /// A found device can be found again once the RSSI of its advertisements falls to or below
+    /// this, in dBm.
+    pub rssi_low_threshold: i32,
+}
+
+impl AdvertisementMonitor {
+    fn is_valid(&self) -> bool {
+        let rssi_range = MONITOR_RSSI_MIN..=MONITOR_RSSI_MAX;
+        !self.patterns.is_empty()
+            && self.patterns.iter().all(|pattern| {
+                !pattern.content.is_empty()
+                    && pattern.start_position as usize + pattern.content.len()
+                        <= AD_STRUCTURE_DATA_MAX_LEN
+            })
+            && rssi_range.contains(&self.rssi_high_threshold)
+            && rssi_range.contains(&self.rssi_low_threshold)
+            && self.rssi_low_threshold <= self.rssi_high_threshold
+    }
+
+    /// Returns whether advertising data contains any of the patterns.
+    fn matches(&self, adv_data: &[u8]) -> bool {
+        self.patterns.iter().any(|pattern| pattern.matches(adv_data))
+    }
+
+    /// Returns the conditions of the equivalent controller filter, or None if some pattern can't
+    /// be offloaded.
+    fn to_apcf_conditions(&self) -> Option<Vec<BtApcfCondition>> {
+        self.patterns.iter().map(|pattern| pattern.to_apcf_condition()).collect()
+    }
+}
+
+/// A characteristic or descriptor of a `GattService`.
+#[derive(Debug, Clone)]
+pub struct GattAttribute {
+    /// `GattDbElementType::Characteristic` or `GattDbElementType::Descriptor`. Descriptors belong
+    /// to the characteristic before them.
+    pub attribute_type: i32,
+    pub uuid: Uuid128,
+    /// Assigned by the stack when the service is added.
+    pub handle: i32,
+    /// Properties of a characteristic, see `GATT_CHAR_PROP_BIT_READ` and the following.
+    pub properties: i32,
+    /// Bit 0 allows reads, bit 1 requires them to be encrypted and bit 2 to be encrypted with a
+    /// key exchanged with MITM protection. Bits 4 to 6 do the same for writes.
+    pub permissions: i32,
+}
+
+/// A service of a GATT server, passed to `IBluetoothGatt::add_service`.
+#[derive(Debug, Clone)]
+pub struct GattService {
+    pub uuid: Uuid128,
+    /// Handle of the service declaration, assigned by the stack when the service is added.
+    pub handle: i32,
+    pub primary: bool,
+    pub attributes: Vec<GattAttribute>,
+}
+
+impl GattService {
+    fn is_valid(&self) -> bool {
+        let known_permissions = GATT_PERM_READ
+            | GATT_PERM_READ_ENCRYPTED
+            | GATT_PERM_READ_ENC_MITM
+            | GATT_PERM_WRITE
+            | GATT_PERM_WRITE_ENCRYPTED
+            | GATT_PERM_WRITE_ENC_MITM;
+
+        self.attributes.first().map_or(false, |attribute| {
+            attribute.attribute_type == GattDbElementType::Characteristic as i32
+        }) && self.attributes.iter().all(|attribute| {
+            (attribute.attribute_type == GattDbElementType::Characteristic as i32
+                || attribute.attribute_type == GattDbElementType::Descriptor as i32)
+                && u8::try_from(attribute.properties).is_ok()
+                && attribute.permissions & !known_permissions == 0
+        })
+    }
+
+    /// Returns the elements that add the service to the native database.
+    ///
+    /// The encryption requirements are left out of the permissions, so that the native stack
+    /// reports the requests and they can be rejected with an attempt to encrypt the link.
+    fn to_db_elements(&self) -> Vec<BtGattDbElement> {
+        let service_type = if self.primary {
+            GattDbElementType::PrimaryService
+        } else {
+            GattDbElementType::SecondaryService
+        };
+
+        std::iter::once(BtGattDbElement {
+            id: 0,
+            uuid: self.uuid.into(),
+            element_type: service_type as i32,
+            attribute_handle: 0,
+            start_handle: 0,
+            end_handle: 0,
+            properties: 0,
+            extended_properties: 0,
+            permissions: 0,
+        })
+        .chain(self.attributes.iter().map(|attribute| {
+            let mut permissions = attribute.permissions & (GATT_PERM_READ | GATT_PERM_WRITE);
+            if attribute.permissions & (GATT_PERM_READ_ENCRYPTED | GATT_PERM_READ_ENC_MITM) != 0 {
+                permissions |= GATT_PERM_READ;
+            }
+            if attribute.permissions & (GATT_PERM_WRITE_ENCRYPTED | GATT_PERM_WRITE_ENC_MITM) != 0 {
+                permissions |= GATT_PERM_WRITE;
+            }
+
+            BtGattDbElement {
+                id: 0,
+                uuid: attribute.uuid.into(),
+                element_type: attribute.attribute_type,
+                attribute_handle: 0,
+                start_handle: 0,
+                end_handle: 0,
+                properties: attribute.properties as u8,
+                extended_properties: 0,
+                permissions: permissions as u16,
+            }
+        }))
+        .collect()
+    }
+}
+
+/// Returns the interval and window of a scan, or None if they are out of range.
+fn scan_parameters(settings: &ScanSettings) -> Option<(i32, i32)> {
+    if settings.interval == 0 && settings.window == 0 {
+        return Some(SCAN_DEFAULT_PARAMETERS);
+    }
+
+    let range = SCAN_INTERVAL_MIN..=SCAN_INTERVAL_MAX;
+    if !range.contains(&settings.interval)
+        || !range.contains(&settings.window)
+        || settings.window > settings.interval
+    {
+        return None;
+    }
+
+    Some((settings.interval, settings.window))
+}
+
+/// Iterates over the type and data of the structures of advertising data.
+fn ad_structures(adv_data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut rest = adv_data;
+    std::iter::from_fn(move || {
+        let len = *rest.first()? as usize;
+        // A structure of length 0 ends the significant part of the data.
+        if len == 0 || rest.len() < len + 1 {
+            return None;
+        }
+
+        let (ad_type, data) = (rest[1], &rest[2..len + 1]);
+        rest = &rest[len + 1..];
+        Some((ad_type, data))
+    })
+}
+
+/// Iterates over the UUIDs and data of the service data in advertising data.
+fn ad_service_data(adv_data: &[u8]) -> impl Iterator<Item = (Uuid128, &[u8])> {
+    ad_structures(adv_data).filter_map(|(ad_type, data)| match ad_type {
+        AD_TYPE_SERVICE_DATA_16 if data.len() >= 2 => {
+            Some((Uuid128::from_u16(u16::from_le_bytes([data[0], data[1]])), &data[2..]))
+        }
+        AD_TYPE_SERVICE_DATA_32 if data.len() >= 4 => {
+            let uuid = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            Some((Uuid128::from_u32(uuid), &data[4..]))
+        }
+        AD_TYPE_SERVICE_DATA_128 if data.len() >= 16 => {
+            // The UUID is little endian in the data, and big endian in `Uuid128`.
+            let mut uuid = [0u8; 16];
+            uuid.copy_from_slice(&data[..16]);
+            uuid.reverse();
+            Some((Uuid128::from_bytes(uuid), &data[16..]))
+        }
+        _ => None,
+    })
+}
+
+/// Returns the Appearance in advertising data, if any.
+fn ad_appearance(adv_data: &[u8]) -> Option<u16> {
+    ad_structures(adv_data)
+        .find(|(ad_type, data)| *ad_type == AD_TYPE_APPEARANCE && data.len() == 2)
+        .map(|(_, data)| u16::from_le_bytes([data[0], data[1]]))
+}
+
+/// Status of the advertising requests, reported through `IAdvertisingSetCallback`.
+///
+/// Failures of the controller are reported with its own status codes.
+#[derive(Debug, FromPrimitive, ToPrimitive, Clone, Copy, PartialEq)]
+#[repr(i32)]
+pub enum AdvertisingStatus {
+    Success = 0,
+    /// The data is longer than the set can advertise.
+    DataTooLarge = 1,
+    /// The controller supports no more advertising sets.
+    TooManyAdvertisers = 2,
+    AlreadyStarted = 3,
+    /// The adapter is not enabled.
+    InternalError = 4,
+    /// The set needs extended advertising, which the controller doesn't support, or an own
+    /// address type that LE privacy doesn't currently give.
+    FeatureUnsupported = 5,
+}
+
+/// Type of the address an advertising set advertises with.
+///
+/// The native stack picks the address of a set when it starts, from the LE privacy of the
+/// adapter (see `IBluetooth::set_le_privacy`), so a set can only ask for the type that privacy
+/// currently gives. The values match the `address_type` of
+/// `IAdvertisingSetCallback::on_own_address_read`.
+#[derive(Debug, FromPrimitive, ToPrimitive, Clone, Copy, PartialEq)]
+#[repr(i32)]
+pub enum OwnAddressType {
+    /// Whichever type LE privacy gives.
+    Default = -1,
+    Public = 0,
+    /// A resolvable private address, rotated by the stack.
+    ResolvablePrivate = 1,
+}
+
+impl Default for OwnAddressType {
+    fn default() -> Self {
+        OwnAddressType::Default
+    }
+}
+
+impl OwnAddressType {
+    /// Returns whether a set started now can advertise with this type of address.
+    fn is_available(&self, le_privacy: bool) -> bool {
+        match self {
+            OwnAddressType::Default => true,
+            OwnAddressType::Public => !le_privacy,
+            OwnAddressType::ResolvablePrivate => le_privacy,
+        }
+    }
+}
+
+/// Parameters of an advertising set, passed to `IBluetoothGatt::start_advertising_set`.
+#[derive(Debug, Default, Clone)]
+pub struct AdvertisingSetParameters {
+    pub connectable: bool,
+    pub scannable: bool,
+    /// Uses legacy advertising PDUs, which limits the data to 31 bytes. Sets are extended
+    /// otherwise, and can't be both connectable and scannable.
+    pub is_legacy: bool,
+    /// Advertising interval, in units of 0.625 ms.
+    pub interval: i32,
+    /// Requested TX power, in dBm. The controller reports the power it selected.
+    pub tx_power_level: i32,
+    /// Address the set advertises with. It can't be changed once the set is started.
+    pub own_address_type: OwnAddressType,
+}
+
+impl AdvertisingSetParameters {
+    fn is_valid(&self) -> bool {
+        let interval_max = match self.is_legacy {
+            true => LEGACY_ADVERTISING_INTERVAL_MAX,
+            false => ADVERTISING_INTERVAL_MAX,
+        };
+
+        self.interval >= ADVERTISING_INTERVAL_MIN
+            && self.interval <= interval_max
+            && self.tx_power_level >= ADVERTISING_TX_POWER_MIN
+            && self.tx_power_level <= ADVERTISING_TX_POWER_MAX
+            && (self.is_legacy || !(self.connectable && self.scannable))
+    }
+
+    fn to_advertise_parameters(&self) -> BtAdvertiseParameters {
+        let mut properties = 0;
+        if self.connectable {
+            properties |= ADVERTISING_PROPERTY_CONNECTABLE;
+        }
+        if self.scannable {
+            properties |= ADVERTISING_PROPERTY_SCANNABLE;
+        }
+        if self.is_legacy {
+            properties |= ADVERTISING_PROPERTY_LEGACY;
+        }
+
+        BtAdvertiseParameters {
+            advertising_event_properties: properties,
+            min_interval: self.interval as u32,
+            max_interval: self.interval as u32,
+            channel_map: ADVERTISING_CHANNEL_ALL,
+            tx_power: self.tx_power_level as i8,
+            primary_advertising_phy: ADVERTISING_PHY_LE_1M,
+            secondary_advertising_phy: ADVERTISING_PHY_LE_1M,
+            scan_request_notification_enable: 0,
+        }
+    }
+
+    /// Returns the longest advertising or scan response data of a set with these parameters.
+    fn max_data_len(&self) -> usize {
+        match self.is_legacy {
+            true => LEGACY_ADVERTISING_DATA_MAX_LEN,
+            false => Controller::new().get_info().le_max_advertising_data_length.into(),
+        }
+    }
+}
+
+/// A GATT client application registered through `IBluetoothGatt::register_client`.
+struct GattClient {
+    /// Assigned by the stack once the registration is done.
+    id: Option<i32>,
+    uuid: Uuid128,
+    /// Id of the callback of the application in `BluetoothGatt::client_callbacks`.
+    callback_id: u32,
+}
+
+/// An LE scanner registered through `IBluetoothGatt::register_scanner`.
+struct Scanner {
+    /// Assigned by the stack once the registration is done.
+    id: Option<i32>,
+    uuid: Uuid128,
+    /// Id of the callback of the scanner in `BluetoothGatt::scanner_callbacks`.
+    callback_id: u32,
+    /// Set while the scanner is scanning.
+    scan: Option<ActiveScan>,
+}
+
+/// An advertisement monitor registered through `IBluetoothGatt::register_advertisement_monitor`.
+struct Monitor {
+    id: i32,
+    /// Id of the callback of the monitor in `BluetoothGatt::monitor_callbacks`.
+    callback_id: u32,
+    monitor: AdvertisementMonitor,
+    /// Index of the filter offloaded to the controller, if any.
+    filter_index: Option<u8>,
+    /// Devices found and not yet below the low RSSI threshold.
+    found: HashSet<BDAddr>,
+}
+
+/// Reporting state of the scan of a scanner.
+struct ActiveScan {
+    /// Interval and window the scan asked for.
+    parameters: (i32, i32),
+    background: bool,
+    report_mode: ScanReportMode,
+    /// Devices already reported in the `FirstMatch` mode.
+    reported: HashSet<BDAddr>,
+    /// Latest result of each device seen during the current period of the `Periodic` mode.
+    batch: HashMap<BDAddr, ScanResult>,
+    /// Anonymous advertisements seen during the current period of the `Periodic` mode, which
+    /// can't be told apart by address.
+    anonymous_batch: Vec<ScanResult>,
+    /// Ends the report periods of the `Periodic` mode.
+    report_ticker: Option<JoinHandle<()>>,
+}
+
+/// A GATT server application registered through `IBluetoothGatt::register_server`.
+struct GattServer {
+    /// Assigned by the stack once the registration is done.
+    id: Option<i32>,
+    uuid: Uuid128,
+    /// Id of the callback of the application in `BluetoothGatt::server_callbacks`.
+    callback_id: u32,
+}
+
+/// A throughput test in progress, see `IBluetoothDebug::start_throughput_test`.
+struct ThroughputTest {
+    /// Tells the timeout of the test apart from the ones of earlier tests.
+    id: u32,
+    mode: ThroughputTestMode,
+    addr: BDAddr,
+    /// Connection of the test client or server to the device.
+    conn_id: i32,
+    /// Handle of the value of the test characteristic, once known.
+    handle: Option<i32>,
+    duration: Duration,
+    payload_len: usize,
+    /// Set once the packets start flowing.
+    started: Option<Instant>,
+    /// Ends the test once its duration is over.
+    timer: Option<JoinHandle<()>>,
+    /// When each packet in flight was handed to the stack, oldest first.
+    in_flight: VecDeque<Instant>,
+    packet_count: u32,
+    byte_count: u64,
+    failure_count: u32,
+    latencies_us: Vec<u32>,
+}
+
+impl ThroughputTest {
+    fn into_result(mut self, status: GattStatus) -> ThroughputTestResult {
+        let duration_ms = self.started.map_or(0, |started| started.elapsed().as_millis() as u32);
+
+        self.latencies_us.sort_unstable();
+        let latencies = &self.latencies_us;
+        let percentile = |p: usize| match latencies.len() {
+            0 => 0,
+            len => latencies[(len - 1) * p / 100],
+        };
+
+        ThroughputTestResult {
+            status: status as i32,
+            duration_ms,
+            payload_len: self.payload_len as u32,
+            packet_count: self.packet_count,
+            byte_count: self.byte_count,
+            failure_count: self.failure_count,
+            // Bits per millisecond are kbit/s.
+            kbps: match duration_ms {
+                0 => 0,
+                ms => (self.byte_count * 8 / ms as u64) as u32,
+            },
+            latency_p50_us: percentile(50),
+            latency_p90_us: percentile(90),
+            latency_p99_us: percentile(99),
+        }
     }
+}
 
-    fn unregister_scanner(&self, _scanner_id: i32) {
-        // TODO: implement
+/// Returns the test service of the throughput tests, whose characteristic takes writes without
+/// response and sends notifications.
+fn throughput_service() -> GattService {
+    GattService {
+        uuid: Uuid128::from_bytes(THROUGHPUT_SERVICE_UUID),
+        handle: 0,
+        primary: true,
+        attributes: vec![
+            GattAttribute {
+                attribute_type: GattDbElementType::Characteristic as i32,
+                uuid: Uuid128::from_bytes(THROUGHPUT_CHARACTERISTIC_UUID),
+                handle: 0,
+                properties: GATT_CHAR_PROP_WRITE_NR | GATT_CHAR_PROP_NOTIFY,
+                permissions: GATT_PERM_WRITE,
+            },
+            GattAttribute {
+                attribute_type: GattDbElementType::Descriptor as i32,
+                uuid: Uuid128::from_u16(CCCD_UUID),
+                handle: 0,
+                properties: 0,
+                permissions: GATT_PERM_READ | GATT_PERM_WRITE,
+            },
+        ],
     }
+}
+
+/// A service added by a server through `IBluetoothGatt::add_service`.
+struct LocalService {
+    server_id: i32,
+    uuid: Uuid128,
+    primary: bool,
+    /// Handles of the attributes of the service, keys of `BluetoothGatt::local_attributes`.
+    attribute_handles: Vec<i32>,
+}
+
+/// An attribute of a `LocalService`.
+struct LocalAttribute {
+    /// The server that added the service of the attribute, the only one that gets its requests.
+    server_id: i32,
+    permissions: i32,
+}
+
+struct PendingNotification {
+    handle: i32,
+    value: Vec<u8>,
+    confirm: bool,
+}
+
+/// Schedules the notifications sent by a server over one connection.
+struct NotificationQueue {
+    server_id: i32,
+    addr: BDAddr,
+    congested: bool,
+    /// Whether a notification was handed to the stack and `indication_sent` is not received yet.
+    in_flight: bool,
+    /// Whether a notification was refused since the queue was last available.
+    refused: bool,
+    pending: VecDeque<PendingNotification>,
+}
+
+/// Connection of a client to a remote device.
+struct ClientConnection {
+    client_id: i32,
+    addr: BDAddr,
+}
+
+/// A write of a Client Characteristic Configuration descriptor, made to apply a change of the
+/// subscriptions to a characteristic.
+struct CccdWrite {
+    /// Handle of the characteristic value.
+    handle: i32,
+    enable: bool,
+}
+
+/// A read of a client in flight, see `BluetoothGatt::client_reads`.
+enum ClientRead {
+    /// Read with `read_using_characteristic_uuid`, with the authentication requirements it was
+    /// requested with.
+    ByUuid(i32),
+    /// Read again by handle, as the value read by UUID may have been cut to fit the response.
+    Long,
+    /// Read of the Database Hash characteristic once the services are discovered, which
+    /// `on_search_complete` waits for.
+    DatabaseHash,
+}
+
+/// Reliable write transaction started by a client on a connection.
+struct ReliableWrite {
+    client_id: i32,
+    /// Whether the transaction is being executed or aborted.
+    closing: bool,
+}
+
+/// A write of a remote device queued until it executes its prepared writes.
+struct PreparedWrite {
+    handle: i32,
+    offset: i32,
+    value: Vec<u8>,
+}
+
+/// An advertising set started through `IBluetoothGatt::start_advertising_set`.
+struct AdvertisingSet {
+    /// Identifies the set in the native stack until it is started.
+    reg_id: i32,
+    /// Assigned by the stack once the set is started.
+    id: Option<i32>,
+    /// Id of the callback of the set in `BluetoothGatt::advertiser_callbacks`.
+    callback_id: u32,
+    parameters: AdvertisingSetParameters,
+    enabled: bool,
+    /// Limits of the latest enabling, applied again when the set is enabled after a parameter
+    /// update.
+    duration: u16,
+    max_ext_adv_events: u8,
+    /// Parameters being applied by `set_advertising_parameters`.
+    pending_parameters: Option<AdvertisingSetParameters>,
+    /// Set while the set is disabled for a parameter update, whose enabling and disabling are
+    /// not reported.
+    updating: bool,
+}
+
+/// Throttling state of the RSSI reads of a remote device.
+#[derive(Default)]
+struct RssiReadState {
+    last_read: Option<Instant>,
+    last_rssi: i32,
+    /// Clients waiting for the result of the read in flight, if any.
+    waiting_clients: Vec<i32>,
+}
+
+/// Implementation of the GATT API (IBluetoothGatt).
+pub struct BluetoothGatt {
+    intf: Arc<Mutex<BluetoothInterface>>,
+    feature_flags: Arc<Mutex<FeatureFlags>>,
+    bluetooth: Arc<Mutex<Bluetooth>>,
+    metrics: Arc<Mutex<Metrics>>,
+    gatt: Option<Gatt>,
+    tx: MessageSender,
+    scanners: Vec<Scanner>,
+    scanner_callbacks: Callbacks<dyn IScannerCallback + Send>,
+    /// Number of scanner registrations, which makes the uuid of each registration unique.
+    scanner_registrations: u32,
+    monitors: Vec<Monitor>,
+    /// Number of monitors registered, which makes the id of each monitor unique.
+    monitor_registrations: i32,
+    monitor_callbacks: Callbacks<dyn IAdvertisementMonitorCallback + Send>,
+    /// Whether the controller only reports the advertisements that pass the monitor filters.
+    scan_filter_enabled: bool,
+    /// Interval and window last set in the controller.
+    scan_parameters: Option<(i32, i32)>,
+    interceptors: Vec<Box<dyn AdvertisementInterceptor>>,
+    clients: Vec<GattClient>,
+    client_callbacks: Callbacks<dyn IBluetoothGattCallback + Send>,
+    /// Keyed by connection id.
+    client_connections: HashMap<i32, ClientConnection>,
+    /// MTU requested by a client for a device, requested again when the client reconnects.
+    preferred_mtus: HashMap<(i32, BDAddr), i32>,
+    /// Negotiated MTU of the client and server connections, keyed by connection id.
+    mtus: HashMap<i32, i32>,
+    rssi_reads: HashMap<BDAddr, RssiReadState>,
+    /// Database discovered on each client connection, keyed by connection id.
+    gatt_dbs: HashMap<i32, Vec<BtGattDbElement>>,
+    /// Database Hash read on each client connection, keyed by connection id.
+    database_hashes: HashMap<i32, Vec<u8>>,
+    /// Client connections whose device indicated that its services changed, waiting for the
+    /// native stack to discover them again.
+    stale_dbs: HashSet<i32>,
+    /// Clients waiting for the result of their writes, with the times of the requests, in request
+    /// order, keyed by connection id.
+    client_writes: HashMap<i32, VecDeque<(i32, Instant)>>,
+    /// Reads of characteristics in request order, keyed by connection id. Values read by UUID
+    /// are cut to what fits in a Read By Type response, so those that may be longer are read
+    /// again by handle, which the stack completes with Read Blob requests.
+    client_reads: HashMap<i32, VecDeque<ClientRead>>,
+    /// Keyed by connection id.
+    reliable_writes: HashMap<i32, ReliableWrite>,
+    /// Clients subscribed to the notifications of a characteristic, keyed by device and handle of
+    /// the characteristic value.
+    subscriptions: HashMap<(BDAddr, i32), HashSet<i32>>,
+    /// Descriptor writes of `register_for_notification`, in request order, keyed by connection
+    /// id.
+    cccd_writes: HashMap<i32, VecDeque<CccdWrite>>,
+    servers: Vec<GattServer>,
+    server_callbacks: Callbacks<dyn IBluetoothGattServerCallback + Send>,
+    /// Keyed by connection id.
+    notification_queues: HashMap<i32, NotificationQueue>,
+    /// Keyed by connection id.
+    prepared_writes: HashMap<i32, Vec<PreparedWrite>>,
+    /// Services being added, in request order, keyed by server id.
+    pending_services: HashMap<i32, VecDeque<GattService>>,
+    /// Keyed by the handle of the service declaration.
+    local_services: HashMap<i32, LocalService>,
+    /// Attributes of the local services, keyed by handle. The native database has a single handle
+    /// space for every server, this tells which server each attribute belongs to.
+    local_attributes: HashMap<i32, LocalAttribute>,
+    /// Ids of the client and server applications of the throughput tests, once registered.
+    throughput_client_id: Option<i32>,
+    throughput_server_id: Option<i32>,
+    /// Handle of the value of the test characteristic in the local database, once added.
+    throughput_handle: Option<i32>,
+    throughput_test: Option<ThroughputTest>,
+    /// Number of throughput tests started, which makes the id of each test unique.
+    throughput_tests: u32,
+    /// Id of the client application of the HOGP host, once registered.
+    hogp_client_id: Option<i32>,
+    /// HID devices that the HOGP host connects to whenever they are in range.
+    hogp_addrs: HashSet<BDAddr>,
+    /// Keyed by connection id.
+    hogp_devices: HashMap<i32, HogpDevice>,
+    advertising_sets: Vec<AdvertisingSet>,
+    /// Number of advertising sets started, which makes the registration id of each set unique.
+    advertiser_registrations: i32,
+    advertiser_callbacks: Callbacks<dyn IAdvertisingSetCallback + Send>,
+    /// Completions of the client registrations, keyed by the id of the callback of the client.
+    client_registrations: Completers<u32, (GattStatus, i32)>,
+    /// Connection priorities requested by the clients, keyed by device and by client id.
+    connection_priorities: HashMap<BDAddr, HashMap<i32, ConnectionPriority>>,
+}
+
+impl BluetoothGatt {
+    /// Constructs a new IBluetoothGatt implementation.
+    pub fn new(
+        tx: MessageSender,
+        intf: Arc<Mutex<BluetoothInterface>>,
+        feature_flags: Arc<Mutex<FeatureFlags>>,
+        bluetooth: Arc<Mutex<Bluetooth>>,
+        metrics: Arc<Mutex<Metrics>>,
+    ) -> BluetoothGatt {
+        BluetoothGatt {
+            intf,
+            feature_flags,
+            bluetooth,
+            metrics,
+            gatt: None,
+            client_callbacks: Callbacks::new(tx.clone(), Message::GattClientCallbackDisconnected),
+            server_callbacks: Callbacks::new(tx.clone(), Message::GattServerCallbackDisconnected),
+            advertiser_callbacks: Callbacks::new(
+                tx.clone(),
+                Message::GattAdvertiserCallbackDisconnected,
+            ),
+            monitor_callbacks: Callbacks::new(tx.clone(), Message::GattMonitorCallbackDisconnected),
+            scanner_callbacks: Callbacks::new(tx.clone(), Message::GattScannerCallbackDisconnected),
+            tx,
+            scanners: vec![],
+            scanner_registrations: 0,
+            monitors: vec![],
+            monitor_registrations: 0,
+            scan_filter_enabled: false,
+            scan_parameters: None,
+            interceptors: vec![],
+            clients: vec![],
+            client_connections: HashMap::new(),
+            preferred_mtus: HashMap::new(),
+            mtus: HashMap::new(),
+            rssi_reads: HashMap::new(),
+            gatt_dbs: HashMap::new(),
+            stale_dbs: HashSet::new(),
+            database_hashes: HashMap::new(),
+            client_writes: HashMap::new(),
+            client_reads: HashMap::new(),
+            reliable_writes: HashMap::new(),
+            subscriptions: HashMap::new(),
+            cccd_writes: HashMap::new(),
+            servers: vec![],
+            notification_queues: HashMap::new(),
+            prepared_writes: HashMap::new(),
+            pending_services: HashMap::new(),
+            local_services: HashMap::new(),
+            local_attributes: HashMap::new(),
+            throughput_client_id: None,
+            throughput_server_id: None,
+            throughput_handle: None,
+            throughput_test: None,
+            throughput_tests: 0,
+            hogp_client_id: None,
+            hogp_addrs: HashSet::new(),
+            hogp_devices: HashMap::new(),
+            advertising_sets: vec![],
+            advertiser_registrations: 0,
+            client_registrations: Completers::new(),
+            connection_priorities: HashMap::new(),
+        }
+    }
+
+    /// Loads and initializes the GATT profile. Must be called after the Bluetooth interface is
+    /// initialized.
+    ///
+    /// GATT stays unavailable if the native stack doesn't support it.
+    pub fn init_profiles(&mut self) {
+        if !self.intf.lock().unwrap().supports_profile(SupportedProfiles::Gatt) {
+            eprintln!("GATT is not supported by the native stack");
+            return;
+        }
+
+        let mut gatt = Gatt::new(&self.intf.lock().unwrap());
+        if !gatt.initialize(Arc::new(btif_gatt_callbacks(self.tx.clone()))) {
+            eprintln!("Failed to initialize GATT");
+            return;
+        }
+
+        // The test service has to be served by both devices of a throughput test.
+        if self.feature_flags.lock().unwrap().is_enabled(Feature::ThroughputTest) {
+            let throughput_uuid = Uuid128::from_bytes(THROUGHPUT_APP_UUID).into();
+            gatt.register_client(&throughput_uuid, false);
+            gatt.register_server(&throughput_uuid, false);
+        }
+        gatt.register_client(&Uuid128::from_bytes(HOGP_APP_UUID).into(), false);
+
+        self.gatt = Some(gatt);
+    }
+
+    /// Starts a throughput test with a connected device, see
+    /// `IBluetoothDebug::start_throughput_test`.
+    ///
+    /// The database of the device is discovered first if the test writes to it and it is not
+    /// known yet. The result is posted as a `Message::DebugThroughputTestDone` once the test
+    /// ends.
+    pub(crate) fn start_throughput_test(
+        &mut self,
+        addr: BDAddr,
+        mode: ThroughputTestMode,
+        duration_secs: u32,
+    ) -> bool {
+        if self.throughput_test.is_some()
+            || !(1..=THROUGHPUT_TEST_MAX_DURATION_SECS).contains(&duration_secs)
+        {
+            return false;
+        }
+
+        let conn_id = match mode {
+            ThroughputTestMode::WriteWithoutResponse => {
+                self.throughput_client_id.and_then(|id| self.find_client_conn_id(id, addr))
+            }
+            ThroughputTestMode::Notification => {
+                self.throughput_server_id.and_then(|id| self.find_server_conn_id(id, addr))
+            }
+        };
+
+        let conn_id = match conn_id {
+            Some(conn_id) => conn_id,
+            None => return false,
+        };
+
+        let handle = match mode {
+            ThroughputTestMode::WriteWithoutResponse => self
+                .find_characteristic(conn_id, Uuid128::from_bytes(THROUGHPUT_CHARACTERISTIC_UUID))
+                .map(|(handle, _)| handle as i32),
+            ThroughputTestMode::Notification => match self.throughput_handle {
+                Some(handle) => Some(handle),
+                None => return false,
+            },
+        };
+
+        if handle.is_none() {
+            match &mut self.gatt {
+                Some(gatt) if gatt.search_service(conn_id) == 0 => {}
+                _ => return false,
+            }
+        }
+
+        self.throughput_tests = self.throughput_tests.wrapping_add(1);
+        self.throughput_test = Some(ThroughputTest {
+            id: self.throughput_tests,
+            mode,
+            addr,
+            conn_id,
+            handle,
+            duration: Duration::from_secs(duration_secs.into()),
+            payload_len: (self.get_mtu(conn_id) - ATT_HEADER_LEN) as usize,
+            started: None,
+            timer: None,
+            in_flight: VecDeque::new(),
+            packet_count: 0,
+            byte_count: 0,
+            failure_count: 0,
+            latencies_us: vec![],
+        });
+
+        if handle.is_some() {
+            self.begin_throughput_test();
+        }
+        true
+    }
+
+    /// Ends the throughput test `id` once its duration is over.
+    pub(crate) fn throughput_test_timeout(&mut self, id: u32) {
+        if self.throughput_test.as_ref().map_or(false, |test| test.id == id) {
+            self.finish_throughput_test(GattStatus::Success);
+        }
+    }
+
+    /// Starts sending the packets of the throughput test, once the test characteristic is known.
+    fn begin_throughput_test(&mut self) {
+        let test = match &mut self.throughput_test {
+            Some(test) => test,
+            None => return,
+        };
+
+        let (tx, id, duration) = (self.tx.clone(), test.id, test.duration);
+        test.started = Some(Instant::now());
+        test.timer = Some(topstack::get_runtime().spawn(async move {
+            tokio::time::sleep(duration).await;
+            let _result = tx.send(Message::GattThroughputTestTimeout(id)).await;
+        }));
+
+        self.pump_throughput_test();
+    }
+
+    /// Hands packets of the throughput test to the stack until `THROUGHPUT_TEST_WINDOW` of them
+    /// are in flight, or the connection is congested.
+    fn pump_throughput_test(&mut self) {
+        let test = match &mut self.throughput_test {
+            Some(test) if test.started.is_some() => test,
+            _ => return,
+        };
+
+        let gatt = match &mut self.gatt {
+            Some(gatt) => gatt,
+            None => return,
+        };
+
+        let handle = test.handle.unwrap_or_default();
+        let payload = vec![0; test.payload_len];
+        while test.in_flight.len() < THROUGHPUT_TEST_WINDOW {
+            let status = match (test.mode, self.throughput_server_id) {
+                (ThroughputTestMode::WriteWithoutResponse, _) => gatt.write_characteristic(
+                    test.conn_id,
+                    handle,
+                    GATT_WRITE_TYPE_NO_RSP,
+                    0,
+                    &payload,
+                ),
+                (ThroughputTestMode::Notification, Some(server_id)) => {
+                    if self.notification_queues.get(&test.conn_id).map_or(false, |q| q.congested) {
+                        return;
+                    }
+                    gatt.send_indication(server_id, handle, test.conn_id, false, &payload)
+                }
+                (ThroughputTestMode::Notification, None) => GattStatus::Error as i32,
+            };
+
+            if status != 0 {
+                self.finish_throughput_test(GattStatus::from(status));
+                return;
+            }
+            test.in_flight.push_back(Instant::now());
+        }
+    }
+
+    /// Accounts for a packet of the throughput test that the stack is done with, and sends the
+    /// next one. Returns false if the connection is not the one of the test.
+    fn throughput_packet_sent(&mut self, conn_id: i32, status: i32) -> bool {
+        let test = match &mut self.throughput_test {
+            Some(test) if test.conn_id == conn_id => test,
+            _ => return false,
+        };
+
+        let handed_at = match test.in_flight.pop_front() {
+            Some(handed_at) => handed_at,
+            None => return true,
+        };
+
+        // Congestion is reported once the packet is queued, which is only a cue to slow down.
+        if status == 0 || status == GattStatus::Congested as i32 {
+            test.packet_count += 1;
+            test.byte_count += test.payload_len as u64;
+            test.latencies_us.push(handed_at.elapsed().as_micros() as u32);
+        } else {
+            test.failure_count += 1;
+        }
+
+        self.pump_throughput_test();
+        true
+    }
+
+    /// Locates the test characteristic in the database of the device of a throughput test once it
+    /// is discovered, failing the test if it can't be.
+    fn throughput_discovery_done(&mut self, conn_id: i32, status: i32) {
+        match &self.throughput_test {
+            Some(test) if test.conn_id == conn_id && test.handle.is_none() => {}
+            _ => return,
+        }
+
+        if status != 0 {
+            self.finish_throughput_test(GattStatus::from(status));
+            return;
+        }
+
+        let uuid = Uuid128::from_bytes(THROUGHPUT_CHARACTERISTIC_UUID);
+        match self.find_characteristic(conn_id, uuid) {
+            Some((handle, _)) => {
+                if let Some(test) = &mut self.throughput_test {
+                    test.handle = Some(handle as i32);
+                }
+                self.begin_throughput_test();
+            }
+            None => self.finish_throughput_test(GattStatus::NotFound),
+        }
+    }
+
+    /// Fails the throughput test if it runs on a connection that closed.
+    fn throughput_connection_closed(&mut self, conn_id: i32) {
+        if self.throughput_test.as_ref().map_or(false, |test| test.conn_id == conn_id) {
+            self.finish_throughput_test(GattStatus::Error);
+        }
+    }
+
+    fn finish_throughput_test(&mut self, status: GattStatus) {
+        let mut test = match self.throughput_test.take() {
+            Some(test) => test,
+            None => return,
+        };
+
+        if let Some(timer) = test.timer.take() {
+            timer.abort();
+        }
+
+        let addr = test.addr;
+        let result = test.into_result(status);
+        let tx = self.tx.clone();
+        topstack::get_runtime().spawn(async move {
+            let _result = tx.send(Message::DebugThroughputTestDone(addr, result)).await;
+        });
+    }
+
+    /// Connects the HOGP host to a bonded HID device, see `hogp`.
+    ///
+    /// The connection is made in the background whenever the device is in range, until
+    /// `forget_hid_device`, so that e.g. a keyboard reconnects on its own when it wakes up.
+    pub(crate) fn connect_hid_device(&mut self, addr: BDAddr) {
+        self.hogp_addrs.insert(addr);
+
+        if let (Some(gatt), Some(client_id)) = (&mut self.gatt, self.hogp_client_id) {
+            gatt.connect(client_id, &addr.to_raw_address(), false, TRANSPORT_LE, false, PHY_LE_1M);
+        }
+    }
+
+    /// Stops connecting the HOGP host to a HID device, e.g. once it is unbonded, and
+    /// disconnects it.
+    pub(crate) fn forget_hid_device(&mut self, addr: BDAddr) {
+        if !self.hogp_addrs.remove(&addr) {
+            return;
+        }
+
+        let client_id = match self.hogp_client_id {
+            Some(client_id) => client_id,
+            None => return,
+        };
+
+        let conn_id = self.find_client_conn_id(client_id, addr).unwrap_or(0);
+
+        // Cancels the background connection as well.
+        if let Some(gatt) = &mut self.gatt {
+            gatt.disconnect(client_id, &addr.to_raw_address(), conn_id);
+        }
+    }
+
+    /// Sends a setup operation of a HOGP device again, once its link had time to be encrypted.
+    pub(crate) fn hogp_operation_retry(&mut self, conn_id: i32) {
+        self.pump_hogp(conn_id);
+    }
+
+    /// Starts setting up the HID device of a connection of the HOGP host once its database is
+    /// discovered.
+    fn hogp_discovery_done(&mut self, conn_id: i32, status: i32) {
+        let addr = match self.client_connections.get(&conn_id) {
+            Some(connection) if Some(connection.client_id) == self.hogp_client_id => {
+                connection.addr
+            }
+            _ => return,
+        };
+
+        if status != 0 {
+            eprintln!(
+                "Failed to discover the services of HID device {:?}: {:?}",
+                addr,
+                GattStatus::from(status)
+            );
+            return;
+        }
+
+        match self.gatt_dbs.get(&conn_id).and_then(|db| HogpDevice::new(addr, db)) {
+            Some(device) => {
+                self.hogp_devices.insert(conn_id, device);
+                self.pump_hogp(conn_id);
+            }
+            None => eprintln!("{:?} doesn't serve a HID service", addr),
+        }
+    }
+
+    /// Answers a request of the kernel to the uhid device of a HOGP device.
+    pub(crate) fn hogp_uhid_request(&mut self, conn_id: i32, request: UhidRequest) {
+        if let Some(device) = self.hogp_devices.get_mut(&conn_id) {
+            device.queue_request(request);
+            self.pump_hogp(conn_id);
+        }
+    }
+
+    /// Sends the next operation of a HOGP device, or creates its input device once it is set
+    /// up. Operations that can't be sent fail right away.
+    fn pump_hogp(&mut self, conn_id: i32) {
+        loop {
+            let (gatt, client_id, device) =
+                match (&mut self.gatt, self.hogp_client_id, self.hogp_devices.get_mut(&conn_id)) {
+                    (Some(gatt), Some(client_id), Some(device)) => (gatt, client_id, device),
+                    _ => return,
+                };
+
+            let operation = match device.next_operation() {
+                Some(operation) => operation,
+                None if device.is_ready_to_start() => break,
+                None => return,
+            };
+
+            let status = match operation {
+                HogpOperation::ReadCharacteristic(handle) => {
+                    gatt.read_characteristic(conn_id, handle, 0)
+                }
+                HogpOperation::ReadDescriptor(handle) => gatt.read_descriptor(conn_id, handle, 0),
+                HogpOperation::EnableNotifications(handle, cccd_handle) => {
+                    let addr = device.addr().to_raw_address();
+                    gatt.register_for_notification(client_id, &addr, handle);
+                    gatt.write_descriptor(conn_id, cccd_handle, 0, &CCCD_ENABLE_NOTIFICATIONS)
+                }
+                HogpOperation::GetReport(_, handle) => gatt.read_characteristic(conn_id, handle, 0),
+                HogpOperation::WriteReport(_, handle, data) => gatt.write_characteristic(
+                    conn_id,
+                    handle as i32,
+                    GATT_WRITE_TYPE_DEFAULT,
+                    0,
+                    &data,
+                ),
+            };
+
+            if status == 0 {
+                return;
+            }
+            device.operation_done(false, &[]);
+        }
+
+        let addr = match self.hogp_devices.get(&conn_id) {
+            Some(device) => device.addr(),
+            None => return,
+        };
+
+        let (name, adapter_addr) = {
+            let bluetooth = self.bluetooth.lock().unwrap();
+            (bluetooth.get_remote_name(addr.to_string()), bluetooth.get_address())
+        };
+
+        if let Some(device) = self.hogp_devices.get_mut(&conn_id) {
+            if let Err(e) = device.start(&name, &adapter_addr, conn_id, self.tx.clone()) {
+                eprintln!("Failed to create the input device of {:?}: {}", addr, e);
+            }
+        }
+    }
+
+    /// Completes the operation in flight of a HOGP device and sends the next one. The
+    /// operation is sent again once the link is encrypted if the device refused it for lack of
+    /// encryption.
+    ///
+    /// Returns false if the connection is not of the HOGP host.
+    fn hogp_operation_done(&mut self, conn_id: i32, status: i32, value: &[u8]) -> bool {
+        let device = match self.hogp_devices.get_mut(&conn_id) {
+            Some(device) => device,
+            None => return false,
+        };
+
+        let status = GattStatus::from(status);
+        let insufficient_security =
+            status == GattStatus::InsufAuthentication || status == GattStatus::InsufEncryption;
+
+        if insufficient_security && device.retry_operation(HOGP_OPERATION_MAX_RETRIES) {
+            let addr = device.addr();
+            self.bluetooth.lock().unwrap().request_le_encryption(&addr, false);
+
+            let tx = self.tx.clone();
+            topstack::get_runtime().spawn(async move {
+                tokio::time::sleep(HOGP_OPERATION_RETRY_DELAY).await;
+                let _result = tx.send(Message::GattHogpOperationRetry(conn_id)).await;
+            });
+            return true;
+        }
+
+        if status != GattStatus::Success {
+            eprintln!(
+                "HOGP operation {:?} of {:?} failed: {:?}",
+                device.operation_in_flight(),
+                device.addr(),
+                status
+            );
+        }
+        device.operation_done(status == GattStatus::Success, value);
+
+        self.pump_hogp(conn_id);
+        true
+    }
+
+    /// Unregisters the client applications of a disconnected client.
+    pub(crate) fn client_callback_disconnected(&mut self, callback_id: u32) {
+        let client_ids: Vec<i32> = self
+            .clients
+            .iter()
+            .filter(|client| client.callback_id == callback_id)
+            .filter_map(|client| client.id)
+            .collect();
+        for client_id in client_ids {
+            self.unregister_client(client_id);
+        }
+
+        // Registrations still pending are dropped when they complete, but nobody awaits them
+        // anymore.
+        self.clients.retain(|client| client.callback_id != callback_id);
+        self.client_registrations.remove(&callback_id);
+        self.client_callbacks.remove_callback(callback_id);
+    }
+
+    /// Unregisters the server applications of a disconnected client.
+    pub(crate) fn server_callback_disconnected(&mut self, callback_id: u32) {
+        let server_ids: Vec<i32> = self
+            .servers
+            .iter()
+            .filter(|server| server.callback_id == callback_id)
+            .filter_map(|server| server.id)
+            .collect();
+        for server_id in server_ids {
+            self.unregister_server(server_id);
+        }
+
+        self.servers.retain(|server| server.callback_id != callback_id);
+        self.server_callbacks.remove_callback(callback_id);
+    }
+
+    /// Stops the advertising sets of a disconnected client.
+    pub(crate) fn advertiser_callback_disconnected(&mut self, callback_id: u32) {
+        let advertiser_ids: Vec<i32> = self
+            .advertising_sets
+            .iter()
+            .filter(|set| set.callback_id == callback_id)
+            .filter_map(|set| set.id)
+            .collect();
+        for advertiser_id in advertiser_ids {
+            self.stop_advertising_set(advertiser_id);
+        }
+
+        // Sets still starting are stopped when they start.
+        self.advertising_sets.retain(|set| set.callback_id != callback_id);
+        self.advertiser_callbacks.remove_callback(callback_id);
+    }
+
+    /// Unregisters the scanners of a disconnected client, stopping their scans.
+    pub(crate) fn scanner_callback_disconnected(&mut self, callback_id: u32) {
+        let scanner_ids: Vec<i32> = self
+            .scanners
+            .iter()
+            .filter(|scanner| scanner.callback_id == callback_id)
+            .filter_map(|scanner| scanner.id)
+            .collect();
+        for scanner_id in scanner_ids {
+            self.unregister_scanner(scanner_id);
+        }
+
+        // Registrations still pending are undone when they complete.
+        self.scanners.retain(|scanner| scanner.callback_id != callback_id);
+        self.scanner_callbacks.remove_callback(callback_id);
+    }
+
+    /// Unregisters the advertisement monitors of a disconnected client.
+    pub(crate) fn monitor_callback_disconnected(&mut self, callback_id: u32) {
+        let monitor_ids: Vec<i32> = self
+            .monitors
+            .iter()
+            .filter(|monitor| monitor.callback_id == callback_id)
+            .map(|monitor| monitor.id)
+            .collect();
+        for monitor_id in monitor_ids {
+            self.unregister_advertisement_monitor(monitor_id);
+        }
+    }
+
+    /// Unregisters every scanner, client and server application, as the daemon shuts down.
+    pub(crate) fn unregister_all(&mut self) {
+        let scanner_ids: Vec<i32> = self.scanners.iter().filter_map(|scanner| scanner.id).collect();
+        for scanner_id in scanner_ids {
+            self.unregister_scanner(scanner_id);
+        }
+
+        let monitor_ids: Vec<i32> = self.monitors.iter().map(|monitor| monitor.id).collect();
+        for monitor_id in monitor_ids {
+            self.unregister_advertisement_monitor(monitor_id);
+        }
+
+        let client_ids: Vec<i32> = self.clients.iter().filter_map(|client| client.id).collect();
+        for client_id in client_ids {
+            self.unregister_client(client_id);
+        }
+
+        let server_ids: Vec<i32> = self.servers.iter().filter_map(|server| server.id).collect();
+        for server_id in server_ids {
+            self.unregister_server(server_id);
+        }
+
+        let advertiser_ids: Vec<i32> =
+            self.advertising_sets.iter().filter_map(|set| set.id).collect();
+        for advertiser_id in advertiser_ids {
+            self.stop_advertising_set(advertiser_id);
+        }
+
+        // Registrations still pending are dropped when they complete.
+        for client in self.clients.drain(..) {
+            self.client_callbacks.remove_callback(client.callback_id);
+        }
+        for server in self.servers.drain(..) {
+            self.server_callbacks.remove_callback(server.callback_id);
+        }
+        for set in self.advertising_sets.drain(..) {
+            self.advertiser_callbacks.remove_callback(set.callback_id);
+        }
+        for scanner in self.scanners.drain(..) {
+            self.scanner_callbacks.remove_callback(scanner.callback_id);
+        }
+    }
+
+    /// Cleans up the GATT profile. No GATT API works afterwards.
+    pub(crate) fn cleanup(&mut self) {
+        self.hogp_devices.clear();
+        if let Some(mut gatt) = self.gatt.take() {
+            gatt.cleanup();
+        }
+    }
+
+    fn find_scanner_mut(&mut self, scanner_id: i32) -> Option<&mut Scanner> {
+        self.scanners.iter_mut().find(|scanner| scanner.id == Some(scanner_id))
+    }
+
+    /// Reports the devices seen during the period that just ended of a `Periodic` scan.
+    pub(crate) fn scanner_report_tick(&mut self, scanner_id: i32) {
+        let scanner = self.scanners.iter_mut().find(|scanner| scanner.id == Some(scanner_id));
+        if let Some(Scanner { callback_id, scan: Some(scan), .. }) = scanner {
+            if let Some(callback) = self.scanner_callbacks.get_by_id(*callback_id) {
+                report_scan_batch(callback, scan);
+            }
+        }
+    }
+
+    /// Stops the scan of a scanner, and LE scanning once no scanner is scanning anymore.
+    fn end_scan(&mut self, scanner_id: i32) -> bool {
+        let scanner = match self.find_scanner_mut(scanner_id) {
+            Some(scanner) => scanner,
+            None => return false,
+        };
+
+        let callback_id = scanner.callback_id;
+        let mut scan = match scanner.scan.take() {
+            Some(scan) => scan,
+            None => return false,
+        };
+
+        if let Some(ticker) = scan.report_ticker.take() {
+            ticker.abort();
+        }
+        if let Some(callback) = self.scanner_callbacks.get_by_id(callback_id) {
+            report_scan_batch(callback, &mut scan);
+        }
+
+        self.update_scan_filter();
+        if self.is_scanning() {
+            self.update_scan_parameters(true);
+        } else if let Some(gatt) = &mut self.gatt {
+            gatt.scan(false);
+        }
+
+        true
+    }
+
+    /// Sets the interval and window of the foreground scan with the highest duty cycle, and the
+    /// shortest interval among equals, or a low duty cycle while there is no foreground scan.
+    ///
+    /// The controller only takes new parameters when scanning starts, so a `running` scan is
+    /// restarted for them.
+    fn update_scan_parameters(&mut self, running: bool) {
+        let parameters = self
+            .scanners
+            .iter()
+            .filter_map(|scanner| scanner.scan.as_ref())
+            .filter(|scan| !scan.background)
+            .map(|scan| scan.parameters)
+            .max_by(|(interval1, window1), (interval2, window2)| {
+                (window1 * interval2).cmp(&(window2 * interval1)).then(interval2.cmp(interval1))
+            })
+            .unwrap_or(SCAN_LOW_DUTY_PARAMETERS);
+
+        let changed = self.scan_parameters != Some(parameters);
+        if let Some(gatt) = &mut self.gatt {
+            // The native stack resets the parameters when the adapter is enabled again.
+            gatt.set_scan_parameters(parameters.0, parameters.1);
+            if running && changed {
+                gatt.scan(false);
+                gatt.scan(true);
+            }
+            self.scan_parameters = Some(parameters);
+        }
+    }
+    /// Returns whether LE scanning is needed, by a scanner or an advertisement monitor.
+    fn is_scanning(&self) -> bool {
+        !self.monitors.is_empty() || self.scanners.iter().any(|scanner| scanner.scan.is_some())
+    }
+
+    /// Returns a controller filter index that no monitor uses, if the controller can offload
+    /// filters and offloading is enabled.
+    fn free_scan_filter_index(&self) -> Option<u8> {
+        if !self.feature_flags.lock().unwrap().is_enabled(Feature::ScanFilterOffload) {
+            return None;
+        }
+
+        let info = Controller::new().get_info();
+        if !info.le_scan_filter_offload {
+            return None;
+        }
+
+        (0..info.le_max_scan_filters)
+            .find(|index| self.monitors.iter().all(|monitor| monitor.filter_index != Some(*index)))
+    }
+
+    /// Enables the controller filters only while every monitor is offloaded and no scanner is
+    /// scanning, as they would hide the other advertisements.
+    fn update_scan_filter(&mut self) {
+        let enable = !self.monitors.is_empty()
+            && self.monitors.iter().all(|monitor| monitor.filter_index.is_some())
+            && self.scanners.iter().all(|scanner| scanner.scan.is_none());
+
+        if enable == self.scan_filter_enabled {
+            return;
+        }
+
+        if let Some(gatt) = &mut self.gatt {
+            gatt.enable_scan_filter(enable);
+            self.scan_filter_enabled = enable;
+        }
+    }
+
+    /// Returns the priority that applies to the connection to a device, the highest one its
+    /// clients requested, if any.
+    fn connection_priority(&self, addr: &BDAddr) -> Option<ConnectionPriority> {
+        self.connection_priorities.get(addr).and_then(|requests| requests.values().min().copied())
+    }
+
+    /// Sets or drops the priority requested by a client for the connection to a device, and
+    /// requests the parameters of the priority that applies if it changed.
+    fn update_connection_priority(
+        &mut self,
+        client_id: i32,
+        addr: BDAddr,
+        priority: Option<ConnectionPriority>,
+    ) -> BtStatus {
+        let previous = self.connection_priority(&addr);
+
+        let requests = self.connection_priorities.entry(addr).or_default();
+        match priority {
+            Some(priority) => {
+                requests.insert(client_id, priority);
+            }
+            None => {
+                requests.remove(&client_id);
+            }
+        }
+        if requests.is_empty() {
+            self.connection_priorities.remove(&addr);
+        }
+
+        let current = self.connection_priority(&addr);
+        if current == previous {
+            return BtStatus::Success;
+        }
+
+        // Fails once the link is down, which leaves nothing to update.
+        let preset = current.unwrap_or(ConnectionPriority::Balanced).preset();
+        self.bluetooth.lock().unwrap().set_le_connection_preset(addr.to_string(), preset)
+    }
+
+    fn find_client(&self, client_id: i32) -> Option<&GattClient> {
+        self.clients.iter().find(|client| client.id == Some(client_id))
+    }
+
+    fn find_server(&self, server_id: i32) -> Option<&GattServer> {
+        self.servers.iter().find(|server| server.id == Some(server_id))
+    }
+
+    fn client_callback(&self, client_id: i32) -> Option<&(dyn IBluetoothGattCallback + Send)> {
+        self.find_client(client_id)
+            .and_then(|client| self.client_callbacks.get_by_id(client.callback_id))
+    }
+
+    fn server_callback(
+        &self,
+        server_id: i32,
+    ) -> Option<&(dyn IBluetoothGattServerCallback + Send)> {
+        self.find_server(server_id)
+            .and_then(|server| self.server_callbacks.get_by_id(server.callback_id))
+    }
+
+    /// Returns the id of the connection of a client to a device.
+    fn find_client_conn_id(&self, client_id: i32, addr: BDAddr) -> Option<i32> {
+        self.client_connections
+            .iter()
+            .find(|(_, c)| c.client_id == client_id && c.addr == addr)
+            .map(|(conn_id, _)| *conn_id)
+    }
+
+    /// Returns whether a connection is of a client.
+    fn is_client_connection(&self, client_id: i32, conn_id: i32) -> bool {
+        self.client_connections.get(&conn_id).map_or(false, |c| c.client_id == client_id)
+    }
+
+    /// Looks up the first characteristic of type `uuid` in the database of a connection.
+    ///
+    /// Returns the handle of its value and the end handle of its service.
+    fn find_characteristic(&self, conn_id: i32, uuid: Uuid128) -> Option<(u16, u16)> {
+        let mut service_end = 0;
+        for element in self.gatt_dbs.get(&conn_id)? {
+            match GattDbElementType::from_i32(element.element_type) {
+                Some(GattDbElementType::PrimaryService)
+                | Some(GattDbElementType::SecondaryService) => service_end = element.end_handle,
+                Some(GattDbElementType::Characteristic)
+                    if Uuid128::from_bytes(element.uuid.uuid) == uuid =>
+                {
+                    return Some((element.attribute_handle, service_end));
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Reads the Database Hash characteristic of the device of a client connection, if its
+    /// database has one. Returns whether the read was sent.
+    fn read_database_hash(&mut self, client_id: i32, conn_id: i32) -> bool {
+        // The HOGP host and the throughput tests handle the reads of their connections themselves.
+        if Some(client_id) == self.hogp_client_id || Some(client_id) == self.throughput_client_id {
+            return false;
+        }
+
+        let handle = match self.find_characteristic(conn_id, Uuid128::from_u16(DATABASE_HASH_UUID))
+        {
+            Some((handle, _)) => handle,
+            None => return false,
+        };
+
+        match &mut self.gatt {
+            Some(gatt) if gatt.read_characteristic(conn_id, handle, 0) == 0 => {
+                self.client_reads.entry(conn_id).or_default().push_back(ClientRead::DatabaseHash);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Looks up the Client Characteristic Configuration descriptor of the characteristic whose
+    /// value has the handle `handle`, in the database of a connection.
+    ///
+    /// Returns the handle of the descriptor and the value that subscribes to the characteristic,
+    /// or None if it can't be subscribed to.
+    fn find_cccd(&self, conn_id: i32, handle: i32) -> Option<(u16, [u8; 2])> {
+        let mut value = None;
+        for element in self.gatt_dbs.get(&conn_id)? {
+            match GattDbElementType::from_i32(element.element_type) {
+                Some(GattDbElementType::Characteristic) if value.is_some() => break,
+                Some(GattDbElementType::Characteristic)
+                    if i32::from(element.attribute_handle) == handle =>
+                {
+                    let properties = i32::from(element.properties);
+                    value = if properties & GATT_CHAR_PROP_NOTIFY != 0 {
+                        Some(CCCD_ENABLE_NOTIFICATIONS)
+                    } else if properties & GATT_CHAR_PROP_INDICATE != 0 {
+                        Some(CCCD_ENABLE_INDICATIONS)
+                    } else {
+                        return None;
+                    };
+                }
+                Some(GattDbElementType::Descriptor)
+                    if value.is_some()
+                        && Uuid128::from_bytes(element.uuid.uuid).as_u16() == Some(CCCD_UUID) =>
+                {
+                    return Some((element.attribute_handle, value?));
+                }
+                Some(GattDbElementType::PrimaryService)
+                | Some(GattDbElementType::SecondaryService)
+                    if value.is_some() =>
+                {
+                    break
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Removes a client from the subscribers of a characteristic of a device.
+    fn drop_subscription(&mut self, client_id: i32, addr: BDAddr, handle: i32) {
+        if let Some(clients) = self.subscriptions.get_mut(&(addr, handle)) {
+            clients.remove(&client_id);
+            if clients.is_empty() {
+                self.subscriptions.remove(&(addr, handle));
+            }
+        }
+    }
+
+    /// Drops the subscriptions of a client, to the characteristics of a device or of all of them.
+    ///
+    /// The native stack is told to stop routing the notifications to the client, but the
+    /// descriptors are left as they are since the connection of the client is gone.
+    fn drop_subscriptions(&mut self, client_id: i32, addr: Option<BDAddr>) {
+        let mut dropped = vec![];
+        self.subscriptions.retain(|(device, handle), clients| {
+            if addr.map_or(true, |addr| addr == *device) && clients.remove(&client_id) {
+                dropped.push((*device, *handle));
+            }
+            !clients.is_empty()
+        });
+
+        if let Some(gatt) = &mut self.gatt {
+            for (device, handle) in dropped {
+                gatt.deregister_for_notification(
+                    client_id,
+                    &device.to_raw_address(),
+                    handle as u16,
+                );
+            }
+        }
+    }
+
+    /// Returns the id of the connection of a device to a server.
+    fn find_server_conn_id(&self, server_id: i32, addr: BDAddr) -> Option<i32> {
+        self.notification_queues
+            .iter()
+            .find(|(_, queue)| queue.server_id == server_id && queue.addr == addr)
+            .map(|(conn_id, _)| *conn_id)
+    }
+
+    /// Checks that the link to a device meets the encryption required to read or write a local
+    /// attribute.
+    ///
+    /// Returns the ATT error to reject the request with if it doesn't, after asking for the link
+    /// to be encrypted, or paired first if the device is not bonded with a key strong enough.
+    fn check_link_security(&self, addr: &BDAddr, handle: i32, write: bool) -> Option<GattStatus> {
+        let permissions = self.local_attributes.get(&handle)?.permissions;
+        let (encrypted, mitm) = if write {
+            (GATT_PERM_WRITE_ENCRYPTED, GATT_PERM_WRITE_ENC_MITM)
+        } else {
+            (GATT_PERM_READ_ENCRYPTED, GATT_PERM_READ_ENC_MITM)
+        };
+
+        let mitm = permissions & mitm != 0;
+        if !mitm && permissions & encrypted == 0 {
+            return None;
+        }
+
+        let mut bluetooth = self.bluetooth.lock().unwrap();
+        let security = bluetooth.get_le_link_security(addr);
+        let key_sufficient = security.key_known && (!mitm || security.key_authenticated);
+        if security.encrypted && key_sufficient {
+            return None;
+        }
+
+        bluetooth.request_le_encryption(addr, mitm);
+        if key_sufficient {
+            Some(GattStatus::InsufEncryption)
+        } else {
+            Some(GattStatus::InsufAuthentication)
+        }
+    }
+
+    /// Adds a service to the native database on behalf of a server, see
+    /// `IBluetoothGatt::add_service`.
+    fn add_local_service(&mut self, server_id: i32, service: GattService) -> GattStatus {
+        let gatt = match &mut self.gatt {
+            Some(gatt) => gatt,
+            None => return GattStatus::Error,
+        };
+
+        if gatt.add_service(server_id, &service.to_db_elements()) != 0 {
+            return GattStatus::Error;
+        }
+
+        self.pending_services.entry(server_id).or_default().push_back(service);
+        GattStatus::Success
+    }
+
+    /// Forgets a local service, whose attributes are then not checked anymore.
+    fn forget_local_service(&mut self, handle: i32) {
+        if let Some(service) = self.local_services.remove(&handle) {
+            for attribute_handle in service.attribute_handles {
+                self.local_attributes.remove(&attribute_handle);
+            }
+        }
+    }
+
+    /// Returns whether a handle is the declaration or an attribute of a service of a server.
+    fn owns_attribute(&self, server_id: i32, handle: i32) -> bool {
+        match (self.local_services.get(&handle), self.local_attributes.get(&handle)) {
+            (Some(service), _) => service.server_id == server_id,
+            (_, Some(attribute)) => attribute.server_id == server_id,
+            _ => false,
+        }
+    }
+
+    /// Returns whether a handle is an attribute of a service of another server than `server_id`,
+    /// whose requests must not reach the server.
+    fn is_foreign_attribute(&self, server_id: i32, handle: i32) -> bool {
+        matches!(self.local_attributes.get(&handle), Some(a) if a.server_id != server_id)
+    }
+
+    /// Returns whether another server than `server_id` has added, or is adding, a primary service
+    /// with the UUID `uuid`.
+    fn is_primary_service_of_other_server(&self, server_id: i32, uuid: &Uuid128) -> bool {
+        let added = self.local_services.values().any(|service| {
+            service.server_id != server_id && service.primary && service.uuid == *uuid
+        });
+        let pending = self.pending_services.iter().any(|(id, services)| {
+            *id != server_id && services.iter().any(|s| s.primary && s.uuid == *uuid)
+        });
+        added || pending
+    }
+
+    fn find_advertising_set_mut(&mut self, advertiser_id: i32) -> Option<&mut AdvertisingSet> {
+        self.advertising_sets.iter_mut().find(|set| set.id == Some(advertiser_id))
+    }
+
+    fn advertiser_callback(
+        &self,
+        advertiser_id: i32,
+    ) -> Option<&(dyn IAdvertisingSetCallback + Send)> {
+        self.advertising_sets
+            .iter()
+            .find(|set| set.id == Some(advertiser_id))
+            .and_then(|set| self.advertiser_callbacks.get_by_id(set.callback_id))
+    }
+
+    /// Checks whether a new advertising set can be started, within the limits of the controller.
+    fn check_new_advertising_set(
+        &self,
+        parameters: &AdvertisingSetParameters,
+        advertise_data: &[u8],
+        scan_response: &[u8],
+    ) -> AdvertisingStatus {
+        let info = Controller::new().get_info();
+        if self.gatt.is_none() || !info.is_ready {
+            return AdvertisingStatus::InternalError;
+        }
+
+        if !parameters.is_legacy && !info.le_extended_advertising {
+            return AdvertisingStatus::FeatureUnsupported;
+        }
+
+        let le_privacy = self.bluetooth.lock().unwrap().get_le_privacy();
+        if !parameters.own_address_type.is_available(le_privacy) {
+            return AdvertisingStatus::FeatureUnsupported;
+        }
+
+        let max_data_len = parameters.max_data_len();
+        if advertise_data.len() > max_data_len || scan_response.len() > max_data_len {
+            return AdvertisingStatus::DataTooLarge;
+        }
+
+        // Controllers without extended advertising still have the legacy set.
+        let max_sets = std::cmp::max(info.le_number_of_advertising_sets, 1) as usize;
+        if self.advertising_sets.len() >= max_sets {
+            return AdvertisingStatus::TooManyAdvertisers;
+        }
+
+        AdvertisingStatus::Success
+    }
+
+    /// Sets the data of an advertising set, if it fits in the advertising PDUs of the set.
+    fn set_advertising_set_data(
+        &mut self,
+        advertiser_id: i32,
+        set_scan_rsp: bool,
+        data: Vec<u8>,
+    ) -> bool {
+        let max_data_len = match self.advertising_sets.iter().find(|s| s.id == Some(advertiser_id))
+        {
+            Some(set) => set.parameters.max_data_len(),
+            None => return false,
+        };
+
+        if data.len() > max_data_len {
+            return false;
+        }
+
+        match &mut self.gatt {
+            Some(gatt) => {
+                gatt.set_advertising_data(advertiser_id, set_scan_rsp, &data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Executes or aborts the reliable write transaction of a client.
+    fn close_reliable_write(&mut self, client_id: i32, conn_id: i32, execute: bool) -> GattStatus {
+        let gatt = match &mut self.gatt {
+            Some(gatt) => gatt,
+            None => return GattStatus::Error,
+        };
+
+        match self.reliable_writes.get_mut(&conn_id) {
+            Some(write) if write.client_id == client_id && !write.closing => write.closing = true,
+            _ => return GattStatus::WrongState,
+        }
+
+        if gatt.execute_write(conn_id, execute as i32) != 0 {
+            self.reliable_writes.remove(&conn_id);
+            return GattStatus::Error;
+        }
+
+        GattStatus::Success
+    }
+
+    /// Hands the next queued notification of a connection to the stack, unless one is in flight
+    /// or the connection is congested.
+    ///
+    /// The notifications the stack refuses are failed and the next ones are tried, as no
+    /// `indication_sent` would come to pump the queue again. A congested queue is pumped again
+    /// when the congestion clears.
+    fn pump_notifications(&mut self, conn_id: i32) {
+        loop {
+            let gatt = match &mut self.gatt {
+                Some(gatt) => gatt,
+                None => return,
+            };
+
+            let queue = match self.notification_queues.get_mut(&conn_id) {
+                Some(queue) => queue,
+                None => return,
+            };
+
+            if queue.in_flight || queue.congested {
+                break;
+            }
+
+            let notification = match queue.pending.pop_front() {
+                Some(notification) => notification,
+                None => break,
+            };
+
+            let status = gatt.send_indication(
+                queue.server_id,
+                notification.handle,
+                conn_id,
+                notification.confirm,
+                &notification.value,
+            );
+
+            if status == 0 {
+                queue.in_flight = true;
+                break;
+            }
+
+            let (server_id, addr) = (queue.server_id, queue.addr);
+            if let Some(callback) = self.server_callback(server_id) {
+                callback.on_notification_sent(addr.to_string(), GattStatus::Error);
+            }
+        }
+
+        self.check_notification_queue_available(conn_id);
+    }
+
+    fn check_notification_queue_available(&mut self, conn_id: i32) {
+        let queue = match self.notification_queues.get_mut(&conn_id) {
+            Some(queue) => queue,
+            None => return,
+        };
+
+        if !queue.refused || queue.pending.len() > NOTIFICATION_QUEUE_MAX_LEN / 2 {
+            return;
+        }
+
+        queue.refused = false;
+        let (server_id, addr) = (queue.server_id, queue.addr);
+        if let Some(callback) = self.server_callback(server_id) {
+            callback.on_notification_queue_available(addr.to_string());
+        }
+    }
+
+    /// Registers a GATT client application as `IBluetoothGatt::register_client` does, and returns
+    /// a completion resolved with the status and client id that `on_client_registered` reports.
+    /// Returns None if the GATT profile is not initialized.
+    pub fn register_client_with_completion(
+        &mut self,
+        app_uuid: Uuid128,
+        callback: Box<dyn IBluetoothGattCallback + Send>,
+        eatt_support: bool,
+    ) -> Option<Completion<(GattStatus, i32)>> {
+        if self.gatt.is_none() {
+            return None;
+        }
+
+        self.register_client(app_uuid, callback, eatt_support);
+        let callback_id = self.clients.last()?.callback_id;
+        Some(self.client_registrations.add(callback_id))
+    }
+
+    /// Registers a module that claims some advertisements before they reach the scanners, see
+    /// `AdvertisementInterceptor`.
+    pub fn register_advertisement_interceptor(
+        &mut self,
+        interceptor: Box<dyn AdvertisementInterceptor>,
+    ) {
+        println!("Registered advertisement interceptor {}", interceptor.name());
+        self.interceptors.push(interceptor);
+    }
+
+    /// Offers an advertisement to the interceptors of the service data it carries. Returns
+    /// whether one of them claimed it.
+    fn intercept_advertisement(&mut self, addr: BDAddr, result: &ScanResult) -> bool {
+        for (uuid, data) in ad_service_data(&result.adv_data) {
+            for interceptor in &mut self.interceptors {
+                if !interceptor.service_data_uuids().contains(&uuid) {
+                    continue;
+                }
+
+                match interceptor.intercept(result, uuid, data) {
+                    InterceptAction::Pass => continue,
+                    InterceptAction::Claim => return true,
+                    InterceptAction::ClaimAndBond => {
+                        // The device keeps advertising, many times a second, until it is bonded.
+                        let mut bluetooth = self.bluetooth.lock().unwrap();
+                        if !result.is_anonymous() && !bluetooth.is_bonded_or_bonding(&addr) {
+                            bluetooth.create_bond(addr.to_string(), TRANSPORT_LE);
+                        }
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+}
+
+fn report_scan_batch(callback: &dyn IScannerCallback, scan: &mut ActiveScan) {
+    for (_, result) in scan.batch.drain() {
+        callback.on_scan_result(result);
+    }
+    for result in scan.anonymous_batch.drain(..) {
+        callback.on_scan_result(result);
+    }
+}
+
+#[btif_callbacks_generator(btif_gatt_callbacks, GattCallbacks)]
+pub(crate) trait BtifGattCallbacks {
+    #[stack_message(GattClientRegistered)]
+    fn client_registered(&mut self, status: i32, client_id: i32, app_uuid: BtUuid);
+
+    #[stack_message(GattClientConnect)]
+    fn client_connect(&mut self, conn_id: i32, status: i32, client_id: i32, addr: RustRawAddress);
+
+    #[stack_message(GattClientDisconnect)]
+    fn client_disconnect(
+        &mut self,
+        conn_id: i32,
+        status: i32,
+        client_id: i32,
+        addr: RustRawAddress,
+    );
+
+    #[stack_message(GattClientReadRemoteRssi)]
+    fn client_read_remote_rssi(
+        &mut self,
+        client_id: i32,
+        addr: RustRawAddress,
+        rssi: i32,
+        status: i32,
+    );
+
+    #[stack_message(GattClientSearchComplete)]
+    fn client_search_complete(&mut self, conn_id: i32, status: i32);
+
+    #[stack_message(GattClientNotify)]
+    fn client_notify(
+        &mut self,
+        conn_id: i32,
+        addr: RustRawAddress,
+        handle: i32,
+        is_notify: bool,
+        value: Vec<u8>,
+    );
+
+    #[stack_message(GattClientGetGattDb)]
+    fn client_get_gatt_db(&mut self, conn_id: i32, elements: Vec<BtGattDbElement>);
+
+    #[stack_message(GattClientReadCharacteristic)]
+    fn client_read_characteristic(
+        &mut self,
+        conn_id: i32,
+        status: i32,
+        handle: i32,
+        value: Vec<u8>,
+    );
+
+    #[stack_message(GattClientWriteCharacteristic)]
+    fn client_write_characteristic(&mut self, conn_id: i32, status: i32, handle: i32);
+
+    #[stack_message(GattClientReadDescriptor)]
+    fn client_read_descriptor(&mut self, conn_id: i32, status: i32, handle: i32, value: Vec<u8>);
+
+    #[stack_message(GattClientWriteDescriptor)]
+    fn client_write_descriptor(&mut self, conn_id: i32, status: i32, handle: i32);
+
+    #[stack_message(GattClientExecuteWrite)]
+    fn client_execute_write(&mut self, conn_id: i32, status: i32);
+
+    #[stack_message(GattClientConfigureMtu)]
+    fn client_configure_mtu(&mut self, conn_id: i32, status: i32, mtu: i32);
+
+    #[stack_message(GattClientPhyUpdated)]
+    fn client_phy_updated(&mut self, conn_id: i32, tx_phy: i32, rx_phy: i32, status: i32);
+
+    #[stack_message(GattClientConnUpdated)]
+    fn client_conn_updated(
+        &mut self,
+        conn_id: i32,
+        interval: i32,
+        latency: i32,
+        timeout: i32,
+        status: i32,
+    );
+
+    #[stack_message(GattClientServiceChanged)]
+    fn client_service_changed(&mut self, conn_id: i32);
+
+    #[stack_message(GattClientServicesRediscovered)]
+    fn client_services_rediscovered(&mut self, addr: RustRawAddress);
+
+    #[stack_message(GattClientReadPhy)]
+    fn client_read_phy(
+        &mut self,
+        client_id: i32,
+        addr: RustRawAddress,
+        tx_phy: i32,
+        rx_phy: i32,
+        status: i32,
+    );
+
+    #[stack_message(GattServerRegistered)]
+    fn server_registered(&mut self, status: i32, server_id: i32, app_uuid: BtUuid);
+
+    #[stack_message(GattServerConnection)]
+    fn server_connection(
+        &mut self,
+        conn_id: i32,
+        server_id: i32,
+        connected: i32,
+        addr: RustRawAddress,
+    );
+
+    #[stack_message(GattServerIndicationSent)]
+    fn server_indication_sent(&mut self, conn_id: i32, status: i32);
+
+    #[stack_message(GattServerCongestion)]
+    fn server_congestion(&mut self, conn_id: i32, congested: bool);
+
+    #[stack_message(GattServerMtuChanged)]
+    fn server_mtu_changed(&mut self, conn_id: i32, mtu: i32);
+
+    #[stack_message(GattServerReadPhy)]
+    fn server_read_phy(
+        &mut self,
+        server_id: i32,
+        addr: RustRawAddress,
+        tx_phy: i32,
+        rx_phy: i32,
+        status: i32,
+    );
+
+    #[stack_message(GattServerRequestWrite)]
+    fn server_request_write(
+        &mut self,
+        conn_id: i32,
+        trans_id: i32,
+        addr: RustRawAddress,
+        handle: i32,
+        offset: i32,
+        need_rsp: bool,
+        is_prep: bool,
+        value: Vec<u8>,
+    );
+
+    #[stack_message(GattServerRequestExecWrite)]
+    fn server_request_exec_write(
+        &mut self,
+        conn_id: i32,
+        trans_id: i32,
+        addr: RustRawAddress,
+        exec_write: i32,
+    );
+
+    #[stack_message(GattServerServiceAdded)]
+    fn server_service_added(&mut self, status: i32, server_id: i32, elements: Vec<BtGattDbElement>);
+
+    #[stack_message(GattServerRequestRead)]
+    fn server_request_read(
+        &mut self,
+        conn_id: i32,
+        trans_id: i32,
+        addr: RustRawAddress,
+        handle: i32,
+        offset: i32,
+        is_long: bool,
+    );
+
+    #[stack_message(GattScannerRegistered)]
+    fn scanner_registered(&mut self, app_uuid: BtUuid, scanner_id: i32, status: i32);
+
+    #[stack_message(GattScanResult)]
+    fn scan_result(
+        &mut self,
+        event_type: i32,
+        addr_type: i32,
+        addr: RustRawAddress,
+        primary_phy: i32,
+        secondary_phy: i32,
+        advertising_sid: i32,
+        tx_power: i32,
+        rssi: i32,
+        periodic_adv_int: i32,
+        adv_data: Vec<u8>,
+    );
+
+    #[stack_message(GattScannerFilterConfigured)]
+    fn scanner_filter_configured(&mut self, filter_index: i32, status: i32);
+
+    #[stack_message(GattAdvertiserSetStarted)]
+    fn advertiser_set_started(
+        &mut self,
+        reg_id: i32,
+        advertiser_id: i32,
+        tx_power: i32,
+        status: i32,
+    );
+
+    #[stack_message(GattAdvertiserEnabled)]
+    fn advertiser_enabled(&mut self, advertiser_id: i32, enable: bool, status: i32);
+
+    #[stack_message(GattAdvertiserDataSet)]
+    fn advertiser_data_set(&mut self, advertiser_id: i32, set_scan_rsp: bool, status: i32);
+
+    #[stack_message(GattAdvertiserParametersUpdated)]
+    fn advertiser_parameters_updated(&mut self, advertiser_id: i32, tx_power: i32, status: i32);
+
+    #[stack_message(GattAdvertiserOwnAddressRead)]
+    fn advertiser_own_address_read(
+        &mut self,
+        advertiser_id: i32,
+        address_type: i32,
+        address: RustRawAddress,
+    );
+}
+
+impl BtifGattCallbacks for BluetoothGatt {
+    fn client_registered(&mut self, status: i32, client_id: i32, app_uuid: BtUuid) {
+        if Uuid128::from(app_uuid) == Uuid128::from_bytes(THROUGHPUT_APP_UUID) {
+            if status == 0 {
+                self.throughput_client_id = Some(client_id);
+            }
+            return;
+        }
+
+        if Uuid128::from(app_uuid) == Uuid128::from_bytes(HOGP_APP_UUID) {
+            if status == 0 {
+                self.hogp_client_id = Some(client_id);
+                let addrs: Vec<BDAddr> = self.hogp_addrs.iter().copied().collect();
+                for addr in addrs {
+                    self.connect_hid_device(addr);
+                }
+            }
+            return;
+        }
+
+        let index = self
+            .clients
+            .iter()
+            .position(|client| client.id.is_none() && client.uuid == Uuid128::from(app_uuid));
+
+        let index = match index {
+            Some(index) => index,
+            None => return,
+        };
+
+        let callback_id = self.clients[index].callback_id;
+        if let Some(callback) = self.client_callbacks.get_by_id(callback_id) {
+            callback.on_client_registered(GattStatus::from(status), client_id);
+        }
+        self.client_registrations.complete(&callback_id, (GattStatus::from(status), client_id));
+
+        if status == 0 {
+            self.clients[index].id = Some(client_id);
+        } else {
+            self.clients.remove(index);
+            self.client_callbacks.remove_callback(callback_id);
+        }
+    }
+
+    fn client_connect(&mut self, conn_id: i32, status: i32, client_id: i32, addr: RustRawAddress) {
+        let addr = BDAddr::from_raw_address(&addr);
+
+        if status == 0 {
+            self.client_connections.insert(conn_id, ClientConnection { client_id, addr });
+
+            // Some devices only serve their characteristics once the MTU is large enough.
+            if let Some(mtu) = self.preferred_mtus.get(&(client_id, addr)) {
+                if let Some(gatt) = &mut self.gatt {
+                    gatt.configure_mtu(conn_id, *mtu);
+                }
+            }
+
+            // HID devices only serve their reports over encrypted links.
+            if Some(client_id) == self.hogp_client_id {
+                self.bluetooth.lock().unwrap().request_le_encryption(&addr, false);
+                if let Some(gatt) = &mut self.gatt {
+                    gatt.search_service(conn_id);
+                }
+            }
+        }
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_client_connection_state(
+                GattStatus::from(status),
+                status == 0,
+                addr.to_string(),
+                conn_id,
+            );
+        }
+    }
+
+    fn client_disconnect(
+        &mut self,
+        conn_id: i32,
+        status: i32,
+        client_id: i32,
+        addr: RustRawAddress,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+
+        self.client_connections.remove(&conn_id);
+        self.mtus.remove(&conn_id);
+        self.gatt_dbs.remove(&conn_id);
+        self.database_hashes.remove(&conn_id);
+        self.stale_dbs.remove(&conn_id);
+        self.client_reads.remove(&conn_id);
+        // The stack drops the writes in flight, a connection id used again must not answer them.
+        self.client_writes.remove(&conn_id);
+        self.reliable_writes.remove(&conn_id);
+        self.cccd_writes.remove(&conn_id);
+        self.drop_subscriptions(client_id, Some(addr));
+        self.throughput_connection_closed(conn_id);
+        // Destroys the input device of a HID device.
+        self.hogp_devices.remove(&conn_id);
+        self.update_connection_priority(client_id, addr, None);
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_client_connection_state(
+                GattStatus::from(status),
+                false,
+                addr.to_string(),
+                conn_id,
+            );
+        }
+    }
+
+    fn client_read_remote_rssi(
+        &mut self,
+        client_id: i32,
+        addr: RustRawAddress,
+        rssi: i32,
+        status: i32,
+    ) {
+        // The reads are made by `Bluetooth`, see `remote_rssi_read`, this only comes for reads
+        // made by the native stack on its own.
+        let addr = BDAddr::from_raw_address(&addr);
+        let waiting_clients = &mut self.rssi_reads.entry(addr).or_default().waiting_clients;
+        if !waiting_clients.contains(&client_id) {
+            waiting_clients.push(client_id);
+        }
+        self.remote_rssi_read(addr, status, rssi);
+    }
+
+    /// Answers the clients waiting for the RSSI of a device, once `Bluetooth::read_remote_rssi`
+    /// is done.
+    pub(crate) fn remote_rssi_read(&mut self, addr: BDAddr, status: i32, rssi: i32) {
+        let state = self.rssi_reads.entry(addr).or_default();
+
+        if status == 0 {
+            state.last_read = Some(Instant::now());
+            state.last_rssi = rssi;
+        }
+
+        for id in std::mem::take(&mut state.waiting_clients) {
+            if let Some(callback) = self.client_callback(id) {
+                callback.on_read_remote_rssi(addr.to_string(), rssi, status);
+            }
+        }
+    }
+
+    fn client_search_complete(&mut self, conn_id: i32, status: i32) {
+        // The database is reported to the client once it is read.
+        let status = match (status, &mut self.gatt) {
+            (0, Some(gatt)) => gatt.get_gatt_db(conn_id),
+            _ => status,
+        };
+
+        if status == 0 {
+            return;
+        }
+
+        self.throughput_discovery_done(conn_id, status);
+        self.hogp_discovery_done(conn_id, status);
+
+        let client_id = match self.client_connections.get(&conn_id) {
+            Some(connection) => connection.client_id,
+            None => return,
+        };
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_search_complete(conn_id, GattStatus::from(status));
+        }
+    }
+
+    fn client_notify(
+        &mut self,
+        conn_id: i32,
+        _addr: RustRawAddress,
+        handle: i32,
+        _is_notify: bool,
+        value: Vec<u8>,
+    ) {
+        if let Some(device) = self.hogp_devices.get_mut(&conn_id) {
+            device.input_report(handle, &value);
+            return;
+        }
+
+        let (client_id, addr) = match self.client_connections.get(&conn_id) {
+            Some(connection) => (connection.client_id, connection.addr),
+            None => return,
+        };
+
+        // The native stack may still route the notifications of a dropped subscription.
+        if !self.subscriptions.get(&(addr, handle)).map_or(false, |c| c.contains(&client_id)) {
+            return;
+        }
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_characteristic_changed(conn_id, handle, value);
+        }
+    }
+
+    fn client_get_gatt_db(&mut self, conn_id: i32, elements: Vec<BtGattDbElement>) {
+        let client_id = match self.client_connections.get(&conn_id) {
+            Some(connection) => connection.client_id,
+            None => return,
+        };
+
+        self.gatt_dbs.insert(conn_id, elements);
+        self.database_hashes.remove(&conn_id);
+        self.throughput_discovery_done(conn_id, 0);
+        self.hogp_discovery_done(conn_id, 0);
+
+        // The discovery is reported once the hash is read, so that the client can check its cache
+        // of the services right away.
+        if self.read_database_hash(client_id, conn_id) {
+            return;
+        }
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_search_complete(conn_id, GattStatus::Success);
+        }
+    }
+
+    fn client_read_characteristic(
+        &mut self,
+        conn_id: i32,
+        status: i32,
+        handle: i32,
+        value: Vec<u8>,
+    ) {
+        if self.hogp_operation_done(conn_id, status, &value) {
+            return;
+        }
+
+        let client_id = match self.client_connections.get(&conn_id) {
+            Some(connection) => connection.client_id,
+            None => return,
+        };
+
+        let read = self.client_reads.get_mut(&conn_id).and_then(|reads| reads.pop_front());
+        if let Some(ClientRead::DatabaseHash) = read {
+            // The services were discovered even if the hash can't be read.
+            if status == 0 && value.len() == DATABASE_HASH_LEN {
+                self.database_hashes.insert(conn_id, value);
+            }
+
+            if let Some(callback) = self.client_callback(client_id) {
+                callback.on_search_complete(conn_id, GattStatus::Success);
+            }
+            return;
+        }
+
+        let max_len = self.get_mtu(conn_id) - ATT_READ_BY_TYPE_HEADER_LEN;
+        if let (Some(ClientRead::ByUuid(auth_req)), Some(gatt)) = (read, &mut self.gatt) {
+            // A value filling the response may have been cut. Otherwise, or if it can't be read
+            // again, the value read so far is reported.
+            if status == 0
+                && value.len() as i32 >= max_len
+                && gatt.read_characteristic(conn_id, handle as u16, auth_req) == 0
+            {
+                self.client_reads.entry(conn_id).or_default().push_back(ClientRead::Long);
+                return;
+            }
+        }
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_characteristic_read(conn_id, status, handle, value);
+        }
+    }
+
+    fn client_write_characteristic(&mut self, conn_id: i32, status: i32, handle: i32) {
+        if self.throughput_packet_sent(conn_id, status)
+            || self.hogp_operation_done(conn_id, status, &[])
+        {
+            return;
+        }
+
+        let client_id = match self.client_writes.get_mut(&conn_id).and_then(|ids| ids.pop_front()) {
+            Some((client_id, requested)) => {
+                let latency = requested.elapsed();
+                self.metrics.lock().unwrap().record_latency(metrics::GATT_WRITE_LATENCY, latency);
+                client_id
+            }
+            None => return,
+        };
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_characteristic_write(conn_id, status, handle);
+        }
+    }
+
+    // Only the HOGP host reads descriptors.
+    fn client_read_descriptor(&mut self, conn_id: i32, status: i32, _handle: i32, value: Vec<u8>) {
+        self.hogp_operation_done(conn_id, status, &value);
+    }
+
+    fn client_write_descriptor(&mut self, conn_id: i32, status: i32, _handle: i32) {
+        if self.hogp_operation_done(conn_id, status, &[]) {
+            return;
+        }
+
+        let write = match self.cccd_writes.get_mut(&conn_id).and_then(|writes| writes.pop_front()) {
+            Some(write) => write,
+            None => return,
+        };
+
+        let (client_id, addr) = match self.client_connections.get(&conn_id) {
+            Some(connection) => (connection.client_id, connection.addr),
+            None => return,
+        };
+
+        if status != GattStatus::Success as i32 && write.enable {
+            // The descriptor is not enabled, so the clients that subscribed while it was written
+            // don't get the notifications either.
+            let clients = self.subscriptions.remove(&(addr, write.handle)).unwrap_or_default();
+            for id in clients {
+                if let Some(gatt) = &mut self.gatt {
+                    gatt.deregister_for_notification(
+                        id,
+                        &addr.to_raw_address(),
+                        write.handle as u16,
+                    );
+                }
+
+                if id == client_id {
+                    continue;
+                }
+
+                let other_conn_id = self
+                    .client_connections
+                    .iter()
+                    .find(|(_, connection)| connection.client_id == id && connection.addr == addr)
+                    .map(|(conn_id, _)| *conn_id);
+                if let (Some(other_conn_id), Some(callback)) =
+                    (other_conn_id, self.client_callback(id))
+                {
+                    callback.on_notification_registered(other_conn_id, status, write.handle, true);
+                }
+            }
+        }
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_notification_registered(conn_id, status, write.handle, write.enable);
+        }
+    }
+
+    fn client_execute_write(&mut self, conn_id: i32, status: i32) {
+        let client_id = match self.reliable_writes.remove(&conn_id) {
+            Some(write) => write.client_id,
+            None => return,
+        };
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_execute_write(conn_id, status);
+        }
+    }
+
+    fn client_configure_mtu(&mut self, conn_id: i32, status: i32, mtu: i32) {
+        let client_id = match self.client_connections.get(&conn_id) {
+            Some(connection) => connection.client_id,
+            None => return,
+        };
+
+        if status == 0 {
+            self.mtus.insert(conn_id, mtu);
+        }
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_configure_mtu(conn_id, GattStatus::from(status), mtu);
+        }
+    }
+
+    fn client_phy_updated(&mut self, conn_id: i32, tx_phy: i32, rx_phy: i32, status: i32) {
+        let (client_id, addr) = match self.client_connections.get(&conn_id) {
+            Some(connection) => (connection.client_id, connection.addr),
+            None => return,
+        };
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_phy_update(addr.to_string(), tx_phy as u32, rx_phy as u32, status);
+        }
+    }
+
+    fn client_conn_updated(
+        &mut self,
+        conn_id: i32,
+        interval: i32,
+        latency: i32,
+        timeout: i32,
+        status: i32,
+    ) {
+        let (client_id, addr) = match self.client_connections.get(&conn_id) {
+            Some(connection) => (connection.client_id, connection.addr),
+            None => return,
+        };
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_connection_updated(addr.to_string(), interval, latency, timeout, status);
+        }
+    }
+
+    fn client_service_changed(&mut self, conn_id: i32) {
+        let (client_id, addr) = match self.client_connections.get(&conn_id) {
+            Some(connection) => (connection.client_id, connection.addr),
+            None => return,
+        };
+
+        // The handles of the previous database may now point to other attributes.
+        self.gatt_dbs.remove(&conn_id);
+        self.database_hashes.remove(&conn_id);
+        self.drop_subscriptions(client_id, Some(addr));
+        // Destroys the input device of a HID device, created again once the services are
+        // discovered.
+        self.hogp_devices.remove(&conn_id);
+
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_service_changed(addr.to_string());
+        }
+
+        // The native stack discovers the services again on its own, searching now would only
+        // return the database it is replacing.
+        self.stale_dbs.insert(conn_id);
+    }
+
+    fn client_services_rediscovered(&mut self, addr: RustRawAddress) {
+        let addr = BDAddr::from_raw_address(&addr);
+        let conn_ids: Vec<i32> = self
+            .stale_dbs
+            .iter()
+            .copied()
+            .filter(|conn_id| {
+                self.client_connections.get(conn_id).map_or(false, |c| c.addr == addr)
+            })
+            .collect();
+
+        for conn_id in conn_ids {
+            self.stale_dbs.remove(&conn_id);
+            // Reported through `client_get_gatt_db`, as a search would.
+            let status = match &mut self.gatt {
+                Some(gatt) => gatt.get_gatt_db(conn_id),
+                None => continue,
+            };
+            if status != 0 {
+                self.client_search_complete(conn_id, status);
+            }
+        }
+    }
+
+    fn client_read_phy(
+        &mut self,
+        client_id: i32,
+        addr: RustRawAddress,
+        tx_phy: i32,
+        rx_phy: i32,
+        status: i32,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+        if let Some(callback) = self.client_callback(client_id) {
+            callback.on_phy_read(addr.to_string(), tx_phy as u32, rx_phy as u32, status);
+        }
+    }
+
+    fn server_registered(&mut self, status: i32, server_id: i32, app_uuid: BtUuid) {
+        if Uuid128::from(app_uuid) == Uuid128::from_bytes(THROUGHPUT_APP_UUID) {
+            if status == 0 {
+                self.throughput_server_id = Some(server_id);
+                self.add_local_service(server_id, throughput_service());
+            }
+            return;
+        }
+
+        let index = self
+            .servers
+            .iter()
+            .position(|server| server.id.is_none() && server.uuid == Uuid128::from(app_uuid));
+
+        let index = match index {
+            Some(index) => index,
+            None => return,
+        };
+
+        let callback_id = self.servers[index].callback_id;
+        if let Some(callback) = self.server_callbacks.get_by_id(callback_id) {
+            callback.on_server_registered(GattStatus::from(status), server_id);
+        }
+
+        if status == 0 {
+            self.servers[index].id = Some(server_id);
+        } else {
+            self.servers.remove(index);
+            self.server_callbacks.remove_callback(callback_id);
+        }
+    }
+
+    fn server_connection(
+        &mut self,
+        conn_id: i32,
+        server_id: i32,
+        connected: i32,
+        addr: RustRawAddress,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+        let connected = connected != 0;
+
+        if connected {
+            self.notification_queues.insert(
+                conn_id,
+                NotificationQueue {
+                    server_id,
+                    addr,
+                    congested: false,
+                    in_flight: false,
+                    refused: false,
+                    pending: VecDeque::new(),
+                },
+            );
+        } else {
+            self.notification_queues.remove(&conn_id);
+            self.prepared_writes.remove(&conn_id);
+            self.mtus.remove(&conn_id);
+            self.throughput_connection_closed(conn_id);
+        }
+
+        if let Some(callback) = self.server_callback(server_id) {
+            callback.on_server_connection_state(server_id, connected, addr.to_string());
+        }
+    }
+
+    fn server_indication_sent(&mut self, conn_id: i32, status: i32) {
+        if self.throughput_packet_sent(conn_id, status) {
+            return;
+        }
+
+        let (server_id, addr) = match self.notification_queues.get_mut(&conn_id) {
+            Some(queue) => {
+                queue.in_flight = false;
+                (queue.server_id, queue.addr)
+            }
+            None => return,
+        };
+
+        if let Some(callback) = self.server_callback(server_id) {
+            callback.on_notification_sent(addr.to_string(), GattStatus::from(status));
+        }
+
+        self.pump_notifications(conn_id);
+    }
+
+    fn server_congestion(&mut self, conn_id: i32, congested: bool) {
+        match self.notification_queues.get_mut(&conn_id) {
+            Some(queue) => queue.congested = congested,
+            None => return,
+        }
+
+        if !congested {
+            self.pump_notifications(conn_id);
+            self.pump_throughput_test();
+        }
+    }
+
+    fn server_mtu_changed(&mut self, conn_id: i32, mtu: i32) {
+        if self.notification_queues.contains_key(&conn_id) {
+            self.mtus.insert(conn_id, mtu);
+        }
+    }
+
+    fn server_read_phy(
+        &mut self,
+        server_id: i32,
+        addr: RustRawAddress,
+        tx_phy: i32,
+        rx_phy: i32,
+        status: i32,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+        if let Some(callback) = self.server_callback(server_id) {
+            callback.on_phy_read(addr.to_string(), tx_phy as u32, rx_phy as u32, status);
+        }
+    }
+
+    fn server_request_write(
+        &mut self,
+        conn_id: i32,
+        trans_id: i32,
+        addr: RustRawAddress,
+        handle: i32,
+        offset: i32,
+        need_rsp: bool,
+        is_prep: bool,
+        value: Vec<u8>,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+        let server_id = match self.notification_queues.get(&conn_id) {
+            Some(queue) => queue.server_id,
+            None => return,
+        };
+
+        if self.is_foreign_attribute(server_id, handle) {
+            if need_rsp || is_prep {
+                if let Some(gatt) = &mut self.gatt {
+                    let status = GattStatus::InvalidHandle as i32;
+                    gatt.send_response(conn_id, trans_id, status, handle, offset, &[]);
+                }
+            }
+            return;
+        }
+
+        if let Some(status) = self.check_link_security(&addr, handle, true) {
+            // Writes without response are dropped, as ATT has no way to reject them.
+            if need_rsp || is_prep {
+                if let Some(gatt) = &mut self.gatt {
+                    gatt.send_response(conn_id, trans_id, status as i32, handle, offset, &[]);
+                }
+            }
+            return;
+        }
+
+        // The packets of the throughput tests are dropped once received.
+        if Some(server_id) == self.throughput_server_id {
+            if need_rsp || is_prep {
+                if let Some(gatt) = &mut self.gatt {
+                    gatt.send_response(conn_id, trans_id, 0, handle, offset, &value);
+                }
+            }
+            return;
+        }
+
+        if !is_prep {
+            if let Some(callback) = self.server_callback(server_id) {
+                callback.on_attribute_write_request(
+                    addr.to_string(),
+                    trans_id,
+                    handle,
+                    offset,
+                    need_rsp,
+                    value,
+                );
+            }
+            return;
+        }
+
+        let gatt = match &mut self.gatt {
+            Some(gatt) => gatt,
+            None => return,
+        };
+
+        let writes = self.prepared_writes.entry(conn_id).or_default();
+        let buffered_len: usize = writes.iter().map(|write| write.value.len()).sum();
+
+        if buffered_len + value.len() > PREPARED_WRITES_MAX_LEN {
+            gatt.send_response(
+                conn_id,
+                trans_id,
+                GattStatus::PrepareQueueFull as i32,
+                handle,
+                offset,
+                &[],
+            );
+            return;
+        }
+
+        // The response to a prepared write echoes the request so the device can check it.
+        gatt.send_response(conn_id, trans_id, 0, handle, offset, &value);
+
+        // Chunks of a long write of the same attribute are reported as one write.
+        if let Some(last) = writes.last_mut() {
+            if last.handle == handle && last.offset + last.value.len() as i32 == offset {
+                last.value.extend(value);
+                return;
+            }
+        }
+
+        writes.push(PreparedWrite { handle, offset, value });
+    }
+
+    fn server_request_exec_write(
+        &mut self,
+        conn_id: i32,
+        trans_id: i32,
+        addr: RustRawAddress,
+        exec_write: i32,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+        let writes = self.prepared_writes.remove(&conn_id).unwrap_or_default();
+
+        if exec_write == 0 {
+            if let Some(gatt) = &mut self.gatt {
+                gatt.send_response(conn_id, trans_id, 0, 0, 0, &[]);
+            }
+            return;
+        }
+
+        let server_id = match self.notification_queues.get(&conn_id) {
+            Some(queue) => queue.server_id,
+            None => return,
+        };
+
+        if Some(server_id) == self.throughput_server_id {
+            if let Some(gatt) = &mut self.gatt {
+                gatt.send_response(conn_id, trans_id, 0, 0, 0, &[]);
+            }
+            return;
+        }
+
+        if let Some(callback) = self.server_callback(server_id) {
+            for write in writes {
+                callback.on_attribute_write_request(
+                    addr.to_string(),
+                    trans_id,
+                    write.handle,
+                    write.offset,
+                    false,
+                    write.value,
+                );
+            }
+            callback.on_execute_write_request(addr.to_string(), trans_id);
+        }
+    }
+
+    fn server_service_added(
+        &mut self,
+        status: i32,
+        server_id: i32,
+        elements: Vec<BtGattDbElement>,
+    ) {
+        let mut service = match self.pending_services.get_mut(&server_id) {
+            Some(pending) => match pending.pop_front() {
+                Some(service) => service,
+                None => return,
+            },
+            None => return,
+        };
+
+        if status == 0 && elements.len() == service.attributes.len() + 1 {
+            service.handle = elements[0].attribute_handle as i32;
+            for (attribute, element) in service.attributes.iter_mut().zip(&elements[1..]) {
+                attribute.handle = element.attribute_handle as i32;
+                let attribute = LocalAttribute { server_id, permissions: attribute.permissions };
+                self.local_attributes.insert(element.attribute_handle as i32, attribute);
+            }
+
+            self.local_services.insert(
+                service.handle,
+                LocalService {
+                    server_id,
+                    uuid: service.uuid,
+                    primary: service.primary,
+                    attribute_handles: service.attributes.iter().map(|a| a.handle).collect(),
+                },
+            );
+
+            if Some(server_id) == self.throughput_server_id {
+                self.throughput_handle = Some(service.attributes[0].handle);
+            }
+        }
+
+        if let Some(callback) = self.server_callback(server_id) {
+            callback.on_service_added(GattStatus::from(status), service);
+        }
+    }
+
+    fn server_request_read(
+        &mut self,
+        conn_id: i32,
+        trans_id: i32,
+        addr: RustRawAddress,
+        handle: i32,
+        offset: i32,
+        is_long: bool,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+        let server_id = match self.notification_queues.get(&conn_id) {
+            Some(queue) => queue.server_id,
+            None => return,
+        };
+
+        // The response of another server couldn't be sent over this connection.
+        if self.is_foreign_attribute(server_id, handle) {
+            if let Some(gatt) = &mut self.gatt {
+                let status = GattStatus::InvalidHandle as i32;
+                gatt.send_response(conn_id, trans_id, status, handle, offset, &[]);
+            }
+            return;
+        }
+
+        if let Some(status) = self.check_link_security(&addr, handle, false) {
+            if let Some(gatt) = &mut self.gatt {
+                gatt.send_response(conn_id, trans_id, status as i32, handle, offset, &[]);
+            }
+            return;
+        }
+
+        // The attributes of the test service read as empty.
+        if Some(server_id) == self.throughput_server_id {
+            if let Some(gatt) = &mut self.gatt {
+                gatt.send_response(conn_id, trans_id, 0, handle, offset, &[]);
+            }
+            return;
+        }
+
+        if let Some(callback) = self.server_callback(server_id) {
+            callback.on_attribute_read_request(addr.to_string(), trans_id, handle, offset, is_long);
+        }
+    }
+
+    fn scanner_registered(&mut self, app_uuid: BtUuid, scanner_id: i32, status: i32) {
+        let index = self
+            .scanners
+            .iter()
+            .position(|scanner| scanner.id.is_none() && scanner.uuid == Uuid128::from(app_uuid));
+
+        let index = match index {
+            Some(index) => index,
+            None => {
+                // The client disconnected while the registration was pending.
+                if status == 0 {
+                    if let Some(gatt) = &mut self.gatt {
+                        gatt.unregister_scanner(scanner_id);
+                    }
+                }
+                return;
+            }
+        };
+
+        let callback_id = self.scanners[index].callback_id;
+        if let Some(callback) = self.scanner_callbacks.get_by_id(callback_id) {
+            callback.on_scanner_registered(status, scanner_id);
+        }
+
+        if status == 0 {
+            self.scanners[index].id = Some(scanner_id);
+        } else {
+            self.scanners.remove(index);
+            self.scanner_callbacks.remove_callback(callback_id);
+        }
+    }
+
+    fn scan_result(
+        &mut self,
+        event_type: i32,
+        addr_type: i32,
+        addr: RustRawAddress,
+        primary_phy: i32,
+        secondary_phy: i32,
+        advertising_sid: i32,
+        tx_power: i32,
+        rssi: i32,
+        periodic_adv_int: i32,
+        adv_data: Vec<u8>,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+        self.metrics.lock().unwrap().increment(metrics::SCAN_RESULTS);
+
+        // Legacy reports don't have the fields of extended advertising, whatever the lower layers
+        // filled them with.
+        let (primary_phy, secondary_phy, advertising_sid, periodic_adv_int) =
+            if event_type & SCAN_EVENT_TYPE_LEGACY != 0 {
+                (LePhy::Le1m as i32, SECONDARY_PHY_NONE, ADVERTISING_SID_NONE, 0)
+            } else {
+                (primary_phy, secondary_phy, advertising_sid, periodic_adv_int)
+            };
+
+        // The native stack already reports the advertisements of bonded devices using private
+        // addresses with their identity addresses. Anonymous advertisements have no address to
+        // remember anything for.
+        let appearance = ad_appearance(&adv_data);
+        if let Some(appearance) = appearance.filter(|_| addr_type != BLE_ADDR_ANONYMOUS) {
+            self.bluetooth.lock().unwrap().update_remote_appearance(addr, appearance);
+        }
+
+        let category = appearance.map_or(DeviceCategory::Unknown, DeviceCategory::from_appearance);
+        let result = ScanResult {
+            address: addr.to_string(),
+            addr_type,
+            event_type,
+            primary_phy,
+            secondary_phy,
+            advertising_sid,
+            tx_power,
+            rssi,
+            periodic_adv_int,
+            adv_data,
+            category,
+        };
+
+        if self.intercept_advertisement(addr, &result) {
+            return;
+        }
+
+        for monitor in &mut self.monitors {
+            if rssi <= monitor.monitor.rssi_low_threshold {
+                monitor.found.remove(&addr);
+            }
+
+            if rssi < monitor.monitor.rssi_high_threshold
+                || monitor.found.contains(&addr)
+                || !monitor.monitor.matches(&result.adv_data)
+            {
+                continue;
+            }
+
+            monitor.found.insert(addr);
+            if let Some(callback) = self.monitor_callbacks.get_by_id(monitor.callback_id) {
+                callback.on_device_found_in_monitor(
+                    monitor.id,
+                    addr.to_string(),
+                    rssi,
+                    result.adv_data.clone(),
+                );
+            }
+        }
+
+        for scanner in &mut self.scanners {
+            let (scan, callback) =
+                match (&mut scanner.scan, self.scanner_callbacks.get_by_id(scanner.callback_id)) {
+                    (Some(scan), Some(callback)) => (scan, callback),
+                    _ => continue,
+                };
+
+            match scan.report_mode {
+                ScanReportMode::All => callback.on_scan_result(result.clone()),
+                // Anonymous advertisers can't be told apart, so each of their advertisements is
+                // a first match.
+                ScanReportMode::FirstMatch => {
+                    if result.is_anonymous() || scan.reported.insert(addr) {
+                        callback.on_scan_result(result.clone());
+                    }
+                }
+                ScanReportMode::Periodic if result.is_anonymous() => {
+                    if scan.anonymous_batch.len() < SCAN_BATCH_ANONYMOUS_MAX {
+                        scan.anonymous_batch.push(result.clone());
+                    }
+                }
+                ScanReportMode::Periodic => {
+                    scan.batch.insert(addr, result.clone());
+                }
+            }
+        }
+    }
+
+    fn scanner_filter_configured(&mut self, filter_index: i32, status: i32) {
+        if status == 0 {
+            return;
+        }
+
+        // The monitor falls back to matching the advertisements on the host.
+        let monitor = self
+            .monitors
+            .iter_mut()
+            .find(|monitor| monitor.filter_index.map(i32::from) == Some(filter_index));
+        if let Some(monitor) = monitor {
+            if let (Some(index), Some(gatt)) = (monitor.filter_index.take(), &mut self.gatt) {
+                gatt.clear_scan_filter(index);
+            }
+        }
+
+        self.update_scan_filter();
+    }
+
+    fn advertiser_set_started(
+        &mut self,
+        reg_id: i32,
+        advertiser_id: i32,
+        tx_power: i32,
+        status: i32,
+    ) {
+        let index =
+            self.advertising_sets.iter().position(|set| set.id.is_none() && set.reg_id == reg_id);
+
+        let index = match index {
+            Some(index) => index,
+            None => {
+                // The client went away while the set was starting.
+                if status == 0 {
+                    if let Some(gatt) = &mut self.gatt {
+                        gatt.unregister_advertiser(advertiser_id);
+                    }
+                }
+                return;
+            }
+        };
+
+        let callback_id = self.advertising_sets[index].callback_id;
+        if let Some(callback) = self.advertiser_callbacks.get_by_id(callback_id) {
+            callback.on_advertising_set_started(advertiser_id, tx_power, status);
+        }
+
+        if status == 0 {
+            self.advertising_sets[index].id = Some(advertiser_id);
+            self.advertising_sets[index].enabled = true;
+        } else {
+            self.advertising_sets.remove(index);
+            self.advertiser_callbacks.remove_callback(callback_id);
+        }
+    }
+
+    fn advertiser_enabled(&mut self, advertiser_id: i32, enable: bool, status: i32) {
+        let set = match self.advertising_sets.iter_mut().find(|s| s.id == Some(advertiser_id)) {
+            Some(set) => set,
+            None => return,
+        };
+
+        if status == 0 {
+            set.enabled = enable;
+        }
+
+        if set.updating {
+            if enable {
+                set.updating = false;
+            } else if status == 0 {
+                // The set is disabled, its parameters can change.
+                let parameters =
+                    set.pending_parameters.as_ref().map(|p| p.to_advertise_parameters());
+                if let (Some(parameters), Some(gatt)) = (parameters, &mut self.gatt) {
+                    gatt.set_advertising_parameters(advertiser_id, &parameters);
+                }
+                return;
+            } else {
+                set.updating = false;
+                set.pending_parameters = None;
+                if let Some(callback) = self.advertiser_callback(advertiser_id) {
+                    callback.on_advertising_parameters_updated(advertiser_id, 0, status);
+                }
+                return;
+            }
+
+            // A set that can't be enabled again is reported as disabled.
+            if status == 0 {
+                return;
+            }
+        }
+
+        if let Some(callback) = self.advertiser_callback(advertiser_id) {
+            callback.on_advertising_enabled(advertiser_id, enable && status == 0, status);
+        }
+    }
+
+    fn advertiser_data_set(&mut self, advertiser_id: i32, set_scan_rsp: bool, status: i32) {
+        if let Some(callback) = self.advertiser_callback(advertiser_id) {
+            if set_scan_rsp {
+                callback.on_scan_response_data_set(advertiser_id, status);
+            } else {
+                callback.on_advertising_data_set(advertiser_id, status);
+            }
+        }
+    }
+
+    fn advertiser_parameters_updated(&mut self, advertiser_id: i32, tx_power: i32, status: i32) {
+        let set = match self.advertising_sets.iter_mut().find(|s| s.id == Some(advertiser_id)) {
+            Some(set) => set,
+            None => return,
+        };
+
+        if let Some(parameters) = set.pending_parameters.take() {
+            if status == 0 {
+                set.parameters = parameters;
+            }
+        }
+
+        // The set was disabled for the update, it advertises again with the same limits.
+        if set.updating {
+            let (duration, max_ext_adv_events) = (set.duration, set.max_ext_adv_events);
+            if let Some(gatt) = &mut self.gatt {
+                gatt.enable_advertising(advertiser_id, true, duration, max_ext_adv_events);
+            }
+        }
+
+        if let Some(callback) = self.advertiser_callback(advertiser_id) {
+            callback.on_advertising_parameters_updated(advertiser_id, tx_power, status);
+        }
+    }
+
+    fn advertiser_own_address_read(
+        &mut self,
+        advertiser_id: i32,
+        address_type: i32,
+        address: RustRawAddress,
+    ) {
+        let address = BDAddr::from_raw_address(&address);
+        if let Some(callback) = self.advertiser_callback(advertiser_id) {
+            callback.on_own_address_read(advertiser_id, address_type, address.to_string());
+        }
+    }
+}
+
+impl IBluetoothGatt for BluetoothGatt {
+    fn register_scanner(&mut self, callback: Box<dyn IScannerCallback + Send>) {
+        let gatt = match &mut self.gatt {
+            Some(gatt) => gatt,
+            None => return,
+        };
+
+        self.scanner_registrations = self.scanner_registrations.wrapping_add(1);
+        let mut uu = [0u8; 16];
+        uu[12..].copy_from_slice(&self.scanner_registrations.to_be_bytes());
+        let uuid = Uuid128::from_bytes(uu);
+
+        let callback_id = self.scanner_callbacks.add_callback(callback);
+        self.scanners.push(Scanner { id: None, uuid, callback_id, scan: None });
+        gatt.register_scanner(&uuid.into());
+    }
+
+    fn unregister_scanner(&mut self, scanner_id: i32) {
+        self.end_scan(scanner_id);
+        if let Some(index) = self.scanners.iter().position(|scanner| scanner.id == Some(scanner_id))
+        {
+            let scanner = self.scanners.remove(index);
+            self.scanner_callbacks.remove_callback(scanner.callback_id);
+        }
+
+        if let Some(gatt) = &mut self.gatt {
+            gatt.unregister_scanner(scanner_id);
+        }
+    }
+
+    fn start_scan(
+        &mut self,
+        scanner_id: i32,
+        settings: ScanSettings,
+        _filters: Vec<ScanFilter>,
+    ) -> bool {
+        // TODO: Apply the scan filters.
+        if settings.report_mode == ScanReportMode::Periodic
+            && settings.report_period_ms < SCAN_REPORT_PERIOD_MIN_MS
+        {
+            return false;
+        }
+
+        let parameters = match scan_parameters(&settings) {
+            Some(parameters) => parameters,
+            None => return false,
+        };
+
+        let tx = self.tx.clone();
+
+        if self.find_scanner_mut(scanner_id).is_none() {
+            return false;
+        }
+
+        // Restarting a scan reports the devices again.
+        self.end_scan(scanner_id);
+        let scanning = self.is_scanning();
+
+        let report_ticker = match settings.report_mode {
+            ScanReportMode::Periodic => {
+                let period = Duration::from_millis(settings.report_period_ms as u64);
+                Some(topstack::get_runtime().spawn(async move {
+                    let mut interval =
+                        tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                    loop {
+                        interval.tick().await;
+                        if tx.send(Message::GattScannerReportTick(scanner_id)).await.is_err() {
+                            break;
+                        }
+                    }
+                }))
+            }
+            _ => None,
+        };
+
+        self.find_scanner_mut(scanner_id).unwrap().scan = Some(ActiveScan {
+            parameters,
+            background: settings.background,
+            report_mode: settings.report_mode,
+            reported: HashSet::new(),
+            batch: HashMap::new(),
+            anonymous_batch: vec![],
+            report_ticker,
+        });
+
+        self.update_scan_parameters(scanning);
+        if !scanning {
+            if let Some(gatt) = &mut self.gatt {
+                gatt.scan(true);
+            }
+        }
+        self.update_scan_filter();
+
+        true
+    }
+
+    fn stop_scan(&mut self, scanner_id: i32) -> bool {
+        self.end_scan(scanner_id)
+    }
+
+    fn register_client(
+        &mut self,
+        app_uuid: Uuid128,
+        callback: Box<dyn IBluetoothGattCallback + Send>,
+        eatt_support: bool,
+    ) {
+        let gatt = match &mut self.gatt {
+            Some(gatt) => gatt,
+            None => return,
+        };
+
+        let callback_id = self.client_callbacks.add_callback(callback);
+        self.clients.push(GattClient { id: None, uuid: app_uuid, callback_id });
+        gatt.register_client(&app_uuid.into(), eatt_support);
+    }
+
+    fn unregister_client(&mut self, client_id: i32) {
+        if let Some(client) = self.find_client(client_id) {
+            let callback_id = client.callback_id;
+            self.client_callbacks.remove_callback(callback_id);
+        }
+        self.clients.retain(|client| client.id != Some(client_id));
+
+        // Leave no writes of the client queued by the remote devices.
+        let conn_ids: Vec<i32> = self
+            .reliable_writes
+            .iter()
+            .filter(|(_, write)| write.client_id == client_id && !write.closing)
+            .map(|(conn_id, _)| *conn_id)
+            .collect();
+        for conn_id in conn_ids {
+            self.close_reliable_write(client_id, conn_id, false);
+        }
+
+        for state in self.rssi_reads.values_mut() {
+            state.waiting_clients.retain(|id| *id != client_id);
+        }
+
+        self.preferred_mtus.retain(|(id, _), _| *id != client_id);
+        self.drop_subscriptions(client_id, None);
+
+        let addrs: Vec<BDAddr> = self
+            .connection_priorities
+            .iter()
+            .filter(|(_, requests)| requests.contains_key(&client_id))
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in addrs {
+            self.update_connection_priority(client_id, addr, None);
+        }
+
+        if let Some(gatt) = &mut self.gatt {
+            gatt.unregister_client(client_id);
+        }
+    }
+
+    fn client_connect(&mut self, client_id: i32, addr: String, is_direct: bool) -> GattStatus {
+        let addr = match BDAddr::from_string(&addr) {
+            Some(addr) => addr,
+            None => return GattStatus::IllegalParameter,
+        };
+
+        if self.find_client(client_id).is_none() {
+            return GattStatus::IllegalParameter;
+        }
+
+        if self.find_client_conn_id(client_id, addr).is_some() {
+            return GattStatus::AlreadyOpen;
+        }
+
+        // The native stack attaches the client to the link if it is up, and keeps the link up
+        // until none of its clients uses it.
+        let gatt = match &mut self.gatt {
+            Some(gatt) => gatt,
+            None => return GattStatus::Error,
+        };
+
+        let raw = addr.to_raw_address();
+        match gatt.connect(client_id, &raw, is_direct, TRANSPORT_LE, false, PHY_LE_1M) {
+            0 => GattStatus::Success,
+            _ => GattStatus::Error,
+        }
+    }
+
+    fn client_disconnect(&mut self, client_id: i32, addr: String) -> GattStatus {
+        let addr = match BDAddr::from_string(&addr) {
+            Some(addr) => addr,
+            None => return GattStatus::IllegalParameter,
+        };
+
+        if self.find_client(client_id).is_none() {
+            return GattStatus::IllegalParameter;
+        }
+
+        // Without a connection, only the background connection of the client is cancelled.
+        let conn_id = self.find_client_conn_id(client_id, addr).unwrap_or(0);
+        let gatt = match &mut self.gatt {
+            Some(gatt) => gatt,
+            None => return GattStatus::Error,
+        };
+
+        match gatt.disconnect(client_id, &addr.to_raw_address(), conn_id) {
+            0 => GattStatus::Success,
+            _ => GattStatus::Error,
+        }
+    }
+
+    fn read_remote_rssi(&mut self, client_id: i32, addr: String) {
+        let addr = match BDAddr::from_string(&addr) {
+            Some(addr) => addr,
+            None => return,
+        };
+
+        if self.find_client(client_id).is_none() {
+            return;
+        }
+
+        let state = self.rssi_reads.entry(addr).or_default();
+
+        // A read of this device is already in flight, the client gets the same result.
+        if !state.waiting_clients.is_empty() {
+            if !state.waiting_clients.contains(&client_id) {
+                state.waiting_clients.push(client_id);
+            }
+            return;
+        }
+
+        if let Some(last_read) = state.last_read {
+            if last_read.elapsed() < RSSI_READ_MIN_INTERVAL {
+                let rssi = state.last_rssi;
+                if let Some(callback) = self.client_callback(client_id) {
+                    callback.on_read_remote_rssi(addr.to_string(), rssi, 0);
+                }
+                return;
+            }
+        }
+
+        if self.gatt.is_none() {
+            return;
+        }
+
+        // Reads through GATT would share the one read the native stack has in flight with the
+        // RSSI monitoring, and never be answered if it is busy.
+        self.rssi_reads.entry(addr).or_default().waiting_clients.push(client_id);
+        self.bluetooth.lock().unwrap().read_remote_rssi(addr);
+    }
+
+    fn read_phy(&mut self, client_id: i32, addr: String) -> GattStatus {
+        let addr = match BDAddr::from_string(&addr) {
+            Some(addr) => addr,
+            None => return GattStatus::IllegalParameter,
+        };
+
+        if self.find_client_conn_id(client_id, addr).is_none() {
+            return GattStatus::IllegalParameter;
+        }
+
+        match &mut self.gatt {
+            Some(gatt) if gatt.client_read_phy(client_id, &addr.to_raw_address()) == 0 => {
+                GattStatus::Success
+            }
+            _ => GattStatus::Error,
+        }
+    }
+
+    fn write_characteristic(
+        &mut self,
+        client_id: i32,
+        conn_id: i32,
+        handle: i32,
+        write_type: i32,
+        auth_req: i32,
+        value: Vec<u8>,
+    ) -> GattStatus {
+        if self.find_client(client_id).is_none() {
+            return GattStatus::IllegalParameter;
+        }
+
+        let write_type = match self.reliable_writes.get(&conn_id) {
+            None => write_type,
+            Some(write) if write.client_id == client_id && !write.closing => {
+                GATT_WRITE_TYPE_PREPARE
+            }
+            // The writes of other clients would end up in the transaction.
+            Some(_) => return GattStatus::Busy,
+        };
+
+        // Longer values are split by the stack, except for the write types that have a single PDU
+        // per value, which it would cut instead.
+        let max_len = match write_type {
+            GATT_WRITE_TYPE_NO_RSP => self.get_mtu(conn_id) - ATT_HEADER_LEN,
+            GATT_WRITE_TYPE_PREPARE => self.get_mtu(conn_id) - ATT_PREPARE_WRITE_HEADER_LEN,
+            _ => GATT_MAX_ATTR_LEN as i32,
+        };
+        if value.len() > GATT_MAX_ATTR_LEN || value.len() as i32 > max_len {
+            return GattStatus::InvalidAttrLen;
+        }
+
+        let status = match &mut self.gatt {
+            Some(gatt) => gatt.write_characteristic(conn_id, handle, write_type, auth_req, &value),
+            None => return GattStatus::Error,
+        };
+
+        if status != 0 {
+            return GattStatus::Error;
+        }
+
+        self.client_writes.entry(conn_id).or_default().push_back((client_id, Instant::now()));
+        GattStatus::Success
+    }
+
+    fn discover_services(&mut self, client_id: i32, conn_id: i32) -> GattStatus {
+        if !self.is_client_connection(client_id, conn_id) {
+            return GattStatus::IllegalParameter;
+        }
+
+        match &mut self.gatt {
+            Some(gatt) if gatt.search_service(conn_id) == 0 => GattStatus::Success,
+            _ => GattStatus::Error,
+        }
+    }
+
+    fn read_using_characteristic_uuid(
+        &mut self,
+        client_id: i32,
+        conn_id: i32,
+        uuid: Uuid128,
+        auth_req: i32,
+    ) -> GattStatus {
+        if !self.is_client_connection(client_id, conn_id) {
+            return GattStatus::IllegalParameter;
+        }
+
+        let (start_handle, end_handle) = match self.find_characteristic(conn_id, uuid) {
+            Some(range) => range,
+            None => return GattStatus::NotFound,
+        };
+
+        let status = match &mut self.gatt {
+            Some(gatt) => gatt.read_using_characteristic_uuid(
+                conn_id,
+                &uuid.into(),
+                start_handle,
+                end_handle,
+                auth_req,
+            ),
+            None => return GattStatus::Error,
+        };
+
+        if status != 0 {
+            return GattStatus::Error;
+        }
+
+        self.client_reads.entry(conn_id).or_default().push_back(ClientRead::ByUuid(auth_req));
+        GattStatus::Success
+    }
+
+    fn write_characteristic_by_uuid(
+        &mut self,
+        client_id: i32,
+        conn_id: i32,
+        uuid: Uuid128,
+        write_type: i32,
+        auth_req: i32,
+        value: Vec<u8>,
+    ) -> GattStatus {
+        if !self.is_client_connection(client_id, conn_id) {
+            return GattStatus::IllegalParameter;
+        }
+
+        let handle = match self.find_characteristic(conn_id, uuid) {
+            Some((handle, _)) => handle,
+            None => return GattStatus::NotFound,
+        };
+
+        self.write_characteristic(client_id, conn_id, handle.into(), write_type, auth_req, value)
+    }
+
+    fn begin_reliable_write(&mut self, client_id: i32, conn_id: i32) -> GattStatus {
+        if self.find_client(client_id).is_none() {
+            return GattStatus::IllegalParameter;
+        }
+
+        if self.reliable_writes.contains_key(&conn_id) {
+            return GattStatus::Busy;
+        }
+
+        self.reliable_writes.insert(conn_id, ReliableWrite { client_id, closing: false });
+        GattStatus::Success
+    }
+
+    fn execute_write(&mut self, client_id: i32, conn_id: i32) -> GattStatus {
+        self.close_reliable_write(client_id, conn_id, true)
+    }
+
+    fn abort_reliable_write(&mut self, client_id: i32, conn_id: i32) -> GattStatus {
+        self.close_reliable_write(client_id, conn_id, false)
+    }
+
+    fn configure_mtu(&mut self, client_id: i32, conn_id: i32, mtu: i32) -> GattStatus {
+        let addr = match self.client_connections.get(&conn_id) {
+            Some(connection) if connection.client_id == client_id => connection.addr,
+            _ => return GattStatus::IllegalParameter,
+        };
+
+        if mtu < ATT_DEFAULT_MTU || mtu > ATT_MAX_MTU {
+            return GattStatus::IllegalParameter;
+        }
+
+        let status = match &mut self.gatt {
+            Some(gatt) => gatt.configure_mtu(conn_id, mtu),
+            None => return GattStatus::Error,
+        };
+
+        if status != 0 {
+            return GattStatus::Error;
+        }
+
+        self.preferred_mtus.insert((client_id, addr), mtu);
+        GattStatus::Success
+    }
+
+    fn request_connection_priority(
+        &mut self,
+        client_id: i32,
+        addr: String,
+        priority: ConnectionPriority,
+    ) -> GattStatus {
+        let addr = match BDAddr::from_string(&addr) {
+            Some(addr) => addr,
+            None => return GattStatus::IllegalParameter,
+        };
+
+        if self.find_client_conn_id(client_id, addr).is_none() {
+            return GattStatus::IllegalParameter;
+        }
+
+        match self.update_connection_priority(client_id, addr, Some(priority)) {
+            BtStatus::Success => GattStatus::Success,
+            _ => GattStatus::Error,
+        }
+    }
+
+    fn register_for_notification(
+        &mut self,
+        client_id: i32,
+        conn_id: i32,
+        handle: i32,
+        enable: bool,
+    ) -> GattStatus {
+        let addr = match self.client_connections.get(&conn_id) {
+            Some(connection) if connection.client_id == client_id => connection.addr,
+            _ => return GattStatus::IllegalParameter,
+        };
+
+        let (cccd_handle, cccd_value) = match self.find_cccd(conn_id, handle) {
+            Some(cccd) => cccd,
+            None => return GattStatus::NotFound,
+        };
+
+        let clients = self.subscriptions.get(&(addr, handle));
+        let subscribed = clients.map_or(false, |c| c.contains(&client_id));
+        let others_subscribed = clients.map_or(false, |c| c.iter().any(|id| *id != client_id));
+
+        let gatt = match &mut self.gatt {
+            Some(gatt) => gatt,
+            None => return GattStatus::Error,
+        };
+
+        // The native stack routes the notifications to the clients registered for them.
+        let raw_addr = addr.to_raw_address();
+        if enable && !subscribed {
+            gatt.register_for_notification(client_id, &raw_addr, handle as u16);
+        }
+
+        // The descriptor is only written for the first and last subscribers.
+        if subscribed != enable && !others_subscribed {
+            let value = if enable { cccd_value } else { CCCD_DISABLE };
+            if gatt.write_descriptor(conn_id, cccd_handle, 0, &value) != 0 {
+                if enable {
+                    gatt.deregister_for_notification(client_id, &raw_addr, handle as u16);
+                }
+                return GattStatus::Error;
+            }
+            self.cccd_writes.entry(conn_id).or_default().push_back(CccdWrite { handle, enable });
+        } else if let Some(callback) = self.client_callback(client_id) {
+            callback.on_notification_registered(
+                conn_id,
+                GattStatus::Success as i32,
+                handle,
+                enable,
+            );
+        }
+
+        if enable {
+            self.subscriptions.entry((addr, handle)).or_default().insert(client_id);
+        } else if subscribed {
+            self.drop_subscription(client_id, addr, handle);
+            if let Some(gatt) = &mut self.gatt {
+                gatt.deregister_for_notification(client_id, &raw_addr, handle as u16);
+            }
+        }
+
+        GattStatus::Success
+    }
+
+    fn get_mtu(&self, conn_id: i32) -> i32 {
+        if let Some(mtu) = self.mtus.get(&conn_id) {
+            return *mtu;
+        }
+
+        if self.client_connections.contains_key(&conn_id)
+            || self.notification_queues.contains_key(&conn_id)
+        {
+            ATT_DEFAULT_MTU
+        } else {
+            0
+        }
+    }
+
+    fn get_database_hash(&self, conn_id: i32) -> Vec<u8> {
+        self.database_hashes.get(&conn_id).cloned().unwrap_or_default()
+    }
+
+    fn register_server(
+        &mut self,
+        app_uuid: Uuid128,
+        callback: Box<dyn IBluetoothGattServerCallback + Send>,
+        eatt_support: bool,
+    ) {
+        let gatt = match &mut self.gatt {
+            Some(gatt) => gatt,
+            None => return,
+        };
+
+        let callback_id = self.server_callbacks.add_callback(callback);
+        self.servers.push(GattServer { id: None, uuid: app_uuid, callback_id });
+        gatt.register_server(&app_uuid.into(), eatt_support);
+    }
+
+    fn unregister_server(&mut self, server_id: i32) {
+        if let Some(server) = self.find_server(server_id) {
+            let callback_id = server.callback_id;
+            self.server_callbacks.remove_callback(callback_id);
+        }
+        self.servers.retain(|server| server.id != Some(server_id));
+        self.notification_queues.retain(|_, queue| queue.server_id != server_id);
+        let notification_queues = &self.notification_queues;
+        self.prepared_writes.retain(|conn_id, _| notification_queues.contains_key(conn_id));
+
+        // The native stack removes the services of the server along with it.
+        self.pending_services.remove(&server_id);
+        let handles: Vec<i32> = self
+            .local_services
+            .iter()
+            .filter(|(_, service)| service.server_id == server_id)
+            .map(|(handle, _)| *handle)
+            .collect();
+        for handle in handles {
+            self.forget_local_service(handle);
+        }
+
+        if let Some(gatt) = &mut self.gatt {
+            gatt.unregister_server(server_id);
+        }
+    }
+
+    fn send_notification(
+        &mut self,
+        server_id: i32,
+        device: String,
+        handle: i32,
+        value: Vec<u8>,
+        confirm: bool,
+    ) -> GattStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return GattStatus::IllegalParameter,
+        };
+
+        if !self.owns_attribute(server_id, handle) {
+            return GattStatus::IllegalParameter;
+        }
+
+        let conn_id = match self.find_server_conn_id(server_id, addr) {
+            Some(conn_id) => conn_id,
+            None => return GattStatus::IllegalParameter,
+        };
+
+        let queue = self.notification_queues.get_mut(&conn_id).unwrap();
+
+        // Only the latest value of an attribute matters to a notification subscriber.
+        // Indications are kept since each of them is confirmed to the caller.
+        if !confirm {
+            if let Some(pending) =
+                queue.pending.iter_mut().find(|n| !n.confirm && n.handle == handle)
+            {
+                pending.value = value;
+                return GattStatus::Success;
+            }
+        }
+
+        if queue.pending.len() >= NOTIFICATION_QUEUE_MAX_LEN {
+            queue.refused = true;
+            return GattStatus::Congested;
+        }
+
+        queue.pending.push_back(PendingNotification { handle, value, confirm });
+        self.pump_notifications(conn_id);
+        GattStatus::Success
+    }
+
+    fn send_response(
+        &mut self,
+        server_id: i32,
+        device: String,
+        trans_id: i32,
+        status: i32,
+        handle: i32,
+        offset: i32,
+        value: Vec<u8>,
+    ) -> GattStatus {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return GattStatus::IllegalParameter,
+        };
+
+        let conn_id = match self.find_server_conn_id(server_id, addr) {
+            Some(conn_id) => conn_id,
+            None => return GattStatus::IllegalParameter,
+        };
+
+        match &mut self.gatt {
+            Some(gatt)
+                if gatt.send_response(conn_id, trans_id, status, handle, offset, &value) == 0 =>
+            {
+                GattStatus::Success
+            }
+            _ => GattStatus::Error,
+        }
+    }
+
+    fn server_read_phy(&mut self, server_id: i32, addr: String) -> GattStatus {
+        let addr = match BDAddr::from_string(&addr) {
+            Some(addr) => addr,
+            None => return GattStatus::IllegalParameter,
+        };
+
+        if self.find_server_conn_id(server_id, addr).is_none() {
+            return GattStatus::IllegalParameter;
+        }
+
+        match &mut self.gatt {
+            Some(gatt) if gatt.server_read_phy(server_id, &addr.to_raw_address()) == 0 => {
+                GattStatus::Success
+            }
+            _ => GattStatus::Error,
+        }
+    }
+
+    fn add_service(&mut self, server_id: i32, service: GattService) -> GattStatus {
+        if self.find_server(server_id).is_none() || !service.is_valid() {
+            return GattStatus::IllegalParameter;
+        }
+
+        // Devices find services by UUID, they would mix up the services of the two servers.
+        if service.primary && self.is_primary_service_of_other_server(server_id, &service.uuid) {
+            return GattStatus::DupReg;
+        }
+
+        self.add_local_service(server_id, service)
+    }
+
+    fn remove_service(&mut self, server_id: i32, handle: i32) -> GattStatus {
+        match self.local_services.get(&handle) {
+            Some(service) if service.server_id == server_id => {}
+            _ => return GattStatus::IllegalParameter,
+        }
+
+        let status = match &mut self.gatt {
+            Some(gatt) => gatt.delete_service(server_id, handle),
+            None => return GattStatus::Error,
+        };
+
+        if status != 0 {
+            return GattStatus::Error;
+        }
+
+        self.forget_local_service(handle);
+        GattStatus::Success
+    }
+
+    fn start_advertising_set(
+        &mut self,
+        parameters: AdvertisingSetParameters,
+        advertise_data: Vec<u8>,
+        scan_response: Vec<u8>,
+        duration: i32,
+        max_ext_adv_events: i32,
+        callback: Box<dyn IAdvertisingSetCallback + Send>,
+    ) -> bool {
+        let (duration, max_ext_adv_events) =
+            match (u16::try_from(duration), u8::try_from(max_ext_adv_events)) {
+                (Ok(duration), Ok(max_ext_adv_events)) => (duration, max_ext_adv_events),
+                _ => return false,
+            };
+
+        if !parameters.is_valid() {
+            return false;
+        }
+
+        let status = self.check_new_advertising_set(&parameters, &advertise_data, &scan_response);
+        if status != AdvertisingStatus::Success {
+            callback.on_advertising_set_started(-1, 0, status as i32);
+            return true;
+        }
+
+        self.advertiser_registrations = match self.advertiser_registrations.checked_add(1) {
+            Some(reg_id) => reg_id,
+            None => 1,
+        };
+        let reg_id = self.advertiser_registrations;
+
+        let native_parameters = parameters.to_advertise_parameters();
+        let callback_id = self.advertiser_callbacks.add_callback(callback);
+        self.advertising_sets.push(AdvertisingSet {
+            reg_id,
+            id: None,
+            callback_id,
+            parameters,
+            enabled: false,
+            duration,
+            max_ext_adv_events,
+            pending_parameters: None,
+            updating: false,
+        });
+
+        if let Some(gatt) = &mut self.gatt {
+            gatt.start_advertising_set(
+                reg_id,
+                &native_parameters,
+                &advertise_data,
+                &scan_response,
+                duration,
+                max_ext_adv_events,
+            );
+        }
+
+        true
+    }
+
+    fn stop_advertising_set(&mut self, advertiser_id: i32) -> bool {
+        let index = match self.advertising_sets.iter().position(|set| set.id == Some(advertiser_id))
+        {
+            Some(index) => index,
+            None => return false,
+        };
+
+        if let Some(gatt) = &mut self.gatt {
+            gatt.unregister_advertiser(advertiser_id);
+        }
+
+        let set = self.advertising_sets.remove(index);
+        if let Some(callback) = self.advertiser_callbacks.get_by_id(set.callback_id) {
+            callback.on_advertising_set_stopped(advertiser_id);
+        }
+        self.advertiser_callbacks.remove_callback(set.callback_id);
+
+        true
+    }
+
+    fn enable_advertising_set(
+        &mut self,
+        advertiser_id: i32,
+        enable: bool,
+        duration: i32,
+        max_ext_adv_events: i32,
+    ) -> bool {
+        let (duration, max_ext_adv_events) =
+            match (u16::try_from(duration), u8::try_from(max_ext_adv_events)) {
+                (Ok(duration), Ok(max_ext_adv_events)) => (duration, max_ext_adv_events),
+                _ => return false,
+            };
+
+        let set = match self.find_advertising_set_mut(advertiser_id) {
+            Some(set) if !set.updating => set,
+            _ => return false,
+        };
+
+        if enable {
+            set.duration = duration;
+            set.max_ext_adv_events = max_ext_adv_events;
+        }
+
+        match &mut self.gatt {
+            Some(gatt) => {
+                gatt.enable_advertising(advertiser_id, enable, duration, max_ext_adv_events);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set_advertising_data(&mut self, advertiser_id: i32, data: Vec<u8>) -> bool {
+        self.set_advertising_set_data(advertiser_id, false, data)
+    }
+
+    fn set_scan_response_data(&mut self, advertiser_id: i32, data: Vec<u8>) -> bool {
+        self.set_advertising_set_data(advertiser_id, true, data)
+    }
+
+    fn set_advertising_parameters(
+        &mut self,
+        advertiser_id: i32,
+        parameters: AdvertisingSetParameters,
+    ) -> bool {
+        if !parameters.is_valid() {
+            return false;
+        }
+
+        if !parameters.is_legacy && !Controller::new().get_info().le_extended_advertising {
+            return false;
+        }
+
+        let gatt = match &mut self.gatt {
+            Some(gatt) => gatt,
+            None => return false,
+        };
+
+        let set = match self.advertising_sets.iter_mut().find(|s| s.id == Some(advertiser_id)) {
+            Some(set) if !set.updating && set.pending_parameters.is_none() => set,
+            _ => return false,
+        };
+
+        if parameters.own_address_type != set.parameters.own_address_type {
+            return false;
+        }
+
+        // The controller only accepts new parameters for a disabled set.
+        if set.enabled {
+            set.updating = true;
+            gatt.enable_advertising(advertiser_id, false, 0, 0);
+        } else {
+            gatt.set_advertising_parameters(advertiser_id, &parameters.to_advertise_parameters());
+        }
+        set.pending_parameters = Some(parameters);
+
+        true
+    }
+
+    fn get_own_address(&mut self, advertiser_id: i32) -> bool {
+        if self.find_advertising_set_mut(advertiser_id).is_none() {
+            return false;
+        }
+
+        match &mut self.gatt {
+            Some(gatt) => {
+                gatt.get_own_address(advertiser_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn register_advertisement_monitor(
+        &mut self,
+        monitor: AdvertisementMonitor,
+        callback: Box<dyn IAdvertisementMonitorCallback + Send>,
+    ) -> i32 {
+        if !monitor.is_valid() || self.gatt.is_none() {
+            return 0;
+        }
+
+        let scanning = self.is_scanning();
+        let conditions = monitor.to_apcf_conditions();
+        let filter_index = conditions.as_ref().and_then(|_| self.free_scan_filter_index());
+
+        self.monitor_registrations += 1;
+        let id = self.monitor_registrations;
+        let callback_id = self.monitor_callbacks.add_callback(callback);
+
+        // Monitors scan in the background.
+        if !scanning {
+            self.update_scan_parameters(false);
+        }
+
+        let gatt = self.gatt.as_mut().unwrap();
+        if let (Some(index), Some(conditions)) = (filter_index, conditions) {
+            gatt.add_scan_filter(
+                index,
+                monitor.rssi_high_threshold as i8,
+                monitor.rssi_low_threshold as i8,
+                &conditions,
+            );
+        }
+        if !scanning {
+            gatt.scan(true);
+        }
+
+        self.monitors.push(Monitor {
+            id,
+            callback_id,
+            monitor,
+            filter_index,
+            found: HashSet::new(),
+        });
+        self.update_scan_filter();
+
+        id
+    }
+
+    fn unregister_advertisement_monitor(&mut self, monitor_id: i32) -> bool {
+        let index = match self.monitors.iter().position(|monitor| monitor.id == monitor_id) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let monitor = self.monitors.remove(index);
+        self.monitor_callbacks.remove_callback(monitor.callback_id);
+
+        // The filter feature is disabled before the filter goes away, so that the advertisements
+        // of the other monitors are not hidden in between.
+        self.update_scan_filter();
+        let scanning = self.is_scanning();
+        if let Some(gatt) = &mut self.gatt {
+            if let Some(filter_index) = monitor.filter_index {
+                gatt.clear_scan_filter(filter_index);
+            }
+            if !scanning {
+                gatt.scan(false);
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service_data(adv_data: &[u8]) -> Vec<(Uuid128, Vec<u8>)> {
+        ad_service_data(adv_data).map(|(uuid, data)| (uuid, data.to_vec())).collect()
+    }
+
+    #[test]
+    fn service_data_16() {
+        // Flags, then the service data of the Eddystone UUID 0xFEAA.
+        let adv_data = [0x02, 0x01, 0x06, 0x05, AD_TYPE_SERVICE_DATA_16, 0xAA, 0xFE, 0x10, 0x20];
+        assert_eq!(service_data(&adv_data), vec![(Uuid128::from_u16(0xFEAA), vec![0x10, 0x20])]);
+    }
+
+    #[test]
+    fn service_data_32() {
+        let adv_data = [0x06, AD_TYPE_SERVICE_DATA_32, 0x78, 0x56, 0x34, 0x12, 0x01];
+        assert_eq!(service_data(&adv_data), vec![(Uuid128::from_u32(0x12345678), vec![0x01])]);
+    }
+
+    #[test]
+    fn service_data_128() {
+        let uuid = Uuid128::from_string("12345678-9abc-def0-1122-334455667788").unwrap();
+        let adv_data = [
+            &[0x13, AD_TYPE_SERVICE_DATA_128][..],
+            &[0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11],
+            &[0xF0, 0xDE, 0xBC, 0x9A, 0x78, 0x56, 0x34, 0x12],
+            &[0x01, 0x02],
+        ]
+        .concat();
+
+        assert_eq!(service_data(&adv_data), vec![(uuid, vec![0x01, 0x02])]);
+    }
+
+    #[test]
+    fn service_data_too_short() {
+        // The UUIDs are cut, and the last structure overruns the data.
+        let adv_data = [
+            &[0x02, AD_TYPE_SERVICE_DATA_16, 0xAA][..],
+            &[0x03, AD_TYPE_SERVICE_DATA_32, 0x78, 0x56],
+            &[0x05, AD_TYPE_SERVICE_DATA_16, 0xAA, 0xFE],
+        ]
+        .concat();
 
-    fn start_scan(&self, _scanner_id: i32, _settings: ScanSettings, _filters: Vec<ScanFilter>) {
-        // TODO: implement
+        assert_eq!(service_data(&adv_data), vec![]);
     }
 
-    fn stop_scan(&self, _scanner_id: i32) {
-        // TODO: implement
+    #[test]
+    fn service_data_ends_at_empty_structure() {
+        let adv_data = [0x03, AD_TYPE_SERVICE_DATA_16, 0xAA, 0xFE, 0x00, 0x03, 0x16, 0x6F, 0xFD];
+        assert_eq!(service_data(&adv_data), vec![(Uuid128::from_u16(0xFEAA), vec![])]);
     }
 }
@@ -0,0 +1,77 @@
+//! Anything related to the logging API (IBluetoothLogging).
+//!
+//! The HCI snoop log records the traffic between the stack and the controller in the btsnoop
+//! format, which Wireshark reads, so that developers and bug reports can include HCI traces
+//! without rebuilding the daemon.
+
+use bt_topshim::snoop::{SnoopLog, SnoopLogMode};
+
+/// Where the snoop logs are written.
+pub const SNOOP_LOG_DIR: &str = "/var/log/bluetooth";
+
+/// Defines the logging API.
+pub trait IBluetoothLogging {
+    /// Starts logging the HCI traffic to `file_name` in `SNOOP_LOG_DIR`, replacing the log in
+    /// progress if there is one.
+    ///
+    /// Once the log holds `max_packets` packets, it is moved to `<file_name>.last`, replacing the
+    /// previous one, and a new log is started. In filtered mode, the audio data and the payloads
+    /// of the ACL packets are left out, except those of the signaling channels, and the log is
+    /// written to `<file_name>.filtered`. Logging goes on across adapter restarts. Returns false
+    /// if `file_name` is not a plain file name or `max_packets` is 0, and if any GD stack feature
+    /// is enabled, as the GD snoop logger is only configured at startup.
+    fn start_snoop_log(&mut self, file_name: String, max_packets: u32, filtered: bool) -> bool;
+
+    /// Stops logging the HCI traffic. The logs are kept.
+    fn stop_snoop_log(&mut self);
+
+    /// Returns whether the HCI traffic is being logged.
+    fn is_snoop_log_enabled(&self) -> bool;
+}
+
+/// Implementation of the logging API.
+pub struct BluetoothLogging {
+    snoop_log: SnoopLog,
+    /// The path and size of the log in progress, if any.
+    snoop_log_config: Option<(String, i32)>,
+}
+
+impl BluetoothLogging {
+    pub fn new() -> BluetoothLogging {
+        BluetoothLogging { snoop_log: SnoopLog::new(), snoop_log_config: None }
+    }
+}
+
+impl IBluetoothLogging for BluetoothLogging {
+    fn start_snoop_log(&mut self, file_name: String, max_packets: u32, filtered: bool) -> bool {
+        // Clients only pick the name, the daemon may write anywhere.
+        if file_name.is_empty() || file_name.contains('/') || file_name == "." || file_name == ".."
+        {
+            return false;
+        }
+
+        if max_packets == 0 || max_packets > i32::MAX as u32 {
+            return false;
+        }
+
+        let path = format!("{}/{}", SNOOP_LOG_DIR, file_name);
+        let mode = if filtered { SnoopLogMode::Filtered } else { SnoopLogMode::Full };
+        if !self.snoop_log.configure(mode, &path, max_packets as i32) {
+            return false;
+        }
+
+        self.snoop_log_config = Some((path, max_packets as i32));
+        true
+    }
+
+    fn stop_snoop_log(&mut self) {
+        // Only the mode changes, the logs are left where they were written.
+        if let Some((path, max_packets)) = self.snoop_log_config.take() {
+            self.snoop_log.configure(SnoopLogMode::Disabled, &path, max_packets);
+        }
+    }
+
+    fn is_snoop_log_enabled(&self) -> bool {
+        self.snoop_log_config.is_some()
+    }
+}
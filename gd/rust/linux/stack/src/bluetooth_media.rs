@@ -0,0 +1,1344 @@
+//! Anything related to audio and media API.
+//!
+//! A2DP is implemented here, the LE audio path is developed in `le_audio`, built with the
+//! `le_audio` feature and served with `Feature::LeAudio` on.
+
+use bt_topshim::btif::ffi::RustRawAddress;
+use bt_topshim::btif::{BluetoothInterface, SupportedProfiles};
+use bt_topshim::profiles::a2dp::ffi::A2dpCodecConfig;
+use bt_topshim::profiles::a2dp::{A2dp, A2dpCallbacks, BtavAudioState, BtavConnectionState};
+use bt_topshim::profiles::ProfileShim;
+use bt_topshim::topstack;
+
+use btif_macros::btif_callbacks_generator;
+use btif_macros::stack_message;
+
+use num_traits::cast::{FromPrimitive, ToPrimitive};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::Receiver;
+
+use crate::device_queue::DeviceQueues;
+use crate::message_trace::MessageRecorder;
+use crate::metrics::{self, Metrics};
+use crate::watchdog::{Handler, Watchdog};
+use crate::{BDAddr, Callbacks, Message, MessageSender, RPCProxy};
+
+#[cfg(feature = "le_audio")]
+pub mod le_audio;
+
+/// How long the device is given to start or stop streaming before a request is sent again.
+const STREAM_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How many times a request to start or stop streaming is sent before it fails.
+const STREAM_REQUEST_ATTEMPTS: u32 = 3;
+
+/// How long the ACL disconnection is awaited after an A2DP disconnection for its reason, before
+/// the device is deemed to have only closed A2DP.
+const DISCONNECT_REASON_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Bounds of the policy set with `IBluetoothMedia::set_reconnect_policy`.
+const RECONNECT_ATTEMPTS_MAX: u32 = 10;
+const RECONNECT_DELAY_MAX_MS: u32 = 60000;
+
+/// HCI disconnect reasons, see `tHCI_REASON`.
+const HCI_ERR_CONNECTION_TOUT: i32 = 0x08;
+const HCI_ERR_PEER_USER: i32 = 0x13;
+const HCI_ERR_REMOTE_LOW_RESOURCE: i32 = 0x14;
+const HCI_ERR_REMOTE_POWER_OFF: i32 = 0x15;
+const HCI_ERR_CONN_CAUSE_LOCAL_HOST: i32 = 0x16;
+const HCI_ERR_LMP_RESPONSE_TIMEOUT: i32 = 0x22;
+
+/// Defines the media API (A2DP source for now).
+pub trait IBluetoothMedia {
+    /// Adds a callback from a client who wishes to observe media events. Returns the id of the
+    /// callback, which identifies the client in `open_audio_session`.
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothMediaCallback + Send>) -> u32;
+
+    /// Initializes the A2DP profile. Must be called before any other media API.
+    fn initialize(&mut self) -> bool;
+
+    /// Connects A2DP to the given device.
+    fn connect(&mut self, device: String);
+
+    /// Disconnects A2DP from the given device.
+    fn disconnect(&mut self, device: String);
+
+    /// Returns the presentation delay of the device in units of 1/10 ms.
+    ///
+    /// This is the delay reported by the sink through AVDTP delay reporting, or the one set by
+    /// `set_presentation_delay`. Returns 0 if no delay is known for the device.
+    fn get_presentation_delay(&self, device: String) -> u32;
+
+    /// Overrides the presentation delay of a connected device in units of 1/10 ms.
+    ///
+    /// Returns true if the request is accepted.
+    fn set_presentation_delay(&mut self, device: String, delay: u32) -> bool;
+
+    /// Returns the A2DP audio state of the device, as a `BtavAudioState` value.
+    ///
+    /// Devices that have not streamed since they connected are reported as stopped.
+    fn get_audio_state(&self, device: String) -> u32;
+
+    /// Returns the address of the device that audio is streamed to, or an empty string if no
+    /// device is active.
+    fn get_active_device(&self) -> String;
+
+    /// Selects the connected device that audio is streamed to, or deselects the active device if
+    /// `device` is empty.
+    ///
+    /// The stream to the previously active device is suspended first. The change is reported
+    /// through `IBluetoothMediaCallback::on_active_device_changed`. Returns false if the device is
+    /// not connected or the request is refused.
+    fn set_active_device(&mut self, device: String) -> bool;
+
+    /// Returns the addresses of the devices that A2DP is connected to.
+    fn get_connected_audio_devices(&self) -> Vec<String>;
+
+    /// Returns the `A2dpSessionType` values that an audio session can be opened with.
+    fn get_supported_session_types(&self) -> Vec<u32>;
+
+    /// Opens the audio session of the audio server, with an `A2dpSessionType` value, on behalf
+    /// of the client that registered the callback `callback_id`. The session is closed if the
+    /// client goes away.
+    ///
+    /// Returns false if the callback is not registered, the session type is not supported or a
+    /// session is already open.
+    fn open_audio_session(&mut self, callback_id: u32, session_type: u32) -> bool;
+
+    /// Closes the audio session, stopping its stream.
+    fn close_audio_session(&mut self);
+
+    /// Returns the `A2dpSessionState` of the audio session.
+    fn get_audio_session_state(&self) -> u32;
+
+    /// Returns the codecs that can be selected with a connected device, i.e. the ones supported
+    /// both locally and by the device. Returns an empty list until the device is configured.
+    fn get_supported_codecs(&self, device: String) -> Vec<A2dpCodecConfig>;
+
+    /// Requests the stream of the audio session to the active device to start.
+    ///
+    /// The session is `Starting` until the device streams. Without an active device, the stream
+    /// goes to the device connecting after the last `connect`, once it is connected. The request
+    /// is sent again if the device doesn't stream in time, and its outcome is reported with
+    /// `on_stream_request_done`. Returns false if no session is open, the session is already
+    /// streaming or there is no device to stream to.
+    fn start_audio_request(&mut self) -> bool;
+
+    /// Requests the stream of the audio session to stop. The session goes back to `Idle`.
+    ///
+    /// The outcome is reported with `on_stream_request_done`, once the device stops streaming.
+    fn stop_audio_request(&mut self) -> bool;
+
+    /// Requests the stream of the audio session to be suspended.
+    fn suspend_audio_request(&mut self) -> bool;
+
+    /// Sets how the devices that disconnect because of a link loss are reconnected: up to
+    /// `max_attempts` times, the first attempt `delay_ms` after the disconnection and each next
+    /// one after twice the delay of the previous one.
+    ///
+    /// Reconnection is off with 0 attempts, the default. A `connect` or `disconnect` of a device
+    /// stops reconnecting it. Returns false if there are more than 10 attempts or the delay is
+    /// not between 1 ms and 60 s.
+    fn set_reconnect_policy(&mut self, max_attempts: u32, delay_ms: u32) -> bool;
+}
+
+/// The interface for media callbacks registered through `IBluetoothMedia::register_callback`.
+pub trait IBluetoothMediaCallback: RPCProxy {
+    /// When an A2DP device is connected.
+    fn on_bluetooth_audio_device_added(&self, addr: String);
+
+    /// When an A2DP device is disconnected.
+    fn on_bluetooth_audio_device_removed(&self, addr: String);
+
+    /// When the presentation delay of a device is changed, in units of 1/10 ms.
+    fn on_audio_delay_changed(&self, addr: String, delay: u32);
+
+    /// When the device that audio is streamed to is changed, with an empty address if there is
+    /// no active device anymore.
+    ///
+    /// Audio servers should reroute their output to the new device.
+    fn on_active_device_changed(&self, addr: String);
+
+    /// When the `A2dpSessionState` of the audio session is changed, either by a request of the
+    /// audio server or by the device, e.g. when it suspends the stream.
+    fn on_audio_session_state_changed(&self, state: u32);
+
+    /// When the codec configuration of a device is changed, with the codec it streams with, the
+    /// codecs supported locally and the ones that can be selected with the device.
+    ///
+    /// Settings UIs can render the codec choices from it instead of polling
+    /// `IBluetoothMedia::get_supported_codecs`.
+    fn on_audio_config_changed(
+        &self,
+        addr: String,
+        current: A2dpCodecConfig,
+        local_capabilities: Vec<A2dpCodecConfig>,
+        selectable_capabilities: Vec<A2dpCodecConfig>,
+    );
+
+    /// When a request of the audio server to start or stop the stream to a device is done, with
+    /// an `A2dpStreamRequest` and whether the device did start or stop streaming.
+    ///
+    /// A start fails if the device doesn't stream after a few attempts, disconnects or the
+    /// audio server stops the stream before it started.
+    fn on_stream_request_done(&self, addr: String, request: u32, success: bool);
+
+    /// When the reason a connected A2DP device disconnected is known, as a
+    /// `MediaDisconnectReason` value. Follows `on_bluetooth_audio_device_removed`.
+    fn on_bluetooth_audio_device_disconnected(&self, addr: String, reason: u32);
+
+    /// When an attempt to reconnect a device that was lost starts, counting from 1. See
+    /// `IBluetoothMedia::set_reconnect_policy`.
+    fn on_audio_reconnect_attempt(&self, addr: String, attempt: u32);
+
+    /// When the attempts to reconnect a device that was lost are over without it connecting.
+    fn on_audio_reconnect_failed(&self, addr: String);
+}
+
+/// How the audio of an audio session is encoded.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(u32)]
+pub enum A2dpSessionType {
+    /// The audio server encodes the audio.
+    Software = 0,
+    /// The controller encodes the audio, which the audio server sends unencoded.
+    Offload = 1,
+}
+
+/// Lifecycle of the audio session of the audio server.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(u32)]
+pub enum A2dpSessionState {
+    Closed = 0,
+    /// Open and not streaming.
+    Idle,
+    /// Waiting for the active device to stream after `start_audio_request`.
+    Starting,
+    Started,
+    /// Suspended by the audio server or by the device.
+    Suspended,
+}
+
+/// A request of the audio server to the stream of a device.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(u32)]
+pub enum A2dpStreamRequest {
+    Start = 0,
+    Stop,
+}
+
+/// Why a connected A2DP device disconnected, as reported by
+/// `IBluetoothMediaCallback::on_bluetooth_audio_device_disconnected`.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(u32)]
+pub enum MediaDisconnectReason {
+    /// Requested with `IBluetoothMedia::disconnect`, or by the local host.
+    Local = 0,
+    /// The device closed the connection, e.g. because it was turned off.
+    Remote,
+    /// The link was lost, e.g. because the device went out of range.
+    LinkLoss,
+    /// Any other reason, e.g. a failure of the controller.
+    Other,
+}
+
+impl MediaDisconnectReason {
+    fn from_hci_reason(hci_reason: i32) -> MediaDisconnectReason {
+        match hci_reason {
+            HCI_ERR_CONN_CAUSE_LOCAL_HOST => MediaDisconnectReason::Local,
+            HCI_ERR_PEER_USER | HCI_ERR_REMOTE_LOW_RESOURCE | HCI_ERR_REMOTE_POWER_OFF => {
+                MediaDisconnectReason::Remote
+            }
+            HCI_ERR_CONNECTION_TOUT | HCI_ERR_LMP_RESPONSE_TIMEOUT => {
+                MediaDisconnectReason::LinkLoss
+            }
+            _ => MediaDisconnectReason::Other,
+        }
+    }
+}
+
+/// A request to the stream of a device, until the device starts or stops streaming.
+struct PendingStreamRequest {
+    request: A2dpStreamRequest,
+    /// Identifies the request in `Message::A2dpStreamRequestTimeout`.
+    id: u32,
+    /// Times the request was sent to the device, 0 while waiting for the device to connect.
+    attempts: u32,
+}
+
+/// What to do about a request whose `Message::A2dpStreamRequestTimeout` fired.
+#[derive(Debug, PartialEq)]
+enum StreamRequestTimeout {
+    /// The request is done or was replaced meanwhile.
+    Stale,
+    /// The device didn't follow the request yet, it is sent again.
+    Retry,
+    /// The device didn't follow the request after `STREAM_REQUEST_ATTEMPTS`.
+    Failed,
+}
+
+/// The requests to the streams of the devices, one per device.
+#[derive(Default)]
+struct StreamRequests {
+    pending: HashMap<BDAddr, PendingStreamRequest>,
+    next_id: u32,
+}
+
+impl StreamRequests {
+    /// Tracks a request to the stream of a device, replacing its previous request.
+    fn add(&mut self, addr: BDAddr, request: A2dpStreamRequest) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.pending.insert(addr, PendingStreamRequest { request, id, attempts: 0 });
+    }
+
+    fn contains(&self, addr: &BDAddr) -> bool {
+        self.pending.contains_key(addr)
+    }
+
+    /// Counts a sending of the request of a device, returned with the id of its timeout.
+    fn attempt(&mut self, addr: &BDAddr) -> Option<(A2dpStreamRequest, u32)> {
+        let pending = self.pending.get_mut(addr)?;
+        pending.attempts += 1;
+        Some((pending.request, pending.id))
+    }
+
+    fn timed_out(&self, addr: &BDAddr, id: u32) -> StreamRequestTimeout {
+        match self.pending.get(addr) {
+            Some(pending) if pending.id == id => {
+                if pending.attempts < STREAM_REQUEST_ATTEMPTS {
+                    StreamRequestTimeout::Retry
+                } else {
+                    StreamRequestTimeout::Failed
+                }
+            }
+            _ => StreamRequestTimeout::Stale,
+        }
+    }
+
+    /// Drops the request of a device, e.g. because it failed, and returns it.
+    fn remove(&mut self, addr: &BDAddr) -> Option<A2dpStreamRequest> {
+        self.pending.remove(addr).map(|pending| pending.request)
+    }
+
+    /// Drops the request of a device if it is `request`, which the device followed. Returns
+    /// whether it was.
+    fn complete(&mut self, addr: &BDAddr, request: A2dpStreamRequest) -> bool {
+        match self.pending.get(addr) {
+            Some(pending) if pending.request == request => {
+                self.pending.remove(addr);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns a device whose request was never sent, as it is waiting for the device to connect.
+    fn waiting_device(&self) -> Option<BDAddr> {
+        self.pending.iter().find(|(_, pending)| pending.attempts == 0).map(|(addr, _)| *addr)
+    }
+}
+
+/// Reconnection of a device that was lost, until it connects or the attempts are over.
+struct PendingReconnect {
+    /// Identifies the reconnection in `Message::A2dpReconnect`.
+    id: u32,
+    /// Attempts made so far.
+    attempts: u32,
+}
+
+/// Implementation of the media API.
+pub struct BluetoothMedia {
+    intf: Arc<Mutex<BluetoothInterface>>,
+    initialized: bool,
+    callbacks: Callbacks<dyn IBluetoothMediaCallback + Send>,
+    tx: MessageSender,
+    metrics: Arc<Mutex<Metrics>>,
+    a2dp: Option<A2dp>,
+    a2dp_states: HashMap<BDAddr, BtavConnectionState>,
+    /// Times of the `connect` calls of the devices that are not connected yet.
+    connect_requests: HashMap<BDAddr, Instant>,
+    audio_states: HashMap<BDAddr, BtavAudioState>,
+    delays: HashMap<BDAddr, u16>,
+    active_device: Option<BDAddr>,
+    /// Type of the open audio session, if any.
+    session_type: Option<A2dpSessionType>,
+    /// Callback id of the client that opened the audio session.
+    session_owner: Option<u32>,
+    session_state: A2dpSessionState,
+    /// Codecs selectable with each configured device.
+    selectable_codecs: HashMap<BDAddr, Vec<A2dpCodecConfig>>,
+    stream_requests: StreamRequests,
+    /// Connected devices being disconnected with `disconnect`.
+    local_disconnects: HashSet<BDAddr>,
+    /// HCI reasons of the ACL disconnections of the connected devices, until A2DP disconnects.
+    acl_disconnect_reasons: HashMap<BDAddr, i32>,
+    /// Devices that disconnected for a reason not known yet, with the id of their
+    /// `Message::A2dpDisconnectReasonTimeout`.
+    unexplained_disconnects: HashMap<BDAddr, u32>,
+    reconnect_attempts: u32,
+    reconnect_delay: Duration,
+    reconnects: HashMap<BDAddr, PendingReconnect>,
+    next_disconnect_id: u32,
+}
+
+impl BluetoothMedia {
+    /// Constructs the IBluetoothMedia implementation.
+    pub fn new(
+        tx: MessageSender,
+        intf: Arc<Mutex<BluetoothInterface>>,
+        metrics: Arc<Mutex<Metrics>>,
+    ) -> BluetoothMedia {
+        BluetoothMedia {
+            intf,
+            initialized: false,
+            callbacks: Callbacks::new(tx.clone(), Message::MediaCallbackDisconnected),
+            tx,
+            metrics,
+            a2dp: None,
+            a2dp_states: HashMap::new(),
+            connect_requests: HashMap::new(),
+            audio_states: HashMap::new(),
+            delays: HashMap::new(),
+            active_device: None,
+            session_type: None,
+            session_owner: None,
+            session_state: A2dpSessionState::Closed,
+            selectable_codecs: HashMap::new(),
+            stream_requests: StreamRequests::default(),
+            local_disconnects: HashSet::new(),
+            acl_disconnect_reasons: HashMap::new(),
+            unexplained_disconnects: HashMap::new(),
+            reconnect_attempts: 0,
+            reconnect_delay: Duration::from_millis(0),
+            reconnects: HashMap::new(),
+            next_disconnect_id: 0,
+        }
+    }
+
+    pub(crate) fn callback_disconnected(&mut self, id: u32) {
+        self.callbacks.remove_callback(id);
+
+        // Nobody is left to stream or close the session.
+        if self.session_owner == Some(id) {
+            self.close_audio_session();
+        }
+    }
+
+    /// Stops the stream of the audio session and closes it, as the daemon shuts down.
+    pub(crate) fn stop_streams(&mut self) {
+        self.close_audio_session();
+    }
+
+    /// Cleans up the A2DP profile. It can be initialized again afterwards.
+    pub(crate) fn cleanup(&mut self) {
+        if let Some(mut a2dp) = self.a2dp.take() {
+            a2dp.cleanup();
+        }
+        self.initialized = false;
+        self.local_disconnects.clear();
+        self.acl_disconnect_reasons.clear();
+        self.unexplained_disconnects.clear();
+        self.reconnects.clear();
+    }
+
+    /// Sends a message to the media dispatch loop after `delay`.
+    fn send_after(&self, delay: Duration, message: Message) {
+        let tx = self.tx.clone();
+        topstack::get_runtime().spawn(async move {
+            tokio::time::sleep(delay).await;
+            let _result = tx.send(message).await;
+        });
+    }
+
+    fn set_session_state(&mut self, state: A2dpSessionState) {
+        if self.session_state == state {
+            return;
+        }
+
+        self.session_state = state;
+
+        for callback in self.callbacks.iter() {
+            callback.on_audio_session_state_changed(state.to_u32().unwrap());
+        }
+    }
+
+    fn is_session_streaming(&self) -> bool {
+        match self.session_state {
+            A2dpSessionState::Starting
+            | A2dpSessionState::Started
+            | A2dpSessionState::Suspended => true,
+            _ => false,
+        }
+    }
+
+    fn update_delay(&mut self, addr: BDAddr, delay: u16) {
+        if self.delays.get(&addr) == Some(&delay) {
+            return;
+        }
+
+        self.delays.insert(addr, delay);
+
+        for callback in self.callbacks.iter() {
+            callback.on_audio_delay_changed(addr.to_string(), delay as u32);
+        }
+    }
+
+    fn is_connected(&self, addr: &BDAddr) -> bool {
+        self.a2dp_states.get(addr) == Some(&BtavConnectionState::Connected)
+    }
+
+    fn update_active_device(&mut self, addr: Option<BDAddr>) {
+        if self.active_device == addr {
+            return;
+        }
+
+        self.active_device = addr;
+
+        let addr = addr.map(|addr| addr.to_string()).unwrap_or_default();
+        for callback in self.callbacks.iter() {
+            callback.on_active_device_changed(addr.clone());
+        }
+
+        // The stream of the session went to the previous device, the audio server restarts it.
+        if self.is_session_streaming() {
+            self.set_session_state(A2dpSessionState::Idle);
+        }
+    }
+
+    /// Makes a device the active one, or deselects the active device if `addr` is None.
+    fn switch_active_device(&mut self, addr: Option<BDAddr>) -> bool {
+        if self.active_device == addr {
+            return true;
+        }
+
+        let a2dp = match &mut self.a2dp {
+            Some(a2dp) => a2dp,
+            None => return false,
+        };
+
+        // Don't let audio of the previous device leak to the new one while the stack switches.
+        if let Some(previous) = self.active_device {
+            if self.audio_states.get(&previous) == Some(&BtavAudioState::Started) {
+                a2dp.suspend_stream();
+            }
+        }
+
+        let raw = match addr {
+            Some(addr) => addr.to_raw_address(),
+            None => RustRawAddress { address: [0; 6] },
+        };
+
+        if a2dp.set_active_device(&raw) != 0 {
+            return false;
+        }
+
+        self.update_active_device(addr);
+        true
+    }
+
+    /// Returns the device the stream of the session goes to: the active device, or else the
+    /// device connecting after the last `connect`.
+    fn stream_device(&self) -> Option<BDAddr> {
+        if self.active_device.is_some() {
+            return self.active_device;
+        }
+
+        self.connect_requests
+            .iter()
+            .filter(|(addr, _)| {
+                self.a2dp_states.get(addr) == Some(&BtavConnectionState::Connecting)
+            })
+            .max_by_key(|(_, requested)| **requested)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// Tracks a request to the stream of a device, sent right away if the device is connected
+    /// and else once it is. A previous request to the device fails.
+    fn request_stream(&mut self, addr: BDAddr, request: A2dpStreamRequest) {
+        self.fail_stream_request(addr);
+        self.stream_requests.add(addr, request);
+
+        if self.is_connected(&addr) {
+            self.send_stream_request(addr);
+        }
+
+        // The device won't report a stop if it wasn't streaming.
+        if request == A2dpStreamRequest::Stop
+            && self.audio_states.get(&addr) != Some(&BtavAudioState::Started)
+        {
+            self.complete_stream_request(addr, request);
+        }
+    }
+
+    /// Sends the pending request of a device to the native stack, and checks that the device
+    /// followed it after `STREAM_REQUEST_TIMEOUT`.
+    fn send_stream_request(&mut self, addr: BDAddr) {
+        let (request, id) = match self.stream_requests.attempt(&addr) {
+            Some(attempt) => attempt,
+            None => return,
+        };
+
+        let offload = self.session_type == Some(A2dpSessionType::Offload);
+        let a2dp = match &mut self.a2dp {
+            Some(a2dp) => a2dp,
+            None => return,
+        };
+
+        match request {
+            A2dpStreamRequest::Start => a2dp.start_audio_request(offload),
+            A2dpStreamRequest::Stop => a2dp.stop_audio_request(),
+        }
+
+        self.send_after(STREAM_REQUEST_TIMEOUT, Message::A2dpStreamRequestTimeout(addr, id));
+    }
+
+    /// Sends a request again if the device didn't follow it in time, or fails it after
+    /// `STREAM_REQUEST_ATTEMPTS`.
+    pub(crate) fn stream_request_timeout(&mut self, addr: BDAddr, id: u32) {
+        match self.stream_requests.timed_out(&addr, id) {
+            StreamRequestTimeout::Stale => {}
+            StreamRequestTimeout::Retry => self.send_stream_request(addr),
+            StreamRequestTimeout::Failed => {
+                eprintln!(
+                    "A2DP stream request to {} failed after {} attempts",
+                    addr.to_string(),
+                    STREAM_REQUEST_ATTEMPTS
+                );
+                self.fail_stream_request(addr);
+            }
+        }
+    }
+
+    /// Fails the pending request of a device, if any. The session stops `Starting` if it was a
+    /// start.
+    fn fail_stream_request(&mut self, addr: BDAddr) {
+        let request = match self.stream_requests.remove(&addr) {
+            Some(request) => request,
+            None => return,
+        };
+
+        self.stream_request_done(addr, request, false);
+
+        if request == A2dpStreamRequest::Start && self.session_state == A2dpSessionState::Starting {
+            self.set_session_state(A2dpSessionState::Idle);
+        }
+    }
+
+    /// Completes the pending request of a device if `request` is the one it waits for.
+    fn complete_stream_request(&mut self, addr: BDAddr, request: A2dpStreamRequest) {
+        if self.stream_requests.complete(&addr, request) {
+            self.stream_request_done(addr, request, true);
+        }
+    }
+
+    fn stream_request_done(&self, addr: BDAddr, request: A2dpStreamRequest, success: bool) {
+        for callback in self.callbacks.iter() {
+            callback.on_stream_request_done(addr.to_string(), request.to_u32().unwrap(), success);
+        }
+    }
+
+    fn new_disconnect_id(&mut self) -> u32 {
+        let id = self.next_disconnect_id;
+        self.next_disconnect_id = self.next_disconnect_id.wrapping_add(1);
+        id
+    }
+
+    /// Records why the BR/EDR ACL of a device disconnected, for the A2DP disconnection that goes
+    /// with it. Called from the main dispatch loop, so either may come first.
+    pub(crate) fn acl_disconnected(&mut self, addr: BDAddr, hci_reason: i32) {
+        if self.unexplained_disconnects.remove(&addr).is_some() {
+            self.device_disconnected(addr, MediaDisconnectReason::from_hci_reason(hci_reason));
+            return;
+        }
+
+        match self.a2dp_states.get(&addr) {
+            Some(BtavConnectionState::Connected) | Some(BtavConnectionState::Disconnecting) => {
+                self.acl_disconnect_reasons.insert(addr, hci_reason);
+            }
+            _ => {}
+        }
+    }
+
+    /// Works out why a connected device disconnected from A2DP, waiting for its ACL
+    /// disconnection if it didn't come first.
+    fn explain_disconnect(&mut self, addr: BDAddr) {
+        if self.local_disconnects.remove(&addr) {
+            self.acl_disconnect_reasons.remove(&addr);
+            self.device_disconnected(addr, MediaDisconnectReason::Local);
+            return;
+        }
+
+        if let Some(hci_reason) = self.acl_disconnect_reasons.remove(&addr) {
+            self.device_disconnected(addr, MediaDisconnectReason::from_hci_reason(hci_reason));
+            return;
+        }
+
+        let id = self.new_disconnect_id();
+        self.unexplained_disconnects.insert(addr, id);
+        self.send_after(DISCONNECT_REASON_TIMEOUT, Message::A2dpDisconnectReasonTimeout(addr, id));
+    }
+
+    /// Deems a device whose link stayed up to have closed A2DP itself.
+    pub(crate) fn disconnect_reason_timeout(&mut self, addr: BDAddr, id: u32) {
+        if self.unexplained_disconnects.get(&addr) != Some(&id) {
+            return;
+        }
+
+        self.unexplained_disconnects.remove(&addr);
+        self.device_disconnected(addr, MediaDisconnectReason::Remote);
+    }
+
+    /// Reports why a device disconnected, and starts reconnecting it if it was lost.
+    fn device_disconnected(&mut self, addr: BDAddr, reason: MediaDisconnectReason) {
+        for callback in self.callbacks.iter() {
+            callback
+                .on_bluetooth_audio_device_disconnected(addr.to_string(), reason.to_u32().unwrap());
+        }
+
+        if reason == MediaDisconnectReason::LinkLoss && self.reconnect_attempts > 0 {
+            let id = self.new_disconnect_id();
+            self.reconnects.insert(addr, PendingReconnect { id, attempts: 0 });
+            self.send_after(self.reconnect_delay, Message::A2dpReconnect(addr, id));
+        }
+    }
+
+    /// Makes the next attempt to reconnect a device that was lost, or gives up once the
+    /// attempts of the reconnect policy are over.
+    pub(crate) fn reconnect(&mut self, addr: BDAddr, id: u32) {
+        let attempts = match self.reconnects.get(&addr) {
+            Some(reconnect) if reconnect.id == id => reconnect.attempts,
+            _ => return,
+        };
+
+        // The previous attempt is still going.
+        if self.a2dp_states.get(&addr) == Some(&BtavConnectionState::Connecting) {
+            self.send_after(self.reconnect_delay, Message::A2dpReconnect(addr, id));
+            return;
+        }
+
+        if attempts >= self.reconnect_attempts {
+            eprintln!(
+                "Failed to reconnect A2DP to {} after {} attempts",
+                addr.to_string(),
+                attempts
+            );
+            self.reconnects.remove(&addr);
+
+            for callback in self.callbacks.iter() {
+                callback.on_audio_reconnect_failed(addr.to_string());
+            }
+            return;
+        }
+
+        match &mut self.a2dp {
+            Some(a2dp) => a2dp.connect(&addr.to_raw_address()),
+            None => {
+                self.reconnects.remove(&addr);
+                return;
+            }
+        }
+
+        let attempt = attempts + 1;
+        if let Some(reconnect) = self.reconnects.get_mut(&addr) {
+            reconnect.attempts = attempt;
+        }
+
+        for callback in self.callbacks.iter() {
+            callback.on_audio_reconnect_attempt(addr.to_string(), attempt);
+        }
+
+        self.send_after(
+            reconnect_backoff(self.reconnect_delay, attempt),
+            Message::A2dpReconnect(addr, id),
+        );
+    }
+}
+
+/// Returns how long to wait after a reconnection attempt before the next one. Each attempt is
+/// given twice as long as the previous one.
+fn reconnect_backoff(delay: Duration, attempt: u32) -> Duration {
+    delay.saturating_mul(2u32.saturating_pow(attempt))
+}
+
+/// Runs the dispatch loop of the messages in `MessageLane::Media`, next to the main dispatch loop.
+///
+/// The events of each device are dispatched in order in its own work queue, see `DeviceQueues`.
+/// The loop returns once every sender is dropped and the queues are done.
+pub(crate) async fn dispatch(
+    mut rx: Receiver<Message>,
+    bluetooth_media: Arc<Mutex<BluetoothMedia>>,
+    watchdog: Watchdog,
+    recorder: Option<MessageRecorder>,
+) {
+    let mut device_queues = DeviceQueues::new();
+
+    while let Some(m) = rx.recv().await {
+        if let Some(recorder) = &recorder {
+            recorder.record(&m);
+        }
+
+        if let Some(addr) = m.device(&device_queues) {
+            let bluetooth_media = bluetooth_media.clone();
+            let watchdog = watchdog.clone();
+            device_queues.post(addr, move || {
+                let _watch = watchdog.watch(Handler::Device(addr), m.name());
+                dispatch_device_message(m, &bluetooth_media);
+            });
+            continue;
+        }
+
+        let _watch = watchdog.watch(Handler::Media, m.name());
+        match m {
+            Message::MediaCallbackDisconnected(id) => {
+                bluetooth_media.lock().unwrap().callback_disconnected(id);
+            }
+
+            _ => eprintln!("Unexpected message in the media dispatch loop"),
+        }
+    }
+
+    device_queues.flush().await;
+}
+
+/// Dispatches a media message about a remote device, see `Message::device`.
+fn dispatch_device_message(m: Message, bluetooth_media: &Arc<Mutex<BluetoothMedia>>) {
+    match m {
+        Message::A2dpConnectionStateChanged(addr, state) => {
+            bluetooth_media.lock().unwrap().connection_state_changed(addr, state);
+        }
+
+        Message::A2dpAudioStateChanged(addr, state) => {
+            bluetooth_media.lock().unwrap().audio_state_changed(addr, state);
+        }
+
+        Message::A2dpAudioConfig(addr, codec_config, local_caps, selectable_caps) => {
+            bluetooth_media.lock().unwrap().audio_config(
+                addr,
+                codec_config,
+                local_caps,
+                selectable_caps,
+            );
+        }
+
+        Message::A2dpAudioDelay(addr, delay) => {
+            bluetooth_media.lock().unwrap().audio_delay(addr, delay);
+        }
+
+        Message::A2dpStreamRequestTimeout(addr, id) => {
+            bluetooth_media.lock().unwrap().stream_request_timeout(addr, id);
+        }
+
+        Message::A2dpDisconnectReasonTimeout(addr, id) => {
+            bluetooth_media.lock().unwrap().disconnect_reason_timeout(addr, id);
+        }
+
+        Message::A2dpReconnect(addr, id) => {
+            bluetooth_media.lock().unwrap().reconnect(addr, id);
+        }
+
+        _ => eprintln!("Unexpected message in a media work queue"),
+    }
+}
+
+#[btif_callbacks_generator(btif_a2dp_callbacks, A2dpCallbacks)]
+pub(crate) trait BtifA2dpCallbacks {
+    #[stack_message(A2dpConnectionStateChanged)]
+    fn connection_state_changed(&mut self, addr: RustRawAddress, state: BtavConnectionState);
+
+    #[stack_message(A2dpAudioStateChanged)]
+    fn audio_state_changed(&mut self, addr: RustRawAddress, state: BtavAudioState);
+
+    #[stack_message(A2dpAudioConfig)]
+    fn audio_config(
+        &mut self,
+        addr: RustRawAddress,
+        codec_config: A2dpCodecConfig,
+        local_capabilities: Vec<A2dpCodecConfig>,
+        selectable_capabilities: Vec<A2dpCodecConfig>,
+    );
+
+    #[stack_message(A2dpAudioDelay)]
+    fn audio_delay(&mut self, addr: RustRawAddress, delay: u16);
+}
+
+impl BtifA2dpCallbacks for BluetoothMedia {
+    fn connection_state_changed(&mut self, addr: RustRawAddress, state: BtavConnectionState) {
+        let addr = BDAddr::from_raw_address(&addr);
+
+        if state == BtavConnectionState::Unknown {
+            eprintln!("Ignoring unknown A2DP connection state of {}", addr.to_string());
+            return;
+        }
+
+        match state {
+            BtavConnectionState::Connected => {
+                self.local_disconnects.remove(&addr);
+                self.acl_disconnect_reasons.remove(&addr);
+                self.reconnects.remove(&addr);
+
+                if let Some(requested) = self.connect_requests.remove(&addr) {
+                    let latency = requested.elapsed();
+                    self.metrics
+                        .lock()
+                        .unwrap()
+                        .record_latency(metrics::A2DP_CONNECTION_LATENCY, latency);
+                }
+
+                for callback in self.callbacks.iter() {
+                    callback.on_bluetooth_audio_device_added(addr.to_string());
+                }
+
+                // A stream requested while the device was connecting goes to it now.
+                self.a2dp_states.insert(addr, state);
+                if self.stream_requests.contains(&addr) {
+                    if self.active_device.is_none() {
+                        self.switch_active_device(Some(addr));
+                    }
+                    self.send_stream_request(addr);
+                }
+                return;
+            }
+            BtavConnectionState::Disconnected => {
+                let was_connected = match self.a2dp_states.get(&addr) {
+                    Some(BtavConnectionState::Connected)
+                    | Some(BtavConnectionState::Disconnecting) => true,
+                    _ => false,
+                };
+
+                // The stream is stopped for good, but can't start anymore.
+                self.complete_stream_request(addr, A2dpStreamRequest::Stop);
+                self.fail_stream_request(addr);
+                self.connect_requests.remove(&addr);
+                self.audio_states.remove(&addr);
+                self.delays.remove(&addr);
+                self.selectable_codecs.remove(&addr);
+                self.a2dp_states.insert(addr, state);
+
+                for callback in self.callbacks.iter() {
+                    callback.on_bluetooth_audio_device_removed(addr.to_string());
+                }
+
+                // Keep audio on Bluetooth if other devices are still connected.
+                if self.active_device == Some(addr) {
+                    let next = self.a2dp_states.iter().find_map(|(addr, state)| {
+                        (*state == BtavConnectionState::Connected).then(|| *addr)
+                    });
+
+                    if next.is_none() || !self.switch_active_device(next) {
+                        self.update_active_device(None);
+                    }
+                }
+
+                // A failed connection attempt is not a disconnection.
+                if was_connected {
+                    self.explain_disconnect(addr);
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        self.a2dp_states.insert(addr, state);
+    }
+
+    fn audio_state_changed(&mut self, addr: RustRawAddress, state: BtavAudioState) {
+        let addr = BDAddr::from_raw_address(&addr);
+
+        if state == BtavAudioState::Unknown {
+            eprintln!("Ignoring unknown A2DP audio state of {}", addr.to_string());
+            return;
+        }
+
+        if state == BtavAudioState::Started {
+            self.update_active_device(Some(addr));
+        }
+
+        self.audio_states.insert(addr, state);
+
+        match state {
+            BtavAudioState::Started => self.complete_stream_request(addr, A2dpStreamRequest::Start),
+            BtavAudioState::Stopped | BtavAudioState::RemoteSuspend => {
+                self.complete_stream_request(addr, A2dpStreamRequest::Stop)
+            }
+            _ => {}
+        }
+
+        if self.session_type.is_none() || self.active_device != Some(addr) {
+            return;
+        }
+
+        match state {
+            BtavAudioState::Started => self.set_session_state(A2dpSessionState::Started),
+            BtavAudioState::RemoteSuspend => self.set_session_state(A2dpSessionState::Suspended),
+            BtavAudioState::Stopped if self.is_session_streaming() => {
+                self.set_session_state(A2dpSessionState::Idle)
+            }
+            _ => {}
+        }
+    }
+
+    fn audio_config(
+        &mut self,
+        addr: RustRawAddress,
+        codec_config: A2dpCodecConfig,
+        local_capabilities: Vec<A2dpCodecConfig>,
+        selectable_capabilities: Vec<A2dpCodecConfig>,
+    ) {
+        let addr = BDAddr::from_raw_address(&addr);
+        self.selectable_codecs.insert(addr, selectable_capabilities.clone());
+
+        for callback in self.callbacks.iter() {
+            callback.on_audio_config_changed(
+                addr.to_string(),
+                codec_config.clone(),
+                local_capabilities.clone(),
+                selectable_capabilities.clone(),
+            );
+        }
+    }
+
+    fn audio_delay(&mut self, addr: RustRawAddress, delay: u16) {
+        self.update_delay(BDAddr::from_raw_address(&addr), delay);
+    }
+}
+
+impl IBluetoothMedia for BluetoothMedia {
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothMediaCallback + Send>) -> u32 {
+        self.callbacks.add_callback(callback)
+    }
+
+    fn initialize(&mut self) -> bool {
+        if self.initialized {
+            return false;
+        }
+
+        if !self.intf.lock().unwrap().supports_profile(SupportedProfiles::A2dp) {
+            eprintln!("A2DP is not supported by the native stack");
+            return false;
+        }
+
+        let mut a2dp = A2dp::new(&self.intf.lock().unwrap());
+        if !a2dp.initialize(Arc::new(btif_a2dp_callbacks(self.tx.clone()))) {
+            return false;
+        }
+
+        self.a2dp = Some(a2dp);
+        self.initialized = true;
+        true
+    }
+
+    fn connect(&mut self, device: String) {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return,
+        };
+
+        if let Some(a2dp) = &mut self.a2dp {
+            a2dp.connect(&addr.to_raw_address());
+            self.connect_requests.entry(addr).or_insert_with(Instant::now);
+            self.reconnects.remove(&addr);
+        }
+    }
+
+    fn disconnect(&mut self, device: String) {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return,
+        };
+
+        if let Some(a2dp) = &mut self.a2dp {
+            a2dp.disconnect(&addr.to_raw_address());
+            self.reconnects.remove(&addr);
+            if self.is_connected(&addr) {
+                self.local_disconnects.insert(addr);
+            }
+        }
+    }
+
+    fn get_presentation_delay(&self, device: String) -> u32 {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return 0,
+        };
+
+        match self.delays.get(&addr) {
+            Some(delay) => *delay as u32,
+            None => 0,
+        }
+    }
+
+    fn set_presentation_delay(&mut self, device: String, delay: u32) -> bool {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+        if !self.is_connected(&addr) {
+            return false;
+        }
+
+        // AVDTP delay reports are 16 bits wide.
+        let delay = std::cmp::min(delay, u16::MAX as u32) as u16;
+
+        match &mut self.a2dp {
+            Some(a2dp) => a2dp.set_audio_delay(&addr.to_raw_address(), delay),
+            None => return false,
+        }
+
+        self.update_delay(addr, delay);
+        true
+    }
+
+    fn get_audio_state(&self, device: String) -> u32 {
+        let state = BDAddr::from_string(&device)
+            .and_then(|addr| self.audio_states.get(&addr).copied())
+            .unwrap_or(BtavAudioState::Stopped);
+
+        state.to_u32().unwrap()
+    }
+
+    fn get_active_device(&self) -> String {
+        match self.active_device {
+            None => String::from(""),
+            Some(addr) => addr.to_string(),
+        }
+    }
+
+    fn set_active_device(&mut self, device: String) -> bool {
+        if device.is_empty() {
+            return self.switch_active_device(None);
+        }
+
+        match BDAddr::from_string(&device) {
+            Some(addr) if self.is_connected(&addr) => self.switch_active_device(Some(addr)),
+            _ => false,
+        }
+    }
+
+    fn get_connected_audio_devices(&self) -> Vec<String> {
+        self.a2dp_states
+            .iter()
+            .filter(|(_, state)| **state == BtavConnectionState::Connected)
+            .map(|(addr, _)| addr.to_string())
+            .collect()
+    }
+
+    fn get_supported_session_types(&self) -> Vec<u32> {
+        let mut session_types = vec![A2dpSessionType::Software];
+        if self.a2dp.as_ref().map_or(false, |a2dp| a2dp.is_offload_enabled()) {
+            session_types.push(A2dpSessionType::Offload);
+        }
+
+        session_types.iter().map(|session_type| session_type.to_u32().unwrap()).collect()
+    }
+
+    fn open_audio_session(&mut self, callback_id: u32, session_type: u32) -> bool {
+        if self.session_type.is_some() || self.callbacks.get_by_id(callback_id).is_none() {
+            return false;
+        }
+
+        let session_type = match A2dpSessionType::from_u32(session_type) {
+            Some(session_type) => session_type,
+            None => return false,
+        };
+
+        if !self.get_supported_session_types().contains(&session_type.to_u32().unwrap()) {
+            return false;
+        }
+
+        self.session_type = Some(session_type);
+        self.session_owner = Some(callback_id);
+        self.set_session_state(A2dpSessionState::Idle);
+        true
+    }
+
+    fn close_audio_session(&mut self) {
+        if self.session_type.is_none() {
+            return;
+        }
+
+        self.stop_audio_request();
+        self.session_type = None;
+        self.session_owner = None;
+        self.set_session_state(A2dpSessionState::Closed);
+    }
+
+    fn get_audio_session_state(&self) -> u32 {
+        self.session_state.to_u32().unwrap()
+    }
+
+    fn get_supported_codecs(&self, device: String) -> Vec<A2dpCodecConfig> {
+        BDAddr::from_string(&device)
+            .and_then(|addr| self.selectable_codecs.get(&addr).cloned())
+            .unwrap_or_default()
+    }
+
+    fn start_audio_request(&mut self) -> bool {
+        if self.session_type.is_none()
+            || self.a2dp.is_none()
+            || self.session_state == A2dpSessionState::Starting
+            || self.session_state == A2dpSessionState::Started
+        {
+            return false;
+        }
+
+        let addr = match self.stream_device() {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+        self.request_stream(addr, A2dpStreamRequest::Start);
+        self.set_session_state(A2dpSessionState::Starting);
+        true
+    }
+
+    fn stop_audio_request(&mut self) -> bool {
+        if !self.is_session_streaming() || self.a2dp.is_none() {
+            return false;
+        }
+
+        // A start still waiting for the device to connect is only dropped.
+        match self.stream_requests.waiting_device() {
+            Some(addr) => self.fail_stream_request(addr),
+            None => {
+                if let Some(addr) = self.stream_device() {
+                    self.request_stream(addr, A2dpStreamRequest::Stop);
+                }
+            }
+        }
+
+        self.set_session_state(A2dpSessionState::Idle);
+        true
+    }
+
+    fn suspend_audio_request(&mut self) -> bool {
+        if self.session_state != A2dpSessionState::Starting
+            && self.session_state != A2dpSessionState::Started
+        {
+            return false;
+        }
+
+        match &mut self.a2dp {
+            Some(a2dp) => a2dp.suspend_stream(),
+            None => return false,
+        }
+
+        self.set_session_state(A2dpSessionState::Suspended);
+        true
+    }
+
+    fn set_reconnect_policy(&mut self, max_attempts: u32, delay_ms: u32) -> bool {
+        if max_attempts > RECONNECT_ATTEMPTS_MAX
+            || (max_attempts > 0 && (delay_ms == 0 || delay_ms > RECONNECT_DELAY_MAX_MS))
+        {
+            return false;
+        }
+
+        self.reconnect_attempts = max_attempts;
+        self.reconnect_delay = Duration::from_millis(delay_ms.into());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disconnect_reason_from_hci_reason() {
+        let reason = MediaDisconnectReason::from_hci_reason;
+        assert_eq!(reason(HCI_ERR_CONN_CAUSE_LOCAL_HOST), MediaDisconnectReason::Local);
+        assert_eq!(reason(HCI_ERR_PEER_USER), MediaDisconnectReason::Remote);
+        assert_eq!(reason(HCI_ERR_REMOTE_LOW_RESOURCE), MediaDisconnectReason::Remote);
+        assert_eq!(reason(HCI_ERR_REMOTE_POWER_OFF), MediaDisconnectReason::Remote);
+        assert_eq!(reason(HCI_ERR_CONNECTION_TOUT), MediaDisconnectReason::LinkLoss);
+        assert_eq!(reason(HCI_ERR_LMP_RESPONSE_TIMEOUT), MediaDisconnectReason::LinkLoss);
+        // Success, and authentication failure.
+        assert_eq!(reason(0), MediaDisconnectReason::Other);
+        assert_eq!(reason(0x05), MediaDisconnectReason::Other);
+    }
+
+    fn addr(last: u8) -> BDAddr {
+        BDAddr::from_string(&format!("11:22:33:44:55:{:02X}", last)).unwrap()
+    }
+
+    #[test]
+    fn stream_request_waits_for_connection() {
+        let mut requests = StreamRequests::default();
+        requests.add(addr(1), A2dpStreamRequest::Start);
+        assert_eq!(requests.waiting_device(), Some(addr(1)));
+
+        // Sent once the device connects.
+        assert_eq!(requests.attempt(&addr(1)), Some((A2dpStreamRequest::Start, 0)));
+        assert_eq!(requests.waiting_device(), None);
+        assert_eq!(requests.timed_out(&addr(1), 0), StreamRequestTimeout::Retry);
+
+        assert!(requests.complete(&addr(1), A2dpStreamRequest::Start));
+        assert!(!requests.contains(&addr(1)));
+    }
+
+    #[test]
+    fn stream_request_fails_after_attempts() {
+        let mut requests = StreamRequests::default();
+        requests.add(addr(1), A2dpStreamRequest::Start);
+
+        for _ in 1..STREAM_REQUEST_ATTEMPTS {
+            let (_, id) = requests.attempt(&addr(1)).unwrap();
+            assert_eq!(requests.timed_out(&addr(1), id), StreamRequestTimeout::Retry);
+        }
+
+        let (_, id) = requests.attempt(&addr(1)).unwrap();
+        assert_eq!(requests.timed_out(&addr(1), id), StreamRequestTimeout::Failed);
+        assert_eq!(requests.remove(&addr(1)), Some(A2dpStreamRequest::Start));
+    }
+
+    #[test]
+    fn stream_request_dropped_on_disconnection() {
+        let mut requests = StreamRequests::default();
+        requests.add(addr(1), A2dpStreamRequest::Start);
+        let (_, id) = requests.attempt(&addr(1)).unwrap();
+
+        // The device disconnects while starting, its timeout fires afterwards.
+        assert!(!requests.complete(&addr(1), A2dpStreamRequest::Stop));
+        assert_eq!(requests.remove(&addr(1)), Some(A2dpStreamRequest::Start));
+        assert_eq!(requests.timed_out(&addr(1), id), StreamRequestTimeout::Stale);
+        assert_eq!(requests.attempt(&addr(1)), None);
+    }
+
+    #[test]
+    fn stream_request_replaced() {
+        let mut requests = StreamRequests::default();
+        requests.add(addr(1), A2dpStreamRequest::Start);
+        requests.add(addr(2), A2dpStreamRequest::Start);
+        let (_, start_id) = requests.attempt(&addr(1)).unwrap();
+
+        requests.add(addr(1), A2dpStreamRequest::Stop);
+        let (request, stop_id) = requests.attempt(&addr(1)).unwrap();
+        assert_eq!(request, A2dpStreamRequest::Stop);
+        assert_ne!(stop_id, start_id);
+        assert_eq!(requests.timed_out(&addr(1), start_id), StreamRequestTimeout::Stale);
+        assert!(!requests.complete(&addr(1), A2dpStreamRequest::Start));
+        assert_eq!(requests.waiting_device(), Some(addr(2)));
+    }
+
+    #[test]
+    fn reconnect_backoff_doubles() {
+        let delay = Duration::from_millis(500);
+        assert_eq!(reconnect_backoff(delay, 0), Duration::from_millis(500));
+        assert_eq!(reconnect_backoff(delay, 1), Duration::from_secs(1));
+        assert_eq!(reconnect_backoff(delay, 3), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn reconnect_backoff_saturates() {
+        let delay = Duration::from_millis(u64::from(RECONNECT_DELAY_MAX_MS));
+        assert_eq!(reconnect_backoff(delay, 64), delay * u32::MAX);
+        assert_eq!(reconnect_backoff(Duration::MAX, 1), Duration::MAX);
+    }
+}
@@ -0,0 +1,435 @@
+//! Anything related to the LE audio API (IBluetoothLeAudio).
+//!
+//! The LE audio API connects to audio devices as the unicast client of the Basic Audio Profile
+//! (BAP), and streams to groups of them, e.g. both earbuds of a pair, through the Audio Stream
+//! Control Service (ASCS). It is the new media path, developed alongside A2DP. It is only built
+//! with the `le_audio` feature, which builds its shim in the native stack, and only served with
+//! `Feature::LeAudio` on until it is complete. Since it is optional, it is plugged into the stack
+//! main dispatch loop as a `StackExtension` instead of having its own `Message` variants.
+
+use bt_topshim::btif::ffi::RustRawAddress;
+use bt_topshim::btif::{BluetoothInterface, SupportedProfiles};
+use bt_topshim::profiles::le_audio::{
+    LeAudio, LeAudioCallbacks, LeAudioConnectionState, LeAudioGroupStatus,
+    LE_AUDIO_GROUP_ID_UNDEFINED,
+};
+use bt_topshim::profiles::ProfileShim;
+use bt_topshim::topstack;
+
+use num_traits::cast::ToPrimitive;
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+
+use crate::extension::StackExtension;
+use crate::{BDAddr, Callbacks, Message, MessageSender, RPCProxy};
+
+/// Defines the LE audio API.
+///
+/// Streams are managed by group: a device is in a group once its audio configuration is reported
+/// through `IBluetoothLeAudioCallback::on_audio_conf`, and the devices of a group stream together.
+pub trait IBluetoothLeAudio {
+    /// Adds a callback from a client who wishes to observe LE audio events.
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothLeAudioCallback + Send>);
+
+    /// Initializes the LE audio client profile. Must be called before any other LE audio API.
+    fn initialize(&mut self) -> bool;
+
+    /// Connects to the given device.
+    fn connect(&mut self, device: String) -> bool;
+
+    /// Disconnects from the given device.
+    fn disconnect(&mut self, device: String) -> bool;
+
+    /// Returns the `LeAudioConnectionState` of the device.
+    fn get_connection_state(&self, device: String) -> u32;
+
+    /// Returns the group of the device, or -1 if the device is in none.
+    fn get_group_id(&self, device: String) -> i32;
+
+    /// Returns the addresses of the connected devices of a group.
+    fn get_group_members(&self, group_id: i32) -> Vec<String>;
+
+    /// Returns the `LeAudioGroupStatus` of a group, `Idle` for the groups not reported yet.
+    fn get_group_status(&self, group_id: i32) -> u32;
+
+    /// Starts streaming to a group for the audio contexts in `content_type`, a bit mask of the
+    /// BAP context types.
+    ///
+    /// The group is streaming once `IBluetoothLeAudioCallback::on_group_status_changed` reports
+    /// it. Returns false if the profile is not initialized or the group is unknown.
+    fn group_stream(&mut self, group_id: i32, content_type: i32) -> bool;
+
+    /// Suspends the streams of a group, which stay configured to resume quickly.
+    fn group_suspend(&mut self, group_id: i32) -> bool;
+
+    /// Stops the streams of a group and releases them.
+    fn group_stop(&mut self, group_id: i32) -> bool;
+}
+
+/// The interface for LE audio callbacks registered through `IBluetoothLeAudio::register_callback`.
+pub trait IBluetoothLeAudioCallback: RPCProxy {
+    /// When the `LeAudioConnectionState` of a device is changed.
+    fn on_connection_state_changed(&self, addr: String, state: u32);
+
+    /// When the `LeAudioGroupStatus` of a group is changed, with the flags of the group.
+    fn on_group_status_changed(&self, group_id: i32, status: u32, flags: i32);
+
+    /// When a device is configured or reconfigured, with the directions it streams in (a bit
+    /// mask of sink and source), its group and the audio locations of its sinks and sources.
+    fn on_audio_conf(
+        &self,
+        addr: String,
+        direction: i32,
+        group_id: i32,
+        sink_audio_location: u32,
+        source_audio_location: u32,
+    );
+
+    /// When another device of the coordinated set of a group is available to connect to, e.g.
+    /// the other earbud of a pair.
+    fn on_set_member_available(&self, addr: String, group_id: i32);
+}
+
+/// Messages of the LE audio API, posted to the stack main dispatch loop as `Message::Extension`.
+enum LeAudioMessage {
+    ConnectionState(RustRawAddress, LeAudioConnectionState),
+    GroupStatus(u8, LeAudioGroupStatus, u8),
+    AudioConf(RustRawAddress, u8, u8, u32, u32),
+    SetMemberAvailable(RustRawAddress, u8),
+    CallbackDisconnected(u32),
+}
+
+/// Posted by `BluetoothLeAudio::callbacks` when the client of a callback disconnects.
+fn callback_disconnected_message(id: u32) -> Message {
+    Message::Extension(Box::new(LeAudioMessage::CallbackDisconnected(id)))
+}
+
+fn post_message(tx: &MessageSender, message: LeAudioMessage) {
+    let tx = tx.clone();
+    topstack::get_runtime().spawn(async move {
+        let _ = tx.send(Message::Extension(Box::new(message))).await;
+    });
+}
+
+fn btif_le_audio_callbacks(tx: MessageSender) -> LeAudioCallbacks {
+    let state_tx = tx.clone();
+    let status_tx = tx.clone();
+    let conf_tx = tx.clone();
+
+    LeAudioCallbacks {
+        connection_state_changed: Box::new(move |addr, state| {
+            post_message(&state_tx, LeAudioMessage::ConnectionState(addr, state));
+        }),
+        group_status_changed: Box::new(move |group_id, status, flags| {
+            post_message(&status_tx, LeAudioMessage::GroupStatus(group_id, status, flags));
+        }),
+        audio_conf: Box::new(move |addr, direction, group_id, sink, source| {
+            let message = LeAudioMessage::AudioConf(addr, direction, group_id, sink, source);
+            post_message(&conf_tx, message);
+        }),
+        set_member_available: Box::new(move |addr, group_id| {
+            post_message(&tx, LeAudioMessage::SetMemberAvailable(addr, group_id));
+        }),
+    }
+}
+
+/// Converts a group ID of the API, returning None for the ones that don't fit or are undefined.
+fn to_group_id(group_id: i32) -> Option<u8> {
+    match u8::try_from(group_id) {
+        Ok(group_id) if group_id != LE_AUDIO_GROUP_ID_UNDEFINED => Some(group_id),
+        _ => None,
+    }
+}
+
+/// Implementation of the LE audio API.
+pub struct BluetoothLeAudio {
+    intf: Arc<Mutex<BluetoothInterface>>,
+    tx: MessageSender,
+    callbacks: Callbacks<dyn IBluetoothLeAudioCallback + Send>,
+    le_audio: Option<LeAudio>,
+    connection_states: HashMap<BDAddr, LeAudioConnectionState>,
+    /// Group of each device, as reported with its audio configuration.
+    groups: HashMap<BDAddr, u8>,
+    group_statuses: HashMap<u8, LeAudioGroupStatus>,
+}
+
+impl BluetoothLeAudio {
+    /// Constructs the IBluetoothLeAudio implementation.
+    pub fn new(tx: MessageSender, intf: Arc<Mutex<BluetoothInterface>>) -> BluetoothLeAudio {
+        BluetoothLeAudio {
+            intf,
+            callbacks: Callbacks::new(tx.clone(), callback_disconnected_message),
+            tx,
+            le_audio: None,
+            connection_states: HashMap::new(),
+            groups: HashMap::new(),
+            group_statuses: HashMap::new(),
+        }
+    }
+
+    fn handle_message(&mut self, message: LeAudioMessage) {
+        match message {
+            LeAudioMessage::ConnectionState(addr, state) => {
+                self.connection_state_changed(BDAddr::from_raw_address(&addr), state);
+            }
+            LeAudioMessage::GroupStatus(group_id, status, flags) => {
+                self.group_status_changed(group_id, status, flags);
+            }
+            LeAudioMessage::AudioConf(addr, direction, group_id, sink, source) => {
+                self.audio_conf(BDAddr::from_raw_address(&addr), direction, group_id, sink, source);
+            }
+            LeAudioMessage::SetMemberAvailable(addr, group_id) => {
+                let addr = BDAddr::from_raw_address(&addr).to_string();
+                for callback in self.callbacks.iter() {
+                    callback.on_set_member_available(addr.clone(), group_id as i32);
+                }
+            }
+            LeAudioMessage::CallbackDisconnected(id) => {
+                self.callbacks.remove_callback(id);
+            }
+        }
+    }
+
+    fn connection_state_changed(&mut self, addr: BDAddr, state: LeAudioConnectionState) {
+        if state == LeAudioConnectionState::Unknown {
+            eprintln!("Ignoring unknown LE audio connection state of {}", addr.to_string());
+            return;
+        }
+
+        if state == LeAudioConnectionState::Disconnected {
+            self.connection_states.remove(&addr);
+        } else {
+            self.connection_states.insert(addr, state);
+        }
+
+        for callback in self.callbacks.iter() {
+            callback.on_connection_state_changed(addr.to_string(), state.to_u32().unwrap());
+        }
+    }
+
+    fn audio_conf(
+        &mut self,
+        addr: BDAddr,
+        direction: u8,
+        group_id: u8,
+        sink_audio_location: u32,
+        source_audio_location: u32,
+    ) {
+        if group_id == LE_AUDIO_GROUP_ID_UNDEFINED {
+            self.groups.remove(&addr);
+        } else {
+            self.groups.insert(addr, group_id);
+        }
+
+        for callback in self.callbacks.iter() {
+            callback.on_audio_conf(
+                addr.to_string(),
+                direction as i32,
+                group_id as i32,
+                sink_audio_location,
+                source_audio_location,
+            );
+        }
+    }
+
+    fn group_status_changed(&mut self, group_id: u8, status: LeAudioGroupStatus, flags: u8) {
+        match status {
+            LeAudioGroupStatus::Unknown => {
+                eprintln!("Ignoring unknown status of LE audio group {}", group_id);
+                return;
+            }
+            LeAudioGroupStatus::Destroyed => {
+                self.group_statuses.remove(&group_id);
+                self.groups.retain(|_, group| *group != group_id);
+            }
+            _ => {
+                self.group_statuses.insert(group_id, status);
+            }
+        }
+
+        for callback in self.callbacks.iter() {
+            callback.on_group_status_changed(
+                group_id as i32,
+                status.to_u32().unwrap(),
+                flags as i32,
+            );
+        }
+    }
+
+    /// Returns the profile and the group a stream request is about, if both are known.
+    fn get_group(&mut self, group_id: i32) -> Option<(&mut LeAudio, u8)> {
+        let group_id = to_group_id(group_id)?;
+        if !self.groups.values().any(|group| *group == group_id) {
+            return None;
+        }
+
+        self.le_audio.as_mut().map(|le_audio| (le_audio, group_id))
+    }
+
+    /// Stops the streams of the groups and cleans up the profile, as the daemon shuts down.
+    fn shutdown(&mut self) {
+        let streaming: Vec<u8> = self
+            .group_statuses
+            .iter()
+            .filter(|(_, status)| **status != LeAudioGroupStatus::Idle)
+            .map(|(group_id, _)| *group_id)
+            .collect();
+
+        if let Some(mut le_audio) = self.le_audio.take() {
+            for group_id in streaming {
+                le_audio.group_stop(group_id);
+            }
+            le_audio.cleanup();
+        }
+    }
+}
+
+impl IBluetoothLeAudio for BluetoothLeAudio {
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothLeAudioCallback + Send>) {
+        self.callbacks.add_callback(callback);
+    }
+
+    fn initialize(&mut self) -> bool {
+        if self.le_audio.is_some() {
+            return false;
+        }
+
+        if !self.intf.lock().unwrap().supports_profile(SupportedProfiles::LeAudio) {
+            eprintln!("LE audio is not supported by the native stack");
+            return false;
+        }
+
+        let mut le_audio = LeAudio::new(&self.intf.lock().unwrap());
+        if !le_audio.initialize(Arc::new(btif_le_audio_callbacks(self.tx.clone()))) {
+            return false;
+        }
+
+        self.le_audio = Some(le_audio);
+        true
+    }
+
+    fn connect(&mut self, device: String) -> bool {
+        match (BDAddr::from_string(&device), &mut self.le_audio) {
+            (Some(addr), Some(le_audio)) => {
+                le_audio.connect(&addr.to_raw_address());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn disconnect(&mut self, device: String) -> bool {
+        match (BDAddr::from_string(&device), &mut self.le_audio) {
+            (Some(addr), Some(le_audio)) => {
+                le_audio.disconnect(&addr.to_raw_address());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn get_connection_state(&self, device: String) -> u32 {
+        let state = BDAddr::from_string(&device)
+            .and_then(|addr| self.connection_states.get(&addr).copied())
+            .unwrap_or(LeAudioConnectionState::Disconnected);
+
+        state.to_u32().unwrap()
+    }
+
+    fn get_group_id(&self, device: String) -> i32 {
+        BDAddr::from_string(&device)
+            .and_then(|addr| self.groups.get(&addr))
+            .map_or(-1, |group_id| *group_id as i32)
+    }
+
+    fn get_group_members(&self, group_id: i32) -> Vec<String> {
+        let group_id = match to_group_id(group_id) {
+            Some(group_id) => group_id,
+            None => return vec![],
+        };
+
+        self.groups
+            .iter()
+            .filter(|(addr, group)| {
+                **group == group_id
+                    && self.connection_states.get(addr) == Some(&LeAudioConnectionState::Connected)
+            })
+            .map(|(addr, _)| addr.to_string())
+            .collect()
+    }
+
+    fn get_group_status(&self, group_id: i32) -> u32 {
+        let status = to_group_id(group_id)
+            .and_then(|group_id| self.group_statuses.get(&group_id).copied())
+            .unwrap_or(LeAudioGroupStatus::Idle);
+
+        status.to_u32().unwrap()
+    }
+
+    fn group_stream(&mut self, group_id: i32, content_type: i32) -> bool {
+        let content_type = match u16::try_from(content_type) {
+            Ok(content_type) => content_type,
+            Err(_) => return false,
+        };
+
+        match self.get_group(group_id) {
+            Some((le_audio, group_id)) => {
+                le_audio.group_stream(group_id, content_type);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn group_suspend(&mut self, group_id: i32) -> bool {
+        match self.get_group(group_id) {
+            Some((le_audio, group_id)) => {
+                le_audio.group_suspend(group_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn group_stop(&mut self, group_id: i32) -> bool {
+        match self.get_group(group_id) {
+            Some((le_audio, group_id)) => {
+                le_audio.group_stop(group_id);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Plugs the LE audio API into the stack main dispatch loop.
+pub struct BluetoothLeAudioExtension {
+    le_audio: Arc<Mutex<BluetoothLeAudio>>,
+}
+
+impl BluetoothLeAudioExtension {
+    pub fn new(le_audio: Arc<Mutex<BluetoothLeAudio>>) -> BluetoothLeAudioExtension {
+        BluetoothLeAudioExtension { le_audio }
+    }
+}
+
+impl StackExtension for BluetoothLeAudioExtension {
+    fn name(&self) -> String {
+        String::from("le_audio")
+    }
+
+    fn init(&mut self, _tx: MessageSender) {}
+
+    fn dispatch(&mut self, message: Box<dyn Any + Send>) -> Result<(), Box<dyn Any + Send>> {
+        let message = message.downcast::<LeAudioMessage>()?;
+        self.le_audio.lock().unwrap().handle_message(*message);
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        self.le_audio.lock().unwrap().shutdown();
+    }
+}
@@ -0,0 +1,253 @@
+//! Anything related to the QA API (IBluetoothQA).
+//!
+//! The QA API drives the controller test modes used for certification and factory testing. It is
+//! only built with the `qa` feature. Since it is optional, it is plugged into the stack main
+//! dispatch loop as a `StackExtension` instead of having its own `Message` variants.
+
+use bt_topshim::btif::ffi::RustRawAddress;
+use bt_topshim::btif::{BluetoothCallbacks, BluetoothInterface};
+use bt_topshim::qa::{Qa, QaCallbacks};
+use bt_topshim::topstack;
+
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+use crate::extension::StackExtension;
+use crate::{BDAddr, Callbacks, Message, MessageSender, RPCProxy};
+
+/// HCI opcodes of the LE test commands, as accepted by `BluetoothInterface::le_test_mode`.
+const HCI_LE_RECEIVER_TEST: u16 = 0x201D;
+const HCI_LE_TRANSMITTER_TEST: u16 = 0x201E;
+const HCI_LE_TEST_END: u16 = 0x201F;
+
+/// Highest RF channel of the LE test commands, i.e. 2480 MHz.
+const LE_TEST_CHANNEL_MAX: u32 = 0x27;
+
+/// Transports accepted by `IBluetoothQA::read_max_tx_power`.
+const BT_TRANSPORT_BR_EDR: i32 = 1;
+const BT_TRANSPORT_LE: i32 = 2;
+
+/// Defines the QA API.
+pub trait IBluetoothQA {
+    /// Adds a callback from a client who wishes to get the results of the test commands.
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothQACallback + Send>);
+
+    /// Enables or disables the Device Under Test mode of the controller.
+    fn set_dut_mode(&mut self, enable: bool) -> bool;
+
+    /// Returns whether the Device Under Test mode is enabled.
+    fn get_dut_mode(&self) -> bool;
+
+    /// Sends a vendor specific test command. Only allowed in Device Under Test mode.
+    ///
+    /// The command complete event is reported with `on_dut_command_complete`.
+    fn send_dut_command(&mut self, opcode: u32, params: Vec<u8>) -> bool;
+
+    /// Starts the LE transmitter test on an RF channel (0 to 39), sending packets of
+    /// `data_length` bytes with the given payload type.
+    fn le_transmitter_test(&mut self, channel: u32, data_length: u32, payload: u32) -> bool;
+
+    /// Starts the LE receiver test on an RF channel (0 to 39).
+    fn le_receiver_test(&mut self, channel: u32) -> bool;
+
+    /// Ends the running LE test. The number of received packets is reported with
+    /// `on_le_test_done`.
+    fn le_test_end(&mut self) -> bool;
+
+    /// Reads the maximum TX power of the connection to a device, on BR/EDR (1) or LE (2). On LE,
+    /// this is the maximum the controller supports, which is the same for every connection.
+    ///
+    /// The result is reported with `on_max_tx_power_read`.
+    fn read_max_tx_power(&mut self, device: String, transport: i32) -> bool;
+}
+
+/// The interface for QA callbacks registered through `IBluetoothQA::register_callback`.
+pub trait IBluetoothQACallback: RPCProxy {
+    /// Reports the command complete event of a command sent with `send_dut_command`.
+    fn on_dut_command_complete(&self, opcode: u32, params: Vec<u8>);
+
+    /// Reports the end of an LE test, with the number of packets received by a receiver test.
+    fn on_le_test_done(&self, status: i32, num_packets: u32);
+
+    /// Reports the maximum TX power in dBm of a connection. `status` is 0 on success.
+    fn on_max_tx_power_read(&self, addr: String, status: i32, tx_power: i32);
+}
+
+/// Messages of the QA API, posted to the stack main dispatch loop as `Message::Extension`.
+enum QaMessage {
+    DutModeRecv(u16, Vec<u8>),
+    LeTestMode(i32, u16),
+    ReadMaxTxPower(RustRawAddress, i32, i32),
+    CallbackDisconnected(u32),
+}
+
+/// Posted by `BluetoothQA::callbacks` when the client of a callback disconnects.
+fn callback_disconnected_message(id: u32) -> Message {
+    Message::Extension(Box::new(QaMessage::CallbackDisconnected(id)))
+}
+
+fn post_message(tx: &MessageSender, message: QaMessage) {
+    let tx = tx.clone();
+    topstack::get_runtime().spawn(async move {
+        let _ = tx.send(Message::Extension(Box::new(message))).await;
+    });
+}
+
+/// Returns the Bluetooth interface callbacks handled by the QA API, every other one is ignored.
+pub fn btif_qa_callbacks(tx: MessageSender) -> BluetoothCallbacks {
+    let dut_tx = tx.clone();
+
+    BluetoothCallbacks {
+        dut_mode_recv: Box::new(move |opcode, buf| {
+            post_message(&dut_tx, QaMessage::DutModeRecv(opcode, buf));
+        }),
+        le_test_mode: Box::new(move |status, num_packets| {
+            post_message(&tx, QaMessage::LeTestMode(status, num_packets));
+        }),
+        ..Default::default()
+    }
+}
+
+/// Implementation of the QA API.
+pub struct BluetoothQA {
+    intf: Arc<Mutex<BluetoothInterface>>,
+    qa: Qa,
+    callbacks: Callbacks<dyn IBluetoothQACallback + Send>,
+    dut_mode: bool,
+}
+
+impl BluetoothQA {
+    /// Constructs the IBluetoothQA implementation.
+    pub fn new(tx: MessageSender, intf: Arc<Mutex<BluetoothInterface>>) -> BluetoothQA {
+        let callbacks = Callbacks::new(tx.clone(), callback_disconnected_message);
+        let qa_callbacks = QaCallbacks {
+            read_max_tx_power: Box::new(move |addr, status, tx_power| {
+                post_message(&tx, QaMessage::ReadMaxTxPower(addr, status, tx_power));
+            }),
+        };
+
+        BluetoothQA { intf, qa: Qa::new(Arc::new(qa_callbacks)), callbacks, dut_mode: false }
+    }
+
+    fn handle_message(&mut self, message: QaMessage) {
+        match message {
+            QaMessage::DutModeRecv(opcode, params) => {
+                for callback in self.callbacks.iter() {
+                    callback.on_dut_command_complete(opcode as u32, params.clone());
+                }
+            }
+            QaMessage::LeTestMode(status, num_packets) => {
+                for callback in self.callbacks.iter() {
+                    callback.on_le_test_done(status, num_packets as u32);
+                }
+            }
+            QaMessage::ReadMaxTxPower(addr, status, tx_power) => {
+                let addr = BDAddr::from_raw_address(&addr).to_string();
+                for callback in self.callbacks.iter() {
+                    callback.on_max_tx_power_read(addr.clone(), status, tx_power);
+                }
+            }
+            QaMessage::CallbackDisconnected(id) => {
+                self.callbacks.remove_callback(id);
+            }
+        }
+    }
+
+    fn le_test_mode(&mut self, opcode: u16, params: &[u8]) -> bool {
+        self.intf.lock().unwrap().le_test_mode(opcode, params) == 0
+    }
+}
+
+impl IBluetoothQA for BluetoothQA {
+    fn register_callback(&mut self, callback: Box<dyn IBluetoothQACallback + Send>) {
+        self.callbacks.add_callback(callback);
+    }
+
+    fn set_dut_mode(&mut self, enable: bool) -> bool {
+        if self.intf.lock().unwrap().dut_mode_configure(enable) != 0 {
+            return false;
+        }
+
+        self.dut_mode = enable;
+        true
+    }
+
+    fn get_dut_mode(&self) -> bool {
+        self.dut_mode
+    }
+
+    fn send_dut_command(&mut self, opcode: u32, params: Vec<u8>) -> bool {
+        if !self.dut_mode || opcode > u16::MAX as u32 {
+            return false;
+        }
+
+        self.intf.lock().unwrap().dut_mode_send(opcode as u16, &params) == 0
+    }
+
+    fn le_transmitter_test(&mut self, channel: u32, data_length: u32, payload: u32) -> bool {
+        if channel > LE_TEST_CHANNEL_MAX || data_length > u8::MAX as u32 || payload > u8::MAX as u32
+        {
+            return false;
+        }
+
+        self.le_test_mode(
+            HCI_LE_TRANSMITTER_TEST,
+            &[channel as u8, data_length as u8, payload as u8],
+        )
+    }
+
+    fn le_receiver_test(&mut self, channel: u32) -> bool {
+        if channel > LE_TEST_CHANNEL_MAX {
+            return false;
+        }
+
+        self.le_test_mode(HCI_LE_RECEIVER_TEST, &[channel as u8])
+    }
+
+    fn le_test_end(&mut self) -> bool {
+        self.le_test_mode(HCI_LE_TEST_END, &[])
+    }
+
+    fn read_max_tx_power(&mut self, device: String, transport: i32) -> bool {
+        if transport != BT_TRANSPORT_BR_EDR && transport != BT_TRANSPORT_LE {
+            return false;
+        }
+
+        match BDAddr::from_string(&device) {
+            Some(addr) => self.qa.read_max_tx_power(&addr.to_raw_address(), transport) == 0,
+            None => false,
+        }
+    }
+}
+
+/// Plugs the QA API into the stack main dispatch loop.
+pub struct BluetoothQAExtension {
+    qa: Arc<Mutex<BluetoothQA>>,
+}
+
+impl BluetoothQAExtension {
+    pub fn new(qa: Arc<Mutex<BluetoothQA>>) -> BluetoothQAExtension {
+        BluetoothQAExtension { qa }
+    }
+}
+
+impl StackExtension for BluetoothQAExtension {
+    fn name(&self) -> String {
+        String::from("qa")
+    }
+
+    fn init(&mut self, _tx: MessageSender) {}
+
+    fn dispatch(&mut self, message: Box<dyn Any + Send>) -> Result<(), Box<dyn Any + Send>> {
+        let message = message.downcast::<QaMessage>()?;
+        self.qa.lock().unwrap().handle_message(*message);
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        let mut qa = self.qa.lock().unwrap();
+        if qa.dut_mode {
+            qa.set_dut_mode(false);
+        }
+    }
+}
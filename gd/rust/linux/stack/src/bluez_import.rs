@@ -0,0 +1,467 @@
+//! Import of the bonds made with BlueZ, so that users migrating from it don't have to pair their
+//! devices again.
+//!
+//! BlueZ keeps a directory per adapter in `BLUEZ_STORAGE_DIR`, named after the address of the
+//! adapter, with an `info` file per remote device in a directory named after the address of the
+//! device. The importer converts the bonded devices of an adapter to the config file of the
+//! native stack, which it only writes if the file doesn't exist yet, i.e. on the first run of the
+//! daemon.
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use crate::BDAddr;
+
+/// Where BlueZ stores its adapters and devices.
+pub const BLUEZ_STORAGE_DIR: &str = "/var/lib/bluetooth";
+
+/// The config file of the native stack, see `ParameterProvider::ConfigFilePath`.
+pub const NATIVE_CONFIG_PATH: &str = "/etc/bluetooth/bt_config.conf";
+
+/// Device types of the native config, as in `bt_device_type_t`.
+const DEVICE_TYPE_BREDR: u32 = 0x1;
+const DEVICE_TYPE_BLE: u32 = 0x2;
+
+/// Security levels of the LE keys of the native config, as in `SMP_SEC_*`.
+const SMP_SEC_UNAUTHENTICATE: u8 = 1 << 0;
+const SMP_SEC_AUTHENTICATED: u8 = 1 << 2;
+
+/// Sections of an INI file, e.g. a BlueZ `info` file, by name.
+type IniSections = HashMap<String, HashMap<String, String>>;
+
+fn parse_ini(contents: &str) -> IniSections {
+    let mut sections = IniSections::new();
+    let mut section = String::new();
+
+    for line in contents.lines().map(|line| line.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].to_string();
+        } else {
+            let mut tokens = line.splitn(2, '=');
+            if let (Some(key), Some(value)) = (tokens.next(), tokens.next()) {
+                sections
+                    .entry(section.clone())
+                    .or_default()
+                    .insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+    }
+
+    sections
+}
+
+/// Parses the hex string of a key, e.g. `LinkKey.Key`. BlueZ stores the bytes of the keys in
+/// the order of the HCI events, as does the native stack.
+fn parse_key(hex: &str) -> Option<Vec<u8>> {
+    // Slicing a multibyte character would panic.
+    if hex.len() != 32 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Converts the `info` file of a BlueZ device to the properties of its section in the native
+/// config. Returns None if the device isn't bonded.
+fn convert_device(addr: &BDAddr, info: &IniSections) -> Option<Vec<(&'static str, String)>> {
+    let general = info.get("General");
+    let general_value = |key: &str| general.and_then(|general| general.get(key));
+    let mut properties = vec![];
+    let mut device_type = 0;
+
+    if let Some(link_key) = info.get("LinkKey") {
+        let key = parse_key(link_key.get("Key")?)?;
+        properties.push(("LinkKey", to_hex(&key)));
+        properties.push(("LinkKeyType", link_key.get("Type")?.clone()));
+        properties.push(("PinKeyLength", link_key.get("PINLength")?.clone()));
+        device_type |= DEVICE_TYPE_BREDR;
+    }
+
+    // The address type is "public" or "static", the latter for the random static addresses.
+    let addr_type: u8 = match general_value("AddressType").map(|t| t.as_str()) {
+        Some("static") => 1,
+        _ => 0,
+    };
+
+    // The key distributed by the device, used while the adapter is central.
+    if let Some(ltk) = info.get("LongTermKey") {
+        let key = parse_key(ltk.get("Key")?)?;
+        let authenticated: u8 = ltk.get("Authenticated")?.parse().ok()?;
+        let key_size: u8 = ltk.get("EncSize")?.parse().ok()?;
+        let ediv: u16 = ltk.get("EDiv")?.parse().ok()?;
+        let rand: u64 = ltk.get("Rand")?.parse().ok()?;
+
+        // Odd values are authenticated keys, values from 2 are Secure Connections keys.
+        let sec_level =
+            if authenticated & 1 != 0 { SMP_SEC_AUTHENTICATED } else { SMP_SEC_UNAUTHENTICATE };
+
+        // Layout of `tBTM_LE_PENC_KEYS`.
+        let mut penc = key.clone();
+        penc.extend_from_slice(&rand.to_le_bytes());
+        penc.extend_from_slice(&ediv.to_le_bytes());
+        penc.push(sec_level);
+        penc.push(key_size);
+        properties.push(("LE_KEY_PENC", to_hex(&penc)));
+
+        // A Secure Connections key is used in both roles, which the native stack stores as a
+        // local key too, with the layout of `tBTM_LE_LENC_KEYS`.
+        if authenticated >= 2 {
+            let mut lenc = key;
+            lenc.extend_from_slice(&0u16.to_le_bytes());
+            lenc.push(key_size);
+            lenc.push(sec_level);
+            properties.push(("LE_KEY_LENC", to_hex(&lenc)));
+        }
+
+        device_type |= DEVICE_TYPE_BLE;
+    }
+
+    if let Some(irk) = info.get("IdentityResolvingKey") {
+        // Layout of `tBTM_LE_PID_KEYS`, the device address being its identity address.
+        let mut pid = parse_key(irk.get("Key")?)?;
+        pid.push(addr_type);
+        pid.extend_from_slice(&addr.val);
+        properties.push(("LE_KEY_PID", to_hex(&pid)));
+        device_type |= DEVICE_TYPE_BLE;
+    }
+
+    if device_type == 0 {
+        return None;
+    }
+
+    if device_type & DEVICE_TYPE_BLE != 0 {
+        properties.push(("AddrType", addr_type.to_string()));
+    }
+    properties.push(("DevType", device_type.to_string()));
+
+    if let Some(name) = general_value("Name") {
+        properties.push(("Name", name.clone()));
+    }
+
+    let class = general_value("Class")
+        .and_then(|class| u32::from_str_radix(class.trim_start_matches("0x"), 16).ok());
+    if let Some(class) = class {
+        properties.push(("DevClass", class.to_string()));
+    }
+
+    // BlueZ lists the UUIDs separated by ';', the native stack by spaces.
+    if let Some(services) = general_value("Services") {
+        let uuids: Vec<&str> = services.split(';').filter(|uuid| !uuid.is_empty()).collect();
+        if !uuids.is_empty() {
+            properties.push(("Service", uuids.join(" ")));
+        }
+    }
+
+    Some(properties)
+}
+
+/// Returns the native config sections of the bonded devices of a BlueZ adapter directory, by
+/// device address.
+fn read_adapter(dir: &Path) -> Vec<(BDAddr, Vec<(&'static str, String)>)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut devices = vec![];
+    for entry in entries.flatten() {
+        let addr = match entry.file_name().to_str().and_then(BDAddr::from_string) {
+            Some(addr) => addr,
+            None => continue,
+        };
+
+        let contents = match fs::read_to_string(entry.path().join("info")) {
+            Ok(contents) => contents,
+            Err(_) => continue,
+        };
+
+        // BlueZ also keeps the devices that were only discovered.
+        if let Some(properties) = convert_device(&addr, &parse_ini(&contents)) {
+            devices.push((addr, properties));
+        }
+    }
+
+    devices.sort_by_key(|(addr, _)| addr.to_string());
+    devices
+}
+
+/// Imports the bonds of a BlueZ adapter to the native config at `config_path`, if there is no
+/// config yet. Returns the number of imported devices.
+///
+/// The bonds are imported from the adapter with the most of them, since the keys are only valid
+/// with the adapter they were made with and the native stack serves a single adapter.
+pub fn import_on_first_run(bluez_dir: &str, config_path: &str) -> usize {
+    if Path::new(config_path).exists() {
+        return 0;
+    }
+
+    let adapters = match fs::read_dir(bluez_dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    let imported = adapters
+        .flatten()
+        .filter_map(|entry| {
+            let addr = entry.file_name().to_str().and_then(BDAddr::from_string)?;
+            Some((addr, read_adapter(&entry.path())))
+        })
+        .max_by_key(|(_, devices)| devices.len());
+
+    let (adapter, devices) = match imported {
+        Some((adapter, devices)) if !devices.is_empty() => (adapter, devices),
+        _ => return 0,
+    };
+
+    // The native stack drops a config without an adapter section.
+    let mut config = format!("[Adapter]\nAddress = {}\n", adapter.to_string().to_lowercase());
+    for (addr, properties) in &devices {
+        config += &format!("\n[{}]\n", addr.to_string().to_lowercase());
+        for (key, value) in properties {
+            config += &format!("{} = {}\n", key, value);
+        }
+    }
+
+    // The config holds the keys of the bonds, so only the daemon may read it.
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(config_path)
+        .and_then(|mut file| file.write_all(config.as_bytes()));
+    if let Err(e) = written {
+        eprintln!("Failed to write the bonds imported from BlueZ to {}: {}", config_path, e);
+        return 0;
+    }
+
+    println!("Imported {} bonds of adapter {} from BlueZ", devices.len(), adapter.to_string());
+    devices.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    const ADAPTER: &str = "00:1A:7D:DA:71:13";
+    const OTHER_ADAPTER: &str = "5C:F3:70:8B:12:34";
+    const HEADPHONES: &str = "00:1B:66:A0:B1:C2";
+    const MOUSE: &str = "C0:11:22:33:44:55";
+    const PHONE: &str = "F4:60:E2:01:02:03";
+
+    /// A BR/EDR device, as BlueZ stores it.
+    const HEADPHONES_INFO: &str = "\
+[General]
+Name=Headphones
+Class=0x240404
+SupportedTechnologies=BR/EDR;
+Trusted=true
+Blocked=false
+Services=0000110b-0000-1000-8000-00805f9b34fb;0000110e-0000-1000-8000-00805f9b34fb;
+
+[LinkKey]
+Key=00112233445566778899AABBCCDDEEFF
+Type=4
+PINLength=0
+";
+
+    /// An LE device with a random static address, bonded with Secure Connections.
+    const MOUSE_INFO: &str = "\
+[General]
+Name=Mouse
+AddressType=static
+SupportedTechnologies=LE;
+Trusted=true
+Services=00001800-0000-1000-8000-00805f9b34fb;00001812-0000-1000-8000-00805f9b34fb;
+
+[IdentityResolvingKey]
+Key=0F0E0D0C0B0A09080706050403020100
+
+[LongTermKey]
+Key=A0A1A2A3A4A5A6A7A8A9AAABACADAEAF
+Authenticated=2
+EncSize=16
+EDiv=0
+Rand=0
+";
+
+    /// A device that was discovered but never bonded.
+    const PHONE_INFO: &str = "\
+[General]
+Name=Phone
+Class=0x5a020c
+SupportedTechnologies=BR/EDR;
+Trusted=false
+";
+
+    /// The native config of the bonds of `HEADPHONES_INFO` and `MOUSE_INFO`.
+    const EXPECTED_CONFIG: &str = "\
+[Adapter]
+Address = 00:1a:7d:da:71:13
+
+[00:1b:66:a0:b1:c2]
+LinkKey = 00112233445566778899aabbccddeeff
+LinkKeyType = 4
+PinKeyLength = 0
+DevType = 1
+Name = Headphones
+DevClass = 2360324
+Service = 0000110b-0000-1000-8000-00805f9b34fb 0000110e-0000-1000-8000-00805f9b34fb
+
+[c0:11:22:33:44:55]
+LE_KEY_PENC = a0a1a2a3a4a5a6a7a8a9aaabacadaeaf000000000000000000000110
+LE_KEY_LENC = a0a1a2a3a4a5a6a7a8a9aaabacadaeaf00001001
+LE_KEY_PID = 0f0e0d0c0b0a0908070605040302010001c01122334455
+AddrType = 1
+DevType = 2
+Name = Mouse
+Service = 00001800-0000-1000-8000-00805f9b34fb 00001812-0000-1000-8000-00805f9b34fb
+";
+
+    /// A directory of its own for each test, removed when dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> TestDir {
+            let name = format!("bluez_import_{}_{}", name, std::process::id());
+            let dir = std::env::temp_dir().join(name);
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("bluez")).unwrap();
+            TestDir(dir)
+        }
+
+        fn bluez_dir(&self) -> String {
+            self.0.join("bluez").to_str().unwrap().to_string()
+        }
+
+        fn config_path(&self) -> String {
+            self.0.join("bt_config.conf").to_str().unwrap().to_string()
+        }
+
+        fn add_device(&self, adapter: &str, device: &str, info: &str) {
+            let dir = self.0.join("bluez").join(adapter).join(device);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("info"), info).unwrap();
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn convert(addr: &str, info: &str) -> Option<Vec<(&'static str, String)>> {
+        convert_device(&BDAddr::from_string(addr).unwrap(), &parse_ini(info))
+    }
+
+    #[test]
+    fn parse_info_file() {
+        let sections = parse_ini("# Comment\n[General]\n Name = Key=Board \n\n[LinkKey]\nType=4\n");
+
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections["General"]["Name"], "Key=Board");
+        assert_eq!(sections["LinkKey"]["Type"], "4");
+    }
+
+    #[test]
+    fn parse_keys() {
+        let key = parse_key("00112233445566778899AABBCCDDEEFF").unwrap();
+        assert_eq!(to_hex(&key), "00112233445566778899aabbccddeeff");
+
+        assert_eq!(parse_key("00112233445566778899AABBCCDDEE"), None);
+        assert_eq!(parse_key("00112233445566778899AABBCCDDEEFG"), None);
+        assert_eq!(parse_key("00112233445566778899AABBCCDDEéF"), None);
+    }
+
+    #[test]
+    fn convert_legacy_le_key() {
+        let info = "\
+[General]
+Name=Keyboard
+AddressType=public
+
+[LongTermKey]
+Key=A0A1A2A3A4A5A6A7A8A9AAABACADAEAF
+Authenticated=1
+EncSize=7
+EDiv=4660
+Rand=1
+";
+
+        // A legacy key is only used while the adapter is central.
+        assert_eq!(
+            convert("00:11:22:33:44:55", info).unwrap(),
+            vec![
+                ("LE_KEY_PENC", "a0a1a2a3a4a5a6a7a8a9aaabacadaeaf010000000000000034120407".into()),
+                ("AddrType", "0".into()),
+                ("DevType", "2".into()),
+                ("Name", "Keyboard".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_unbonded_and_malformed_devices() {
+        assert_eq!(convert(PHONE, PHONE_INFO), None);
+
+        let missing_type = HEADPHONES_INFO.replace("Type=4\n", "");
+        assert_eq!(convert(HEADPHONES, &missing_type), None);
+
+        let short_key = MOUSE_INFO.replace("Key=A0A1", "Key=A0");
+        assert_eq!(convert(MOUSE, &short_key), None);
+    }
+
+    #[test]
+    fn import_adapter_with_most_bonds() {
+        let dir = TestDir::new("import");
+        dir.add_device(ADAPTER, HEADPHONES, HEADPHONES_INFO);
+        dir.add_device(ADAPTER, MOUSE, MOUSE_INFO);
+        dir.add_device(ADAPTER, PHONE, PHONE_INFO);
+        dir.add_device(OTHER_ADAPTER, HEADPHONES, HEADPHONES_INFO);
+        // BlueZ keeps more than devices in the directory of an adapter.
+        dir.add_device(ADAPTER, "cache", PHONE_INFO);
+        fs::write(dir.0.join("bluez").join(ADAPTER).join("settings"), "[General]\n").unwrap();
+
+        assert_eq!(import_on_first_run(&dir.bluez_dir(), &dir.config_path()), 2);
+        assert_eq!(fs::read_to_string(dir.config_path()).unwrap(), EXPECTED_CONFIG);
+
+        let mode = fs::metadata(dir.config_path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn import_only_on_first_run() {
+        let dir = TestDir::new("second_run");
+        dir.add_device(ADAPTER, HEADPHONES, HEADPHONES_INFO);
+        fs::write(dir.config_path(), "[Adapter]\n").unwrap();
+
+        assert_eq!(import_on_first_run(&dir.bluez_dir(), &dir.config_path()), 0);
+        assert_eq!(fs::read_to_string(dir.config_path()).unwrap(), "[Adapter]\n");
+    }
+
+    #[test]
+    fn import_without_bonds() {
+        let dir = TestDir::new("no_bonds");
+        dir.add_device(ADAPTER, PHONE, PHONE_INFO);
+
+        assert_eq!(import_on_first_run(&dir.bluez_dir(), &dir.config_path()), 0);
+        assert!(!Path::new(&dir.config_path()).exists());
+
+        let missing = dir.0.join("missing").to_str().unwrap().to_string();
+        assert_eq!(import_on_first_run(&missing, &dir.config_path()), 0);
+    }
+}
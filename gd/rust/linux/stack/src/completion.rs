@@ -0,0 +1,147 @@
+//! Futures of the operations of the APIs that complete through a callback.
+//!
+//! Many requests are answered by a callback, e.g. `IBluetoothGatt::register_client` by
+//! `IBluetoothGattCallback::on_client_registered`. Components of the daemon that chain such
+//! requests, e.g. connecting a profile once a bond is made, can instead await a `Completion` of
+//! the operation, which the module resolves when it reports the outcome to its clients.
+//!
+//! The completions are resolved by the dispatch loop, so the lock of the module must be released
+//! before awaiting one, e.g.
+//!
+//! ```ignore
+//! let bond = bluetooth.lock().unwrap().create_bond_with_completion(device, 0)?;
+//! let (status, state) = bond.wait(Duration::from_secs(30)).await?;
+//! ```
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+/// Why a `Completion` ended without the outcome of its operation.
+#[derive(Debug, PartialEq)]
+pub enum CompletionError {
+    /// The operation didn't complete in time. It may still complete later.
+    Timeout,
+    /// The module gave up on the operation, e.g. because the adapter turned off.
+    Abandoned,
+}
+
+/// The outcome of an operation, once it completes.
+pub struct Completion<T> {
+    rx: oneshot::Receiver<T>,
+}
+
+impl<T> Completion<T> {
+    /// Waits for up to `timeout` for the operation to complete.
+    pub async fn wait(self, timeout: Duration) -> Result<T, CompletionError> {
+        match tokio::time::timeout(timeout, self.rx).await {
+            Ok(Ok(outcome)) => Ok(outcome),
+            Ok(Err(_)) => Err(CompletionError::Abandoned),
+            Err(_) => Err(CompletionError::Timeout),
+        }
+    }
+}
+
+/// The completions of the operations in progress, keyed by what tells the operations apart, e.g.
+/// the address of the device being bonded.
+pub(crate) struct Completers<K, T> {
+    waiting: HashMap<K, Vec<oneshot::Sender<T>>>,
+}
+
+impl<K: Eq + Hash, T: Clone> Completers<K, T> {
+    pub(crate) fn new() -> Completers<K, T> {
+        Completers { waiting: HashMap::new() }
+    }
+
+    /// Returns a completion of the operation identified by `key`. Several completions of the same
+    /// operation are resolved together.
+    pub(crate) fn add(&mut self, key: K) -> Completion<T> {
+        let (tx, rx) = oneshot::channel();
+
+        // Drops the completions that are no longer awaited, e.g. after a timeout.
+        let senders = self.waiting.entry(key).or_default();
+        senders.retain(|sender| !sender.is_closed());
+        senders.push(tx);
+
+        Completion { rx }
+    }
+
+    /// Resolves the completions of the operation identified by `key`, if there are any.
+    pub(crate) fn complete(&mut self, key: &K, outcome: T) {
+        for sender in self.waiting.remove(key).unwrap_or_default() {
+            let _ = sender.send(outcome.clone());
+        }
+    }
+
+    /// Abandons the completions of the operation identified by `key`, e.g. because the client
+    /// that made it went away.
+    pub(crate) fn remove(&mut self, key: &K) {
+        self.waiting.remove(key);
+    }
+
+    /// Abandons the completions of every operation.
+    pub(crate) fn clear(&mut self) {
+        self.waiting.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::future::Future;
+
+    fn run<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    const WAIT: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn completions_resolved_together() {
+        let mut completers = Completers::new();
+        let first = completers.add(1);
+        let second = completers.add(1);
+        let other = completers.add(2);
+
+        completers.complete(&1, "done");
+        assert_eq!(run(first.wait(WAIT)), Ok("done"));
+        assert_eq!(run(second.wait(WAIT)), Ok("done"));
+
+        // Only the operation that completed is resolved.
+        assert_eq!(run(other.wait(Duration::from_millis(10))), Err(CompletionError::Timeout));
+    }
+
+    #[test]
+    fn timed_out_completions_dropped() {
+        let mut completers = Completers::new();
+        let timed_out = completers.add(1);
+        assert_eq!(run(timed_out.wait(Duration::from_millis(10))), Err(CompletionError::Timeout));
+
+        let awaited = completers.add(1);
+        assert_eq!(completers.waiting.get(&1).map(|senders| senders.len()), Some(1));
+        completers.complete(&1, 5);
+        assert_eq!(run(awaited.wait(WAIT)), Ok(5));
+        assert!(completers.waiting.is_empty());
+    }
+
+    #[test]
+    fn completions_abandoned() {
+        let mut completers: Completers<u32, ()> = Completers::new();
+        let removed = completers.add(1);
+        let kept = completers.add(2);
+        completers.remove(&1);
+        assert_eq!(run(removed.wait(WAIT)), Err(CompletionError::Abandoned));
+
+        let cleared = completers.add(3);
+        completers.clear();
+        assert_eq!(run(kept.wait(WAIT)), Err(CompletionError::Abandoned));
+        assert_eq!(run(cleared.wait(WAIT)), Err(CompletionError::Abandoned));
+    }
+}
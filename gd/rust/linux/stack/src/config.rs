@@ -0,0 +1,268 @@
+//! Runtime configuration of the stack (feature flags).
+
+use std::collections::HashMap;
+use std::fs;
+
+/// A feature that can be turned on or off without rebuilding the stack, e.g. while it is
+/// experimental.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Offloads the filters of the advertisement monitors to the controller when it supports it.
+    /// Changes apply to the monitors registered afterwards.
+    ScanFilterOffload,
+    /// Robust caching of the GATT databases of remote devices.
+    GattRobustCaching,
+    /// LE scanning implemented by the GD stack.
+    GdScanning,
+    /// `IBluetoothDebug::send_vendor_command`, in builds with the `vendor_commands` feature. It
+    /// can only be turned on in the flag file.
+    VendorCommands,
+    /// Aborts the daemon when a dispatch handler is stuck, see `Watchdog`, instead of only
+    /// logging it. Applied when the daemon starts.
+    WatchdogAbort,
+    /// Records the messages dispatched by the stack to a trace, see `MessageRecorder`. Applied
+    /// when the daemon starts. The trace holds the keys of the pairings, so it can only be turned
+    /// on in the flag file.
+    MessageTrace,
+    /// Serves the test service of `IBluetoothDebug::start_throughput_test` and runs the tests.
+    /// Applied when the daemon starts.
+    ThroughputTest,
+    /// Logs the D-Bus method calls of the interfaces exported with the `trace` option, see
+    /// `dbus_projection::set_method_tracer`, and records their durations and errors in the
+    /// metrics. Applied when the daemon starts.
+    MethodTrace,
+    /// The LE audio API, `IBluetoothLeAudio`, in builds with the `le_audio` feature, which builds
+    /// the LE audio shim of the native stack. Applied when the daemon starts.
+    LeAudio,
+}
+
+const FEATURES: [Feature; 9] = [
+    Feature::ScanFilterOffload,
+    Feature::GattRobustCaching,
+    Feature::GdScanning,
+    Feature::VendorCommands,
+    Feature::WatchdogAbort,
+    Feature::MessageTrace,
+    Feature::ThroughputTest,
+    Feature::MethodTrace,
+    Feature::LeAudio,
+];
+
+impl Feature {
+    /// Returns the name of the feature in the flag file and the debug API.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::ScanFilterOffload => "scan_filter_offload",
+            Feature::GattRobustCaching => "gatt_robust_caching",
+            Feature::GdScanning => "gd_scanning",
+            Feature::VendorCommands => "vendor_commands",
+            Feature::WatchdogAbort => "watchdog_abort",
+            Feature::MessageTrace => "message_trace",
+            Feature::ThroughputTest => "throughput_test",
+            Feature::MethodTrace => "method_trace",
+            Feature::LeAudio => "le_audio",
+        }
+    }
+
+    /// Returns the feature named `name`, if any.
+    pub fn from_name(name: &str) -> Option<Feature> {
+        FEATURES.iter().find(|feature| feature.name() == name).copied()
+    }
+
+    fn default_enabled(&self) -> bool {
+        match self {
+            Feature::ScanFilterOffload => true,
+            Feature::GattRobustCaching
+            | Feature::GdScanning
+            | Feature::VendorCommands
+            | Feature::WatchdogAbort
+            | Feature::MessageTrace
+            | Feature::ThroughputTest
+            | Feature::MethodTrace
+            | Feature::LeAudio => false,
+        }
+    }
+
+    /// Returns whether the feature can be overridden through the debug API. Features that open
+    /// the controller or the keys of the pairings to the clients of the daemon can only be turned
+    /// on in the flag file.
+    pub fn is_overridable(&self) -> bool {
+        match self {
+            Feature::VendorCommands | Feature::MessageTrace => false,
+            _ => true,
+        }
+    }
+
+    /// Returns the init flag that turns the feature on in the native stack, for the features
+    /// implemented there.
+    fn init_flag(&self) -> Option<&'static str> {
+        match self {
+            Feature::ScanFilterOffload
+            | Feature::VendorCommands
+            | Feature::WatchdogAbort
+            | Feature::MessageTrace
+            | Feature::ThroughputTest
+            | Feature::MethodTrace
+            | Feature::LeAudio => None,
+            Feature::GattRobustCaching => Some("INIT_gatt_robust_caching"),
+            Feature::GdScanning => Some("INIT_gd_scanning"),
+        }
+    }
+}
+
+/// Values of the features, shared by the modules of the stack as an `Arc<Mutex<FeatureFlags>>`.
+///
+/// The values are read from a flag file when the daemon starts, and can be overridden until it
+/// stops through `IBluetoothDebug::set_feature_flag`. Features of the native stack are only
+/// applied when it is initialized, so overriding them has no effect until the daemon restarts.
+pub struct FeatureFlags {
+    values: HashMap<Feature, bool>,
+    overrides: HashMap<Feature, bool>,
+}
+
+impl Default for FeatureFlags {
+    /// Constructs the flags with the default value of each feature.
+    fn default() -> Self {
+        FeatureFlags {
+            values: FEATURES.iter().map(|f| (*f, f.default_enabled())).collect(),
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl FeatureFlags {
+    /// Reads the flags from the file at `path`.
+    ///
+    /// The file is a flat TOML document of boolean keys named after the features, e.g.
+    /// `gd_scanning = true`. Section headers are skipped, the keys of every section are read.
+    /// Features that are not in the file keep their default value, as do all of them if the file
+    /// can't be read or parsed.
+    pub fn load(path: &str) -> FeatureFlags {
+        let mut flags = FeatureFlags::default();
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                eprintln!("Using the default feature flags, {} is not readable: {}", path, e);
+                return flags;
+            }
+        };
+
+        match FeatureFlags::parse(&contents) {
+            Ok(values) => flags.values.extend(values),
+            Err(e) => eprintln!("Using the default feature flags, {} is malformed: {}", path, e),
+        }
+
+        flags
+    }
+
+    fn parse(contents: &str) -> Result<HashMap<Feature, bool>, String> {
+        let mut values = HashMap::new();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            }
+            .trim();
+            if line.is_empty() || (line.starts_with('[') && line.ends_with(']')) {
+                continue;
+            }
+
+            let mut pair = line.splitn(2, '=');
+            let (key, value) = match (pair.next(), pair.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => return Err(format!("line {} is not a key = value pair", number + 1)),
+            };
+
+            let value = match value {
+                "true" => true,
+                "false" => false,
+                _ => return Err(format!("line {}: {} is not a boolean", number + 1, value)),
+            };
+
+            // Flags of other versions of the daemon are tolerated.
+            match Feature::from_name(key) {
+                Some(feature) => {
+                    values.insert(feature, value);
+                }
+                None => eprintln!("Ignoring unknown feature flag {}", key),
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// Returns whether a feature is on.
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        self.overrides
+            .get(&feature)
+            .or(self.values.get(&feature))
+            .copied()
+            .unwrap_or(feature.default_enabled())
+    }
+
+    /// Overrides the value of a feature until the daemon stops, or reverts it to the value read
+    /// from the flag file if `enabled` is `None`.
+    pub fn set_override(&mut self, feature: Feature, enabled: Option<bool>) {
+        match enabled {
+            Some(enabled) => self.overrides.insert(feature, enabled),
+            None => self.overrides.remove(&feature),
+        };
+    }
+
+    /// Returns the init flags that pass the features of the native stack to
+    /// `BluetoothInterface::initialize`.
+    pub fn init_flags(&self) -> Vec<String> {
+        FEATURES
+            .iter()
+            .filter_map(|feature| {
+                feature.init_flag().map(|flag| format!("{}={}", flag, self.is_enabled(*feature)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_flags() {
+        let contents =
+            "# Features of the daemon\n\ngd_scanning = true\nwatchdog_abort=false # off\n";
+        let values = FeatureFlags::parse(contents).unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values.get(&Feature::GdScanning), Some(&true));
+        assert_eq!(values.get(&Feature::WatchdogAbort), Some(&false));
+    }
+
+    #[test]
+    fn parse_skips_sections_and_unknown_flags() {
+        let contents = "[features]\nthroughput_test = true\n[ other ]\nno_such_flag = true\n";
+        let values = FeatureFlags::parse(contents).unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values.get(&Feature::ThroughputTest), Some(&true));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_lines() {
+        assert!(FeatureFlags::parse("gd_scanning").is_err());
+        assert!(FeatureFlags::parse("gd_scanning = 1").is_err());
+        assert!(FeatureFlags::parse("[features\ngd_scanning = true").is_err());
+    }
+
+    #[test]
+    fn overrides_and_defaults() {
+        let mut flags = FeatureFlags::default();
+        assert!(flags.is_enabled(Feature::ScanFilterOffload));
+        assert!(!flags.is_enabled(Feature::GdScanning));
+
+        flags.set_override(Feature::GdScanning, Some(true));
+        assert!(flags.is_enabled(Feature::GdScanning));
+        assert!(flags.init_flags().contains(&String::from("INIT_gd_scanning=true")));
+
+        flags.set_override(Feature::GdScanning, None);
+        assert!(!flags.is_enabled(Feature::GdScanning));
+    }
+}
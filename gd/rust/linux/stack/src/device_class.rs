@@ -0,0 +1,129 @@
+//! Classification of remote devices into the categories that UIs pick icons from.
+
+/// Kind of a remote device, derived from its Class of Device on BR/EDR or its Appearance on LE.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq)]
+#[repr(i32)]
+pub enum DeviceCategory {
+    /// The device didn't tell what it is, or is of none of the other kinds.
+    Unknown = 0,
+    Phone,
+    /// Headsets, headphones and earbuds.
+    Headset,
+    Keyboard,
+    /// Mice and other pointing devices.
+    Mouse,
+    Speaker,
+    Watch,
+}
+
+/// Major device classes of the Class of Device, see the Assigned Numbers.
+const COD_MAJOR_PHONE: u32 = 0x02;
+const COD_MAJOR_AUDIO_VIDEO: u32 = 0x04;
+const COD_MAJOR_PERIPHERAL: u32 = 0x05;
+const COD_MAJOR_WEARABLE: u32 = 0x07;
+
+/// Appearance categories, the upper 10 bits of the Appearance.
+const APPEARANCE_PHONE: u16 = 0x001;
+const APPEARANCE_WATCH: u16 = 0x003;
+const APPEARANCE_HID: u16 = 0x00F;
+const APPEARANCE_SPEAKER: u16 = 0x021;
+const APPEARANCE_WEARABLE_AUDIO: u16 = 0x025;
+
+/// Subcategories of `APPEARANCE_HID`, the lower 6 bits of the Appearance.
+const APPEARANCE_HID_KEYBOARD: u16 = 0x01;
+const APPEARANCE_HID_MOUSE: u16 = 0x02;
+
+impl DeviceCategory {
+    /// Classifies a BR/EDR device from its Class of Device, of which only the lower 24 bits are
+    /// significant.
+    pub fn from_class_of_device(cod: u32) -> DeviceCategory {
+        let minor = (cod >> 2) & 0x3F;
+        match (cod >> 8) & 0x1F {
+            COD_MAJOR_PHONE => DeviceCategory::Phone,
+            COD_MAJOR_AUDIO_VIDEO => match minor {
+                // Wearable headset, hands-free and headphones.
+                0x01 | 0x02 | 0x06 => DeviceCategory::Headset,
+                // Loudspeaker, portable audio and HiFi audio.
+                0x05 | 0x07 | 0x0A => DeviceCategory::Speaker,
+                _ => DeviceCategory::Unknown,
+            },
+            // The upper 2 bits of the minor class tell keyboards and pointing devices apart. A
+            // combo device is shown as a keyboard.
+            COD_MAJOR_PERIPHERAL => match minor >> 4 {
+                0x01 | 0x03 => DeviceCategory::Keyboard,
+                0x02 => DeviceCategory::Mouse,
+                _ => DeviceCategory::Unknown,
+            },
+            COD_MAJOR_WEARABLE if minor == 0x01 => DeviceCategory::Watch,
+            _ => DeviceCategory::Unknown,
+        }
+    }
+
+    /// Classifies an LE device from its Appearance.
+    pub fn from_appearance(appearance: u16) -> DeviceCategory {
+        match (appearance >> 6, appearance & 0x3F) {
+            (APPEARANCE_PHONE, _) => DeviceCategory::Phone,
+            (APPEARANCE_WATCH, _) => DeviceCategory::Watch,
+            (APPEARANCE_HID, APPEARANCE_HID_KEYBOARD) => DeviceCategory::Keyboard,
+            (APPEARANCE_HID, APPEARANCE_HID_MOUSE) => DeviceCategory::Mouse,
+            (APPEARANCE_SPEAKER, _) => DeviceCategory::Speaker,
+            (APPEARANCE_WEARABLE_AUDIO, _) => DeviceCategory::Headset,
+            _ => DeviceCategory::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn category_from_class_of_device() {
+        // Smartphone, with service class bits set.
+        assert_eq!(DeviceCategory::from_class_of_device(0x5A020C), DeviceCategory::Phone);
+        // Wearable headset, hands-free and headphones.
+        assert_eq!(DeviceCategory::from_class_of_device(0x0404), DeviceCategory::Headset);
+        assert_eq!(DeviceCategory::from_class_of_device(0x0408), DeviceCategory::Headset);
+        assert_eq!(DeviceCategory::from_class_of_device(0x0418), DeviceCategory::Headset);
+        // Loudspeaker.
+        assert_eq!(DeviceCategory::from_class_of_device(0x0414), DeviceCategory::Speaker);
+        // Keyboard, pointing device and combo.
+        assert_eq!(DeviceCategory::from_class_of_device(0x0540), DeviceCategory::Keyboard);
+        assert_eq!(DeviceCategory::from_class_of_device(0x0580), DeviceCategory::Mouse);
+        assert_eq!(DeviceCategory::from_class_of_device(0x05C0), DeviceCategory::Keyboard);
+        // Wristwatch.
+        assert_eq!(DeviceCategory::from_class_of_device(0x0704), DeviceCategory::Watch);
+    }
+
+    #[test]
+    fn unknown_class_of_device() {
+        assert_eq!(DeviceCategory::from_class_of_device(0), DeviceCategory::Unknown);
+        // VCR, joystick and wearable jacket.
+        assert_eq!(DeviceCategory::from_class_of_device(0x042C), DeviceCategory::Unknown);
+        assert_eq!(DeviceCategory::from_class_of_device(0x0504), DeviceCategory::Unknown);
+        assert_eq!(DeviceCategory::from_class_of_device(0x0708), DeviceCategory::Unknown);
+        // Uncategorized major class.
+        assert_eq!(DeviceCategory::from_class_of_device(0x1F00), DeviceCategory::Unknown);
+    }
+
+    #[test]
+    fn category_from_appearance() {
+        assert_eq!(DeviceCategory::from_appearance(0x0040), DeviceCategory::Phone);
+        // Generic and sports watch.
+        assert_eq!(DeviceCategory::from_appearance(0x00C0), DeviceCategory::Watch);
+        assert_eq!(DeviceCategory::from_appearance(0x00C1), DeviceCategory::Watch);
+        assert_eq!(DeviceCategory::from_appearance(0x03C1), DeviceCategory::Keyboard);
+        assert_eq!(DeviceCategory::from_appearance(0x03C2), DeviceCategory::Mouse);
+        assert_eq!(DeviceCategory::from_appearance(0x0841), DeviceCategory::Speaker);
+        // Earbud.
+        assert_eq!(DeviceCategory::from_appearance(0x0941), DeviceCategory::Headset);
+    }
+
+    #[test]
+    fn unknown_appearance() {
+        assert_eq!(DeviceCategory::from_appearance(0), DeviceCategory::Unknown);
+        // Generic HID and gamepad.
+        assert_eq!(DeviceCategory::from_appearance(0x03C0), DeviceCategory::Unknown);
+        assert_eq!(DeviceCategory::from_appearance(0x03C4), DeviceCategory::Unknown);
+    }
+}
@@ -0,0 +1,107 @@
+//! Per-device work queues of the dispatch loops.
+
+use bt_topshim::topstack;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::task::JoinHandle;
+
+use crate::BDAddr;
+
+/// Work posted to the queue of a device.
+type Work = Box<dyn FnOnce() + Send>;
+
+struct DeviceQueue {
+    tx: UnboundedSender<Work>,
+    task: JoinHandle<()>,
+    /// Work posted and not done yet, decremented by the task once each work returns.
+    pending: Arc<AtomicUsize>,
+}
+
+impl DeviceQueue {
+    fn is_idle(&self) -> bool {
+        self.pending.load(Ordering::Acquire) == 0
+    }
+}
+
+/// Runs the work of each remote device in order, and the work of different devices in parallel,
+/// so that a device that is slow to handle doesn't hold back the events of the others.
+///
+/// Each device gets a queue, drained by a task of its own, when work is posted for it. Queues
+/// with no work left are closed when a new queue is opened and by `wait_idle`, so that a device
+/// seen once doesn't keep a task. Work of different devices still waits on each other for the
+/// locks of the modules it uses.
+pub(crate) struct DeviceQueues {
+    queues: HashMap<BDAddr, DeviceQueue>,
+    /// Devices of the open GATT connections, keyed by connection id, so that the events of a
+    /// connection go to the queue of its device.
+    conn_addrs: HashMap<i32, BDAddr>,
+}
+
+impl DeviceQueues {
+    pub(crate) fn new() -> DeviceQueues {
+        DeviceQueues { queues: HashMap::new(), conn_addrs: HashMap::new() }
+    }
+
+    /// Queues work for a device, to be run once the work already posted for it is done.
+    pub(crate) fn post(&mut self, addr: BDAddr, work: impl FnOnce() + Send + 'static) {
+        if !self.queues.contains_key(&addr) {
+            self.close_idle();
+        }
+
+        let queue = self.queues.entry(addr).or_insert_with(|| {
+            let (tx, mut rx) = unbounded_channel::<Work>();
+            let pending = Arc::new(AtomicUsize::new(0));
+            let task_pending = pending.clone();
+            let task = topstack::get_runtime().spawn(async move {
+                while let Some(work) = rx.recv().await {
+                    work();
+                    task_pending.fetch_sub(1, Ordering::AcqRel);
+                }
+            });
+            DeviceQueue { tx, task, pending }
+        });
+
+        // The task only stops once the sender is dropped.
+        queue.pending.fetch_add(1, Ordering::AcqRel);
+        let _result = queue.tx.send(Box::new(work));
+    }
+
+    /// Closes the queues that have no work left. Their tasks end on their own.
+    fn close_idle(&mut self) {
+        self.queues.retain(|_, queue| !queue.is_idle());
+    }
+
+    /// Records the device of a GATT connection, until `connection_closed`.
+    pub(crate) fn connection_opened(&mut self, conn_id: i32, addr: BDAddr) {
+        self.conn_addrs.insert(conn_id, addr);
+    }
+
+    pub(crate) fn connection_closed(&mut self, conn_id: i32) {
+        self.conn_addrs.remove(&conn_id);
+    }
+
+    /// Returns the device of an open GATT connection.
+    pub(crate) fn conn_addr(&self, conn_id: i32) -> Option<BDAddr> {
+        self.conn_addrs.get(&conn_id).copied()
+    }
+
+    /// Waits for the work already posted to every device, closing the queues. The messages that
+    /// are not about a device wait on this so that they are handled after the device messages
+    /// received before them.
+    pub(crate) async fn wait_idle(&mut self) {
+        for (_, queue) in self.queues.drain() {
+            drop(queue.tx);
+            let _result = queue.task.await;
+        }
+    }
+
+    /// Closes the queues once the work already posted is done, and waits for it.
+    pub(crate) async fn flush(&mut self) {
+        self.conn_addrs.clear();
+        self.wait_idle().await;
+    }
+}
@@ -0,0 +1,66 @@
+//! Extension point for modules that live outside of this crate (e.g. proprietary profiles).
+//!
+//! An extension gets the sender of the stack main dispatch loop when it is registered, so it can
+//! post its own messages as `Message::Extension`. Those messages are handed back to the registered
+//! extensions by `Stack::dispatch` on the same loop as the core stack messages.
+
+use std::any::Any;
+
+use crate::MessageSender;
+
+/// Lifecycle hooks of a module plugged into the stack main dispatch loop.
+pub trait StackExtension: Send {
+    /// Returns the name of the extension, used for logging.
+    fn name(&self) -> String;
+
+    /// Called once when the extension is registered.
+    fn init(&mut self, tx: MessageSender);
+
+    /// Handles a message posted as `Message::Extension`.
+    ///
+    /// Returns the message back if it is not meant for this extension, so that it can be offered
+    /// to the next one.
+    fn dispatch(&mut self, message: Box<dyn Any + Send>) -> Result<(), Box<dyn Any + Send>>;
+
+    /// Called when the stack main dispatch loop quits.
+    fn shutdown(&mut self);
+}
+
+/// Holds the extensions registered to the stack.
+pub struct StackExtensions {
+    extensions: Vec<Box<dyn StackExtension>>,
+    tx: MessageSender,
+}
+
+impl StackExtensions {
+    /// Constructs an empty set of extensions.
+    pub fn new(tx: MessageSender) -> StackExtensions {
+        StackExtensions { extensions: vec![], tx }
+    }
+
+    /// Registers and initializes an extension.
+    pub fn register(&mut self, mut extension: Box<dyn StackExtension>) {
+        extension.init(self.tx.clone());
+        self.extensions.push(extension);
+    }
+
+    pub(crate) fn dispatch(&mut self, message: Box<dyn Any + Send>) {
+        let mut message = message;
+
+        for extension in &mut self.extensions {
+            match extension.dispatch(message) {
+                Ok(()) => return,
+                Err(m) => message = m,
+            }
+        }
+
+        eprintln!("No extension handles the message");
+    }
+
+    pub(crate) fn shutdown(&mut self) {
+        for extension in &mut self.extensions {
+            println!("Shutting down extension {}", extension.name());
+            extension.shutdown();
+        }
+    }
+}
@@ -0,0 +1,876 @@
+//! HID over GATT (HOGP) host.
+//!
+//! Makes BLE keyboards, mice and other HID devices work without a HID host in the native stack:
+//! once the GATT client of the host is connected to a device, its HID service is read and its
+//! input reports are forwarded to the kernel through uhid. The uhid device lives as long as the
+//! GATT connection, see `BluetoothGatt::connect_hid_device`.
+//!
+//! The requests of the kernel, e.g. output reports setting the LEDs of keyboards, are read from
+//! uhid and sent to the devices as `Message::GattHogpUhidRequest`.
+
+use bt_topshim::profiles::gatt::ffi::BtGattDbElement;
+use bt_topshim::profiles::gatt::GattDbElementType;
+use bt_topshim::topstack;
+
+use num_traits::FromPrimitive;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::os::unix::fs::OpenOptionsExt;
+
+use tokio::io::unix::AsyncFd;
+use tokio::task::JoinHandle;
+
+use crate::bluetooth_gatt::CCCD_UUID;
+use crate::uuid::Uuid128;
+use crate::{BDAddr, Message, MessageSender};
+
+/// HID service, and the characteristics and descriptor of it that the host uses.
+pub(crate) const HID_SERVICE_UUID: u16 = 0x1812;
+const HID_INFORMATION_UUID: u16 = 0x2A4A;
+const REPORT_MAP_UUID: u16 = 0x2A4B;
+const REPORT_UUID: u16 = 0x2A4D;
+const REPORT_REFERENCE_UUID: u16 = 0x2908;
+
+/// PnP ID characteristic of the Device Information service, with the vendor and product ids.
+const PNP_ID_UUID: u16 = 0x2A50;
+
+/// Value of the Client Characteristic Configuration descriptor that enables notifications.
+pub(crate) const CCCD_ENABLE_NOTIFICATIONS: [u8; 2] = [0x01, 0x00];
+
+const UHID_PATH: &str = "/dev/uhid";
+
+/// Types of `struct uhid_event`, see linux/uhid.h.
+const UHID_OUTPUT: u32 = 6;
+const UHID_GET_REPORT: u32 = 9;
+const UHID_GET_REPORT_REPLY: u32 = 10;
+const UHID_CREATE2: u32 = 11;
+const UHID_INPUT2: u32 = 12;
+const UHID_SET_REPORT: u32 = 13;
+const UHID_SET_REPORT_REPLY: u32 = 14;
+
+/// Sizes of the fields of `struct uhid_create2_req` and `struct uhid_input2_req`.
+const UHID_NAME_LEN: usize = 128;
+const UHID_PHYS_LEN: usize = 64;
+const UHID_UNIQ_LEN: usize = 64;
+const UHID_DATA_MAX: usize = 4096;
+
+/// Size of `struct uhid_event`, whose largest request is `struct uhid_create2_req`.
+const UHID_EVENT_LEN: usize =
+    4 + UHID_NAME_LEN + UHID_PHYS_LEN + UHID_UNIQ_LEN + 20 + UHID_DATA_MAX;
+
+/// Report types of uhid requests, see `enum uhid_report_type`.
+const UHID_FEATURE_REPORT: u8 = 0;
+const UHID_OUTPUT_REPORT: u8 = 1;
+const UHID_INPUT_REPORT: u8 = 2;
+
+/// Report types of the Report Reference descriptor.
+const REPORT_TYPE_INPUT: u8 = 1;
+const REPORT_TYPE_OUTPUT: u8 = 2;
+const REPORT_TYPE_FEATURE: u8 = 3;
+
+/// Error replied to the requests of the kernel that fail, `EIO` of errno.h.
+const UHID_REPLY_EIO: u16 = 5;
+
+/// `BUS_BLUETOOTH` of linux/input.h.
+const BUS_BLUETOOTH: u16 = 0x05;
+
+/// A GATT operation of a HOGP device, to set it up or for a request of the kernel.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum HogpOperation {
+    ReadCharacteristic(u16),
+    ReadDescriptor(u16),
+    /// Registers for the notifications of the input report of the value handle, and enables
+    /// them in its Client Characteristic Configuration descriptor.
+    EnableNotifications(u16, u16),
+    /// Reads the report of the value handle for the `UhidRequest::GetReport` of the id.
+    GetReport(u32, u16),
+    /// Writes the report of the value handle, for the `UhidRequest::SetReport` of the id if any
+    /// or for an output report.
+    WriteReport(Option<u32>, u16, Vec<u8>),
+}
+
+/// A request of the kernel to a uhid device.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum UhidRequest {
+    /// An output report to send, e.g. the LEDs of a keyboard. Its first byte is the report id
+    /// if the report map uses report ids.
+    Output { report_type: u8, data: Vec<u8> },
+    /// Reads a report, to be answered with the same id.
+    GetReport { id: u32, report_id: u8, report_type: u8 },
+    /// Writes a report, to be answered with the same id. The first byte of the data is the
+    /// report id if the report map uses report ids.
+    SetReport { id: u32, report_id: u8, report_type: u8, data: Vec<u8> },
+}
+
+impl UhidRequest {
+    /// Parses a `struct uhid_event` read from uhid. Returns None for the events that are not
+    /// requests, e.g. `UHID_START`.
+    fn parse(event: &[u8]) -> Option<UhidRequest> {
+        let u16_at = |i: usize| Some(u16::from_ne_bytes(event.get(i..i + 2)?.try_into().ok()?));
+        let u32_at = |i: usize| Some(u32::from_ne_bytes(event.get(i..i + 4)?.try_into().ok()?));
+
+        match u32_at(0)? {
+            // data[UHID_DATA_MAX], size, rtype.
+            UHID_OUTPUT => {
+                let size = usize::from(u16_at(4 + UHID_DATA_MAX)?).min(UHID_DATA_MAX);
+                Some(UhidRequest::Output {
+                    report_type: *event.get(4 + UHID_DATA_MAX + 2)?,
+                    data: event.get(4..4 + size)?.to_vec(),
+                })
+            }
+            // id, rnum, rtype.
+            UHID_GET_REPORT => Some(UhidRequest::GetReport {
+                id: u32_at(4)?,
+                report_id: *event.get(8)?,
+                report_type: *event.get(9)?,
+            }),
+            // id, rnum, rtype, size, data[UHID_DATA_MAX].
+            UHID_SET_REPORT => {
+                let size = usize::from(u16_at(10)?).min(UHID_DATA_MAX);
+                Some(UhidRequest::SetReport {
+                    id: u32_at(4)?,
+                    report_id: *event.get(8)?,
+                    report_type: *event.get(9)?,
+                    data: event.get(12..12 + size)?.to_vec(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the type of the Report Reference descriptor of a uhid report type.
+fn report_reference_type(uhid_type: u8) -> Option<u8> {
+    match uhid_type {
+        UHID_FEATURE_REPORT => Some(REPORT_TYPE_FEATURE),
+        UHID_OUTPUT_REPORT => Some(REPORT_TYPE_OUTPUT),
+        UHID_INPUT_REPORT => Some(REPORT_TYPE_INPUT),
+        _ => None,
+    }
+}
+
+/// A Report characteristic of the HID service.
+struct Report {
+    /// Handle of the value of the characteristic.
+    handle: u16,
+    /// Handle of its Report Reference descriptor, if any.
+    reference_handle: Option<u16>,
+    /// Handle of its Client Characteristic Configuration descriptor, which only input reports
+    /// have.
+    cccd_handle: Option<u16>,
+    /// From the Report Reference descriptor, 0 if the report map doesn't use report ids.
+    id: u8,
+    /// From the Report Reference descriptor, 0 if it has none.
+    report_type: u8,
+}
+
+/// HID device of a GATT connection of the HOGP host.
+///
+/// The device is set up by sending its operations one at a time, each once the previous one is
+/// done, and is then started to create its uhid device. The requests of the kernel are then
+/// queued as operations too.
+pub(crate) struct HogpDevice {
+    addr: BDAddr,
+    report_map_handle: u16,
+    hid_information_handle: Option<u16>,
+    pnp_id_handle: Option<u16>,
+    reports: Vec<Report>,
+    report_map: Vec<u8>,
+    country: u8,
+    vendor: u16,
+    product: u16,
+    version: u16,
+    /// Setup operations left, in order.
+    operations: VecDeque<HogpOperation>,
+    /// Operation waiting for its result, if any.
+    in_flight: Option<HogpOperation>,
+    /// Number of times the operation in flight was sent again.
+    retries: u32,
+    uhid: Option<UhidDevice>,
+}
+
+impl HogpDevice {
+    /// Looks up the HID service in the database of a device. Returns None if the device doesn't
+    /// serve one with a report map.
+    ///
+    /// Only the first HID service of the device is used.
+    pub(crate) fn new(addr: BDAddr, db: &[BtGattDbElement]) -> Option<HogpDevice> {
+        let mut in_hid_service = false;
+        let mut seen_hid_service = false;
+        let mut report_map_handle = None;
+        let mut hid_information_handle = None;
+        let mut pnp_id_handle = None;
+        let mut reports: Vec<Report> = vec![];
+        // Whether the descriptors that follow belong to the last report.
+        let mut in_report = false;
+
+        for element in db {
+            let uuid = Uuid128::from_bytes(element.uuid.uuid).as_u16();
+            match GattDbElementType::from_i32(element.element_type) {
+                Some(GattDbElementType::PrimaryService)
+                | Some(GattDbElementType::SecondaryService) => {
+                    in_hid_service = !seen_hid_service && uuid == Some(HID_SERVICE_UUID);
+                    seen_hid_service |= in_hid_service;
+                    in_report = false;
+                }
+                Some(GattDbElementType::Characteristic) => {
+                    in_report = false;
+                    match uuid {
+                        Some(PNP_ID_UUID) => pnp_id_handle = Some(element.attribute_handle),
+                        Some(REPORT_MAP_UUID) if in_hid_service => {
+                            report_map_handle = Some(element.attribute_handle)
+                        }
+                        Some(HID_INFORMATION_UUID) if in_hid_service => {
+                            hid_information_handle = Some(element.attribute_handle)
+                        }
+                        Some(REPORT_UUID) if in_hid_service => {
+                            in_report = true;
+                            reports.push(Report {
+                                handle: element.attribute_handle,
+                                reference_handle: None,
+                                cccd_handle: None,
+                                id: 0,
+                                report_type: 0,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                Some(GattDbElementType::Descriptor) if in_report => {
+                    let report = reports.last_mut().unwrap();
+                    match uuid {
+                        Some(REPORT_REFERENCE_UUID) => {
+                            report.reference_handle = Some(element.attribute_handle)
+                        }
+                        Some(CCCD_UUID) => report.cccd_handle = Some(element.attribute_handle),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let report_map_handle = report_map_handle?;
+
+        let mut operations = VecDeque::new();
+        operations.push_back(HogpOperation::ReadCharacteristic(report_map_handle));
+        for handle in hid_information_handle.iter().chain(pnp_id_handle.iter()) {
+            operations.push_back(HogpOperation::ReadCharacteristic(*handle));
+        }
+        for report in &reports {
+            if let Some(handle) = report.reference_handle {
+                operations.push_back(HogpOperation::ReadDescriptor(handle));
+            }
+        }
+        for report in &reports {
+            if let Some(cccd_handle) = report.cccd_handle {
+                operations
+                    .push_back(HogpOperation::EnableNotifications(report.handle, cccd_handle));
+            }
+        }
+
+        Some(HogpDevice {
+            addr,
+            report_map_handle,
+            hid_information_handle,
+            pnp_id_handle,
+            reports,
+            report_map: vec![],
+            country: 0,
+            vendor: 0,
+            product: 0,
+            version: 0,
+            operations,
+            in_flight: None,
+            retries: 0,
+            uhid: None,
+        })
+    }
+
+    pub(crate) fn addr(&self) -> BDAddr {
+        self.addr
+    }
+
+    /// Returns the next operation to send, which is then in flight until `operation_done`.
+    /// Returns None while an operation is in flight, and once there is none left.
+    pub(crate) fn next_operation(&mut self) -> Option<HogpOperation> {
+        if self.in_flight.is_some() {
+            return None;
+        }
+
+        self.in_flight = self.operations.pop_front();
+        self.in_flight.clone()
+    }
+
+    /// Returns the operation in flight, if any.
+    pub(crate) fn operation_in_flight(&self) -> Option<HogpOperation> {
+        self.in_flight.clone()
+    }
+
+    /// Returns whether the setup operations are all done, whether they succeeded or not, and
+    /// the uhid device is still to be created.
+    pub(crate) fn is_ready_to_start(&self) -> bool {
+        self.uhid.is_none() && self.in_flight.is_none() && self.operations.is_empty()
+    }
+
+    /// Completes the operation in flight with whether it succeeded and the value it read, which
+    /// is empty if the operation failed or doesn't read.
+    pub(crate) fn operation_done(&mut self, success: bool, value: &[u8]) {
+        self.retries = 0;
+        let value: &[u8] = if success { value } else { &[] };
+        match self.in_flight.take() {
+            Some(HogpOperation::ReadCharacteristic(handle)) if handle == self.report_map_handle => {
+                self.report_map = value.iter().take(UHID_DATA_MAX).copied().collect();
+            }
+            // bcdHID, then bCountryCode.
+            Some(HogpOperation::ReadCharacteristic(handle))
+                if Some(handle) == self.hid_information_handle && value.len() >= 3 =>
+            {
+                self.country = value[2];
+            }
+            // Vendor id source, then the vendor id, product id and product version.
+            Some(HogpOperation::ReadCharacteristic(handle))
+                if Some(handle) == self.pnp_id_handle && value.len() >= 7 =>
+            {
+                self.vendor = u16::from_le_bytes([value[1], value[2]]);
+                self.product = u16::from_le_bytes([value[3], value[4]]);
+                self.version = u16::from_le_bytes([value[5], value[6]]);
+            }
+            // Report id, then report type.
+            Some(HogpOperation::ReadDescriptor(handle)) if value.len() >= 2 => {
+                if let Some(report) =
+                    self.reports.iter_mut().find(|r| r.reference_handle == Some(handle))
+                {
+                    report.id = value[0];
+                    report.report_type = value[1];
+                }
+            }
+            Some(HogpOperation::GetReport(id, handle)) => {
+                let report_id = self.find_report_by_handle(handle).map_or(0, |report| report.id);
+                self.reply_get_report(id, success, report_id, value);
+            }
+            Some(HogpOperation::WriteReport(Some(id), _, _)) => {
+                if let Some(uhid) = &mut self.uhid {
+                    let err = if success { 0 } else { UHID_REPLY_EIO };
+                    if let Err(e) = uhid.set_report_reply(id, err) {
+                        eprintln!("Failed to answer a report write of {:?}: {}", self.addr, e);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn find_report_by_handle(&self, handle: u16) -> Option<&Report> {
+        self.reports.iter().find(|report| report.handle == handle)
+    }
+
+    /// Returns the report of a request of the kernel, found by its id and uhid report type.
+    fn find_report(&self, report_id: u8, uhid_type: u8) -> Option<&Report> {
+        let report_type = report_reference_type(uhid_type)?;
+        self.reports
+            .iter()
+            .find(|report| report.id == report_id && report.report_type == report_type)
+    }
+
+    /// Answers a GET_REPORT request of the kernel with the report read from the device.
+    fn reply_get_report(&mut self, id: u32, success: bool, report_id: u8, value: &[u8]) {
+        let uhid = match &mut self.uhid {
+            Some(uhid) => uhid,
+            None => return,
+        };
+
+        // The report id is not part of the value read over GATT.
+        let mut data = Vec::with_capacity(value.len() + 1);
+        if success && report_id != 0 {
+            data.push(report_id);
+        }
+        data.extend_from_slice(value);
+
+        let err = if success { 0 } else { UHID_REPLY_EIO };
+        if let Err(e) = uhid.get_report_reply(id, err, &data) {
+            eprintln!("Failed to answer a report read of {:?}: {}", self.addr, e);
+        }
+    }
+
+    /// Queues the operation answering a request of the kernel, or answers it right away if the
+    /// device has no such report.
+    pub(crate) fn queue_request(&mut self, request: UhidRequest) {
+        match request {
+            UhidRequest::Output { report_type, data } => {
+                let report_id = data.first().copied().unwrap_or(0);
+                let (handle, data) = match self.report_data(report_id, report_type, &data) {
+                    Some(report) => report,
+                    None => return,
+                };
+                self.operations.push_back(HogpOperation::WriteReport(None, handle, data));
+            }
+            UhidRequest::GetReport { id, report_id, report_type } => {
+                match self.find_report(report_id, report_type) {
+                    Some(report) => {
+                        let handle = report.handle;
+                        self.operations.push_back(HogpOperation::GetReport(id, handle));
+                    }
+                    None => self.reply_get_report(id, false, 0, &[]),
+                }
+            }
+            UhidRequest::SetReport { id, report_id, report_type, data } => {
+                if let Some((handle, data)) = self.report_data(report_id, report_type, &data) {
+                    let operation = HogpOperation::WriteReport(Some(id), handle, data);
+                    self.operations.push_back(operation);
+                } else if let Some(uhid) = &mut self.uhid {
+                    let _result = uhid.set_report_reply(id, UHID_REPLY_EIO);
+                }
+            }
+        }
+    }
+
+    /// Returns the value handle of the report a request of the kernel writes, and the value to
+    /// write, which is the data without the report id if the report map uses report ids.
+    fn report_data(&self, report_id: u8, uhid_type: u8, data: &[u8]) -> Option<(u16, Vec<u8>)> {
+        // Without report ids, the data is the value as is.
+        if let Some(report) = self.find_report(0, uhid_type) {
+            return Some((report.handle, data.to_vec()));
+        }
+
+        let report = self.find_report(report_id, uhid_type)?;
+        Some((report.handle, data.get(1..)?.to_vec()))
+    }
+
+    /// Puts the operation in flight back in front of the others, to be sent again. Returns
+    /// false, leaving it in flight, if it was already sent again `max_retries` times.
+    pub(crate) fn retry_operation(&mut self, max_retries: u32) -> bool {
+        if self.retries >= max_retries {
+            return false;
+        }
+
+        if let Some(operation) = self.in_flight.take() {
+            self.operations.push_front(operation);
+        }
+        self.retries += 1;
+        true
+    }
+
+    /// Creates the uhid device that the input reports are forwarded to, once the device is set
+    /// up.
+    ///
+    /// `name` is the name of the device, and `adapter_addr` the address of the adapter it is
+    /// connected to. The requests of the kernel are posted with the id of the GATT connection.
+    pub(crate) fn start(
+        &mut self,
+        name: &str,
+        adapter_addr: &str,
+        conn_id: i32,
+        tx: MessageSender,
+    ) -> Result<()> {
+        if self.report_map.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "the report map could not be read"));
+        }
+
+        // Named like the HID devices of the kernel Bluetooth stack.
+        let uhid = UhidDevice::create(
+            name,
+            &adapter_addr.to_lowercase(),
+            &self.addr.to_string().to_lowercase(),
+            self,
+            conn_id,
+            tx,
+        )?;
+        self.uhid = Some(uhid);
+        Ok(())
+    }
+
+    /// Forwards an input report notified by the device to the kernel. Reports notified before
+    /// the device is started are dropped.
+    pub(crate) fn input_report(&mut self, handle: i32, value: &[u8]) {
+        let uhid = match &mut self.uhid {
+            Some(uhid) => uhid,
+            None => return,
+        };
+
+        let report = match self.reports.iter().find(|report| report.handle as i32 == handle) {
+            Some(report) => report,
+            None => return,
+        };
+
+        // The report id is not part of the value notified over GATT.
+        let mut data = Vec::with_capacity(value.len() + 1);
+        if report.id != 0 {
+            data.push(report.id);
+        }
+        data.extend_from_slice(value);
+
+        if let Err(e) = uhid.input(&data) {
+            eprintln!("Failed to forward an input report of {:?}: {}", self.addr, e);
+        }
+    }
+}
+
+/// A HID device created in the kernel through uhid. The kernel destroys it once the file is
+/// closed, i.e. when this is dropped.
+struct UhidDevice {
+    file: File,
+    /// Reads the requests of the kernel from a duplicate of the file.
+    reader: JoinHandle<()>,
+}
+
+impl Drop for UhidDevice {
+    fn drop(&mut self) {
+        // Closes the duplicate of the file.
+        self.reader.abort();
+    }
+}
+
+impl UhidDevice {
+    fn create(
+        name: &str,
+        phys: &str,
+        uniq: &str,
+        device: &HogpDevice,
+        conn_id: i32,
+        tx: MessageSender,
+    ) -> Result<UhidDevice> {
+        let mut event = UHID_CREATE2.to_ne_bytes().to_vec();
+        push_string(&mut event, name, UHID_NAME_LEN);
+        push_string(&mut event, phys, UHID_PHYS_LEN);
+        push_string(&mut event, uniq, UHID_UNIQ_LEN);
+        event.extend_from_slice(&(device.report_map.len() as u16).to_ne_bytes());
+        event.extend_from_slice(&BUS_BLUETOOTH.to_ne_bytes());
+        event.extend_from_slice(&u32::from(device.vendor).to_ne_bytes());
+        event.extend_from_slice(&u32::from(device.product).to_ne_bytes());
+        event.extend_from_slice(&u32::from(device.version).to_ne_bytes());
+        event.extend_from_slice(&u32::from(device.country).to_ne_bytes());
+        event.extend_from_slice(&device.report_map);
+
+        // Non-blocking, so that the requests can be read asynchronously.
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(nix::libc::O_NONBLOCK)
+            .open(UHID_PATH)?;
+        file.write_all(&event)?;
+
+        let reader = topstack::get_runtime().spawn(read_requests(file.try_clone()?, conn_id, tx));
+        Ok(UhidDevice { file, reader })
+    }
+
+    fn get_report_reply(&mut self, id: u32, err: u16, data: &[u8]) -> Result<()> {
+        let data = &data[..data.len().min(UHID_DATA_MAX)];
+
+        let mut event = UHID_GET_REPORT_REPLY.to_ne_bytes().to_vec();
+        event.extend_from_slice(&id.to_ne_bytes());
+        event.extend_from_slice(&err.to_ne_bytes());
+        event.extend_from_slice(&(data.len() as u16).to_ne_bytes());
+        event.extend_from_slice(data);
+        self.file.write_all(&event)
+    }
+
+    fn set_report_reply(&mut self, id: u32, err: u16) -> Result<()> {
+        let mut event = UHID_SET_REPORT_REPLY.to_ne_bytes().to_vec();
+        event.extend_from_slice(&id.to_ne_bytes());
+        event.extend_from_slice(&err.to_ne_bytes());
+        self.file.write_all(&event)
+    }
+
+    fn input(&mut self, data: &[u8]) -> Result<()> {
+        let data = &data[..data.len().min(UHID_DATA_MAX)];
+
+        let mut event = UHID_INPUT2.to_ne_bytes().to_vec();
+        event.extend_from_slice(&(data.len() as u16).to_ne_bytes());
+        event.extend_from_slice(data);
+        self.file.write_all(&event)
+    }
+}
+
+/// Reads the requests of the kernel from a uhid device until it is dropped, posting them as
+/// `Message::GattHogpUhidRequest`.
+async fn read_requests(file: File, conn_id: i32, tx: MessageSender) {
+    let file = match AsyncFd::new(file) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to read the requests of a uhid device: {}", e);
+            return;
+        }
+    };
+
+    let mut event = vec![0; UHID_EVENT_LEN];
+    loop {
+        let mut guard = match file.readable().await {
+            Ok(guard) => guard,
+            Err(_) => return,
+        };
+
+        let len = match guard.try_io(|file| (&mut file.get_ref()).read(&mut event)) {
+            Ok(Ok(len)) => len,
+            Ok(Err(e)) => {
+                eprintln!("Failed to read the requests of a uhid device: {}", e);
+                return;
+            }
+            // Not readable anymore, wait again.
+            Err(_) => continue,
+        };
+
+        if let Some(request) = UhidRequest::parse(&event[..len]) {
+            let _result = tx.send(Message::GattHogpUhidRequest(conn_id, request)).await;
+        }
+    }
+}
+
+/// Appends a string to a uhid event as a NUL terminated field of `len` bytes, truncating it if
+/// needed.
+fn push_string(event: &mut Vec<u8>, s: &str, len: usize) {
+    let bytes = &s.as_bytes()[..s.len().min(len - 1)];
+    event.extend_from_slice(bytes);
+    event.resize(event.len() + len - bytes.len(), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use num_traits::ToPrimitive;
+
+    const DEVICE_INFORMATION_UUID: u16 = 0x180A;
+
+    fn element(element_type: GattDbElementType, uuid: u16, handle: u16) -> BtGattDbElement {
+        BtGattDbElement {
+            id: handle,
+            uuid: Uuid128::from_u16(uuid).into(),
+            element_type: element_type.to_i32().unwrap(),
+            attribute_handle: handle,
+            start_handle: 0,
+            end_handle: 0,
+            properties: 0,
+            extended_properties: 0,
+            permissions: 0,
+        }
+    }
+
+    /// A keyboard with an input report and an output report of id 1, and a PnP ID.
+    fn keyboard_db() -> Vec<BtGattDbElement> {
+        use GattDbElementType::{Characteristic, Descriptor, PrimaryService};
+
+        vec![
+            element(PrimaryService, HID_SERVICE_UUID, 1),
+            element(Characteristic, REPORT_MAP_UUID, 3),
+            element(Characteristic, HID_INFORMATION_UUID, 5),
+            element(Characteristic, REPORT_UUID, 7),
+            element(Descriptor, REPORT_REFERENCE_UUID, 8),
+            element(Descriptor, CCCD_UUID, 9),
+            element(Characteristic, REPORT_UUID, 11),
+            element(Descriptor, REPORT_REFERENCE_UUID, 12),
+            element(PrimaryService, DEVICE_INFORMATION_UUID, 20),
+            element(Characteristic, PNP_ID_UUID, 22),
+        ]
+    }
+
+    fn addr() -> BDAddr {
+        BDAddr::from_string("11:22:33:44:55:66").unwrap()
+    }
+
+    /// Runs the setup operations of a keyboard, answering them with the values of `values`.
+    fn set_up(device: &mut HogpDevice, values: &[(HogpOperation, &[u8])]) {
+        for (operation, value) in values {
+            assert_eq!(device.next_operation().as_ref(), Some(operation));
+            assert_eq!(device.next_operation(), None);
+            device.operation_done(true, value);
+        }
+    }
+
+    fn keyboard() -> HogpDevice {
+        let mut device = HogpDevice::new(addr(), &keyboard_db()).unwrap();
+        set_up(
+            &mut device,
+            &[
+                (HogpOperation::ReadCharacteristic(3), &[0x05, 0x01, 0x09, 0x06]),
+                (HogpOperation::ReadCharacteristic(5), &[0x11, 0x01, 0x21, 0x02]),
+                (
+                    HogpOperation::ReadCharacteristic(22),
+                    &[0x02, 0x6D, 0x04, 0x1C, 0xC5, 0x01, 0x01],
+                ),
+                (HogpOperation::ReadDescriptor(8), &[1, REPORT_TYPE_INPUT]),
+                (HogpOperation::ReadDescriptor(12), &[1, REPORT_TYPE_OUTPUT]),
+                (HogpOperation::EnableNotifications(7, 9), &[]),
+            ],
+        );
+        device
+    }
+
+    #[test]
+    fn set_up_keyboard() {
+        let device = keyboard();
+
+        assert!(device.is_ready_to_start());
+        assert_eq!(device.report_map, vec![0x05, 0x01, 0x09, 0x06]);
+        assert_eq!(device.country, 0x21);
+        assert_eq!((device.vendor, device.product, device.version), (0x046D, 0xC51C, 0x0101));
+        assert_eq!((device.reports[0].id, device.reports[0].report_type), (1, REPORT_TYPE_INPUT));
+        assert_eq!((device.reports[1].id, device.reports[1].report_type), (1, REPORT_TYPE_OUTPUT));
+    }
+
+    #[test]
+    fn no_report_map() {
+        let db: Vec<BtGattDbElement> =
+            keyboard_db().into_iter().filter(|e| e.attribute_handle != 3).collect();
+        assert!(HogpDevice::new(addr(), &db).is_none());
+    }
+
+    #[test]
+    fn failed_operation_keeps_defaults() {
+        let mut device = HogpDevice::new(addr(), &keyboard_db()).unwrap();
+        device.next_operation();
+        device.operation_done(true, &[0x05, 0x01]);
+        assert_eq!(device.next_operation(), Some(HogpOperation::ReadCharacteristic(5)));
+        device.operation_done(false, &[0x11, 0x01, 0x21, 0x02]);
+
+        assert_eq!(device.country, 0);
+        assert!(!device.is_ready_to_start());
+    }
+
+    #[test]
+    fn retry_operation() {
+        let mut device = HogpDevice::new(addr(), &keyboard_db()).unwrap();
+        device.next_operation();
+
+        assert!(device.retry_operation(1));
+        assert_eq!(device.operation_in_flight(), None);
+        assert_eq!(device.next_operation(), Some(HogpOperation::ReadCharacteristic(3)));
+        assert!(!device.retry_operation(1));
+        assert_eq!(device.operation_in_flight(), Some(HogpOperation::ReadCharacteristic(3)));
+    }
+
+    #[test]
+    fn queue_requests() {
+        let mut device = keyboard();
+
+        // The report id is dropped from the value written over GATT.
+        device.queue_request(UhidRequest::Output {
+            report_type: UHID_OUTPUT_REPORT,
+            data: vec![1, 0x02],
+        });
+        device.queue_request(UhidRequest::GetReport {
+            id: 7,
+            report_id: 1,
+            report_type: UHID_INPUT_REPORT,
+        });
+        device.queue_request(UhidRequest::SetReport {
+            id: 8,
+            report_id: 1,
+            report_type: UHID_OUTPUT_REPORT,
+            data: vec![1, 0x04],
+        });
+        // No such report.
+        device.queue_request(UhidRequest::GetReport {
+            id: 9,
+            report_id: 2,
+            report_type: UHID_FEATURE_REPORT,
+        });
+
+        assert_eq!(device.next_operation(), Some(HogpOperation::WriteReport(None, 11, vec![0x02])));
+        device.operation_done(true, &[]);
+        assert_eq!(device.next_operation(), Some(HogpOperation::GetReport(7, 7)));
+        device.operation_done(true, &[0x00]);
+        assert_eq!(
+            device.next_operation(),
+            Some(HogpOperation::WriteReport(Some(8), 11, vec![0x04]))
+        );
+        device.operation_done(true, &[]);
+        assert_eq!(device.next_operation(), None);
+    }
+
+    #[test]
+    fn report_data_without_report_ids() {
+        let mut device = HogpDevice::new(addr(), &keyboard_db()).unwrap();
+        device.reports[1].report_type = REPORT_TYPE_OUTPUT;
+
+        assert_eq!(device.report_data(0, UHID_OUTPUT_REPORT, &[0x02]), Some((11, vec![0x02])));
+        assert_eq!(device.report_data(0, UHID_FEATURE_REPORT, &[0x02]), None);
+    }
+
+    #[test]
+    fn report_data_with_report_ids() {
+        let device = keyboard();
+
+        assert_eq!(device.report_data(1, UHID_OUTPUT_REPORT, &[1, 0x02]), Some((11, vec![0x02])));
+        assert_eq!(device.report_data(2, UHID_OUTPUT_REPORT, &[2, 0x02]), None);
+        assert_eq!(device.report_data(1, UHID_OUTPUT_REPORT, &[]), None);
+    }
+
+    fn event(event_type: u32) -> Vec<u8> {
+        let mut event = vec![0; UHID_EVENT_LEN];
+        event[..4].copy_from_slice(&event_type.to_ne_bytes());
+        event
+    }
+
+    #[test]
+    fn parse_output() {
+        let mut event = event(UHID_OUTPUT);
+        event[4..6].copy_from_slice(&[1, 0x02]);
+        event[4 + UHID_DATA_MAX..4 + UHID_DATA_MAX + 2].copy_from_slice(&2u16.to_ne_bytes());
+        event[4 + UHID_DATA_MAX + 2] = UHID_OUTPUT_REPORT;
+
+        assert_eq!(
+            UhidRequest::parse(&event),
+            Some(UhidRequest::Output { report_type: UHID_OUTPUT_REPORT, data: vec![1, 0x02] })
+        );
+    }
+
+    #[test]
+    fn parse_get_report() {
+        let mut event = event(UHID_GET_REPORT);
+        event[4..8].copy_from_slice(&7u32.to_ne_bytes());
+        event[8] = 1;
+        event[9] = UHID_FEATURE_REPORT;
+
+        assert_eq!(
+            UhidRequest::parse(&event),
+            Some(UhidRequest::GetReport { id: 7, report_id: 1, report_type: UHID_FEATURE_REPORT })
+        );
+    }
+
+    #[test]
+    fn parse_set_report() {
+        let mut event = event(UHID_SET_REPORT);
+        event[4..8].copy_from_slice(&8u32.to_ne_bytes());
+        event[8] = 1;
+        event[9] = UHID_OUTPUT_REPORT;
+        event[10..12].copy_from_slice(&2u16.to_ne_bytes());
+        event[12..14].copy_from_slice(&[1, 0x04]);
+
+        assert_eq!(
+            UhidRequest::parse(&event),
+            Some(UhidRequest::SetReport {
+                id: 8,
+                report_id: 1,
+                report_type: UHID_OUTPUT_REPORT,
+                data: vec![1, 0x04],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_other_events() {
+        // UHID_START and UHID_OPEN.
+        assert_eq!(UhidRequest::parse(&event(2)), None);
+        assert_eq!(UhidRequest::parse(&event(4)), None);
+        // Truncated.
+        assert_eq!(UhidRequest::parse(&event(UHID_SET_REPORT)[..10]), None);
+        assert_eq!(UhidRequest::parse(&[]), None);
+    }
+
+    #[test]
+    fn push_string_truncates() {
+        let mut event = vec![];
+        push_string(&mut event, "kbd", 8);
+        assert_eq!(event, b"kbd\0\0\0\0\0");
+
+        let mut event = vec![];
+        push_string(&mut event, "keyboard", 4);
+        assert_eq!(event, b"key\0");
+    }
+}
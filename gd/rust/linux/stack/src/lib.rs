@@ -7,23 +7,67 @@
 extern crate num_derive;
 
 pub mod bluetooth;
+pub mod bluetooth_debug;
 pub mod bluetooth_gatt;
+pub mod bluetooth_logging;
+pub mod bluetooth_media;
+#[cfg(feature = "qa")]
+pub mod bluetooth_qa;
+pub mod bluez_import;
+pub mod completion;
+pub mod config;
+pub mod device_class;
+mod device_queue;
+pub mod extension;
+mod hogp;
+pub mod message_trace;
+pub mod metrics;
+pub mod obex;
+pub mod policy;
+pub mod uuid;
+pub mod watchdog;
 
 use bt_topshim::btif::ffi;
-use bt_topshim::btif::BtState;
+use bt_topshim::btif::{BtAclState, BtBondState, BtDiscoveryState, BtSspVariant, BtState};
+use bt_topshim::profiles::a2dp::ffi::A2dpCodecConfig;
+use bt_topshim::profiles::a2dp::{BtavAudioState, BtavConnectionState};
+use bt_topshim::profiles::gatt::ffi::BtGattDbElement;
+use bt_topshim::topstack;
 
+use num_traits::cast::ToPrimitive;
+
+use serde::{Deserialize, Serialize};
+
+use std::any::Any;
 use std::convert::TryInto;
 use std::fmt::{Debug, Formatter, Result};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use tokio::sync::mpsc::channel;
+use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::Instant;
+
+use crate::bluetooth::{
+    AdapterState, Bluetooth, BtifAclCallbacks, BtifBluetoothCallbacks, IBluetooth, PendingOperation,
+};
+use crate::bluetooth_debug::{BluetoothDebug, BtifDebugCallbacks, ThroughputTestResult};
+use crate::bluetooth_gatt::{BluetoothGatt, BtifGattCallbacks};
+use crate::bluetooth_media::{BluetoothMedia, BtifA2dpCallbacks};
+use crate::device_queue::DeviceQueues;
+use crate::extension::StackExtensions;
+use crate::hogp::UhidRequest;
+use crate::message_trace::MessageRecorder;
+use crate::policy::BluetoothPolicy;
+use crate::watchdog::{Handler, Watchdog};
 
-use crate::bluetooth::{Bluetooth, BtifBluetoothCallbacks};
+/// How long the adapter is given to turn off when the stack shuts down.
+const SHUTDOWN_ADAPTER_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Represents a Bluetooth address.
 // TODO: Add support for LE random addresses.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct BDAddr {
     val: [u8; 6],
 }
@@ -51,37 +95,569 @@ impl BDAddr {
     fn from_byte_vec(raw_addr: &Vec<u8>) -> BDAddr {
         BDAddr { val: raw_addr.clone().try_into().unwrap() }
     }
+
+    /// Parses a BDAddr from a string of the form "XX:XX:XX:XX:XX:XX".
+    fn from_string(addr: &str) -> Option<BDAddr> {
+        let bytes =
+            addr.split(':').map(|x| u8::from_str_radix(x, 16).ok()).collect::<Option<Vec<u8>>>()?;
+
+        match bytes.len() {
+            6 => Some(BDAddr::from_byte_vec(&bytes)),
+            _ => None,
+        }
+    }
+
+    /// Constructs a BDAddr from the address type passed across the topshim boundary.
+    fn from_raw_address(raw: &ffi::RustRawAddress) -> BDAddr {
+        BDAddr { val: raw.address }
+    }
+
+    /// Converts to the address type passed across the topshim boundary.
+    fn to_raw_address(&self) -> ffi::RustRawAddress {
+        ffi::RustRawAddress { address: self.val }
+    }
 }
 
 /// Message types that are sent to the stack main dispatch loop.
+///
+/// Messages are serializable so that they can be recorded and replayed, see `message_trace`.
+#[derive(Serialize, Deserialize)]
 pub enum Message {
     BluetoothAdapterStateChanged(BtState),
     BluetoothAdapterPropertiesChanged(i32, i32, Vec<ffi::BtProperty>),
+    BluetoothRemoteDevicePropertiesChanged(i32, ffi::RustRawAddress, i32, Vec<ffi::BtProperty>),
+    BluetoothDeviceFound(i32, Vec<ffi::BtProperty>),
+    BluetoothDiscoveryStateChanged(BtDiscoveryState),
+    BluetoothPinRequest(ffi::RustRawAddress, String, u32, bool),
+    BluetoothSspRequest(ffi::RustRawAddress, String, u32, BtSspVariant, u32),
+    BluetoothBondStateChanged(i32, ffi::RustRawAddress, BtBondState),
+    BluetoothBondedNameTimeout(BDAddr),
+    BluetoothBondRetry(BDAddr),
+    BluetoothDeviceBlocked(BDAddr),
+    BluetoothDeviceUnblocked(BDAddr),
+    BluetoothDiscoverableTimeout(u32),
+    BluetoothPairableTimeout(u32),
+    BluetoothFactoryResetDone,
+    BluetoothOperationTimeout(PendingOperation, u32),
+    BluetoothAclStateChanged(i32, ffi::RustRawAddress, BtAclState, i32),
+    BluetoothLocalOobDataGenerated(i32, ffi::BtOutOfBandData),
     BluetoothCallbackDisconnected(u32),
+    BluetoothPairingAgentDisconnected(u32),
+    BluetoothRssiMonitorTick(BDAddr),
+    BluetoothRemoteRssiRead(ffi::RustRawAddress, i32, i32),
+    BluetoothRssiReadDone(BDAddr, i32, i32),
+    BluetoothLePhyRead(ffi::RustRawAddress, i32, u8, u8),
+    BluetoothRoleSwitchRequested(ffi::RustRawAddress, i32),
+    BluetoothRoleSwitchCheck(BDAddr),
+
+    GattClientRegistered(i32, i32, ffi::BtUuid),
+    GattClientConnect(i32, i32, i32, ffi::RustRawAddress),
+    GattClientDisconnect(i32, i32, i32, ffi::RustRawAddress),
+    GattClientReadRemoteRssi(i32, ffi::RustRawAddress, i32, i32),
+    GattClientSearchComplete(i32, i32),
+    GattClientNotify(i32, ffi::RustRawAddress, i32, bool, Vec<u8>),
+    GattClientGetGattDb(i32, Vec<BtGattDbElement>),
+    GattClientReadCharacteristic(i32, i32, i32, Vec<u8>),
+    GattClientWriteCharacteristic(i32, i32, i32),
+    GattClientReadDescriptor(i32, i32, i32, Vec<u8>),
+    GattClientWriteDescriptor(i32, i32, i32),
+    GattClientExecuteWrite(i32, i32),
+    GattClientConfigureMtu(i32, i32, i32),
+    GattClientPhyUpdated(i32, i32, i32, i32),
+    GattClientConnUpdated(i32, i32, i32, i32, i32),
+    GattClientServiceChanged(i32),
+    GattClientServicesRediscovered(ffi::RustRawAddress),
+    GattClientReadPhy(i32, ffi::RustRawAddress, i32, i32, i32),
+    GattServerRegistered(i32, i32, ffi::BtUuid),
+    GattServerConnection(i32, i32, i32, ffi::RustRawAddress),
+    GattServerIndicationSent(i32, i32),
+    GattServerCongestion(i32, bool),
+    GattServerMtuChanged(i32, i32),
+    GattServerReadPhy(i32, ffi::RustRawAddress, i32, i32, i32),
+    GattServerRequestWrite(i32, i32, ffi::RustRawAddress, i32, i32, bool, bool, Vec<u8>),
+    GattServerRequestExecWrite(i32, i32, ffi::RustRawAddress, i32),
+    GattServerServiceAdded(i32, i32, Vec<BtGattDbElement>),
+    GattServerRequestRead(i32, i32, ffi::RustRawAddress, i32, i32, bool),
+    GattThroughputTestTimeout(u32),
+    GattHogpOperationRetry(i32),
+    GattHogpUhidRequest(i32, UhidRequest),
+    GattClientCallbackDisconnected(u32),
+    GattServerCallbackDisconnected(u32),
+    GattScannerRegistered(ffi::BtUuid, i32, i32),
+    GattScanResult(i32, i32, ffi::RustRawAddress, i32, i32, i32, i32, i32, i32, Vec<u8>),
+    GattScannerReportTick(i32),
+    GattScannerFilterConfigured(i32, i32),
+    GattScannerCallbackDisconnected(u32),
+    GattMonitorCallbackDisconnected(u32),
+    GattAdvertiserSetStarted(i32, i32, i32, i32),
+    GattAdvertiserEnabled(i32, bool, i32),
+    GattAdvertiserDataSet(i32, bool, i32),
+    GattAdvertiserParametersUpdated(i32, i32, i32),
+    GattAdvertiserOwnAddressRead(i32, i32, ffi::RustRawAddress),
+    GattAdvertiserCallbackDisconnected(u32),
+
+    DebugLinkQualityReport(u64, i32, i32, i32, i32, i32, i32),
+    DebugLinkQualityMetricsTick,
+    DebugThroughputTestDone(BDAddr, ThroughputTestResult),
+    DebugVendorCommandComplete(u16, Vec<u8>),
+    DebugCallbackDisconnected(u32),
+
+    A2dpConnectionStateChanged(ffi::RustRawAddress, BtavConnectionState),
+    A2dpAudioStateChanged(ffi::RustRawAddress, BtavAudioState),
+    MediaCallbackDisconnected(u32),
+    A2dpAudioConfig(
+        ffi::RustRawAddress,
+        A2dpCodecConfig,
+        Vec<A2dpCodecConfig>,
+        Vec<A2dpCodecConfig>,
+    ),
+    A2dpAudioDelay(ffi::RustRawAddress, u16),
+    A2dpStreamRequestTimeout(BDAddr, u32),
+    A2dpDisconnectReasonTimeout(BDAddr, u32),
+    A2dpReconnect(BDAddr, u32),
+
+    /// Messages defined by the modules registered through `StackExtensions`. They are not
+    /// recorded.
+    #[serde(skip)]
+    Extension(Box<dyn Any + Send>),
+
+    /// Shuts the stack down, see `Stack::dispatch`.
+    Shutdown,
+}
+
+/// Priority lanes of the main dispatch loop.
+///
+/// Messages in the control lane are always dispatched before the ones in the data lane, so that
+/// bursts of data (e.g. scan results) don't delay adapter state changes.
+///
+/// The messages about remote devices all go in the data lane, so that the messages about one
+/// device keep their order, e.g. a GATT disconnection doesn't overtake the notifications that
+/// preceded it. They are only handed to the work queue of their device there, see
+/// `DeviceQueues`, so a burst from one device doesn't hold back the others.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageLane {
+    Control,
+    Data,
+    /// Dispatched by a loop of its own, see `bluetooth_media::dispatch`, so that audio state
+    /// changes are not delayed by the main dispatch loop.
+    Media,
+}
+
+impl Message {
+    /// Returns the name of the variant of the message, for logging.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Message::BluetoothAdapterStateChanged(..) => "BluetoothAdapterStateChanged",
+            Message::BluetoothAdapterPropertiesChanged(..) => "BluetoothAdapterPropertiesChanged",
+            Message::BluetoothRemoteDevicePropertiesChanged(..) => {
+                "BluetoothRemoteDevicePropertiesChanged"
+            }
+            Message::BluetoothDeviceFound(..) => "BluetoothDeviceFound",
+            Message::BluetoothDiscoveryStateChanged(..) => "BluetoothDiscoveryStateChanged",
+            Message::BluetoothPinRequest(..) => "BluetoothPinRequest",
+            Message::BluetoothSspRequest(..) => "BluetoothSspRequest",
+            Message::BluetoothBondStateChanged(..) => "BluetoothBondStateChanged",
+            Message::BluetoothBondedNameTimeout(..) => "BluetoothBondedNameTimeout",
+            Message::BluetoothBondRetry(..) => "BluetoothBondRetry",
+            Message::BluetoothDeviceBlocked(..) => "BluetoothDeviceBlocked",
+            Message::BluetoothDeviceUnblocked(..) => "BluetoothDeviceUnblocked",
+            Message::BluetoothDiscoverableTimeout(..) => "BluetoothDiscoverableTimeout",
+            Message::BluetoothPairableTimeout(..) => "BluetoothPairableTimeout",
+            Message::BluetoothFactoryResetDone => "BluetoothFactoryResetDone",
+            Message::BluetoothOperationTimeout(..) => "BluetoothOperationTimeout",
+            Message::BluetoothAclStateChanged(..) => "BluetoothAclStateChanged",
+            Message::BluetoothLocalOobDataGenerated(..) => "BluetoothLocalOobDataGenerated",
+            Message::BluetoothCallbackDisconnected(..) => "BluetoothCallbackDisconnected",
+            Message::BluetoothPairingAgentDisconnected(..) => "BluetoothPairingAgentDisconnected",
+            Message::BluetoothRssiMonitorTick(..) => "BluetoothRssiMonitorTick",
+            Message::BluetoothRemoteRssiRead(..) => "BluetoothRemoteRssiRead",
+            Message::BluetoothRssiReadDone(..) => "BluetoothRssiReadDone",
+            Message::BluetoothLePhyRead(..) => "BluetoothLePhyRead",
+            Message::BluetoothRoleSwitchRequested(..) => "BluetoothRoleSwitchRequested",
+            Message::BluetoothRoleSwitchCheck(..) => "BluetoothRoleSwitchCheck",
+            Message::GattClientRegistered(..) => "GattClientRegistered",
+            Message::GattClientConnect(..) => "GattClientConnect",
+            Message::GattClientDisconnect(..) => "GattClientDisconnect",
+            Message::GattClientReadRemoteRssi(..) => "GattClientReadRemoteRssi",
+            Message::GattClientSearchComplete(..) => "GattClientSearchComplete",
+            Message::GattClientNotify(..) => "GattClientNotify",
+            Message::GattClientGetGattDb(..) => "GattClientGetGattDb",
+            Message::GattClientReadCharacteristic(..) => "GattClientReadCharacteristic",
+            Message::GattClientWriteCharacteristic(..) => "GattClientWriteCharacteristic",
+            Message::GattClientReadDescriptor(..) => "GattClientReadDescriptor",
+            Message::GattClientWriteDescriptor(..) => "GattClientWriteDescriptor",
+            Message::GattClientExecuteWrite(..) => "GattClientExecuteWrite",
+            Message::GattClientConfigureMtu(..) => "GattClientConfigureMtu",
+            Message::GattClientPhyUpdated(..) => "GattClientPhyUpdated",
+            Message::GattClientConnUpdated(..) => "GattClientConnUpdated",
+            Message::GattClientServiceChanged(..) => "GattClientServiceChanged",
+            Message::GattClientServicesRediscovered(..) => "GattClientServicesRediscovered",
+            Message::GattClientReadPhy(..) => "GattClientReadPhy",
+            Message::GattServerRegistered(..) => "GattServerRegistered",
+            Message::GattServerConnection(..) => "GattServerConnection",
+            Message::GattServerIndicationSent(..) => "GattServerIndicationSent",
+            Message::GattServerCongestion(..) => "GattServerCongestion",
+            Message::GattServerMtuChanged(..) => "GattServerMtuChanged",
+            Message::GattServerReadPhy(..) => "GattServerReadPhy",
+            Message::GattServerRequestWrite(..) => "GattServerRequestWrite",
+            Message::GattServerRequestExecWrite(..) => "GattServerRequestExecWrite",
+            Message::GattServerServiceAdded(..) => "GattServerServiceAdded",
+            Message::GattServerRequestRead(..) => "GattServerRequestRead",
+            Message::GattThroughputTestTimeout(..) => "GattThroughputTestTimeout",
+            Message::GattHogpOperationRetry(..) => "GattHogpOperationRetry",
+            Message::GattHogpUhidRequest(..) => "GattHogpUhidRequest",
+            Message::GattClientCallbackDisconnected(..) => "GattClientCallbackDisconnected",
+            Message::GattServerCallbackDisconnected(..) => "GattServerCallbackDisconnected",
+            Message::GattScannerRegistered(..) => "GattScannerRegistered",
+            Message::GattScanResult(..) => "GattScanResult",
+            Message::GattScannerReportTick(..) => "GattScannerReportTick",
+            Message::GattScannerFilterConfigured(..) => "GattScannerFilterConfigured",
+            Message::GattScannerCallbackDisconnected(..) => "GattScannerCallbackDisconnected",
+            Message::GattMonitorCallbackDisconnected(..) => "GattMonitorCallbackDisconnected",
+            Message::GattAdvertiserSetStarted(..) => "GattAdvertiserSetStarted",
+            Message::GattAdvertiserEnabled(..) => "GattAdvertiserEnabled",
+            Message::GattAdvertiserDataSet(..) => "GattAdvertiserDataSet",
+            Message::GattAdvertiserParametersUpdated(..) => "GattAdvertiserParametersUpdated",
+            Message::GattAdvertiserOwnAddressRead(..) => "GattAdvertiserOwnAddressRead",
+            Message::GattAdvertiserCallbackDisconnected(..) => "GattAdvertiserCallbackDisconnected",
+            Message::DebugLinkQualityReport(..) => "DebugLinkQualityReport",
+            Message::DebugLinkQualityMetricsTick => "DebugLinkQualityMetricsTick",
+            Message::DebugThroughputTestDone(..) => "DebugThroughputTestDone",
+            Message::DebugVendorCommandComplete(..) => "DebugVendorCommandComplete",
+            Message::DebugCallbackDisconnected(..) => "DebugCallbackDisconnected",
+            Message::A2dpConnectionStateChanged(..) => "A2dpConnectionStateChanged",
+            Message::A2dpAudioStateChanged(..) => "A2dpAudioStateChanged",
+            Message::MediaCallbackDisconnected(..) => "MediaCallbackDisconnected",
+            Message::A2dpAudioConfig(..) => "A2dpAudioConfig",
+            Message::A2dpAudioDelay(..) => "A2dpAudioDelay",
+            Message::A2dpStreamRequestTimeout(..) => "A2dpStreamRequestTimeout",
+            Message::A2dpDisconnectReasonTimeout(..) => "A2dpDisconnectReasonTimeout",
+            Message::A2dpReconnect(..) => "A2dpReconnect",
+            Message::Extension(..) => "Extension",
+            Message::Shutdown => "Shutdown",
+        }
+    }
+
+    /// Returns the lane the message is dispatched in.
+    pub fn lane(&self) -> MessageLane {
+        match self {
+            Message::A2dpConnectionStateChanged(..)
+            | Message::A2dpAudioStateChanged(..)
+            | Message::MediaCallbackDisconnected(..)
+            | Message::A2dpAudioConfig(..)
+            | Message::A2dpAudioDelay(..)
+            | Message::A2dpStreamRequestTimeout(..)
+            | Message::A2dpDisconnectReasonTimeout(..)
+            | Message::A2dpReconnect(..) => MessageLane::Media,
+            Message::GattScanResult(..)
+            | Message::GattScannerReportTick(..)
+            | Message::DebugLinkQualityReport(..)
+            | Message::DebugLinkQualityMetricsTick => MessageLane::Data,
+            _ if self.device_key().is_some() => MessageLane::Data,
+            _ => MessageLane::Control,
+        }
+    }
+
+    /// Returns the remote device the message is about, if any, in which case it is dispatched
+    /// in the work queue of that device (see `DeviceQueues`) rather than in order with the
+    /// messages of the other devices.
+    ///
+    /// Messages of GATT connections are only about a device while `queues` knows the connection.
+    pub(crate) fn device(&self, queues: &DeviceQueues) -> Option<BDAddr> {
+        match self.device_key()? {
+            DeviceKey::Addr(addr) => Some(addr),
+            DeviceKey::Conn(conn_id) => queues.conn_addr(conn_id),
+        }
+    }
+
+    fn device_key(&self) -> Option<DeviceKey> {
+        match self {
+            Message::BluetoothRemoteDevicePropertiesChanged(_, addr, _, _)
+            | Message::BluetoothPinRequest(addr, _, _, _)
+            | Message::BluetoothSspRequest(addr, _, _, _, _)
+            | Message::BluetoothBondStateChanged(_, addr, _)
+            | Message::BluetoothAclStateChanged(_, addr, _, _)
+            | Message::BluetoothRemoteRssiRead(addr, _, _)
+            | Message::BluetoothLePhyRead(addr, _, _, _)
+            | Message::BluetoothRoleSwitchRequested(addr, _)
+            | Message::GattClientConnect(_, _, _, addr)
+            | Message::GattClientDisconnect(_, _, _, addr)
+            | Message::GattClientReadRemoteRssi(_, addr, _, _)
+            | Message::GattClientNotify(_, addr, _, _, _)
+            | Message::GattClientReadPhy(_, addr, _, _, _)
+            | Message::GattClientServicesRediscovered(addr)
+            | Message::GattServerReadPhy(_, addr, _, _, _)
+            | Message::GattServerConnection(_, _, _, addr)
+            | Message::GattServerRequestWrite(_, _, addr, _, _, _, _, _)
+            | Message::GattServerRequestExecWrite(_, _, addr, _)
+            | Message::GattServerRequestRead(_, _, addr, _, _, _)
+            | Message::A2dpConnectionStateChanged(addr, _)
+            | Message::A2dpAudioStateChanged(addr, _)
+            | Message::A2dpAudioConfig(addr, _, _, _)
+            | Message::A2dpAudioDelay(addr, _) => {
+                Some(DeviceKey::Addr(BDAddr::from_raw_address(addr)))
+            }
+            Message::BluetoothBondedNameTimeout(addr)
+            | Message::BluetoothBondRetry(addr)
+            | Message::BluetoothDeviceBlocked(addr)
+            | Message::BluetoothDeviceUnblocked(addr)
+            | Message::BluetoothRssiMonitorTick(addr)
+            | Message::BluetoothRssiReadDone(addr, _, _)
+            | Message::BluetoothRoleSwitchCheck(addr)
+            | Message::A2dpStreamRequestTimeout(addr, _)
+            | Message::A2dpDisconnectReasonTimeout(addr, _)
+            | Message::A2dpReconnect(addr, _)
+            | Message::DebugThroughputTestDone(addr, _) => Some(DeviceKey::Addr(*addr)),
+            Message::GattClientSearchComplete(conn_id, _)
+            | Message::GattClientGetGattDb(conn_id, _)
+            | Message::GattClientReadCharacteristic(conn_id, _, _, _)
+            | Message::GattClientWriteCharacteristic(conn_id, _, _)
+            | Message::GattClientReadDescriptor(conn_id, _, _, _)
+            | Message::GattClientWriteDescriptor(conn_id, _, _)
+            | Message::GattClientExecuteWrite(conn_id, _)
+            | Message::GattClientConfigureMtu(conn_id, _, _)
+            | Message::GattClientPhyUpdated(conn_id, _, _, _)
+            | Message::GattClientConnUpdated(conn_id, _, _, _, _)
+            | Message::GattClientServiceChanged(conn_id)
+            | Message::GattServerIndicationSent(conn_id, _)
+            | Message::GattServerCongestion(conn_id, _)
+            | Message::GattServerMtuChanged(conn_id, _)
+            | Message::GattHogpOperationRetry(conn_id)
+            | Message::GattHogpUhidRequest(conn_id, _) => Some(DeviceKey::Conn(*conn_id)),
+            _ => None,
+        }
+    }
+}
+
+/// What ties a message to a remote device, see `Message::device`.
+enum DeviceKey {
+    Addr(BDAddr),
+    /// A GATT connection, whose device is known to `DeviceQueues`.
+    Conn(i32),
+}
+
+/// Sends messages to the main dispatch loop, each in the lane it belongs to.
+#[derive(Clone)]
+pub struct MessageSender {
+    control: Sender<Message>,
+    data: Sender<Message>,
+    media: Sender<Message>,
+}
+
+impl MessageSender {
+    /// Sends a message in its lane, waiting for room in that lane if it is full.
+    pub async fn send(&self, message: Message) -> std::result::Result<(), SendError<Message>> {
+        match message.lane() {
+            MessageLane::Control => self.control.send(message).await,
+            MessageLane::Data => self.data.send(message).await,
+            MessageLane::Media => self.media.send(message).await,
+        }
+    }
+}
+
+/// Receives the messages of both lanes of the main dispatch loop, and the media messages until
+/// the media dispatch loop takes them over.
+pub struct MessageReceiver {
+    control: Receiver<Message>,
+    data: Receiver<Message>,
+    media: Option<Receiver<Message>>,
+    /// Set for a receiver without lanes, see `Stack::create_replay_channel`. The media messages
+    /// come in order with the others and are passed on to the media dispatch loop from here.
+    media_tx: Option<Sender<Message>>,
+}
+
+impl MessageReceiver {
+    /// Receives the next message, from the control lane if it has any.
+    ///
+    /// Returns None once every sender is dropped.
+    async fn recv(&mut self) -> Option<Message> {
+        loop {
+            let m = tokio::select! {
+                biased;
+                Some(m) = self.control.recv() => m,
+                Some(m) = self.data.recv() => m,
+                else => return None,
+            };
+
+            match &self.media_tx {
+                Some(media_tx) if self.media.is_none() && m.lane() == MessageLane::Media => {
+                    // The media dispatch loop is gone once the stack shuts down.
+                    let _ = media_tx.send(m).await;
+                }
+                _ => return Some(m),
+            }
+        }
+    }
+}
+
+/// The modules that the messages about remote devices are dispatched to, from the work queues
+/// of the devices.
+#[derive(Clone)]
+struct DeviceModules {
+    bluetooth: Arc<Mutex<Bluetooth>>,
+    bluetooth_gatt: Arc<Mutex<BluetoothGatt>>,
+    bluetooth_policy: Arc<Mutex<BluetoothPolicy>>,
+    bluetooth_debug: Arc<Mutex<BluetoothDebug>>,
+    bluetooth_media: Arc<Mutex<BluetoothMedia>>,
 }
 
 /// Umbrella class for the Bluetooth stack.
 pub struct Stack {}
 
 impl Stack {
-    /// Creates an mpsc channel for passing messages to the main dispatch loop.
-    pub fn create_channel() -> (Sender<Message>, Receiver<Message>) {
-        channel::<Message>(1)
+    /// Creates the mpsc channels for passing messages to the main dispatch loop.
+    pub fn create_channel() -> (MessageSender, MessageReceiver) {
+        let (control_tx, control_rx) = channel::<Message>(1);
+        // The data lane is deeper so that data producers are not throttled by each control
+        // message.
+        let (data_tx, data_rx) = channel::<Message>(16);
+        let (media_tx, media_rx) = channel::<Message>(1);
+
+        (
+            MessageSender { control: control_tx, data: data_tx, media: media_tx },
+            MessageReceiver {
+                control: control_rx,
+                data: data_rx,
+                media: Some(media_rx),
+                media_tx: None,
+            },
+        )
+    }
+
+    /// Creates a channel without lanes, which feeds the dispatch loops the messages in the order
+    /// they are sent. Used to replay a trace, see `message_trace::replay`.
+    pub fn create_replay_channel() -> (Sender<Message>, MessageReceiver) {
+        let (tx, rx) = channel::<Message>(1);
+        // Nothing is sent in the data lane, so it is closed from the start.
+        let (_, data_rx) = channel::<Message>(1);
+        let (media_tx, media_rx) = channel::<Message>(1);
+
+        (
+            tx,
+            MessageReceiver {
+                control: rx,
+                data: data_rx,
+                media: Some(media_rx),
+                media_tx: Some(media_tx),
+            },
+        )
     }
 
-    /// Runs the main dispatch loop.
-    pub async fn dispatch(mut rx: Receiver<Message>, bluetooth: Arc<Mutex<Bluetooth>>) {
+    /// Runs the main dispatch loop, and the media dispatch loop alongside it.
+    ///
+    /// The loop returns once every sender is dropped, or once the stack is shut down by a
+    /// `Message::Shutdown`. Shutting down stops the extensions, closes the audio session,
+    /// unregisters the GATT applications and turns the adapter off, then cleans up the profiles
+    /// and the Bluetooth interface. The adapter is given `SHUTDOWN_ADAPTER_TIMEOUT` to turn off.
+    ///
+    /// The loops tell `watchdog` which message they are handling, see `Watchdog`, and record the
+    /// messages to `recorder` if any, see `MessageRecorder`.
+    pub async fn dispatch(
+        mut rx: MessageReceiver,
+        bluetooth: Arc<Mutex<Bluetooth>>,
+        bluetooth_gatt: Arc<Mutex<BluetoothGatt>>,
+        bluetooth_media: Arc<Mutex<BluetoothMedia>>,
+        bluetooth_policy: Arc<Mutex<BluetoothPolicy>>,
+        bluetooth_debug: Arc<Mutex<BluetoothDebug>>,
+        extensions: Arc<Mutex<StackExtensions>>,
+        watchdog: Watchdog,
+        recorder: Option<MessageRecorder>,
+    ) {
+        let media_dispatch = rx.media.take().map(|media_rx| {
+            topstack::get_runtime().spawn(bluetooth_media::dispatch(
+                media_rx,
+                bluetooth_media.clone(),
+                watchdog.clone(),
+                recorder.clone(),
+            ))
+        });
+
+        // Set once shutting down, until when the adapter is waited for.
+        let mut shutdown_deadline: Option<Instant> = None;
+
+        let mut device_queues = DeviceQueues::new();
+        let device_modules = DeviceModules {
+            bluetooth: bluetooth.clone(),
+            bluetooth_gatt: bluetooth_gatt.clone(),
+            bluetooth_policy: bluetooth_policy.clone(),
+            bluetooth_debug: bluetooth_debug.clone(),
+            bluetooth_media: bluetooth_media.clone(),
+        };
+
         loop {
-            let m = rx.recv().await;
+            let m = match shutdown_deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, rx.recv()).await {
+                    Ok(m) => m,
+                    Err(_) => {
+                        eprintln!("Adapter did not turn off in time, shutting down anyway");
+                        break;
+                    }
+                },
+                None => rx.recv().await,
+            };
 
             if m.is_none() {
                 eprintln!("Message dispatch loop quit");
+                extensions.lock().unwrap().shutdown();
                 break;
             }
 
-            match m.unwrap() {
+            let m = m.unwrap();
+            if let Some(recorder) = &recorder {
+                recorder.record(&m);
+            }
+
+            // The device of a connection is known from its first message until its last.
+            let device = m.device(&device_queues);
+            match &m {
+                Message::GattClientConnect(conn_id, 0, _, addr) => {
+                    device_queues.connection_opened(*conn_id, BDAddr::from_raw_address(addr));
+                }
+                Message::GattServerConnection(conn_id, _, connected, addr) if *connected != 0 => {
+                    device_queues.connection_opened(*conn_id, BDAddr::from_raw_address(addr));
+                }
+                Message::GattClientDisconnect(conn_id, ..)
+                | Message::GattServerConnection(conn_id, ..) => {
+                    device_queues.connection_closed(*conn_id);
+                }
+                _ => {}
+            }
+
+            if let Some(addr) = device {
+                let modules = device_modules.clone();
+                let watchdog = watchdog.clone();
+                device_queues.post(addr, move || {
+                    let _watch = watchdog.watch(Handler::Device(addr), m.name());
+                    Stack::dispatch_device_message(m, &modules);
+                });
+                continue;
+            }
+
+            device_queues.wait_idle().await;
+            let _watch = watchdog.watch(Handler::Main, m.name());
+            match m {
                 Message::BluetoothAdapterStateChanged(state) => {
+                    let enabled = state == BtState::On;
                     bluetooth.lock().unwrap().adapter_state_changed(state);
+
+                    if shutdown_deadline.is_some() {
+                        if !enabled {
+                            break;
+                        }
+                        // The adapter finished turning on while shutting down.
+                        bluetooth.lock().unwrap().disable();
+                    } else if enabled {
+                        let bonded_devices = {
+                            let bluetooth = bluetooth.lock().unwrap();
+                            bluetooth
+                                .get_bonded_devices()
+                                .into_iter()
+                                .filter(|addr| !bluetooth.is_device_blocked(addr))
+                                .map(|addr| (addr, bluetooth.get_cached_uuids(&addr)))
+                                .collect()
+                        };
+                        bluetooth_policy.lock().unwrap().on_adapter_enabled(bonded_devices);
+                    }
                 }
 
                 Message::BluetoothAdapterPropertiesChanged(status, num_properties, properties) => {
@@ -92,10 +668,511 @@ impl Stack {
                     );
                 }
 
+                Message::BluetoothDeviceFound(num_properties, properties) => {
+                    bluetooth.lock().unwrap().device_found(num_properties, properties);
+                }
+
+                Message::BluetoothDiscoveryStateChanged(state) => {
+                    bluetooth.lock().unwrap().discovery_state_changed(state);
+                }
+
+                Message::BluetoothDiscoverableTimeout(id) => {
+                    bluetooth.lock().unwrap().discoverable_timeout(id);
+                }
+
+                Message::BluetoothPairableTimeout(id) => {
+                    bluetooth.lock().unwrap().pairable_timeout(id);
+                }
+
+                Message::BluetoothFactoryResetDone => {
+                    bluetooth.lock().unwrap().factory_reset_done();
+                }
+
+                Message::BluetoothOperationTimeout(operation, id) => {
+                    bluetooth.lock().unwrap().operation_timeout(operation, id);
+                }
+
+                Message::BluetoothLocalOobDataGenerated(transport, oob_data) => {
+                    bluetooth.lock().unwrap().local_oob_data_generated(transport, oob_data);
+                }
+
                 Message::BluetoothCallbackDisconnected(id) => {
                     bluetooth.lock().unwrap().callback_disconnected(id);
                 }
+
+                Message::BluetoothPairingAgentDisconnected(id) => {
+                    bluetooth.lock().unwrap().pairing_agent_disconnected(id);
+                }
+
+                Message::GattClientRegistered(status, client_id, app_uuid) => {
+                    bluetooth_gatt.lock().unwrap().client_registered(status, client_id, app_uuid);
+                }
+
+                Message::GattServerRegistered(status, server_id, app_uuid) => {
+                    bluetooth_gatt.lock().unwrap().server_registered(status, server_id, app_uuid);
+                }
+
+                Message::GattServerServiceAdded(status, server_id, elements) => {
+                    bluetooth_gatt
+                        .lock()
+                        .unwrap()
+                        .server_service_added(status, server_id, elements);
+                }
+
+                Message::GattThroughputTestTimeout(id) => {
+                    bluetooth_gatt.lock().unwrap().throughput_test_timeout(id);
+                }
+
+                Message::GattClientCallbackDisconnected(id) => {
+                    bluetooth_gatt.lock().unwrap().client_callback_disconnected(id);
+                }
+
+                Message::GattServerCallbackDisconnected(id) => {
+                    bluetooth_gatt.lock().unwrap().server_callback_disconnected(id);
+                }
+
+                Message::GattScannerRegistered(app_uuid, scanner_id, status) => {
+                    bluetooth_gatt.lock().unwrap().scanner_registered(app_uuid, scanner_id, status);
+                }
+
+                Message::GattScanResult(
+                    event_type,
+                    addr_type,
+                    addr,
+                    primary_phy,
+                    secondary_phy,
+                    advertising_sid,
+                    tx_power,
+                    rssi,
+                    periodic_adv_int,
+                    adv_data,
+                ) => {
+                    bluetooth_gatt.lock().unwrap().scan_result(
+                        event_type,
+                        addr_type,
+                        addr,
+                        primary_phy,
+                        secondary_phy,
+                        advertising_sid,
+                        tx_power,
+                        rssi,
+                        periodic_adv_int,
+                        adv_data,
+                    );
+                }
+
+                Message::GattScannerReportTick(scanner_id) => {
+                    bluetooth_gatt.lock().unwrap().scanner_report_tick(scanner_id);
+                }
+
+                Message::GattScannerFilterConfigured(filter_index, status) => {
+                    bluetooth_gatt.lock().unwrap().scanner_filter_configured(filter_index, status);
+                }
+
+                Message::GattScannerCallbackDisconnected(id) => {
+                    bluetooth_gatt.lock().unwrap().scanner_callback_disconnected(id);
+                }
+
+                Message::GattMonitorCallbackDisconnected(id) => {
+                    bluetooth_gatt.lock().unwrap().monitor_callback_disconnected(id);
+                }
+
+                Message::GattAdvertiserSetStarted(reg_id, advertiser_id, tx_power, status) => {
+                    bluetooth_gatt.lock().unwrap().advertiser_set_started(
+                        reg_id,
+                        advertiser_id,
+                        tx_power,
+                        status,
+                    );
+                }
+
+                Message::GattAdvertiserEnabled(advertiser_id, enable, status) => {
+                    bluetooth_gatt.lock().unwrap().advertiser_enabled(
+                        advertiser_id,
+                        enable,
+                        status,
+                    );
+                }
+
+                Message::GattAdvertiserDataSet(advertiser_id, set_scan_rsp, status) => {
+                    bluetooth_gatt.lock().unwrap().advertiser_data_set(
+                        advertiser_id,
+                        set_scan_rsp,
+                        status,
+                    );
+                }
+
+                Message::GattAdvertiserParametersUpdated(advertiser_id, tx_power, status) => {
+                    bluetooth_gatt.lock().unwrap().advertiser_parameters_updated(
+                        advertiser_id,
+                        tx_power,
+                        status,
+                    );
+                }
+
+                Message::GattAdvertiserOwnAddressRead(advertiser_id, address_type, address) => {
+                    bluetooth_gatt.lock().unwrap().advertiser_own_address_read(
+                        advertiser_id,
+                        address_type,
+                        address,
+                    );
+                }
+
+                Message::GattAdvertiserCallbackDisconnected(id) => {
+                    bluetooth_gatt.lock().unwrap().advertiser_callback_disconnected(id);
+                }
+
+                Message::DebugLinkQualityReport(
+                    timestamp,
+                    report_id,
+                    rssi,
+                    snr,
+                    retransmission_count,
+                    packets_not_receive_count,
+                    negative_acknowledgement_count,
+                ) => {
+                    bluetooth_debug.lock().unwrap().link_quality_report(
+                        timestamp,
+                        report_id,
+                        rssi,
+                        snr,
+                        retransmission_count,
+                        packets_not_receive_count,
+                        negative_acknowledgement_count,
+                    );
+                }
+
+                Message::DebugLinkQualityMetricsTick => {
+                    bluetooth_debug.lock().unwrap().report_link_quality_metrics();
+                }
+
+                Message::DebugVendorCommandComplete(opcode, params) => {
+                    bluetooth_debug.lock().unwrap().vendor_command_complete(opcode, params);
+                }
+
+                Message::DebugCallbackDisconnected(id) => {
+                    bluetooth_debug.lock().unwrap().callback_disconnected(id);
+                }
+
+                // Sent in the media lane, see `bluetooth_media::dispatch`.
+                Message::A2dpConnectionStateChanged(..)
+                | Message::A2dpAudioStateChanged(..)
+                | Message::MediaCallbackDisconnected(..)
+                | Message::A2dpAudioConfig(..)
+                | Message::A2dpAudioDelay(..)
+                | Message::A2dpStreamRequestTimeout(..)
+                | Message::A2dpDisconnectReasonTimeout(..)
+                | Message::A2dpReconnect(..) => {}
+
+                Message::Extension(message) => {
+                    extensions.lock().unwrap().dispatch(message);
+                }
+
+                Message::Shutdown => {
+                    if shutdown_deadline.is_some() {
+                        continue;
+                    }
+
+                    println!("Shutting down the stack");
+                    shutdown_deadline = Some(Instant::now() + SHUTDOWN_ADAPTER_TIMEOUT);
+                    extensions.lock().unwrap().shutdown();
+                    bluetooth_media.lock().unwrap().stop_streams();
+                    bluetooth_gatt.lock().unwrap().unregister_all();
+
+                    // Refused while turning on, the adapter is disabled again once it is on.
+                    let mut adapter = bluetooth.lock().unwrap();
+                    adapter.disable();
+                    if adapter.get_state() == AdapterState::Off.to_u32().unwrap() {
+                        break;
+                    }
+                }
+
+                // Messages of GATT connections that are not known to `device_queues`.
+                m => Stack::dispatch_device_message(m, &device_modules),
+            }
+        }
+
+        // The work of the devices is done before the modules are cleaned up.
+        device_queues.flush().await;
+
+        if let Some(media_dispatch) = media_dispatch {
+            media_dispatch.abort();
+        }
+
+        if shutdown_deadline.is_some() {
+            bluetooth_media.lock().unwrap().cleanup();
+            bluetooth_gatt.lock().unwrap().cleanup();
+            bluetooth.lock().unwrap().cleanup();
+            println!("Stack shut down");
+        }
+    }
+
+    /// Dispatches a message about a remote device, see `Message::device`.
+    fn dispatch_device_message(m: Message, modules: &DeviceModules) {
+        let DeviceModules {
+            bluetooth,
+            bluetooth_gatt,
+            bluetooth_policy,
+            bluetooth_debug,
+            bluetooth_media,
+        } = modules;
+
+        match m {
+            Message::BluetoothRemoteDevicePropertiesChanged(
+                status,
+                addr,
+                num_properties,
+                properties,
+            ) => {
+                bluetooth.lock().unwrap().remote_device_properties_changed(
+                    status,
+                    addr,
+                    num_properties,
+                    properties,
+                );
+
+                let addr = BDAddr::from_raw_address(&addr);
+                let uuids = bluetooth.lock().unwrap().get_cached_uuids(&addr);
+                if let Some(uuids) = uuids {
+                    bluetooth_policy.lock().unwrap().on_remote_uuids_known(addr, uuids);
+                }
+            }
+
+            Message::BluetoothPinRequest(addr, name, cod, min_16_digit) => {
+                bluetooth.lock().unwrap().pin_request(addr, name, cod, min_16_digit);
+            }
+
+            Message::BluetoothSspRequest(addr, name, cod, variant, passkey) => {
+                bluetooth.lock().unwrap().ssp_request(addr, name, cod, variant, passkey);
+            }
+
+            Message::BluetoothBondStateChanged(status, addr, state) => {
+                let bonded = status == 0 && state == BtBondState::Bonded;
+                bluetooth.lock().unwrap().bond_state_changed(status, addr, state);
+
+                let addr = BDAddr::from_raw_address(&addr);
+                if bonded {
+                    let uuids = bluetooth.lock().unwrap().get_cached_uuids(&addr);
+                    bluetooth_policy.lock().unwrap().on_device_bonded(addr, uuids);
+                } else if state == BtBondState::NotBonded {
+                    bluetooth_gatt.lock().unwrap().forget_hid_device(addr);
+                }
+            }
+
+            Message::BluetoothBondedNameTimeout(addr) => {
+                bluetooth.lock().unwrap().bonded_name_timeout(addr);
+            }
+
+            Message::BluetoothBondRetry(addr) => {
+                bluetooth.lock().unwrap().bond_retry(addr);
+            }
+
+            Message::BluetoothDeviceBlocked(addr) => {
+                bluetooth_gatt.lock().unwrap().forget_hid_device(addr);
+            }
+
+            Message::BluetoothDeviceUnblocked(addr) => {
+                let adapter = bluetooth.lock().unwrap();
+                if adapter.get_bonded_devices().contains(&addr) {
+                    let uuids = adapter.get_cached_uuids(&addr);
+                    drop(adapter);
+                    bluetooth_policy.lock().unwrap().on_device_unblocked(addr, uuids);
+                }
+            }
+
+            Message::BluetoothAclStateChanged(status, addr, state, hci_reason) => {
+                let connected = state == BtAclState::Connected;
+                bluetooth_debug
+                    .lock()
+                    .unwrap()
+                    .acl_state_changed(BDAddr::from_raw_address(&addr), connected);
+                // A2DP only runs over BR/EDR, which stays up when the LE link of a dual-mode
+                // device disconnects.
+                let media_addr = BDAddr::from_raw_address(&addr);
+                if !connected && !bluetooth.lock().unwrap().is_br_edr_connected(&media_addr) {
+                    bluetooth_media.lock().unwrap().acl_disconnected(media_addr, hci_reason);
+                }
+                bluetooth.lock().unwrap().acl_state_changed(status, addr, state, hci_reason);
+            }
+
+            Message::BluetoothRssiMonitorTick(addr) => {
+                bluetooth.lock().unwrap().rssi_monitor_tick(addr);
+            }
+
+            Message::BluetoothRemoteRssiRead(addr, status, rssi) => {
+                bluetooth.lock().unwrap().read_rssi(addr, status, rssi);
+            }
+
+            Message::BluetoothRssiReadDone(addr, status, rssi) => {
+                bluetooth_gatt.lock().unwrap().remote_rssi_read(addr, status, rssi);
+            }
+
+            Message::BluetoothLePhyRead(addr, status, tx_phy, rx_phy) => {
+                bluetooth.lock().unwrap().read_phy(addr, status, tx_phy, rx_phy);
+            }
+
+            Message::BluetoothRoleSwitchRequested(addr, status) => {
+                bluetooth.lock().unwrap().switch_role(addr, status);
+            }
+
+            Message::BluetoothRoleSwitchCheck(addr) => {
+                bluetooth.lock().unwrap().role_switch_check(addr);
+            }
+
+            Message::GattClientConnect(conn_id, status, client_id, addr) => {
+                bluetooth_gatt.lock().unwrap().client_connect(conn_id, status, client_id, addr);
+            }
+
+            Message::GattClientDisconnect(conn_id, status, client_id, addr) => {
+                bluetooth_gatt.lock().unwrap().client_disconnect(conn_id, status, client_id, addr);
+            }
+
+            Message::GattClientReadRemoteRssi(client_id, addr, rssi, status) => {
+                bluetooth_gatt
+                    .lock()
+                    .unwrap()
+                    .client_read_remote_rssi(client_id, addr, rssi, status);
+            }
+
+            Message::GattClientSearchComplete(conn_id, status) => {
+                bluetooth_gatt.lock().unwrap().client_search_complete(conn_id, status);
+            }
+
+            Message::GattClientNotify(conn_id, addr, handle, is_notify, value) => {
+                bluetooth_gatt
+                    .lock()
+                    .unwrap()
+                    .client_notify(conn_id, addr, handle, is_notify, value);
+            }
+
+            Message::GattClientGetGattDb(conn_id, elements) => {
+                bluetooth_gatt.lock().unwrap().client_get_gatt_db(conn_id, elements);
+            }
+
+            Message::GattClientReadCharacteristic(conn_id, status, handle, value) => {
+                bluetooth_gatt
+                    .lock()
+                    .unwrap()
+                    .client_read_characteristic(conn_id, status, handle, value);
+            }
+
+            Message::GattClientWriteCharacteristic(conn_id, status, handle) => {
+                bluetooth_gatt.lock().unwrap().client_write_characteristic(conn_id, status, handle);
+            }
+
+            Message::GattClientReadDescriptor(conn_id, status, handle, value) => {
+                bluetooth_gatt
+                    .lock()
+                    .unwrap()
+                    .client_read_descriptor(conn_id, status, handle, value);
+            }
+
+            Message::GattClientWriteDescriptor(conn_id, status, handle) => {
+                bluetooth_gatt.lock().unwrap().client_write_descriptor(conn_id, status, handle);
+            }
+
+            Message::GattClientExecuteWrite(conn_id, status) => {
+                bluetooth_gatt.lock().unwrap().client_execute_write(conn_id, status);
+            }
+
+            Message::GattClientConfigureMtu(conn_id, status, mtu) => {
+                bluetooth_gatt.lock().unwrap().client_configure_mtu(conn_id, status, mtu);
+            }
+
+            Message::GattClientPhyUpdated(conn_id, tx_phy, rx_phy, status) => {
+                bluetooth_gatt.lock().unwrap().client_phy_updated(conn_id, tx_phy, rx_phy, status);
+            }
+
+            Message::GattClientConnUpdated(conn_id, interval, latency, timeout, status) => {
+                bluetooth_gatt
+                    .lock()
+                    .unwrap()
+                    .client_conn_updated(conn_id, interval, latency, timeout, status);
+            }
+
+            Message::GattClientServiceChanged(conn_id) => {
+                bluetooth_gatt.lock().unwrap().client_service_changed(conn_id);
+            }
+
+            Message::GattClientServicesRediscovered(addr) => {
+                bluetooth_gatt.lock().unwrap().client_services_rediscovered(addr);
+            }
+
+            Message::GattClientReadPhy(client_id, addr, tx_phy, rx_phy, status) => {
+                bluetooth_gatt
+                    .lock()
+                    .unwrap()
+                    .client_read_phy(client_id, addr, tx_phy, rx_phy, status);
+            }
+
+            Message::GattServerConnection(conn_id, server_id, connected, addr) => {
+                bluetooth_gatt
+                    .lock()
+                    .unwrap()
+                    .server_connection(conn_id, server_id, connected, addr);
+            }
+
+            Message::GattServerIndicationSent(conn_id, status) => {
+                bluetooth_gatt.lock().unwrap().server_indication_sent(conn_id, status);
+            }
+
+            Message::GattServerCongestion(conn_id, congested) => {
+                bluetooth_gatt.lock().unwrap().server_congestion(conn_id, congested);
+            }
+
+            Message::GattServerMtuChanged(conn_id, mtu) => {
+                bluetooth_gatt.lock().unwrap().server_mtu_changed(conn_id, mtu);
             }
+
+            Message::GattServerReadPhy(server_id, addr, tx_phy, rx_phy, status) => {
+                bluetooth_gatt
+                    .lock()
+                    .unwrap()
+                    .server_read_phy(server_id, addr, tx_phy, rx_phy, status);
+            }
+
+            Message::GattServerRequestWrite(
+                conn_id,
+                trans_id,
+                addr,
+                handle,
+                offset,
+                need_rsp,
+                is_prep,
+                value,
+            ) => {
+                bluetooth_gatt.lock().unwrap().server_request_write(
+                    conn_id, trans_id, addr, handle, offset, need_rsp, is_prep, value,
+                );
+            }
+
+            Message::GattServerRequestExecWrite(conn_id, trans_id, addr, exec_write) => {
+                bluetooth_gatt
+                    .lock()
+                    .unwrap()
+                    .server_request_exec_write(conn_id, trans_id, addr, exec_write);
+            }
+
+            Message::GattServerRequestRead(conn_id, trans_id, addr, handle, offset, is_long) => {
+                bluetooth_gatt
+                    .lock()
+                    .unwrap()
+                    .server_request_read(conn_id, trans_id, addr, handle, offset, is_long);
+            }
+
+            Message::GattHogpOperationRetry(conn_id) => {
+                bluetooth_gatt.lock().unwrap().hogp_operation_retry(conn_id);
+            }
+
+            Message::GattHogpUhidRequest(conn_id, request) => {
+                bluetooth_gatt.lock().unwrap().hogp_uhid_request(conn_id, request);
+            }
+
+            Message::DebugThroughputTestDone(addr, result) => {
+                bluetooth_debug.lock().unwrap().throughput_test_done(addr, result);
+            }
+
+            _ => eprintln!("Unexpected message in a device work queue"),
         }
     }
 }
@@ -106,5 +1183,172 @@ impl Stack {
 /// RPC object. Therefore the object may be disconnected and thus should implement
 /// `register_disconnect` to let others observe the disconnection event.
 pub trait RPCProxy {
-    fn register_disconnect(&mut self, f: Box<dyn Fn() + Send>);
+    /// Registers `f` to be called when the remote object disconnects. Returns an id that
+    /// `unregister` takes.
+    fn register_disconnect(&mut self, f: Box<dyn Fn() + Send>) -> u32;
+
+    /// Unregisters a function registered with `register_disconnect`, e.g. because the client
+    /// unregistered the callback explicitly. Returns false if there is no such function.
+    fn unregister(&mut self, id: u32) -> bool;
+}
+
+/// Callbacks registered by the clients of an API, each identified by an id.
+///
+/// A callback is removed once its client disconnects: `disconnected_message` is posted to the
+/// dispatch loop with the id of the callback, and the API handles it by calling
+/// `remove_callback`, along with cleaning up any state of the client.
+pub struct Callbacks<T: ?Sized + RPCProxy + Send> {
+    /// Callbacks with their ids and the ids of their disconnect functions.
+    callbacks: Vec<(u32, u32, Box<T>)>,
+    last_id: u32,
+    tx: MessageSender,
+    disconnected_message: fn(u32) -> Message,
+}
+
+impl<T: ?Sized + RPCProxy + Send> Callbacks<T> {
+    pub fn new(tx: MessageSender, disconnected_message: fn(u32) -> Message) -> Callbacks<T> {
+        Callbacks { callbacks: vec![], last_id: 0, tx, disconnected_message }
+    }
+
+    /// Adds a callback and returns its id.
+    pub fn add_callback(&mut self, mut callback: Box<T>) -> u32 {
+        // Ids wrap around, skipping the ones still in use.
+        loop {
+            self.last_id = self.last_id.wrapping_add(1);
+            if self.get_by_id(self.last_id).is_none() {
+                break;
+            }
+        }
+
+        let id = self.last_id;
+        let tx = self.tx.clone();
+        let disconnected_message = self.disconnected_message;
+        let disconnect_id = callback.register_disconnect(Box::new(move || {
+            let tx = tx.clone();
+            topstack::get_runtime().spawn(async move {
+                let _result = tx.send(disconnected_message(id)).await;
+            });
+        }));
+
+        self.callbacks.push((id, disconnect_id, callback));
+        id
+    }
+
+    /// Removes a callback, no longer watching for the disconnection of its client. Returns false
+    /// if there is no callback with this id.
+    pub fn remove_callback(&mut self, id: u32) -> bool {
+        let index = match self.callbacks.iter().position(|(callback_id, _, _)| *callback_id == id) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let (_, disconnect_id, mut callback) = self.callbacks.remove(index);
+        callback.unregister(disconnect_id);
+        true
+    }
+
+    pub fn get_by_id(&self, id: u32) -> Option<&T> {
+        self.callbacks
+            .iter()
+            .find(|(callback_id, _, _)| *callback_id == id)
+            .map(|(_, _, callback)| callback.as_ref())
+    }
+
+    /// Iterates over the callbacks in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.callbacks.iter().map(|(_, _, callback)| callback.as_ref())
+    }
+
+    /// Iterates over the callbacks along with their ids, in the order they were added.
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (u32, &T)> {
+        self.callbacks.iter().map(|(id, _, callback)| (*id, callback.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_messages_keep_their_order() {
+        let addr = ffi::RustRawAddress { address: [0x11, 0x22, 0x33, 0x44, 0x55, 0x66] };
+        let messages = vec![
+            Message::GattClientNotify(1, addr, 0x10, false, vec![1]),
+            Message::GattClientDisconnect(1, 0, 3, addr),
+            Message::BluetoothRemoteRssiRead(addr, -40, 0),
+            Message::BluetoothAclStateChanged(0, addr, BtAclState::Disconnected, 0x13),
+            Message::BluetoothAdapterStateChanged(BtState::Off),
+        ];
+        let count = messages.len();
+
+        let (tx, mut rx) = Stack::create_channel();
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let received = runtime.block_on(async {
+            for message in messages {
+                tx.send(message).await.unwrap();
+            }
+
+            let mut received = vec![];
+            while received.len() < count {
+                received.push(rx.recv().await.unwrap().name());
+            }
+            received
+        });
+
+        // Only the adapter state change is not about the device, and overtakes the others.
+        assert_eq!(
+            received,
+            vec![
+                "BluetoothAdapterStateChanged",
+                "GattClientNotify",
+                "GattClientDisconnect",
+                "BluetoothRemoteRssiRead",
+                "BluetoothAclStateChanged",
+            ]
+        );
+    }
+
+    #[test]
+    fn replay_channel_keeps_the_order() {
+        let addr = ffi::RustRawAddress { address: [0x11, 0x22, 0x33, 0x44, 0x55, 0x66] };
+        let messages = vec![
+            Message::GattScannerReportTick(5),
+            Message::BluetoothAdapterStateChanged(BtState::On),
+            Message::A2dpReconnect(BDAddr::from_string("11:22:33:44:55:66").unwrap(), 2),
+            Message::BluetoothRemoteRssiRead(addr, -40, 0),
+            Message::BluetoothDiscoverableTimeout(3),
+        ];
+
+        let (tx, mut rx) = Stack::create_replay_channel();
+        // As `Stack::dispatch` does.
+        let mut media_rx = rx.media.take().unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let (received, media) = runtime.block_on(async {
+            let send = async {
+                for message in messages {
+                    tx.send(message).await.unwrap();
+                }
+            };
+            let receive = async {
+                let mut received = vec![];
+                while received.len() < 4 {
+                    received.push(rx.recv().await.unwrap().name());
+                }
+                received
+            };
+            let ((), received) = tokio::join!(send, receive);
+            (received, media_rx.recv().await.unwrap().name())
+        });
+
+        assert_eq!(
+            received,
+            vec![
+                "GattScannerReportTick",
+                "BluetoothAdapterStateChanged",
+                "BluetoothRemoteRssiRead",
+                "BluetoothDiscoverableTimeout",
+            ]
+        );
+        assert_eq!(media, "A2dpReconnect");
+    }
 }
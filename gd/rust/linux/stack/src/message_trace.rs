@@ -0,0 +1,299 @@
+//! Recording of the messages dispatched by the stack, and their replay.
+//!
+//! A trace holds a message per line, as JSON, with the time it was dispatched at since the
+//! recording started. Replaying a trace captured on a device feeds the callbacks of the native
+//! stack back into the stack in the same order, e.g. into a stack built against fake interfaces
+//! in a test, to reproduce the sequence of events that led to a bug.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
+
+use crate::Message;
+
+/// Where the daemon records the trace when `Feature::MessageTrace` is on.
+pub const MESSAGE_TRACE_PATH: &str = "/var/log/bluetooth/message_trace.jsonl";
+
+/// Size of a trace past which it is moved to `<path>.old` and a new one is started, so that the
+/// traces take at most twice as much.
+const MESSAGE_TRACE_MAX_LEN: u64 = 16 * 1024 * 1024;
+
+/// Lines waiting to be written to the trace. The messages recorded while it is full are dropped
+/// rather than slowing the dispatch loops down.
+const MESSAGE_TRACE_QUEUE_LEN: usize = 256;
+
+/// A line of a trace.
+#[derive(Serialize, Deserialize)]
+struct TraceRecord<M> {
+    /// Milliseconds since the recording started.
+    timestamp_ms: u64,
+    message: M,
+}
+
+/// Records the messages dispatched by the stack to a trace, shared by the dispatch loops.
+///
+/// The trace is written by a thread of its own, so that recording doesn't block the dispatch
+/// loops on the disk.
+#[derive(Clone)]
+pub struct MessageRecorder {
+    lines: SyncSender<Vec<u8>>,
+    /// Number of messages dropped since the writer last caught up.
+    dropped: Arc<AtomicUsize>,
+    start: Instant,
+}
+
+impl MessageRecorder {
+    /// Starts recording to a new trace at `path`, replacing the previous one.
+    ///
+    /// The trace holds the keys and PINs of the pairings, so only the daemon may read it.
+    pub fn create(path: &str) -> io::Result<MessageRecorder> {
+        let file = create_trace(path)?;
+        let (recorder, lines) = MessageRecorder::new();
+
+        let path = path.to_string();
+        let dropped = recorder.dropped.clone();
+        thread::Builder::new()
+            .name("bt_message_trace".to_string())
+            .spawn(move || write_trace(&path, file, lines, &dropped, MESSAGE_TRACE_MAX_LEN))?;
+
+        Ok(recorder)
+    }
+
+    /// Returns a recorder and the lines it records.
+    fn new() -> (MessageRecorder, Receiver<Vec<u8>>) {
+        let (tx, rx) = sync_channel(MESSAGE_TRACE_QUEUE_LEN);
+        let recorder = MessageRecorder {
+            lines: tx,
+            dropped: Arc::new(AtomicUsize::new(0)),
+            start: Instant::now(),
+        };
+        (recorder, rx)
+    }
+
+    /// Appends a message to the trace.
+    pub(crate) fn record(&self, message: &Message) {
+        // The messages of the extensions are opaque to the stack.
+        if let Message::Extension(_) = message {
+            return;
+        }
+
+        let record = TraceRecord { timestamp_ms: self.start.elapsed().as_millis() as u64, message };
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Failed to record {}: {}", message.name(), e);
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        // The writer logs the messages dropped, and why it stops if it does.
+        if let Err(TrySendError::Full(_)) = self.lines.try_send(line) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn create_trace(path: &str) -> io::Result<File> {
+    OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)
+}
+
+/// Writes the lines of a recorder to the trace at `path` until every recorder is dropped. The
+/// trace is moved to `<path>.old` once it grows past `max_len`.
+fn write_trace(
+    path: &str,
+    mut file: File,
+    lines: Receiver<Vec<u8>>,
+    dropped: &AtomicUsize,
+    max_len: u64,
+) {
+    let mut len = 0;
+
+    for line in lines {
+        if len > 0 && len + line.len() as u64 > max_len {
+            let rotated =
+                fs::rename(path, format!("{}.old", path)).and_then(|()| create_trace(path));
+            file = match rotated {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("Failed to start a new message trace: {}", e);
+                    return;
+                }
+            };
+            len = 0;
+        }
+
+        if let Err(e) = file.write_all(&line) {
+            eprintln!("Failed to write the message trace: {}", e);
+            return;
+        }
+        len += line.len() as u64;
+
+        let count = dropped.swap(0, Ordering::Relaxed);
+        if count > 0 {
+            eprintln!("Dropped {} messages from the message trace", count);
+        }
+    }
+}
+
+/// Sends the messages of the trace at `path` to the stack through `tx`, in the order they were
+/// recorded. Returns the number of messages sent.
+///
+/// `tx` comes from `Stack::create_replay_channel`, so that the stack dispatches the messages in
+/// the recorded order rather than by lane.
+///
+/// If `keep_timing` is set, the messages are spaced as they were when recorded, otherwise they
+/// are sent as fast as the stack takes them. Replay fails at the first line that is not a
+/// message, and stops once the stack stops taking messages.
+pub async fn replay(path: &str, tx: Sender<Message>, keep_timing: bool) -> io::Result<usize> {
+    let trace = tokio::fs::read_to_string(path).await?;
+    let start = Instant::now();
+    let mut count = 0;
+
+    for line in trace.lines().filter(|line| !line.is_empty()) {
+        let record: TraceRecord<Message> = serde_json::from_str(line)?;
+
+        if keep_timing {
+            tokio::time::sleep_until(start + Duration::from_millis(record.timestamp_ms)).await;
+        }
+
+        if tx.send(record.message).await.is_err() {
+            break;
+        }
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bt_topshim::btif::ffi::RustRawAddress;
+    use bt_topshim::btif::{BtBondState, BtState};
+
+    use crate::hogp::UhidRequest;
+    use crate::{BDAddr, MessageLane, Stack};
+
+    fn messages() -> Vec<Message> {
+        let raw_addr = RustRawAddress { address: [0x11, 0x22, 0x33, 0x44, 0x55, 0x66] };
+        vec![
+            Message::BluetoothAdapterStateChanged(BtState::On),
+            Message::BluetoothBondStateChanged(0, raw_addr, BtBondState::Bonded),
+            Message::BluetoothDiscoverableTimeout(3),
+            Message::BluetoothRssiMonitorTick(BDAddr::from_string("11:22:33:44:55:66").unwrap()),
+            Message::GattHogpUhidRequest(
+                1,
+                UhidRequest::SetReport { id: 2, report_id: 1, report_type: 1, data: vec![1, 4] },
+            ),
+            Message::GattScannerReportTick(5),
+            Message::A2dpReconnect(BDAddr::from_string("11:22:33:44:55:66").unwrap(), 2),
+            Message::BluetoothDiscoverableTimeout(4),
+        ]
+    }
+
+    fn to_json(message: &Message) -> String {
+        serde_json::to_string(message).unwrap()
+    }
+
+    fn trace_path(name: &str) -> String {
+        let name = format!("message_trace_{}_{}.jsonl", name, std::process::id());
+        std::env::temp_dir().join(name).to_str().unwrap().to_string()
+    }
+
+    /// Records messages to a new trace, as the thread of `MessageRecorder::create` would.
+    fn record(path: &str, messages: &[Message], max_len: u64) {
+        let (recorder, lines) = MessageRecorder::new();
+        for message in messages {
+            recorder.record(message);
+        }
+        let dropped = recorder.dropped.clone();
+        drop(recorder);
+
+        write_trace(path, create_trace(path).unwrap(), lines, &dropped, max_len);
+    }
+
+    #[test]
+    fn message_round_trip() {
+        for message in messages() {
+            let json = to_json(&message);
+            let parsed: Message = serde_json::from_str(&json).unwrap();
+
+            assert_eq!(parsed.name(), message.name());
+            assert_eq!(to_json(&parsed), json);
+        }
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let messages = messages();
+        // Messages of every lane, which must not overtake each other.
+        let lanes: Vec<MessageLane> = messages.iter().map(|m| m.lane()).collect();
+        assert!(lanes.contains(&MessageLane::Control));
+        assert!(lanes.contains(&MessageLane::Data));
+        assert!(lanes.contains(&MessageLane::Media));
+        let path = trace_path("replay");
+        record(&path, &messages, MESSAGE_TRACE_MAX_LEN);
+
+        let (tx, mut rx) = Stack::create_replay_channel();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        let (count, replayed) = runtime.block_on(async {
+            let receive = async {
+                let mut replayed = vec![];
+                while replayed.len() < messages.len() {
+                    replayed.push(rx.recv().await.unwrap());
+                }
+                replayed
+            };
+            tokio::join!(replay(&path, tx, false), receive)
+        });
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(count.unwrap(), messages.len());
+        let replayed: Vec<String> = replayed.iter().map(to_json).collect();
+        let expected: Vec<String> = messages.iter().map(to_json).collect();
+        assert_eq!(replayed, expected);
+    }
+
+    #[test]
+    fn rotate_full_trace() {
+        let messages = messages();
+        let path = trace_path("rotate");
+        // Every line is a trace of its own.
+        record(&path, &messages, 1);
+
+        let old_path = format!("{}.old", path);
+        let trace = fs::read_to_string(&path).unwrap();
+        let old_trace = fs::read_to_string(&old_path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&old_path).unwrap();
+
+        let last: TraceRecord<Message> = serde_json::from_str(trace.trim_end()).unwrap();
+        let second_to_last: TraceRecord<Message> =
+            serde_json::from_str(old_trace.trim_end()).unwrap();
+        assert_eq!(to_json(&last.message), to_json(&messages[messages.len() - 1]));
+        assert_eq!(to_json(&second_to_last.message), to_json(&messages[messages.len() - 2]));
+    }
+
+    #[test]
+    fn drop_messages_while_behind() {
+        let (recorder, lines) = MessageRecorder::new();
+        for _ in 0..MESSAGE_TRACE_QUEUE_LEN + 3 {
+            recorder.record(&Message::GattScannerReportTick(5));
+        }
+
+        assert_eq!(recorder.dropped.load(Ordering::Relaxed), 3);
+        drop(recorder);
+        assert_eq!(lines.iter().count(), MESSAGE_TRACE_QUEUE_LEN);
+    }
+}
@@ -0,0 +1,384 @@
+//! Counters and latency histograms of the health of the stack, e.g. how often pairing succeeds,
+//! for the platform telemetry to consume.
+//!
+//! The modules record into a shared `Metrics`, which hands what was recorded to its exporters
+//! every `METRICS_EXPORT_INTERVAL` and then starts over, so that each export covers one period.
+
+use bt_topshim::topstack;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Interval between two exports of the metrics.
+const METRICS_EXPORT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Upper bounds of the buckets of the latency histograms, in milliseconds. Longer latencies go to
+/// an overflow bucket.
+pub const LATENCY_BUCKETS_MS: [u64; 10] = [10, 20, 50, 100, 200, 500, 1000, 2000, 5000, 10000];
+
+/// Bonds that succeeded.
+pub const PAIRING_SUCCEEDED: &str = "pairing.succeeded";
+/// Bonds that failed, also counted by `BondStatus` as e.g. `pairing.failed.Timeout`.
+pub const PAIRING_FAILED: &str = "pairing.failed";
+/// Time from `IBluetoothMedia::connect` to the A2DP connection.
+pub const A2DP_CONNECTION_LATENCY: &str = "a2dp.connection_latency";
+/// Advertisements received while scanning.
+pub const SCAN_RESULTS: &str = "scan.results";
+/// Time from `IBluetoothGatt::write_characteristic` to the write response.
+pub const GATT_WRITE_LATENCY: &str = "gatt.write_latency";
+/// Callbacks from the native stack whose handler panicked, see `topstack::catch_callback_panic`.
+pub const CALLBACK_PANICS: &str = "callback.panics";
+/// Time the traced D-Bus method calls take, by method as e.g.
+/// `dbus.call_latency.org.chromium.bluetooth.Bluetooth.GetAddress`, see `Feature::MethodTrace`.
+pub const DBUS_CALL_LATENCY: &str = "dbus.call_latency";
+/// Traced D-Bus method calls replied with an error, by method as for `DBUS_CALL_LATENCY`.
+pub const DBUS_CALL_ERRORS: &str = "dbus.call_errors";
+
+/// Distribution of latencies over the `LATENCY_BUCKETS_MS` buckets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    /// Number of latencies in each bucket, the overflow bucket last.
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_ms: u64,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram { buckets: vec![0; LATENCY_BUCKETS_MS.len() + 1], count: 0, sum_ms: 0 }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        let ms = latency.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+    }
+}
+
+/// The metrics recorded over a period.
+#[derive(Clone, Debug)]
+pub struct MetricsSnapshot {
+    pub period: Duration,
+    pub counters: BTreeMap<String, u64>,
+    pub histograms: BTreeMap<String, Histogram>,
+}
+
+impl MetricsSnapshot {
+    /// Returns how many times per second a counter was incremented over the period, e.g. to get
+    /// the rate of the scan results.
+    pub fn rate(&self, counter: &str) -> f64 {
+        match self.counters.get(counter) {
+            Some(count) if !self.period.is_zero() => *count as f64 / self.period.as_secs_f64(),
+            _ => 0.0,
+        }
+    }
+
+    /// Formats the snapshot as text, a metric per line.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("period_s {}\n", self.period.as_secs());
+
+        for (name, count) in &self.counters {
+            text += &format!("{} {}\n", name, count);
+        }
+
+        for (name, histogram) in &self.histograms {
+            let buckets: Vec<String> =
+                histogram.buckets.iter().map(|count| count.to_string()).collect();
+            text += &format!(
+                "{} count {} sum_ms {} buckets {}\n",
+                name,
+                histogram.count,
+                histogram.sum_ms,
+                buckets.join(",")
+            );
+        }
+
+        text
+    }
+}
+
+/// A consumer of the metrics, e.g. the platform telemetry.
+pub trait MetricsExporter: Send {
+    /// Returns the name of the exporter, used for logging.
+    fn name(&self) -> String;
+
+    /// Exports what was recorded during the period that just ended.
+    fn export(&mut self, snapshot: &MetricsSnapshot);
+}
+
+/// Writes the metrics to the log.
+pub struct LogExporter;
+
+impl MetricsExporter for LogExporter {
+    fn name(&self) -> String {
+        String::from("log")
+    }
+
+    fn export(&mut self, snapshot: &MetricsSnapshot) {
+        print!("Stack metrics:\n{}", snapshot.to_text());
+    }
+}
+
+/// Sends the metrics as text to a Unix datagram socket, e.g. the one of a telemetry daemon.
+///
+/// Nothing is buffered, the metrics of a period are lost if the socket is not there to receive
+/// them.
+pub struct SocketExporter {
+    socket: UnixDatagram,
+    path: String,
+}
+
+impl SocketExporter {
+    pub fn new(path: &str) -> io::Result<SocketExporter> {
+        let socket = UnixDatagram::unbound()?;
+        socket.set_nonblocking(true)?;
+        Ok(SocketExporter { socket, path: path.to_string() })
+    }
+}
+
+impl MetricsExporter for SocketExporter {
+    fn name(&self) -> String {
+        format!("socket {}", self.path)
+    }
+
+    fn export(&mut self, snapshot: &MetricsSnapshot) {
+        if let Err(e) = self.socket.send_to(snapshot.to_text().as_bytes(), &self.path) {
+            eprintln!("Failed to send the metrics to {}: {}", self.path, e);
+        }
+    }
+}
+
+/// Metrics recorded by the modules of the stack since the last export.
+pub struct Metrics {
+    counters: BTreeMap<String, u64>,
+    histograms: BTreeMap<String, Histogram>,
+    /// Start of the current period.
+    since: Instant,
+    /// `topstack::callback_panic_count` at the start of the current period. The panics are
+    /// counted by the topshim, which can't record into `Metrics`.
+    callback_panics_since: u64,
+    exporters: Vec<Box<dyn MetricsExporter>>,
+}
+
+impl Metrics {
+    /// Constructs the metrics, with no exporter.
+    pub fn new() -> Metrics {
+        Metrics {
+            counters: BTreeMap::new(),
+            histograms: BTreeMap::new(),
+            since: Instant::now(),
+            callback_panics_since: topstack::callback_panic_count(),
+            exporters: vec![],
+        }
+    }
+
+    pub fn register_exporter(&mut self, exporter: Box<dyn MetricsExporter>) {
+        println!("Exporting the stack metrics to {}", exporter.name());
+        self.exporters.push(exporter);
+    }
+
+    pub(crate) fn increment(&mut self, counter: &str) {
+        *self.counters.entry(counter.to_string()).or_default() += 1;
+    }
+
+    pub(crate) fn record_latency(&mut self, histogram: &str, latency: Duration) {
+        self.histograms.entry(histogram.to_string()).or_insert_with(Histogram::new).record(latency);
+    }
+
+    /// Records a D-Bus method call of `iface` that took `duration`, and failed if it was replied
+    /// with an error.
+    pub fn record_method_call(
+        &mut self,
+        iface: &str,
+        method: &str,
+        duration: Duration,
+        failed: bool,
+    ) {
+        self.record_latency(&format!("{}.{}.{}", DBUS_CALL_LATENCY, iface, method), duration);
+        if failed {
+            self.increment(&format!("{}.{}.{}", DBUS_CALL_ERRORS, iface, method));
+        }
+    }
+
+    /// Returns what was recorded during the current period, e.g. for a debug dump.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.snapshot_at(topstack::callback_panic_count())
+    }
+
+    /// Returns what was recorded during the current period, given the callback panics counted
+    /// by the topshim so far.
+    fn snapshot_at(&self, callback_panics: u64) -> MetricsSnapshot {
+        let mut counters = self.counters.clone();
+        let panics = callback_panics.saturating_sub(self.callback_panics_since);
+        if panics > 0 {
+            counters.insert(CALLBACK_PANICS.to_string(), panics);
+        }
+
+        MetricsSnapshot {
+            period: self.since.elapsed(),
+            counters,
+            histograms: self.histograms.clone(),
+        }
+    }
+
+    /// Hands what was recorded to the exporters, and starts a new period.
+    pub fn export(&mut self) {
+        let callback_panics = topstack::callback_panic_count();
+        let snapshot = self.snapshot_at(callback_panics);
+        for exporter in &mut self.exporters {
+            exporter.export(&snapshot);
+        }
+
+        self.counters.clear();
+        self.histograms.clear();
+        self.since = Instant::now();
+        self.callback_panics_since = callback_panics;
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+/// Exports the metrics every `METRICS_EXPORT_INTERVAL`.
+pub fn start_export(metrics: Arc<Mutex<Metrics>>) {
+    topstack::get_runtime().spawn(async move {
+        loop {
+            tokio::time::sleep(METRICS_EXPORT_INTERVAL).await;
+            metrics.lock().unwrap().export();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps the snapshots it is handed.
+    struct FakeExporter {
+        snapshots: Arc<Mutex<Vec<MetricsSnapshot>>>,
+    }
+
+    impl MetricsExporter for FakeExporter {
+        fn name(&self) -> String {
+            String::from("fake")
+        }
+
+        fn export(&mut self, snapshot: &MetricsSnapshot) {
+            self.snapshots.lock().unwrap().push(snapshot.clone());
+        }
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram = Histogram::new();
+        for ms in [0, 10, 11, 10000, 10001, 60000] {
+            histogram.record(Duration::from_millis(ms));
+        }
+
+        // A latency goes to the first bucket whose bound it doesn't exceed.
+        assert_eq!(histogram.buckets, vec![2, 1, 0, 0, 0, 0, 0, 0, 0, 1, 2]);
+        assert_eq!(histogram.count, 6);
+        assert_eq!(histogram.sum_ms, 80022);
+    }
+
+    #[test]
+    fn snapshot_to_text() {
+        let mut histogram = Histogram::new();
+        histogram.record(Duration::from_millis(15));
+        histogram.record(Duration::from_millis(15000));
+        let snapshot = MetricsSnapshot {
+            period: Duration::from_secs(300),
+            counters: BTreeMap::from([
+                (PAIRING_SUCCEEDED.to_string(), 3),
+                (PAIRING_FAILED.to_string(), 1),
+            ]),
+            histograms: BTreeMap::from([(GATT_WRITE_LATENCY.to_string(), histogram)]),
+        };
+
+        assert_eq!(
+            snapshot.to_text(),
+            "period_s 300\n\
+             pairing.failed 1\n\
+             pairing.succeeded 3\n\
+             gatt.write_latency count 2 sum_ms 15015 buckets 0,1,0,0,0,0,0,0,0,0,1\n"
+        );
+    }
+
+    #[test]
+    fn snapshot_rate() {
+        let snapshot = MetricsSnapshot {
+            period: Duration::from_secs(4),
+            counters: BTreeMap::from([(SCAN_RESULTS.to_string(), 10)]),
+            histograms: BTreeMap::new(),
+        };
+        assert_eq!(snapshot.rate(SCAN_RESULTS), 2.5);
+        assert_eq!(snapshot.rate(PAIRING_SUCCEEDED), 0.0);
+
+        let empty = MetricsSnapshot { period: Duration::ZERO, ..snapshot };
+        assert_eq!(empty.rate(SCAN_RESULTS), 0.0);
+    }
+
+    #[test]
+    fn export_starts_new_period() {
+        let snapshots = Arc::new(Mutex::new(vec![]));
+        let mut metrics = Metrics::new();
+        metrics.register_exporter(Box::new(FakeExporter { snapshots: snapshots.clone() }));
+
+        metrics.increment(SCAN_RESULTS);
+        metrics.increment(SCAN_RESULTS);
+        metrics.record_latency(A2DP_CONNECTION_LATENCY, Duration::from_millis(120));
+        metrics.export();
+        metrics.export();
+
+        let snapshots = snapshots.lock().unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].counters.get(SCAN_RESULTS), Some(&2));
+        assert_eq!(snapshots[0].histograms[A2DP_CONNECTION_LATENCY].count, 1);
+        assert!(snapshots[1].counters.is_empty());
+        assert!(snapshots[1].histograms.is_empty());
+    }
+
+    #[test]
+    fn record_method_calls() {
+        let mut metrics = Metrics::new();
+        let iface = "org.chromium.bluetooth.Bluetooth";
+        metrics.record_method_call(iface, "GetAddress", Duration::from_millis(5), false);
+        metrics.record_method_call(iface, "GetAddress", Duration::from_millis(30), true);
+        metrics.record_method_call(iface, "GetName", Duration::from_millis(5), false);
+
+        let snapshot = metrics.snapshot();
+        let latency =
+            &snapshot.histograms["dbus.call_latency.org.chromium.bluetooth.Bluetooth.GetAddress"];
+        assert_eq!(latency.count, 2);
+        assert_eq!(latency.sum_ms, 35);
+        assert_eq!(
+            snapshot.counters.get("dbus.call_errors.org.chromium.bluetooth.Bluetooth.GetAddress"),
+            Some(&1)
+        );
+        assert_eq!(
+            snapshot.counters.get("dbus.call_errors.org.chromium.bluetooth.Bluetooth.GetName"),
+            None
+        );
+    }
+
+    #[test]
+    fn snapshot_counts_callback_panics_of_period() {
+        let mut metrics = Metrics::new();
+        metrics.callback_panics_since = 5;
+
+        assert_eq!(metrics.snapshot_at(5).counters.get(CALLBACK_PANICS), None);
+        assert_eq!(metrics.snapshot_at(7).counters.get(CALLBACK_PANICS), Some(&2));
+    }
+}
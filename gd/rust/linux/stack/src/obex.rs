@@ -0,0 +1,577 @@
+//! OBEX client sessions, the protocol that Object Push and the other file transfer profiles run
+//! on.
+//!
+//! Sessions run over any reliable byte stream, i.e. an RFCOMM or L2CAP socket connected to the
+//! OBEX server of a device.
+
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Version of the protocol, 1.0, sent when connecting.
+const OBEX_VERSION: u8 = 0x10;
+
+/// Longest packet the client accepts, told to the server when connecting.
+const MAX_PACKET_LEN: u16 = u16::MAX;
+
+/// Shortest maximum packet length a server may accept, which applies until it tells its own.
+const MIN_PACKET_LEN: u16 = 255;
+
+/// Length of the opcode or response code and the packet length that start every packet.
+const PACKET_HEADER_LEN: usize = 3;
+
+/// Length of the version, flags and maximum packet length that follow the packet header in
+/// CONNECT requests and responses.
+const CONNECT_FIELDS_LEN: usize = 4;
+
+/// Opcodes of the requests. The final bit marks the last packet of a request.
+const OPCODE_CONNECT: u8 = 0x80;
+const OPCODE_DISCONNECT: u8 = 0x81;
+const OPCODE_PUT: u8 = 0x02;
+const OPCODE_GET: u8 = 0x03;
+const OPCODE_ABORT: u8 = 0xFF;
+const OPCODE_FINAL: u8 = 0x80;
+
+/// Response codes, with the final bit set as it always is.
+const RESPONSE_CONTINUE: u8 = 0x90;
+const RESPONSE_SUCCESS: u8 = 0xA0;
+
+/// Header ids.
+const HEADER_NAME: u8 = 0x01;
+const HEADER_TYPE: u8 = 0x42;
+const HEADER_LENGTH: u8 = 0xC3;
+const HEADER_TARGET: u8 = 0x46;
+const HEADER_BODY: u8 = 0x48;
+const HEADER_END_OF_BODY: u8 = 0x49;
+const HEADER_WHO: u8 = 0x4A;
+const HEADER_CONNECTION_ID: u8 = 0xCB;
+
+/// Encodings of the headers, given by the upper 2 bits of their id. Unicode text and byte
+/// sequences are prefixed with their length, which counts the id and itself.
+const HEADER_ENCODING_MASK: u8 = 0xC0;
+const HEADER_ENCODING_U8: u8 = 0x80;
+const HEADER_ENCODING_U32: u8 = 0xC0;
+const HEADER_PREFIX_LEN: usize = 3;
+
+/// A header of an OBEX packet.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Header {
+    /// Name of the object, usually a file name.
+    Name(String),
+    /// MIME type of the object.
+    Type(String),
+    /// Length of the object, in bytes.
+    Length(u32),
+    /// Service the session connects to, e.g. the folder browsing service of FTP. Object Push
+    /// connects to the default service of the server.
+    Target(Vec<u8>),
+    /// Service the server connected the session to, in reply to `Target`.
+    Who(Vec<u8>),
+    /// A chunk of the object.
+    Body(Vec<u8>),
+    /// The last chunk of the object.
+    EndOfBody(Vec<u8>),
+    /// Session the server assigned when connecting, sent with every request.
+    ConnectionId(u32),
+    /// Any other header, with its id and its value as sent.
+    Other(u8, Vec<u8>),
+}
+
+impl Header {
+    fn id_and_value(&self) -> (u8, Vec<u8>) {
+        match self {
+            Header::Name(name) => {
+                let mut value = vec![];
+                for unit in name.encode_utf16() {
+                    value.extend_from_slice(&unit.to_be_bytes());
+                }
+                // Only non-empty names are null terminated.
+                if !value.is_empty() {
+                    value.extend_from_slice(&[0, 0]);
+                }
+                (HEADER_NAME, value)
+            }
+            Header::Type(mime_type) => {
+                let mut value = mime_type.as_bytes().to_vec();
+                value.push(0);
+                (HEADER_TYPE, value)
+            }
+            Header::Length(len) => (HEADER_LENGTH, len.to_be_bytes().to_vec()),
+            Header::Target(target) => (HEADER_TARGET, target.clone()),
+            Header::Who(who) => (HEADER_WHO, who.clone()),
+            Header::Body(chunk) => (HEADER_BODY, chunk.clone()),
+            Header::EndOfBody(chunk) => (HEADER_END_OF_BODY, chunk.clone()),
+            Header::ConnectionId(id) => (HEADER_CONNECTION_ID, id.to_be_bytes().to_vec()),
+            Header::Other(id, value) => (*id, value.clone()),
+        }
+    }
+
+    fn from_id_and_value(id: u8, value: &[u8]) -> Header {
+        let text = |value: &[u8]| match value.split_last() {
+            Some((0, text)) => text.to_vec(),
+            _ => value.to_vec(),
+        };
+        let to_u32 = |value: &[u8]| u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+
+        match id {
+            HEADER_NAME => {
+                let units: Vec<u16> =
+                    value.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+                let units = units.strip_suffix(&[0]).unwrap_or(&units);
+                Header::Name(String::from_utf16_lossy(units))
+            }
+            HEADER_TYPE => Header::Type(String::from_utf8_lossy(&text(value)).into_owned()),
+            HEADER_LENGTH => Header::Length(to_u32(value)),
+            HEADER_TARGET => Header::Target(value.to_vec()),
+            HEADER_WHO => Header::Who(value.to_vec()),
+            HEADER_BODY => Header::Body(value.to_vec()),
+            HEADER_END_OF_BODY => Header::EndOfBody(value.to_vec()),
+            HEADER_CONNECTION_ID => Header::ConnectionId(to_u32(value)),
+            _ => Header::Other(id, value.to_vec()),
+        }
+    }
+
+    fn encode(&self, packet: &mut Vec<u8>) {
+        let (id, value) = self.id_and_value();
+        packet.push(id);
+        match id & HEADER_ENCODING_MASK {
+            HEADER_ENCODING_U8 | HEADER_ENCODING_U32 => {}
+            _ => {
+                let len = (value.len() + HEADER_PREFIX_LEN) as u16;
+                packet.extend_from_slice(&len.to_be_bytes());
+            }
+        }
+        packet.extend_from_slice(&value);
+    }
+}
+
+/// Parses the headers that end a packet.
+fn parse_headers(mut data: &[u8]) -> Result<Vec<Header>, ObexError> {
+    let mut headers = vec![];
+
+    while let Some(&id) = data.first() {
+        let (value_start, len) = match id & HEADER_ENCODING_MASK {
+            HEADER_ENCODING_U8 => (1, 2),
+            HEADER_ENCODING_U32 => (1, 5),
+            _ => match data.get(1..HEADER_PREFIX_LEN) {
+                Some(len) => (HEADER_PREFIX_LEN, u16::from_be_bytes([len[0], len[1]]).into()),
+                None => (HEADER_PREFIX_LEN, 0),
+            },
+        };
+
+        if len < value_start || len > data.len() {
+            return Err(ObexError::Malformed(format!("header {:#04x} is truncated", id)));
+        }
+        headers.push(Header::from_id_and_value(id, &data[value_start..len]));
+        data = &data[len..];
+    }
+
+    Ok(headers)
+}
+
+/// Failure of an OBEX request.
+#[derive(Debug)]
+pub enum ObexError {
+    /// The transport failed, e.g. because the device disconnected.
+    Io(io::Error),
+    /// The server sent a packet that doesn't follow the protocol.
+    Malformed(String),
+    /// The server refused the request with this response code, e.g. 0xC3 for Forbidden.
+    Refused(u8),
+    /// The request doesn't fit in a packet the server accepts, e.g. because of a long name.
+    TooLong,
+    /// The request was cancelled through its progress callback.
+    Aborted,
+}
+
+impl Display for ObexError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ObexError::Io(e) => write!(f, "transport failed: {}", e),
+            ObexError::Malformed(reason) => write!(f, "malformed response: {}", reason),
+            ObexError::Refused(code) => write!(f, "refused with response code {:#04x}", code),
+            ObexError::TooLong => write!(f, "request too long for the server"),
+            ObexError::Aborted => write!(f, "request aborted"),
+        }
+    }
+}
+
+impl From<io::Error> for ObexError {
+    fn from(e: io::Error) -> Self {
+        ObexError::Io(e)
+    }
+}
+
+/// Response of the server to the last packet of a request.
+struct Response {
+    code: u8,
+    /// Fields between the packet header and the headers, only in CONNECT responses.
+    fields: Vec<u8>,
+    headers: Vec<Header>,
+}
+
+/// Client end of an OBEX session.
+///
+/// Requests are made one at a time, each taking as many packets as the maximum packet length
+/// of the server requires. A request fails as soon as the server refuses one of its packets.
+pub struct ObexClient<T> {
+    transport: T,
+    /// Longest packet the server accepts, as it told when connecting.
+    max_packet_len: usize,
+    /// Session the server assigned when connecting, if it did.
+    connection_id: Option<u32>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> ObexClient<T> {
+    /// Constructs a client over a transport connected to an OBEX server. The session starts with
+    /// `connect`.
+    pub fn new(transport: T) -> ObexClient<T> {
+        ObexClient { transport, max_packet_len: MIN_PACKET_LEN.into(), connection_id: None }
+    }
+
+    /// Connects the session to a service of the server, or to its default service if `target`
+    /// is None, and returns the headers of the response.
+    pub async fn connect(&mut self, target: Option<&[u8]>) -> Result<Vec<Header>, ObexError> {
+        let mut fields = vec![OBEX_VERSION, 0];
+        fields.extend_from_slice(&MAX_PACKET_LEN.to_be_bytes());
+        let headers: Vec<Header> =
+            target.map(|target| Header::Target(target.to_vec())).into_iter().collect();
+
+        let response = self.request(OPCODE_CONNECT, &fields, &headers).await?;
+        let max_packet_len = u16::from_be_bytes([response.fields[2], response.fields[3]]);
+        if max_packet_len < MIN_PACKET_LEN {
+            return Err(ObexError::Malformed(format!("packet length {}", max_packet_len)));
+        }
+
+        self.max_packet_len = max_packet_len.into();
+        self.connection_id = response.headers.iter().find_map(|header| match header {
+            Header::ConnectionId(id) => Some(*id),
+            _ => None,
+        });
+        Ok(response.headers)
+    }
+
+    /// Ends the session. The transport can be closed once this returns.
+    pub async fn disconnect(&mut self) -> Result<(), ObexError> {
+        self.request(OPCODE_DISCONNECT, &[], &[]).await?;
+        self.connection_id = None;
+        Ok(())
+    }
+
+    /// Sends an object to the server, e.g. a file to the inbox of a phone with Object Push.
+    ///
+    /// `progress` is called with the number of bytes sent after each packet, and aborts the
+    /// request if it returns false.
+    pub async fn put<F: FnMut(usize) -> bool>(
+        &mut self,
+        name: &str,
+        mime_type: Option<&str>,
+        data: &[u8],
+        mut progress: F,
+    ) -> Result<(), ObexError> {
+        let len = u32::try_from(data.len()).map_err(|_| ObexError::TooLong)?;
+        let mut headers = vec![Header::Name(name.to_string())];
+        headers.extend(mime_type.map(|mime_type| Header::Type(mime_type.to_string())));
+        headers.push(Header::Length(len));
+
+        let mut sent = 0;
+        loop {
+            // The object takes the room the other headers leave in the packet.
+            let room = self
+                .max_packet_len
+                .checked_sub(PACKET_HEADER_LEN + self.headers_len(&headers) + HEADER_PREFIX_LEN)
+                .ok_or(ObexError::TooLong)?;
+            let chunk = &data[sent..data.len().min(sent + room)];
+            let last = sent + chunk.len() == data.len();
+
+            let (opcode, expected) = if last {
+                headers.push(Header::EndOfBody(chunk.to_vec()));
+                (OPCODE_PUT | OPCODE_FINAL, RESPONSE_SUCCESS)
+            } else {
+                headers.push(Header::Body(chunk.to_vec()));
+                (OPCODE_PUT, RESPONSE_CONTINUE)
+            };
+
+            let response = self.request(opcode, &[], &headers).await?;
+            if response.code != expected {
+                return Err(ObexError::Malformed(format!("response code {:#04x}", response.code)));
+            }
+
+            sent += chunk.len();
+            if last {
+                progress(sent);
+                return Ok(());
+            }
+            if !progress(sent) {
+                return self.abort().await;
+            }
+            headers.clear();
+        }
+    }
+
+    /// Fetches an object from the server, by name or type or both, e.g. the business card of the
+    /// owner of a phone with Object Push by the `text/x-vcard` type alone.
+    ///
+    /// `progress` is called with the number of bytes received after each packet, and aborts the
+    /// request if it returns false.
+    pub async fn get<F: FnMut(usize) -> bool>(
+        &mut self,
+        name: Option<&str>,
+        mime_type: Option<&str>,
+        mut progress: F,
+    ) -> Result<Vec<u8>, ObexError> {
+        let mut headers: Vec<Header> =
+            name.map(|name| Header::Name(name.to_string())).into_iter().collect();
+        headers.extend(mime_type.map(|mime_type| Header::Type(mime_type.to_string())));
+
+        let mut data = vec![];
+        loop {
+            let response = self.request(OPCODE_GET | OPCODE_FINAL, &[], &headers).await?;
+            for header in response.headers {
+                if let Header::Body(chunk) | Header::EndOfBody(chunk) = header {
+                    data.extend(chunk);
+                }
+            }
+
+            if response.code == RESPONSE_SUCCESS {
+                progress(data.len());
+                return Ok(data);
+            }
+            if !progress(data.len()) {
+                return self.abort().await;
+            }
+            headers.clear();
+        }
+    }
+
+    /// Aborts the request in progress, which fails with `ObexError::Aborted`.
+    async fn abort<R>(&mut self) -> Result<R, ObexError> {
+        self.request(OPCODE_ABORT, &[], &[]).await?;
+        Err(ObexError::Aborted)
+    }
+
+    /// Returns the length of the headers of a request, including the connection id.
+    fn headers_len(&self, headers: &[Header]) -> usize {
+        let mut encoded = vec![];
+        self.connection_id.map(Header::ConnectionId).iter().chain(headers).for_each(|header| {
+            header.encode(&mut encoded);
+        });
+        encoded.len()
+    }
+
+    /// Sends a packet of a request and reads the response to it. Responses other than Continue
+    /// and Success fail with `ObexError::Refused`.
+    async fn request(
+        &mut self,
+        opcode: u8,
+        fields: &[u8],
+        headers: &[Header],
+    ) -> Result<Response, ObexError> {
+        let mut packet = vec![opcode, 0, 0];
+        packet.extend_from_slice(fields);
+        if opcode != OPCODE_CONNECT {
+            if let Some(id) = self.connection_id {
+                Header::ConnectionId(id).encode(&mut packet);
+            }
+        }
+        for header in headers {
+            header.encode(&mut packet);
+        }
+
+        if packet.len() > self.max_packet_len {
+            return Err(ObexError::TooLong);
+        }
+        let len = packet.len() as u16;
+        packet[1..PACKET_HEADER_LEN].copy_from_slice(&len.to_be_bytes());
+        self.transport.write_all(&packet).await?;
+
+        let mut header = [0u8; PACKET_HEADER_LEN];
+        self.transport.read_exact(&mut header).await?;
+        let len = u16::from_be_bytes([header[1], header[2]]) as usize;
+        if len < PACKET_HEADER_LEN {
+            return Err(ObexError::Malformed(format!("packet length {}", len)));
+        }
+        let mut rest = vec![0u8; len - PACKET_HEADER_LEN];
+        self.transport.read_exact(&mut rest).await?;
+
+        let code = header[0];
+        if code != RESPONSE_CONTINUE && code != RESPONSE_SUCCESS {
+            return Err(ObexError::Refused(code));
+        }
+
+        let fields_len = if opcode == OPCODE_CONNECT { CONNECT_FIELDS_LEN } else { 0 };
+        if rest.len() < fields_len {
+            return Err(ObexError::Malformed(format!("packet length {}", len)));
+        }
+        let headers = parse_headers(&rest[fields_len..])?;
+        rest.truncate(fields_len);
+        Ok(Response { code, fields: rest, headers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::future::Future;
+    use tokio::io::DuplexStream;
+
+    fn run<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+    }
+
+    fn encode(headers: &[Header]) -> Vec<u8> {
+        let mut data = vec![];
+        for header in headers {
+            header.encode(&mut data);
+        }
+        data
+    }
+
+    /// Reads a request packet, returned as its opcode and the headers that follow `fields_len`
+    /// bytes of fields.
+    async fn read_request(server: &mut DuplexStream, fields_len: usize) -> (u8, Vec<Header>) {
+        let mut header = [0u8; PACKET_HEADER_LEN];
+        server.read_exact(&mut header).await.unwrap();
+        let len = u16::from_be_bytes([header[1], header[2]]) as usize;
+        let mut rest = vec![0u8; len - PACKET_HEADER_LEN];
+        server.read_exact(&mut rest).await.unwrap();
+        (header[0], parse_headers(&rest[fields_len..]).unwrap())
+    }
+
+    async fn respond(server: &mut DuplexStream, code: u8, fields: &[u8], headers: &[Header]) {
+        let mut packet = vec![code, 0, 0];
+        packet.extend_from_slice(fields);
+        packet.extend(encode(headers));
+        let len = packet.len() as u16;
+        packet[1..PACKET_HEADER_LEN].copy_from_slice(&len.to_be_bytes());
+        server.write_all(&packet).await.unwrap();
+    }
+
+    /// Answers a CONNECT with the shortest packet length, so that objects take several packets.
+    async fn accept_connect(server: &mut DuplexStream, connection_id: u32) {
+        let (opcode, _) = read_request(server, CONNECT_FIELDS_LEN).await;
+        assert_eq!(opcode, OPCODE_CONNECT);
+        let mut fields = vec![OBEX_VERSION, 0];
+        fields.extend_from_slice(&MIN_PACKET_LEN.to_be_bytes());
+        respond(server, RESPONSE_SUCCESS, &fields, &[Header::ConnectionId(connection_id)]).await;
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        let headers = vec![
+            Header::Name(String::from("photo.jpg")),
+            Header::Name(String::new()),
+            Header::Type(String::from("text/x-vcard")),
+            Header::Length(0x12345678),
+            Header::Target(vec![0xF9, 0xEC, 0x7B, 0xC4]),
+            Header::Body(vec![1, 2, 3]),
+            Header::EndOfBody(vec![]),
+            Header::ConnectionId(7),
+            Header::Other(0x10, vec![0xAB]),
+        ];
+        assert_eq!(parse_headers(&encode(&headers)).unwrap(), headers);
+    }
+
+    #[test]
+    fn truncated_header_is_malformed() {
+        let mut data = encode(&[Header::Body(vec![1, 2, 3])]);
+        data.pop();
+        assert!(matches!(parse_headers(&data), Err(ObexError::Malformed(_))));
+        assert!(matches!(parse_headers(&[HEADER_CONNECTION_ID, 0]), Err(ObexError::Malformed(_))));
+    }
+
+    #[test]
+    fn put_in_packets() {
+        let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let (client, mut server) = tokio::io::duplex(4096);
+
+        let serve = async {
+            accept_connect(&mut server, 7).await;
+
+            let mut received = vec![];
+            loop {
+                let (opcode, headers) = read_request(&mut server, 0).await;
+                assert_eq!(headers.first(), Some(&Header::ConnectionId(7)));
+                for header in headers {
+                    match header {
+                        Header::Body(chunk) | Header::EndOfBody(chunk) => received.extend(chunk),
+                        _ => {}
+                    }
+                }
+
+                if opcode == OPCODE_PUT | OPCODE_FINAL {
+                    respond(&mut server, RESPONSE_SUCCESS, &[], &[]).await;
+                    return received;
+                }
+                assert_eq!(opcode, OPCODE_PUT);
+                respond(&mut server, RESPONSE_CONTINUE, &[], &[]).await;
+            }
+        };
+
+        let mut progress = vec![];
+        let put = async {
+            let mut obex = ObexClient::new(client);
+            obex.connect(None).await.unwrap();
+            obex.put("data.bin", None, &data, |sent| {
+                progress.push(sent);
+                true
+            })
+            .await
+        };
+
+        let (result, received) = run(async { tokio::join!(put, serve) });
+        assert!(result.is_ok());
+        assert_eq!(received, data);
+        assert!(progress.len() > 2);
+        assert_eq!(progress.last(), Some(&data.len()));
+    }
+
+    #[test]
+    fn refused_request() {
+        let (client, mut server) = tokio::io::duplex(4096);
+
+        let serve = async {
+            accept_connect(&mut server, 1).await;
+            read_request(&mut server, 0).await;
+            respond(&mut server, 0xC3, &[], &[]).await;
+        };
+
+        let put = async {
+            let mut obex = ObexClient::new(client);
+            obex.connect(None).await.unwrap();
+            obex.put("secret.txt", Some("text/plain"), b"hello", |_| true).await
+        };
+
+        let (result, _) = run(async { tokio::join!(put, serve) });
+        assert!(matches!(result, Err(ObexError::Refused(0xC3))));
+    }
+
+    #[test]
+    fn get_aborted_by_progress() {
+        let (client, mut server) = tokio::io::duplex(4096);
+
+        let serve = async {
+            accept_connect(&mut server, 1).await;
+            let (opcode, headers) = read_request(&mut server, 0).await;
+            assert_eq!(opcode, OPCODE_GET | OPCODE_FINAL);
+            assert!(headers.contains(&Header::Type(String::from("text/x-vcard"))));
+            respond(&mut server, RESPONSE_CONTINUE, &[], &[Header::Body(vec![0; 100])]).await;
+
+            let (opcode, _) = read_request(&mut server, 0).await;
+            assert_eq!(opcode, OPCODE_ABORT);
+            respond(&mut server, RESPONSE_SUCCESS, &[], &[]).await;
+        };
+
+        let get = async {
+            let mut obex = ObexClient::new(client);
+            obex.connect(None).await.unwrap();
+            obex.get(None, Some("text/x-vcard"), |received| received < 100).await
+        };
+
+        let (result, _) = run(async { tokio::join!(get, serve) });
+        assert!(matches!(result, Err(ObexError::Aborted)));
+    }
+}
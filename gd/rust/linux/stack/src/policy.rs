@@ -0,0 +1,200 @@
+//! Policies applied by the stack on its own, without being asked by a client.
+//!
+//! For now this only covers connecting the profiles of bonded devices automatically.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::bluetooth_gatt::BluetoothGatt;
+use crate::bluetooth_media::{BluetoothMedia, IBluetoothMedia};
+use crate::hogp::HID_SERVICE_UUID;
+use crate::uuid::Uuid128;
+use crate::BDAddr;
+
+/// Profiles that the policy may connect automatically.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    A2dp,
+    Hid,
+    Hfp,
+}
+
+impl Profile {
+    /// Returns the service UUIDs that tell a remote device supports the profile.
+    fn service_uuids(&self) -> Vec<Uuid128> {
+        match self {
+            // Audio Sink, as the adapter is the source.
+            Profile::A2dp => vec![Uuid128::from_u16(0x110B)],
+            // HID over BR/EDR or over GATT.
+            Profile::Hid => vec![Uuid128::from_u16(0x1124), Uuid128::from_u16(HID_SERVICE_UUID)],
+            // Hands-Free, as the adapter is the audio gateway.
+            Profile::Hfp => vec![Uuid128::from_u16(0x111E)],
+        }
+    }
+}
+
+/// Configures when and which profiles get connected automatically.
+#[derive(Clone, Debug)]
+pub struct PolicyConfig {
+    /// Whether to connect the profiles of a device right after bonding with it.
+    pub connect_on_bond: bool,
+
+    /// Whether to connect the profiles of all bonded devices when the adapter is enabled.
+    pub connect_on_enable: bool,
+
+    /// The profiles to connect, in order.
+    pub profiles: Vec<Profile>,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        PolicyConfig {
+            connect_on_bond: true,
+            connect_on_enable: true,
+            profiles: vec![Profile::A2dp, Profile::Hid, Profile::Hfp],
+        }
+    }
+}
+
+/// Defines the policy API.
+pub trait IBluetoothPolicy {
+    /// Enables or disables connecting the profiles of a device automatically.
+    ///
+    /// Auto-connect is enabled for every device by default. Returns false if the address is
+    /// invalid.
+    fn set_auto_connect(&mut self, device: String, enable: bool) -> bool;
+
+    /// Returns whether the profiles of a device are connected automatically.
+    fn get_auto_connect(&self, device: String) -> bool;
+}
+
+/// Implementation of the policy API.
+pub struct BluetoothPolicy {
+    config: PolicyConfig,
+    bluetooth_media: Arc<Mutex<BluetoothMedia>>,
+    bluetooth_gatt: Arc<Mutex<BluetoothGatt>>,
+    // TODO: Persist this along with the other bonded device properties.
+    auto_connect_disabled: HashSet<BDAddr>,
+    // Devices to connect once their UUIDs are known.
+    waiting_for_uuids: HashSet<BDAddr>,
+}
+
+impl BluetoothPolicy {
+    /// Constructs the IBluetoothPolicy implementation.
+    pub fn new(
+        config: PolicyConfig,
+        bluetooth_media: Arc<Mutex<BluetoothMedia>>,
+        bluetooth_gatt: Arc<Mutex<BluetoothGatt>>,
+    ) -> BluetoothPolicy {
+        BluetoothPolicy {
+            config,
+            bluetooth_media,
+            bluetooth_gatt,
+            auto_connect_disabled: HashSet::new(),
+            waiting_for_uuids: HashSet::new(),
+        }
+    }
+
+    /// Called when bonding with a device succeeds, with its UUIDs if they are known.
+    pub(crate) fn on_device_bonded(&mut self, addr: BDAddr, uuids: Option<Vec<Uuid128>>) {
+        if self.config.connect_on_bond {
+            self.connect_profiles(addr, uuids);
+        }
+    }
+
+    /// Called when a bonded device is unblocked, with its UUIDs if they are known. Its profiles
+    /// are connected again as they are when the adapter is enabled, e.g. the HOGP host that was
+    /// told to forget it while blocked.
+    pub(crate) fn on_device_unblocked(&mut self, addr: BDAddr, uuids: Option<Vec<Uuid128>>) {
+        if self.config.connect_on_enable {
+            self.connect_profiles(addr, uuids);
+        }
+    }
+
+    /// Called when the adapter is enabled, with the bonded devices and their UUIDs if known.
+    pub(crate) fn on_adapter_enabled(
+        &mut self,
+        bonded_devices: Vec<(BDAddr, Option<Vec<Uuid128>>)>,
+    ) {
+        self.waiting_for_uuids.clear();
+
+        if !self.config.connect_on_enable {
+            return;
+        }
+
+        for (addr, uuids) in bonded_devices {
+            self.connect_profiles(addr, uuids);
+        }
+    }
+
+    /// Called when the UUIDs of a remote device are read or discovered.
+    pub(crate) fn on_remote_uuids_known(&mut self, addr: BDAddr, uuids: Vec<Uuid128>) {
+        if self.waiting_for_uuids.remove(&addr) {
+            self.connect_profiles(addr, Some(uuids));
+        }
+    }
+
+    /// Connects the enabled profiles that the device supports, or waits for its UUIDs if they
+    /// are not known yet so that e.g. A2DP is never attempted to a mouse.
+    fn connect_profiles(&mut self, addr: BDAddr, uuids: Option<Vec<Uuid128>>) {
+        if self.auto_connect_disabled.contains(&addr) {
+            return;
+        }
+
+        let uuids = match uuids {
+            Some(uuids) => uuids,
+            None => {
+                self.waiting_for_uuids.insert(addr);
+                return;
+            }
+        };
+
+        for profile in &self.config.profiles {
+            if !profile.service_uuids().iter().any(|uuid| uuids.contains(uuid)) {
+                continue;
+            }
+
+            match profile {
+                Profile::A2dp => {
+                    self.bluetooth_media.lock().unwrap().connect(addr.to_string());
+                }
+                // HID over BR/EDR is not supported by the stack yet.
+                Profile::Hid if uuids.contains(&Uuid128::from_u16(HID_SERVICE_UUID)) => {
+                    self.bluetooth_gatt.lock().unwrap().connect_hid_device(addr);
+                }
+                // TODO: Connect once the HID and HFP profiles are exposed by the stack.
+                Profile::Hid | Profile::Hfp => {
+                    println!("Auto-connect of {:?} to {:?} is not supported yet", profile, addr);
+                }
+            }
+        }
+    }
+}
+
+impl IBluetoothPolicy for BluetoothPolicy {
+    fn set_auto_connect(&mut self, device: String, enable: bool) -> bool {
+        let addr = match BDAddr::from_string(&device) {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+        if enable {
+            self.auto_connect_disabled.remove(&addr);
+        } else {
+            self.auto_connect_disabled.insert(addr);
+            self.waiting_for_uuids.remove(&addr);
+            // The HOGP host would otherwise keep connecting to the device whenever it advertises.
+            self.bluetooth_gatt.lock().unwrap().forget_hid_device(addr);
+        }
+
+        true
+    }
+
+    fn get_auto_connect(&self, device: String) -> bool {
+        match BDAddr::from_string(&device) {
+            Some(addr) => !self.auto_connect_disabled.contains(&addr),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Bluetooth UUIDs.
+
+use bt_topshim::btif::ffi::BtUuid;
+
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter, Result};
+
+/// The Bluetooth Base UUID, 00000000-0000-1000-8000-00805F9B34FB, that 16 and 32-bit UUIDs are
+/// short forms of.
+const BASE_UUID: [u8; 16] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0x80, 0x5F, 0x9B, 0x34, 0xFB,
+];
+
+/// Represents a Bluetooth UUID in its 128-bit form, most significant byte first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Uuid128 {
+    uu: [u8; 16],
+}
+
+impl Uuid128 {
+    pub fn from_bytes(uu: [u8; 16]) -> Uuid128 {
+        Uuid128 { uu }
+    }
+
+    /// Constructs the UUID of a 16-bit short form, e.g. 0x180D for the Heart Rate service.
+    pub fn from_u16(short: u16) -> Uuid128 {
+        Uuid128::from_u32(short.into())
+    }
+
+    /// Constructs the UUID of a 32-bit short form.
+    pub fn from_u32(short: u32) -> Uuid128 {
+        let mut uu = BASE_UUID;
+        uu[..4].copy_from_slice(&short.to_be_bytes());
+        Uuid128 { uu }
+    }
+
+    /// Parses a UUID of the form "0000180d-0000-1000-8000-00805f9b34fb", or of the short forms
+    /// "180d" and "0000180d". Hex digits may be of any case.
+    pub fn from_string(uuid: &str) -> Option<Uuid128> {
+        let all_hex = |s: &str| s.chars().all(|c| c.is_ascii_hexdigit());
+
+        match uuid.len() {
+            4 if all_hex(uuid) => u16::from_str_radix(uuid, 16).ok().map(Uuid128::from_u16),
+            8 if all_hex(uuid) => u32::from_str_radix(uuid, 16).ok().map(Uuid128::from_u32),
+            36 => {
+                let groups: Vec<&str> = uuid.split('-').collect();
+                let group_lens: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+                if group_lens != [8, 4, 4, 4, 12] || !groups.iter().all(|group| all_hex(group)) {
+                    return None;
+                }
+
+                let hex = groups.concat();
+                let mut uu = [0u8; 16];
+                for (i, byte) in uu.iter_mut().enumerate() {
+                    *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+                }
+
+                Some(Uuid128 { uu })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        self.uu
+    }
+
+    /// Returns the 16-bit short form of the UUID, if it has one.
+    pub fn as_u16(&self) -> Option<u16> {
+        self.as_u32().and_then(|short| u16::try_from(short).ok())
+    }
+
+    /// Returns the 32-bit short form of the UUID, if it has one.
+    pub fn as_u32(&self) -> Option<u32> {
+        if self.uu[4..] != BASE_UUID[4..] {
+            return None;
+        }
+
+        Some(u32::from_be_bytes([self.uu[0], self.uu[1], self.uu[2], self.uu[3]]))
+    }
+}
+
+impl Display for Uuid128 {
+    /// Formats the UUID in its 128-bit form, e.g. "0000180d-0000-1000-8000-00805f9b34fb".
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        for (i, byte) in self.uu.iter().enumerate() {
+            if i == 4 || i == 6 || i == 8 || i == 10 {
+                f.write_str("-")?;
+            }
+            f.write_fmt(format_args!("{:02x}", byte))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<BtUuid> for Uuid128 {
+    fn from(uuid: BtUuid) -> Uuid128 {
+        Uuid128 { uu: uuid.uuid }
+    }
+}
+
+impl From<Uuid128> for BtUuid {
+    fn from(uuid: Uuid128) -> BtUuid {
+        BtUuid { uuid: uuid.uu }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEART_RATE: &str = "0000180d-0000-1000-8000-00805f9b34fb";
+
+    #[test]
+    fn parse_valid_uuids() {
+        let heart_rate = Uuid128::from_u16(0x180d);
+        let fixtures = [
+            (HEART_RATE, heart_rate),
+            ("0000180D-0000-1000-8000-00805F9B34FB", heart_rate),
+            ("180d", heart_rate),
+            ("180D", heart_rate),
+            ("0000180d", heart_rate),
+            ("12345678", Uuid128::from_u32(0x12345678)),
+            (
+                "6e400001-b5a3-f393-e0a9-e50e24dcca9e",
+                Uuid128::from_bytes([
+                    0x6e, 0x40, 0x00, 0x01, 0xb5, 0xa3, 0xf3, 0x93, 0xe0, 0xa9, 0xe5, 0x0e, 0x24,
+                    0xdc, 0xca, 0x9e,
+                ]),
+            ),
+        ];
+
+        for (uuid, expected) in fixtures {
+            assert_eq!(Uuid128::from_string(uuid), Some(expected), "{}", uuid);
+        }
+    }
+
+    #[test]
+    fn parse_invalid_uuids() {
+        let fixtures = [
+            "",
+            "180",
+            "180g",
+            "+180",
+            "0000180d0",
+            "0000180d-0000-1000-8000-00805f9b34f",
+            "0000180d-0000-1000-8000-00805f9b34fbb",
+            "0000180d00000-1000-8000-00805f9b34fb",
+            "0000180d-0000-1000-8000-00805f9b34fg",
+            "0000180d-0000-1000-8000+00805f9b34fb",
+            "{000180d-0000-1000-8000-00805f9b34fb",
+        ];
+
+        for uuid in fixtures {
+            assert_eq!(Uuid128::from_string(uuid), None, "{}", uuid);
+        }
+    }
+
+    #[test]
+    fn format_uuid() {
+        assert_eq!(Uuid128::from_u16(0x180d).to_string(), HEART_RATE);
+
+        let uuid = Uuid128::from_string("6E400001-B5A3-F393-E0A9-E50E24DCCA9E").unwrap();
+        assert_eq!(uuid.to_string(), "6e400001-b5a3-f393-e0a9-e50e24dcca9e");
+        assert_eq!(Uuid128::from_string(&uuid.to_string()), Some(uuid));
+    }
+
+    #[test]
+    fn short_forms() {
+        let heart_rate = Uuid128::from_string(HEART_RATE).unwrap();
+        assert_eq!(heart_rate.as_u16(), Some(0x180d));
+        assert_eq!(heart_rate.as_u32(), Some(0x180d));
+
+        let long_short = Uuid128::from_u32(0x12345678);
+        assert_eq!(long_short.as_u16(), None);
+        assert_eq!(long_short.as_u32(), Some(0x12345678));
+
+        let custom = Uuid128::from_string("6e400001-b5a3-f393-e0a9-e50e24dcca9e").unwrap();
+        assert_eq!(custom.as_u16(), None);
+        assert_eq!(custom.as_u32(), None);
+    }
+
+    #[test]
+    fn topshim_conversion() {
+        let uuid = Uuid128::from_u16(0x180d);
+        let bt_uuid: BtUuid = uuid.into();
+        assert_eq!(bt_uuid.uuid, uuid.to_bytes());
+        assert_eq!(Uuid128::from(bt_uuid), uuid);
+    }
+}
@@ -0,0 +1,222 @@
+//! Watchdog of the dispatch loops.
+//!
+//! The dispatch loops and the work queues of the devices tell the watchdog which message they are
+//! handling. A thread of its own checks them periodically, since a stuck handler may also hold
+//! the runtime thread it runs on, and reports the handlers that have been handling a message for
+//! longer than `STUCK_HANDLER_THRESHOLD`, e.g. because they wait for a lock that is never
+//! released. Without it, such lockups only show as a stack that stopped answering.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::BDAddr;
+
+/// Interval between two checks of the handlers.
+const WATCHDOG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a handler can handle a message before it is reported as stuck.
+const STUCK_HANDLER_THRESHOLD: Duration = Duration::from_secs(10);
+
+/// What dispatches a message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Handler {
+    /// The main dispatch loop.
+    Main,
+    /// The media dispatch loop, see `bluetooth_media::dispatch`.
+    Media,
+    /// The work queue of a device, see `DeviceQueues`.
+    Device(BDAddr),
+}
+
+impl Display for Handler {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Handler::Main => write!(f, "main dispatch loop"),
+            Handler::Media => write!(f, "media dispatch loop"),
+            Handler::Device(addr) => write!(f, "work queue of {}", addr.to_string()),
+        }
+    }
+}
+
+struct Busy {
+    handler: Handler,
+    message: &'static str,
+    since: Instant,
+    /// Whether the handler was already reported as stuck with this message.
+    reported: bool,
+}
+
+#[derive(Default)]
+struct WatchdogState {
+    busy: HashMap<u64, Busy>,
+    next_id: u64,
+}
+
+/// Watches the handlers of the messages, shared by the dispatch loops.
+#[derive(Clone)]
+pub struct Watchdog {
+    state: Arc<Mutex<WatchdogState>>,
+}
+
+impl Watchdog {
+    /// Starts watching the handlers.
+    ///
+    /// A stuck handler is logged once per message. If `abort_when_stuck` is true, the daemon is
+    /// aborted instead, after logging every busy handler, so that the lockup leaves a crash report
+    /// and the daemon is restarted.
+    pub fn start(abort_when_stuck: bool) -> Watchdog {
+        let watchdog = Watchdog::new();
+
+        let state = watchdog.state.clone();
+        let spawned = thread::Builder::new().name("bt_watchdog".to_string()).spawn(move || loop {
+            thread::sleep(WATCHDOG_CHECK_INTERVAL);
+            Watchdog::check(&state, abort_when_stuck);
+        });
+        if let Err(e) = spawned {
+            eprintln!("Failed to start the watchdog: {}", e);
+        }
+
+        watchdog
+    }
+
+    fn new() -> Watchdog {
+        Watchdog { state: Arc::new(Mutex::new(WatchdogState::default())) }
+    }
+
+    /// Records that `handler` is handling `message`, until the returned guard is dropped.
+    pub(crate) fn watch(&self, handler: Handler, message: &'static str) -> WatchGuard {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.busy.insert(id, Busy { handler, message, since: Instant::now(), reported: false });
+
+        WatchGuard { state: self.state.clone(), id }
+    }
+
+    fn check(state: &Mutex<WatchdogState>, abort_when_stuck: bool) {
+        let mut state = state.lock().unwrap();
+        let stuck = Watchdog::find_stuck(&mut state, STUCK_HANDLER_THRESHOLD);
+
+        for (handler, message, elapsed) in &stuck {
+            eprintln!(
+                "Watchdog: {} stuck handling {} for {} ms",
+                handler,
+                message,
+                elapsed.as_millis()
+            );
+        }
+
+        if stuck.is_empty() || !abort_when_stuck {
+            return;
+        }
+
+        eprintln!("Watchdog: aborting, busy handlers:");
+        for busy in state.busy.values() {
+            eprintln!(
+                "  {} handling {} for {} ms",
+                busy.handler,
+                busy.message,
+                busy.since.elapsed().as_millis()
+            );
+        }
+        std::process::abort();
+    }
+
+    /// Returns the handlers that have been handling a message for longer than `threshold` and
+    /// weren't reported yet, with the message and how long they have been handling it, and marks
+    /// them as reported.
+    fn find_stuck(
+        state: &mut WatchdogState,
+        threshold: Duration,
+    ) -> Vec<(Handler, &'static str, Duration)> {
+        let mut stuck = vec![];
+
+        for busy in state.busy.values_mut() {
+            let elapsed = busy.since.elapsed();
+            if elapsed < threshold || busy.reported {
+                continue;
+            }
+
+            busy.reported = true;
+            stuck.push((busy.handler, busy.message, elapsed));
+        }
+
+        stuck
+    }
+}
+
+/// Clears the message of a handler from the watchdog when the handler is done with it.
+pub(crate) struct WatchGuard {
+    state: Arc<Mutex<WatchdogState>>,
+    id: u64,
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().busy.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: Duration = Duration::from_millis(20);
+
+    fn find_stuck(watchdog: &Watchdog) -> Vec<(Handler, &'static str)> {
+        let mut state = watchdog.state.lock().unwrap();
+        let mut stuck: Vec<(Handler, &'static str)> = Watchdog::find_stuck(&mut state, THRESHOLD)
+            .into_iter()
+            .map(|(handler, message, _)| (handler, message))
+            .collect();
+        stuck.sort_by_key(|(_, message)| *message);
+        stuck
+    }
+
+    #[test]
+    fn stuck_handler_reported_once() {
+        let watchdog = Watchdog::new();
+        let addr = BDAddr::from_string("11:22:33:44:55:66").unwrap();
+        let stuck = watchdog.watch(Handler::Device(addr), "BluetoothBondStateChanged");
+
+        thread::sleep(THRESHOLD * 2);
+        let _busy = watchdog.watch(Handler::Main, "BluetoothAdapterStateChanged");
+
+        // Only the handler busy for longer than the threshold is reported, and only once.
+        assert_eq!(
+            find_stuck(&watchdog),
+            vec![(Handler::Device(addr), "BluetoothBondStateChanged")]
+        );
+        assert_eq!(find_stuck(&watchdog), vec![]);
+
+        drop(stuck);
+        assert_eq!(watchdog.state.lock().unwrap().busy.len(), 1);
+
+        // The handler is reported again if it gets stuck on another message.
+        let _stuck = watchdog.watch(Handler::Device(addr), "BluetoothBondRetry");
+        thread::sleep(THRESHOLD * 2);
+        assert_eq!(
+            find_stuck(&watchdog),
+            vec![
+                (Handler::Main, "BluetoothAdapterStateChanged"),
+                (Handler::Device(addr), "BluetoothBondRetry")
+            ]
+        );
+    }
+
+    #[test]
+    fn handler_cleared_on_drop() {
+        let watchdog = Watchdog::new();
+        {
+            let _watch = watchdog.watch(Handler::Media, "A2dpAudioStateChanged");
+            assert_eq!(watchdog.state.lock().unwrap().busy.len(), 1);
+        }
+
+        assert!(watchdog.state.lock().unwrap().busy.is_empty());
+        thread::sleep(THRESHOLD * 2);
+        assert_eq!(find_stuck(&watchdog), vec![]);
+    }
+}
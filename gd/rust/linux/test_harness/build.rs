@@ -0,0 +1,36 @@
+use pkg_config::Config;
+use std::env;
+
+fn main() {
+    // The main linking point with c++ code is the libbluetooth-static.a
+    // These includes all the symbols built via C++ but doesn't include other
+    // links (i.e. pkg-config)
+    println!("cargo:rustc-link-lib=static=bluetooth-static");
+
+    // The platform build puts the library in the target directory. Otherwise it has to be in the
+    // default library paths.
+    match env::var("CARGO_TARGET_DIR") {
+        Ok(target_dir) => println!("cargo:rustc-link-search=native={}", target_dir),
+        Err(_) => println!(
+            "cargo:warning=CARGO_TARGET_DIR is not set, looking for libbluetooth-static.a in the \
+             default library paths"
+        ),
+    }
+    println!("cargo:rerun-if-env-changed=CARGO_TARGET_DIR");
+
+    // A few dynamic links
+    println!("cargo:rustc-link-lib=dylib=flatbuffers");
+    println!("cargo:rustc-link-lib=dylib=protobuf");
+    println!("cargo:rustc-link-lib=dylib=resolv");
+
+    // Clang requires -lc++ instead of -lstdc++
+    println!("cargo:rustc-link-lib=c++");
+
+    // A few more dependencies from pkg-config. These aren't included as part of
+    // the libbluetooth-static.a
+    Config::new().probe("libchrome").unwrap();
+    Config::new().probe("libmodp_b64").unwrap();
+    Config::new().probe("tinyxml2").unwrap();
+
+    println!("cargo:rerun-if-changed=build.rs");
+}
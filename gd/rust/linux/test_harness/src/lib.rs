@@ -0,0 +1,225 @@
+//! Harness for end-to-end tests of the D-Bus projection, without hardware.
+//!
+//! A `TestBus` is a private bus daemon, so that the tests neither depend on nor disturb the buses
+//! of the machine they run on. A `TestDaemon` exports objects on it the way btserv does on the
+//! system bus, and the tests talk to them as clients over connections of their own, which lets
+//! them cover method marshalling, callback delivery and the cleanup of disconnected clients.
+//!
+//! Most btstack modules need the native stack behind topshim, so the tests export fakes of their
+//! API traits. The modules that can be called without it, e.g. `BluetoothLogging` with arguments
+//! it refuses, are exported with the interfaces of btserv. There is no mock of topshim, so the
+//! btstack modules that drive the native stack are not covered.
+
+use bt_topshim::topstack;
+
+use dbus::channel::{Channel, MatchingReceiver};
+use dbus::message::MatchRule;
+use dbus::nonblock::SyncConnection;
+
+use dbus_crossroads::Crossroads;
+
+use dbus_projection::DisconnectWatcher;
+
+use dbus_tokio::connection;
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{self, Child, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::task::JoinHandle;
+
+/// Configuration of the bus daemon, with `@SOCKET@` replaced by the path of its socket.
+///
+/// Everyone is allowed everything, as the tests are the only clients.
+const BUS_CONFIG: &str = r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:path=@SOCKET@</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#;
+
+/// Environment variable that skips the tests on machines without dbus-daemon, where they
+/// otherwise fail, see `TestBus::start_if_available`.
+pub const SKIP_WITHOUT_DBUS_ENV: &str = "BT_TEST_SKIP_WITHOUT_DBUS";
+
+/// Tells apart the buses of the tests running in parallel in the same process.
+static NEXT_BUS_ID: AtomicU32 = AtomicU32::new(0);
+
+/// A private D-Bus daemon, stopped when dropped.
+pub struct TestBus {
+    daemon: Child,
+    /// Directory of the configuration and socket of the daemon.
+    dir: PathBuf,
+    address: String,
+}
+
+impl TestBus {
+    /// Starts a bus daemon, listening on a socket in a temporary directory of its own.
+    ///
+    /// Fails with `io::ErrorKind::NotFound` if dbus-daemon is not installed.
+    pub fn start() -> io::Result<TestBus> {
+        let id = NEXT_BUS_ID.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("bt_test_bus.{}.{}", process::id(), id));
+        fs::create_dir_all(&dir)?;
+
+        let config = dir.join("bus.conf");
+        let socket = dir.join("bus");
+        fs::write(&config, BUS_CONFIG.replace("@SOCKET@", &socket.to_string_lossy()))?;
+
+        let mut daemon = Command::new("dbus-daemon")
+            .arg(format!("--config-file={}", config.display()))
+            .arg("--nofork")
+            .arg("--print-address")
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        // The address is printed once the daemon listens, so it can be connected to right away.
+        let mut address = String::new();
+        let read = BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address);
+        let address = address.trim().to_string();
+        if read.is_err() || address.is_empty() {
+            let _ = daemon.kill();
+            let _ = daemon.wait();
+            let _ = fs::remove_dir_all(&dir);
+            return Err(io::Error::new(io::ErrorKind::Other, "dbus-daemon didn't start"));
+        }
+
+        Ok(TestBus { daemon, dir, address })
+    }
+
+    /// Starts a bus daemon like `start`, or returns None if dbus-daemon is not installed and
+    /// `SKIP_WITHOUT_DBUS_ENV` is set, for the tests to be skipped.
+    ///
+    /// Panics if dbus-daemon doesn't start otherwise, so that the tests don't pass without having
+    /// run.
+    pub fn start_if_available() -> Option<TestBus> {
+        match TestBus::start() {
+            Ok(bus) => Some(bus),
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound
+                    && env::var_os(SKIP_WITHOUT_DBUS_ENV).is_some() =>
+            {
+                eprintln!("Skipping the test, dbus-daemon is not installed");
+                None
+            }
+            Err(e) => panic!(
+                "Failed to start dbus-daemon: {}. Set {} to skip the tests without it.",
+                e, SKIP_WITHOUT_DBUS_ENV
+            ),
+        }
+    }
+
+    /// Returns the address clients connect to, e.g. to set `DBUS_SESSION_BUS_ADDRESS` for a
+    /// client running in another process.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Connects to the bus, with the connection served on the topstack runtime like those of
+    /// btserv. Must be called from that runtime.
+    pub async fn connect(&self) -> Result<TestConnection, dbus::Error> {
+        let mut channel = Channel::open_private(&self.address)?;
+        channel.register()?;
+
+        let (resource, conn) = connection::from_channel::<SyncConnection>(channel)?;
+        // The resource only finishes when the connection is lost, e.g. when the bus stops.
+        let resource = topstack::get_runtime().spawn(async {
+            let _err = resource.await;
+        });
+
+        Ok(TestConnection { conn, resource })
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A connection to a `TestBus`.
+pub struct TestConnection {
+    pub conn: Arc<SyncConnection>,
+    resource: JoinHandle<()>,
+}
+
+impl TestConnection {
+    /// Closes the connection, which the bus announces to the other clients as a disconnect.
+    ///
+    /// The connection is only closed once every other reference to `conn` is dropped.
+    pub fn disconnect(self) {
+        self.resource.abort();
+    }
+}
+
+/// Objects exported on a `TestBus` as btserv exports them, with a `DisconnectWatcher` to clean up
+/// after clients that disconnect.
+pub struct TestDaemon {
+    pub connection: TestConnection,
+    pub disconnect_watcher: Arc<Mutex<DisconnectWatcher>>,
+    cr: Arc<Mutex<Crossroads>>,
+}
+
+impl TestDaemon {
+    /// Connects to the bus as `name`, and serves the method calls to the objects exported with
+    /// `export`. Must be called from the topstack runtime.
+    pub async fn start(bus: &TestBus, name: &'static str) -> Result<TestDaemon, Box<dyn Error>> {
+        let connection = bus.connect().await?;
+        let conn = connection.conn.clone();
+        conn.request_name(name, false, true, false).await?;
+
+        let mut cr = Crossroads::new();
+        cr.set_async_support(Some((
+            conn.clone(),
+            Box::new(|x| {
+                topstack::get_runtime().spawn(x);
+            }),
+        )));
+
+        let disconnect_watcher = Arc::new(Mutex::new(DisconnectWatcher::new()));
+        disconnect_watcher.lock().unwrap().setup_watch(conn.clone()).await;
+
+        // Unlike btserv, objects can still be exported once the calls are served.
+        let cr = Arc::new(Mutex::new(cr));
+        let served_cr = cr.clone();
+        conn.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |msg, conn| {
+                served_cr.lock().unwrap().handle_message(msg, conn).unwrap();
+                true
+            }),
+        );
+
+        Ok(TestDaemon { connection, disconnect_watcher, cr })
+    }
+
+    /// Exports an object with an exporter generated by `generate_dbus_exporter`, e.g.
+    ///
+    /// ```ignore
+    /// daemon.export(|conn, cr, watcher| export_foo_dbus_obj("/foo", conn, cr, foo, watcher));
+    /// ```
+    pub fn export<F>(&self, export: F)
+    where
+        F: FnOnce(Arc<SyncConnection>, &mut Crossroads, Arc<Mutex<DisconnectWatcher>>),
+    {
+        export(
+            self.connection.conn.clone(),
+            &mut self.cr.lock().unwrap(),
+            self.disconnect_watcher.clone(),
+        );
+    }
+}
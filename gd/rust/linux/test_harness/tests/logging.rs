@@ -0,0 +1,104 @@
+//! End-to-end tests of `IBluetoothLogging` as btserv exports it, with the btstack implementation
+//! behind it.
+//!
+//! The calls are made with arguments that the implementation refuses before configuring the
+//! snoop log, which needs the native stack.
+
+use bt_test_harness::{TestBus, TestDaemon};
+use bt_topshim::topstack;
+
+use btstack::bluetooth_logging::BluetoothLogging;
+
+use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+use dbus::nonblock::{Proxy, SyncConnection};
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::iface_bluetooth_logging::{export_bluetooth_logging_dbus_obj, INTERFACE_VERSION};
+
+mod dbus_arg {
+    use dbus_macros::generate_dbus_arg;
+
+    generate_dbus_arg!();
+}
+
+// btserv is only a binary, so the interface is built from its sources.
+#[path = "../../service/src/iface_bluetooth_logging.rs"]
+mod iface_bluetooth_logging;
+
+const DAEMON_NAME: &str = "org.chromium.bluetooth";
+const LOGGING_PATH: &str = "/org/chromium/bluetooth/logging";
+const LOGGING_IFACE: &str = "org.chromium.bluetooth.BluetoothLogging";
+
+fn run<F: Future>(future: F) -> F::Output {
+    topstack::get_runtime().block_on(future)
+}
+
+/// Starts a daemon exporting `BluetoothLogging` the way btserv does.
+async fn start_daemon(bus: &TestBus) -> TestDaemon {
+    let daemon = TestDaemon::start(bus, DAEMON_NAME).await.unwrap();
+    let logging = Arc::new(Mutex::new(BluetoothLogging::new()));
+
+    daemon.export(|conn, cr, disconnect_watcher| {
+        export_bluetooth_logging_dbus_obj(LOGGING_PATH, conn, cr, logging, disconnect_watcher)
+    });
+
+    daemon
+}
+
+fn logging_proxy(conn: Arc<SyncConnection>) -> Proxy<'static, Arc<SyncConnection>> {
+    Proxy::new(DAEMON_NAME, LOGGING_PATH, Duration::from_secs(2), conn)
+}
+
+#[test]
+fn version_is_exported() {
+    let bus = match TestBus::start_if_available() {
+        Some(bus) => bus,
+        None => return,
+    };
+    run(async {
+        let _daemon = start_daemon(&bus).await;
+        let client = bus.connect().await.unwrap();
+        let proxy = logging_proxy(client.conn.clone());
+
+        let version: u32 = proxy.get(LOGGING_IFACE, "Version").await.unwrap();
+        assert_eq!(version, INTERFACE_VERSION);
+    });
+}
+
+#[test]
+fn invalid_snoop_logs_are_refused() {
+    let bus = match TestBus::start_if_available() {
+        Some(bus) => bus,
+        None => return,
+    };
+    run(async {
+        let _daemon = start_daemon(&bus).await;
+        let client = bus.connect().await.unwrap();
+        let proxy = logging_proxy(client.conn.clone());
+
+        for (file_name, max_packets) in [("../hci.log", 100u32), ("", 100), ("hci.log", 0)] {
+            let (started,): (bool,) = proxy
+                .method_call(LOGGING_IFACE, "StartSnoopLog", (file_name, max_packets, false))
+                .await
+                .unwrap();
+            assert!(!started, "{} {}", file_name, max_packets);
+        }
+
+        let (enabled,): (bool,) =
+            proxy.method_call(LOGGING_IFACE, "IsSnoopLogEnabled", ()).await.unwrap();
+        assert!(!enabled);
+
+        // Stopping a log that never started does nothing.
+        let result: Result<(), dbus::Error> =
+            proxy.method_call(LOGGING_IFACE, "StopSnoopLog", ()).await;
+        result.unwrap();
+
+        // Arguments of the wrong type are rejected rather than passed to the API.
+        let result: Result<(bool,), _> =
+            proxy.method_call(LOGGING_IFACE, "StartSnoopLog", ("hci.log", 100i32, false)).await;
+        assert!(result.is_err());
+    });
+}
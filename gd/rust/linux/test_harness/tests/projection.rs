@@ -0,0 +1,259 @@
+//! End-to-end tests of the D-Bus projection, with a fake API exported on a private bus.
+
+use bt_test_harness::{TestBus, TestDaemon};
+use bt_topshim::topstack;
+
+use dbus::channel::MatchingReceiver;
+use dbus::message::MatchRule;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::strings::Path;
+
+use dbus_crossroads::Crossroads;
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use crate::counter::{export_counter_dbus_obj, Counter};
+
+mod dbus_arg {
+    use dbus_macros::generate_dbus_arg;
+
+    generate_dbus_arg!();
+}
+
+mod counter {
+    use btstack::RPCProxy;
+
+    use dbus::nonblock::SyncConnection;
+    use dbus::strings::{BusName, Path};
+
+    use dbus_macros::{dbus_method, dbus_proxy_obj, generate_dbus_exporter};
+
+    use dbus_projection::DisconnectWatcher;
+
+    use std::collections::HashMap;
+    use std::error::Error;
+    use std::sync::{Arc, Mutex};
+
+    use crate::dbus_arg::{DBusArg, DBusArgError};
+
+    /// Callback of `ICounter`, registered by its clients like the callbacks of the btstack APIs.
+    pub trait ICounterCallback: RPCProxy {
+        fn on_changed(&self, value: i32);
+    }
+
+    /// The fake API, standing in for a btstack module.
+    pub trait ICounter {
+        fn register_callback(&mut self, callback: Box<dyn ICounterCallback + Send>);
+
+        /// Adds `value` to the counter, and returns the new value.
+        fn add(&mut self, value: i32) -> i32;
+
+        fn join(&self, words: Vec<String>, separator: String) -> String;
+    }
+
+    #[derive(Default)]
+    pub struct Counter {
+        value: i32,
+        last_id: u32,
+        /// Callbacks of the clients, removed when their client disconnects.
+        pub callbacks: Arc<Mutex<HashMap<u32, Box<dyn ICounterCallback + Send>>>>,
+    }
+
+    impl ICounter for Counter {
+        fn register_callback(&mut self, mut callback: Box<dyn ICounterCallback + Send>) {
+            self.last_id += 1;
+            let id = self.last_id;
+
+            let callbacks = self.callbacks.clone();
+            callback.register_disconnect(Box::new(move || {
+                callbacks.lock().unwrap().remove(&id);
+            }));
+            self.callbacks.lock().unwrap().insert(id, callback);
+        }
+
+        fn add(&mut self, value: i32) -> i32 {
+            self.value += value;
+            for callback in self.callbacks.lock().unwrap().values() {
+                callback.on_changed(self.value);
+            }
+            self.value
+        }
+
+        fn join(&self, words: Vec<String>, separator: String) -> String {
+            words.join(&separator)
+        }
+    }
+
+    #[allow(dead_code)]
+    struct CounterCallbackDBus {}
+
+    #[dbus_proxy_obj(CounterCallback, "org.chromium.bluetooth.test.CounterCallback")]
+    impl ICounterCallback for CounterCallbackDBus {
+        #[dbus_method("OnChanged")]
+        fn on_changed(&self, value: i32) {}
+    }
+
+    #[allow(dead_code)]
+    struct ICounterDBus {}
+
+    #[generate_dbus_exporter(
+        export_counter_dbus_obj,
+        "org.chromium.bluetooth.test.Counter",
+        version = 1
+    )]
+    impl ICounter for ICounterDBus {
+        #[dbus_method("RegisterCallback")]
+        fn register_callback(&mut self, callback: Box<dyn ICounterCallback + Send>) {}
+
+        #[dbus_method("Add")]
+        fn add(&mut self, value: i32) -> i32 {
+            0
+        }
+
+        #[dbus_method("Join")]
+        fn join(&self, words: Vec<String>, separator: String) -> String {
+            String::new()
+        }
+    }
+}
+
+const DAEMON_NAME: &str = "org.chromium.bluetooth.test";
+const COUNTER_PATH: &str = "/org/chromium/bluetooth/test/counter";
+const COUNTER_IFACE: &str = "org.chromium.bluetooth.test.Counter";
+const CALLBACK_PATH: &str = "/org/chromium/bluetooth/test/callback";
+const CALLBACK_IFACE: &str = "org.chromium.bluetooth.test.CounterCallback";
+
+fn run<F: Future>(future: F) -> F::Output {
+    topstack::get_runtime().block_on(future)
+}
+
+/// Starts a daemon exporting a `Counter`, which is returned for the test to inspect.
+async fn start_daemon(bus: &TestBus) -> (TestDaemon, Arc<Mutex<Counter>>) {
+    let daemon = TestDaemon::start(bus, DAEMON_NAME).await.unwrap();
+    let counter = Arc::new(Mutex::new(Counter::default()));
+
+    let obj = counter.clone();
+    daemon.export(|conn, cr, disconnect_watcher| {
+        export_counter_dbus_obj(COUNTER_PATH, conn, cr, obj, disconnect_watcher)
+    });
+
+    (daemon, counter)
+}
+
+fn counter_proxy(conn: Arc<SyncConnection>) -> Proxy<'static, Arc<SyncConnection>> {
+    Proxy::new(DAEMON_NAME, COUNTER_PATH, Duration::from_secs(2), conn)
+}
+
+/// Exports the callback object of a client, which forwards the values it is called with.
+fn serve_callback(conn: &Arc<SyncConnection>) -> mpsc::UnboundedReceiver<i32> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let mut cr = Crossroads::new();
+    let token = cr.register(CALLBACK_IFACE, |ibuilder| {
+        ibuilder.method("OnChanged", ("value",), (), move |_, _, (value,): (i32,)| {
+            let _ = tx.send(value);
+            Ok(())
+        });
+    });
+    cr.insert(CALLBACK_PATH, &[token], ());
+
+    conn.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |msg, conn| {
+            cr.handle_message(msg, conn).unwrap();
+            true
+        }),
+    );
+
+    rx
+}
+
+/// Registers the callback object exported by `serve_callback`.
+async fn register_callback(proxy: &Proxy<'static, Arc<SyncConnection>>) {
+    let result: Result<(), dbus::Error> =
+        proxy.method_call(COUNTER_IFACE, "RegisterCallback", (Path::from(CALLBACK_PATH),)).await;
+    result.unwrap();
+}
+
+/// Waits for up to a second for `condition` to hold, as the effects of messages are asynchronous.
+async fn wait_for<F: Fn() -> bool>(condition: F) -> bool {
+    for _ in 0..100 {
+        if condition() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    condition()
+}
+
+#[test]
+fn method_calls_are_marshalled() {
+    let bus = match TestBus::start_if_available() {
+        Some(bus) => bus,
+        None => return,
+    };
+    run(async {
+        let (_daemon, _counter) = start_daemon(&bus).await;
+        let client = bus.connect().await.unwrap();
+        let proxy = counter_proxy(client.conn.clone());
+
+        let (value,): (i32,) = proxy.method_call(COUNTER_IFACE, "Add", (2,)).await.unwrap();
+        assert_eq!(value, 2);
+        let (value,): (i32,) = proxy.method_call(COUNTER_IFACE, "Add", (3,)).await.unwrap();
+        assert_eq!(value, 5);
+
+        let words = vec![String::from("a"), String::from("b")];
+        let (joined,): (String,) =
+            proxy.method_call(COUNTER_IFACE, "Join", (words, ", ")).await.unwrap();
+        assert_eq!(joined, "a, b");
+
+        // Arguments of the wrong type are rejected rather than passed to the API.
+        let result: Result<(i32,), _> = proxy.method_call(COUNTER_IFACE, "Add", ("2",)).await;
+        assert!(result.is_err());
+    });
+}
+
+#[test]
+fn callbacks_are_delivered() {
+    let bus = match TestBus::start_if_available() {
+        Some(bus) => bus,
+        None => return,
+    };
+    run(async {
+        let (_daemon, _counter) = start_daemon(&bus).await;
+        let client = bus.connect().await.unwrap();
+        let mut changes = serve_callback(&client.conn);
+        let proxy = counter_proxy(client.conn.clone());
+
+        register_callback(&proxy).await;
+        let (_value,): (i32,) = proxy.method_call(COUNTER_IFACE, "Add", (4,)).await.unwrap();
+
+        let change = tokio::time::timeout(Duration::from_secs(2), changes.recv()).await;
+        assert_eq!(change.unwrap(), Some(4));
+    });
+}
+
+#[test]
+fn callbacks_of_disconnected_clients_are_removed() {
+    let bus = match TestBus::start_if_available() {
+        Some(bus) => bus,
+        None => return,
+    };
+    run(async {
+        let (_daemon, counter) = start_daemon(&bus).await;
+        let callbacks = counter.lock().unwrap().callbacks.clone();
+        let client = bus.connect().await.unwrap();
+        let proxy = counter_proxy(client.conn.clone());
+
+        register_callback(&proxy).await;
+        assert_eq!(callbacks.lock().unwrap().len(), 1);
+
+        drop(proxy);
+        client.disconnect();
+        assert!(wait_for(|| callbacks.lock().unwrap().is_empty()).await);
+    });
+}
@@ -0,0 +1,349 @@
+/*
+ * Copyright (C) 2021 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#include "gd/rust/topshim/acl/acl_shim.h"
+
+#include <algorithm>
+#include <chrono>
+#include <cstring>
+#include <future>
+#include <memory>
+
+#include "base/bind.h"
+#include "base/location.h"
+#include "include/hardware/bluetooth.h"
+#include "main/shim/shim.h"
+#include "osi/include/alarm.h"
+#include "rust/cxx.h"
+#include "src/acl.rs.h"
+#include "src/btif.rs.h"
+#include "stack/btm/btm_ble_int.h"
+#include "stack/btm/btm_dev.h"
+#include "stack/btm/btm_sec.h"
+#include "stack/btm/security_device_record.h"
+#include "stack/include/acl_api.h"
+#include "stack/include/btm_ble_api.h"
+#include "stack/include/btm_status.h"
+#include "stack/include/btu.h"
+#include "stack/include/hcimsgs.h"
+#include "stack/include/l2c_api.h"
+#include "types/hci_role.h"
+
+extern tBTM_CB btm_cb;
+
+namespace bluetooth {
+namespace topshim {
+namespace rust {
+namespace internal {
+// The BTM completion callbacks only carry the command result so only one ACL
+// interface can exist at a time.
+static AclIntf* g_aclif;
+
+namespace rusty = ::bluetooth::topshim::rust;
+
+static RustRawAddress to_rust_address(const RawAddress& address) {
+  RustRawAddress raddr;
+  std::copy(std::begin(address.address), std::end(address.address), std::begin(raddr.address));
+
+  return raddr;
+}
+
+static RawAddress from_rust_address(const RustRawAddress& address) {
+  RawAddress r;
+  r.FromOctets(address.address.data());
+
+  return r;
+}
+
+static void read_rssi_cb(void* p) {
+  if (!g_aclif) return;
+
+  auto result = static_cast<tBTM_RSSI_RESULT*>(p);
+  // A null result means the command timed out.
+  if (!result) {
+    rusty::acl_read_rssi_callback(*g_aclif->GetCallbacks(), RustRawAddress{}, BTM_ERR_PROCESSING, 0);
+    return;
+  }
+
+  rusty::acl_read_rssi_callback(
+      *g_aclif->GetCallbacks(), to_rust_address(result->rem_bda), result->status, result->rssi);
+}
+
+static void read_rssi(RawAddress address) {
+  tBTM_STATUS status = BTM_ReadRSSI(address, read_rssi_cb);
+
+  // The completion callback is only called if the command was sent.
+  if (status != BTM_CMD_STARTED && g_aclif) {
+    rusty::acl_read_rssi_callback(*g_aclif->GetCallbacks(), to_rust_address(address), status, 0);
+  }
+}
+
+// The PHY read result doesn't carry the address so it is bound to the callback.
+static void read_phy_cb(RawAddress address, uint8_t tx_phy, uint8_t rx_phy, uint8_t status) {
+  if (!g_aclif) return;
+
+  rusty::acl_read_phy_callback(*g_aclif->GetCallbacks(), to_rust_address(address), status, tx_phy, rx_phy);
+}
+
+static void read_phy(RawAddress address) {
+  BTM_BleReadPhy(address, base::Bind(&read_phy_cb, address));
+}
+
+// The outcome is reported by the connection update event, to the GATT clients of the connection.
+static void update_connection_parameters(
+    RawAddress address, uint16_t min_interval, uint16_t max_interval, uint16_t latency, uint16_t timeout) {
+  L2CA_UpdateBleConnParams(address, min_interval, max_interval, latency, timeout, 0, 0);
+}
+
+static void set_data_length(RawAddress address, uint16_t tx_pdu_length) {
+  BTM_SetBleDataLength(address, tx_pdu_length);
+}
+
+// The outcome is reported by the bond state and encryption change events.
+static void request_le_encryption(RawAddress address, bool mitm) {
+  BTM_SetEncryption(
+      address, BT_TRANSPORT_LE, nullptr, nullptr, mitm ? BTM_BLE_SEC_ENCRYPT_MITM : BTM_BLE_SEC_ENCRYPT_NO_MITM);
+}
+
+static bool uses_secure_connections(const RawAddress& address, tBT_TRANSPORT transport) {
+  tBTM_SEC_DEV_REC* p_dev_rec = btm_find_dev(address);
+  if (!p_dev_rec) return false;
+
+  if (transport == BT_TRANSPORT_LE) {
+    // Secure Connections pairing derives the LTK on both sides, it has no EDIV or Rand.
+    static const BT_OCTET8 kNoRand = {};
+    return (p_dev_rec->ble.key_type & BTM_LE_KEY_PENC) && p_dev_rec->ble.keys.ediv == 0 &&
+           memcmp(p_dev_rec->ble.keys.rand, kNoRand, BT_OCTET8_LEN) == 0;
+  }
+
+  return p_dev_rec->link_key_type == BTM_LKEY_TYPE_UNAUTH_COMB_P_256 ||
+         p_dev_rec->link_key_type == BTM_LKEY_TYPE_AUTH_COMB_P_256;
+}
+
+// How long a read waits for the main thread before giving up.
+static constexpr std::chrono::seconds kMainThreadReadTimeout(1);
+
+// BTM state belongs to the main thread, so reads run there and wait for the result. `fallback` is
+// returned if the main thread is not running or doesn't answer in time.
+template <typename T>
+static T read_on_main_thread(base::OnceCallback<T()> read, T fallback) {
+  auto promise = std::make_shared<std::promise<T>>();
+  std::future<T> result = promise->get_future();
+  bt_status_t status = do_in_main_thread(
+      FROM_HERE,
+      base::BindOnce(
+          [](base::OnceCallback<T()> read, std::shared_ptr<std::promise<T>> promise) {
+            promise->set_value(std::move(read).Run());
+          },
+          std::move(read),
+          promise));
+  if (status != BT_STATUS_SUCCESS) return fallback;
+  if (result.wait_for(kMainThreadReadTimeout) != std::future_status::ready) return fallback;
+
+  return result.get();
+}
+
+static LeLinkSecurity get_le_link_security(RawAddress address) {
+  return LeLinkSecurity{
+      .encrypted = BTM_IsEncrypted(address, BT_TRANSPORT_LE),
+      .key_known = BTM_IsLinkKeyKnown(address, BT_TRANSPORT_LE),
+      .key_authenticated = BTM_IsLinkKeyAuthed(address, BT_TRANSPORT_LE),
+  };
+}
+
+static RustRawAddress resolve_identity_address(RawAddress address) {
+  tBTM_SEC_DEV_REC* p_dev_rec = btm_ble_resolve_random_addr(address);
+  if (!p_dev_rec) return RustRawAddress{};
+
+  // Devices that didn't share an identity address are known by the address they bonded with.
+  const RawAddress& identity = p_dev_rec->ble.identity_address_with_type.bda;
+  return to_rust_address(identity.IsEmpty() ? p_dev_rec->bd_addr : identity);
+}
+
+static LinkSecurity get_link_security(RawAddress address, tBT_TRANSPORT transport) {
+  bool connected = BTM_IsAclConnectionUp(address, transport);
+  bool encrypted = connected && BTM_IsEncrypted(address, transport);
+  return LinkSecurity{
+      .connected = connected,
+      .encrypted = encrypted,
+      .authenticated = encrypted && BTM_IsLinkKeyAuthed(address, transport),
+      .secure_connections = encrypted && uses_secure_connections(address, transport),
+  };
+}
+
+static int32_t get_link_role(RawAddress address, int32_t transport) {
+  if (transport == BT_TRANSPORT_LE) return L2CA_GetBleConnRole(address);
+
+  tHCI_ROLE role = HCI_ROLE_UNKNOWN;
+  BTM_GetRole(address, &role);
+  return role;
+}
+
+// BTM reports the role change to BTA only, so the outcome is only known by reading the role again.
+static void switch_role_to_central(RawAddress address) {
+  tBTM_STATUS status = BTM_SwitchRoleToCentral(address);
+  if (!g_aclif) return;
+
+  rusty::acl_switch_role_callback(*g_aclif->GetCallbacks(), to_rust_address(address), status);
+}
+
+// Fails for the transports the device is not connected over, which is expected.
+static void disconnect(RawAddress address) {
+  btm_remove_acl(address, BT_TRANSPORT_BR_EDR);
+  btm_remove_acl(address, BT_TRANSPORT_LE);
+}
+
+// Only fails if LE is not supported, which the adapter API checks first.
+static void configure_le_privacy(bool enable) {
+  BTM_BleConfigPrivacy(enable);
+}
+
+// A static address replaces the resolvable private addresses, which are turned off with privacy.
+static void set_le_static_address(RawAddress address) {
+  BTM_BleConfigPrivacy(false);
+
+  tBTM_LE_RANDOM_CB* p_cb = &btm_cb.ble_ctr_cb.addr_mgnt_cb;
+  // The refresh timer would replace the address with a new resolvable private address.
+  alarm_cancel(p_cb->refresh_raddr_timer);
+  p_cb->own_addr_type = BLE_ADDR_RANDOM;
+  btm_ble_set_random_address(address);
+}
+}  // namespace internal
+
+AclIntf::AclIntf(::rust::Box<RustAclCallbacks> callbacks) {
+  callbacks_ = std::make_unique<::rust::Box<RustAclCallbacks>>(std::move(callbacks));
+}
+
+AclIntf::~AclIntf() {
+  internal::g_aclif = nullptr;
+}
+
+int AclIntf::ReadRssi(const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  // BTM must be called from the main thread.
+  return do_in_main_thread(FROM_HERE, base::BindOnce(internal::read_rssi, addr));
+}
+
+int AclIntf::SetPreferredPhy(
+    const RustRawAddress& address, uint8_t tx_phys, uint8_t rx_phys, uint16_t phy_options) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return do_in_main_thread(FROM_HERE, base::BindOnce(&BTM_BleSetPhy, addr, tx_phys, rx_phys, phy_options));
+}
+
+int AclIntf::ReadPhy(const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return do_in_main_thread(FROM_HERE, base::BindOnce(internal::read_phy, addr));
+}
+
+int AclIntf::UpdateConnectionParameters(
+    const RustRawAddress& address, uint16_t min_interval, uint16_t max_interval, uint16_t latency, uint16_t timeout)
+    const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return do_in_main_thread(
+      FROM_HERE,
+      base::BindOnce(internal::update_connection_parameters, addr, min_interval, max_interval, latency, timeout));
+}
+
+int AclIntf::SetDataLength(const RustRawAddress& address, uint16_t tx_pdu_length) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return do_in_main_thread(FROM_HERE, base::BindOnce(internal::set_data_length, addr, tx_pdu_length));
+}
+
+LeLinkSecurity AclIntf::GetLeLinkSecurity(const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return internal::read_on_main_thread(base::BindOnce(internal::get_le_link_security, addr), LeLinkSecurity{});
+}
+
+LinkSecurity AclIntf::GetLinkSecurity(const RustRawAddress& address, int32_t transport) const {
+  RawAddress addr = internal::from_rust_address(address);
+  tBT_TRANSPORT bt_transport = transport == BT_TRANSPORT_LE ? BT_TRANSPORT_LE : BT_TRANSPORT_BR_EDR;
+
+  return internal::read_on_main_thread(
+      base::BindOnce(internal::get_link_security, addr, bt_transport), LinkSecurity{});
+}
+
+int AclIntf::RequestLeEncryption(const RustRawAddress& address, bool mitm) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return do_in_main_thread(FROM_HERE, base::BindOnce(internal::request_le_encryption, addr, mitm));
+}
+
+RustRawAddress AclIntf::ResolveIdentityAddress(const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+  if (!BTM_BLE_IS_RESOLVE_BDA(addr)) return RustRawAddress{};
+
+  return internal::read_on_main_thread(
+      base::BindOnce(internal::resolve_identity_address, addr), RustRawAddress{});
+}
+
+int AclIntf::ConfigureLePrivacy(bool enable) const {
+  return do_in_main_thread(FROM_HERE, base::BindOnce(internal::configure_le_privacy, enable));
+}
+
+bool AclIntf::IsLePrivacyEnabled() const {
+  return internal::read_on_main_thread(base::BindOnce(&BTM_BleLocalPrivacyEnabled), false);
+}
+
+int AclIntf::SetRpaTimeout(uint16_t timeout) const {
+  return do_in_main_thread(FROM_HERE, base::BindOnce(&btsnd_hcic_ble_set_rand_priv_addr_timeout, timeout));
+}
+
+int AclIntf::SetLeStaticAddress(const RustRawAddress& address) const {
+  // The address is set in the legacy BTM state, which the GD stack doesn't use for its own address.
+  if (::bluetooth::shim::is_any_gd_enabled()) return BT_STATUS_UNSUPPORTED;
+
+  RawAddress addr = internal::from_rust_address(address);
+
+  return do_in_main_thread(FROM_HERE, base::BindOnce(internal::set_le_static_address, addr));
+}
+
+int32_t AclIntf::GetLinkRole(const RustRawAddress& address, int32_t transport) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return internal::read_on_main_thread(
+      base::BindOnce(internal::get_link_role, addr, transport), static_cast<int32_t>(HCI_ROLE_UNKNOWN));
+}
+
+int AclIntf::SwitchRoleToCentral(const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return do_in_main_thread(FROM_HERE, base::BindOnce(internal::switch_role_to_central, addr));
+}
+
+int AclIntf::Disconnect(const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return do_in_main_thread(FROM_HERE, base::BindOnce(internal::disconnect, addr));
+}
+
+std::unique_ptr<AclIntf> LoadAcl(::rust::Box<RustAclCallbacks> callbacks) {
+  // Don't allow the ACL interface to be allocated twice
+  if (internal::g_aclif) std::abort();
+
+  auto aclif = std::make_unique<AclIntf>(std::move(callbacks));
+  internal::g_aclif = aclif.get();
+  return aclif;
+}
+
+}  // namespace rust
+}  // namespace topshim
+}  // namespace bluetooth
@@ -0,0 +1,71 @@
+/*
+ * Copyright (C) 2021 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#ifndef GD_RUST_TOPSHIM_ACL_ACL_SHIM_H
+#define GD_RUST_TOPSHIM_ACL_ACL_SHIM_H
+
+#include <memory>
+
+#include "rust/cxx.h"
+
+namespace bluetooth {
+namespace topshim {
+namespace rust {
+
+struct RustAclCallbacks;
+struct RustRawAddress;
+struct LeLinkSecurity;
+struct LinkSecurity;
+
+// Reads and configures the state of ACL connections, which the Bluetooth HAL interface doesn't expose.
+class AclIntf {
+ public:
+  AclIntf(::rust::Box<RustAclCallbacks> callbacks);
+  ~AclIntf();
+
+  int ReadRssi(const RustRawAddress& address) const;
+  int SetPreferredPhy(const RustRawAddress& address, uint8_t tx_phys, uint8_t rx_phys, uint16_t phy_options) const;
+  int ReadPhy(const RustRawAddress& address) const;
+  int UpdateConnectionParameters(
+      const RustRawAddress& address, uint16_t min_interval, uint16_t max_interval, uint16_t latency, uint16_t timeout)
+      const;
+  int SetDataLength(const RustRawAddress& address, uint16_t tx_pdu_length) const;
+  LeLinkSecurity GetLeLinkSecurity(const RustRawAddress& address) const;
+  LinkSecurity GetLinkSecurity(const RustRawAddress& address, int32_t transport) const;
+  int RequestLeEncryption(const RustRawAddress& address, bool mitm) const;
+  RustRawAddress ResolveIdentityAddress(const RustRawAddress& address) const;
+  int ConfigureLePrivacy(bool enable) const;
+  bool IsLePrivacyEnabled() const;
+  int SetRpaTimeout(uint16_t timeout) const;
+  int SetLeStaticAddress(const RustRawAddress& address) const;
+  int32_t GetLinkRole(const RustRawAddress& address, int32_t transport) const;
+  int SwitchRoleToCentral(const RustRawAddress& address) const;
+  int Disconnect(const RustRawAddress& address) const;
+
+  ::rust::Box<RustAclCallbacks>& GetCallbacks() {
+    return *callbacks_;
+  }
+
+ private:
+  std::unique_ptr<::rust::Box<RustAclCallbacks>> callbacks_;
+};
+
+std::unique_ptr<AclIntf> LoadAcl(::rust::Box<RustAclCallbacks> callbacks);
+
+}  // namespace rust
+}  // namespace topshim
+}  // namespace bluetooth
+
+#endif  // GD_RUST_TOPSHIM_ACL_ACL_SHIM_H
@@ -0,0 +1,239 @@
+/*
+ * Copyright (C) 2021 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#include "gd/rust/topshim/btav/btav_shim.h"
+
+#include <algorithm>
+#include <memory>
+#include <vector>
+
+#include "btif/include/btif_av.h"
+#include "include/hardware/bluetooth.h"
+#include "rust/cxx.h"
+#include "src/btif.rs.h"
+#include "src/profiles/a2dp.rs.h"
+
+namespace bluetooth {
+namespace topshim {
+namespace rust {
+namespace internal {
+// Like the Bluetooth interface, the A2DP callbacks don't carry a pointer back to
+// the interface object so only one A2DP interface can exist at a time.
+static A2dpIntf* g_a2dpif;
+
+namespace rusty = ::bluetooth::topshim::rust;
+
+// Returns the callbacks of the interface, or null if they are gone. Callbacks posted to the JNI
+// thread before the profile was cleaned up may still run afterwards.
+static ::rust::Box<RustA2dpCallbacks>* get_callbacks() {
+  if (!g_a2dpif) return nullptr;
+
+  return g_a2dpif->GetCallbacks();
+}
+
+static RustRawAddress to_rust_address(const RawAddress& address) {
+  RustRawAddress raddr;
+  std::copy(std::begin(address.address), std::end(address.address), std::begin(raddr.address));
+
+  return raddr;
+}
+
+static RawAddress from_rust_address(const RustRawAddress& address) {
+  RawAddress r;
+  r.FromOctets(address.address.data());
+
+  return r;
+}
+
+static void connection_state_cb(const RawAddress& bd_addr, btav_connection_state_t state) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(bd_addr);
+
+  rusty::a2dp_connection_state_callback(**callbacks, addr, state);
+}
+
+static void audio_state_cb(const RawAddress& bd_addr, btav_audio_state_t state) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(bd_addr);
+
+  rusty::a2dp_audio_state_callback(**callbacks, addr, state);
+}
+
+static A2dpCodecConfig to_rust_codec_config(const btav_a2dp_codec_config_t& config) {
+  A2dpCodecConfig rconfig = {
+      .codec_type = static_cast<int32_t>(config.codec_type),
+      .codec_priority = static_cast<int32_t>(config.codec_priority),
+      .sample_rate = static_cast<int32_t>(config.sample_rate),
+      .bits_per_sample = static_cast<int32_t>(config.bits_per_sample),
+      .channel_mode = static_cast<int32_t>(config.channel_mode),
+      .codec_specific_1 = config.codec_specific_1,
+      .codec_specific_2 = config.codec_specific_2,
+      .codec_specific_3 = config.codec_specific_3,
+      .codec_specific_4 = config.codec_specific_4,
+  };
+
+  return rconfig;
+}
+
+static ::rust::Vec<A2dpCodecConfig> to_rust_codec_configs(const std::vector<btav_a2dp_codec_config_t>& configs) {
+  ::rust::Vec<A2dpCodecConfig> rconfigs;
+  for (const auto& config : configs) {
+    rconfigs.push_back(to_rust_codec_config(config));
+  }
+
+  return rconfigs;
+}
+
+static void audio_config_cb(
+    const RawAddress& bd_addr,
+    btav_a2dp_codec_config_t codec_config,
+    std::vector<btav_a2dp_codec_config_t> codecs_local_capabilities,
+    std::vector<btav_a2dp_codec_config_t> codecs_selectable_capabilities) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(bd_addr);
+
+  rusty::a2dp_audio_config_callback(
+      **callbacks,
+      addr,
+      to_rust_codec_config(codec_config),
+      to_rust_codec_configs(codecs_local_capabilities),
+      to_rust_codec_configs(codecs_selectable_capabilities));
+}
+
+static void audio_delay_cb(const RawAddress& bd_addr, uint16_t delay) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(bd_addr);
+
+  rusty::a2dp_audio_delay_callback(**callbacks, addr, delay);
+}
+
+// TODO: Implement remaining callbacks
+
+static bool mandatory_codec_preferred_cb(const RawAddress& bd_addr) {
+  return false;
+}
+
+btav_source_callbacks_t g_callbacks = {
+    sizeof(btav_source_callbacks_t),
+    connection_state_cb,
+    audio_state_cb,
+    audio_config_cb,
+    mandatory_codec_preferred_cb,
+};
+}  // namespace internal
+
+A2dpIntf::~A2dpIntf() {
+  internal::g_a2dpif = nullptr;
+}
+
+bool A2dpIntf::Initialize(::rust::Box<RustA2dpCallbacks> callbacks) {
+  if (init_) return true;
+  if (!intf_) return false;
+
+  callbacks_ = std::make_unique<::rust::Box<RustA2dpCallbacks>>(std::move(callbacks));
+
+  std::vector<btav_a2dp_codec_config_t> codec_priorities;
+  std::vector<btav_a2dp_codec_config_t> offloading_preference;
+  int ret = intf_->init(&internal::g_callbacks, 1, codec_priorities, offloading_preference);
+
+  init_ = ret == BT_STATUS_SUCCESS;
+  if (init_) {
+    // The delay reports are not part of the A2DP source callbacks.
+    btif_av_register_audio_delay_callback(internal::audio_delay_cb);
+  }
+  return init_;
+}
+
+void A2dpIntf::CleanUp() {
+  if (!init_) return;
+
+  // Callbacks already posted to the JNI thread find no callbacks to call once these are dropped.
+  btif_av_register_audio_delay_callback(nullptr);
+  intf_->cleanup();
+  callbacks_.reset();
+  init_ = false;
+}
+
+int A2dpIntf::Connect(const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return intf_->connect(addr);
+}
+
+int A2dpIntf::Disconnect(const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return intf_->disconnect(addr);
+}
+
+int A2dpIntf::SetActiveDevice(const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return intf_->set_active_device(addr);
+}
+
+void A2dpIntf::SuspendStream() const {
+  btif_av_stream_suspend();
+}
+
+void A2dpIntf::StartAudioRequest(bool offload) const {
+  if (offload) {
+    btif_av_stream_start_offload();
+  } else {
+    btif_av_stream_start();
+  }
+}
+
+void A2dpIntf::StopAudioRequest() const {
+  // An empty address stops the stream of the active peer.
+  btif_av_stream_stop(RawAddress::kEmpty);
+}
+
+bool A2dpIntf::IsOffloadEnabled() const {
+  return btif_av_is_a2dp_offload_enabled();
+}
+
+uint16_t A2dpIntf::GetAudioDelay() const {
+  return btif_av_get_audio_delay();
+}
+
+void A2dpIntf::SetAudioDelay(const RustRawAddress& address, uint16_t delay) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  btif_av_set_audio_delay(addr, delay);
+}
+
+std::unique_ptr<A2dpIntf> LoadA2dp(const BluetoothIntf& btif) {
+  // Don't allow the A2DP interface to be allocated twice
+  if (internal::g_a2dpif) std::abort();
+
+  auto intf = static_cast<const btav_source_interface_t*>(btif.GetProfileInterface(BT_PROFILE_ADVANCED_AUDIO_ID));
+  auto a2dpif = std::make_unique<A2dpIntf>(intf);
+  internal::g_a2dpif = a2dpif.get();
+  return a2dpif;
+}
+
+}  // namespace rust
+}  // namespace topshim
+}  // namespace bluetooth
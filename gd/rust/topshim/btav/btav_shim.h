@@ -0,0 +1,67 @@
+/*
+ * Copyright (C) 2021 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#ifndef GD_RUST_TOPSHIM_BTAV_BTAV_SHIM_H
+#define GD_RUST_TOPSHIM_BTAV_BTAV_SHIM_H
+
+#include <memory>
+
+#include "btif/btif_shim.h"
+#include "include/hardware/bt_av.h"
+#include "rust/cxx.h"
+
+namespace bluetooth {
+namespace topshim {
+namespace rust {
+
+struct RustA2dpCallbacks;
+
+class A2dpIntf {
+ public:
+  A2dpIntf(const btav_source_interface_t* intf) : init_(false), intf_(intf){};
+  ~A2dpIntf();
+
+  bool Initialize(::rust::Box<RustA2dpCallbacks> callbacks);
+  void CleanUp();
+
+  int Connect(const RustRawAddress& address) const;
+  int Disconnect(const RustRawAddress& address) const;
+  int SetActiveDevice(const RustRawAddress& address) const;
+  void SuspendStream() const;
+
+  void StartAudioRequest(bool offload) const;
+  void StopAudioRequest() const;
+  bool IsOffloadEnabled() const;
+
+  uint16_t GetAudioDelay() const;
+  void SetAudioDelay(const RustRawAddress& address, uint16_t delay) const;
+
+  ::rust::Box<RustA2dpCallbacks>* GetCallbacks() {
+    return callbacks_.get();
+  }
+
+ private:
+  std::unique_ptr<::rust::Box<RustA2dpCallbacks>> callbacks_;
+  bool init_;
+  const btav_source_interface_t* intf_;
+};
+
+std::unique_ptr<A2dpIntf> LoadA2dp(const BluetoothIntf& btif);
+
+}  // namespace rust
+}  // namespace topshim
+}  // namespace bluetooth
+
+#endif  // GD_RUST_TOPSHIM_BTAV_BTAV_SHIM_H
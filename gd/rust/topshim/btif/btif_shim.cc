@@ -19,9 +19,13 @@
 #include <algorithm>
 #include <cstdlib>
 #include <cstring>
+#include <iterator>
 #include <memory>
+#include <string>
+#include <vector>
 
 #include "btcore/include/hal_util.h"
+#include "btif/include/btif_dm.h"
 #include "include/hardware/bluetooth.h"
 #include "rust/cxx.h"
 #include "src/btif.rs.h"
@@ -70,6 +74,26 @@ static RawAddress from_rust_address(const RustRawAddress& address) {
   return r;
 }
 
+static BtOutOfBandData to_rust_oob_data(const bt_oob_data_t& data) {
+  BtOutOfBandData roob;
+  roob.is_valid = data.is_valid;
+  std::copy(std::begin(data.address), std::end(data.address), std::begin(roob.address));
+  std::copy(std::begin(data.c), std::end(data.c), std::begin(roob.c));
+  std::copy(std::begin(data.r), std::end(data.r), std::begin(roob.r));
+
+  return roob;
+}
+
+static bt_oob_data_t from_rust_oob_data(const BtOutOfBandData& data) {
+  bt_oob_data_t oob{};
+  oob.is_valid = data.is_valid;
+  std::copy(std::begin(data.address), std::end(data.address), oob.address);
+  std::copy(std::begin(data.c), std::end(data.c), oob.c);
+  std::copy(std::begin(data.r), std::end(data.r), oob.r);
+
+  return oob;
+}
+
 static ::rust::String bdname_to_string(bt_bdname_t* bdname) {
   if (!bdname) {
     return std::string("");
@@ -135,16 +159,46 @@ static void acl_state_changed_cb(
   rust::acl_state_changed_callback(*g_btif->GetCallbacks(), status, addr, state, hci_reason);
 }
 
-// TODO(abps) - Implement remaining callbacks
+static void generate_local_oob_data_cb(tBT_TRANSPORT transport, bt_oob_data_t oob_data) {
+  rust::generate_local_oob_data_callback(*g_btif->GetCallbacks(), transport, to_rust_oob_data(oob_data));
+}
 
-static void thread_event_cb(bt_cb_thread_evt evt) {}
+static void dut_mode_recv_cb(uint16_t opcode, uint8_t* buf, uint8_t len) {
+  ::rust::Vec<uint8_t> data;
+  std::copy(buf, buf + len, std::back_inserter(data));
+
+  rust::dut_mode_recv_callback(*g_btif->GetCallbacks(), opcode, std::move(data));
+}
 
-static void dut_mode_recv_cb(uint16_t opcode, uint8_t* buf, uint8_t len) {}
+static void le_test_mode_cb(bt_status_t status, uint16_t num_packets) {
+  rust::le_test_mode_callback(*g_btif->GetCallbacks(), status, num_packets);
+}
 
-static void le_test_mode_cb(bt_status_t status, uint16_t num_packets) {}
+// TODO(abps) - Implement remaining callbacks
+
+static void thread_event_cb(bt_cb_thread_evt evt) {}
 
 static void energy_info_cb(bt_activity_energy_info* energy_info, bt_uid_traffic_t* uid_data) {}
 
+static void link_quality_report_cb(
+    uint64_t timestamp,
+    int report_id,
+    int rssi,
+    int snr,
+    int retransmission_count,
+    int packets_not_receive_count,
+    int negative_acknowledgement_count) {
+  rust::link_quality_report_callback(
+      *g_btif->GetCallbacks(),
+      timestamp,
+      report_id,
+      rssi,
+      snr,
+      retransmission_count,
+      packets_not_receive_count,
+      negative_acknowledgement_count);
+}
+
 bt_callbacks_t g_callbacks = {
     sizeof(bt_callbacks_t),
     adapter_state_changed_cb,
@@ -160,6 +214,8 @@ bt_callbacks_t g_callbacks = {
     dut_mode_recv_cb,
     le_test_mode_cb,
     energy_info_cb,
+    link_quality_report_cb,
+    generate_local_oob_data_cb,
 };
 }  // namespace internal
 
@@ -290,6 +346,18 @@ int BluetoothIntf::CreateBond(const RustRawAddress& address, int transport) cons
   return intf_->create_bond(&addr, transport);
 }
 
+int BluetoothIntf::CreateBondOutOfBand(
+    const RustRawAddress& address,
+    int transport,
+    const BtOutOfBandData& p192_data,
+    const BtOutOfBandData& p256_data) const {
+  RawAddress addr = internal::from_rust_address(address);
+  bt_oob_data_t p192 = internal::from_rust_oob_data(p192_data);
+  bt_oob_data_t p256 = internal::from_rust_oob_data(p256_data);
+
+  return intf_->create_bond_out_of_band(&addr, transport, &p192, &p256);
+}
+
 int BluetoothIntf::RemoveBond(const RustRawAddress& address) const {
   RawAddress addr = internal::from_rust_address(address);
 
@@ -308,6 +376,37 @@ int BluetoothIntf::GetConnectionState(const RustRawAddress& address) const {
   return intf_->get_connection_state(&addr);
 }
 
+int BluetoothIntf::GenerateLocalOobData(int transport) const {
+  return intf_->generate_local_oob_data(static_cast<tBT_TRANSPORT>(transport));
+}
+
+int BluetoothIntf::DutModeConfigure(uint8_t enable) const {
+  return intf_->dut_mode_configure(enable);
+}
+
+int BluetoothIntf::DutModeSend(uint16_t opcode, ::rust::Slice<const uint8_t> buf) const {
+  // HCI command parameters are limited to 255 bytes.
+  if (buf.size() > UINT8_MAX) return BT_STATUS_PARM_INVALID;
+
+  std::vector<uint8_t> data(buf.begin(), buf.end());
+  return intf_->dut_mode_send(opcode, data.data(), static_cast<uint8_t>(data.size()));
+}
+
+int BluetoothIntf::LeTestMode(uint16_t opcode, ::rust::Slice<const uint8_t> buf) const {
+  if (buf.size() > UINT8_MAX) return BT_STATUS_PARM_INVALID;
+
+  std::vector<uint8_t> data(buf.begin(), buf.end());
+  return intf_->le_test_mode(opcode, data.data(), static_cast<uint8_t>(data.size()));
+}
+
+int BluetoothIntf::ConfigClear() const {
+  return intf_->config_clear();
+}
+
+void BluetoothIntf::SetAutoAcceptTemporaryPairing(bool auto_accept) const {
+  btif_dm_set_auto_accept_temporary_pairing(auto_accept);
+}
+
 int BluetoothIntf::PinReply(
     const RustRawAddress& address, uint8_t accept, uint8_t pin_len, const BtPinCode& code) const {
   RawAddress addr = internal::from_rust_address(address);
@@ -324,6 +423,16 @@ int BluetoothIntf::SspReply(const RustRawAddress& address, int ssp_variant, uint
   return intf_->ssp_reply(&addr, static_cast<bt_ssp_variant_t>(ssp_variant), accept, passkey);
 }
 
+const void* BluetoothIntf::GetProfileInterface(const char* profile_id) const {
+  if (!init_) return nullptr;
+
+  return intf_->get_profile_interface(profile_id);
+}
+
+bool BluetoothIntf::HasProfileInterface(::rust::Str profile_id) const {
+  return GetProfileInterface(std::string(profile_id).c_str()) != nullptr;
+}
+
 std::unique_ptr<BluetoothIntf> Load() {
   // Don't allow the bluetooth interface to be allocated twice
   if (internal::g_btif) std::abort();
@@ -31,6 +31,7 @@ struct RustRawAddress;
 struct BtProperty;
 struct BtPinCode;
 struct BtUuid;
+struct BtOutOfBandData;
 
 class BluetoothIntf {
  public:
@@ -57,11 +58,25 @@ class BluetoothIntf {
   int CancelDiscovery() const;
 
   int CreateBond(const RustRawAddress& address, int transport) const;
+  int CreateBondOutOfBand(
+      const RustRawAddress& address,
+      int transport,
+      const BtOutOfBandData& p192_data,
+      const BtOutOfBandData& p256_data) const;
   int RemoveBond(const RustRawAddress& address) const;
   int CancelBond(const RustRawAddress& address) const;
 
   int GetConnectionState(const RustRawAddress& address) const;
 
+  int GenerateLocalOobData(int transport) const;
+
+  int DutModeConfigure(uint8_t enable) const;
+  int DutModeSend(uint16_t opcode, ::rust::Slice<const uint8_t> buf) const;
+  int LeTestMode(uint16_t opcode, ::rust::Slice<const uint8_t> buf) const;
+
+  int ConfigClear() const;
+  void SetAutoAcceptTemporaryPairing(bool auto_accept) const;
+
   int PinReply(const RustRawAddress& address, uint8_t accept, uint8_t pin_len, const BtPinCode& code) const;
   int SspReply(const RustRawAddress& address, int ssp_variant, uint8_t accept, uint32_t passkey) const;
 
@@ -69,6 +84,11 @@ class BluetoothIntf {
     return *callbacks_;
   }
 
+  bool HasProfileInterface(::rust::Str profile_id) const;
+
+  // Not exposed to Rust. Used by the profile shims to load their interfaces.
+  const void* GetProfileInterface(const char* profile_id) const;
+
  private:
   void ConvertFlags(::rust::Vec<::rust::String>& flags);
 
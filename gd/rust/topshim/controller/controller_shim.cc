@@ -0,0 +1,62 @@
+/*
+ * Copyright (C) 2021 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#include "gd/rust/topshim/controller/controller_shim.h"
+
+#include "device/include/controller.h"
+#include "rust/cxx.h"
+#include "src/controller.rs.h"
+#include "stack/include/btm_ble_api.h"
+
+namespace bluetooth {
+namespace topshim {
+namespace rust {
+
+BtControllerInfo GetControllerInfo() {
+  BtControllerInfo info = {};
+  const controller_t* controller = controller_get_interface();
+
+  info.is_ready = controller->get_is_ready();
+  if (!info.is_ready) return info;
+
+  const bt_version_t* version = controller->get_bt_version();
+  info.hci_version = version->hci_version;
+  info.hci_revision = version->hci_revision;
+  info.lmp_version = version->lmp_version;
+  info.manufacturer = version->manufacturer;
+  info.lmp_subversion = version->lmp_subversion;
+
+  info.le_supported = controller->supports_ble();
+  if (!info.le_supported) return info;
+
+  info.le_2m_phy = controller->supports_ble_2m_phy();
+  info.le_coded_phy = controller->supports_ble_coded_phy();
+  info.le_extended_advertising = controller->supports_ble_extended_advertising();
+  info.le_periodic_advertising = controller->supports_ble_periodic_advertising();
+  info.le_max_advertising_data_length = controller->get_ble_maxium_advertising_data_length();
+  info.le_number_of_advertising_sets = controller->get_ble_number_of_supported_advertising_sets();
+
+  tBTM_BLE_VSC_CB vendor_capabilities = {};
+  BTM_BleGetVendorCapabilities(&vendor_capabilities);
+  info.le_scan_filter_offload = vendor_capabilities.filter_support != 0;
+  info.le_max_scan_filters = vendor_capabilities.max_filter;
+
+  return info;
+}
+
+}  // namespace rust
+}  // namespace topshim
+}  // namespace bluetooth
@@ -0,0 +1,817 @@
+/*
+ * Copyright (C) 2021 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#include "gd/rust/topshim/gatt/gatt_shim.h"
+
+#include <algorithm>
+#include <iterator>
+#include <memory>
+#include <vector>
+
+#include "base/bind.h"
+#include "include/hardware/bluetooth.h"
+#include "rust/cxx.h"
+#include "src/btif.rs.h"
+#include "src/profiles/gatt.rs.h"
+#include "types/bluetooth/uuid.h"
+
+namespace bluetooth {
+namespace topshim {
+namespace rust {
+namespace internal {
+// The GATT callbacks don't carry a pointer back to the interface object so only one GATT
+// interface can exist at a time.
+static GattIntf* g_gattif;
+
+namespace rusty = ::bluetooth::topshim::rust;
+
+// Returns the callbacks of the interface, or null if they are gone. Callbacks posted to the JNI
+// thread before the profile was cleaned up may still run afterwards.
+static ::rust::Box<RustGattCallbacks>* get_callbacks() {
+  if (!g_gattif) return nullptr;
+
+  return g_gattif->GetCallbacks();
+}
+
+static RustRawAddress to_rust_address(const RawAddress& address) {
+  RustRawAddress raddr;
+  std::copy(std::begin(address.address), std::end(address.address), std::begin(raddr.address));
+
+  return raddr;
+}
+
+static RawAddress from_rust_address(const RustRawAddress& address) {
+  RawAddress r;
+  r.FromOctets(address.address.data());
+
+  return r;
+}
+
+static BtUuid to_rust_uuid(const bluetooth::Uuid& uuid) {
+  BtUuid ruuid;
+  const auto& bytes = uuid.To128BitBE();
+  std::copy(std::begin(bytes), std::end(bytes), std::begin(ruuid.uuid));
+
+  return ruuid;
+}
+
+static bluetooth::Uuid from_rust_uuid(const BtUuid& uuid) {
+  return bluetooth::Uuid::From128BitBE(uuid.uuid.data());
+}
+
+static void register_client_cb(int status, int client_if, const bluetooth::Uuid& app_uuid) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_client_register_client_callback(**callbacks, status, client_if, to_rust_uuid(app_uuid));
+}
+
+static void connect_cb(int conn_id, int status, int client_if, const RawAddress& bda) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(bda);
+
+  rusty::gatt_client_connect_callback(**callbacks, conn_id, status, client_if, addr);
+}
+
+static void disconnect_cb(int conn_id, int status, int client_if, const RawAddress& bda) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(bda);
+
+  rusty::gatt_client_disconnect_callback(**callbacks, conn_id, status, client_if, addr);
+}
+
+static void read_remote_rssi_cb(int client_if, const RawAddress& bda, int rssi, int status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(bda);
+
+  rusty::gatt_client_read_remote_rssi_callback(**callbacks, client_if, addr, rssi, status);
+}
+
+static void search_complete_cb(int conn_id, int status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_client_search_complete_callback(**callbacks, conn_id, status);
+}
+
+static void notify_cb(int conn_id, const btgatt_notify_params_t& p_data) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(p_data.bda);
+  ::rust::Vec<uint8_t> value;
+  std::copy(p_data.value, p_data.value + p_data.len, std::back_inserter(value));
+
+  rusty::gatt_client_notify_callback(
+      **callbacks, conn_id, addr, p_data.handle, p_data.is_notify, std::move(value));
+}
+
+static BtGattDbElement to_rust_db_element(const btgatt_db_element_t& element) {
+  BtGattDbElement relement = {
+      .id = element.id,
+      .uuid = to_rust_uuid(element.uuid),
+      .element_type = static_cast<int32_t>(element.type),
+      .attribute_handle = element.attribute_handle,
+      .start_handle = element.start_handle,
+      .end_handle = element.end_handle,
+      .properties = element.properties,
+      .extended_properties = element.extended_properties,
+      .permissions = element.permissions,
+  };
+
+  return relement;
+}
+
+static btgatt_db_element_t from_rust_db_element(const BtGattDbElement& element) {
+  btgatt_db_element_t e = {};
+  e.id = element.id;
+  e.uuid = from_rust_uuid(element.uuid);
+  e.type = static_cast<bt_gatt_db_attribute_type_t>(element.element_type);
+  e.attribute_handle = element.attribute_handle;
+  e.start_handle = element.start_handle;
+  e.end_handle = element.end_handle;
+  e.properties = element.properties;
+  e.extended_properties = element.extended_properties;
+  e.permissions = element.permissions;
+
+  return e;
+}
+
+static void get_gatt_db_cb(int conn_id, const btgatt_db_element_t* db, int count) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  ::rust::Vec<BtGattDbElement> elements;
+  for (int i = 0; i < count; i++) {
+    elements.push_back(to_rust_db_element(db[i]));
+  }
+
+  rusty::gatt_client_get_gatt_db_callback(**callbacks, conn_id, std::move(elements));
+}
+
+static void read_characteristic_cb(int conn_id, int status, btgatt_read_params_t* p_data) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  ::rust::Vec<uint8_t> value;
+  std::copy(p_data->value.value, p_data->value.value + p_data->value.len, std::back_inserter(value));
+
+  rusty::gatt_client_read_characteristic_callback(
+      **callbacks, conn_id, status, p_data->handle, std::move(value));
+}
+
+static void write_characteristic_cb(int conn_id, int status, uint16_t handle) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_client_write_characteristic_callback(**callbacks, conn_id, status, handle);
+}
+
+static void read_descriptor_cb(int conn_id, int status, const btgatt_read_params_t& p_data) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  ::rust::Vec<uint8_t> value;
+  std::copy(p_data.value.value, p_data.value.value + p_data.value.len, std::back_inserter(value));
+
+  rusty::gatt_client_read_descriptor_callback(
+      **callbacks, conn_id, status, p_data.handle, std::move(value));
+}
+
+static void write_descriptor_cb(int conn_id, int status, uint16_t handle) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_client_write_descriptor_callback(**callbacks, conn_id, status, handle);
+}
+
+static void execute_write_cb(int conn_id, int status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_client_execute_write_callback(**callbacks, conn_id, status);
+}
+
+static void configure_mtu_cb(int conn_id, int status, int mtu) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_client_configure_mtu_callback(**callbacks, conn_id, status, mtu);
+}
+
+static void phy_updated_cb(int conn_id, uint8_t tx_phy, uint8_t rx_phy, uint8_t status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_client_phy_updated_callback(**callbacks, conn_id, tx_phy, rx_phy, status);
+}
+
+static void conn_updated_cb(int conn_id, uint16_t interval, uint16_t latency, uint16_t timeout, uint8_t status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_client_conn_updated_callback(**callbacks, conn_id, interval, latency, timeout, status);
+}
+
+static void service_changed_cb(int conn_id) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_client_service_changed_callback(**callbacks, conn_id);
+}
+
+static void services_rediscovered_cb(const RawAddress& bda) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_client_services_rediscovered_callback(**callbacks, to_rust_address(bda));
+}
+
+// The PHY read result doesn't carry the client or the address so they are bound to the callback.
+static void client_read_phy_cb(int client_if, RawAddress address, uint8_t tx_phy, uint8_t rx_phy, uint8_t status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_client_read_phy_callback(
+      **callbacks, client_if, to_rust_address(address), tx_phy, rx_phy, status);
+}
+
+static void register_server_cb(int status, int server_if, const bluetooth::Uuid& app_uuid) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_server_register_server_callback(**callbacks, status, server_if, to_rust_uuid(app_uuid));
+}
+
+static void server_connection_cb(int conn_id, int server_if, int connected, const RawAddress& bda) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(bda);
+
+  rusty::gatt_server_connection_callback(**callbacks, conn_id, server_if, connected, addr);
+}
+
+static void service_added_cb(int status, int server_if, std::vector<btgatt_db_element_t> service) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  ::rust::Vec<BtGattDbElement> elements;
+  for (const auto& element : service) {
+    elements.push_back(to_rust_db_element(element));
+  }
+
+  rusty::gatt_server_service_added_callback(**callbacks, status, server_if, std::move(elements));
+}
+
+// Characteristic and descriptor reads are handled alike.
+static void request_read_cb(int conn_id, int trans_id, const RawAddress& bda, int attr_handle, int offset, bool is_long) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(bda);
+
+  rusty::gatt_server_request_read_callback(
+      **callbacks, conn_id, trans_id, addr, attr_handle, offset, is_long);
+}
+
+static void indication_sent_cb(int conn_id, int status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_server_indication_sent_callback(**callbacks, conn_id, status);
+}
+
+static void server_congestion_cb(int conn_id, bool congested) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_server_congestion_callback(**callbacks, conn_id, congested);
+}
+
+static void server_mtu_changed_cb(int conn_id, int mtu) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_server_mtu_changed_callback(**callbacks, conn_id, mtu);
+}
+
+// Characteristic and descriptor writes are handled alike.
+static void request_write_cb(
+    int conn_id,
+    int trans_id,
+    const RawAddress& bda,
+    int attr_handle,
+    int offset,
+    bool need_rsp,
+    bool is_prep,
+    std::vector<uint8_t> value) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(bda);
+  ::rust::Vec<uint8_t> rvalue;
+  std::copy(value.begin(), value.end(), std::back_inserter(rvalue));
+
+  rusty::gatt_server_request_write_callback(
+      **callbacks, conn_id, trans_id, addr, attr_handle, offset, need_rsp, is_prep, std::move(rvalue));
+}
+
+static void request_exec_write_cb(int conn_id, int trans_id, const RawAddress& bda, int exec_write) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(bda);
+
+  rusty::gatt_server_request_exec_write_callback(**callbacks, conn_id, trans_id, addr, exec_write);
+}
+
+// The PHY read result doesn't carry the server or the address so they are bound to the callback.
+static void server_read_phy_cb(int server_if, RawAddress address, uint8_t tx_phy, uint8_t rx_phy, uint8_t status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_server_read_phy_callback(
+      **callbacks, server_if, to_rust_address(address), tx_phy, rx_phy, status);
+}
+
+// The scanner registration result doesn't carry the app uuid so it is bound to the callback.
+static void scanner_registered_cb(bluetooth::Uuid app_uuid, uint8_t scanner_id, uint8_t btm_status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_scanner_registered_callback(**callbacks, to_rust_uuid(app_uuid), scanner_id, btm_status);
+}
+
+static void scan_result_cb(
+    uint16_t event_type,
+    uint8_t addr_type,
+    RawAddress* bda,
+    uint8_t primary_phy,
+    uint8_t secondary_phy,
+    uint8_t advertising_sid,
+    int8_t tx_power,
+    int8_t rssi,
+    uint16_t periodic_adv_int,
+    std::vector<uint8_t> adv_data,
+    RawAddress* original_bda) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  RustRawAddress addr = to_rust_address(*bda);
+  ::rust::Vec<uint8_t> radv_data;
+  std::copy(adv_data.begin(), adv_data.end(), std::back_inserter(radv_data));
+
+  rusty::gatt_scanner_scan_result_callback(
+      **callbacks,
+      event_type,
+      addr_type,
+      addr,
+      primary_phy,
+      secondary_phy,
+      advertising_sid,
+      tx_power,
+      rssi,
+      periodic_adv_int,
+      std::move(radv_data));
+}
+
+// The scan filter setup result doesn't carry the filter index so it is bound to the callback.
+static void scan_filter_param_setup_cb(
+    uint8_t filter_index, uint8_t available_space, uint8_t action_type, uint8_t btm_status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_scanner_filter_configured_callback(**callbacks, filter_index, btm_status);
+}
+
+// The advertiser results are delivered to the callbacks passed with each request, so the
+// advertiser id is bound to them where the result doesn't carry it.
+static void advertising_set_started_cb(int reg_id, uint8_t advertiser_id, int8_t tx_power, uint8_t status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_advertiser_set_started_callback(**callbacks, reg_id, advertiser_id, tx_power, status);
+}
+
+static void advertising_enabled_cb(int advertiser_id, bool enable, uint8_t status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_advertiser_enabled_callback(**callbacks, advertiser_id, enable, status);
+}
+
+// The set is disabled once its duration or maximum number of events is reached.
+static void advertising_timeout_cb(uint8_t advertiser_id, uint8_t status) {
+  advertising_enabled_cb(advertiser_id, false, status);
+}
+
+static void advertising_data_set_cb(int advertiser_id, bool set_scan_rsp, uint8_t status) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_advertiser_data_set_callback(**callbacks, advertiser_id, set_scan_rsp, status);
+}
+
+static void advertising_parameters_updated_cb(int advertiser_id, uint8_t status, int8_t tx_power) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_advertiser_parameters_updated_callback(**callbacks, advertiser_id, tx_power, status);
+}
+
+static void own_address_read_cb(int advertiser_id, uint8_t address_type, RawAddress address) {
+  auto callbacks = get_callbacks();
+  if (!callbacks) return;
+
+  rusty::gatt_advertiser_own_address_read_callback(
+      **callbacks, advertiser_id, address_type, to_rust_address(address));
+}
+
+static AdvertiseParameters from_rust_advertise_parameters(const BtAdvertiseParameters& params) {
+  AdvertiseParameters p = {
+      .advertising_event_properties = params.advertising_event_properties,
+      .min_interval = params.min_interval,
+      .max_interval = params.max_interval,
+      .channel_map = params.channel_map,
+      .tx_power = params.tx_power,
+      .primary_advertising_phy = params.primary_advertising_phy,
+      .secondary_advertising_phy = params.secondary_advertising_phy,
+      .scan_request_notification_enable = params.scan_request_notification_enable,
+  };
+
+  return p;
+}
+
+// TODO: Implement remaining callbacks. Callbacks left as nullptr are ignored by btif.
+
+btgatt_client_callbacks_t g_client_callbacks = {
+    .register_client_cb = register_client_cb,
+    .open_cb = connect_cb,
+    .close_cb = disconnect_cb,
+    .search_complete_cb = search_complete_cb,
+    .notify_cb = notify_cb,
+    .read_characteristic_cb = read_characteristic_cb,
+    .write_characteristic_cb = write_characteristic_cb,
+    .read_descriptor_cb = read_descriptor_cb,
+    .write_descriptor_cb = write_descriptor_cb,
+    .execute_write_cb = execute_write_cb,
+    .read_remote_rssi_cb = read_remote_rssi_cb,
+    .configure_mtu_cb = configure_mtu_cb,
+    .get_gatt_db_cb = get_gatt_db_cb,
+    .phy_updated_cb = phy_updated_cb,
+    .conn_updated_cb = conn_updated_cb,
+    .service_changed_cb = service_changed_cb,
+    .services_rediscovered_cb = services_rediscovered_cb,
+};
+
+btgatt_server_callbacks_t g_server_callbacks = {
+    .register_server_cb = register_server_cb,
+    .connection_cb = server_connection_cb,
+    .service_added_cb = service_added_cb,
+    .request_read_characteristic_cb = request_read_cb,
+    .request_read_descriptor_cb = request_read_cb,
+    .request_write_characteristic_cb = request_write_cb,
+    .request_write_descriptor_cb = request_write_cb,
+    .request_exec_write_cb = request_exec_write_cb,
+    .indication_sent_cb = indication_sent_cb,
+    .congestion_cb = server_congestion_cb,
+    .mtu_changed_cb = server_mtu_changed_cb,
+};
+
+btgatt_scanner_callbacks_t g_scanner_callbacks = {
+    .scan_result_cb = scan_result_cb,
+};
+
+btgatt_callbacks_t g_callbacks = {
+    sizeof(btgatt_callbacks_t),
+    &g_client_callbacks,
+    &g_server_callbacks,
+    &g_scanner_callbacks,
+};
+}  // namespace internal
+
+GattIntf::~GattIntf() {
+  internal::g_gattif = nullptr;
+}
+
+bool GattIntf::Initialize(::rust::Box<RustGattCallbacks> callbacks) {
+  if (init_) return true;
+  if (!intf_) return false;
+
+  callbacks_ = std::make_unique<::rust::Box<RustGattCallbacks>>(std::move(callbacks));
+
+  init_ = intf_->init(&internal::g_callbacks) == BT_STATUS_SUCCESS;
+  return init_;
+}
+
+void GattIntf::CleanUp() {
+  if (!init_) return;
+
+  // Callbacks already posted to the JNI thread find no callbacks to call once these are dropped.
+  intf_->cleanup();
+  callbacks_.reset();
+  init_ = false;
+}
+
+int GattIntf::ClientRegisterClient(const BtUuid& uuid, bool eatt_support) const {
+  return intf_->client->register_client(internal::from_rust_uuid(uuid), eatt_support);
+}
+
+int GattIntf::ClientUnregisterClient(int client_if) const {
+  return intf_->client->unregister_client(client_if);
+}
+
+int GattIntf::ClientConnect(
+    int client_if,
+    const RustRawAddress& address,
+    bool is_direct,
+    int transport,
+    bool opportunistic,
+    int initiating_phys) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return intf_->client->connect(client_if, addr, is_direct, transport, opportunistic, initiating_phys);
+}
+
+int GattIntf::ClientDisconnect(int client_if, const RustRawAddress& address, int conn_id) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return intf_->client->disconnect(client_if, addr, conn_id);
+}
+
+int GattIntf::ClientReadRemoteRssi(int client_if, const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return intf_->client->read_remote_rssi(client_if, addr);
+}
+
+int GattIntf::ClientSearchService(int conn_id) const {
+  return intf_->client->search_service(conn_id, nullptr);
+}
+
+int GattIntf::ClientGetGattDb(int conn_id) const {
+  return intf_->client->get_gatt_db(conn_id);
+}
+
+int GattIntf::ClientReadCharacteristic(int conn_id, uint16_t handle, int auth_req) const {
+  return intf_->client->read_characteristic(conn_id, handle, auth_req);
+}
+
+int GattIntf::ClientReadUsingCharacteristicUuid(
+    int conn_id, const BtUuid& uuid, uint16_t start_handle, uint16_t end_handle, int auth_req) const {
+  return intf_->client->read_using_characteristic_uuid(
+      conn_id, internal::from_rust_uuid(uuid), start_handle, end_handle, auth_req);
+}
+
+int GattIntf::ClientWriteCharacteristic(
+    int conn_id, int handle, int write_type, int auth_req, ::rust::Slice<const uint8_t> value) const {
+  std::vector<uint8_t> data(value.begin(), value.end());
+
+  return intf_->client->write_characteristic(conn_id, handle, write_type, auth_req, std::move(data));
+}
+
+int GattIntf::ClientReadDescriptor(int conn_id, uint16_t handle, int auth_req) const {
+  return intf_->client->read_descriptor(conn_id, handle, auth_req);
+}
+
+int GattIntf::ClientWriteDescriptor(
+    int conn_id, uint16_t handle, int auth_req, ::rust::Slice<const uint8_t> value) const {
+  std::vector<uint8_t> data(value.begin(), value.end());
+
+  return intf_->client->write_descriptor(conn_id, handle, auth_req, std::move(data));
+}
+
+int GattIntf::ClientExecuteWrite(int conn_id, int execute) const {
+  return intf_->client->execute_write(conn_id, execute);
+}
+
+int GattIntf::ClientRegisterForNotification(int client_if, const RustRawAddress& address, uint16_t handle) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return intf_->client->register_for_notification(client_if, addr, handle);
+}
+
+int GattIntf::ClientDeregisterForNotification(int client_if, const RustRawAddress& address, uint16_t handle) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return intf_->client->deregister_for_notification(client_if, addr, handle);
+}
+
+int GattIntf::ClientConfigureMtu(int conn_id, int mtu) const {
+  return intf_->client->configure_mtu(conn_id, mtu);
+}
+
+int GattIntf::ClientReadPhy(int client_if, const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return intf_->client->read_phy(addr, base::Bind(&internal::client_read_phy_cb, client_if, addr));
+}
+
+int GattIntf::ServerRegisterServer(const BtUuid& uuid, bool eatt_support) const {
+  return intf_->server->register_server(internal::from_rust_uuid(uuid), eatt_support);
+}
+
+int GattIntf::ServerUnregisterServer(int server_if) const {
+  return intf_->server->unregister_server(server_if);
+}
+
+int GattIntf::ServerAddService(int server_if, ::rust::Slice<const BtGattDbElement> service) const {
+  std::vector<btgatt_db_element_t> elements;
+  std::transform(service.begin(), service.end(), std::back_inserter(elements), internal::from_rust_db_element);
+
+  return intf_->server->add_service(server_if, std::move(elements));
+}
+
+int GattIntf::ServerDeleteService(int server_if, int service_handle) const {
+  return intf_->server->delete_service(server_if, service_handle);
+}
+
+int GattIntf::ServerSendIndication(
+    int server_if, int attribute_handle, int conn_id, bool confirm, ::rust::Slice<const uint8_t> value) const {
+  std::vector<uint8_t> data(value.begin(), value.end());
+
+  return intf_->server->send_indication(server_if, attribute_handle, conn_id, confirm, std::move(data));
+}
+
+int GattIntf::ServerSendResponse(
+    int conn_id, int trans_id, int status, int handle, int offset, ::rust::Slice<const uint8_t> value) const {
+  btgatt_response_t response = {};
+  response.attr_value.handle = handle;
+  response.attr_value.offset = offset;
+  response.attr_value.len = std::min(value.size(), sizeof(response.attr_value.value));
+  std::copy_n(value.begin(), response.attr_value.len, response.attr_value.value);
+
+  return intf_->server->send_response(conn_id, trans_id, status, response);
+}
+
+int GattIntf::ServerReadPhy(int server_if, const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  return intf_->server->read_phy(addr, base::Bind(&internal::server_read_phy_cb, server_if, addr));
+}
+
+void GattIntf::ScannerRegisterScanner(const BtUuid& uuid) const {
+  bluetooth::Uuid app_uuid = internal::from_rust_uuid(uuid);
+
+  intf_->scanner->RegisterScanner(app_uuid, base::Bind(&internal::scanner_registered_cb, app_uuid));
+}
+
+void GattIntf::ScannerUnregister(int scanner_id) const {
+  intf_->scanner->Unregister(scanner_id);
+}
+
+void GattIntf::ScannerScan(bool start) const {
+  intf_->scanner->Scan(start);
+}
+
+void GattIntf::ScannerSetScanParameters(int interval, int window) const {
+  // Only the LE 1M PHY is scanned.
+  std::vector<uint32_t> intervals = {static_cast<uint32_t>(interval)};
+  std::vector<uint32_t> windows = {static_cast<uint32_t>(window)};
+
+  intf_->scanner->SetScanParameters(1 /* PHY_LE_1M */, intervals, windows, base::DoNothing());
+}
+
+void GattIntf::ScannerScanFilterAdd(
+    uint8_t filter_index,
+    int8_t rssi_high_threshold,
+    int8_t rssi_low_threshold,
+    ::rust::Slice<const BtApcfCondition> conditions) const {
+  std::vector<ApcfCommand> commands;
+  uint16_t features = 0;
+  for (const auto& condition : conditions) {
+    ApcfCommand command = {};
+    command.type = condition.filter_type;
+    command.company = condition.company;
+    command.company_mask = condition.company_mask;
+    command.data.assign(condition.data.begin(), condition.data.end());
+    command.data_mask.assign(condition.data_mask.begin(), condition.data_mask.end());
+    commands.push_back(std::move(command));
+
+    features |= 1 << condition.filter_type;
+  }
+
+  // Any of the conditions lets an advertisement through. Advertisements are reported as soon as
+  // they pass the filter, rather than tracked by the controller.
+  auto params = std::make_unique<btgatt_filt_param_setup_t>();
+  params->feat_seln = features;
+  params->list_logic_type = 0 /* BTM_BLE_PF_LOGIC_OR */;
+  params->filt_logic_type = 0 /* BTM_BLE_PF_LOGIC_OR */;
+  params->rssi_high_thres = static_cast<uint8_t>(rssi_high_threshold);
+  params->rssi_low_thres = static_cast<uint8_t>(rssi_low_threshold);
+  params->dely_mode = 0;
+
+  intf_->scanner->ScanFilterAdd(filter_index, std::move(commands), base::DoNothing());
+  // The scanner id is only used to track advertisers, which the immediate delivery mode doesn't.
+  intf_->scanner->ScanFilterParamSetup(
+      0,
+      0 /* BTM_BLE_SCAN_COND_ADD */,
+      filter_index,
+      std::move(params),
+      base::Bind(&internal::scan_filter_param_setup_cb, filter_index));
+}
+
+void GattIntf::ScannerScanFilterClear(uint8_t filter_index) const {
+  intf_->scanner->ScanFilterParamSetup(
+      0, 1 /* BTM_BLE_SCAN_COND_DELETE */, filter_index, nullptr, base::DoNothing());
+  intf_->scanner->ScanFilterClear(filter_index, base::DoNothing());
+}
+
+void GattIntf::ScannerScanFilterEnable(bool enable) const {
+  intf_->scanner->ScanFilterEnable(enable, base::DoNothing());
+}
+
+void GattIntf::AdvertiserStartAdvertisingSet(
+    int reg_id,
+    const BtAdvertiseParameters& params,
+    ::rust::Slice<const uint8_t> advertise_data,
+    ::rust::Slice<const uint8_t> scan_response_data,
+    uint16_t duration,
+    uint8_t max_ext_adv_events) const {
+  std::vector<uint8_t> adv_data(advertise_data.begin(), advertise_data.end());
+  std::vector<uint8_t> scan_rsp(scan_response_data.begin(), scan_response_data.end());
+  // Periodic advertising is not supported.
+  PeriodicAdvertisingParameters periodic_params = {};
+
+  intf_->advertiser->StartAdvertisingSet(
+      reg_id,
+      base::Bind(&internal::advertising_set_started_cb, reg_id),
+      internal::from_rust_advertise_parameters(params),
+      std::move(adv_data),
+      std::move(scan_rsp),
+      periodic_params,
+      std::vector<uint8_t>(),
+      duration,
+      max_ext_adv_events,
+      base::Bind(&internal::advertising_timeout_cb));
+}
+
+void GattIntf::AdvertiserSetParameters(int advertiser_id, const BtAdvertiseParameters& params) const {
+  intf_->advertiser->SetParameters(
+      advertiser_id,
+      internal::from_rust_advertise_parameters(params),
+      base::Bind(&internal::advertising_parameters_updated_cb, advertiser_id));
+}
+
+void GattIntf::AdvertiserSetData(int advertiser_id, bool set_scan_rsp, ::rust::Slice<const uint8_t> data) const {
+  std::vector<uint8_t> value(data.begin(), data.end());
+
+  intf_->advertiser->SetData(
+      advertiser_id,
+      set_scan_rsp,
+      std::move(value),
+      base::Bind(&internal::advertising_data_set_cb, advertiser_id, set_scan_rsp));
+}
+
+void GattIntf::AdvertiserEnable(int advertiser_id, bool enable, uint16_t duration, uint8_t max_ext_adv_events)
+    const {
+  intf_->advertiser->Enable(
+      advertiser_id,
+      enable,
+      base::Bind(&internal::advertising_enabled_cb, advertiser_id, enable),
+      duration,
+      max_ext_adv_events,
+      base::Bind(&internal::advertising_enabled_cb, advertiser_id, false));
+}
+
+void GattIntf::AdvertiserGetOwnAddress(int advertiser_id) const {
+  intf_->advertiser->GetOwnAddress(advertiser_id, base::Bind(&internal::own_address_read_cb, advertiser_id));
+}
+
+void GattIntf::AdvertiserUnregister(int advertiser_id) const {
+  intf_->advertiser->Unregister(advertiser_id);
+}
+
+std::unique_ptr<GattIntf> LoadGatt(const BluetoothIntf& btif) {
+  // Don't allow the GATT interface to be allocated twice
+  if (internal::g_gattif) std::abort();
+
+  auto intf = static_cast<const btgatt_interface_t*>(btif.GetProfileInterface(BT_PROFILE_GATT_ID));
+  auto gattif = std::make_unique<GattIntf>(intf);
+  internal::g_gattif = gattif.get();
+  return gattif;
+}
+
+}  // namespace rust
+}  // namespace topshim
+}  // namespace bluetooth
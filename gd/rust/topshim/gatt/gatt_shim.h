@@ -0,0 +1,123 @@
+/*
+ * Copyright (C) 2021 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#ifndef GD_RUST_TOPSHIM_GATT_GATT_SHIM_H
+#define GD_RUST_TOPSHIM_GATT_GATT_SHIM_H
+
+#include <memory>
+
+#include "btif/btif_shim.h"
+#include "include/hardware/bt_gatt.h"
+#include "rust/cxx.h"
+
+namespace bluetooth {
+namespace topshim {
+namespace rust {
+
+struct RustGattCallbacks;
+struct BtAdvertiseParameters;
+struct BtApcfCondition;
+struct BtGattDbElement;
+
+class GattIntf {
+ public:
+  GattIntf(const btgatt_interface_t* intf) : init_(false), intf_(intf){};
+  ~GattIntf();
+
+  bool Initialize(::rust::Box<RustGattCallbacks> callbacks);
+  void CleanUp();
+
+  // GATT client
+  int ClientRegisterClient(const BtUuid& uuid, bool eatt_support) const;
+  int ClientUnregisterClient(int client_if) const;
+  int ClientConnect(
+      int client_if,
+      const RustRawAddress& address,
+      bool is_direct,
+      int transport,
+      bool opportunistic,
+      int initiating_phys) const;
+  int ClientDisconnect(int client_if, const RustRawAddress& address, int conn_id) const;
+  int ClientReadRemoteRssi(int client_if, const RustRawAddress& address) const;
+  int ClientSearchService(int conn_id) const;
+  int ClientGetGattDb(int conn_id) const;
+  int ClientReadCharacteristic(int conn_id, uint16_t handle, int auth_req) const;
+  int ClientReadUsingCharacteristicUuid(
+      int conn_id, const BtUuid& uuid, uint16_t start_handle, uint16_t end_handle, int auth_req) const;
+  int ClientWriteCharacteristic(
+      int conn_id, int handle, int write_type, int auth_req, ::rust::Slice<const uint8_t> value) const;
+  int ClientReadDescriptor(int conn_id, uint16_t handle, int auth_req) const;
+  int ClientWriteDescriptor(int conn_id, uint16_t handle, int auth_req, ::rust::Slice<const uint8_t> value) const;
+  int ClientExecuteWrite(int conn_id, int execute) const;
+  int ClientRegisterForNotification(int client_if, const RustRawAddress& address, uint16_t handle) const;
+  int ClientDeregisterForNotification(int client_if, const RustRawAddress& address, uint16_t handle) const;
+  int ClientConfigureMtu(int conn_id, int mtu) const;
+  int ClientReadPhy(int client_if, const RustRawAddress& address) const;
+
+  // GATT server
+  int ServerRegisterServer(const BtUuid& uuid, bool eatt_support) const;
+  int ServerUnregisterServer(int server_if) const;
+  int ServerAddService(int server_if, ::rust::Slice<const BtGattDbElement> service) const;
+  int ServerDeleteService(int server_if, int service_handle) const;
+  int ServerSendIndication(
+      int server_if, int attribute_handle, int conn_id, bool confirm, ::rust::Slice<const uint8_t> value) const;
+  int ServerSendResponse(
+      int conn_id, int trans_id, int status, int handle, int offset, ::rust::Slice<const uint8_t> value) const;
+  int ServerReadPhy(int server_if, const RustRawAddress& address) const;
+
+  // LE scanner
+  void ScannerRegisterScanner(const BtUuid& uuid) const;
+  void ScannerUnregister(int scanner_id) const;
+  void ScannerScan(bool start) const;
+  void ScannerSetScanParameters(int interval, int window) const;
+  void ScannerScanFilterAdd(
+      uint8_t filter_index,
+      int8_t rssi_high_threshold,
+      int8_t rssi_low_threshold,
+      ::rust::Slice<const BtApcfCondition> conditions) const;
+  void ScannerScanFilterClear(uint8_t filter_index) const;
+  void ScannerScanFilterEnable(bool enable) const;
+
+  // LE advertiser
+  void AdvertiserStartAdvertisingSet(
+      int reg_id,
+      const BtAdvertiseParameters& params,
+      ::rust::Slice<const uint8_t> advertise_data,
+      ::rust::Slice<const uint8_t> scan_response_data,
+      uint16_t duration,
+      uint8_t max_ext_adv_events) const;
+  void AdvertiserSetParameters(int advertiser_id, const BtAdvertiseParameters& params) const;
+  void AdvertiserSetData(int advertiser_id, bool set_scan_rsp, ::rust::Slice<const uint8_t> data) const;
+  void AdvertiserEnable(int advertiser_id, bool enable, uint16_t duration, uint8_t max_ext_adv_events) const;
+  void AdvertiserGetOwnAddress(int advertiser_id) const;
+  void AdvertiserUnregister(int advertiser_id) const;
+
+  ::rust::Box<RustGattCallbacks>* GetCallbacks() {
+    return callbacks_.get();
+  }
+
+ private:
+  std::unique_ptr<::rust::Box<RustGattCallbacks>> callbacks_;
+  bool init_;
+  const btgatt_interface_t* intf_;
+};
+
+std::unique_ptr<GattIntf> LoadGatt(const BluetoothIntf& btif);
+
+}  // namespace rust
+}  // namespace topshim
+}  // namespace bluetooth
+
+#endif  // GD_RUST_TOPSHIM_GATT_GATT_SHIM_H
@@ -0,0 +1,160 @@
+/*
+ * Copyright (C) 2021 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#include "gd/rust/topshim/le_audio/le_audio_shim.h"
+
+#include <algorithm>
+#include <memory>
+
+#include "include/hardware/bluetooth.h"
+#include "rust/cxx.h"
+#include "src/btif.rs.h"
+#include "src/profiles/le_audio.rs.h"
+
+namespace bluetooth {
+namespace topshim {
+namespace rust {
+namespace internal {
+// The LE audio callbacks don't carry a pointer back to the interface object so
+// only one LE audio interface can exist at a time.
+static LeAudioIntf* g_leaudioif;
+
+namespace rusty = ::bluetooth::topshim::rust;
+
+static RustRawAddress to_rust_address(const RawAddress& address) {
+  RustRawAddress raddr;
+  std::copy(std::begin(address.address), std::end(address.address), std::begin(raddr.address));
+
+  return raddr;
+}
+
+static RawAddress from_rust_address(const RustRawAddress& address) {
+  RawAddress r;
+  r.FromOctets(address.address.data());
+
+  return r;
+}
+
+// Returns the callbacks of the interface, or null if they are gone. The native
+// profile has no way to unregister its callbacks, so it may still call them
+// after the interface is cleaned up or destroyed.
+static ::rust::Box<RustLeAudioCallbacks>* get_callbacks() {
+  if (!g_leaudioif) return nullptr;
+
+  return g_leaudioif->GetCallbacks();
+}
+
+class LeAudioCallbacksShim : public le_audio::LeAudioClientCallbacks {
+ public:
+  void OnConnectionState(le_audio::ConnectionState state, const RawAddress& address) override {
+    auto callbacks = get_callbacks();
+    if (!callbacks) return;
+
+    rusty::le_audio_connection_state_callback(**callbacks, to_rust_address(address), static_cast<int32_t>(state));
+  }
+
+  void OnGroupStatus(uint8_t group_id, le_audio::GroupStatus group_status, uint8_t group_flags) override {
+    auto callbacks = get_callbacks();
+    if (!callbacks) return;
+
+    rusty::le_audio_group_status_callback(**callbacks, group_id, static_cast<int32_t>(group_status), group_flags);
+  }
+
+  void OnAudioConf(
+      const RawAddress& addr,
+      uint8_t direction,
+      uint8_t group_id,
+      uint32_t snk_audio_location,
+      uint32_t src_audio_location) override {
+    auto callbacks = get_callbacks();
+    if (!callbacks) return;
+
+    rusty::le_audio_audio_conf_callback(
+        **callbacks, to_rust_address(addr), direction, group_id, snk_audio_location, src_audio_location);
+  }
+
+  void OnSetMemberAvailable(const RawAddress& address, uint8_t group_id) override {
+    auto callbacks = get_callbacks();
+    if (!callbacks) return;
+
+    rusty::le_audio_set_member_available_callback(**callbacks, to_rust_address(address), group_id);
+  }
+};
+
+static LeAudioCallbacksShim g_callbacks;
+}  // namespace internal
+
+LeAudioIntf::~LeAudioIntf() {
+  internal::g_leaudioif = nullptr;
+}
+
+bool LeAudioIntf::Initialize(::rust::Box<RustLeAudioCallbacks> callbacks) {
+  if (init_) return true;
+  if (!intf_) return false;
+
+  callbacks_ = std::make_unique<::rust::Box<RustLeAudioCallbacks>>(std::move(callbacks));
+  intf_->Initialize(&internal::g_callbacks);
+
+  init_ = true;
+  return init_;
+}
+
+void LeAudioIntf::CleanUp() {
+  if (!init_) return;
+
+  intf_->Cleanup();
+  callbacks_.reset();
+  init_ = false;
+}
+
+void LeAudioIntf::Connect(const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  intf_->Connect(addr);
+}
+
+void LeAudioIntf::Disconnect(const RustRawAddress& address) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  intf_->Disconnect(addr);
+}
+
+void LeAudioIntf::GroupStream(uint8_t group_id, uint16_t content_type) const {
+  intf_->GroupStream(group_id, content_type);
+}
+
+void LeAudioIntf::GroupSuspend(uint8_t group_id) const {
+  intf_->GroupSuspend(group_id);
+}
+
+void LeAudioIntf::GroupStop(uint8_t group_id) const {
+  intf_->GroupStop(group_id);
+}
+
+std::unique_ptr<LeAudioIntf> LoadLeAudio(const BluetoothIntf& btif) {
+  // Don't allow the LE audio interface to be allocated twice
+  if (internal::g_leaudioif) std::abort();
+
+  auto intf = static_cast<le_audio::LeAudioClientInterface*>(
+      const_cast<void*>(btif.GetProfileInterface(BT_PROFILE_LE_AUDIO_ID)));
+  auto leaudioif = std::make_unique<LeAudioIntf>(intf);
+  internal::g_leaudioif = leaudioif.get();
+  return leaudioif;
+}
+
+}  // namespace rust
+}  // namespace topshim
+}  // namespace bluetooth
@@ -0,0 +1,64 @@
+/*
+ * Copyright (C) 2021 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#ifndef GD_RUST_TOPSHIM_LE_AUDIO_LE_AUDIO_SHIM_H
+#define GD_RUST_TOPSHIM_LE_AUDIO_LE_AUDIO_SHIM_H
+
+#include <memory>
+
+#include "btif/btif_shim.h"
+#include "include/hardware/bt_le_audio.h"
+#include "rust/cxx.h"
+
+namespace bluetooth {
+namespace topshim {
+namespace rust {
+
+struct RustLeAudioCallbacks;
+
+class LeAudioIntf {
+ public:
+  LeAudioIntf(le_audio::LeAudioClientInterface* intf) : init_(false), intf_(intf){};
+  ~LeAudioIntf();
+
+  bool Initialize(::rust::Box<RustLeAudioCallbacks> callbacks);
+  void CleanUp();
+
+  void Connect(const RustRawAddress& address) const;
+  void Disconnect(const RustRawAddress& address) const;
+
+  void GroupStream(uint8_t group_id, uint16_t content_type) const;
+  void GroupSuspend(uint8_t group_id) const;
+  void GroupStop(uint8_t group_id) const;
+
+  // Null once the interface is cleaned up.
+  ::rust::Box<RustLeAudioCallbacks>* GetCallbacks() {
+    return callbacks_.get();
+  }
+
+ private:
+  std::unique_ptr<::rust::Box<RustLeAudioCallbacks>> callbacks_;
+  bool init_;
+  le_audio::LeAudioClientInterface* intf_;
+};
+
+std::unique_ptr<LeAudioIntf> LoadLeAudio(const BluetoothIntf& btif);
+
+}  // namespace rust
+}  // namespace topshim
+}  // namespace bluetooth
+
+#endif  // GD_RUST_TOPSHIM_LE_AUDIO_LE_AUDIO_SHIM_H
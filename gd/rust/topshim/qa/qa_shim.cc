@@ -0,0 +1,107 @@
+/*
+ * Copyright (C) 2021 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#include "gd/rust/topshim/qa/qa_shim.h"
+
+#include <algorithm>
+#include <memory>
+
+#include "base/bind.h"
+#include "base/location.h"
+#include "include/hardware/bluetooth.h"
+#include "rust/cxx.h"
+#include "src/btif.rs.h"
+#include "src/qa.rs.h"
+#include "stack/include/acl_api.h"
+#include "stack/include/btm_status.h"
+#include "stack/include/btu.h"
+
+namespace bluetooth {
+namespace topshim {
+namespace rust {
+namespace internal {
+// The BTM completion callbacks only carry the command result so only one QA
+// interface can exist at a time.
+static QaIntf* g_qaif;
+
+namespace rusty = ::bluetooth::topshim::rust;
+
+static RustRawAddress to_rust_address(const RawAddress& address) {
+  RustRawAddress raddr;
+  std::copy(std::begin(address.address), std::end(address.address), std::begin(raddr.address));
+
+  return raddr;
+}
+
+static RawAddress from_rust_address(const RustRawAddress& address) {
+  RawAddress r;
+  r.FromOctets(address.address.data());
+
+  return r;
+}
+
+static void read_max_tx_power_cb(void* p) {
+  if (!g_qaif) return;
+
+  auto result = static_cast<tBTM_TX_POWER_RESULT*>(p);
+  // A null result means the command timed out.
+  if (!result) {
+    rusty::qa_read_max_tx_power_callback(*g_qaif->GetCallbacks(), RustRawAddress{}, BTM_ERR_PROCESSING, 0);
+    return;
+  }
+
+  rusty::qa_read_max_tx_power_callback(
+      *g_qaif->GetCallbacks(), to_rust_address(result->rem_bda), result->status, result->tx_power);
+}
+
+static void read_max_tx_power(RawAddress address, tBT_TRANSPORT transport) {
+  tBTM_STATUS status = BTM_ReadMaxTxPower(address, transport, read_max_tx_power_cb);
+
+  // The completion callback is only called if the command was sent.
+  if (status != BTM_CMD_STARTED && g_qaif) {
+    rusty::qa_read_max_tx_power_callback(*g_qaif->GetCallbacks(), to_rust_address(address), status, 0);
+  }
+}
+}  // namespace internal
+
+QaIntf::QaIntf(::rust::Box<RustQaCallbacks> callbacks) {
+  callbacks_ = std::make_unique<::rust::Box<RustQaCallbacks>>(std::move(callbacks));
+}
+
+QaIntf::~QaIntf() {
+  internal::g_qaif = nullptr;
+}
+
+int QaIntf::ReadMaxTxPower(const RustRawAddress& address, int transport) const {
+  RawAddress addr = internal::from_rust_address(address);
+
+  // BTM must be called from the main thread.
+  return do_in_main_thread(
+      FROM_HERE, base::BindOnce(internal::read_max_tx_power, addr, static_cast<tBT_TRANSPORT>(transport)));
+}
+
+std::unique_ptr<QaIntf> LoadQa(::rust::Box<RustQaCallbacks> callbacks) {
+  // Don't allow the QA interface to be allocated twice
+  if (internal::g_qaif) std::abort();
+
+  auto qaif = std::make_unique<QaIntf>(std::move(callbacks));
+  internal::g_qaif = qaif.get();
+  return qaif;
+}
+
+}  // namespace rust
+}  // namespace topshim
+}  // namespace bluetooth
@@ -0,0 +1,52 @@
+/*
+ * Copyright (C) 2021 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#ifndef GD_RUST_TOPSHIM_QA_QA_SHIM_H
+#define GD_RUST_TOPSHIM_QA_QA_SHIM_H
+
+#include <memory>
+
+#include "rust/cxx.h"
+
+namespace bluetooth {
+namespace topshim {
+namespace rust {
+
+struct RustQaCallbacks;
+struct RustRawAddress;
+
+// Controller test commands that are not exposed by the Bluetooth HAL interface.
+class QaIntf {
+ public:
+  QaIntf(::rust::Box<RustQaCallbacks> callbacks);
+  ~QaIntf();
+
+  int ReadMaxTxPower(const RustRawAddress& address, int transport) const;
+
+  ::rust::Box<RustQaCallbacks>& GetCallbacks() {
+    return *callbacks_;
+  }
+
+ private:
+  std::unique_ptr<::rust::Box<RustQaCallbacks>> callbacks_;
+};
+
+std::unique_ptr<QaIntf> LoadQa(::rust::Box<RustQaCallbacks> callbacks);
+
+}  // namespace rust
+}  // namespace topshim
+}  // namespace bluetooth
+
+#endif  // GD_RUST_TOPSHIM_QA_QA_SHIM_H
@@ -0,0 +1,41 @@
+/*
+ * Copyright (C) 2021 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#include "gd/rust/topshim/snoop/snoop_shim.h"
+
+#include <string>
+
+#include "hci/include/btsnoop.h"
+#include "main/shim/shim.h"
+#include "rust/cxx.h"
+#include "src/snoop.rs.h"
+
+namespace bluetooth {
+namespace topshim {
+namespace rust {
+
+bool SetSnoopLogConfig(::rust::Str mode, ::rust::Str path, int32_t max_packets_per_file) {
+  // The legacy HCI layer isn't started with the GD stack, whose snoop logger is only configured
+  // at startup.
+  if (::bluetooth::shim::is_any_gd_enabled()) return false;
+
+  btsnoop_set_config(std::string(mode).c_str(), std::string(path).c_str(), max_packets_per_file);
+  return true;
+}
+
+}  // namespace rust
+}  // namespace topshim
+}  // namespace bluetooth
@@ -0,0 +1,314 @@
+//! ACL interface shim
+//!
+//! This is a shim for reading and configuring the state of ACL connections, which the Bluetooth
+//! HAL interface doesn't expose.
+
+use std::sync::Arc;
+
+use crate::topstack;
+
+#[cxx::bridge(namespace = bluetooth::topshim::rust)]
+pub mod ffi {
+    /// Security of the LE link to a device.
+    pub struct LeLinkSecurity {
+        encrypted: bool,
+        /// Whether the device is bonded, i.e. the link can be encrypted without pairing.
+        key_known: bool,
+        /// Whether the key was exchanged with MITM protection.
+        key_authenticated: bool,
+    }
+
+    /// Security of the link to a device over a transport.
+    pub struct LinkSecurity {
+        connected: bool,
+        encrypted: bool,
+        /// Whether the link is encrypted with a key exchanged with MITM protection.
+        authenticated: bool,
+        /// Whether the key was exchanged with Secure Connections pairing.
+        secure_connections: bool,
+    }
+
+    unsafe extern "C++" {
+        include!("acl/acl_shim.h");
+
+        type RustRawAddress = crate::btif::ffi::RustRawAddress;
+
+        // Opaque type meant to represent C object for the ACL interface.
+        type AclIntf;
+
+        fn LoadAcl(callbacks: Box<RustAclCallbacks>) -> UniquePtr<AclIntf>;
+
+        fn ReadRssi(&self, address: &RustRawAddress) -> i32;
+        fn SetPreferredPhy(
+            &self,
+            address: &RustRawAddress,
+            tx_phys: u8,
+            rx_phys: u8,
+            phy_options: u16,
+        ) -> i32;
+        fn ReadPhy(&self, address: &RustRawAddress) -> i32;
+        fn UpdateConnectionParameters(
+            &self,
+            address: &RustRawAddress,
+            min_interval: u16,
+            max_interval: u16,
+            latency: u16,
+            timeout: u16,
+        ) -> i32;
+        fn SetDataLength(&self, address: &RustRawAddress, tx_pdu_length: u16) -> i32;
+        fn GetLeLinkSecurity(&self, address: &RustRawAddress) -> LeLinkSecurity;
+        fn GetLinkSecurity(&self, address: &RustRawAddress, transport: i32) -> LinkSecurity;
+        fn RequestLeEncryption(&self, address: &RustRawAddress, mitm: bool) -> i32;
+        fn ResolveIdentityAddress(&self, address: &RustRawAddress) -> RustRawAddress;
+        fn ConfigureLePrivacy(&self, enable: bool) -> i32;
+        fn IsLePrivacyEnabled(&self) -> bool;
+        fn SetRpaTimeout(&self, timeout: u16) -> i32;
+        fn SetLeStaticAddress(&self, address: &RustRawAddress) -> i32;
+        fn GetLinkRole(&self, address: &RustRawAddress, transport: i32) -> i32;
+        fn SwitchRoleToCentral(&self, address: &RustRawAddress) -> i32;
+        fn Disconnect(&self, address: &RustRawAddress) -> i32;
+    }
+
+    extern "Rust" {
+        type RustAclCallbacks;
+
+        // Callbacks from C++ to Rust. See `btif::ffi` for how the callbacks are stored.
+
+        fn acl_read_rssi_callback(
+            cb: &RustAclCallbacks,
+            address: RustRawAddress,
+            status: i32,
+            rssi: i32,
+        );
+        fn acl_read_phy_callback(
+            cb: &RustAclCallbacks,
+            address: RustRawAddress,
+            status: i32,
+            tx_phy: u8,
+            rx_phy: u8,
+        );
+        fn acl_switch_role_callback(cb: &RustAclCallbacks, address: RustRawAddress, status: i32);
+    }
+
+    unsafe impl Box<RustAclCallbacks> {}
+}
+
+/// Rust struct of closures for all ACL callbacks from C++.
+pub struct AclCallbacks {
+    /// Called with the BTM status and the RSSI in dBm of a connection.
+    pub read_rssi: Box<dyn Fn(ffi::RustRawAddress, i32, i32) + Send>,
+    /// Called with the HCI status and the transmitter and receiver PHYs of an LE connection.
+    pub read_phy: Box<dyn Fn(ffi::RustRawAddress, i32, u8, u8) + Send>,
+    /// Called with the BTM status of a switch of a BR/EDR connection to the central role, 0 if
+    /// the role was already central and 1 once the switch is started.
+    pub switch_role: Box<dyn Fn(ffi::RustRawAddress, i32) + Send>,
+}
+
+impl Default for AclCallbacks {
+    /// Constructs callbacks that ignore every event.
+    fn default() -> Self {
+        AclCallbacks {
+            read_rssi: Box::new(|_, _, _| {}),
+            read_phy: Box::new(|_, _, _, _| {}),
+            switch_role: Box::new(|_, _| {}),
+        }
+    }
+}
+
+pub struct RustAclCallbacks {
+    inner: Arc<AclCallbacks>,
+}
+
+/// Rust interface to the native ACL connections.
+pub struct Acl {
+    internal: cxx::UniquePtr<ffi::AclIntf>,
+}
+
+impl Acl {
+    pub fn new(callbacks: Arc<AclCallbacks>) -> Acl {
+        Acl { internal: ffi::LoadAcl(Box::new(RustAclCallbacks { inner: callbacks.clone() })) }
+    }
+
+    /// Reads the RSSI of the connection to a device, over LE or BR/EDR. This triggers a read_rssi
+    /// callback.
+    ///
+    /// Only one read can be in flight in the stack, a read made meanwhile (e.g. through GATT)
+    /// fails with a busy status.
+    pub fn read_rssi(&mut self, address: &ffi::RustRawAddress) -> i32 {
+        self.internal.ReadRssi(address)
+    }
+
+    /// Sets the PHYs preferred for the LE connection to a device, as bitmasks of the HCI LE Set
+    /// PHY command (bit 0 for LE 1M, bit 1 for LE 2M and bit 2 for LE Coded).
+    ///
+    /// The controller may or may not switch to the preferred PHYs, depending on what the remote
+    /// device supports. The PHY update is reported to the GATT clients of the connection.
+    pub fn set_preferred_phy(
+        &mut self,
+        address: &ffi::RustRawAddress,
+        tx_phys: u8,
+        rx_phys: u8,
+        phy_options: u16,
+    ) -> i32 {
+        self.internal.SetPreferredPhy(address, tx_phys, rx_phys, phy_options)
+    }
+
+    /// Reads the PHYs of the LE connection to a device. This triggers a read_phy callback, which
+    /// fails if the device has no LE connection.
+    pub fn read_phy(&mut self, address: &ffi::RustRawAddress) -> i32 {
+        self.internal.ReadPhy(address)
+    }
+
+    /// Requests new parameters for the LE connection to a device. Intervals are in units of
+    /// 1.25 ms, `latency` is in connection events and `timeout` in units of 10 ms.
+    ///
+    /// The parameters negotiated with the remote device are reported to the GATT clients of the
+    /// connection.
+    pub fn update_connection_parameters(
+        &mut self,
+        address: &ffi::RustRawAddress,
+        min_interval: u16,
+        max_interval: u16,
+        latency: u16,
+        timeout: u16,
+    ) -> i32 {
+        self.internal.UpdateConnectionParameters(
+            address,
+            min_interval,
+            max_interval,
+            latency,
+            timeout,
+        )
+    }
+
+    /// Sets the maximum number of payload octets the controller sends in an LE packet of the
+    /// connection to a device. The controller clamps it to what it supports.
+    pub fn set_data_length(&mut self, address: &ffi::RustRawAddress, tx_pdu_length: u16) -> i32 {
+        self.internal.SetDataLength(address, tx_pdu_length)
+    }
+
+    /// Returns the security of the LE link to a device, as last updated by the stack.
+    pub fn get_le_link_security(&self, address: &ffi::RustRawAddress) -> ffi::LeLinkSecurity {
+        self.internal.GetLeLinkSecurity(address)
+    }
+
+    /// Returns the security of the link to a device over a transport, 1 for BR/EDR and 2 for LE,
+    /// as last updated by the stack on encryption changes.
+    pub fn get_link_security(
+        &self,
+        address: &ffi::RustRawAddress,
+        transport: i32,
+    ) -> ffi::LinkSecurity {
+        self.internal.GetLinkSecurity(address, transport)
+    }
+
+    /// Encrypts the LE link to a device, pairing first if the device is not bonded or, if `mitm`
+    /// is set, its key was exchanged without MITM protection.
+    ///
+    /// As a peripheral, this sends a security request for the central to start the procedure.
+    pub fn request_le_encryption(&mut self, address: &ffi::RustRawAddress, mitm: bool) -> i32 {
+        self.internal.RequestLeEncryption(address, mitm)
+    }
+
+    /// Returns the identity address of the bonded device a resolvable private address belongs
+    /// to, found with the IRKs of the bonded devices, or None if it belongs to none of them.
+    ///
+    /// This runs an AES computation per bonded device on the stack's main thread and waits for it,
+    /// so it is meant for connections rather than every advertisement.
+    pub fn resolve_identity_address(
+        &self,
+        address: &ffi::RustRawAddress,
+    ) -> Option<ffi::RustRawAddress> {
+        let identity = self.internal.ResolveIdentityAddress(address);
+        if identity.address == [0; 6] {
+            None
+        } else {
+            Some(identity)
+        }
+    }
+
+    /// Enables or disables LE privacy, i.e. whether the local device uses resolvable private
+    /// addresses rather than its public address over LE.
+    ///
+    /// Advertising sets keep the address they registered with, only sets started afterwards use
+    /// the new setting. The stack enables privacy again whenever the adapter is enabled.
+    pub fn configure_le_privacy(&mut self, enable: bool) -> i32 {
+        self.internal.ConfigureLePrivacy(enable)
+    }
+
+    /// Returns whether LE privacy is enabled, as read on the stack's main thread once the changes
+    /// already requested are applied. False if the stack is not running.
+    pub fn is_le_privacy_enabled(&self) -> bool {
+        self.internal.IsLePrivacyEnabled()
+    }
+
+    /// Sets how often, in seconds, the controller rotates the resolvable private addresses it
+    /// generates for the devices of its resolving list.
+    ///
+    /// The addresses generated by the stack, e.g. for the advertising sets, keep rotating every 7
+    /// to 15 minutes. The stack sets a new timeout whenever the adapter is enabled.
+    pub fn set_rpa_timeout(&mut self, timeout: u16) -> i32 {
+        self.internal.SetRpaTimeout(timeout)
+    }
+
+    /// Makes the adapter use a static random address over LE, rather than its public address or
+    /// resolvable private addresses. This disables LE privacy.
+    ///
+    /// The address is not checked to be a static random address. It is used until LE privacy is
+    /// configured again, and the stack enables privacy again whenever the adapter is enabled.
+    /// Returns `BtStatus::Unsupported` with the GD stack, which manages the LE address itself.
+    pub fn set_le_static_address(&mut self, address: &ffi::RustRawAddress) -> i32 {
+        self.internal.SetLeStaticAddress(address)
+    }
+
+    /// Returns the HCI role of the local device on the connection to a device over a transport,
+    /// 1 for BR/EDR and 2 for LE: 0 for central, 1 for peripheral or 0xFF if not connected.
+    pub fn get_link_role(&self, address: &ffi::RustRawAddress, transport: i32) -> i32 {
+        self.internal.GetLinkRole(address, transport)
+    }
+
+    /// Switches the local device to the central role on the BR/EDR connection to a device. This
+    /// triggers a switch_role callback.
+    ///
+    /// The completion of the switch is not reported, the role has to be read again.
+    pub fn switch_role_to_central(&mut self, address: &ffi::RustRawAddress) -> i32 {
+        self.internal.SwitchRoleToCentral(address)
+    }
+
+    /// Disconnects the BR/EDR and LE connections to a device, whichever are up, whatever profile
+    /// uses them.
+    pub fn disconnect(&mut self, address: &ffi::RustRawAddress) -> i32 {
+        self.internal.Disconnect(address)
+    }
+}
+
+unsafe impl Send for Acl {}
+
+fn acl_read_rssi_callback(
+    cb: &RustAclCallbacks,
+    address: ffi::RustRawAddress,
+    status: i32,
+    rssi: i32,
+) {
+    topstack::catch_callback_panic("acl_read_rssi", || {
+        (cb.inner.read_rssi)(address, status, rssi);
+    });
+}
+
+fn acl_read_phy_callback(
+    cb: &RustAclCallbacks,
+    address: ffi::RustRawAddress,
+    status: i32,
+    tx_phy: u8,
+    rx_phy: u8,
+) {
+    topstack::catch_callback_panic("acl_read_phy", || {
+        (cb.inner.read_phy)(address, status, tx_phy, rx_phy);
+    });
+}
+
+fn acl_switch_role_callback(cb: &RustAclCallbacks, address: ffi::RustRawAddress, status: i32) {
+    topstack::catch_callback_panic("acl_switch_role", || {
+        (cb.inner.switch_role)(address, status);
+    });
+}
@@ -7,10 +7,13 @@
 #![allow(unused_variables)]
 
 use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use std::vec::Vec;
 
-#[derive(FromPrimitive, ToPrimitive, PartialEq, PartialOrd)]
+use crate::topstack;
+
+#[derive(FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Serialize, Deserialize)]
 #[repr(i32)]
 pub enum BtState {
     Off = 0,
@@ -41,14 +44,52 @@ pub enum BtPropertyType {
     RemoteDeviceTimestamp = 0xFF,
 }
 
-#[derive(FromPrimitive, ToPrimitive, PartialEq, PartialOrd)]
+#[derive(FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Debug)]
+#[repr(i32)]
+pub enum BtScanMode {
+    None = 0,
+    Connectable,
+    ConnectableDiscoverable,
+}
+
+#[derive(FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Serialize, Deserialize)]
 #[repr(i32)]
 pub enum BtDiscoveryState {
     Stopped = 0x0,
     Started,
 }
 
-#[derive(FromPrimitive, ToPrimitive, PartialEq, PartialOrd)]
+#[derive(
+    Clone, Copy, FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Debug, Serialize, Deserialize,
+)]
+#[repr(i32)]
+pub enum BtBondState {
+    NotBonded = 0,
+    Bonding,
+    Bonded,
+}
+
+/// Mirrors `bt_ssp_variant_t`, how the user takes part in a Secure Simple Pairing.
+#[derive(
+    Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Serialize, Deserialize,
+)]
+#[repr(i32)]
+pub enum BtSspVariant {
+    PasskeyConfirmation = 0,
+    PasskeyEntry,
+    Consent,
+    PasskeyNotification,
+}
+
+#[derive(FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Debug, Serialize, Deserialize)]
+#[repr(i32)]
+pub enum BtAclState {
+    Connected = 0,
+    Disconnected,
+}
+
+/// Mirrors `bt_status_t`, the status of the requests to the native stack.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq, PartialOrd)]
 #[repr(i32)]
 pub enum BtStatus {
     Success = 0,
@@ -66,11 +107,67 @@ pub enum BtStatus {
     JniEnvironmentError,
     JniThreadAttachError,
     WakeLockError,
+    /// Not reported by btif, used by the stack for requests that got no answer in time.
+    Timeout,
 
     // Any statuses that couldn't be cleanly converted
     Unknown = 0xff,
 }
 
+impl From<i32> for BtStatus {
+    fn from(status: i32) -> Self {
+        BtStatus::from_i32(status).unwrap_or(BtStatus::Unknown)
+    }
+}
+
+/// Profile interfaces that libbluetooth may provide through `get_profile_interface`.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq, PartialOrd)]
+#[repr(i32)]
+pub enum SupportedProfiles {
+    Gatt = 0,
+    A2dp,
+    A2dpSink,
+    Avrcp,
+    HidHost,
+    Hfp,
+    Socket,
+    Pan,
+    HearingAid,
+    LeAudio,
+}
+
+impl SupportedProfiles {
+    /// Every profile that can be queried with `BluetoothInterface::supports_profile`.
+    pub const ALL: [SupportedProfiles; 10] = [
+        SupportedProfiles::Gatt,
+        SupportedProfiles::A2dp,
+        SupportedProfiles::A2dpSink,
+        SupportedProfiles::Avrcp,
+        SupportedProfiles::HidHost,
+        SupportedProfiles::Hfp,
+        SupportedProfiles::Socket,
+        SupportedProfiles::Pan,
+        SupportedProfiles::HearingAid,
+        SupportedProfiles::LeAudio,
+    ];
+
+    /// Returns the profile ID passed to `get_profile_interface`, e.g. `BT_PROFILE_GATT_ID`.
+    fn profile_id(&self) -> &'static str {
+        match self {
+            SupportedProfiles::Gatt => "gatt",
+            SupportedProfiles::A2dp => "a2dp",
+            SupportedProfiles::A2dpSink => "a2dp_sink",
+            SupportedProfiles::Avrcp => "avrcp",
+            SupportedProfiles::HidHost => "hidhost",
+            SupportedProfiles::Hfp => "handsfree",
+            SupportedProfiles::Socket => "socket",
+            SupportedProfiles::Pan => "pan",
+            SupportedProfiles::HearingAid => "hearing_aid",
+            SupportedProfiles::LeAudio => "le_audio",
+        }
+    }
+}
+
 // FFI is a public module because we want Rust and C++ to share enums listed
 // here. We redefine most of the Bluetooth structures we want to use because
 // of memory management issues (for example, some api calls will free the
@@ -82,20 +179,35 @@ pub mod ffi {
         pin: [u8; 16],
     }
 
+    #[derive(Serialize, Deserialize)]
     pub struct BtProperty {
         prop_type: i32,
         len: i32,
         val: Vec<u8>,
     }
 
+    #[derive(Serialize, Deserialize)]
     pub struct BtUuid {
         uuid: [u8; 16],
     }
 
+    #[derive(Serialize, Deserialize)]
     pub struct RustRawAddress {
         address: [u8; 6],
     }
 
+    /// Out of band pairing data, as represented by `bt_oob_data_t`.
+    ///
+    /// `c` and `r` hold the Simple Pairing hash and randomizer for BR/EDR, or the confirmation
+    /// and random values for LE Secure Connections.
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct BtOutOfBandData {
+        is_valid: bool,
+        address: [u8; 7],
+        c: [u8; 16],
+        r: [u8; 16],
+    }
+
     unsafe extern "C++" {
         include!("btif/btif_shim.h");
 
@@ -129,9 +241,14 @@ pub mod ffi {
         fn CancelDiscovery(&self) -> i32;
 
         fn CreateBond(&self, address: &RustRawAddress, transport: i32) -> i32;
-        // TODO(abps): Implement at P3
-        // fn CreateBondOutOfBand(address: &RustRawAddress, transport: i32,
-        //  oob_data: &BtOutOfBandData) -> i32;
+        fn CreateBondOutOfBand(
+            &self,
+            address: &RustRawAddress,
+            transport: i32,
+            p192_data: &BtOutOfBandData,
+            p256_data: &BtOutOfBandData,
+        ) -> i32;
+        fn GenerateLocalOobData(&self, transport: i32) -> i32;
         fn RemoveBond(&self, address: &RustRawAddress) -> i32;
         fn CancelBond(&self, address: &RustRawAddress) -> i32;
 
@@ -152,13 +269,14 @@ pub mod ffi {
             passkey: u32,
         ) -> i32;
 
-        // TODO(abps): Implement at P1
-        // fn GetProfileInterface(profile_id: &str) -> Option<BtProfileInterface>;
+        fn HasProfileInterface(&self, profile_id: &str) -> bool;
 
-        // TODO(abps): Implement at P2
-        // fn dut_mode_configure(enable: u8) -> i32;
-        // fn dut_mode_send(opcode: u16, buf: [u8], len: u8) -> i32;
-        // fn le_test_mode(opcode: u16, buf: [u8], len: u8) -> i32;
+        fn DutModeConfigure(&self, enable: u8) -> i32;
+        fn DutModeSend(&self, opcode: u16, buf: &[u8]) -> i32;
+        fn LeTestMode(&self, opcode: u16, buf: &[u8]) -> i32;
+
+        fn ConfigClear(&self) -> i32;
+        fn SetAutoAcceptTemporaryPairing(&self, auto_accept: bool);
 
         // TODO(abps): Implement at P1
         // fn SetOsCallouts(callouts: Box<RustOsCallouts>) -> i32;
@@ -167,7 +285,6 @@ pub mod ffi {
         // fn ReadEnergyInfo(&self) -> i32;
         // fn Dump(fd: i32, args: &[str]);
         // fn DumpMetrics() -> String;
-        // fn ConfigClear(&self) -> i32;
         // fn InteropDatabaseClear(&self);
         // fn InteropDatabaseAdd(&self, feature: u16, address: &RustRawAddress, match_len: u8);
 
@@ -235,7 +352,23 @@ pub mod ffi {
             state: i32,
             hci_reason: i32,
         );
-
+        fn link_quality_report_callback(
+            cb: &RustCallbacks,
+            timestamp: u64,
+            report_id: i32,
+            rssi: i32,
+            snr: i32,
+            retransmission_count: i32,
+            packets_not_receive_count: i32,
+            negative_acknowledgement_count: i32,
+        );
+        fn generate_local_oob_data_callback(
+            cb: &RustCallbacks,
+            transport: i32,
+            oob_data: BtOutOfBandData,
+        );
+        fn dut_mode_recv_callback(cb: &RustCallbacks, opcode: u16, buf: Vec<u8>);
+        fn le_test_mode_callback(cb: &RustCallbacks, status: i32, num_packets: u16);
     }
 
     unsafe impl Box<RustCallbacks> {}
@@ -254,9 +387,34 @@ pub struct BluetoothCallbacks {
     pub device_found: Box<dyn Fn(i32, Vec<ffi::BtProperty>) + Send>,
     pub discovery_state_changed: Box<dyn Fn(BtDiscoveryState) + Send>,
     pub pin_request: Box<dyn Fn(ffi::RustRawAddress, String, u32, bool) + Send>,
-    pub ssp_request: Box<dyn Fn(ffi::RustRawAddress, String, u32, i32, u32) + Send>,
-    pub bond_state_changed: Box<dyn Fn(i32, ffi::RustRawAddress, i32) + Send>,
-    pub acl_state_changed: Box<dyn Fn(i32, ffi::RustRawAddress, i32, i32) + Send>,
+    pub ssp_request: Box<dyn Fn(ffi::RustRawAddress, String, u32, BtSspVariant, u32) + Send>,
+    pub bond_state_changed: Box<dyn Fn(i32, ffi::RustRawAddress, BtBondState) + Send>,
+    pub acl_state_changed: Box<dyn Fn(i32, ffi::RustRawAddress, BtAclState, i32) + Send>,
+    pub link_quality_report: Box<dyn Fn(u64, i32, i32, i32, i32, i32, i32) + Send>,
+    pub local_oob_data_generated: Box<dyn Fn(i32, ffi::BtOutOfBandData) + Send>,
+    pub dut_mode_recv: Box<dyn Fn(u16, Vec<u8>) + Send>,
+    pub le_test_mode: Box<dyn Fn(i32, u16) + Send>,
+}
+
+impl Default for BluetoothCallbacks {
+    /// Constructs callbacks that ignore every event.
+    fn default() -> Self {
+        BluetoothCallbacks {
+            adapter_state_changed: Box::new(|_| {}),
+            adapter_properties_changed: Box::new(|_, _, _| {}),
+            remote_device_properties_changed: Box::new(|_, _, _, _| {}),
+            device_found: Box::new(|_, _| {}),
+            discovery_state_changed: Box::new(|_| {}),
+            pin_request: Box::new(|_, _, _, _| {}),
+            ssp_request: Box::new(|_, _, _, _, _| {}),
+            bond_state_changed: Box::new(|_, _, _| {}),
+            acl_state_changed: Box::new(|_, _, _, _| {}),
+            link_quality_report: Box::new(|_, _, _, _, _, _, _| {}),
+            local_oob_data_generated: Box::new(|_, _| {}),
+            dut_mode_recv: Box::new(|_, _| {}),
+            le_test_mode: Box::new(|_, _| {}),
+        }
+    }
 }
 
 pub struct RustCallbacks {
@@ -300,6 +458,24 @@ impl BluetoothInterface {
         self.internal.CleanUp()
     }
 
+    /// Returns whether libbluetooth provides the interface of `profile`.
+    ///
+    /// Profiles are only reported once the interface is initialized. Profile shims must not be
+    /// loaded for unsupported profiles.
+    pub fn supports_profile(&self, profile: SupportedProfiles) -> bool {
+        self.internal.HasProfileInterface(profile.profile_id())
+    }
+
+    /// Returns every profile that libbluetooth provides the interface of.
+    pub fn supported_profiles(&self) -> Vec<SupportedProfiles> {
+        SupportedProfiles::ALL.iter().copied().filter(|p| self.supports_profile(*p)).collect()
+    }
+
+    /// Returns the underlying interface so that profile shims can be loaded from it.
+    pub(crate) fn as_ffi(&self) -> &ffi::BluetoothIntf {
+        &self.internal
+    }
+
     pub fn get_adapter_properties(&mut self) -> i32 {
         self.internal.GetAdapterProperties()
     }
@@ -313,9 +489,24 @@ impl BluetoothInterface {
     }
 
     //fn GetRemoteDeviceProperties(&self, address: &RustRawAddress) -> i32;
-    //fn GetRemoteDeviceProperty(&self, address: &RustRawAddress, prop_type: i32) -> i32;
+
+    /// Reads a stored property of a remote device. This triggers a
+    /// remote_device_properties_changed callback.
+    pub fn get_remote_device_property(
+        &mut self,
+        address: &ffi::RustRawAddress,
+        prop_type: i32,
+    ) -> i32 {
+        self.internal.GetRemoteDeviceProperty(address, prop_type)
+    }
+
     //fn SetRemoteDeviceProperty(&self, address: &RustRawAddress, prop: &BtProperty) -> i32;
-    //fn GetRemoteServices(&self, address: &RustRawAddress) -> i32;
+
+    /// Starts a service discovery on a remote device. The UUIDs found are reported through a
+    /// remote_device_properties_changed callback.
+    pub fn get_remote_services(&mut self, address: &ffi::RustRawAddress) -> i32 {
+        self.internal.GetRemoteServices(address)
+    }
 
     pub fn start_discovery(&mut self) -> i32 {
         self.internal.StartDiscovery()
@@ -327,6 +518,17 @@ impl BluetoothInterface {
     pub fn create_bond(&mut self, address: &ffi::RustRawAddress, transport: i32) -> i32 {
         self.internal.CreateBond(address, transport)
     }
+    /// Bonds with the given out of band data. Pass data with `is_valid` unset for the P-192 or
+    /// P-256 values that are not available.
+    pub fn create_bond_out_of_band(
+        &mut self,
+        address: &ffi::RustRawAddress,
+        transport: i32,
+        p192_data: &ffi::BtOutOfBandData,
+        p256_data: &ffi::BtOutOfBandData,
+    ) -> i32 {
+        self.internal.CreateBondOutOfBand(address, transport, p192_data, p256_data)
+    }
     pub fn remove_bond(&mut self, address: &ffi::RustRawAddress) -> i32 {
         self.internal.RemoveBond(address)
     }
@@ -337,16 +539,70 @@ impl BluetoothInterface {
     pub fn get_connection_state(&mut self, address: &ffi::RustRawAddress) -> i32 {
         self.internal.GetConnectionState(address)
     }
+
+    /// Answers a pin_request callback. The pin is at most 16 bytes long.
+    pub fn pin_reply(&mut self, address: &ffi::RustRawAddress, accept: bool, pin: &[u8]) -> i32 {
+        let mut code = ffi::BtPinCode { pin: [0; 16] };
+        let len = pin.len().min(code.pin.len());
+        code.pin[..len].copy_from_slice(&pin[..len]);
+        self.internal.PinReply(address, accept as u8, len as u8, &code)
+    }
+
+    /// Answers a ssp_request callback, with the passkey entered by the user for
+    /// `BtSspVariant::PasskeyEntry`.
+    pub fn ssp_reply(
+        &mut self,
+        address: &ffi::RustRawAddress,
+        variant: BtSspVariant,
+        accept: bool,
+        passkey: u32,
+    ) -> i32 {
+        self.internal.SspReply(address, variant as i32, accept as u8, passkey)
+    }
+
+    /// Reads the local out of band data. This triggers a local_oob_data_generated callback.
+    pub fn generate_local_oob_data(&mut self, transport: i32) -> i32 {
+        self.internal.GenerateLocalOobData(transport)
+    }
+
+    /// Enables or disables Device Under Test mode in the controller.
+    pub fn dut_mode_configure(&mut self, enable: bool) -> i32 {
+        self.internal.DutModeConfigure(enable as u8)
+    }
+
+    /// Sends a vendor test command while in DUT mode. This triggers a dut_mode_recv callback.
+    pub fn dut_mode_send(&mut self, opcode: u16, buf: &[u8]) -> i32 {
+        self.internal.DutModeSend(opcode, buf)
+    }
+
+    /// Sends an LE test command. Ending a test triggers a le_test_mode callback.
+    pub fn le_test_mode(&mut self, opcode: u16, buf: &[u8]) -> i32 {
+        self.internal.LeTestMode(opcode, buf)
+    }
+
+    /// Clears the stored configuration: the bonds, what is known of the remote devices and the
+    /// adapter settings. Call it while the adapter is on, the GD stack checks that it is started.
+    pub fn config_clear(&mut self) -> i32 {
+        self.internal.ConfigClear()
+    }
+
+    /// Sets whether incoming temporary Just Works pairings are accepted without consent. When
+    /// unset they are reported through `ssp_request` as a consent request.
+    pub fn set_auto_accept_temporary_pairing(&mut self, auto_accept: bool) {
+        self.internal.SetAutoAcceptTemporaryPairing(auto_accept)
+    }
 }
 
 unsafe impl Send for BluetoothInterface {}
 
 fn adapter_state_changed_callback(cb: &RustCallbacks, state: i32) {
-    let new_state = match BtState::from_i32(state) {
-        Some(x) => x,
-        None => BtState::Off,
-    };
-    (cb.inner.adapter_state_changed)(new_state);
+    topstack::catch_callback_panic("adapter_state_changed", || {
+        let new_state = match BtState::from_i32(state) {
+            Some(x) => x,
+            None => BtState::Off,
+        };
+        (cb.inner.adapter_state_changed)(new_state);
+    });
 }
 
 fn adapter_properties_callback(
@@ -355,7 +611,9 @@ fn adapter_properties_callback(
     num_properties: i32,
     properties: Vec<ffi::BtProperty>,
 ) {
-    (cb.inner.adapter_properties_changed)(status, num_properties, properties);
+    topstack::catch_callback_panic("adapter_properties", || {
+        (cb.inner.adapter_properties_changed)(status, num_properties, properties);
+    });
 }
 
 fn remote_device_properties_callback(
@@ -365,7 +623,9 @@ fn remote_device_properties_callback(
     num_properties: i32,
     properties: Vec<ffi::BtProperty>,
 ) {
-    (cb.inner.remote_device_properties_changed)(status, address, num_properties, properties);
+    topstack::catch_callback_panic("remote_device_properties", || {
+        (cb.inner.remote_device_properties_changed)(status, address, num_properties, properties);
+    });
 }
 
 fn device_found_callback(
@@ -373,14 +633,18 @@ fn device_found_callback(
     num_properties: i32,
     properties: Vec<ffi::BtProperty>,
 ) {
-    (cb.inner.device_found)(num_properties, properties);
+    topstack::catch_callback_panic("device_found", || {
+        (cb.inner.device_found)(num_properties, properties);
+    });
 }
 fn discovery_state_changed_callback(cb: &RustCallbacks, state: i32) {
-    let new_state = match BtDiscoveryState::from_i32(state) {
-        Some(x) => x,
-        None => BtDiscoveryState::Stopped,
-    };
-    (cb.inner.discovery_state_changed)(new_state);
+    topstack::catch_callback_panic("discovery_state_changed", || {
+        let new_state = match BtDiscoveryState::from_i32(state) {
+            Some(x) => x,
+            None => BtDiscoveryState::Stopped,
+        };
+        (cb.inner.discovery_state_changed)(new_state);
+    });
 }
 fn pin_request_callback(
     cb: &RustCallbacks,
@@ -389,7 +653,9 @@ fn pin_request_callback(
     cod: u32,
     min_16_digit: bool,
 ) {
-    (cb.inner.pin_request)(remote_addr, bd_name, cod, min_16_digit);
+    topstack::catch_callback_panic("pin_request", || {
+        (cb.inner.pin_request)(remote_addr, bd_name, cod, min_16_digit);
+    });
 }
 fn ssp_request_callback(
     cb: &RustCallbacks,
@@ -399,7 +665,16 @@ fn ssp_request_callback(
     variant: i32,
     pass_key: u32,
 ) {
-    (cb.inner.ssp_request)(remote_addr, bd_name, cod, variant, pass_key);
+    topstack::catch_callback_panic("ssp_request", || {
+        let variant = match BtSspVariant::from_i32(variant) {
+            Some(x) => x,
+            None => {
+                eprintln!("Ignoring a SSP request with unknown variant {}", variant);
+                return;
+            }
+        };
+        (cb.inner.ssp_request)(remote_addr, bd_name, cod, variant, pass_key);
+    });
 }
 fn bond_state_changed_callback(
     cb: &RustCallbacks,
@@ -407,7 +682,13 @@ fn bond_state_changed_callback(
     remote_addr: ffi::RustRawAddress,
     state: i32,
 ) {
-    (cb.inner.bond_state_changed)(status, remote_addr, state);
+    topstack::catch_callback_panic("bond_state_changed", || {
+        let new_state = match BtBondState::from_i32(state) {
+            Some(x) => x,
+            None => BtBondState::NotBonded,
+        };
+        (cb.inner.bond_state_changed)(status, remote_addr, new_state);
+    });
 }
 fn acl_state_changed_callback(
     cb: &RustCallbacks,
@@ -416,5 +697,52 @@ fn acl_state_changed_callback(
     state: i32,
     hci_reason: i32,
 ) {
-    (cb.inner.acl_state_changed)(status, remote_addr, state, hci_reason);
+    topstack::catch_callback_panic("acl_state_changed", || {
+        let new_state = match BtAclState::from_i32(state) {
+            Some(x) => x,
+            None => BtAclState::Disconnected,
+        };
+        (cb.inner.acl_state_changed)(status, remote_addr, new_state, hci_reason);
+    });
+}
+fn link_quality_report_callback(
+    cb: &RustCallbacks,
+    timestamp: u64,
+    report_id: i32,
+    rssi: i32,
+    snr: i32,
+    retransmission_count: i32,
+    packets_not_receive_count: i32,
+    negative_acknowledgement_count: i32,
+) {
+    topstack::catch_callback_panic("link_quality_report", || {
+        (cb.inner.link_quality_report)(
+            timestamp,
+            report_id,
+            rssi,
+            snr,
+            retransmission_count,
+            packets_not_receive_count,
+            negative_acknowledgement_count,
+        );
+    });
+}
+fn generate_local_oob_data_callback(
+    cb: &RustCallbacks,
+    transport: i32,
+    oob_data: ffi::BtOutOfBandData,
+) {
+    topstack::catch_callback_panic("generate_local_oob_data", || {
+        (cb.inner.local_oob_data_generated)(transport, oob_data);
+    });
+}
+fn dut_mode_recv_callback(cb: &RustCallbacks, opcode: u16, buf: Vec<u8>) {
+    topstack::catch_callback_panic("dut_mode_recv", || {
+        (cb.inner.dut_mode_recv)(opcode, buf);
+    });
+}
+fn le_test_mode_callback(cb: &RustCallbacks, status: i32, num_packets: u16) {
+    topstack::catch_callback_panic("le_test_mode", || {
+        (cb.inner.le_test_mode)(status, num_packets);
+    });
 }
@@ -0,0 +1,55 @@
+//! Controller interface shim
+//!
+//! This is a shim for reading the local controller version and supported features, as cached by
+//! the stack when the controller module starts up.
+
+#[cxx::bridge(namespace = bluetooth::topshim::rust)]
+pub mod ffi {
+    /// Version and LE feature support of the local controller.
+    #[derive(Clone, Debug, Default)]
+    pub struct BtControllerInfo {
+        /// Whether the controller module is up. Every other field is unset otherwise.
+        is_ready: bool,
+        hci_version: u8,
+        hci_revision: u16,
+        lmp_version: u8,
+        manufacturer: u16,
+        lmp_subversion: u16,
+        le_supported: bool,
+        le_2m_phy: bool,
+        le_coded_phy: bool,
+        le_extended_advertising: bool,
+        le_periodic_advertising: bool,
+        le_max_advertising_data_length: u16,
+        le_number_of_advertising_sets: u8,
+        /// Whether advertising packet content filters can be offloaded to the controller, as
+        /// read from its vendor capabilities, and how many.
+        le_scan_filter_offload: bool,
+        le_max_scan_filters: u8,
+    }
+
+    unsafe extern "C++" {
+        include!("controller/controller_shim.h");
+
+        fn GetControllerInfo() -> BtControllerInfo;
+    }
+}
+
+pub type BtControllerInfo = ffi::BtControllerInfo;
+
+/// Rust interface to the local controller information.
+pub struct Controller {}
+
+impl Controller {
+    pub fn new() -> Controller {
+        Controller {}
+    }
+
+    /// Reads the version and supported features of the local controller.
+    ///
+    /// The information is only available while the adapter is enabled; `is_ready` is unset
+    /// otherwise.
+    pub fn get_info(&self) -> BtControllerInfo {
+        ffi::GetControllerInfo()
+    }
+}
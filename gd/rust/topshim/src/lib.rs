@@ -4,5 +4,11 @@ extern crate lazy_static;
 #[macro_use]
 extern crate num_derive;
 
+pub mod acl;
 pub mod btif;
+pub mod controller;
+pub mod profiles;
+pub mod qa;
+pub mod snoop;
 pub mod topstack;
+pub mod vendor;
@@ -0,0 +1,252 @@
+//! A2DP source interface shim
+//!
+//! This is a shim interface for calling the C++ A2DP source interface via Rust.
+
+use crate::btif::BluetoothInterface;
+use crate::profiles::ProfileShim;
+use crate::topstack;
+
+use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(
+    Debug, FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Clone, Copy, Serialize, Deserialize,
+)]
+#[repr(i32)]
+pub enum BtavConnectionState {
+    Disconnected = 0,
+    Connecting,
+    Connected,
+    Disconnecting,
+    /// A state value that this shim doesn't know about.
+    Unknown = -1,
+}
+
+#[derive(
+    Debug, FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Clone, Copy, Serialize, Deserialize,
+)]
+#[repr(i32)]
+pub enum BtavAudioState {
+    RemoteSuspend = 0,
+    Stopped,
+    Started,
+    /// A state value that this shim doesn't know about.
+    Unknown = -1,
+}
+
+#[cxx::bridge(namespace = bluetooth::topshim::rust)]
+pub mod ffi {
+    /// Mirrors `btav_a2dp_codec_config_t`. The sample rate, bits per sample and channel mode
+    /// are bit masks, so a capability may hold several values.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct A2dpCodecConfig {
+        codec_type: i32,
+        codec_priority: i32,
+        sample_rate: i32,
+        bits_per_sample: i32,
+        channel_mode: i32,
+        codec_specific_1: i64,
+        codec_specific_2: i64,
+        codec_specific_3: i64,
+        codec_specific_4: i64,
+    }
+
+    unsafe extern "C++" {
+        include!("btav/btav_shim.h");
+
+        type BluetoothIntf = crate::btif::ffi::BluetoothIntf;
+        type RustRawAddress = crate::btif::ffi::RustRawAddress;
+
+        // Opaque type meant to represent C object for the A2DP source interface.
+        type A2dpIntf;
+
+        // Loads the A2DP source profile from an already loaded Bluetooth interface.
+        fn LoadA2dp(btif: &BluetoothIntf) -> UniquePtr<A2dpIntf>;
+
+        fn Initialize(self: Pin<&mut A2dpIntf>, callbacks: Box<RustA2dpCallbacks>) -> bool;
+        fn CleanUp(self: Pin<&mut A2dpIntf>);
+
+        fn Connect(&self, address: &RustRawAddress) -> i32;
+        fn Disconnect(&self, address: &RustRawAddress) -> i32;
+        fn SetActiveDevice(&self, address: &RustRawAddress) -> i32;
+        fn SuspendStream(&self);
+
+        // Stream requests of the audio server, to the active sink.
+        fn StartAudioRequest(&self, offload: bool);
+        fn StopAudioRequest(&self);
+        fn IsOffloadEnabled(&self) -> bool;
+
+        // Delay values are in units of 1/10 ms, as reported by AVDTP delay reporting.
+        fn GetAudioDelay(&self) -> u16;
+        fn SetAudioDelay(&self, address: &RustRawAddress, delay: u16);
+    }
+
+    extern "Rust" {
+        type RustA2dpCallbacks;
+
+        // Callbacks from C++ to Rust. See `btif::ffi` for how the callbacks are stored.
+
+        fn a2dp_connection_state_callback(
+            cb: &RustA2dpCallbacks,
+            address: RustRawAddress,
+            state: i32,
+        );
+        fn a2dp_audio_state_callback(cb: &RustA2dpCallbacks, address: RustRawAddress, state: i32);
+        fn a2dp_audio_config_callback(
+            cb: &RustA2dpCallbacks,
+            address: RustRawAddress,
+            codec_config: A2dpCodecConfig,
+            local_capabilities: Vec<A2dpCodecConfig>,
+            selectable_capabilities: Vec<A2dpCodecConfig>,
+        );
+        fn a2dp_audio_delay_callback(cb: &RustA2dpCallbacks, address: RustRawAddress, delay: u16);
+    }
+
+    unsafe impl Box<RustA2dpCallbacks> {}
+}
+
+/// Rust struct of closures for all A2DP callbacks from C++.
+pub struct A2dpCallbacks {
+    pub connection_state_changed: Box<dyn Fn(ffi::RustRawAddress, BtavConnectionState) + Send>,
+    pub audio_state_changed: Box<dyn Fn(ffi::RustRawAddress, BtavAudioState) + Send>,
+    /// Codec of a sink, with the codecs supported locally and the ones selectable with the sink.
+    pub audio_config: Box<
+        dyn Fn(
+                ffi::RustRawAddress,
+                ffi::A2dpCodecConfig,
+                Vec<ffi::A2dpCodecConfig>,
+                Vec<ffi::A2dpCodecConfig>,
+            ) + Send,
+    >,
+    /// Delay of a sink in units of 1/10 ms, e.g. from an AVDTP delay report.
+    pub audio_delay: Box<dyn Fn(ffi::RustRawAddress, u16) + Send>,
+}
+
+impl Default for A2dpCallbacks {
+    /// Constructs callbacks that ignore every event.
+    fn default() -> Self {
+        A2dpCallbacks {
+            connection_state_changed: Box::new(|_, _| {}),
+            audio_state_changed: Box::new(|_, _| {}),
+            audio_config: Box::new(|_, _, _, _| {}),
+            audio_delay: Box::new(|_, _| {}),
+        }
+    }
+}
+
+pub struct RustA2dpCallbacks {
+    inner: Arc<A2dpCallbacks>,
+}
+
+/// Rust interface to the native A2DP source profile.
+pub struct A2dp {
+    internal: cxx::UniquePtr<ffi::A2dpIntf>,
+}
+
+impl ProfileShim for A2dp {
+    type Callbacks = A2dpCallbacks;
+
+    fn initialize(&mut self, callbacks: Arc<A2dpCallbacks>) -> bool {
+        self.internal.pin_mut().Initialize(Box::new(RustA2dpCallbacks { inner: callbacks }))
+    }
+
+    fn cleanup(&mut self) {
+        self.internal.pin_mut().CleanUp()
+    }
+}
+
+impl Drop for A2dp {
+    fn drop(&mut self) {
+        self.cleanup();
+    }
+}
+
+impl A2dp {
+    pub fn new(intf: &BluetoothInterface) -> A2dp {
+        A2dp { internal: ffi::LoadA2dp(intf.as_ffi()) }
+    }
+
+    pub fn connect(&mut self, address: &ffi::RustRawAddress) -> i32 {
+        self.internal.Connect(address)
+    }
+
+    pub fn disconnect(&mut self, address: &ffi::RustRawAddress) -> i32 {
+        self.internal.Disconnect(address)
+    }
+
+    /// Selects the sink that audio is streamed to. An empty address deselects the active sink.
+    pub fn set_active_device(&mut self, address: &ffi::RustRawAddress) -> i32 {
+        self.internal.SetActiveDevice(address)
+    }
+
+    /// Suspends the stream to the active sink, if it is started.
+    pub fn suspend_stream(&mut self) {
+        self.internal.SuspendStream()
+    }
+
+    /// Starts the stream to the active sink, encoded by the controller if `offload` is set. This
+    /// triggers an `audio_state_changed` callback once the sink is streaming.
+    pub fn start_audio_request(&mut self, offload: bool) {
+        self.internal.StartAudioRequest(offload)
+    }
+
+    /// Stops the stream to the active sink.
+    pub fn stop_audio_request(&mut self) {
+        self.internal.StopAudioRequest()
+    }
+
+    /// Returns whether A2DP encoding can be offloaded to the controller.
+    pub fn is_offload_enabled(&self) -> bool {
+        self.internal.IsOffloadEnabled()
+    }
+
+    /// Returns the delay reported by the active sink, in units of 1/10 ms.
+    pub fn get_audio_delay(&self) -> u16 {
+        self.internal.GetAudioDelay()
+    }
+
+    /// Overrides the delay used for the given sink, in units of 1/10 ms.
+    pub fn set_audio_delay(&mut self, address: &ffi::RustRawAddress, delay: u16) {
+        self.internal.SetAudioDelay(address, delay)
+    }
+}
+
+unsafe impl Send for A2dp {}
+
+fn a2dp_connection_state_callback(
+    cb: &RustA2dpCallbacks,
+    address: ffi::RustRawAddress,
+    state: i32,
+) {
+    topstack::catch_callback_panic("a2dp_connection_state", || {
+        let new_state =
+            BtavConnectionState::from_i32(state).unwrap_or(BtavConnectionState::Unknown);
+        (cb.inner.connection_state_changed)(address, new_state);
+    });
+}
+
+fn a2dp_audio_state_callback(cb: &RustA2dpCallbacks, address: ffi::RustRawAddress, state: i32) {
+    topstack::catch_callback_panic("a2dp_audio_state", || {
+        let new_state = BtavAudioState::from_i32(state).unwrap_or(BtavAudioState::Unknown);
+        (cb.inner.audio_state_changed)(address, new_state);
+    });
+}
+
+fn a2dp_audio_config_callback(
+    cb: &RustA2dpCallbacks,
+    address: ffi::RustRawAddress,
+    codec_config: ffi::A2dpCodecConfig,
+    local_capabilities: Vec<ffi::A2dpCodecConfig>,
+    selectable_capabilities: Vec<ffi::A2dpCodecConfig>,
+) {
+    topstack::catch_callback_panic("a2dp_audio_config", || {
+        (cb.inner.audio_config)(address, codec_config, local_capabilities, selectable_capabilities);
+    });
+}
+
+fn a2dp_audio_delay_callback(cb: &RustA2dpCallbacks, address: ffi::RustRawAddress, delay: u16) {
+    topstack::catch_callback_panic("a2dp_audio_delay", || {
+        (cb.inner.audio_delay)(address, delay);
+    });
+}
@@ -0,0 +1,1347 @@
+//! GATT interface shim
+//!
+//! This is a shim interface for calling the C++ GATT client and server interfaces via Rust.
+
+use crate::btif::BluetoothInterface;
+use crate::profiles::ProfileShim;
+use crate::topstack;
+
+use num_traits::FromPrimitive;
+
+use std::sync::Arc;
+
+/// Type of an attribute of a GATT database, as in `bt_gatt_db_attribute_type_t`.
+#[derive(Debug, FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Clone, Copy)]
+#[repr(i32)]
+pub enum GattDbElementType {
+    PrimaryService = 0,
+    SecondaryService,
+    IncludedService,
+    Characteristic,
+    Descriptor,
+}
+
+/// Mirrors `tGATT_STATUS`: 0, an ATT error code or a status of the native GATT implementation.
+#[derive(Clone, Copy, Debug, FromPrimitive, ToPrimitive, PartialEq, PartialOrd)]
+#[repr(i32)]
+pub enum GattStatus {
+    Success = 0x00,
+    InvalidHandle = 0x01,
+    ReadNotPermit = 0x02,
+    WriteNotPermit = 0x03,
+    InvalidPdu = 0x04,
+    InsufAuthentication = 0x05,
+    ReqNotSupported = 0x06,
+    InvalidOffset = 0x07,
+    InsufAuthorization = 0x08,
+    PrepareQueueFull = 0x09,
+    NotFound = 0x0a,
+    NotLong = 0x0b,
+    InsufKeySize = 0x0c,
+    InvalidAttrLen = 0x0d,
+    ErrUnlikely = 0x0e,
+    InsufEncryption = 0x0f,
+    UnsupportGrpType = 0x10,
+    InsufResource = 0x11,
+    DatabaseOutOfSync = 0x12,
+    ValueNotAllowed = 0x13,
+    TooShort = 0x7f,
+    NoResources = 0x80,
+    InternalError = 0x81,
+    WrongState = 0x82,
+    DbFull = 0x83,
+    Busy = 0x84,
+    Error = 0x85,
+    CmdStarted = 0x86,
+    IllegalParameter = 0x87,
+    Pending = 0x88,
+    AuthFail = 0x89,
+    More = 0x8a,
+    InvalidCfg = 0x8b,
+    ServiceStarted = 0x8c,
+    EncryptedNoMitm = 0x8d,
+    NotEncrypted = 0x8e,
+    Congested = 0x8f,
+    DupReg = 0x90,
+    AlreadyOpen = 0x91,
+    Cancel = 0x92,
+    CccCfgErr = 0xfd,
+    PrcInProgress = 0xfe,
+    OutOfRange = 0xff,
+}
+
+impl From<i32> for GattStatus {
+    /// Statuses that are not known are converted to `GattStatus::Error`.
+    fn from(status: i32) -> Self {
+        GattStatus::from_i32(status).unwrap_or(GattStatus::Error)
+    }
+}
+
+#[cxx::bridge(namespace = bluetooth::topshim::rust)]
+pub mod ffi {
+    /// Mirrors `btgatt_db_element_t`. For characteristics, `attribute_handle` is the handle of
+    /// the value. `start_handle` and `end_handle` are only set for services.
+    #[derive(Serialize, Deserialize)]
+    pub struct BtGattDbElement {
+        id: u16,
+        uuid: BtUuid,
+        element_type: i32,
+        attribute_handle: u16,
+        start_handle: u16,
+        end_handle: u16,
+        properties: u8,
+        extended_properties: u16,
+        permissions: u16,
+    }
+
+    /// Mirrors `AdvertiseParameters`. Intervals are in units of 0.625 ms and `tx_power` is in
+    /// dBm.
+    pub struct BtAdvertiseParameters {
+        advertising_event_properties: u16,
+        min_interval: u32,
+        max_interval: u32,
+        channel_map: u8,
+        tx_power: i8,
+        primary_advertising_phy: u8,
+        secondary_advertising_phy: u8,
+        scan_request_notification_enable: u8,
+    }
+
+    /// A condition of an advertising packet content filter, mirroring the fields of
+    /// `ApcfCommand` used by the manufacturer data and service data filter types.
+    pub struct BtApcfCondition {
+        filter_type: u8,
+        company: u16,
+        company_mask: u16,
+        data: Vec<u8>,
+        data_mask: Vec<u8>,
+    }
+
+    unsafe extern "C++" {
+        include!("gatt/gatt_shim.h");
+
+        type BluetoothIntf = crate::btif::ffi::BluetoothIntf;
+        type RustRawAddress = crate::btif::ffi::RustRawAddress;
+        type BtUuid = crate::btif::ffi::BtUuid;
+
+        // Opaque type meant to represent C object for the GATT interface.
+        type GattIntf;
+
+        // Loads the GATT profile from an already loaded Bluetooth interface.
+        fn LoadGatt(btif: &BluetoothIntf) -> UniquePtr<GattIntf>;
+
+        fn Initialize(self: Pin<&mut GattIntf>, callbacks: Box<RustGattCallbacks>) -> bool;
+        fn CleanUp(self: Pin<&mut GattIntf>);
+
+        // GATT client
+        fn ClientRegisterClient(&self, uuid: &BtUuid, eatt_support: bool) -> i32;
+        fn ClientUnregisterClient(&self, client_if: i32) -> i32;
+        fn ClientConnect(
+            &self,
+            client_if: i32,
+            address: &RustRawAddress,
+            is_direct: bool,
+            transport: i32,
+            opportunistic: bool,
+            initiating_phys: i32,
+        ) -> i32;
+        fn ClientDisconnect(&self, client_if: i32, address: &RustRawAddress, conn_id: i32) -> i32;
+        fn ClientReadRemoteRssi(&self, client_if: i32, address: &RustRawAddress) -> i32;
+        fn ClientSearchService(&self, conn_id: i32) -> i32;
+        fn ClientGetGattDb(&self, conn_id: i32) -> i32;
+        fn ClientReadCharacteristic(&self, conn_id: i32, handle: u16, auth_req: i32) -> i32;
+        fn ClientReadUsingCharacteristicUuid(
+            &self,
+            conn_id: i32,
+            uuid: &BtUuid,
+            start_handle: u16,
+            end_handle: u16,
+            auth_req: i32,
+        ) -> i32;
+        fn ClientWriteCharacteristic(
+            &self,
+            conn_id: i32,
+            handle: i32,
+            write_type: i32,
+            auth_req: i32,
+            value: &[u8],
+        ) -> i32;
+        fn ClientReadDescriptor(&self, conn_id: i32, handle: u16, auth_req: i32) -> i32;
+        fn ClientWriteDescriptor(
+            &self,
+            conn_id: i32,
+            handle: u16,
+            auth_req: i32,
+            value: &[u8],
+        ) -> i32;
+        fn ClientExecuteWrite(&self, conn_id: i32, execute: i32) -> i32;
+        fn ClientRegisterForNotification(
+            &self,
+            client_if: i32,
+            address: &RustRawAddress,
+            handle: u16,
+        ) -> i32;
+        fn ClientDeregisterForNotification(
+            &self,
+            client_if: i32,
+            address: &RustRawAddress,
+            handle: u16,
+        ) -> i32;
+        fn ClientConfigureMtu(&self, conn_id: i32, mtu: i32) -> i32;
+        fn ClientReadPhy(&self, client_if: i32, address: &RustRawAddress) -> i32;
+
+        // GATT server
+        fn ServerRegisterServer(&self, uuid: &BtUuid, eatt_support: bool) -> i32;
+        fn ServerUnregisterServer(&self, server_if: i32) -> i32;
+        fn ServerAddService(&self, server_if: i32, service: &[BtGattDbElement]) -> i32;
+        fn ServerDeleteService(&self, server_if: i32, service_handle: i32) -> i32;
+        fn ServerSendIndication(
+            &self,
+            server_if: i32,
+            attribute_handle: i32,
+            conn_id: i32,
+            confirm: bool,
+            value: &[u8],
+        ) -> i32;
+        fn ServerSendResponse(
+            &self,
+            conn_id: i32,
+            trans_id: i32,
+            status: i32,
+            handle: i32,
+            offset: i32,
+            value: &[u8],
+        ) -> i32;
+        fn ServerReadPhy(&self, server_if: i32, address: &RustRawAddress) -> i32;
+
+        // LE scanner
+        fn ScannerRegisterScanner(&self, uuid: &BtUuid);
+        fn ScannerUnregister(&self, scanner_id: i32);
+        fn ScannerScan(&self, start: bool);
+        fn ScannerSetScanParameters(&self, interval: i32, window: i32);
+        fn ScannerScanFilterAdd(
+            &self,
+            filter_index: u8,
+            rssi_high_threshold: i8,
+            rssi_low_threshold: i8,
+            conditions: &[BtApcfCondition],
+        );
+        fn ScannerScanFilterClear(&self, filter_index: u8);
+        fn ScannerScanFilterEnable(&self, enable: bool);
+
+        // LE advertiser
+        fn AdvertiserStartAdvertisingSet(
+            &self,
+            reg_id: i32,
+            params: &BtAdvertiseParameters,
+            advertise_data: &[u8],
+            scan_response_data: &[u8],
+            duration: u16,
+            max_ext_adv_events: u8,
+        );
+        fn AdvertiserSetParameters(&self, advertiser_id: i32, params: &BtAdvertiseParameters);
+        fn AdvertiserSetData(&self, advertiser_id: i32, set_scan_rsp: bool, data: &[u8]);
+        fn AdvertiserEnable(
+            &self,
+            advertiser_id: i32,
+            enable: bool,
+            duration: u16,
+            max_ext_adv_events: u8,
+        );
+        fn AdvertiserGetOwnAddress(&self, advertiser_id: i32);
+        fn AdvertiserUnregister(&self, advertiser_id: i32);
+    }
+
+    extern "Rust" {
+        type RustGattCallbacks;
+
+        // Callbacks from C++ to Rust. See `btif::ffi` for how the callbacks are stored.
+
+        fn gatt_client_register_client_callback(
+            cb: &RustGattCallbacks,
+            status: i32,
+            client_if: i32,
+            app_uuid: BtUuid,
+        );
+        fn gatt_client_connect_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            status: i32,
+            client_if: i32,
+            address: RustRawAddress,
+        );
+        fn gatt_client_disconnect_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            status: i32,
+            client_if: i32,
+            address: RustRawAddress,
+        );
+        fn gatt_client_read_remote_rssi_callback(
+            cb: &RustGattCallbacks,
+            client_if: i32,
+            address: RustRawAddress,
+            rssi: i32,
+            status: i32,
+        );
+        fn gatt_client_search_complete_callback(cb: &RustGattCallbacks, conn_id: i32, status: i32);
+        fn gatt_client_notify_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            address: RustRawAddress,
+            handle: i32,
+            is_notify: bool,
+            value: Vec<u8>,
+        );
+        fn gatt_client_get_gatt_db_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            elements: Vec<BtGattDbElement>,
+        );
+        fn gatt_client_read_characteristic_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            status: i32,
+            handle: i32,
+            value: Vec<u8>,
+        );
+        fn gatt_client_write_characteristic_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            status: i32,
+            handle: i32,
+        );
+        fn gatt_client_read_descriptor_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            status: i32,
+            handle: i32,
+            value: Vec<u8>,
+        );
+        fn gatt_client_write_descriptor_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            status: i32,
+            handle: i32,
+        );
+        fn gatt_client_execute_write_callback(cb: &RustGattCallbacks, conn_id: i32, status: i32);
+        fn gatt_client_configure_mtu_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            status: i32,
+            mtu: i32,
+        );
+        fn gatt_client_phy_updated_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            tx_phy: i32,
+            rx_phy: i32,
+            status: i32,
+        );
+        fn gatt_client_conn_updated_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            interval: i32,
+            latency: i32,
+            timeout: i32,
+            status: i32,
+        );
+        fn gatt_client_service_changed_callback(cb: &RustGattCallbacks, conn_id: i32);
+        fn gatt_client_services_rediscovered_callback(
+            cb: &RustGattCallbacks,
+            address: RustRawAddress,
+        );
+        fn gatt_client_read_phy_callback(
+            cb: &RustGattCallbacks,
+            client_if: i32,
+            address: RustRawAddress,
+            tx_phy: i32,
+            rx_phy: i32,
+            status: i32,
+        );
+
+        fn gatt_server_register_server_callback(
+            cb: &RustGattCallbacks,
+            status: i32,
+            server_if: i32,
+            app_uuid: BtUuid,
+        );
+        fn gatt_server_connection_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            server_if: i32,
+            connected: i32,
+            address: RustRawAddress,
+        );
+        fn gatt_server_service_added_callback(
+            cb: &RustGattCallbacks,
+            status: i32,
+            server_if: i32,
+            elements: Vec<BtGattDbElement>,
+        );
+        fn gatt_server_request_read_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            trans_id: i32,
+            address: RustRawAddress,
+            handle: i32,
+            offset: i32,
+            is_long: bool,
+        );
+        fn gatt_server_indication_sent_callback(cb: &RustGattCallbacks, conn_id: i32, status: i32);
+        fn gatt_server_congestion_callback(cb: &RustGattCallbacks, conn_id: i32, congested: bool);
+        fn gatt_server_mtu_changed_callback(cb: &RustGattCallbacks, conn_id: i32, mtu: i32);
+        fn gatt_server_read_phy_callback(
+            cb: &RustGattCallbacks,
+            server_if: i32,
+            address: RustRawAddress,
+            tx_phy: i32,
+            rx_phy: i32,
+            status: i32,
+        );
+        fn gatt_server_request_write_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            trans_id: i32,
+            address: RustRawAddress,
+            handle: i32,
+            offset: i32,
+            need_rsp: bool,
+            is_prep: bool,
+            value: Vec<u8>,
+        );
+        fn gatt_server_request_exec_write_callback(
+            cb: &RustGattCallbacks,
+            conn_id: i32,
+            trans_id: i32,
+            address: RustRawAddress,
+            exec_write: i32,
+        );
+
+        fn gatt_scanner_registered_callback(
+            cb: &RustGattCallbacks,
+            app_uuid: BtUuid,
+            scanner_id: i32,
+            status: i32,
+        );
+        fn gatt_scanner_scan_result_callback(
+            cb: &RustGattCallbacks,
+            event_type: i32,
+            addr_type: i32,
+            address: RustRawAddress,
+            primary_phy: i32,
+            secondary_phy: i32,
+            advertising_sid: i32,
+            tx_power: i32,
+            rssi: i32,
+            periodic_adv_int: i32,
+            adv_data: Vec<u8>,
+        );
+        fn gatt_scanner_filter_configured_callback(
+            cb: &RustGattCallbacks,
+            filter_index: i32,
+            status: i32,
+        );
+
+        fn gatt_advertiser_set_started_callback(
+            cb: &RustGattCallbacks,
+            reg_id: i32,
+            advertiser_id: i32,
+            tx_power: i32,
+            status: i32,
+        );
+        fn gatt_advertiser_enabled_callback(
+            cb: &RustGattCallbacks,
+            advertiser_id: i32,
+            enable: bool,
+            status: i32,
+        );
+        fn gatt_advertiser_data_set_callback(
+            cb: &RustGattCallbacks,
+            advertiser_id: i32,
+            set_scan_rsp: bool,
+            status: i32,
+        );
+        fn gatt_advertiser_parameters_updated_callback(
+            cb: &RustGattCallbacks,
+            advertiser_id: i32,
+            tx_power: i32,
+            status: i32,
+        );
+        fn gatt_advertiser_own_address_read_callback(
+            cb: &RustGattCallbacks,
+            advertiser_id: i32,
+            address_type: i32,
+            address: RustRawAddress,
+        );
+    }
+
+    unsafe impl Box<RustGattCallbacks> {}
+}
+
+/// Rust struct of closures for all GATT callbacks from C++.
+pub struct GattCallbacks {
+    pub client_registered: Box<dyn Fn(i32, i32, ffi::BtUuid) + Send>,
+    pub client_connect: Box<dyn Fn(i32, i32, i32, ffi::RustRawAddress) + Send>,
+    pub client_disconnect: Box<dyn Fn(i32, i32, i32, ffi::RustRawAddress) + Send>,
+    pub client_read_remote_rssi: Box<dyn Fn(i32, ffi::RustRawAddress, i32, i32) + Send>,
+    pub client_search_complete: Box<dyn Fn(i32, i32) + Send>,
+    /// Notification or indication of a characteristic registered with
+    /// `register_for_notification`.
+    pub client_notify: Box<dyn Fn(i32, ffi::RustRawAddress, i32, bool, Vec<u8>) + Send>,
+    /// Attributes of the database of a connection, services first followed by their content.
+    pub client_get_gatt_db: Box<dyn Fn(i32, Vec<ffi::BtGattDbElement>) + Send>,
+    pub client_read_characteristic: Box<dyn Fn(i32, i32, i32, Vec<u8>) + Send>,
+    pub client_write_characteristic: Box<dyn Fn(i32, i32, i32) + Send>,
+    pub client_read_descriptor: Box<dyn Fn(i32, i32, i32, Vec<u8>) + Send>,
+    pub client_write_descriptor: Box<dyn Fn(i32, i32, i32) + Send>,
+    pub client_execute_write: Box<dyn Fn(i32, i32) + Send>,
+    pub client_configure_mtu: Box<dyn Fn(i32, i32, i32) + Send>,
+    /// PHYs of a client connection after an update, whichever side requested it.
+    pub client_phy_updated: Box<dyn Fn(i32, i32, i32, i32) + Send>,
+    /// Connection interval, peripheral latency and supervision timeout of a client connection
+    /// after an update, whichever side requested it.
+    pub client_conn_updated: Box<dyn Fn(i32, i32, i32, i32, i32) + Send>,
+    /// Service Changed indication received on a client connection. The database discovered on
+    /// the connection is stale until it is discovered again.
+    pub client_service_changed: Box<dyn Fn(i32) + Send>,
+    /// The services of a device were discovered again, e.g. after a Service Changed indication.
+    /// `get_gatt_db` then returns the new database on every client connection to the device.
+    pub client_services_rediscovered: Box<dyn Fn(ffi::RustRawAddress) + Send>,
+    /// Result of `client_read_phy`, with the client and address it was called with.
+    pub client_read_phy: Box<dyn Fn(i32, ffi::RustRawAddress, i32, i32, i32) + Send>,
+    pub server_registered: Box<dyn Fn(i32, i32, ffi::BtUuid) + Send>,
+    pub server_connection: Box<dyn Fn(i32, i32, i32, ffi::RustRawAddress) + Send>,
+    /// Result of `add_service`, with the attributes of the service and their assigned handles.
+    pub server_service_added: Box<dyn Fn(i32, i32, Vec<ffi::BtGattDbElement>) + Send>,
+    /// Read of a characteristic or descriptor, continuing a long read if `is_long` is set.
+    pub server_request_read: Box<dyn Fn(i32, i32, ffi::RustRawAddress, i32, i32, bool) + Send>,
+    pub server_indication_sent: Box<dyn Fn(i32, i32) + Send>,
+    pub server_congestion: Box<dyn Fn(i32, bool) + Send>,
+    pub server_mtu_changed: Box<dyn Fn(i32, i32) + Send>,
+    /// Result of `server_read_phy`, with the server and address it was called with.
+    pub server_read_phy: Box<dyn Fn(i32, ffi::RustRawAddress, i32, i32, i32) + Send>,
+    /// Write of a characteristic or descriptor, prepared if `is_prep` is set.
+    pub server_request_write:
+        Box<dyn Fn(i32, i32, ffi::RustRawAddress, i32, i32, bool, bool, Vec<u8>) + Send>,
+    pub server_request_exec_write: Box<dyn Fn(i32, i32, ffi::RustRawAddress, i32) + Send>,
+    pub scanner_registered: Box<dyn Fn(ffi::BtUuid, i32, i32) + Send>,
+    /// Advertisement received while scanning, reported once for each advertising event.
+    pub scan_result:
+        Box<dyn Fn(i32, i32, ffi::RustRawAddress, i32, i32, i32, i32, i32, i32, Vec<u8>) + Send>,
+    /// Result of `add_scan_filter`, with the filter index it was called with.
+    pub scanner_filter_configured: Box<dyn Fn(i32, i32) + Send>,
+    /// Result of `start_advertising_set`, with the registration id it was called with.
+    pub advertiser_set_started: Box<dyn Fn(i32, i32, i32, i32) + Send>,
+    /// Result of `enable_advertising`, also reported as disabled once the duration or maximum
+    /// number of events of the set is reached.
+    pub advertiser_enabled: Box<dyn Fn(i32, bool, i32) + Send>,
+    pub advertiser_data_set: Box<dyn Fn(i32, bool, i32) + Send>,
+    pub advertiser_parameters_updated: Box<dyn Fn(i32, i32, i32) + Send>,
+    pub advertiser_own_address_read: Box<dyn Fn(i32, i32, ffi::RustRawAddress) + Send>,
+}
+
+impl Default for GattCallbacks {
+    /// Constructs callbacks that ignore every event.
+    fn default() -> Self {
+        GattCallbacks {
+            client_registered: Box::new(|_, _, _| {}),
+            client_connect: Box::new(|_, _, _, _| {}),
+            client_disconnect: Box::new(|_, _, _, _| {}),
+            client_read_remote_rssi: Box::new(|_, _, _, _| {}),
+            client_search_complete: Box::new(|_, _| {}),
+            client_notify: Box::new(|_, _, _, _, _| {}),
+            client_get_gatt_db: Box::new(|_, _| {}),
+            client_read_characteristic: Box::new(|_, _, _, _| {}),
+            client_write_characteristic: Box::new(|_, _, _| {}),
+            client_read_descriptor: Box::new(|_, _, _, _| {}),
+            client_write_descriptor: Box::new(|_, _, _| {}),
+            client_execute_write: Box::new(|_, _| {}),
+            client_configure_mtu: Box::new(|_, _, _| {}),
+            client_phy_updated: Box::new(|_, _, _, _| {}),
+            client_conn_updated: Box::new(|_, _, _, _, _| {}),
+            client_service_changed: Box::new(|_| {}),
+            client_services_rediscovered: Box::new(|_| {}),
+            client_read_phy: Box::new(|_, _, _, _, _| {}),
+            server_registered: Box::new(|_, _, _| {}),
+            server_connection: Box::new(|_, _, _, _| {}),
+            server_service_added: Box::new(|_, _, _| {}),
+            server_request_read: Box::new(|_, _, _, _, _, _| {}),
+            server_indication_sent: Box::new(|_, _| {}),
+            server_congestion: Box::new(|_, _| {}),
+            server_mtu_changed: Box::new(|_, _| {}),
+            server_read_phy: Box::new(|_, _, _, _, _| {}),
+            server_request_write: Box::new(|_, _, _, _, _, _, _, _| {}),
+            server_request_exec_write: Box::new(|_, _, _, _| {}),
+            scanner_registered: Box::new(|_, _, _| {}),
+            scan_result: Box::new(|_, _, _, _, _, _, _, _, _, _| {}),
+            scanner_filter_configured: Box::new(|_, _| {}),
+            advertiser_set_started: Box::new(|_, _, _, _| {}),
+            advertiser_enabled: Box::new(|_, _, _| {}),
+            advertiser_data_set: Box::new(|_, _, _| {}),
+            advertiser_parameters_updated: Box::new(|_, _, _| {}),
+            advertiser_own_address_read: Box::new(|_, _, _| {}),
+        }
+    }
+}
+
+pub struct RustGattCallbacks {
+    inner: Arc<GattCallbacks>,
+}
+
+/// Rust interface to the native GATT profile.
+pub struct Gatt {
+    internal: cxx::UniquePtr<ffi::GattIntf>,
+}
+
+impl ProfileShim for Gatt {
+    type Callbacks = GattCallbacks;
+
+    fn initialize(&mut self, callbacks: Arc<GattCallbacks>) -> bool {
+        self.internal.pin_mut().Initialize(Box::new(RustGattCallbacks { inner: callbacks }))
+    }
+
+    fn cleanup(&mut self) {
+        self.internal.pin_mut().CleanUp()
+    }
+}
+
+impl Drop for Gatt {
+    fn drop(&mut self) {
+        self.cleanup();
+    }
+}
+
+impl Gatt {
+    pub fn new(intf: &BluetoothInterface) -> Gatt {
+        Gatt { internal: ffi::LoadGatt(intf.as_ffi()) }
+    }
+
+    /// Registers a GATT client application. This triggers a `client_registered` callback.
+    pub fn register_client(&mut self, uuid: &ffi::BtUuid, eatt_support: bool) -> i32 {
+        self.internal.ClientRegisterClient(uuid, eatt_support)
+    }
+
+    pub fn unregister_client(&mut self, client_if: i32) -> i32 {
+        self.internal.ClientUnregisterClient(client_if)
+    }
+
+    /// Connects a client to a remote device. This triggers a `client_connect` callback.
+    ///
+    /// Unless `is_direct` is set, the connection is made in the background whenever the device
+    /// is in range, until `disconnect` is called.
+    pub fn connect(
+        &mut self,
+        client_if: i32,
+        address: &ffi::RustRawAddress,
+        is_direct: bool,
+        transport: i32,
+        opportunistic: bool,
+        initiating_phys: i32,
+    ) -> i32 {
+        self.internal.ClientConnect(
+            client_if,
+            address,
+            is_direct,
+            transport,
+            opportunistic,
+            initiating_phys,
+        )
+    }
+
+    /// Disconnects a client from a remote device, or cancels its background connection if
+    /// `conn_id` is 0. This triggers a `client_disconnect` callback if it was connected.
+    pub fn disconnect(
+        &mut self,
+        client_if: i32,
+        address: &ffi::RustRawAddress,
+        conn_id: i32,
+    ) -> i32 {
+        self.internal.ClientDisconnect(client_if, address, conn_id)
+    }
+
+    /// Requests the RSSI of a remote device. This triggers a `client_read_remote_rssi` callback.
+    pub fn read_remote_rssi(&mut self, client_if: i32, address: &ffi::RustRawAddress) -> i32 {
+        self.internal.ClientReadRemoteRssi(client_if, address)
+    }
+
+    /// Discovers the services of a connected device. This triggers a `client_search_complete`
+    /// callback.
+    pub fn search_service(&mut self, conn_id: i32) -> i32 {
+        self.internal.ClientSearchService(conn_id)
+    }
+
+    /// Reads the database discovered on a connection. This triggers a `client_get_gatt_db`
+    /// callback.
+    pub fn get_gatt_db(&mut self, conn_id: i32) -> i32 {
+        self.internal.ClientGetGattDb(conn_id)
+    }
+
+    /// Reads a characteristic of a connected device, including the parts of a long value that
+    /// don't fit in the MTU. This triggers a `client_read_characteristic` callback.
+    pub fn read_characteristic(&mut self, conn_id: i32, handle: u16, auth_req: i32) -> i32 {
+        self.internal.ClientReadCharacteristic(conn_id, handle, auth_req)
+    }
+
+    /// Reads the first characteristic of type `uuid` between `start_handle` and `end_handle`.
+    /// This triggers a `client_read_characteristic` callback.
+    pub fn read_using_characteristic_uuid(
+        &mut self,
+        conn_id: i32,
+        uuid: &ffi::BtUuid,
+        start_handle: u16,
+        end_handle: u16,
+        auth_req: i32,
+    ) -> i32 {
+        self.internal.ClientReadUsingCharacteristicUuid(
+            conn_id,
+            uuid,
+            start_handle,
+            end_handle,
+            auth_req,
+        )
+    }
+
+    /// Writes a characteristic of a connected device. This triggers a
+    /// `client_write_characteristic` callback.
+    ///
+    /// Writes of the prepare type are queued by the remote device until `execute_write`.
+    pub fn write_characteristic(
+        &mut self,
+        conn_id: i32,
+        handle: i32,
+        write_type: i32,
+        auth_req: i32,
+        value: &[u8],
+    ) -> i32 {
+        self.internal.ClientWriteCharacteristic(conn_id, handle, write_type, auth_req, value)
+    }
+
+    /// Reads a descriptor of a connected device. This triggers a `client_read_descriptor`
+    /// callback.
+    pub fn read_descriptor(&mut self, conn_id: i32, handle: u16, auth_req: i32) -> i32 {
+        self.internal.ClientReadDescriptor(conn_id, handle, auth_req)
+    }
+
+    /// Writes a descriptor of a connected device. This triggers a `client_write_descriptor`
+    /// callback.
+    pub fn write_descriptor(
+        &mut self,
+        conn_id: i32,
+        handle: u16,
+        auth_req: i32,
+        value: &[u8],
+    ) -> i32 {
+        self.internal.ClientWriteDescriptor(conn_id, handle, auth_req, value)
+    }
+
+    /// Executes, or cancels if `execute` is 0, the prepared writes of a connection. This triggers
+    /// a `client_execute_write` callback.
+    pub fn execute_write(&mut self, conn_id: i32, execute: i32) -> i32 {
+        self.internal.ClientExecuteWrite(conn_id, execute)
+    }
+
+    /// Routes the notifications and indications of a characteristic of a device to the
+    /// `client_notify` callback. The device only sends them once they are enabled in its Client
+    /// Characteristic Configuration descriptor.
+    pub fn register_for_notification(
+        &mut self,
+        client_if: i32,
+        address: &ffi::RustRawAddress,
+        handle: u16,
+    ) -> i32 {
+        self.internal.ClientRegisterForNotification(client_if, address, handle)
+    }
+
+    /// Stops routing the notifications and indications of a characteristic of a device to the
+    /// `client_notify` callback, without changing its Client Characteristic Configuration
+    /// descriptor.
+    pub fn deregister_for_notification(
+        &mut self,
+        client_if: i32,
+        address: &ffi::RustRawAddress,
+        handle: u16,
+    ) -> i32 {
+        self.internal.ClientDeregisterForNotification(client_if, address, handle)
+    }
+
+    /// Requests an ATT MTU exchange on a connection. This triggers a `client_configure_mtu`
+    /// callback.
+    pub fn configure_mtu(&mut self, conn_id: i32, mtu: i32) -> i32 {
+        self.internal.ClientConfigureMtu(conn_id, mtu)
+    }
+
+    /// Reads the PHYs of the connection of a client to a remote device. This triggers a
+    /// `client_read_phy` callback.
+    pub fn client_read_phy(&mut self, client_if: i32, address: &ffi::RustRawAddress) -> i32 {
+        self.internal.ClientReadPhy(client_if, address)
+    }
+
+    /// Registers a GATT server application. This triggers a `server_registered` callback.
+    pub fn register_server(&mut self, uuid: &ffi::BtUuid, eatt_support: bool) -> i32 {
+        self.internal.ServerRegisterServer(uuid, eatt_support)
+    }
+
+    pub fn unregister_server(&mut self, server_if: i32) -> i32 {
+        self.internal.ServerUnregisterServer(server_if)
+    }
+
+    /// Adds a service to the local database. This triggers a `server_service_added` callback.
+    ///
+    /// `service` starts with the service declaration, followed by its characteristics each with
+    /// their descriptors.
+    pub fn add_service(&mut self, server_if: i32, service: &[ffi::BtGattDbElement]) -> i32 {
+        self.internal.ServerAddService(server_if, service)
+    }
+
+    /// Removes a service added with `add_service` from the local database.
+    pub fn delete_service(&mut self, server_if: i32, service_handle: i32) -> i32 {
+        self.internal.ServerDeleteService(server_if, service_handle)
+    }
+
+    /// Sends a notification, or an indication if `confirm` is set. This triggers a
+    /// `server_indication_sent` callback.
+    pub fn send_indication(
+        &mut self,
+        server_if: i32,
+        attribute_handle: i32,
+        conn_id: i32,
+        confirm: bool,
+        value: &[u8],
+    ) -> i32 {
+        self.internal.ServerSendIndication(server_if, attribute_handle, conn_id, confirm, value)
+    }
+
+    /// Responds to a read or write request of a remote device.
+    pub fn send_response(
+        &mut self,
+        conn_id: i32,
+        trans_id: i32,
+        status: i32,
+        handle: i32,
+        offset: i32,
+        value: &[u8],
+    ) -> i32 {
+        self.internal.ServerSendResponse(conn_id, trans_id, status, handle, offset, value)
+    }
+
+    /// Reads the PHYs of the connection of a remote device to a server. This triggers a
+    /// `server_read_phy` callback.
+    pub fn server_read_phy(&mut self, server_if: i32, address: &ffi::RustRawAddress) -> i32 {
+        self.internal.ServerReadPhy(server_if, address)
+    }
+
+    /// Registers an LE scanner. This triggers a `scanner_registered` callback with the same
+    /// `uuid`.
+    pub fn register_scanner(&mut self, uuid: &ffi::BtUuid) {
+        self.internal.ScannerRegisterScanner(uuid)
+    }
+
+    pub fn unregister_scanner(&mut self, scanner_id: i32) {
+        self.internal.ScannerUnregister(scanner_id)
+    }
+
+    /// Starts or stops LE scanning. Scanning is shared by all the scanners, results are reported
+    /// with `scan_result` callbacks.
+    pub fn scan(&mut self, start: bool) {
+        self.internal.ScannerScan(start)
+    }
+
+    /// Sets the scan interval and window on the LE 1M PHY, in units of 0.625 ms.
+    pub fn set_scan_parameters(&mut self, interval: i32, window: i32) {
+        self.internal.ScannerSetScanParameters(interval, window)
+    }
+
+    /// Sets up an advertising packet content filter offloaded to the controller. This triggers a
+    /// `scanner_filter_configured` callback with the same `filter_index`.
+    ///
+    /// An advertisement passes the filter if it meets any of the conditions and its RSSI is at or
+    /// above `rssi_high_threshold`. While the filter feature is enabled, only the advertisements
+    /// passing a filter are reported with `scan_result` callbacks.
+    pub fn add_scan_filter(
+        &mut self,
+        filter_index: u8,
+        rssi_high_threshold: i8,
+        rssi_low_threshold: i8,
+        conditions: &[ffi::BtApcfCondition],
+    ) {
+        self.internal.ScannerScanFilterAdd(
+            filter_index,
+            rssi_high_threshold,
+            rssi_low_threshold,
+            conditions,
+        )
+    }
+
+    /// Removes an advertising packet content filter and its conditions.
+    pub fn clear_scan_filter(&mut self, filter_index: u8) {
+        self.internal.ScannerScanFilterClear(filter_index)
+    }
+
+    /// Enables or disables the advertising packet content filter feature of the controller.
+    pub fn enable_scan_filter(&mut self, enable: bool) {
+        self.internal.ScannerScanFilterEnable(enable)
+    }
+
+    /// Registers, configures and enables an advertising set. This triggers an
+    /// `advertiser_set_started` callback with the same `reg_id`.
+    ///
+    /// The set advertises for `duration`, in units of 10 ms, or `max_ext_adv_events` events, 0
+    /// for no limit.
+    pub fn start_advertising_set(
+        &mut self,
+        reg_id: i32,
+        params: &ffi::BtAdvertiseParameters,
+        advertise_data: &[u8],
+        scan_response_data: &[u8],
+        duration: u16,
+        max_ext_adv_events: u8,
+    ) {
+        self.internal.AdvertiserStartAdvertisingSet(
+            reg_id,
+            params,
+            advertise_data,
+            scan_response_data,
+            duration,
+            max_ext_adv_events,
+        )
+    }
+
+    /// Sets the parameters of an advertising set, which must not be enabled. This triggers an
+    /// `advertiser_parameters_updated` callback.
+    pub fn set_advertising_parameters(
+        &mut self,
+        advertiser_id: i32,
+        params: &ffi::BtAdvertiseParameters,
+    ) {
+        self.internal.AdvertiserSetParameters(advertiser_id, params)
+    }
+
+    /// Sets the advertising or scan response data of an advertising set. This triggers an
+    /// `advertiser_data_set` callback.
+    pub fn set_advertising_data(&mut self, advertiser_id: i32, set_scan_rsp: bool, data: &[u8]) {
+        self.internal.AdvertiserSetData(advertiser_id, set_scan_rsp, data)
+    }
+
+    /// Enables or disables an advertising set. This triggers an `advertiser_enabled` callback.
+    pub fn enable_advertising(
+        &mut self,
+        advertiser_id: i32,
+        enable: bool,
+        duration: u16,
+        max_ext_adv_events: u8,
+    ) {
+        self.internal.AdvertiserEnable(advertiser_id, enable, duration, max_ext_adv_events)
+    }
+
+    /// Reads the address an advertising set advertises with. This triggers an
+    /// `advertiser_own_address_read` callback.
+    pub fn get_own_address(&mut self, advertiser_id: i32) {
+        self.internal.AdvertiserGetOwnAddress(advertiser_id)
+    }
+
+    /// Stops and releases an advertising set.
+    pub fn unregister_advertiser(&mut self, advertiser_id: i32) {
+        self.internal.AdvertiserUnregister(advertiser_id)
+    }
+}
+
+unsafe impl Send for Gatt {}
+
+fn gatt_client_register_client_callback(
+    cb: &RustGattCallbacks,
+    status: i32,
+    client_if: i32,
+    app_uuid: ffi::BtUuid,
+) {
+    topstack::catch_callback_panic("gatt_client_register_client", || {
+        (cb.inner.client_registered)(status, client_if, app_uuid);
+    });
+}
+
+fn gatt_client_connect_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    status: i32,
+    client_if: i32,
+    address: ffi::RustRawAddress,
+) {
+    topstack::catch_callback_panic("gatt_client_connect", || {
+        (cb.inner.client_connect)(conn_id, status, client_if, address);
+    });
+}
+
+fn gatt_client_disconnect_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    status: i32,
+    client_if: i32,
+    address: ffi::RustRawAddress,
+) {
+    topstack::catch_callback_panic("gatt_client_disconnect", || {
+        (cb.inner.client_disconnect)(conn_id, status, client_if, address);
+    });
+}
+
+fn gatt_client_read_remote_rssi_callback(
+    cb: &RustGattCallbacks,
+    client_if: i32,
+    address: ffi::RustRawAddress,
+    rssi: i32,
+    status: i32,
+) {
+    topstack::catch_callback_panic("gatt_client_read_remote_rssi", || {
+        (cb.inner.client_read_remote_rssi)(client_if, address, rssi, status);
+    });
+}
+
+fn gatt_client_search_complete_callback(cb: &RustGattCallbacks, conn_id: i32, status: i32) {
+    topstack::catch_callback_panic("gatt_client_search_complete", || {
+        (cb.inner.client_search_complete)(conn_id, status);
+    });
+}
+
+fn gatt_client_notify_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    address: ffi::RustRawAddress,
+    handle: i32,
+    is_notify: bool,
+    value: Vec<u8>,
+) {
+    topstack::catch_callback_panic("gatt_client_notify", || {
+        (cb.inner.client_notify)(conn_id, address, handle, is_notify, value);
+    });
+}
+
+fn gatt_client_get_gatt_db_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    elements: Vec<ffi::BtGattDbElement>,
+) {
+    topstack::catch_callback_panic("gatt_client_get_gatt_db", || {
+        (cb.inner.client_get_gatt_db)(conn_id, elements);
+    });
+}
+
+fn gatt_client_read_characteristic_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    status: i32,
+    handle: i32,
+    value: Vec<u8>,
+) {
+    topstack::catch_callback_panic("gatt_client_read_characteristic", || {
+        (cb.inner.client_read_characteristic)(conn_id, status, handle, value);
+    });
+}
+
+fn gatt_client_write_characteristic_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    status: i32,
+    handle: i32,
+) {
+    topstack::catch_callback_panic("gatt_client_write_characteristic", || {
+        (cb.inner.client_write_characteristic)(conn_id, status, handle);
+    });
+}
+
+fn gatt_client_read_descriptor_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    status: i32,
+    handle: i32,
+    value: Vec<u8>,
+) {
+    topstack::catch_callback_panic("gatt_client_read_descriptor", || {
+        (cb.inner.client_read_descriptor)(conn_id, status, handle, value);
+    });
+}
+
+fn gatt_client_write_descriptor_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    status: i32,
+    handle: i32,
+) {
+    topstack::catch_callback_panic("gatt_client_write_descriptor", || {
+        (cb.inner.client_write_descriptor)(conn_id, status, handle);
+    });
+}
+
+fn gatt_client_execute_write_callback(cb: &RustGattCallbacks, conn_id: i32, status: i32) {
+    topstack::catch_callback_panic("gatt_client_execute_write", || {
+        (cb.inner.client_execute_write)(conn_id, status);
+    });
+}
+
+fn gatt_client_configure_mtu_callback(cb: &RustGattCallbacks, conn_id: i32, status: i32, mtu: i32) {
+    topstack::catch_callback_panic("gatt_client_configure_mtu", || {
+        (cb.inner.client_configure_mtu)(conn_id, status, mtu);
+    });
+}
+
+fn gatt_client_phy_updated_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    tx_phy: i32,
+    rx_phy: i32,
+    status: i32,
+) {
+    topstack::catch_callback_panic("gatt_client_phy_updated", || {
+        (cb.inner.client_phy_updated)(conn_id, tx_phy, rx_phy, status);
+    });
+}
+
+fn gatt_client_conn_updated_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    interval: i32,
+    latency: i32,
+    timeout: i32,
+    status: i32,
+) {
+    topstack::catch_callback_panic("gatt_client_conn_updated", || {
+        (cb.inner.client_conn_updated)(conn_id, interval, latency, timeout, status);
+    });
+}
+
+fn gatt_client_service_changed_callback(cb: &RustGattCallbacks, conn_id: i32) {
+    topstack::catch_callback_panic("gatt_client_service_changed", || {
+        (cb.inner.client_service_changed)(conn_id);
+    });
+}
+
+fn gatt_client_services_rediscovered_callback(
+    cb: &RustGattCallbacks,
+    address: ffi::RustRawAddress,
+) {
+    topstack::catch_callback_panic("gatt_client_services_rediscovered", || {
+        (cb.inner.client_services_rediscovered)(address);
+    });
+}
+
+fn gatt_client_read_phy_callback(
+    cb: &RustGattCallbacks,
+    client_if: i32,
+    address: ffi::RustRawAddress,
+    tx_phy: i32,
+    rx_phy: i32,
+    status: i32,
+) {
+    topstack::catch_callback_panic("gatt_client_read_phy", || {
+        (cb.inner.client_read_phy)(client_if, address, tx_phy, rx_phy, status);
+    });
+}
+
+fn gatt_server_register_server_callback(
+    cb: &RustGattCallbacks,
+    status: i32,
+    server_if: i32,
+    app_uuid: ffi::BtUuid,
+) {
+    topstack::catch_callback_panic("gatt_server_register_server", || {
+        (cb.inner.server_registered)(status, server_if, app_uuid);
+    });
+}
+
+fn gatt_server_connection_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    server_if: i32,
+    connected: i32,
+    address: ffi::RustRawAddress,
+) {
+    topstack::catch_callback_panic("gatt_server_connection", || {
+        (cb.inner.server_connection)(conn_id, server_if, connected, address);
+    });
+}
+
+fn gatt_server_service_added_callback(
+    cb: &RustGattCallbacks,
+    status: i32,
+    server_if: i32,
+    elements: Vec<ffi::BtGattDbElement>,
+) {
+    topstack::catch_callback_panic("gatt_server_service_added", || {
+        (cb.inner.server_service_added)(status, server_if, elements);
+    });
+}
+
+fn gatt_server_request_read_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    trans_id: i32,
+    address: ffi::RustRawAddress,
+    handle: i32,
+    offset: i32,
+    is_long: bool,
+) {
+    topstack::catch_callback_panic("gatt_server_request_read", || {
+        (cb.inner.server_request_read)(conn_id, trans_id, address, handle, offset, is_long);
+    });
+}
+
+fn gatt_server_indication_sent_callback(cb: &RustGattCallbacks, conn_id: i32, status: i32) {
+    topstack::catch_callback_panic("gatt_server_indication_sent", || {
+        (cb.inner.server_indication_sent)(conn_id, status);
+    });
+}
+
+fn gatt_server_congestion_callback(cb: &RustGattCallbacks, conn_id: i32, congested: bool) {
+    topstack::catch_callback_panic("gatt_server_congestion", || {
+        (cb.inner.server_congestion)(conn_id, congested);
+    });
+}
+
+fn gatt_server_mtu_changed_callback(cb: &RustGattCallbacks, conn_id: i32, mtu: i32) {
+    topstack::catch_callback_panic("gatt_server_mtu_changed", || {
+        (cb.inner.server_mtu_changed)(conn_id, mtu);
+    });
+}
+
+fn gatt_server_read_phy_callback(
+    cb: &RustGattCallbacks,
+    server_if: i32,
+    address: ffi::RustRawAddress,
+    tx_phy: i32,
+    rx_phy: i32,
+    status: i32,
+) {
+    topstack::catch_callback_panic("gatt_server_read_phy", || {
+        (cb.inner.server_read_phy)(server_if, address, tx_phy, rx_phy, status);
+    });
+}
+
+fn gatt_server_request_write_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    trans_id: i32,
+    address: ffi::RustRawAddress,
+    handle: i32,
+    offset: i32,
+    need_rsp: bool,
+    is_prep: bool,
+    value: Vec<u8>,
+) {
+    topstack::catch_callback_panic("gatt_server_request_write", || {
+        (cb.inner.server_request_write)(
+            conn_id, trans_id, address, handle, offset, need_rsp, is_prep, value,
+        );
+    });
+}
+
+fn gatt_server_request_exec_write_callback(
+    cb: &RustGattCallbacks,
+    conn_id: i32,
+    trans_id: i32,
+    address: ffi::RustRawAddress,
+    exec_write: i32,
+) {
+    topstack::catch_callback_panic("gatt_server_request_exec_write", || {
+        (cb.inner.server_request_exec_write)(conn_id, trans_id, address, exec_write);
+    });
+}
+
+fn gatt_scanner_registered_callback(
+    cb: &RustGattCallbacks,
+    app_uuid: ffi::BtUuid,
+    scanner_id: i32,
+    status: i32,
+) {
+    topstack::catch_callback_panic("gatt_scanner_registered", || {
+        (cb.inner.scanner_registered)(app_uuid, scanner_id, status);
+    });
+}
+
+fn gatt_scanner_scan_result_callback(
+    cb: &RustGattCallbacks,
+    event_type: i32,
+    addr_type: i32,
+    address: ffi::RustRawAddress,
+    primary_phy: i32,
+    secondary_phy: i32,
+    advertising_sid: i32,
+    tx_power: i32,
+    rssi: i32,
+    periodic_adv_int: i32,
+    adv_data: Vec<u8>,
+) {
+    topstack::catch_callback_panic("gatt_scanner_scan_result", || {
+        (cb.inner.scan_result)(
+            event_type,
+            addr_type,
+            address,
+            primary_phy,
+            secondary_phy,
+            advertising_sid,
+            tx_power,
+            rssi,
+            periodic_adv_int,
+            adv_data,
+        );
+    });
+}
+
+fn gatt_scanner_filter_configured_callback(cb: &RustGattCallbacks, filter_index: i32, status: i32) {
+    topstack::catch_callback_panic("gatt_scanner_filter_configured", || {
+        (cb.inner.scanner_filter_configured)(filter_index, status);
+    });
+}
+
+fn gatt_advertiser_set_started_callback(
+    cb: &RustGattCallbacks,
+    reg_id: i32,
+    advertiser_id: i32,
+    tx_power: i32,
+    status: i32,
+) {
+    topstack::catch_callback_panic("gatt_advertiser_set_started", || {
+        (cb.inner.advertiser_set_started)(reg_id, advertiser_id, tx_power, status);
+    });
+}
+
+fn gatt_advertiser_enabled_callback(
+    cb: &RustGattCallbacks,
+    advertiser_id: i32,
+    enable: bool,
+    status: i32,
+) {
+    topstack::catch_callback_panic("gatt_advertiser_enabled", || {
+        (cb.inner.advertiser_enabled)(advertiser_id, enable, status);
+    });
+}
+
+fn gatt_advertiser_data_set_callback(
+    cb: &RustGattCallbacks,
+    advertiser_id: i32,
+    set_scan_rsp: bool,
+    status: i32,
+) {
+    topstack::catch_callback_panic("gatt_advertiser_data_set", || {
+        (cb.inner.advertiser_data_set)(advertiser_id, set_scan_rsp, status);
+    });
+}
+
+fn gatt_advertiser_parameters_updated_callback(
+    cb: &RustGattCallbacks,
+    advertiser_id: i32,
+    tx_power: i32,
+    status: i32,
+) {
+    topstack::catch_callback_panic("gatt_advertiser_parameters_updated", || {
+        (cb.inner.advertiser_parameters_updated)(advertiser_id, tx_power, status);
+    });
+}
+
+fn gatt_advertiser_own_address_read_callback(
+    cb: &RustGattCallbacks,
+    advertiser_id: i32,
+    address_type: i32,
+    address: ffi::RustRawAddress,
+) {
+    topstack::catch_callback_panic("gatt_advertiser_own_address_read", || {
+        (cb.inner.advertiser_own_address_read)(advertiser_id, address_type, address);
+    });
+}
@@ -0,0 +1,232 @@
+//! LE audio client interface shim
+//!
+//! This is a shim interface for calling the C++ LE audio client interface via Rust. The client
+//! connects to the audio devices with the Basic Audio Profile (BAP) and sets up the streams of
+//! their groups with the Audio Stream Control Service (ASCS).
+
+use crate::btif::BluetoothInterface;
+use crate::profiles::ProfileShim;
+use crate::topstack;
+
+use num_traits::FromPrimitive;
+use std::sync::Arc;
+
+/// Group ID of a device that is in no group, `INSTANCE_ID_UNDEFINED`.
+pub const LE_AUDIO_GROUP_ID_UNDEFINED: u8 = 0xFF;
+
+#[derive(Debug, FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Clone, Copy)]
+#[repr(i32)]
+pub enum LeAudioConnectionState {
+    Disconnected = 0,
+    Connecting,
+    Connected,
+    Disconnecting,
+    /// A state value that this shim doesn't know about.
+    Unknown = -1,
+}
+
+/// Stream status of a group of devices, e.g. both earbuds of a pair.
+#[derive(Debug, FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Clone, Copy)]
+#[repr(i32)]
+pub enum LeAudioGroupStatus {
+    Idle = 0,
+    Streaming,
+    Suspended,
+    Reconfigured,
+    /// The group no longer exists, e.g. once its last device is gone.
+    Destroyed,
+    /// A status value that this shim doesn't know about.
+    Unknown = -1,
+}
+
+#[cxx::bridge(namespace = bluetooth::topshim::rust)]
+pub mod ffi {
+    unsafe extern "C++" {
+        include!("le_audio/le_audio_shim.h");
+
+        type BluetoothIntf = crate::btif::ffi::BluetoothIntf;
+        type RustRawAddress = crate::btif::ffi::RustRawAddress;
+
+        // Opaque type meant to represent C object for the LE audio client interface.
+        type LeAudioIntf;
+
+        // Loads the LE audio client profile from an already loaded Bluetooth interface.
+        fn LoadLeAudio(btif: &BluetoothIntf) -> UniquePtr<LeAudioIntf>;
+
+        fn Initialize(self: Pin<&mut LeAudioIntf>, callbacks: Box<RustLeAudioCallbacks>) -> bool;
+        fn CleanUp(self: Pin<&mut LeAudioIntf>);
+
+        fn Connect(&self, address: &RustRawAddress);
+        fn Disconnect(&self, address: &RustRawAddress);
+
+        // Stream requests of a group. The content type is a bit mask of the BAP context types.
+        fn GroupStream(&self, group_id: u8, content_type: u16);
+        fn GroupSuspend(&self, group_id: u8);
+        fn GroupStop(&self, group_id: u8);
+    }
+
+    extern "Rust" {
+        type RustLeAudioCallbacks;
+
+        // Callbacks from C++ to Rust. See `btif::ffi` for how the callbacks are stored.
+
+        fn le_audio_connection_state_callback(
+            cb: &RustLeAudioCallbacks,
+            address: RustRawAddress,
+            state: i32,
+        );
+        fn le_audio_group_status_callback(
+            cb: &RustLeAudioCallbacks,
+            group_id: u8,
+            status: i32,
+            flags: u8,
+        );
+        fn le_audio_audio_conf_callback(
+            cb: &RustLeAudioCallbacks,
+            address: RustRawAddress,
+            direction: u8,
+            group_id: u8,
+            sink_audio_location: u32,
+            source_audio_location: u32,
+        );
+        fn le_audio_set_member_available_callback(
+            cb: &RustLeAudioCallbacks,
+            address: RustRawAddress,
+            group_id: u8,
+        );
+    }
+
+    unsafe impl Box<RustLeAudioCallbacks> {}
+}
+
+/// Rust struct of closures for all LE audio client callbacks from C++.
+pub struct LeAudioCallbacks {
+    pub connection_state_changed: Box<dyn Fn(ffi::RustRawAddress, LeAudioConnectionState) + Send>,
+    /// Status of a group, with its flags.
+    pub group_status_changed: Box<dyn Fn(u8, LeAudioGroupStatus, u8) + Send>,
+    /// Configuration of a new or reconfigured device: the directions it streams in (a bit mask
+    /// of sink and source), its group, and the audio locations of its sinks and sources.
+    pub audio_conf: Box<dyn Fn(ffi::RustRawAddress, u8, u8, u32, u32) + Send>,
+    /// A device of the coordinated set of a group is available, e.g. the other earbud of a pair.
+    pub set_member_available: Box<dyn Fn(ffi::RustRawAddress, u8) + Send>,
+}
+
+impl Default for LeAudioCallbacks {
+    /// Constructs callbacks that ignore every event.
+    fn default() -> Self {
+        LeAudioCallbacks {
+            connection_state_changed: Box::new(|_, _| {}),
+            group_status_changed: Box::new(|_, _, _| {}),
+            audio_conf: Box::new(|_, _, _, _, _| {}),
+            set_member_available: Box::new(|_, _| {}),
+        }
+    }
+}
+
+pub struct RustLeAudioCallbacks {
+    inner: Arc<LeAudioCallbacks>,
+}
+
+/// Rust interface to the native LE audio client profile.
+pub struct LeAudio {
+    internal: cxx::UniquePtr<ffi::LeAudioIntf>,
+}
+
+impl ProfileShim for LeAudio {
+    type Callbacks = LeAudioCallbacks;
+
+    fn initialize(&mut self, callbacks: Arc<LeAudioCallbacks>) -> bool {
+        self.internal.pin_mut().Initialize(Box::new(RustLeAudioCallbacks { inner: callbacks }))
+    }
+
+    fn cleanup(&mut self) {
+        self.internal.pin_mut().CleanUp()
+    }
+}
+
+impl Drop for LeAudio {
+    fn drop(&mut self) {
+        self.cleanup();
+    }
+}
+
+impl LeAudio {
+    pub fn new(intf: &BluetoothInterface) -> LeAudio {
+        LeAudio { internal: ffi::LoadLeAudio(intf.as_ffi()) }
+    }
+
+    pub fn connect(&mut self, address: &ffi::RustRawAddress) {
+        self.internal.Connect(address)
+    }
+
+    pub fn disconnect(&mut self, address: &ffi::RustRawAddress) {
+        self.internal.Disconnect(address)
+    }
+
+    /// Starts streaming to a group for the BAP context types in `content_type`. This triggers a
+    /// `group_status_changed` callback once the group streams.
+    pub fn group_stream(&mut self, group_id: u8, content_type: u16) {
+        self.internal.GroupStream(group_id, content_type)
+    }
+
+    /// Suspends the streams of a group, keeping them configured to resume quickly.
+    pub fn group_suspend(&mut self, group_id: u8) {
+        self.internal.GroupSuspend(group_id)
+    }
+
+    /// Stops the streams of a group and releases them.
+    pub fn group_stop(&mut self, group_id: u8) {
+        self.internal.GroupStop(group_id)
+    }
+}
+
+unsafe impl Send for LeAudio {}
+
+fn le_audio_connection_state_callback(
+    cb: &RustLeAudioCallbacks,
+    address: ffi::RustRawAddress,
+    state: i32,
+) {
+    topstack::catch_callback_panic("le_audio_connection_state", || {
+        let new_state =
+            LeAudioConnectionState::from_i32(state).unwrap_or(LeAudioConnectionState::Unknown);
+        (cb.inner.connection_state_changed)(address, new_state);
+    });
+}
+
+fn le_audio_group_status_callback(cb: &RustLeAudioCallbacks, group_id: u8, status: i32, flags: u8) {
+    topstack::catch_callback_panic("le_audio_group_status", || {
+        let new_status =
+            LeAudioGroupStatus::from_i32(status).unwrap_or(LeAudioGroupStatus::Unknown);
+        (cb.inner.group_status_changed)(group_id, new_status, flags);
+    });
+}
+
+fn le_audio_audio_conf_callback(
+    cb: &RustLeAudioCallbacks,
+    address: ffi::RustRawAddress,
+    direction: u8,
+    group_id: u8,
+    sink_audio_location: u32,
+    source_audio_location: u32,
+) {
+    topstack::catch_callback_panic("le_audio_audio_conf", || {
+        (cb.inner.audio_conf)(
+            address,
+            direction,
+            group_id,
+            sink_audio_location,
+            source_audio_location,
+        );
+    });
+}
+
+fn le_audio_set_member_available_callback(
+    cb: &RustLeAudioCallbacks,
+    address: ffi::RustRawAddress,
+    group_id: u8,
+) {
+    topstack::catch_callback_panic("le_audio_set_member_available", || {
+        (cb.inner.set_member_available)(address, group_id);
+    });
+}
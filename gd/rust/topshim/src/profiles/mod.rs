@@ -0,0 +1,28 @@
+//! Profile interfaces shim
+//!
+//! Each submodule wraps one of the profile interfaces exposed through
+//! `bt_interface_t::get_profile_interface`.
+
+use std::sync::Arc;
+
+pub mod a2dp;
+pub mod gatt;
+#[cfg(feature = "le_audio")]
+pub mod le_audio;
+
+/// Lifecycle of a profile interface.
+///
+/// The native profile reports its events to the callbacks it is initialized with until it is
+/// cleaned up. Profiles are cleaned up when dropped, so that the native stack never calls the
+/// callbacks of a profile that is gone.
+pub trait ProfileShim {
+    type Callbacks;
+
+    /// Initializes the native profile, or does nothing if it is already initialized. Returns
+    /// false if the native stack doesn't support the profile or failed to initialize it.
+    fn initialize(&mut self, callbacks: Arc<Self::Callbacks>) -> bool;
+
+    /// Cleans up the native profile and drops its callbacks, or does nothing if it is not
+    /// initialized. It can be initialized again afterwards.
+    fn cleanup(&mut self);
+}
@@ -0,0 +1,86 @@
+//! QA interface shim
+//!
+//! This is a shim for controller test commands that are not exposed by the Bluetooth HAL
+//! interface, such as reading the maximum transmit power of a connection.
+
+use std::sync::Arc;
+
+use crate::topstack;
+
+#[cxx::bridge(namespace = bluetooth::topshim::rust)]
+pub mod ffi {
+    unsafe extern "C++" {
+        include!("qa/qa_shim.h");
+
+        type RustRawAddress = crate::btif::ffi::RustRawAddress;
+
+        // Opaque type meant to represent C object for the QA interface.
+        type QaIntf;
+
+        fn LoadQa(callbacks: Box<RustQaCallbacks>) -> UniquePtr<QaIntf>;
+
+        fn ReadMaxTxPower(&self, address: &RustRawAddress, transport: i32) -> i32;
+    }
+
+    extern "Rust" {
+        type RustQaCallbacks;
+
+        // Callbacks from C++ to Rust. See `btif::ffi` for how the callbacks are stored.
+
+        fn qa_read_max_tx_power_callback(
+            cb: &RustQaCallbacks,
+            address: RustRawAddress,
+            status: i32,
+            tx_power: i32,
+        );
+    }
+
+    unsafe impl Box<RustQaCallbacks> {}
+}
+
+/// Rust struct of closures for all QA callbacks from C++.
+pub struct QaCallbacks {
+    /// Called with the BTM status and the maximum TX power in dBm of a connection.
+    pub read_max_tx_power: Box<dyn Fn(ffi::RustRawAddress, i32, i32) + Send>,
+}
+
+impl Default for QaCallbacks {
+    /// Constructs callbacks that ignore every event.
+    fn default() -> Self {
+        QaCallbacks { read_max_tx_power: Box::new(|_, _, _| {}) }
+    }
+}
+
+pub struct RustQaCallbacks {
+    inner: Arc<QaCallbacks>,
+}
+
+/// Rust interface to the native controller test commands.
+pub struct Qa {
+    internal: cxx::UniquePtr<ffi::QaIntf>,
+}
+
+impl Qa {
+    pub fn new(callbacks: Arc<QaCallbacks>) -> Qa {
+        Qa { internal: ffi::LoadQa(Box::new(RustQaCallbacks { inner: callbacks.clone() })) }
+    }
+
+    /// Reads the maximum TX power of the connection to a device on the given transport (1 for
+    /// BR/EDR, 2 for LE). This triggers a read_max_tx_power callback.
+    pub fn read_max_tx_power(&mut self, address: &ffi::RustRawAddress, transport: i32) -> i32 {
+        self.internal.ReadMaxTxPower(address, transport)
+    }
+}
+
+unsafe impl Send for Qa {}
+
+fn qa_read_max_tx_power_callback(
+    cb: &RustQaCallbacks,
+    address: ffi::RustRawAddress,
+    status: i32,
+    tx_power: i32,
+) {
+    topstack::catch_callback_panic("qa_read_max_tx_power", || {
+        (cb.inner.read_max_tx_power)(address, status, tx_power);
+    });
+}
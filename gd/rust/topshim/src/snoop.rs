@@ -0,0 +1,52 @@
+//! HCI snoop log shim
+//!
+//! This is a shim for configuring the snoop log of the HCI layer, which otherwise reads its mode,
+//! path and size from system properties that are only set on Android.
+
+#[cxx::bridge(namespace = bluetooth::topshim::rust)]
+pub mod ffi {
+    unsafe extern "C++" {
+        include!("snoop/snoop_shim.h");
+
+        fn SetSnoopLogConfig(mode: &str, path: &str, max_packets_per_file: i32) -> bool;
+    }
+}
+
+/// What the snoop log records.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SnoopLogMode {
+    Disabled,
+    /// Leaves out the audio data and the payloads of the ACL packets, except those of the
+    /// signaling channels. The log is written to `<path>.filtered`.
+    Filtered,
+    Full,
+}
+
+impl SnoopLogMode {
+    fn name(&self) -> &'static str {
+        match self {
+            SnoopLogMode::Disabled => "disabled",
+            SnoopLogMode::Filtered => "filtered",
+            SnoopLogMode::Full => "full",
+        }
+    }
+}
+
+/// Rust interface to the snoop log.
+pub struct SnoopLog {}
+
+impl SnoopLog {
+    pub fn new() -> SnoopLog {
+        SnoopLog {}
+    }
+
+    /// Sets the mode of the snoop log, where it is written, and the number of packets after
+    /// which it is moved to `<path>.last` and a new one started.
+    ///
+    /// The configuration applies right away while the adapter is enabled, and otherwise when it
+    /// is next enabled. The logs of the previous configuration are kept. Returns false if the
+    /// snoop log can't be configured, i.e. with the GD stack.
+    pub fn configure(&mut self, mode: SnoopLogMode, path: &str, max_packets_per_file: i32) -> bool {
+        ffi::SetSnoopLogConfig(mode.name(), path, max_packets_per_file)
+    }
+}
@@ -2,6 +2,8 @@
 //!
 //! Helpers for dealing with the stack on top of the Bluetooth interface.
 
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::runtime::{Builder, Runtime};
 
@@ -21,3 +23,29 @@ lazy_static! {
 pub fn get_runtime() -> Arc<Runtime> {
     RUNTIME.clone()
 }
+
+/// Number of panics caught by `catch_callback_panic` since startup.
+static CALLBACK_PANICS: AtomicU64 = AtomicU64::new(0);
+
+/// Runs the handler of a callback from C++, catching any panic.
+///
+/// A panic unwinding across the FFI boundary aborts the whole daemon, so a faulty handler only
+/// drops the event instead. The panic is logged and counted. Every callback that the shims
+/// receive from C++ runs its handler through this.
+pub fn catch_callback_panic<F: FnOnce()>(name: &str, f: F) {
+    if let Err(e) = panic::catch_unwind(AssertUnwindSafe(f)) {
+        let count = CALLBACK_PANICS.fetch_add(1, Ordering::Relaxed) + 1;
+        let reason = e
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| e.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| String::from("unknown"));
+
+        eprintln!("Callback {} panicked, dropping it ({} so far): {}", name, count, reason);
+    }
+}
+
+/// Returns the number of panics caught in callbacks from C++ since startup.
+pub fn callback_panic_count() -> u64 {
+    CALLBACK_PANICS.load(Ordering::Relaxed)
+}
@@ -0,0 +1,77 @@
+//! Vendor interface shim
+//!
+//! This is a shim for sending vendor specific HCI commands to the controller, which the Bluetooth
+//! HAL interface doesn't expose.
+
+use std::sync::Arc;
+
+use crate::topstack;
+
+#[cxx::bridge(namespace = bluetooth::topshim::rust)]
+pub mod ffi {
+    unsafe extern "C++" {
+        include!("vendor/vendor_shim.h");
+
+        // Opaque type meant to represent C object for the vendor interface.
+        type VendorIntf;
+
+        fn LoadVendor(callbacks: Box<RustVendorCallbacks>) -> UniquePtr<VendorIntf>;
+
+        fn SendCommand(&self, opcode: u16, params: &[u8]) -> i32;
+    }
+
+    extern "Rust" {
+        type RustVendorCallbacks;
+
+        // Callbacks from C++ to Rust. See `btif::ffi` for how the callbacks are stored.
+
+        fn vendor_command_complete_callback(cb: &RustVendorCallbacks, opcode: u16, params: &[u8]);
+    }
+
+    unsafe impl Box<RustVendorCallbacks> {}
+}
+
+/// Rust struct of closures for all vendor callbacks from C++.
+pub struct VendorCallbacks {
+    /// Called with the opcode and the return parameters of the command complete event of a
+    /// vendor specific command.
+    pub command_complete: Box<dyn Fn(u16, Vec<u8>) + Send>,
+}
+
+impl Default for VendorCallbacks {
+    /// Constructs callbacks that ignore every event.
+    fn default() -> Self {
+        VendorCallbacks { command_complete: Box::new(|_, _| {}) }
+    }
+}
+
+pub struct RustVendorCallbacks {
+    inner: Arc<VendorCallbacks>,
+}
+
+/// Rust interface to the vendor specific HCI commands.
+pub struct Vendor {
+    internal: cxx::UniquePtr<ffi::VendorIntf>,
+}
+
+impl Vendor {
+    pub fn new(callbacks: Arc<VendorCallbacks>) -> Vendor {
+        Vendor {
+            internal: ffi::LoadVendor(Box::new(RustVendorCallbacks { inner: callbacks.clone() })),
+        }
+    }
+
+    /// Sends a vendor specific command with up to 255 bytes of parameters. This triggers a
+    /// command_complete callback once the controller answers.
+    pub fn send_command(&mut self, opcode: u16, params: &[u8]) -> i32 {
+        self.internal.SendCommand(opcode, params)
+    }
+}
+
+unsafe impl Send for Vendor {}
+
+fn vendor_command_complete_callback(cb: &RustVendorCallbacks, opcode: u16, params: &[u8]) {
+    topstack::catch_callback_panic("vendor_command_complete", || {
+        (cb.inner.command_complete)(opcode, params.to_vec());
+    });
+}
@@ -54,3 +54,11 @@ typedef struct btsnoop_t {
 } btsnoop_t;
 
 const btsnoop_t* btsnoop_get_interface(void);
+
+// Sets the snoop log |mode| ("disabled", "filtered" or "full"), the |path| of
+// the log and the number of packets after which the log is moved to
+// "<path>.last" and a new one started, instead of the system properties. The
+// configuration applies right away if the module is started, and otherwise
+// when it starts. The logs of the previous configuration are kept.
+void btsnoop_set_config(const char* mode, const char* path,
+                        int32_t max_packets_per_file);
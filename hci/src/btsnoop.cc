@@ -18,6 +18,7 @@
 
 #define LOG_TAG "bt_snoop"
 
+#include <algorithm>
 #include <mutex>
 
 #include <arpa/inet.h>
@@ -92,6 +93,11 @@ static const size_t RFC_EVENT_OFFSET = 9;
 // a filtered packet.
 static const uint32_t L2C_HEADER_SIZE = 9;
 
+// The sizes of the SCO and ISO headers. The audio data past them is removed
+// from a filtered packet.
+static const uint32_t SCO_HEADER_SIZE = 4;
+static const uint32_t ISO_HEADER_SIZE = 5;
+
 static int logfile_fd = INVALID_FD;
 static std::mutex btsnoop_mutex;
 
@@ -162,6 +168,17 @@ std::unordered_map<uint16_t, uint16_t> local_cid_to_acl;
 static bool is_btsnoop_enabled;
 static bool is_btsnoop_filtered;
 
+// Whether the module is started, in which case a configuration set with
+// |btsnoop_set_config| applies right away.
+static bool is_btsnoop_started;
+
+// The configuration set with |btsnoop_set_config|, which takes over the one of
+// the system properties.
+static bool is_config_overridden;
+static std::string mode_override;
+static std::string path_override;
+static int32_t packets_per_file_override;
+
 // TODO(zachoverflow): merge btsnoop and btsnoop_net together
 void btsnoop_net_open();
 void btsnoop_net_close();
@@ -170,6 +187,9 @@ void btsnoop_net_write(const void* data, size_t length);
 static void delete_btsnoop_files(bool filtered);
 static std::string get_btsnoop_log_path(bool filtered);
 static std::string get_btsnoop_last_log_path(std::string log_path);
+static std::string get_btsnoop_mode();
+static void start_btsnoop();
+static void stop_btsnoop();
 static void open_next_snoop_file();
 static void btsnoop_write_packet(packet_type_t type, uint8_t* packet,
                                  bool is_received, uint64_t timestamp_us);
@@ -177,48 +197,10 @@ static void btsnoop_write_packet(packet_type_t type, uint8_t* packet,
 // Module lifecycle functions
 
 static future_t* start_up() {
-  std::array<char, PROPERTY_VALUE_MAX> property = {};
   std::lock_guard<std::mutex> lock(btsnoop_mutex);
 
-  // Default mode is FILTERED on userdebug/eng build, DISABLED on user build.
-  // It can also be overwritten by modifying the global setting.
-  int is_debuggable = osi_property_get_int32(IS_DEBUGGABLE_PROPERTY, 0);
-  std::string default_mode = BTSNOOP_MODE_DISABLED;
-  if (is_debuggable) {
-    int len = osi_property_get(BTSNOOP_DEFAULT_MODE_PROPERTY, property.data(),
-                               BTSNOOP_MODE_DISABLED);
-    default_mode = std::string(property.data(), len);
-  }
-
-  // Get the actual mode
-  int len = osi_property_get(BTSNOOP_LOG_MODE_PROPERTY, property.data(),
-                             default_mode.c_str());
-  std::string btsnoop_mode(property.data(), len);
-
-  if (btsnoop_mode == BTSNOOP_MODE_FILTERED) {
-    LOG(INFO) << __func__ << ": Filtered Snoop Logs enabled";
-    is_btsnoop_enabled = true;
-    is_btsnoop_filtered = true;
-    delete_btsnoop_files(false);
-  } else if (btsnoop_mode == BTSNOOP_MODE_FULL) {
-    LOG(INFO) << __func__ << ": Snoop Logs fully enabled";
-    is_btsnoop_enabled = true;
-    is_btsnoop_filtered = false;
-    delete_btsnoop_files(true);
-  } else {
-    LOG(INFO) << __func__ << ": Snoop Logs disabled";
-    is_btsnoop_enabled = false;
-    is_btsnoop_filtered = false;
-    delete_btsnoop_files(true);
-    delete_btsnoop_files(false);
-  }
-
-  if (is_btsnoop_enabled) {
-    open_next_snoop_file();
-    packets_per_file = osi_property_get_int32(BTSNOOP_MAX_PACKETS_PROPERTY,
-                                              DEFAULT_BTSNOOP_SIZE);
-    btsnoop_net_open();
-  }
+  start_btsnoop();
+  is_btsnoop_started = true;
 
   return NULL;
 }
@@ -226,21 +208,23 @@ static future_t* start_up() {
 static future_t* shut_down(void) {
   std::lock_guard<std::mutex> lock(btsnoop_mutex);
 
-  if (is_btsnoop_enabled) {
-    if (is_btsnoop_filtered) {
-      delete_btsnoop_files(false);
+  // The logs of a configuration set with |btsnoop_set_config| are left to
+  // whoever set it.
+  if (!is_config_overridden) {
+    if (is_btsnoop_enabled) {
+      if (is_btsnoop_filtered) {
+        delete_btsnoop_files(false);
+      } else {
+        delete_btsnoop_files(true);
+      }
     } else {
       delete_btsnoop_files(true);
+      delete_btsnoop_files(false);
     }
-  } else {
-    delete_btsnoop_files(true);
-    delete_btsnoop_files(false);
   }
 
-  if (logfile_fd != INVALID_FD) close(logfile_fd);
-  logfile_fd = INVALID_FD;
-
-  if (is_btsnoop_enabled) btsnoop_net_close();
+  stop_btsnoop();
+  is_btsnoop_started = false;
 
   return NULL;
 }
@@ -352,6 +336,91 @@ static const btsnoop_t interface = {capture, allowlist_l2c_channel,
 
 const btsnoop_t* btsnoop_get_interface() { return &interface; }
 
+void btsnoop_set_config(const char* mode, const char* path,
+                        int32_t max_packets_per_file) {
+  std::lock_guard<std::mutex> lock(btsnoop_mutex);
+
+  if (is_btsnoop_started) stop_btsnoop();
+
+  is_config_overridden = true;
+  mode_override = mode;
+  path_override = path;
+  packets_per_file_override = max_packets_per_file;
+
+  if (is_btsnoop_started) start_btsnoop();
+}
+
+static std::string get_btsnoop_mode() {
+  if (is_config_overridden) return mode_override;
+
+  std::array<char, PROPERTY_VALUE_MAX> property = {};
+
+  // Default mode is FILTERED on userdebug/eng build, DISABLED on user build.
+  // It can also be overwritten by modifying the global setting.
+  int is_debuggable = osi_property_get_int32(IS_DEBUGGABLE_PROPERTY, 0);
+  std::string default_mode = BTSNOOP_MODE_DISABLED;
+  if (is_debuggable) {
+    int len = osi_property_get(BTSNOOP_DEFAULT_MODE_PROPERTY, property.data(),
+                               BTSNOOP_MODE_DISABLED);
+    default_mode = std::string(property.data(), len);
+  }
+
+  // Get the actual mode
+  int len = osi_property_get(BTSNOOP_LOG_MODE_PROPERTY, property.data(),
+                             default_mode.c_str());
+  return std::string(property.data(), len);
+}
+
+// Starts logging in the current mode. Must be called with |btsnoop_mutex| held.
+static void start_btsnoop() {
+  std::string btsnoop_mode = get_btsnoop_mode();
+
+  // The logs of the other modes are only deleted when the mode comes from the
+  // system properties.
+  bool delete_logs = !is_config_overridden;
+
+  if (btsnoop_mode == BTSNOOP_MODE_FILTERED) {
+    LOG(INFO) << __func__ << ": Filtered Snoop Logs enabled";
+    is_btsnoop_enabled = true;
+    is_btsnoop_filtered = true;
+    if (delete_logs) delete_btsnoop_files(false);
+  } else if (btsnoop_mode == BTSNOOP_MODE_FULL) {
+    LOG(INFO) << __func__ << ": Snoop Logs fully enabled";
+    is_btsnoop_enabled = true;
+    is_btsnoop_filtered = false;
+    if (delete_logs) delete_btsnoop_files(true);
+  } else {
+    LOG(INFO) << __func__ << ": Snoop Logs disabled";
+    is_btsnoop_enabled = false;
+    is_btsnoop_filtered = false;
+    if (delete_logs) {
+      delete_btsnoop_files(true);
+      delete_btsnoop_files(false);
+    }
+  }
+
+  if (is_btsnoop_enabled) {
+    packets_per_file =
+        is_config_overridden
+            ? packets_per_file_override
+            : osi_property_get_int32(BTSNOOP_MAX_PACKETS_PROPERTY,
+                                     DEFAULT_BTSNOOP_SIZE);
+    open_next_snoop_file();
+    btsnoop_net_open();
+  }
+}
+
+// Stops logging, keeping the log files. Must be called with |btsnoop_mutex|
+// held.
+static void stop_btsnoop() {
+  if (logfile_fd != INVALID_FD) close(logfile_fd);
+  logfile_fd = INVALID_FD;
+
+  if (is_btsnoop_enabled) btsnoop_net_close();
+  is_btsnoop_enabled = false;
+  is_btsnoop_filtered = false;
+}
+
 static void delete_btsnoop_files(bool filtered) {
   LOG(INFO) << __func__
             << ": Deleting snoop logs if they exist. filtered = " << filtered;
@@ -361,9 +430,12 @@ static void delete_btsnoop_files(bool filtered) {
 }
 
 std::string get_btsnoop_log_path(bool filtered) {
-  char btsnoop_path[PROPERTY_VALUE_MAX];
-  osi_property_get(BTSNOOP_PATH_PROPERTY, btsnoop_path, DEFAULT_BTSNOOP_PATH);
-  std::string result(btsnoop_path);
+  std::string result = path_override;
+  if (!is_config_overridden) {
+    char btsnoop_path[PROPERTY_VALUE_MAX];
+    osi_property_get(BTSNOOP_PATH_PROPERTY, btsnoop_path, DEFAULT_BTSNOOP_PATH);
+    result = btsnoop_path;
+  }
   if (filtered) result = result.append(".filtered");
 
   return result;
@@ -476,14 +548,26 @@ static void btsnoop_write_packet(packet_type_t type, uint8_t* packet,
   btsnoop_header_t header;
   header.length_original = htonl(length_he);
 
-  bool rejectlisted = false;
-  if (is_btsnoop_filtered && type == kAclPacket) {
-    rejectlisted = should_filter_log(is_received, packet);
+  // Filtered logs leave out the audio data, and the payloads of the ACL
+  // packets outside of the allowlisted channels.
+  if (is_btsnoop_filtered) {
+    switch (type) {
+      case kAclPacket:
+        if (should_filter_log(is_received, packet))
+          length_he = std::min(length_he, L2C_HEADER_SIZE);
+        break;
+      case kScoPacket:
+        length_he = std::min(length_he, SCO_HEADER_SIZE);
+        break;
+      case kIsoPacket:
+        length_he = std::min(length_he, ISO_HEADER_SIZE);
+        break;
+      default:
+        break;
+    }
   }
 
-  header.length_captured =
-      rejectlisted ? htonl(L2C_HEADER_SIZE) : header.length_original;
-  if (rejectlisted) length_he = L2C_HEADER_SIZE;
+  header.length_captured = htonl(length_he);
   header.flags = htonl(flags);
   header.dropped_packets = 0;
   header.timestamp = htonll(timestamp_us + BTSNOOP_EPOCH_DELTA);
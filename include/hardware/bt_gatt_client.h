@@ -184,6 +184,10 @@ typedef void (*conn_updated_callback)(int conn_id, uint16_t interval,
 /** Callback when services are changed */
 typedef void (*service_changed_callback)(int conn_id);
 
+/** Callback when the services of a remote device were discovered again, e.g.
+ * after they changed. The database read with get_gatt_db is up to date. */
+typedef void (*services_rediscovered_callback)(const RawAddress& bda);
+
 typedef struct {
   register_client_callback register_client_cb;
   connect_callback open_cb;
@@ -205,6 +209,7 @@ typedef struct {
   phy_updated_callback phy_updated_cb;
   conn_updated_callback conn_updated_cb;
   service_changed_callback service_changed_cb;
+  services_rediscovered_callback services_rediscovered_cb;
 } btgatt_client_callbacks_t;
 
 /** Represents the standard BT-GATT client interface. */
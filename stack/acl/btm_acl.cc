@@ -1735,6 +1735,13 @@ tBTM_STATUS BTM_ReadFailedContactCounter(const RawAddress& remote_bda,
   return (BTM_UNKNOWN_ADDR);
 }
 
+#define BTM_READ_RSSI_TYPE_CUR 0x00
+#define BTM_READ_RSSI_TYPE_MAX 0X01
+
+static tBTM_STATUS btm_read_tx_power(const RawAddress& remote_bda,
+                                     tBT_TRANSPORT transport,
+                                     tBTM_CMPL_CB* p_cb, bool max);
+
 /*******************************************************************************
  *
  * Function         BTM_ReadTxPower
@@ -1749,9 +1756,31 @@ tBTM_STATUS BTM_ReadFailedContactCounter(const RawAddress& remote_bda,
  ******************************************************************************/
 tBTM_STATUS BTM_ReadTxPower(const RawAddress& remote_bda,
                             tBT_TRANSPORT transport, tBTM_CMPL_CB* p_cb) {
+  return btm_read_tx_power(remote_bda, transport, p_cb, false);
+}
+
+/*******************************************************************************
+ *
+ * Function         BTM_ReadMaxTxPower
+ *
+ * Description      This function is called to read the maximum
+ *                  TX power of the connection. On LE, this is the maximum
+ *                  TX power supported by the controller. The tx power level
+ *                  results are returned in the callback.
+ *                  (tBTM_TX_POWER_RESULT)
+ *
+ * Returns          BTM_CMD_STARTED if successfully initiated or error code
+ *
+ ******************************************************************************/
+tBTM_STATUS BTM_ReadMaxTxPower(const RawAddress& remote_bda,
+                               tBT_TRANSPORT transport, tBTM_CMPL_CB* p_cb) {
+  return btm_read_tx_power(remote_bda, transport, p_cb, true);
+}
+
+static tBTM_STATUS btm_read_tx_power(const RawAddress& remote_bda,
+                                     tBT_TRANSPORT transport,
+                                     tBTM_CMPL_CB* p_cb, bool max) {
   tACL_CONN* p;
-#define BTM_READ_RSSI_TYPE_CUR 0x00
-#define BTM_READ_RSSI_TYPE_MAX 0X01
 
   VLOG(2) << __func__ << ": RemBdAddr: " << remote_bda;
 
@@ -1767,9 +1796,14 @@ tBTM_STATUS BTM_ReadTxPower(const RawAddress& remote_bda,
 
     if (p->transport == BT_TRANSPORT_LE) {
       btm_cb.devcb.read_tx_pwr_addr = remote_bda;
-      btsnd_hcic_ble_read_adv_chnl_tx_power();
+      if (max) {
+        btsnd_hcic_ble_read_transmit_power();
+      } else {
+        btsnd_hcic_ble_read_adv_chnl_tx_power();
+      }
     } else {
-      btsnd_hcic_read_tx_power(p->hci_handle, BTM_READ_RSSI_TYPE_CUR);
+      btsnd_hcic_read_tx_power(p->hci_handle, max ? BTM_READ_RSSI_TYPE_MAX
+                                                  : BTM_READ_RSSI_TYPE_CUR);
     }
 
     return (BTM_CMD_STARTED);
@@ -1845,6 +1879,25 @@ void btm_read_tx_power_complete(uint8_t* p, bool is_ble) {
   }
 }
 
+/*******************************************************************************
+ *
+ * Function         btm_ble_read_transmit_power_complete
+ *
+ * Description      This function is called when the command complete message
+ *                  is received from the HCI for the LE read transmit power
+ *                  request. Only the maximum TX power is reported.
+ *
+ * Returns          void
+ *
+ ******************************************************************************/
+void btm_ble_read_transmit_power_complete(uint8_t* p) {
+  // The status is followed by the minimum and the maximum TX power. Without
+  // the minimum, the response reads as the one of the advertising channel TX
+  // power.
+  uint8_t rsp[2] = {p[0], p[2]};
+  btm_read_tx_power_complete(rsp, true);
+}
+
 /*******************************************************************************
  *
  * Function         btm_read_rssi_timeout
@@ -1302,6 +1302,10 @@ static void btu_hcif_hdl_command_complete(uint16_t opcode, uint8_t* p,
       btm_read_tx_power_complete(p, true);
       break;
 
+    case HCI_BLE_READ_TRANSMIT_POWER:
+      btm_ble_read_transmit_power_complete(p);
+      break;
+
     case HCI_BLE_WRITE_ADV_ENABLE:
       btm_ble_write_adv_enable_complete(p);
       break;
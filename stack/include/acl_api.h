@@ -170,6 +170,24 @@ tBTM_STATUS BTM_ReadFailedContactCounter(const RawAddress& remote_bda,
 tBTM_STATUS BTM_ReadTxPower(const RawAddress& remote_bda,
                             tBT_TRANSPORT transport, tBTM_CMPL_CB* p_cb);
 
+/*******************************************************************************
+ *
+ * Function         BTM_ReadMaxTxPower
+ *
+ * Description      This function is called to read the maximum TX power of
+ *                  the connection, or on LE the maximum TX power supported
+ *                  by the controller. The TX power level results are
+ *                  returned in the callback.
+ *                  (tBTM_TX_POWER_RESULT)
+ *
+ * Returns          BTM_CMD_STARTED if command issued to controller.
+ *                  BTM_UNKNOWN_ADDR if no active link with bd addr specified
+ *                  BTM_BUSY if command is already in progress
+ *
+ ******************************************************************************/
+tBTM_STATUS BTM_ReadMaxTxPower(const RawAddress& remote_bda,
+                               tBT_TRANSPORT transport, tBTM_CMPL_CB* p_cb);
+
 /*******************************************************************************
  *
  * Function         BTM_GetNumAclLinks
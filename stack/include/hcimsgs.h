@@ -540,6 +540,8 @@ extern void btsnd_hcic_ble_write_adv_params(
 
 extern void btsnd_hcic_ble_read_adv_chnl_tx_power(void);
 
+extern void btsnd_hcic_ble_read_transmit_power(void);
+
 extern void btsnd_hcic_ble_set_adv_data(uint8_t data_len, uint8_t* p_data);
 
 extern void btsnd_hcic_ble_set_scan_rsp_data(uint8_t data_len,
@@ -147,6 +147,9 @@ void btsnd_hcic_ble_rand(base::Callback<void(BT_OCTET8)> cb) {
 void btsnd_hcic_ble_read_adv_chnl_tx_power(void) {
   mock_function_count_map[__func__]++;
 }
+void btsnd_hcic_ble_read_transmit_power(void) {
+  mock_function_count_map[__func__]++;
+}
 void btsnd_hcic_ble_read_chnl_map(uint16_t handle) {
   mock_function_count_map[__func__]++;
 }
@@ -185,6 +185,11 @@ tBTM_STATUS BTM_ReadTxPower(const RawAddress& remote_bda,
   mock_function_count_map[__func__]++;
   return BTM_SUCCESS;
 }
+tBTM_STATUS BTM_ReadMaxTxPower(const RawAddress& remote_bda,
+                               tBT_TRANSPORT transport, tBTM_CMPL_CB* p_cb) {
+  mock_function_count_map[__func__]++;
+  return BTM_SUCCESS;
+}
 tBTM_STATUS BTM_SetLinkSuperTout(const RawAddress& remote_bda,
                                  uint16_t timeout) {
   mock_function_count_map[__func__]++;
@@ -426,6 +431,9 @@ void btm_read_rssi_timeout(UNUSED_ATTR void* data) {
 void btm_read_tx_power_complete(uint8_t* p, bool is_ble) {
   mock_function_count_map[__func__]++;
 }
+void btm_ble_read_transmit_power_complete(uint8_t* p) {
+  mock_function_count_map[__func__]++;
+}
 void btm_read_tx_power_timeout(UNUSED_ATTR void* data) {
   mock_function_count_map[__func__]++;
 }